@@ -51,12 +51,33 @@ class NotImplemented(TinychainError):
     __uri__ = uri(TinychainError) + "/not_implemented"
 
 
+class PayloadTooLarge(TinychainError):
+    """Error indicating that the request body is larger than the host accepts."""
+
+    __uri__ = uri(TinychainError) + "/payload_too_large"
+
+
+class TooManyRequests(TinychainError):
+    """Error indicating that the requestor has sent too many requests and should retry later."""
+
+    __uri__ = uri(TinychainError) + "/too_many_requests"
+
+
 class Unauthorized(TinychainError):
     """Error indicating that the requestor's credentials are missing or invalid."""
 
     __uri__ = uri(TinychainError) + "/unauthorized"
 
 
+class Unavailable(TinychainError):
+    """
+    Error indicating that the requested resource is temporarily unavailable,
+    for example because it's locked for maintenance.
+    """
+
+    __uri__ = uri(TinychainError) + "/unavailable"
+
+
 class UnknownError(TinychainError):
     """An internal error with no handling or recovery logic defined."""
 
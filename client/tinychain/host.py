@@ -47,8 +47,14 @@ class Host(object):
             raise NotFound(response)
         elif status == 405:
             raise MethodNotAllowed(response)
+        elif status == 413:
+            raise PayloadTooLarge(response)
+        elif status == 429:
+            raise TooManyRequests(response)
         elif status == 501:
             raise NotImplemented(response)
+        elif status == 503:
+            raise Unavailable(response)
         else:
             raise UnknownError(f"HTTP error code {status}: {response}")
 
@@ -17,8 +17,12 @@ pub enum ErrorType {
     MethodNotAllowed,
     NotFound,
     NotImplemented,
+    PayloadTooLarge,
+    PolicyViolation,
     Timeout,
+    TooManyRequests,
     Unauthorized,
+    Unavailable,
 }
 
 impl fmt::Debug for ErrorType {
@@ -38,8 +42,12 @@ impl fmt::Display for ErrorType {
             Self::MethodNotAllowed => f.write_str("method not allowed"),
             Self::NotFound => f.write_str("not found"),
             Self::NotImplemented => f.write_str("not implemented"),
+            Self::PayloadTooLarge => f.write_str("payload too large"),
+            Self::PolicyViolation => f.write_str("policy violation"),
             Self::Timeout => f.write_str("request timeout"),
+            Self::TooManyRequests => f.write_str("too many requests"),
             Self::Unauthorized => f.write_str("unauthorized"),
+            Self::Unavailable => f.write_str("temporarily unavailable"),
         }
     }
 }
@@ -59,7 +67,7 @@ impl TCError {
     /// Error indicating that the an upstream server send an invalid response.
     pub fn bad_gateway<I: fmt::Display>(cause: I) -> Self {
         Self {
-            code: ErrorType::BadRequest,
+            code: ErrorType::BadGateway,
             message: cause.to_string(),
         }
     }
@@ -123,6 +131,22 @@ impl TCError {
         }
     }
 
+    /// Error indicating that the request body is larger than this host accepts.
+    pub fn payload_too_large<I: fmt::Display>(info: I) -> Self {
+        Self {
+            code: ErrorType::PayloadTooLarge,
+            message: info.to_string(),
+        }
+    }
+
+    /// Error indicating that the request is well-formed, but violates a policy of this host.
+    pub fn policy_violation<I: fmt::Display>(info: I) -> Self {
+        Self {
+            code: ErrorType::PolicyViolation,
+            message: info.to_string(),
+        }
+    }
+
     /// Error indicating that the request failed to complete in the allotted time.
     pub fn timeout<I: fmt::Display>(info: I) -> Self {
         Self {
@@ -131,6 +155,14 @@ impl TCError {
         }
     }
 
+    /// Error indicating that the client has sent too many requests, and should retry later.
+    pub fn too_many_requests<I: fmt::Display>(info: I) -> Self {
+        Self {
+            code: ErrorType::TooManyRequests,
+            message: info.to_string(),
+        }
+    }
+
     /// Error indicating that the user's credentials are missing or nonsensical.
     pub fn unauthorized<I: fmt::Display>(info: I) -> Self {
         Self {
@@ -139,6 +171,15 @@ impl TCError {
         }
     }
 
+    /// Error indicating that the requested resource is temporarily unavailable, for example
+    /// because it's locked for maintenance, and the request may succeed if retried later.
+    pub fn unavailable<I: fmt::Display>(info: I) -> Self {
+        Self {
+            code: ErrorType::Unavailable,
+            message: info.to_string(),
+        }
+    }
+
     /// Error indicating that the request is badly-constructed or nonsensical.
     pub fn unsupported<I: fmt::Display>(info: I) -> Self {
         Self {
@@ -1,10 +1,15 @@
 //! A generic [`Id`]
 
+use std::borrow::Cow;
+use std::cmp::Ordering;
 use std::fmt;
+use std::hash::{Hash, Hasher};
 use std::iter;
 use std::ops::Deref;
 use std::path::PathBuf;
 use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering as AtomicOrdering};
+use std::sync::Arc;
 
 use async_trait::async_trait;
 use destream::{de, Decoder, Encoder, FromStream, IntoStream, ToStream};
@@ -12,6 +17,7 @@ use regex::Regex;
 use safecast::TryCastFrom;
 use serde::de::{Deserialize, Deserializer, Error};
 use serde::ser::{Serialize, Serializer};
+use unicode_normalization::UnicodeNormalization;
 
 use tc_error::*;
 
@@ -20,6 +26,64 @@ const RESERVED_CHARS: [&str; 21] = [
     "#", "(", ")",
 ];
 
+static TRIM: AtomicBool = AtomicBool::new(false);
+static NFC: AtomicBool = AtomicBool::new(false);
+static CASE_FOLD: AtomicBool = AtomicBool::new(false);
+
+/// How to normalize a string into an [`Id`] when it's decoded from a request.
+///
+/// By default, no normalization is applied, so an `Id` with surrounding whitespace is rejected
+/// and case is significant. Normalization is only applied at decode time, so an `Id` parsed by
+/// the host software itself is never affected.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct IdNormalization {
+    /// Remove leading and trailing whitespace.
+    pub trim: bool,
+    /// Convert to Unicode Normalization Form C, so that canonically equivalent strings are equal.
+    pub nfc: bool,
+    /// Convert to lowercase, so that case is not significant.
+    pub case_fold: bool,
+}
+
+impl IdNormalization {
+    /// Return the normalization currently configured for this process.
+    pub fn current() -> Self {
+        Self {
+            trim: TRIM.load(AtomicOrdering::Relaxed),
+            nfc: NFC.load(AtomicOrdering::Relaxed),
+            case_fold: CASE_FOLD.load(AtomicOrdering::Relaxed),
+        }
+    }
+
+    /// Set the normalization applied to every `Id` subsequently decoded by this process.
+    pub fn configure(self) {
+        TRIM.store(self.trim, AtomicOrdering::Relaxed);
+        NFC.store(self.nfc, AtomicOrdering::Relaxed);
+        CASE_FOLD.store(self.case_fold, AtomicOrdering::Relaxed);
+    }
+
+    fn apply<'a>(&self, id: &'a str) -> Cow<'a, str> {
+        let mut normalized = Cow::Borrowed(id);
+
+        if self.trim {
+            normalized = match normalized {
+                Cow::Borrowed(id) => Cow::Borrowed(id.trim()),
+                Cow::Owned(id) => Cow::Owned(id.trim().to_string()),
+            };
+        }
+
+        if self.nfc && !unicode_normalization::is_nfc(&normalized) {
+            normalized = Cow::Owned(normalized.nfc().collect());
+        }
+
+        if self.case_fold && normalized.chars().any(char::is_uppercase) {
+            normalized = Cow::Owned(normalized.to_lowercase());
+        }
+
+        normalized
+    }
+}
+
 /// A static label which implements `Into<Id>`.
 pub struct Label {
     id: &'static str,
@@ -36,17 +100,53 @@ impl fmt::Display for Label {
     }
 }
 
+// the same few labels are converted into an `Id` over and over again, so an `Id` made from a
+// label borrows its static string rather than allocating a new one
 impl From<Label> for Id {
     fn from(l: Label) -> Id {
         Id {
-            id: l.id.to_string(),
+            id: IdStr::Static(l.id),
+            original: None,
         }
     }
 }
 
 impl From<uuid::Uuid> for Id {
     fn from(id: uuid::Uuid) -> Self {
-        Id { id: id.to_string() }
+        Id {
+            id: id.to_string().into(),
+            original: None,
+        }
+    }
+}
+
+// the string underlying an `Id`, which is either static or shared
+#[derive(Clone, Debug)]
+enum IdStr {
+    Static(&'static str),
+    Shared(Arc<str>),
+}
+
+impl Deref for IdStr {
+    type Target = str;
+
+    fn deref(&self) -> &str {
+        match self {
+            Self::Static(id) => id,
+            Self::Shared(id) => id,
+        }
+    }
+}
+
+impl From<String> for IdStr {
+    fn from(id: String) -> Self {
+        Self::Shared(id.into())
+    }
+}
+
+impl<'a> From<&'a str> for IdStr {
+    fn from(id: &'a str) -> Self {
+        Self::Shared(id.into())
     }
 }
 
@@ -57,15 +157,50 @@ impl From<uuid::Uuid> for Id {
 ///
 /// An `Id` must be valid UTF8 and must not contain whitespace or any control character sequence
 /// like `{/, .., ~, $, \, ^, &, |, =, {, }, <, >, ', ", ?, :, @, #}`.
-#[derive(Clone, Debug, Eq, PartialEq, Hash, Ord, PartialOrd)]
+///
+/// An `Id` is immutable, so cloning an `Id` only copies a reference to the same string.
+///
+/// An `Id` decoded from a request may be normalized according to the current
+/// [`IdNormalization`], in which case it's displayed, encoded, compared and hashed in its
+/// normalized form. The string it was decoded from is kept only so that an error message can
+/// refer to the `Id` as the user spelled it (see [`Id::original`]).
+#[derive(Clone, Debug)]
 pub struct Id {
-    id: String,
+    id: IdStr,
+    original: Option<Arc<str>>,
 }
 
 impl Id {
+    /// Parse an `Id` decoded from a request, normalized according to the current
+    /// [`IdNormalization`].
+    pub fn decode(id: &str) -> TCResult<Id> {
+        Self::decode_with(id, IdNormalization::current())
+    }
+
+    fn decode_with(id: &str, normalization: IdNormalization) -> TCResult<Id> {
+        let normalized = normalization.apply(id);
+        if normalized == id {
+            return id.parse();
+        }
+
+        validate_id(&normalized)?;
+
+        Ok(Id {
+            id: IdStr::Shared(normalized.into()),
+            original: Some(id.into()),
+        })
+    }
+
     /// Borrows the String underlying this `Id`.
     pub fn as_str(&self) -> &str {
-        self.id.as_str()
+        &self.id
+    }
+
+    /// Borrows the string this `Id` was decoded from, before it was normalized.
+    ///
+    /// This is only meant for error messages; an `Id` is always encoded in its normalized form.
+    pub fn original(&self) -> &str {
+        self.original.as_deref().unwrap_or(&self.id)
     }
 
     /// Return true if this `Id` begins with the specified string.
@@ -74,27 +209,53 @@ impl Id {
     }
 }
 
+impl PartialEq for Id {
+    fn eq(&self, other: &Id) -> bool {
+        *self.id == *other.id
+    }
+}
+
+impl Eq for Id {}
+
+impl Hash for Id {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        (*self.id).hash(state)
+    }
+}
+
+impl PartialOrd for Id {
+    fn partial_cmp(&self, other: &Id) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Id {
+    fn cmp(&self, other: &Id) -> Ordering {
+        (*self.id).cmp(&*other.id)
+    }
+}
+
 impl PartialEq<str> for Id {
     fn eq(&self, other: &str) -> bool {
-        self.id == other
+        &*self.id == other
     }
 }
 
 impl<'a> PartialEq<&'a str> for Id {
     fn eq(&self, other: &&'a str) -> bool {
-        self.id == *other
+        &*self.id == *other
     }
 }
 
 impl PartialEq<Label> for Id {
     fn eq(&self, other: &Label) -> bool {
-        self.id == other.id
+        &*self.id == other.id
     }
 }
 
 impl PartialEq<Id> for &str {
     fn eq(&self, other: &Id) -> bool {
-        self == &other.id
+        *self == &*other.id
     }
 }
 
@@ -130,14 +291,14 @@ impl de::Visitor for IdVisitor {
     }
 
     fn visit_string<E: de::Error>(self, s: String) -> Result<Self::Value, E> {
-        Id::from_str(&s).map_err(de::Error::custom)
+        Id::decode(&s).map_err(de::Error::custom)
     }
 
     async fn visit_map<M: de::MapAccess>(self, mut access: M) -> Result<Self::Value, M::Error> {
         if let Some(key) = access.next_key::<String>(()).await? {
             let value: [u8; 0] = access.next_value(()).await?;
             if value.is_empty() {
-                Id::from_str(&key).map_err(de::Error::custom)
+                Id::decode(&key).map_err(de::Error::custom)
             } else {
                 Err(de::Error::custom("Expected Id but found OpRef"))
             }
@@ -149,13 +310,13 @@ impl de::Visitor for IdVisitor {
 
 impl<'en> ToStream<'en> for Id {
     fn to_stream<E: Encoder<'en>>(&'en self, e: E) -> Result<E::Ok, E::Error> {
-        e.encode_str(&self.id)
+        e.encode_str(self.as_str())
     }
 }
 
 impl<'en> IntoStream<'en> for Id {
     fn into_stream<E: Encoder<'en>>(self, e: E) -> Result<E::Ok, E::Error> {
-        e.encode_str(&self.id)
+        e.encode_str(self.as_str())
     }
 }
 
@@ -164,7 +325,10 @@ impl FromStr for Id {
 
     fn from_str(id: &str) -> TCResult<Id> {
         validate_id(id)?;
-        Ok(Id { id: id.to_string() })
+        Ok(Id {
+            id: id.into(),
+            original: None,
+        })
     }
 }
 
@@ -190,7 +354,7 @@ impl TryCastFrom<Id> for usize {
 
 impl fmt::Display for Id {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "{}", self.id)
+        f.write_str(self.as_str())
     }
 }
 
@@ -473,3 +637,83 @@ impl<'a> fmt::Display for TCPath<'a> {
         )
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use futures::TryStreamExt;
+
+    use super::*;
+
+    #[test]
+    fn test_static_label() {
+        const NAME: Label = label("name");
+
+        let first = Id::from(NAME);
+        let second = Id::from(NAME);
+        assert_eq!(first.as_str().as_ptr(), second.as_str().as_ptr());
+        assert_eq!(first.as_str().as_ptr(), "name".as_ptr());
+        assert_eq!(first, "name".parse::<Id>().unwrap());
+    }
+
+    #[test]
+    fn test_normalization() -> TCResult<()> {
+        let none = IdNormalization::default();
+        assert!(Id::decode_with(" name", none).is_err());
+        assert_ne!(
+            Id::decode_with("Name", none)?,
+            Id::decode_with("name", none)?
+        );
+
+        let all = IdNormalization {
+            trim: true,
+            nfc: true,
+            case_fold: true,
+        };
+
+        let id = Id::decode_with(" Name\t", all)?;
+        assert_eq!(id, "name".parse::<Id>()?);
+        assert_eq!(id.as_str(), "name");
+        assert_eq!(id.original(), " Name\t");
+        assert_eq!(id.to_string(), "name");
+
+        // "e" followed by a combining acute accent is canonically equivalent to "\u{e9}"
+        let id = Id::decode_with("caf\u{65}\u{301}", all)?;
+        assert_eq!(id.as_str(), "caf\u{e9}");
+
+        let id = Id::decode_with("name", all)?;
+        assert_eq!(id.original(), "name");
+        assert!(id.original.is_none());
+
+        assert!(Id::decode_with(" $name ", all).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_encode_normalized() -> TCResult<()> {
+        let all = IdNormalization {
+            trim: true,
+            nfc: true,
+            case_fold: true,
+        };
+
+        let id = Id::decode_with(" Name\t", all)?;
+        let (encoded, decoded) = futures::executor::block_on(async {
+            let encoded = destream_json::encode(id.clone()).expect("encode Id");
+            let encoded: Vec<u8> = encoded.try_concat().await.expect("encoded Id");
+
+            let source = futures::stream::iter(vec![encoded.clone()]);
+            let decoded: String = destream_json::decode((), source).await.expect("decode");
+            (encoded, decoded)
+        });
+
+        assert_eq!(encoded, b"\"name\"".to_vec());
+
+        // the encoded Id can be decoded by a host which doesn't normalize Ids
+        let decoded = Id::decode_with(&decoded, IdNormalization::default())?;
+        assert_eq!(decoded, id);
+        assert_eq!(decoded.as_str(), "name");
+
+        Ok(())
+    }
+}
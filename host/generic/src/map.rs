@@ -4,6 +4,7 @@ use std::collections::HashMap;
 use std::fmt;
 use std::iter::FromIterator;
 use std::ops::{Deref, DerefMut};
+use std::sync::Arc;
 
 use async_trait::async_trait;
 use destream::de::{Decoder, FromStream};
@@ -12,14 +13,18 @@ use safecast::{Match, TryCastFrom, TryCastInto};
 use super::{Id, Tuple};
 
 /// A generic map whose keys are [`Id`]s, based on [`HashMap`]
+///
+/// The contents of a `Map` are shared between its clones, and only copied when a clone is
+/// mutated, so cloning a `Map` never allocates.
 #[derive(Clone)]
 pub struct Map<T: Clone> {
-    inner: HashMap<Id, T>,
+    inner: Arc<HashMap<Id, T>>,
 }
 
 impl<T: Clone> Map<T> {
+    /// Return the contents of this `Map`, copying them only if they're shared with a clone.
     pub fn into_inner(self) -> HashMap<Id, T> {
-        self.inner
+        Arc::try_unwrap(self.inner).unwrap_or_else(|inner| HashMap::clone(&inner))
     }
 }
 
@@ -53,7 +58,7 @@ impl<T: Clone> Deref for Map<T> {
 
 impl<T: Clone> DerefMut for Map<T> {
     fn deref_mut(&'_ mut self) -> &'_ mut <Self as Deref>::Target {
-        &mut self.inner
+        Arc::make_mut(&mut self.inner)
     }
 }
 
@@ -62,20 +67,22 @@ impl<T: Clone> IntoIterator for Map<T> {
     type IntoIter = <HashMap<Id, T> as IntoIterator>::IntoIter;
 
     fn into_iter(self) -> Self::IntoIter {
-        self.inner.into_iter()
+        self.into_inner().into_iter()
     }
 }
 
 impl<T: Clone> FromIterator<(Id, T)> for Map<T> {
     fn from_iter<I: IntoIterator<Item = (Id, T)>>(iter: I) -> Self {
         let inner = HashMap::from_iter(iter);
-        Map { inner }
+        Map::from(inner)
     }
 }
 
 impl<T: Clone> From<HashMap<Id, T>> for Map<T> {
     fn from(inner: HashMap<Id, T>) -> Self {
-        Map { inner }
+        Map {
+            inner: Arc::new(inner),
+        }
     }
 }
 
@@ -98,12 +105,12 @@ where
             }
         }
 
-        Some(Self { inner })
+        Some(Self::from(inner))
     }
 }
 
 #[async_trait]
-impl<T: Clone + FromStream<Context = ()>> FromStream for Map<T>
+impl<T: Clone + Sync + FromStream<Context = ()>> FromStream for Map<T>
 where
     T::Context: Copy,
 {
@@ -111,7 +118,7 @@ where
 
     async fn from_stream<D: Decoder>(context: T::Context, d: &mut D) -> Result<Self, D::Error> {
         let inner = HashMap::<Id, T>::from_stream(context, d).await?;
-        Ok(Self { inner })
+        Ok(Self::from(inner))
     }
 }
 
@@ -152,3 +159,22 @@ impl<T: Clone + fmt::Display> fmt::Display for Map<T> {
         )
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::label;
+
+    use super::*;
+
+    #[test]
+    fn test_copy_on_write() {
+        let mut map: Map<u64> = vec![(label("a").into(), 1)].into_iter().collect();
+        let clone = map.clone();
+        assert!(Arc::ptr_eq(&map.inner, &clone.inner));
+
+        map.insert(label("b").into(), 2);
+        assert!(!Arc::ptr_eq(&map.inner, &clone.inner));
+        assert_eq!(map.len(), 2);
+        assert_eq!(clone.into_inner().len(), 1);
+    }
+}
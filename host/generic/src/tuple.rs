@@ -7,20 +7,25 @@
 use std::fmt;
 use std::iter::FromIterator;
 use std::ops::{Deref, DerefMut};
+use std::sync::Arc;
 
 use async_trait::async_trait;
 use destream::de::{Decoder, FromStream};
 use safecast::*;
 
 /// A generic tuple type, based on [`Vec`]
+///
+/// The contents of a `Tuple` are shared between its clones, and only copied when a clone is
+/// mutated, so cloning a `Tuple` never allocates.
 #[derive(Clone, Default, Eq, PartialEq)]
 pub struct Tuple<T: Clone> {
-    inner: Vec<T>,
+    inner: Arc<Vec<T>>,
 }
 
 impl<T: Clone> Tuple<T> {
+    /// Return the contents of this `Tuple`, copying them only if they're shared with a clone.
     pub fn into_inner(self) -> Vec<T> {
-        self.inner
+        Arc::try_unwrap(self.inner).unwrap_or_else(|inner| Vec::clone(&inner))
     }
 }
 
@@ -40,20 +45,24 @@ impl<T: Clone> Deref for Tuple<T> {
 
 impl<T: Clone> DerefMut for Tuple<T> {
     fn deref_mut(&'_ mut self) -> &'_ mut <Self as Deref>::Target {
-        &mut self.inner
+        Arc::make_mut(&mut self.inner)
     }
 }
 
 impl<T: Clone, F: Into<T>> FromIterator<F> for Tuple<T> {
     fn from_iter<I: IntoIterator<Item = F>>(iter: I) -> Self {
         let inner = Vec::from_iter(iter.into_iter().map(|f| f.into()));
-        Tuple { inner }
+        Tuple {
+            inner: Arc::new(inner),
+        }
     }
 }
 
 impl<T: Clone> From<Vec<T>> for Tuple<T> {
     fn from(inner: Vec<T>) -> Self {
-        Tuple { inner }
+        Tuple {
+            inner: Arc::new(inner),
+        }
     }
 }
 
@@ -62,7 +71,7 @@ impl<T: Clone> IntoIterator for Tuple<T> {
     type IntoIter = <Vec<T> as IntoIterator>::IntoIter;
 
     fn into_iter(self) -> Self::IntoIter {
-        self.inner.into_iter()
+        self.into_inner().into_iter()
     }
 }
 
@@ -73,7 +82,7 @@ impl<F: Clone, T: TryCastFrom<F>> TryCastFrom<Tuple<F>> for Vec<T> {
 
     fn opt_cast_from(tuple: Tuple<F>) -> Option<Self> {
         let mut cast: Vec<T> = Vec::with_capacity(tuple.len());
-        for val in tuple.inner.into_iter() {
+        for val in tuple.into_iter() {
             if let Some(val) = val.opt_cast_into() {
                 cast.push(val)
             } else {
@@ -173,7 +182,7 @@ impl<F: Clone, T1: TryCastFrom<F>, T2: TryCastFrom<F>, T3: TryCastFrom<F>, T4: T
 }
 
 #[async_trait]
-impl<T: Clone + FromStream> FromStream for Tuple<T>
+impl<T: Clone + Sync + FromStream> FromStream for Tuple<T>
 where
     T::Context: Copy,
 {
@@ -181,7 +190,7 @@ where
 
     async fn from_stream<D: Decoder>(context: Self::Context, d: &mut D) -> Result<Self, D::Error> {
         let inner = Vec::<T>::from_stream(context, d).await?;
-        Ok(Self { inner })
+        Ok(Self::from(inner))
     }
 }
 
@@ -14,6 +14,7 @@ use tc_transact::{Transact, TxnId};
 use tc_value::Value;
 use tcgeneric::*;
 
+use crate::collection::{Collection, CollectionType};
 use crate::fs;
 use crate::scalar::OpRef;
 use crate::state::State;
@@ -32,13 +33,28 @@ const SUBJECT: Label = label("subject");
 pub const EXT: &str = "chain";
 
 /// The schema of a [`Chain`], used when constructing a new `Chain` or loading a `Chain` from disk.
+///
+/// A schema of the form `[<collection class>, <collection schema>]`, like
+/// `[{"/state/collection/btree": []}, [["name", {"/state/scalar/value/string": []}]]]`, is the
+/// schema of a [`Collection`]. Any other schema is the initial value of a [`Value`].
 #[derive(Clone)]
 pub enum Schema {
+    Collection(CollectionType, Value),
     Value(Value),
 }
 
 impl CastFrom<Value> for Schema {
     fn cast_from(value: Value) -> Self {
+        if let Value::Tuple(tuple) = &value {
+            if let [Value::Link(class), schema] = &tuple[..] {
+                if class.host().is_none() {
+                    if let Some(class) = CollectionType::from_path(class.path()) {
+                        return Self::Collection(class, schema.clone());
+                    }
+                }
+            }
+        }
+
         Self::Value(value)
     }
 }
@@ -46,6 +62,7 @@ impl CastFrom<Value> for Schema {
 /// The state whose transactional integrity is protected by a [`Chain`].
 #[derive(Clone)]
 pub enum Subject {
+    Collection(Collection),
     Value(fs::File<Bytes>),
 }
 
@@ -55,6 +72,7 @@ impl Subject {
         debug!("Subject::at {}", txn_id);
 
         match self {
+            Self::Collection(collection) => Ok(State::Collection(collection.clone())),
             Self::Value(file) => {
                 let block_id = SUBJECT.into();
                 let block = file.get_block(txn_id, &block_id).await?;
@@ -67,8 +85,14 @@ impl Subject {
     }
 
     /// Set the state of this `Subject` to `value` at the given [`TxnId`].
+    ///
+    /// A [`Collection`] can't be replaced, only modified through its own methods.
     pub async fn put(&self, txn_id: &TxnId, key: Value, value: State) -> TCResult<()> {
         match self {
+            Self::Collection(collection) => Err(TCError::unsupported(format!(
+                "cannot replace the {} of a Chain",
+                collection
+            ))),
             Self::Value(file) => {
                 if key.is_some() {
                     return Err(TCError::bad_request("Value has no such property", key));
@@ -105,12 +129,14 @@ impl Transact for Subject {
         );
 
         match self {
+            Self::Collection(collection) => collection.commit(txn_id).await,
             Self::Value(file) => file.commit(txn_id).await,
         }
     }
 
     async fn finalize(&self, txn_id: &TxnId) {
         match self {
+            Self::Collection(collection) => collection.finalize(txn_id).await,
             Self::Value(file) => file.finalize(txn_id).await,
         }
     }
@@ -6,7 +6,7 @@ use std::convert::TryInto;
 
 use async_trait::async_trait;
 use bytes::Bytes;
-use futures::join;
+use futures::{join, TryFutureExt};
 use log::debug;
 
 use tc_error::*;
@@ -14,6 +14,7 @@ use tc_transact::fs::{Dir, File, Persist};
 use tc_transact::{Transact, TxnId};
 use tcgeneric::Instance;
 
+use crate::collection::Collection;
 use crate::fs;
 use crate::scalar::OpRef;
 
@@ -53,6 +54,12 @@ impl Persist for SyncChain {
 
     async fn load(schema: Self::Schema, dir: fs::Dir, txn_id: TxnId) -> TCResult<Self> {
         let subject = match &schema {
+            Schema::Collection(class, schema) => {
+                let dir = dir.get_or_create_dir(txn_id, SUBJECT.into()).await?;
+                Collection::load(*class, schema.clone(), dir, txn_id)
+                    .map_ok(Subject::Collection)
+                    .await?
+            }
             Schema::Value(value) => {
                 let file: fs::File<Bytes> =
                     if let Some(file) = dir.get_file(&txn_id, &SUBJECT.into()).await? {
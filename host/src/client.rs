@@ -0,0 +1,176 @@
+//! An async client for Rust services which call a Tinychain host over HTTP.
+//!
+//! Example:
+//! ```no_run
+//! # async fn example() -> tinychain::error::TCResult<()> {
+//! use tinychain::client::Client;
+//! use tinychain::value::{Number, Value};
+//!
+//! let client = Client::new();
+//! let link = "http://127.0.0.1:8702/app/users".parse().unwrap();
+//! let user = client.get(link, Value::from(Number::from(1u64))).await?;
+//! # Ok(())
+//! # }
+//! ```
+
+use std::time::Duration;
+
+use futures::{TryFutureExt, TryStreamExt};
+use hyper::body::Body;
+use hyper::client::HttpConnector;
+use log::debug;
+
+use tc_error::*;
+use tc_transact::TxnId;
+use tc_value::{Link, Value};
+use tcgeneric::Map;
+
+use crate::http::{req_builder, transform_error, url};
+use crate::scalar::Scalar;
+use crate::state::State;
+
+const IDLE_TIMEOUT: u64 = 30;
+
+/// A Tinychain HTTP client for use outside of a host.
+///
+/// Request bodies are encoded, and responses decoded, with the same [`Scalar`] encoding the
+/// host itself uses. Since a client has no transaction context, a response is always decoded
+/// as a [`State::Scalar`]; for example, the rows of a table are decoded as a tuple of tuples.
+pub struct Client {
+    client: hyper::Client<HttpConnector, Body>,
+    token: Option<String>,
+    txn_id: Option<TxnId>,
+}
+
+impl Client {
+    /// Construct a new `Client` with no credentials.
+    pub fn new() -> Self {
+        let client = hyper::Client::builder()
+            .pool_idle_timeout(Duration::from_secs(IDLE_TIMEOUT))
+            .build_http();
+
+        Self {
+            client,
+            token: None,
+            txn_id: None,
+        }
+    }
+
+    /// Authorize every request made by this `Client` with the given bearer token.
+    pub fn with_token(mut self, token: String) -> Self {
+        self.token = Some(token);
+        self
+    }
+
+    /// Make every request made by this `Client` part of the given transaction.
+    pub fn with_txn_id(mut self, txn_id: TxnId) -> Self {
+        self.txn_id = Some(txn_id);
+        self
+    }
+
+    /// Read the [`State`] at `key` within `link`.
+    pub async fn get(&self, link: Link, key: Value) -> TCResult<State> {
+        let response = self.request("GET", &link, &key, Body::empty()).await?;
+        decode(&link, response).await
+    }
+
+    /// Set `key` = `value` within the state referred to by `link`.
+    pub async fn put(&self, link: Link, key: Value, value: Scalar) -> TCResult<()> {
+        let body = encode(value)?;
+        self.request("PUT", &link, &key, body).await?;
+        Ok(())
+    }
+
+    /// Execute the POST op at `link` with the given parameters.
+    pub async fn post(&self, link: Link, params: Map<Scalar>) -> TCResult<State> {
+        let body = encode(Scalar::Map(params))?;
+        let response = self.request("POST", &link, &Value::None, body).await?;
+        decode(&link, response).await
+    }
+
+    /// Delete `key` from the state referred to by `link`.
+    pub async fn delete(&self, link: Link, key: Value) -> TCResult<()> {
+        self.request("DELETE", &link, &key, Body::empty()).await?;
+        Ok(())
+    }
+
+    async fn request(
+        &self,
+        method: &str,
+        link: &Link,
+        key: &Value,
+        body: Body,
+    ) -> TCResult<hyper::Response<Body>> {
+        let uri = url(link, self.txn_id.as_ref(), key)?;
+        debug!("{} {}", method, uri);
+
+        let req = req_builder(method, uri, self.token.as_deref())
+            .body(body)
+            .map_err(|e| TCError::bad_request("invalid request", e))?;
+
+        let response = self
+            .client
+            .request(req)
+            .map_err(TCError::bad_gateway)
+            .await?;
+
+        if response.status().is_success() {
+            Ok(response)
+        } else {
+            Err(transform_error(link, response).await)
+        }
+    }
+}
+
+impl Default for Client {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn encode(value: Scalar) -> TCResult<Body> {
+    let body = destream_json::encode(value)
+        .map_err(|e| TCError::bad_request("unable to encode stream", e))?;
+
+    Ok(Body::wrap_stream(body))
+}
+
+async fn decode(link: &Link, response: hyper::Response<Body>) -> TCResult<State> {
+    let body = response.into_body().map_ok(|bytes| bytes.to_vec());
+
+    destream_json::try_decode((), body)
+        .map_ok(State::Scalar)
+        .map_err(|e| TCError::bad_request(format!("error decoding response from {}", link), e))
+        .await
+}
+
+#[cfg(test)]
+mod tests {
+    use safecast::TryCastFrom;
+    use tc_value::Number;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_decode() -> TCResult<()> {
+        let link: Link = "http://127.0.0.1:8702/app/users".parse()?;
+
+        let response = hyper::Response::new(Body::from("[1, 2]"));
+        let expected = vec![
+            Value::from(Number::from(1u64)),
+            Value::from(Number::from(2u64)),
+        ];
+        match decode(&link, response).await? {
+            State::Scalar(scalar) => {
+                let value = Value::opt_cast_from(scalar).expect("Value");
+                assert!(value == Value::Tuple(expected.into()))
+            }
+            other => panic!("expected a Scalar but found {}", other),
+        }
+
+        let response = hyper::Response::new(Body::from("[1, "));
+        assert!(decode(&link, response).await.is_err());
+
+        Ok(())
+    }
+}
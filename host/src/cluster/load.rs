@@ -1,4 +1,4 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::convert::{TryFrom, TryInto};
 use std::sync::Arc;
 
@@ -14,8 +14,8 @@ use tcgeneric::*;
 
 use crate::chain::{Chain, ChainType, SyncChain};
 use crate::fs;
-use crate::object::{InstanceClass, InstanceExt};
-use crate::scalar::{Link, OpRef, Scalar, Value};
+use crate::object::{InstanceClass, InstanceExt, Interface};
+use crate::scalar::{Link, OpRef, Plans, Scalar, Value};
 use crate::txn::{Actor, TxnId};
 
 use super::Cluster;
@@ -36,6 +36,7 @@ pub async fn instantiate(
     let mut chain_schema = HashMap::new();
     let mut cluster_proto = HashMap::new();
     let mut classes = HashMap::new();
+    let mut interfaces = HashMap::new();
 
     for (id, scalar) in proto.into_iter() {
         debug!("Cluster member: {}", scalar);
@@ -53,8 +54,12 @@ pub async fn instantiate(
                         chain_schema.insert(id, (ct, schema));
                     }
                     OpRef::Post((extends, proto)) => {
-                        let extends = extends.try_into()?;
-                        classes.insert(id, InstanceClass::new(Some(extends), proto));
+                        let extends: Link = extends.try_into()?;
+                        if extends.host().is_none() && Interface::is_interface(extends.path()) {
+                            interfaces.insert(id, Interface::try_from(proto)?);
+                        } else {
+                            classes.insert(id, InstanceClass::new(Some(extends), proto));
+                        }
                     }
                     other => return Err(TCError::bad_request("expected a Chain but found", other)),
                 }
@@ -71,6 +76,11 @@ pub async fn instantiate(
         }
     }
 
+    let classes = resolve_superclasses(&path, classes)?;
+    for class in classes.values() {
+        validate_interfaces(&path, class, &interfaces)?;
+    }
+
     let dir = get_or_create_dir(data_dir, txn_id, &path).await?;
 
     let mut chains = HashMap::<Id, Chain>::new();
@@ -90,6 +100,8 @@ pub async fn instantiate(
         chains.insert(id, chain);
     }
 
+    let plans = Plans::compile(cluster_proto.iter())?;
+
     let actor_id = Value::from(Link::default());
     let cluster = Cluster {
         actor: Arc::new(Actor::new(actor_id)),
@@ -102,6 +114,7 @@ pub async fn instantiate(
             format!("Cluster {} installed deps", path),
             HashMap::new().into(),
         ),
+        plans,
     };
 
     let class = InstanceClass::new(Some(path.into()), cluster_proto.into());
@@ -109,6 +122,76 @@ pub async fn instantiate(
     Ok(InstanceExt::new(cluster, class))
 }
 
+/// Link each class which extends another class in this cluster to its superclass.
+fn resolve_superclasses(
+    path: &[PathSegment],
+    declared: HashMap<Id, InstanceClass>,
+) -> TCResult<HashMap<Id, InstanceClass>> {
+    let mut resolved = HashMap::with_capacity(declared.len());
+    for id in declared.keys() {
+        resolve_class(path, id, &declared, &mut resolved, &mut HashSet::new())?;
+    }
+
+    Ok(resolved)
+}
+
+fn resolve_class(
+    path: &[PathSegment],
+    id: &Id,
+    declared: &HashMap<Id, InstanceClass>,
+    resolved: &mut HashMap<Id, InstanceClass>,
+    visiting: &mut HashSet<Id>,
+) -> TCResult<InstanceClass> {
+    if let Some(class) = resolved.get(id) {
+        return Ok(class.clone());
+    }
+
+    if !visiting.insert(id.clone()) {
+        return Err(TCError::bad_request("circular inheritance in class", id));
+    }
+
+    let class = declared[id].clone();
+    let extends = class.extends();
+
+    let class = match local_member(path, &extends) {
+        Some(superclass) if declared.contains_key(superclass) => {
+            let superclass = resolve_class(path, superclass, declared, resolved, visiting)?;
+            class.with_superclass(superclass)
+        }
+        _ => class,
+    };
+
+    resolved.insert(id.clone(), class.clone());
+    Ok(class)
+}
+
+/// Check that the given class implements every interface it claims to implement.
+fn validate_interfaces(
+    path: &[PathSegment],
+    class: &InstanceClass,
+    interfaces: &HashMap<Id, Interface>,
+) -> TCResult<()> {
+    for link in class.interfaces()? {
+        let interface = local_member(path, &link).and_then(|id| interfaces.get(id));
+        let interface =
+            interface.ok_or_else(|| TCError::bad_request("unknown interface", &link))?;
+
+        interface.validate(class)?;
+    }
+
+    Ok(())
+}
+
+/// Return the name of the member of the cluster at `path` which `link` refers to, if any.
+fn local_member<'a>(path: &[PathSegment], link: &'a Link) -> Option<&'a Id> {
+    let member = link.path();
+    if link.host().is_none() && member.len() == path.len() + 1 && &member[..path.len()] == path {
+        member.last()
+    } else {
+        None
+    }
+}
+
 async fn get_or_create_dir(
     data_dir: fs::Dir,
     txn_id: TxnId,
@@ -9,7 +9,7 @@ use std::iter::FromIterator;
 use std::sync::Arc;
 
 use async_trait::async_trait;
-use futures::future::join_all;
+use futures::stream::{self, StreamExt};
 use log::{debug, info};
 use uplock::RwLock;
 
@@ -19,8 +19,9 @@ use tc_transact::{Transact, Transaction};
 use tcgeneric::*;
 
 use crate::chain::Chain;
+use crate::concurrency::Concurrency;
 use crate::object::InstanceClass;
-use crate::scalar::{Link, OpDef};
+use crate::scalar::{Link, OpDef, Plan, Plans};
 use crate::state::State;
 use crate::txn::{Actor, Scope, Txn, TxnId};
 
@@ -53,6 +54,7 @@ pub struct Cluster {
     confirmed: RwLock<TxnId>,
     owned: RwLock<HashMap<TxnId, Owner>>,
     installed: TxnLock<Mutable<HashMap<Link, HashSet<Scope>>>>,
+    plans: Plans,
 }
 
 impl Cluster {
@@ -187,6 +189,11 @@ impl Cluster {
         self.classes.get(name)
     }
 
+    /// The compiled [`Plan`] of the method with the given name, if there is one.
+    pub fn plan(&self, method: &Id) -> Option<Arc<Plan>> {
+        self.plans.get(method)
+    }
+
     /// Borrow the public key of this `Cluster`.
     pub fn public_key(&self) -> &[u8] {
         self.actor.public_key().as_bytes()
@@ -209,9 +216,23 @@ impl Instance for Cluster {
 #[async_trait]
 impl Transact for Cluster {
     async fn commit(&self, txn_id: &TxnId) {
+        #[cfg(feature = "faults")]
+        crate::faults::commit_delay().await;
+
         let mut confirmed = self.confirmed.write().await;
 
-        join_all(self.chains.values().map(|chain| chain.commit(txn_id))).await;
+        let concurrency = Concurrency::current().collection;
+        // collect the futures up front, since a lazy iterator over borrowed chains makes the
+        // resulting future too specific to be Send for any lifetime
+        let commits: Vec<_> = self
+            .chains
+            .values()
+            .map(|chain| chain.commit(txn_id))
+            .collect();
+        stream::iter(commits)
+            .buffer_unordered(concurrency)
+            .collect::<Vec<()>>()
+            .await;
 
         *confirmed = *txn_id;
 
@@ -219,7 +240,17 @@ impl Transact for Cluster {
     }
 
     async fn finalize(&self, txn_id: &TxnId) {
-        join_all(self.chains.values().map(|chain| chain.finalize(txn_id))).await;
+        let concurrency = Concurrency::current().collection;
+        let finalizes: Vec<_> = self
+            .chains
+            .values()
+            .map(|chain| chain.finalize(txn_id))
+            .collect();
+        stream::iter(finalizes)
+            .buffer_unordered(concurrency)
+            .collect::<Vec<()>>()
+            .await;
+
         self.owned.write().await.remove(txn_id);
         self.installed.finalize(txn_id).await;
     }
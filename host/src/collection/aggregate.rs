@@ -0,0 +1,253 @@
+//! A hash aggregation, for grouping rows which aren't sorted by their group, which spills
+//! partitions of its input to the workspace when its groups don't fit in memory.
+
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+
+use futures::stream::{self, BoxStream, Stream, StreamExt, TryStreamExt};
+
+use tc_error::*;
+use tc_transact::Transaction;
+
+use crate::fs;
+use crate::txn::Txn;
+
+use super::schema::Key;
+use super::sort::{self, Run, BLOCK_ROWS};
+
+// the number of partitions to spill the rows which don't fit in memory to
+const FANOUT: usize = 16;
+
+// past this many passes, hold every group in memory rather than spill again
+const MAX_DEPTH: u64 = 8;
+
+// the workspace of a pass, and the partially written run and buffered rows of each partition
+type Partitions = (fs::Dir, Vec<(Option<Run>, Vec<Key>)>);
+
+/// An aggregate function over the rows of each group.
+pub trait Aggregate: Clone + Send + Sync + 'static {
+    /// Return the key of the group which the given `row` belongs to.
+    fn group(&self, row: &Key) -> Key;
+
+    /// Return the state of a new group, whose first row is the given `row`.
+    fn init(&self, row: &Key) -> Key;
+
+    /// Update the `state` of a group with another of its rows.
+    fn fold(&self, state: &mut Key, row: &Key);
+}
+
+/// Aggregate the given `rows` by group, in the workspace of the given transaction.
+///
+/// Each row of the returned stream is the key of a group followed by its aggregate state. Groups
+/// are returned in no particular order. While the groups held in memory exceed the sort budget,
+/// the rows of any new group are spilled to one of several partitions in the workspace, each of
+/// which is aggregated separately as the returned stream is consumed.
+pub async fn hash_aggregate<S, A>(
+    txn: &Txn,
+    rows: S,
+    aggregate: A,
+) -> TCResult<BoxStream<'static, TCResult<Key>>>
+where
+    S: Stream<Item = TCResult<Key>> + Send + Unpin,
+    A: Aggregate,
+{
+    hash_aggregate_with_budget(txn, rows, aggregate, sort::sort_budget()).await
+}
+
+async fn hash_aggregate_with_budget<S, A>(
+    txn: &Txn,
+    rows: S,
+    aggregate: A,
+    budget: usize,
+) -> TCResult<BoxStream<'static, TCResult<Key>>>
+where
+    S: Stream<Item = TCResult<Key>> + Send + Unpin,
+    A: Aggregate,
+{
+    let (groups, spilled) = aggregate_pass(txn, rows, &aggregate, budget, 0).await?;
+    let spilled = partitions(txn.clone(), aggregate, spilled, budget, 1);
+    Ok(stream::iter(groups.into_iter().map(Ok))
+        .chain(spilled)
+        .boxed())
+}
+
+fn partitions<A: Aggregate>(
+    txn: Txn,
+    aggregate: A,
+    runs: Vec<Run>,
+    budget: usize,
+    depth: u64,
+) -> BoxStream<'static, TCResult<Key>> {
+    stream::iter(runs)
+        .then(move |run| {
+            let txn = txn.clone();
+            let aggregate = aggregate.clone();
+
+            async move {
+                let rows = run.read(*txn.id())?;
+                let (groups, spilled) =
+                    aggregate_pass(&txn, rows, &aggregate, budget, depth).await?;
+                let spilled = partitions(txn, aggregate, spilled, budget, depth + 1);
+                Ok::<_, TCError>(stream::iter(groups.into_iter().map(Ok)).chain(spilled))
+            }
+        })
+        .try_flatten()
+        .boxed()
+}
+
+// aggregate as many groups as fit in memory, and spill the rows of every other group
+async fn aggregate_pass<S, A>(
+    txn: &Txn,
+    mut rows: S,
+    aggregate: &A,
+    budget: usize,
+    depth: u64,
+) -> TCResult<(Vec<Key>, Vec<Run>)>
+where
+    S: Stream<Item = TCResult<Key>> + Send + Unpin,
+    A: Aggregate,
+{
+    let txn_id = *txn.id();
+
+    let mut groups: HashMap<String, (Key, Key)> = HashMap::new();
+    let mut size = 0;
+    let mut spilled: Option<Partitions> = None;
+
+    while let Some(row) = rows.try_next().await? {
+        let group = aggregate.group(&row);
+        let hash_key = serde_json::to_string(&group)
+            .map_err(|e| TCError::bad_request("unable to hash group", e))?;
+
+        if let Some((_, state)) = groups.get_mut(&hash_key) {
+            aggregate.fold(state, &row);
+            continue;
+        }
+
+        if size < budget || depth >= MAX_DEPTH {
+            let state = aggregate.init(&row);
+            size += hash_key.len() + sort::size_of(&group) + sort::size_of(&state);
+            groups.insert(hash_key, (group, state));
+            continue;
+        }
+
+        if spilled.is_none() {
+            let dir = sort::temp_dir(txn).await?;
+            let partitions = (0..FANOUT).map(|_| (None, Vec::new())).collect();
+            spilled = Some((dir, partitions));
+        }
+
+        let (dir, partitions) = spilled.as_mut().expect("spilled partitions");
+        let i = partition(&hash_key, depth);
+        let (run, buffer) = &mut partitions[i];
+        buffer.push(row);
+
+        if buffer.len() >= BLOCK_ROWS {
+            if run.is_none() {
+                *run = Some(Run::create(dir, txn_id, i).await?);
+            }
+
+            let run = run.as_mut().expect("spilled partition");
+            run.append(txn_id, buffer).await?;
+            buffer.clear();
+        }
+    }
+
+    let groups = groups
+        .into_iter()
+        .map(|(_, (mut group, state))| {
+            group.extend(state);
+            group
+        })
+        .collect();
+
+    let mut runs = Vec::new();
+    if let Some((dir, partitions)) = spilled {
+        for (i, (run, buffer)) in partitions.into_iter().enumerate() {
+            let mut run = match run {
+                Some(run) => run,
+                None if buffer.is_empty() => continue,
+                None => Run::create(&dir, txn_id, i).await?,
+            };
+
+            if !buffer.is_empty() {
+                run.append(txn_id, &buffer).await?;
+            }
+
+            runs.push(run);
+        }
+    }
+
+    Ok((groups, runs))
+}
+
+// the partition to spill the rows of a group to, which is different at each depth so that
+// a partition which is still too large will be split when it's aggregated
+fn partition(hash_key: &str, depth: u64) -> usize {
+    let mut hasher = DefaultHasher::new();
+    depth.hash(&mut hasher);
+    hash_key.hash(&mut hasher);
+    (hasher.finish() % FANOUT as u64) as usize
+}
+
+#[cfg(test)]
+mod tests {
+    use tc_value::{Number, Value};
+
+    use crate::test::TestHost;
+
+    use super::*;
+
+    // counts the rows of each group, where the group of a row is its first column
+    #[derive(Clone)]
+    struct Count;
+
+    impl Aggregate for Count {
+        fn group(&self, row: &Key) -> Key {
+            vec![row[0].clone()]
+        }
+
+        fn init(&self, _row: &Key) -> Key {
+            vec![Value::from(Number::from(1u64))]
+        }
+
+        fn fold(&self, state: &mut Key, _row: &Key) {
+            if let Value::Number(count) = &mut state[0] {
+                *count += Number::from(1u64);
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_spill() -> TCResult<()> {
+        let host = TestHost::new(vec![]).await?;
+        let txn = host.new_txn(false).await?;
+
+        // more groups than fit in the budget, with more than a block of rows in each partition
+        let groups = 10 * FANOUT as u64;
+        let per_group = 2 * BLOCK_ROWS as u64 / 10;
+        let len = groups * per_group;
+        let rows = (0..len).map(|n| Ok(vec![Value::from(Number::from(n % groups))]));
+
+        let mut counts: Vec<Key> =
+            hash_aggregate_with_budget(&txn, stream::iter(rows), Count, 1_000)
+                .await?
+                .try_collect()
+                .await?;
+
+        counts.sort_by(|l, r| super::super::schema::collate(l, r));
+
+        let expected: Vec<Key> = (0..groups)
+            .map(|group| {
+                vec![
+                    Value::from(Number::from(group)),
+                    Value::from(Number::from(per_group)),
+                ]
+            })
+            .collect();
+
+        assert!(counts == expected);
+
+        Ok(())
+    }
+}
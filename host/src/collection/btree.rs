@@ -0,0 +1,402 @@
+//! A [`BTree`], an ordered collection of [`Key`]s.
+
+use std::cmp::Ordering;
+use std::fmt;
+use std::ops::Bound;
+
+use async_trait::async_trait;
+use destream::{de, en};
+use futures::stream::BoxStream;
+use futures::TryFutureExt;
+
+use tc_error::*;
+use tc_transact::fs::Persist;
+use tc_transact::{IntoView, Transact, Transaction, TxnId};
+
+use crate::fs;
+use crate::txn::Txn;
+
+use super::schema::{self, Column, Key, RowSchema};
+use super::sort;
+use super::store::{Conflict, LoadRows, Rows};
+use super::Contents;
+
+/// A range of the rows of a [`BTree`], each end of which is a (possibly partial) [`Key`].
+pub type KeyRange = (Bound<Key>, Bound<Key>);
+
+/// An ordered collection of unique [`Key`]s, each matching a [`RowSchema`].
+///
+/// The rows of a `BTree` are stored in blocks of a transactional file.
+/// INCOMPLETE AND UNSTABLE.
+#[derive(Clone)]
+pub struct BTree {
+    schema: RowSchema,
+    rows: Rows,
+}
+
+impl BTree {
+    /// Create a new, empty `BTree` with the given [`RowSchema`] in the workspace of `txn`.
+    pub async fn create(txn: &Txn, schema: RowSchema) -> TCResult<Self> {
+        let dir = sort::temp_dir(txn).await?;
+        Self::load(schema, dir, *txn.id()).await
+    }
+
+    /// The [`RowSchema`] of this `BTree`.
+    pub fn schema(&'_ self) -> &'_ [Column] {
+        &self.schema
+    }
+
+    /// Return the number of rows in this `BTree` as of the given [`TxnId`].
+    pub async fn count(&self, txn_id: &TxnId) -> TCResult<u64> {
+        self.rows.count(txn_id).await
+    }
+
+    /// Return up to `limit` rows of this `BTree`, in key order, starting at `offset`.
+    pub async fn page(&self, txn_id: &TxnId, offset: u64, limit: usize) -> TCResult<Vec<Key>> {
+        self.rows.page(txn_id, offset, limit).await
+    }
+
+    /// Stream every row of this `BTree`, in key order.
+    pub fn rows(&self, txn_id: TxnId) -> BoxStream<'static, TCResult<Key>> {
+        self.rows.clone().stream(txn_id)
+    }
+
+    /// Stream every row of this `BTree` within the given [`KeyRange`], in key order.
+    pub fn range(
+        &self,
+        txn_id: TxnId,
+        range: KeyRange,
+    ) -> TCResult<BoxStream<'static, TCResult<Key>>> {
+        let range = validate_range(&self.schema, range)?;
+        let rows = self.rows.clone();
+        Ok(rows.stream_range(txn_id, move |row| compare_range(row, &range)))
+    }
+
+    /// Delete every row of this `BTree` within the given [`KeyRange`].
+    pub async fn delete(&self, txn_id: TxnId, range: KeyRange) -> TCResult<()> {
+        let range = validate_range(&self.schema, range)?;
+        self.rows
+            .delete(txn_id, |row| compare_range(row, &range))
+            .await
+    }
+
+    /// Insert the given [`Key`], if it's not already present in this `BTree`.
+    pub async fn insert(&self, txn_id: TxnId, key: Key) -> TCResult<()> {
+        self.insert_all(txn_id, vec![key]).await
+    }
+
+    /// Insert each of the given [`Key`]s which is not already present in this `BTree`.
+    ///
+    /// The batch is sorted first, then merged with the existing rows in a single pass, which is
+    /// much faster than inserting a large batch one key at a time.
+    pub async fn insert_all(&self, txn_id: TxnId, keys: Vec<Key>) -> TCResult<()> {
+        self.rows.insert(txn_id, keys, Conflict::Ignore).await
+    }
+
+    /// Construct a new `BTree`, in the workspace of `txn`, with a copy of only the rows of this
+    /// `BTree` which begin with `prefix`.
+    pub async fn slice(&self, txn: &Txn, prefix: Key) -> TCResult<BTree> {
+        let prefix = schema::validate_prefix(&self.schema, prefix)?;
+
+        let slice = Self::create(txn, self.schema.clone()).await?;
+        self.rows
+            .copy_range(*txn.id(), |row| schema::collate(row, &prefix), &slice.rows)
+            .await?;
+
+        Ok(slice)
+    }
+}
+
+fn validate_range(schema: &RowSchema, range: KeyRange) -> TCResult<KeyRange> {
+    let validate = |bound| match bound {
+        Bound::Included(key) => schema::validate_prefix(schema, key).map(Bound::Included),
+        Bound::Excluded(key) => schema::validate_prefix(schema, key).map(Bound::Excluded),
+        Bound::Unbounded => Ok(Bound::Unbounded),
+    };
+
+    let (start, end) = range;
+    Ok((validate(start)?, validate(end)?))
+}
+
+// whether `row` falls before, within, or after `range`
+fn compare_range(row: &Key, range: &KeyRange) -> Ordering {
+    let before = match &range.0 {
+        Bound::Included(start) => schema::collate(row, start) == Ordering::Less,
+        Bound::Excluded(start) => schema::collate(row, start) != Ordering::Greater,
+        Bound::Unbounded => false,
+    };
+
+    let after = match &range.1 {
+        Bound::Included(end) => schema::collate(row, end) == Ordering::Greater,
+        Bound::Excluded(end) => schema::collate(row, end) != Ordering::Less,
+        Bound::Unbounded => false,
+    };
+
+    if before {
+        Ordering::Less
+    } else if after {
+        Ordering::Greater
+    } else {
+        Ordering::Equal
+    }
+}
+
+fn validate_schema(schema: &RowSchema) -> TCResult<()> {
+    if schema.is_empty() {
+        Err(TCError::bad_request(
+            "a BTree must have at least one column, not",
+            schema::display(schema),
+        ))
+    } else {
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl Persist for BTree {
+    type Schema = RowSchema;
+    type Store = fs::Dir;
+
+    fn schema(&'_ self) -> &'_ RowSchema {
+        &self.schema
+    }
+
+    async fn load(schema: RowSchema, dir: fs::Dir, txn_id: TxnId) -> TCResult<Self> {
+        validate_schema(&schema)?;
+
+        let rows = Rows::load(schema.clone(), schema.len(), &dir, txn_id).await?;
+        Ok(Self { schema, rows })
+    }
+}
+
+#[async_trait]
+impl Transact for BTree {
+    async fn commit(&self, txn_id: &TxnId) {
+        self.rows.commit(txn_id).await
+    }
+
+    async fn finalize(&self, txn_id: &TxnId) {
+        self.rows.finalize(txn_id).await
+    }
+}
+
+#[async_trait]
+impl de::FromStream for BTree {
+    type Context = Txn;
+
+    async fn from_stream<D: de::Decoder>(txn: Txn, decoder: &mut D) -> Result<Self, D::Error> {
+        decoder.decode_seq(BTreeVisitor { txn }).await
+    }
+}
+
+struct BTreeVisitor {
+    txn: Txn,
+}
+
+#[async_trait]
+impl de::Visitor for BTreeVisitor {
+    type Value = BTree;
+
+    fn expecting() -> &'static str {
+        "a BTree schema followed by its rows"
+    }
+
+    async fn visit_seq<A: de::SeqAccess>(self, mut seq: A) -> Result<BTree, A::Error> {
+        let schema: RowSchema = seq
+            .next_element(())
+            .await?
+            .ok_or_else(|| de::Error::invalid_length(0, Self::expecting()))?;
+
+        let btree = BTree::create(&self.txn, schema)
+            .map_err(de::Error::custom)
+            .await?;
+
+        let context = (self.txn, btree.rows.clone(), Conflict::Ignore);
+        seq.next_element::<LoadRows>(context).await?;
+
+        Ok(btree)
+    }
+}
+
+impl<'en> IntoView<'en, fs::Dir> for BTree {
+    type Txn = Txn;
+    type View = BTreeView;
+
+    fn into_view(self, txn: Txn) -> BTreeView {
+        BTreeView {
+            btree: self,
+            txn_id: *txn.id(),
+        }
+    }
+}
+
+impl fmt::Display for BTree {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "BTree with schema {}", schema::display(&self.schema))
+    }
+}
+
+/// A view of a [`BTree`] as of a single transaction, used to encode its schema and contents.
+pub struct BTreeView {
+    btree: BTree,
+    txn_id: TxnId,
+}
+
+impl<'en> en::IntoStream<'en> for BTreeView {
+    fn into_stream<E: en::Encoder<'en>>(self, encoder: E) -> Result<E::Ok, E::Error> {
+        let contents = Contents::new(self.btree.rows(self.txn_id));
+        (self.btree.schema, contents).into_stream(encoder)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::{future, stream, TryStreamExt};
+
+    use tc_value::{Number, NumberType, UIntType, Value, ValueType};
+    use tcgeneric::label;
+
+    use crate::test::TestHost;
+
+    use super::*;
+
+    fn key(n: u64) -> Key {
+        vec![Value::from(Number::from(n))]
+    }
+
+    fn schema() -> RowSchema {
+        let dtype = ValueType::Number(NumberType::UInt(UIntType::U64));
+        vec![Column::from((label("n"), dtype))]
+    }
+
+    #[tokio::test]
+    async fn test_insert_across_blocks() -> TCResult<()> {
+        let host = TestHost::new(vec![]).await?;
+        let txn = host.new_txn(true).await?;
+        let txn_id = *txn.id();
+
+        let btree = BTree::create(&txn, schema()).await?;
+
+        // insert in descending order, so that every insert lands in the first block
+        let len = 3 * super::super::store::BLOCK_SIZE as u64;
+        for n in (0..len).rev().step_by(7) {
+            btree.insert(txn_id, key(n)).await?;
+        }
+
+        btree
+            .insert_all(txn_id, (0..len).map(key).collect())
+            .await?;
+        btree.insert(txn_id, key(5)).await?;
+        assert_eq!(btree.count(&txn_id).await?, len);
+
+        let rows: Vec<Key> = btree.rows(txn_id).try_collect().await?;
+        assert!(rows == (0..len).map(key).collect::<Vec<Key>>());
+
+        let page = btree.page(&txn_id, len - 2, 10).await?;
+        assert!(page == vec![key(len - 2), key(len - 1)]);
+
+        let slice = btree.slice(&txn, key(17)).await?;
+        assert_eq!(slice.count(&txn_id).await?, 1);
+
+        let range = (Bound::Included(key(10)), Bound::Excluded(key(len - 10)));
+        let rows: Vec<Key> = btree.range(txn_id, range.clone())?.try_collect().await?;
+        assert!(rows == (10..len - 10).map(key).collect::<Vec<Key>>());
+
+        btree.delete(txn_id, range).await?;
+        assert_eq!(btree.count(&txn_id).await?, 20);
+
+        let range = (Bound::Excluded(key(5)), Bound::Unbounded);
+        let rows: Vec<Key> = btree.range(txn_id, range)?.try_collect().await?;
+        assert!(rows == (6..10).chain(len - 10..len).map(key).collect::<Vec<Key>>());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_null_range() -> TCResult<()> {
+        let host = TestHost::new(vec![]).await?;
+        let txn = host.new_txn(true).await?;
+        let txn_id = *txn.id();
+
+        let btree = BTree::create(&txn, schema()).await?;
+        btree
+            .insert_all(txn_id, vec![key(1), vec![Value::None], key(0)])
+            .await?;
+
+        // NULL sorts first, so it's the first row of the BTree
+        let rows: Vec<Key> = btree.rows(txn_id).try_collect().await?;
+        assert!(rows == vec![vec![Value::None], key(0), key(1)]);
+
+        // a NULL bound is like IS NULL
+        let null = (
+            Bound::Included(vec![Value::None]),
+            Bound::Included(vec![Value::None]),
+        );
+        let rows: Vec<Key> = btree.range(txn_id, null.clone())?.try_collect().await?;
+        assert!(rows == vec![vec![Value::None]]);
+
+        let not_null = (Bound::Excluded(vec![Value::None]), Bound::Unbounded);
+        let rows: Vec<Key> = btree.range(txn_id, not_null)?.try_collect().await?;
+        assert!(rows == vec![key(0), key(1)]);
+
+        btree.delete(txn_id, null).await?;
+        assert_eq!(btree.count(&txn_id).await?, 2);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_stats() -> TCResult<()> {
+        let host = TestHost::new(vec![]).await?;
+        let txn = host.new_txn(true).await?;
+        let txn_id = *txn.id();
+
+        let btree = BTree::create(&txn, schema()).await?;
+
+        let tracker = tc_transact::stats::track(txn_id);
+        btree.insert(txn_id, key(1)).await?;
+        assert!(tracker.stats().blocks_read() > 0);
+        assert!(tracker.stats().blocks_written() > 0);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_commit() -> TCResult<()> {
+        let host = TestHost::new(vec![]).await?;
+        let txn = host.new_txn(true).await?;
+        let dir = sort::temp_dir(&txn).await?;
+
+        let btree = BTree::load(schema(), dir.clone(), *txn.id()).await?;
+        btree.insert(*txn.id(), key(1)).await?;
+        txn.context().commit(txn.id()).await;
+
+        let next = host.new_txn(true).await?;
+        let btree = BTree::load(schema(), dir, *next.id()).await?;
+        btree.insert(*next.id(), key(2)).await?;
+        assert_eq!(btree.count(next.id()).await?, 2);
+
+        // the second transaction never commits, so a third only sees the first
+        let last = host.new_txn(false).await?;
+        assert_eq!(btree.count(last.id()).await?, 1);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_decode() -> TCResult<()> {
+        let host = TestHost::new(vec![]).await?;
+        let txn = host.new_txn(false).await?;
+
+        let encoded = r#"[[["n", "/state/scalar/value/number/uint/64"]], [[3], [1], [2], [1]]]"#;
+        let encoded = stream::once(future::ready(encoded.as_bytes().to_vec()));
+        let btree: BTree = destream_json::decode(txn.clone(), encoded)
+            .map_err(|e| TCError::bad_request("invalid BTree", e))
+            .await?;
+
+        let rows: Vec<Key> = btree.rows(*txn.id()).try_collect().await?;
+        assert!(rows == vec![key(1), key(2), key(3)]);
+
+        Ok(())
+    }
+}
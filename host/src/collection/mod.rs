@@ -0,0 +1,285 @@
+//! A [`Collection`] such as a [`BTree`], [`Table`], or [`Tensor`].
+//! INCOMPLETE AND UNSTABLE.
+
+use std::fmt;
+
+use async_trait::async_trait;
+use destream::{de, en, EncodeMap};
+use futures::stream::BoxStream;
+use futures::{TryFutureExt, TryStreamExt};
+use safecast::{CastFrom, TryCastInto};
+
+use tc_error::*;
+use tc_transact::fs::Persist;
+use tc_transact::{IntoView, Transact, Transaction, TxnId};
+use tc_value::{Number, Value, ValueType};
+use tcgeneric::*;
+
+use crate::fs::Dir;
+use crate::txn::Txn;
+
+mod aggregate;
+mod btree;
+mod schema;
+mod sort;
+mod store;
+mod table;
+mod tensor;
+
+pub use btree::{BTree, BTreeView, KeyRange};
+pub use schema::{collate, Column, Key, Row, RowSchema, TableSchema};
+pub use sort::{set_sort_budget, DEFAULT_SORT_BUDGET};
+pub use table::{Table, TableView};
+pub use tensor::{Shape, Tensor, TensorView};
+
+const PREFIX: PathLabel = path_label(&["state", "collection"]);
+
+/// The type of a [`Collection`].
+#[derive(Clone, Copy, Eq, PartialEq)]
+pub enum CollectionType {
+    BTree,
+    Table,
+    Tensor,
+}
+
+impl Class for CollectionType {
+    type Instance = Collection;
+}
+
+impl NativeClass for CollectionType {
+    fn from_path(path: &[PathSegment]) -> Option<Self> {
+        if path.len() == 3 && path[..2] == PREFIX[..] {
+            match path[2].as_str() {
+                "btree" => Some(Self::BTree),
+                "table" => Some(Self::Table),
+                "tensor" => Some(Self::Tensor),
+                _ => None,
+            }
+        } else {
+            None
+        }
+    }
+
+    fn path(&self) -> TCPathBuf {
+        let suffix = match self {
+            Self::BTree => "btree",
+            Self::Table => "table",
+            Self::Tensor => "tensor",
+        };
+
+        TCPathBuf::from(PREFIX).append(label(suffix))
+    }
+}
+
+impl fmt::Display for CollectionType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::BTree => f.write_str("type BTree"),
+            Self::Table => f.write_str("type Table"),
+            Self::Tensor => f.write_str("type Tensor"),
+        }
+    }
+}
+
+/// A stateful, transaction-aware collection of data, encoded as its schema plus its contents.
+///
+/// For example, a [`BTree`] with a single column is encoded as
+/// `{"/state/collection/btree": [[["name", {"/state/scalar/value/string": []}]], [["one"], ["two"]]]}`.
+#[derive(Clone)]
+pub enum Collection {
+    BTree(BTree),
+    Table(Table),
+    Tensor(Tensor),
+}
+
+impl Collection {
+    /// Construct a new, empty `Collection` of the given [`CollectionType`] from its schema, in
+    /// the workspace of the given `txn`.
+    ///
+    /// A `BTree` takes a list of columns like `["name", {"/state/scalar/value/string": []}]`,
+    /// a `Table` takes a list of key columns and a list of value columns, and a `Tensor` takes a
+    /// number type and a shape, like `[{"/state/scalar/value/number/float/32": []}, [2, 3]]`.
+    pub async fn create(txn: &Txn, class: CollectionType, schema: Value) -> TCResult<Self> {
+        let dir = sort::temp_dir(txn).await?;
+        Self::load(class, schema, dir, *txn.id()).await
+    }
+
+    /// Load the `Collection` of the given [`CollectionType`] and schema from the given `dir`, or
+    /// create it if it doesn't exist yet.
+    pub async fn load(
+        class: CollectionType,
+        schema: Value,
+        dir: Dir,
+        txn_id: TxnId,
+    ) -> TCResult<Self> {
+        match class {
+            CollectionType::BTree => {
+                let schema =
+                    schema.try_cast_into(|v| TCError::bad_request("invalid BTree schema", v))?;
+
+                BTree::load(schema, dir, txn_id).map_ok(Self::BTree).await
+            }
+            CollectionType::Table => {
+                let schema =
+                    schema.try_cast_into(|v| TCError::bad_request("invalid Table schema", v))?;
+
+                Table::load(schema, dir, txn_id).map_ok(Self::Table).await
+            }
+            CollectionType::Tensor => {
+                let (dtype, shape) = match schema {
+                    Value::Tuple(schema) if schema.len() == 2 => {
+                        let mut schema = schema.into_inner();
+                        let shape = schema.pop().unwrap();
+                        (schema.pop().unwrap(), shape)
+                    }
+                    other => return Err(TCError::bad_request("invalid Tensor schema", other)),
+                };
+
+                let shape: Vec<Number> =
+                    shape.try_cast_into(|v| TCError::bad_request("invalid Tensor shape", v))?;
+
+                let dtype = match schema::value_type(&dtype) {
+                    Some(ValueType::Number(dtype)) => dtype,
+                    _ => return Err(TCError::bad_request("invalid Tensor data type", dtype)),
+                };
+
+                let shape = shape.into_iter().map(u64::cast_from).collect();
+                Tensor::load((dtype, shape), dir, txn_id)
+                    .map_ok(Self::Tensor)
+                    .await
+            }
+        }
+    }
+}
+
+impl Instance for Collection {
+    type Class = CollectionType;
+
+    fn class(&self) -> CollectionType {
+        match self {
+            Self::BTree(_) => CollectionType::BTree,
+            Self::Table(_) => CollectionType::Table,
+            Self::Tensor(_) => CollectionType::Tensor,
+        }
+    }
+}
+
+impl From<BTree> for Collection {
+    fn from(btree: BTree) -> Self {
+        Self::BTree(btree)
+    }
+}
+
+impl From<Table> for Collection {
+    fn from(table: Table) -> Self {
+        Self::Table(table)
+    }
+}
+
+impl From<Tensor> for Collection {
+    fn from(tensor: Tensor) -> Self {
+        Self::Tensor(tensor)
+    }
+}
+
+#[async_trait]
+impl Transact for Collection {
+    async fn commit(&self, txn_id: &TxnId) {
+        match self {
+            Self::BTree(btree) => btree.commit(txn_id).await,
+            Self::Table(table) => table.commit(txn_id).await,
+            Self::Tensor(tensor) => tensor.commit(txn_id).await,
+        }
+    }
+
+    async fn finalize(&self, txn_id: &TxnId) {
+        match self {
+            Self::BTree(btree) => btree.finalize(txn_id).await,
+            Self::Table(table) => table.finalize(txn_id).await,
+            Self::Tensor(tensor) => tensor.finalize(txn_id).await,
+        }
+    }
+}
+
+impl<'en> IntoView<'en, Dir> for Collection {
+    type Txn = Txn;
+    type View = CollectionView;
+
+    fn into_view(self, txn: Txn) -> CollectionView {
+        CollectionView {
+            collection: self,
+            txn,
+        }
+    }
+}
+
+impl fmt::Display for Collection {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::BTree(btree) => fmt::Display::fmt(btree, f),
+            Self::Table(table) => fmt::Display::fmt(table, f),
+            Self::Tensor(tensor) => fmt::Display::fmt(tensor, f),
+        }
+    }
+}
+
+/// A view of a [`Collection`] as of a single transaction, used to encode it.
+pub struct CollectionView {
+    collection: Collection,
+    txn: Txn,
+}
+
+impl<'en> en::IntoStream<'en> for CollectionView {
+    fn into_stream<E: en::Encoder<'en>>(self, encoder: E) -> Result<E::Ok, E::Error> {
+        let class = self.collection.class().path().to_string();
+        let mut map = encoder.encode_map(Some(1))?;
+
+        match self.collection {
+            Collection::BTree(btree) => map.encode_entry(class, btree.into_view(self.txn)),
+            Collection::Table(table) => map.encode_entry(class, table.into_view(self.txn)),
+            Collection::Tensor(tensor) => map.encode_entry(class, tensor.into_view(self.txn)),
+        }?;
+
+        map.end()
+    }
+}
+
+/// A helper struct to decode a [`Collection`] given its [`CollectionType`].
+pub struct CollectionVisitor;
+
+impl CollectionVisitor {
+    /// Decode the schema and contents of a [`Collection`] of the given [`CollectionType`] into
+    /// the workspace of the given `txn`.
+    pub async fn visit_map_value<A: de::MapAccess>(
+        txn: Txn,
+        class: CollectionType,
+        access: &mut A,
+    ) -> Result<Collection, A::Error> {
+        match class {
+            CollectionType::BTree => access.next_value(txn).map_ok(Collection::BTree).await,
+            CollectionType::Table => access.next_value(txn).map_ok(Collection::Table).await,
+            CollectionType::Tensor => access.next_value(txn).map_ok(Collection::Tensor).await,
+        }
+    }
+}
+
+/// The contents of a [`Collection`] as of a single transaction, encoded as a stream of elements.
+struct Contents<T> {
+    elements: BoxStream<'static, TCResult<T>>,
+}
+
+impl<T> Contents<T> {
+    fn new(elements: BoxStream<'static, TCResult<T>>) -> Self {
+        Self { elements }
+    }
+}
+
+impl<'en, T> en::IntoStream<'en> for Contents<T>
+where
+    T: en::IntoStream<'en> + Send + 'en,
+{
+    fn into_stream<E: en::Encoder<'en>>(self, encoder: E) -> Result<E::Ok, E::Error> {
+        let contents = self.elements.map_err(en::Error::custom);
+        encoder.encode_seq_stream(contents)
+    }
+}
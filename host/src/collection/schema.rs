@@ -0,0 +1,620 @@
+//! The schema of a [`super::BTree`] or [`super::Table`].
+
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::fmt;
+
+use async_trait::async_trait;
+use bytes::Bytes;
+use destream::{de, en};
+use safecast::{CastFrom, TryCastFrom, TryCastInto};
+
+use tc_error::*;
+use tc_value::{Link, Number, Value, ValueType};
+use tcgeneric::{Id, NativeClass, Tuple};
+
+/// A single row of a [`super::BTree`] or [`super::Table`].
+pub type Key = Vec<Value>;
+
+/// The schema of a row of a [`super::BTree`].
+pub type RowSchema = Vec<Column>;
+
+/// A named, typed column, with an optional maximum length for a `String` or `Bytes` column.
+#[derive(Clone, Eq, PartialEq)]
+pub struct Column {
+    name: Id,
+    dtype: ValueType,
+    max_len: Option<usize>,
+}
+
+impl Column {
+    /// The name of this `Column`.
+    pub fn name(&'_ self) -> &'_ Id {
+        &self.name
+    }
+
+    /// The [`ValueType`] of this `Column`.
+    pub fn dtype(&self) -> ValueType {
+        self.dtype
+    }
+
+    /// The maximum length of a value in this `Column`, if any.
+    pub fn max_len(&'_ self) -> &'_ Option<usize> {
+        &self.max_len
+    }
+
+    /// Cast the given [`Value`] into the type of this `Column`, or return an error.
+    ///
+    /// `NULL`, i.e. [`Value::None`], is valid in a column of any type, including a key column,
+    /// and is never cast. See [`collate`] for how `NULL` is ordered.
+    pub fn validate(&self, value: Value) -> TCResult<Value> {
+        if let Value::None = value {
+            return Ok(Value::None);
+        }
+
+        let value = value.clone().into_type(self.dtype).ok_or_else(|| {
+            TCError::bad_request(format!("invalid value for column {}", self.name), value)
+        })?;
+
+        if let Some(max_len) = self.max_len {
+            let len = match &value {
+                Value::Bytes(bytes) => bytes.len(),
+                Value::String(s) => s.len(),
+                _ => 0,
+            };
+
+            if len > max_len {
+                return Err(TCError::bad_request(
+                    format!("column {} has a maximum length of {}", self.name, max_len),
+                    len,
+                ));
+            }
+        }
+
+        Ok(value)
+    }
+}
+
+impl<I: Into<Id>> From<(I, ValueType)> for Column {
+    fn from(column: (I, ValueType)) -> Column {
+        let (name, dtype) = column;
+        Column {
+            name: name.into(),
+            dtype,
+            max_len: None,
+        }
+    }
+}
+
+impl TryCastFrom<Value> for Column {
+    fn can_cast_from(value: &Value) -> bool {
+        match value {
+            Value::Tuple(tuple) if tuple.len() == 2 || tuple.len() == 3 => {
+                Id::can_cast_from(&tuple[0])
+                    && value_type(&tuple[1]).is_some()
+                    && tuple.get(2).map(Number::can_cast_from).unwrap_or(true)
+            }
+            _ => false,
+        }
+    }
+
+    fn opt_cast_from(value: Value) -> Option<Self> {
+        let mut column = match value {
+            Value::Tuple(tuple) if tuple.len() == 2 || tuple.len() == 3 => tuple.into_inner(),
+            _ => return None,
+        };
+
+        let max_len = if column.len() == 3 {
+            let max_len: Number = column.pop().unwrap().opt_cast_into()?;
+            Some(usize::cast_from(max_len))
+        } else {
+            None
+        };
+
+        let dtype = value_type(&column.pop().unwrap())?;
+        let name = column.pop().unwrap().opt_cast_into()?;
+
+        Some(Column {
+            name,
+            dtype,
+            max_len,
+        })
+    }
+}
+
+#[async_trait]
+impl de::FromStream for Column {
+    type Context = ();
+
+    async fn from_stream<D: de::Decoder>(_: (), decoder: &mut D) -> Result<Self, D::Error> {
+        decoder.decode_seq(ColumnVisitor).await
+    }
+}
+
+impl<'en> en::IntoStream<'en> for Column {
+    fn into_stream<E: en::Encoder<'en>>(self, encoder: E) -> Result<E::Ok, E::Error> {
+        let dtype = Link::from(self.dtype.path());
+
+        if let Some(max_len) = self.max_len {
+            (self.name, dtype, max_len as u64).into_stream(encoder)
+        } else {
+            (self.name, dtype).into_stream(encoder)
+        }
+    }
+}
+
+impl fmt::Display for Column {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if let Some(max_len) = self.max_len {
+            write!(f, "{}: {} (max length {})", self.name, self.dtype, max_len)
+        } else {
+            write!(f, "{}: {}", self.name, self.dtype)
+        }
+    }
+}
+
+struct ColumnVisitor;
+
+#[async_trait]
+impl de::Visitor for ColumnVisitor {
+    type Value = Column;
+
+    fn expecting() -> &'static str {
+        "a Column definition, e.g. [\"name\", \"/state/scalar/value/string\", 128]"
+    }
+
+    async fn visit_seq<A: de::SeqAccess>(self, mut access: A) -> Result<Self::Value, A::Error> {
+        let name = access
+            .next_element(())
+            .await?
+            .ok_or_else(|| de::Error::invalid_length(0, Self::expecting()))?;
+
+        let dtype = access
+            .next_element(())
+            .await?
+            .ok_or_else(|| de::Error::invalid_length(1, Self::expecting()))?;
+
+        let dtype: Value = dtype;
+        let dtype =
+            value_type(&dtype).ok_or_else(|| de::Error::invalid_value(dtype, "a Value type"))?;
+
+        let max_len: Option<u64> = access.next_element(()).await?;
+
+        Ok(Column {
+            name,
+            dtype,
+            max_len: max_len.map(|max_len| max_len as usize),
+        })
+    }
+}
+
+/// The schema of a [`super::Table`], i.e. its key columns and value columns.
+#[derive(Clone, Eq, PartialEq)]
+pub struct TableSchema {
+    key: RowSchema,
+    values: RowSchema,
+    offsets: HashMap<Id, usize>,
+}
+
+impl TableSchema {
+    fn new(key: RowSchema, values: RowSchema) -> Self {
+        let offsets = key
+            .iter()
+            .chain(&values)
+            .enumerate()
+            .map(|(i, column)| (column.name.clone(), i))
+            .collect();
+
+        Self {
+            key,
+            values,
+            offsets,
+        }
+    }
+
+    /// The key columns of this `TableSchema`.
+    pub fn key(&'_ self) -> &'_ [Column] {
+        &self.key
+    }
+
+    /// The value columns of this `TableSchema`.
+    pub fn values(&'_ self) -> &'_ [Column] {
+        &self.values
+    }
+
+    /// The schema of the primary index of a [`super::Table`] with this schema.
+    pub fn primary(&self) -> RowSchema {
+        self.key.iter().chain(&self.values).cloned().collect()
+    }
+
+    /// The offset of the column with the given `name` in a row of the primary index of a
+    /// [`super::Table`] with this schema, if there is such a column.
+    pub fn offset(&self, name: &Id) -> Option<usize> {
+        self.offsets.get(name).copied()
+    }
+
+    /// The offset of the value column with the given `name` in a row of the primary index of a
+    /// [`super::Table`] with this schema, if there is such a column.
+    pub fn value_offset(&self, name: &Id) -> Option<usize> {
+        self.offsets
+            .get(name)
+            .copied()
+            .filter(|offset| *offset >= self.key.len())
+    }
+
+    /// Construct a typed [`Row`] view of the given `row`, which must be a row of the primary
+    /// index of a [`super::Table`] with this schema, i.e. one which has already been validated.
+    pub fn row<'a>(&'a self, row: &'a [Value]) -> Row<'a> {
+        debug_assert_eq!(row.len(), self.offsets.len());
+
+        Row {
+            schema: self,
+            values: row,
+        }
+    }
+
+    fn column(&self, offset: usize) -> &Column {
+        if offset < self.key.len() {
+            &self.key[offset]
+        } else {
+            &self.values[offset - self.key.len()]
+        }
+    }
+}
+
+impl From<(RowSchema, RowSchema)> for TableSchema {
+    fn from(schema: (RowSchema, RowSchema)) -> Self {
+        let (key, values) = schema;
+        Self::new(key, values)
+    }
+}
+
+impl TryCastFrom<Value> for TableSchema {
+    fn can_cast_from(value: &Value) -> bool {
+        <(RowSchema, RowSchema) as TryCastFrom<Value>>::can_cast_from(value)
+    }
+
+    fn opt_cast_from(value: Value) -> Option<Self> {
+        let (key, values): (RowSchema, RowSchema) = value.opt_cast_into()?;
+
+        if key.is_empty() {
+            None
+        } else {
+            Some(Self::new(key, values))
+        }
+    }
+}
+
+#[async_trait]
+impl de::FromStream for TableSchema {
+    type Context = ();
+
+    async fn from_stream<D: de::Decoder>(context: (), decoder: &mut D) -> Result<Self, D::Error> {
+        let (key, values): (RowSchema, RowSchema) =
+            de::FromStream::from_stream(context, decoder).await?;
+
+        if key.is_empty() {
+            return Err(de::Error::custom(
+                "a Table must have at least one key column",
+            ));
+        }
+
+        Ok(Self::new(key, values))
+    }
+}
+
+impl<'en> en::IntoStream<'en> for TableSchema {
+    fn into_stream<E: en::Encoder<'en>>(self, encoder: E) -> Result<E::Ok, E::Error> {
+        (self.key, self.values).into_stream(encoder)
+    }
+}
+
+impl fmt::Display for TableSchema {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} => {}", display(&self.key), display(&self.values))
+    }
+}
+
+/// A typed view of a validated row of a [`super::Table`], whose columns are looked up by name.
+///
+/// Since each value of a validated row already has the type of its [`Column`], the typed getters
+/// of a `Row` return a reference to the value itself rather than casting it.
+pub struct Row<'a> {
+    schema: &'a TableSchema,
+    values: &'a [Value],
+}
+
+impl<'a> Row<'a> {
+    /// Borrow the value of the column with the given `name`.
+    pub fn get(&self, name: &Id) -> TCResult<&'a Value> {
+        self.schema
+            .offsets
+            .get(name)
+            .map(|offset| &self.values[*offset])
+            .ok_or_else(|| TCError::not_found(format!("column {}", name.original())))
+    }
+
+    /// Borrow the value of the `Bytes` column with the given `name`.
+    pub fn get_bytes(&self, name: &Id) -> TCResult<Option<&'a Bytes>> {
+        match self.get(name)? {
+            Value::Bytes(bytes) => Ok(Some(bytes)),
+            Value::None => Ok(None),
+            other => Err(self.invalid(name, other)),
+        }
+    }
+
+    /// Borrow the value of the `Link` column with the given `name`.
+    pub fn get_link(&self, name: &Id) -> TCResult<Option<&'a Link>> {
+        match self.get(name)? {
+            Value::Link(link) => Ok(Some(link)),
+            Value::None => Ok(None),
+            other => Err(self.invalid(name, other)),
+        }
+    }
+
+    /// Return the value of the `Number` column with the given `name`.
+    pub fn get_number(&self, name: &Id) -> TCResult<Option<Number>> {
+        match self.get(name)? {
+            Value::Number(n) => Ok(Some(*n)),
+            Value::None => Ok(None),
+            other => Err(self.invalid(name, other)),
+        }
+    }
+
+    /// Borrow the value of the `String` column with the given `name`.
+    pub fn get_str(&self, name: &Id) -> TCResult<Option<&'a str>> {
+        match self.get(name)? {
+            Value::String(s) => Ok(Some(s)),
+            Value::None => Ok(None),
+            other => Err(self.invalid(name, other)),
+        }
+    }
+
+    fn invalid(&self, name: &Id, value: &Value) -> TCError {
+        let column = self.schema.column(self.schema.offsets[name]);
+        TCError::bad_request(
+            format!("wrong type requested for column {}, found", column),
+            value,
+        )
+    }
+}
+
+/// Cast each value in `row` into the type of its [`Column`], or return an error.
+pub fn validate_row(schema: &[Column], row: Key) -> TCResult<Key> {
+    if row.len() != schema.len() {
+        return Err(TCError::bad_request(
+            format!("expected a row with {} columns but found", schema.len()),
+            Value::Tuple(Tuple::from(row)),
+        ));
+    }
+
+    schema
+        .iter()
+        .zip(row)
+        .map(|(column, value)| column.validate(value))
+        .collect()
+}
+
+/// Cast each value in `prefix` into the type of its [`Column`], or return an error.
+///
+/// A `NULL` in `prefix` is kept as-is, so that the prefix matches only rows which are `NULL` in
+/// that column, like `IS NULL` in SQL. This is also how each end of a range is validated.
+pub fn validate_prefix(schema: &[Column], prefix: Key) -> TCResult<Key> {
+    if prefix.len() > schema.len() {
+        return Err(TCError::bad_request(
+            format!("expected at most {} columns but found", schema.len()),
+            prefix.len(),
+        ));
+    }
+
+    schema
+        .iter()
+        .zip(prefix)
+        .map(|(column, value)| column.validate(value))
+        .collect()
+}
+
+/// Compare two rows, or a row and a prefix of a row, in key order.
+///
+/// A prefix is considered equal to every row which begins with it.
+///
+/// `NULL`, i.e. [`Value::None`], sorts before every other value, and is equal to itself, so that
+/// the rows which are `NULL` in the same column are kept together, whether they're sorted,
+/// grouped, deduplicated, or matched by a prefix or a range. Unlike SQL, this means that a unique
+/// key may only be `NULL` in a single row.
+///
+/// Otherwise, values of different types sort by type: numbers, then strings, bytes, links, and
+/// tuples. A `NaN` sorts after every other number and is equal to itself.
+pub fn collate(left: &[Value], right: &[Value]) -> Ordering {
+    for (l, r) in left.iter().zip(right) {
+        match collate_value(l, r) {
+            Ordering::Equal => {}
+            order => return order,
+        }
+    }
+
+    Ordering::Equal
+}
+
+fn collate_value(left: &Value, right: &Value) -> Ordering {
+    match (left, right) {
+        // NULL is handled first, so that it's never compared by its string representation
+        (Value::None, Value::None) => Ordering::Equal,
+        (Value::None, _) => Ordering::Less,
+        (_, Value::None) => Ordering::Greater,
+        (Value::Bytes(l), Value::Bytes(r)) => l.cmp(r),
+        (Value::Link(l), Value::Link(r)) => l.to_string().cmp(&r.to_string()),
+        (Value::Number(l), Value::Number(r)) => collate_number(l, r),
+        (Value::String(l), Value::String(r)) => l.cmp(r),
+        (Value::Tuple(l), Value::Tuple(r)) => match collate(l, r) {
+            Ordering::Equal => l.len().cmp(&r.len()),
+            order => order,
+        },
+        (l, r) => rank(l).cmp(&rank(r)),
+    }
+}
+
+// NaN sorts after every other number, and is equal to itself
+fn collate_number(left: &Number, right: &Number) -> Ordering {
+    match (is_nan(left), is_nan(right)) {
+        (true, true) => Ordering::Equal,
+        (true, false) => Ordering::Greater,
+        (false, true) => Ordering::Less,
+        (false, false) => left.partial_cmp(right).unwrap_or(Ordering::Equal),
+    }
+}
+
+fn is_nan(n: &Number) -> bool {
+    n.partial_cmp(n).is_none()
+}
+
+// the sort position of each type of value, so that a column of mixed types is still sorted
+fn rank(value: &Value) -> u8 {
+    match value {
+        Value::None => 0,
+        Value::Number(_) => 1,
+        Value::String(_) => 2,
+        Value::Bytes(_) => 3,
+        Value::Link(_) => 4,
+        Value::Tuple(_) => 5,
+    }
+}
+
+/// Format the given [`RowSchema`] for display.
+pub fn display(schema: &[Column]) -> String {
+    let columns: Vec<String> = schema.iter().map(|column| column.to_string()).collect();
+    format!("[{}]", columns.join(", "))
+}
+
+/// Parse a [`ValueType`] encoded as a [`Link`] to its class, e.g. `"/state/scalar/value/string"`.
+pub fn value_type(dtype: &Value) -> Option<ValueType> {
+    // a `Link` is encoded as a string, so a decoded data type will usually be a `Value::String`
+    let link = match dtype {
+        Value::Link(link) => link.clone(),
+        Value::String(s) => s.parse::<Link>().ok()?,
+        _ => return None,
+    };
+
+    if link.host().is_none() {
+        ValueType::from_path(link.path())
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tc_value::{NumberType, UIntType};
+    use tcgeneric::label;
+
+    use super::*;
+
+    #[test]
+    fn test_row() -> TCResult<()> {
+        let uint = ValueType::Number(NumberType::UInt(UIntType::U64));
+        let key = vec![Column::from((label("id"), uint))];
+        let values = vec![
+            Column::from((label("name"), ValueType::String)),
+            Column::from((label("age"), uint)),
+        ];
+        let schema = TableSchema::from((key, values));
+
+        let values = vec![
+            Value::from(Number::from(1u64)),
+            Value::String("alice".into()),
+            Value::None,
+        ];
+        let row = schema.row(&values);
+
+        let id: Id = label("id").into();
+        let name: Id = label("name").into();
+        let age: Id = label("age").into();
+        assert!(row.get_number(&id)? == Some(Number::from(1u64)));
+        assert_eq!(row.get_str(&name)?, Some("alice"));
+        assert!(row.get_number(&age)?.is_none());
+
+        assert!(row.get_number(&name).unwrap_err().code() == ErrorType::BadRequest);
+
+        let unknown: Id = label("unknown").into();
+        assert!(row.get(&unknown).map(|_| ()).unwrap_err().code() == ErrorType::NotFound);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_value_offset() {
+        let uint = ValueType::Number(NumberType::UInt(UIntType::U64));
+        let key = vec![
+            Column::from((label("a"), uint)),
+            Column::from((label("b"), uint)),
+        ];
+        let values = vec![Column::from((label("v"), uint))];
+        let schema = TableSchema::from((key, values));
+
+        let a: Id = label("a").into();
+        let v: Id = label("v").into();
+        let unknown: Id = label("unknown").into();
+        assert_eq!(schema.value_offset(&v), Some(2));
+        assert_eq!(schema.value_offset(&a), None);
+        assert_eq!(schema.value_offset(&unknown), None);
+    }
+
+    #[test]
+    fn test_null() -> TCResult<()> {
+        let uint = ValueType::Number(NumberType::UInt(UIntType::U64));
+        let column = Column::from((label("a"), uint));
+        assert!(column.validate(Value::None)? == Value::None);
+
+        let schema = vec![column, Column::from((label("b"), uint))];
+        let prefix = validate_prefix(&schema, vec![Value::None])?;
+        assert!(prefix == vec![Value::None]);
+
+        let one = Value::from(Number::from(1u64));
+        let null_row = vec![Value::None, one.clone()];
+        let one_row = vec![one.clone(), one.clone()];
+
+        assert_eq!(collate(&null_row, &one_row), Ordering::Less);
+        assert_eq!(collate(&one_row, &null_row), Ordering::Greater);
+        assert_eq!(collate(&null_row, &null_row), Ordering::Equal);
+        assert_eq!(collate(&prefix, &null_row), Ordering::Equal);
+        assert_eq!(collate(&prefix, &one_row), Ordering::Less);
+
+        // NULL is not the same as an empty string
+        let empty = vec![Value::String("".into())];
+        assert_eq!(collate(&prefix, &empty), Ordering::Less);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_collate_types() {
+        let nan = Value::from(Number::from(f64::NAN));
+        let one = Value::from(Number::from(1.));
+        let max = Value::from(Number::from(f64::MAX));
+        let string = Value::String("1".into());
+
+        assert_eq!(collate_value(&nan, &nan), Ordering::Equal);
+        assert_eq!(collate_value(&max, &nan), Ordering::Less);
+        assert_eq!(collate_value(&nan, &one), Ordering::Greater);
+        assert_eq!(collate_value(&Value::None, &nan), Ordering::Less);
+
+        // values of different types sort by type, not by their string representation
+        assert_eq!(collate_value(&one, &string), Ordering::Less);
+        assert_eq!(collate_value(&string, &nan), Ordering::Greater);
+
+        let mut rows = vec![
+            vec![string.clone()],
+            vec![nan.clone()],
+            vec![Value::None],
+            vec![one.clone()],
+        ];
+        rows.sort_by(|l, r| collate(l, r));
+
+        // NaN is not equal to itself under `==`, so compare each row by collation
+        let expected = vec![vec![Value::None], vec![one], vec![nan], vec![string]];
+        assert!(rows
+            .iter()
+            .zip(&expected)
+            .all(|(row, expected)| collate(row, expected) == Ordering::Equal));
+    }
+}
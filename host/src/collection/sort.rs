@@ -0,0 +1,411 @@
+//! A parallel merge sort, for bulk loads of collection rows, and an external merge sort, for
+//! sorting more rows than fit in memory.
+
+use std::cmp::Ordering;
+use std::convert::TryFrom;
+use std::sync::atomic::{AtomicUsize, Ordering as AtomicOrdering};
+
+use bytes::Bytes;
+use futures::future::try_join_all;
+use futures::stream::{self, BoxStream, Stream, StreamExt, TryStreamExt};
+use uuid::Uuid;
+
+use tc_error::*;
+use tc_transact::fs::{Dir, File};
+use tc_transact::{Transaction, TxnId};
+use tcgeneric::{label, Id, Label};
+
+use crate::concurrency::Concurrency;
+use crate::fs;
+use crate::scalar::{ScalarType, Value, ValueType};
+use crate::state::StateType;
+use crate::txn::Txn;
+
+use super::schema::Key;
+
+/// The default maximum size, in bytes, of the rows which an external sort holds in memory.
+pub const DEFAULT_SORT_BUDGET: usize = 64_000_000;
+
+// the smallest run worth sorting on its own worker
+const MIN_RUN: usize = 4096;
+
+// the number of rows in each block of a run spilled to disk
+pub(super) const BLOCK_ROWS: usize = 1024;
+
+// the number of blocks of each spilled run to read ahead while merging
+const READ_AHEAD: usize = 2;
+
+// the name of the file of a spilled run, within the directory of that run
+const ROWS: Label = label("rows");
+
+static SORT_BUDGET: AtomicUsize = AtomicUsize::new(DEFAULT_SORT_BUDGET);
+
+/// Set the maximum size, in bytes, of the rows which an external sort holds in memory.
+///
+/// The size of a row is estimated, so this is a soft limit.
+pub fn set_sort_budget(bytes: usize) {
+    SORT_BUDGET.store(bytes.max(1), AtomicOrdering::Relaxed);
+}
+
+/// Sort `items` by `compare`.
+///
+/// A large input is split into runs which are sorted concurrently on the blocking thread pool,
+/// up to [`Concurrency::sort`] at a time, then merged pairwise, also concurrently. The sort is
+/// stable.
+pub async fn sort_by<T, F>(mut items: Vec<T>, compare: F) -> TCResult<Vec<T>>
+where
+    T: Send + 'static,
+    F: Fn(&T, &T) -> Ordering + Clone + Send + 'static,
+{
+    let workers = Concurrency::current().sort;
+    if workers < 2 || items.len() < MIN_RUN * 2 {
+        items.sort_by(compare);
+        return Ok(items);
+    }
+
+    let run_len = std::cmp::max(MIN_RUN, items.len().div_ceil(workers));
+    let mut runs = Vec::with_capacity(items.len().div_ceil(run_len));
+    while items.len() > run_len {
+        runs.push(items.split_off(items.len() - run_len));
+    }
+    runs.push(items);
+    runs.reverse();
+
+    let sorts = runs.into_iter().map(|mut run| {
+        let compare = compare.clone();
+        tokio::task::spawn_blocking(move || {
+            run.sort_by(compare);
+            run
+        })
+    });
+
+    let mut runs = try_join_all(sorts).await.map_err(TCError::internal)?;
+
+    while runs.len() > 1 {
+        let mut pairs = Vec::with_capacity(runs.len().div_ceil(2));
+        let mut unpaired = runs.into_iter();
+        while let Some(left) = unpaired.next() {
+            pairs.push((left, unpaired.next()));
+        }
+
+        let merges = pairs.into_iter().map(|(left, right)| {
+            let compare = compare.clone();
+            tokio::task::spawn_blocking(move || match right {
+                Some(right) => merge_by(left, right, compare),
+                None => left,
+            })
+        });
+
+        runs = try_join_all(merges).await.map_err(TCError::internal)?;
+    }
+
+    Ok(runs.pop().unwrap_or_default())
+}
+
+/// Merge the sorted runs `left` and `right` into a single sorted run.
+///
+/// Where an item of `left` is equal to an item of `right`, the item of `left` comes first.
+pub fn merge_by<T, F>(left: Vec<T>, right: Vec<T>, compare: F) -> Vec<T>
+where
+    F: Fn(&T, &T) -> Ordering,
+{
+    let mut merged = Vec::with_capacity(left.len() + right.len());
+    let mut left = left.into_iter().peekable();
+    let mut right = right.into_iter().peekable();
+
+    loop {
+        let next = match (left.peek(), right.peek()) {
+            (Some(l), Some(r)) if compare(l, r) == Ordering::Greater => right.next(),
+            (Some(_), _) => left.next(),
+            (None, Some(_)) => right.next(),
+            (None, None) => return merged,
+        };
+
+        merged.extend(next);
+    }
+}
+
+/// The maximum size, in bytes, of the rows which a spilling operator should hold in memory.
+pub(super) fn sort_budget() -> usize {
+    SORT_BUDGET.load(AtomicOrdering::Relaxed)
+}
+
+/// Sort the given `rows` by `compare`, in the workspace of the given transaction.
+///
+/// Whenever the rows held in memory exceed the sort budget, they're sorted and spilled to a file
+/// in the workspace as a sorted run. The spilled runs are then merged with the rows which remain
+/// in memory as the returned stream is consumed. The sort is stable.
+pub async fn external_sort_by<S, F>(
+    txn: &Txn,
+    rows: S,
+    compare: F,
+) -> TCResult<BoxStream<'static, TCResult<Key>>>
+where
+    S: Stream<Item = TCResult<Key>> + Send + Unpin,
+    F: Fn(&Key, &Key) -> Ordering + Clone + Send + Sync + 'static,
+{
+    external_sort_with_budget(txn, rows, compare, sort_budget()).await
+}
+
+async fn external_sort_with_budget<S, F>(
+    txn: &Txn,
+    mut rows: S,
+    compare: F,
+    budget: usize,
+) -> TCResult<BoxStream<'static, TCResult<Key>>>
+where
+    S: Stream<Item = TCResult<Key>> + Send + Unpin,
+    F: Fn(&Key, &Key) -> Ordering + Clone + Send + Sync + 'static,
+{
+    let txn_id = *txn.id();
+
+    let mut workspace: Option<fs::Dir> = None;
+    let mut runs = Vec::new();
+    let mut buffer = Vec::new();
+    let mut buffered = 0;
+
+    while let Some(row) = rows.try_next().await? {
+        buffered += size_of(&row);
+        buffer.push(row);
+
+        if buffered >= budget {
+            let run = sort_by(std::mem::take(&mut buffer), compare.clone()).await?;
+            buffered = 0;
+
+            let dir = match &workspace {
+                Some(dir) => dir.clone(),
+                None => {
+                    let dir = temp_dir(txn).await?;
+                    workspace = Some(dir.clone());
+                    dir
+                }
+            };
+
+            let mut spilled = Run::create(&dir, txn_id, runs.len()).await?;
+            for block in run.chunks(BLOCK_ROWS) {
+                spilled.append(txn_id, block).await?;
+            }
+
+            runs.push(spilled);
+        }
+    }
+
+    let last = sort_by(buffer, compare.clone()).await?;
+    let last = stream::iter(last.into_iter().map(Ok)).boxed();
+    if runs.is_empty() {
+        return Ok(last);
+    }
+
+    let mut sources = runs
+        .into_iter()
+        .map(|run| run.read(txn_id))
+        .collect::<TCResult<Vec<_>>>()?;
+
+    sources.push(last);
+
+    let merge = Merge::new(sources, compare).await?;
+    let merged = stream::unfold(merge, |mut merge| async move {
+        merge.next().await.map(|row| (row, merge))
+    });
+
+    Ok(merged.boxed())
+}
+
+// a k-way merge of sorted runs, which prefers the earliest run when rows are equal
+struct Merge<F> {
+    runs: Vec<BoxStream<'static, TCResult<Key>>>,
+    heads: Vec<Option<Key>>,
+    compare: F,
+}
+
+impl<F: Fn(&Key, &Key) -> Ordering> Merge<F> {
+    async fn new(mut runs: Vec<BoxStream<'static, TCResult<Key>>>, compare: F) -> TCResult<Self> {
+        let mut heads = Vec::with_capacity(runs.len());
+        for run in &mut runs {
+            heads.push(run.try_next().await?);
+        }
+
+        Ok(Self {
+            runs,
+            heads,
+            compare,
+        })
+    }
+
+    async fn next(&mut self) -> Option<TCResult<Key>> {
+        let mut min: Option<usize> = None;
+        for (i, head) in self.heads.iter().enumerate() {
+            if let Some(row) = head {
+                min = match min {
+                    Some(j) => {
+                        let least = self.heads[j].as_ref().expect("merge head");
+                        if (self.compare)(row, least) == Ordering::Less {
+                            Some(i)
+                        } else {
+                            Some(j)
+                        }
+                    }
+                    None => Some(i),
+                };
+            }
+        }
+
+        let i = min?;
+        let row = self.heads[i].take();
+
+        match self.runs[i].try_next().await {
+            Ok(next) => self.heads[i] = next,
+            Err(cause) => return Some(Err(cause)),
+        }
+
+        row.map(Ok)
+    }
+}
+
+/// Create a new directory, with a unique name, in the workspace of the given `txn`.
+pub(super) async fn temp_dir(txn: &Txn) -> TCResult<fs::Dir> {
+    txn.context()
+        .create_dir(*txn.id(), Uuid::new_v4().into())
+        .await
+}
+
+/// A sequence of rows spilled to a file in the workspace, one block at a time.
+pub(super) struct Run {
+    file: fs::File<Bytes>,
+    num_blocks: usize,
+}
+
+impl Run {
+    /// Create a new, empty `Run` with the given number in the given `dir`.
+    ///
+    /// Each `Run` gets a directory of its own, since the pending versions of the blocks of every
+    /// file in the same directory share a version directory, where the blocks of two runs would
+    /// overwrite each other.
+    pub async fn create(dir: &fs::Dir, txn_id: TxnId, run: usize) -> TCResult<Self> {
+        let dir = dir.create_dir(txn_id, block_id(run)?).await?;
+        let class = StateType::Scalar(ScalarType::Value(ValueType::Bytes));
+        let file = dir.create_file(txn_id, ROWS.into(), class).await?;
+        let file = fs::File::<Bytes>::try_from(file)?;
+        Ok(Self {
+            file,
+            num_blocks: 0,
+        })
+    }
+
+    /// Write the given `rows` to a new block at the end of this `Run`.
+    pub async fn append(&mut self, txn_id: TxnId, rows: &[Key]) -> TCResult<()> {
+        let block = serde_json::to_vec(rows)
+            .map_err(|e| TCError::internal(format!("unable to spill rows: {}", e)))?;
+
+        self.file
+            .create_block(txn_id, block_id(self.num_blocks)?, Bytes::from(block))
+            .await?;
+
+        self.num_blocks += 1;
+        Ok(())
+    }
+
+    /// Stream the rows of this `Run` back from the workspace, in the order they were written.
+    pub fn read(self, txn_id: TxnId) -> TCResult<BoxStream<'static, TCResult<Key>>> {
+        let block_ids = (0..self.num_blocks)
+            .map(block_id)
+            .collect::<TCResult<Vec<Id>>>()?;
+
+        let rows = self
+            .file
+            .read_ahead(txn_id, block_ids, READ_AHEAD)
+            .map(|block| {
+                let block = block?;
+                serde_json::from_slice::<Vec<Key>>(&block)
+                    .map_err(|e| TCError::internal(format!("spilled rows corrupted! {}", e)))
+            })
+            .map_ok(|rows| stream::iter(rows.into_iter().map(Ok)))
+            .try_flatten();
+
+        Ok(rows.boxed())
+    }
+}
+
+#[inline]
+fn block_id(i: usize) -> TCResult<Id> {
+    i.to_string().parse()
+}
+
+/// Estimate the memory used by the given `row`.
+pub(super) fn size_of(row: &[Value]) -> usize {
+    row.iter().map(value_size).sum()
+}
+
+fn value_size(value: &Value) -> usize {
+    let heap = match value {
+        Value::Bytes(bytes) => bytes.len(),
+        Value::Link(link) => link.to_string().len(),
+        Value::String(s) => s.len(),
+        Value::Tuple(tuple) => tuple.iter().map(value_size).sum(),
+        _ => 0,
+    };
+
+    std::mem::size_of::<Value>() + heap
+}
+
+#[cfg(test)]
+mod tests {
+    use tc_value::Number;
+
+    use crate::test::TestHost;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_sort_by() -> TCResult<()> {
+        // enough items to be split into runs, with many equal keys to check that the sort is stable
+        let len = MIN_RUN * 5 + 3;
+        let items: Vec<(usize, usize)> = (0..len).map(|i| ((i * 7919) % 100, i)).collect();
+
+        let mut expected = items.clone();
+        expected.sort_by_key(|(key, _)| *key);
+
+        let sorted = sort_by(items, |l, r| l.0.cmp(&r.0)).await?;
+        assert_eq!(sorted, expected);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_merge_by() {
+        let left = vec![(1, 'a'), (3, 'a'), (5, 'a')];
+        let right = vec![(1, 'b'), (2, 'b'), (6, 'b')];
+        let merged = merge_by(left, right, |l, r| l.0.cmp(&r.0));
+        assert_eq!(
+            merged,
+            vec![(1, 'a'), (1, 'b'), (2, 'b'), (3, 'a'), (5, 'a'), (6, 'b')]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_external_sort_spills_many_runs() -> TCResult<()> {
+        let host = TestHost::new(vec![]).await?;
+        let txn = host.new_txn(false).await?;
+
+        // more than one block per run, and more than one run
+        let len = 5 * BLOCK_ROWS as u64;
+        let rows = (0..len)
+            .map(|n| Ok(vec![Value::from(Number::from((n * 7919) % len))]))
+            .collect::<Vec<TCResult<Key>>>();
+
+        let budget = 2 * BLOCK_ROWS * size_of(&[Value::from(Number::from(0u64))]) + 1;
+        let compare = |l: &Key, r: &Key| super::super::schema::collate(l, r);
+        let sorted: Vec<Key> = external_sort_with_budget(&txn, stream::iter(rows), compare, budget)
+            .await?
+            .try_collect()
+            .await?;
+
+        let expected: Vec<Key> = (0..len)
+            .map(|n| vec![Value::from(Number::from(n))])
+            .collect();
+
+        assert!(sorted == expected);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,861 @@
+//! Transactional storage for the contents of a [`super::Collection`], in the blocks of a file.
+//!
+//! The rows of a [`super::BTree`] or [`super::Table`] are stored in key order as [`Rows`], and
+//! the elements of a [`super::Tensor`] are stored in row-major order as [`Elements`]. Every write
+//! goes through a [`fs::File`], so it's only visible to its own transaction until that
+//! transaction commits, and it's written to disk on commit.
+
+use std::cmp::Ordering;
+use std::convert::TryFrom;
+use std::ops::Range;
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use bytes::Bytes;
+use destream::de;
+use futures::future::{self, TryFutureExt};
+use futures::stream::{self, BoxStream, Stream, StreamExt, TryStreamExt};
+use safecast::TryCastFrom;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+use tc_error::*;
+use tc_transact::fs::{Dir, File};
+use tc_transact::{Transact, Transaction, TxnId};
+use tc_value::{Number, NumberInstance, NumberType, Value};
+use tcgeneric::{label, Id, Label};
+
+use crate::fs;
+use crate::scalar::{ScalarType, ValueType};
+use crate::state::StateType;
+use crate::txn::Txn;
+
+use super::schema::{self, Column, Key};
+use super::sort;
+
+/// The maximum number of rows, or elements, in a single block.
+pub const BLOCK_SIZE: usize = 1024;
+
+const CONTENTS: Label = label("contents");
+const INDEX: Label = label("index");
+
+// the number of blocks to read ahead of the block being consumed, when streaming the contents
+const READ_AHEAD: usize = 2;
+
+/// What to do when a row to insert has the same key as an existing row.
+#[derive(Clone, Copy, Eq, PartialEq)]
+pub enum Conflict {
+    /// Keep the existing row.
+    Ignore,
+    /// Replace the existing row.
+    Replace,
+    /// Return an error.
+    Reject,
+}
+
+// the ID, length, and first row of one block of `Rows`
+struct Entry {
+    id: u64,
+    len: usize,
+    first: Key,
+}
+
+// the blocks of `Rows` in key order, and the ID of the next block to create
+#[derive(Default)]
+struct Index {
+    next: u64,
+    blocks: Vec<Entry>,
+}
+
+impl Index {
+    fn new_block(&mut self, rows: &[Key]) -> Entry {
+        let id = self.next;
+        self.next += 1;
+        entry(id, rows)
+    }
+
+    // the range of blocks which may contain a row for which `range` returns `Ordering::Equal`
+    fn candidates<F: Fn(&Key) -> Ordering>(&self, range: &F) -> Range<usize> {
+        let start = self
+            .blocks
+            .partition_point(|entry| range(&entry.first) == Ordering::Less);
+
+        let end = self
+            .blocks
+            .partition_point(|entry| range(&entry.first) != Ordering::Greater);
+
+        start.saturating_sub(1)..end
+    }
+}
+
+/// The rows of a `BTree` or `Table`, in key order.
+///
+/// Each block holds up to [`BLOCK_SIZE`] consecutive rows, and an index block holds the ID,
+/// length, and first row of each block, so that finding a row only reads the index and the one
+/// block which could contain it.
+#[derive(Clone)]
+pub struct Rows {
+    schema: Vec<Column>,
+    key_len: usize,
+    file: fs::File<Bytes>,
+}
+
+impl Rows {
+    /// Load the `Rows` with the given schema, whose first `key_len` columns are the key, from the
+    /// given `dir`, or create them if there are none.
+    pub async fn load(
+        schema: Vec<Column>,
+        key_len: usize,
+        dir: &fs::Dir,
+        txn_id: TxnId,
+    ) -> TCResult<Self> {
+        let file = open(dir, txn_id).await?;
+        let rows = Self {
+            schema,
+            key_len,
+            file,
+        };
+
+        if !rows.file.block_exists(&txn_id, &INDEX.into()).await? {
+            rows.write_index(txn_id, &Index::default()).await?;
+        }
+
+        Ok(rows)
+    }
+
+    /// Return the number of rows as of the given [`TxnId`].
+    pub async fn count(&self, txn_id: &TxnId) -> TCResult<u64> {
+        let index = self.index(txn_id).await?;
+        Ok(index.blocks.iter().map(|entry| entry.len as u64).sum())
+    }
+
+    /// Return up to `limit` rows, in key order, starting at `offset`.
+    pub async fn page(&self, txn_id: &TxnId, offset: u64, limit: usize) -> TCResult<Vec<Key>> {
+        let index = self.index(txn_id).await?;
+
+        let mut skip = offset;
+        let mut page = Vec::new();
+        for entry in &index.blocks {
+            if page.len() >= limit {
+                break;
+            } else if skip >= entry.len as u64 {
+                skip -= entry.len as u64;
+                continue;
+            }
+
+            let rows = self.read_block(txn_id, entry.id).await?;
+            let take = limit - page.len();
+            page.extend(rows.into_iter().skip(skip as usize).take(take));
+            skip = 0;
+        }
+
+        Ok(page)
+    }
+
+    /// Stream every row, in key order.
+    pub fn stream(self, txn_id: TxnId) -> BoxStream<'static, TCResult<Key>> {
+        self.stream_range(txn_id, |_| Ordering::Equal)
+    }
+
+    /// Stream every row for which `range` returns `Ordering::Equal`, in key order, reading only
+    /// the blocks which could contain one.
+    pub fn stream_range<F>(self, txn_id: TxnId, range: F) -> BoxStream<'static, TCResult<Key>>
+    where
+        F: Fn(&Key) -> Ordering + Send + Sync + 'static,
+    {
+        let range = Arc::new(range);
+
+        let rows = stream::once(async move {
+            let index = self.index(&txn_id).await?;
+            let blocks: Vec<u64> = index.blocks[index.candidates(range.as_ref())]
+                .iter()
+                .map(|entry| entry.id)
+                .collect();
+
+            let rows = stream::iter(blocks)
+                .map(move |block| {
+                    let this = self.clone();
+                    async move { this.read_block(&txn_id, block).await }
+                })
+                .buffered(READ_AHEAD)
+                .map_ok(move |rows| {
+                    let range = range.clone();
+                    let rows = rows
+                        .into_iter()
+                        .filter(move |row| range(row) == Ordering::Equal);
+
+                    stream::iter(rows.map(Ok))
+                })
+                .try_flatten();
+
+            TCResult::Ok(rows)
+        })
+        .try_flatten();
+
+        rows.boxed()
+    }
+
+    /// Return every row for which `range` returns `Ordering::Equal`.
+    ///
+    /// `range` must return `Ordering::Less` for every row before the range, and
+    /// `Ordering::Greater` for every row after it.
+    pub async fn range<F>(&self, txn_id: &TxnId, range: F) -> TCResult<Vec<Key>>
+    where
+        F: Fn(&Key) -> Ordering,
+    {
+        let index = self.index(txn_id).await?;
+
+        let mut found = Vec::new();
+        for entry in &index.blocks[index.candidates(&range)] {
+            let rows = self.read_block(txn_id, entry.id).await?;
+            found.extend(rows.into_iter().filter(|row| range(row) == Ordering::Equal));
+        }
+
+        Ok(found)
+    }
+
+    /// Copy every row for which `range` returns `Ordering::Equal` to the end of `dest`, one block
+    /// at a time.
+    pub async fn copy_range<F>(&self, txn_id: TxnId, range: F, dest: &Rows) -> TCResult<()>
+    where
+        F: Fn(&Key) -> Ordering,
+    {
+        let index = self.index(&txn_id).await?;
+        let mut dest_index = dest.index(&txn_id).await?;
+
+        for entry in &index.blocks[index.candidates(&range)] {
+            let rows = self.read_block(&txn_id, entry.id).await?;
+            let rows: Vec<Key> = rows
+                .into_iter()
+                .filter(|row| range(row) == Ordering::Equal)
+                .collect();
+
+            if !rows.is_empty() {
+                let entry = dest_index.new_block(&rows);
+                dest.write_block(txn_id, entry.id, &rows).await?;
+                dest_index.blocks.push(entry);
+            }
+        }
+
+        dest.write_index(txn_id, &dest_index).await
+    }
+
+    /// Insert the given `rows`, in any order.
+    pub async fn insert(&self, txn_id: TxnId, rows: Vec<Key>, conflict: Conflict) -> TCResult<()> {
+        let rows = rows
+            .into_iter()
+            .map(|row| schema::validate_row(&self.schema, row))
+            .collect::<TCResult<Vec<Key>>>()?;
+
+        let rows = sort::sort_by(rows, self.compare()).await?;
+        self.merge(txn_id, rows, conflict).await
+    }
+
+    /// Insert every row of the given stream, in any order.
+    ///
+    /// The rows are sorted in the workspace of the given `txn` first, so that each block is only
+    /// written once no matter how many rows there are.
+    pub async fn extend<S>(&self, txn: &Txn, rows: S, conflict: Conflict) -> TCResult<()>
+    where
+        S: Stream<Item = TCResult<Key>> + Send + Unpin,
+    {
+        let schema = self.schema.clone();
+        let rows = rows.and_then(|row| future::ready(schema::validate_row(&schema, row)));
+        let mut sorted = sort::external_sort_by(txn, rows, self.compare()).await?;
+
+        let txn_id = *txn.id();
+        let mut batch = Vec::with_capacity(BLOCK_SIZE);
+        while let Some(row) = sorted.try_next().await? {
+            batch.push(row);
+
+            if batch.len() == BLOCK_SIZE {
+                self.merge(txn_id, std::mem::take(&mut batch), conflict)
+                    .await?;
+            }
+        }
+
+        self.merge(txn_id, batch, conflict).await
+    }
+
+    // merge the given `batch` of rows, which must be valid and sorted by key, into these `Rows`
+    //
+    // each existing block is read and written at most once, and a block which grows larger than
+    // `BLOCK_SIZE` is split
+    async fn merge(&self, txn_id: TxnId, batch: Vec<Key>, conflict: Conflict) -> TCResult<()> {
+        let compare = self.compare();
+        let batch = dedup(batch, &compare, conflict)?;
+        if batch.is_empty() {
+            return Ok(());
+        }
+
+        let mut index = self.index(&txn_id).await?;
+        let mut blocks = Vec::with_capacity(index.blocks.len());
+        let mut batch = batch.into_iter().peekable();
+        let mut existing = std::mem::take(&mut index.blocks).into_iter().peekable();
+
+        while let Some(entry) = existing.next() {
+            let mut rows = Vec::new();
+            while let Some(row) = batch.peek() {
+                match existing.peek() {
+                    Some(next) if compare(row, &next.first) != Ordering::Less => break,
+                    _ => rows.extend(batch.next()),
+                }
+            }
+
+            if rows.is_empty() {
+                blocks.push(entry);
+                continue;
+            }
+
+            let block = self.read_block(&txn_id, entry.id).await?;
+            let merged = merge_rows(block, rows, &compare, conflict)?;
+            for (i, chunk) in merged.chunks(BLOCK_SIZE).enumerate() {
+                let entry = if i == 0 {
+                    self::entry(entry.id, chunk)
+                } else {
+                    index.new_block(chunk)
+                };
+
+                self.write_block(txn_id, entry.id, chunk).await?;
+                blocks.push(entry);
+            }
+        }
+
+        // if there were no existing blocks, the whole batch is left over
+        let rest: Vec<Key> = batch.collect();
+        for chunk in rest.chunks(BLOCK_SIZE) {
+            let entry = index.new_block(chunk);
+            self.write_block(txn_id, entry.id, chunk).await?;
+            blocks.push(entry);
+        }
+
+        index.blocks = blocks;
+        self.write_index(txn_id, &index).await
+    }
+
+    /// Call `update` on every row for which `range` returns `Ordering::Equal`.
+    ///
+    /// `update` must not change the key order of the rows.
+    pub async fn update<F, U>(&self, txn_id: TxnId, range: F, update: U) -> TCResult<()>
+    where
+        F: Fn(&Key) -> Ordering,
+        U: Fn(&mut Key),
+    {
+        let mut index = self.index(&txn_id).await?;
+        let candidates = index.candidates(&range);
+
+        let mut updated = false;
+        for entry in &mut index.blocks[candidates] {
+            let mut rows = self.read_block(&txn_id, entry.id).await?;
+
+            let mut changed = false;
+            for row in rows.iter_mut() {
+                if range(row) == Ordering::Equal {
+                    update(row);
+                    changed = true;
+                }
+            }
+
+            if changed {
+                self.write_block(txn_id, entry.id, &rows).await?;
+                *entry = self::entry(entry.id, &rows);
+                updated = true;
+            }
+        }
+
+        if updated {
+            self.write_index(txn_id, &index).await
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Delete every row for which `range` returns `Ordering::Equal`.
+    pub async fn delete<F>(&self, txn_id: TxnId, range: F) -> TCResult<()>
+    where
+        F: Fn(&Key) -> Ordering,
+    {
+        let mut index = self.index(&txn_id).await?;
+        let candidates = index.candidates(&range);
+
+        let mut deleted = false;
+        for entry in &mut index.blocks[candidates] {
+            let mut rows = self.read_block(&txn_id, entry.id).await?;
+            let len = rows.len();
+            rows.retain(|row| range(row) != Ordering::Equal);
+
+            if rows.len() < len {
+                self.write_block(txn_id, entry.id, &rows).await?;
+                entry.len = rows.len();
+                if let Some(first) = rows.first() {
+                    entry.first = first.clone();
+                }
+
+                deleted = true;
+            }
+        }
+
+        if deleted {
+            // an empty block stays in the file, but it's never read again
+            index.blocks.retain(|entry| entry.len > 0);
+            self.write_index(txn_id, &index).await
+        } else {
+            Ok(())
+        }
+    }
+
+    fn compare(&self) -> impl Fn(&Key, &Key) -> Ordering + Clone + Send + Sync + 'static {
+        let key_len = self.key_len;
+        move |l: &Key, r: &Key| schema::collate(&l[..key_len], &r[..key_len])
+    }
+
+    async fn index(&self, txn_id: &TxnId) -> TCResult<Index> {
+        let (next, blocks): (u64, Vec<(u64, usize, Key)>) =
+            read_json(&self.file, txn_id, &INDEX.into()).await?;
+
+        let blocks = blocks
+            .into_iter()
+            .map(|(id, len, first)| {
+                schema::validate_prefix(&self.schema, first)
+                    .map(|first| Entry { id, len, first })
+                    .map_err(corrupted)
+            })
+            .collect::<TCResult<Vec<Entry>>>()?;
+
+        Ok(Index { next, blocks })
+    }
+
+    async fn write_index(&self, txn_id: TxnId, index: &Index) -> TCResult<()> {
+        let blocks: Vec<(u64, usize, &Key)> = index
+            .blocks
+            .iter()
+            .map(|entry| (entry.id, entry.len, &entry.first))
+            .collect();
+
+        write_json(&self.file, txn_id, INDEX.into(), &(index.next, blocks)).await
+    }
+
+    async fn read_block(&self, txn_id: &TxnId, block: u64) -> TCResult<Vec<Key>> {
+        let rows: Vec<Key> = read_json(&self.file, txn_id, &block_id(block)?).await?;
+
+        // the types of some values, like a 32-bit float, don't survive the trip through JSON
+        rows.into_iter()
+            .map(|row| schema::validate_row(&self.schema, row).map_err(corrupted))
+            .collect()
+    }
+
+    async fn write_block(&self, txn_id: TxnId, block: u64, rows: &[Key]) -> TCResult<()> {
+        write_json(&self.file, txn_id, block_id(block)?, rows).await
+    }
+}
+
+/// The elements of a `Tensor`, in row-major order.
+///
+/// Block `i` holds the [`BLOCK_SIZE`] elements starting at offset `i * BLOCK_SIZE`. A block which
+/// has never been written isn't stored at all, and holds only zeros, so a new `Tensor` takes no
+/// space on disk no matter how large its shape.
+#[derive(Clone)]
+pub struct Elements {
+    dtype: NumberType,
+    size: u64,
+    file: fs::File<Bytes>,
+}
+
+impl Elements {
+    /// Load the `size` elements of type `dtype` from the given `dir`, or create them if there
+    /// are none.
+    pub async fn load(
+        dtype: NumberType,
+        size: u64,
+        dir: &fs::Dir,
+        txn_id: TxnId,
+    ) -> TCResult<Self> {
+        let file = open(dir, txn_id).await?;
+        Ok(Self { dtype, size, file })
+    }
+
+    /// Read the element at the given `offset`.
+    pub async fn get(&self, txn_id: &TxnId, offset: u64) -> TCResult<Number> {
+        let block = self.read_block(txn_id, offset / BLOCK_SIZE as u64).await?;
+        Ok(block[(offset % BLOCK_SIZE as u64) as usize])
+    }
+
+    /// Read the elements at the given `range` of offsets.
+    pub async fn read(&self, txn_id: &TxnId, range: Range<u64>) -> TCResult<Vec<Number>> {
+        let range = range.start..range.end.min(self.size);
+        let mut elements = Vec::with_capacity(range.end.saturating_sub(range.start) as usize);
+
+        let mut offset = range.start;
+        while offset < range.end {
+            let block = offset / BLOCK_SIZE as u64;
+            let start = block * BLOCK_SIZE as u64;
+            let end = range.end.min(start + BLOCK_SIZE as u64);
+
+            let contents = self.read_block(txn_id, block).await?;
+            elements
+                .extend_from_slice(&contents[(offset - start) as usize..(end - start) as usize]);
+            offset = end;
+        }
+
+        Ok(elements)
+    }
+
+    /// Overwrite the elements starting at `offset` with the given `elements`.
+    pub async fn write(&self, txn_id: TxnId, offset: u64, elements: &[Number]) -> TCResult<()> {
+        let end = offset + elements.len() as u64;
+        if end > self.size {
+            return Err(TCError::bad_request(
+                format!("Tensor of size {} has no offset", self.size),
+                end - 1,
+            ));
+        }
+
+        let mut at = offset;
+        while at < end {
+            let block = at / BLOCK_SIZE as u64;
+            let start = block * BLOCK_SIZE as u64;
+            let stop = end.min(start + BLOCK_SIZE as u64);
+
+            let mut contents = self.read_block(&txn_id, block).await?;
+            let source = &elements[(at - offset) as usize..(stop - offset) as usize];
+            let dest = &mut contents[(at - start) as usize..(stop - start) as usize];
+            for (dest, source) in dest.iter_mut().zip(source) {
+                *dest = source.into_type(self.dtype);
+            }
+
+            let contents: Vec<Value> = contents.into_iter().map(Value::from).collect();
+            write_json(&self.file, txn_id, block_id(block)?, &contents).await?;
+            at = stop;
+        }
+
+        Ok(())
+    }
+
+    /// Set every element in the given `range` of offsets to `value`.
+    pub async fn fill(&self, txn_id: TxnId, range: Range<u64>, value: Number) -> TCResult<()> {
+        let mut offset = range.start;
+        while offset < range.end {
+            let len = (range.end - offset).min(BLOCK_SIZE as u64);
+            self.write(txn_id, offset, &vec![value; len as usize])
+                .await?;
+
+            offset += len;
+        }
+
+        Ok(())
+    }
+
+    /// Stream every element, in row-major order.
+    pub fn stream(self, txn_id: TxnId) -> BoxStream<'static, TCResult<Number>> {
+        let num_blocks = self.size.div_ceil(BLOCK_SIZE as u64);
+
+        stream::iter(0..num_blocks)
+            .map(move |block| {
+                let this = self.clone();
+                async move { this.read_block(&txn_id, block).await }
+            })
+            .buffered(READ_AHEAD)
+            .map_ok(|elements| stream::iter(elements.into_iter().map(Ok)))
+            .try_flatten()
+            .boxed()
+    }
+
+    async fn read_block(&self, txn_id: &TxnId, block: u64) -> TCResult<Vec<Number>> {
+        let start = block * BLOCK_SIZE as u64;
+        let len = self.size.saturating_sub(start).min(BLOCK_SIZE as u64) as usize;
+
+        let block_id = block_id(block)?;
+        if !self.file.block_exists(txn_id, &block_id).await? {
+            return Ok(vec![Number::from(false).into_type(self.dtype); len]);
+        }
+
+        let elements: Vec<Value> = read_json(&self.file, txn_id, &block_id).await?;
+        if elements.len() != len {
+            return Err(corrupted(format!(
+                "expected {} Tensor elements in block {} but found {}",
+                len,
+                block,
+                elements.len()
+            )));
+        }
+
+        elements
+            .into_iter()
+            .map(|value| match value {
+                Value::Number(n) => Ok(n.into_type(self.dtype)),
+                other => Err(corrupted(format!("invalid Tensor element {}", other))),
+            })
+            .collect()
+    }
+}
+
+#[async_trait]
+impl Transact for Rows {
+    async fn commit(&self, txn_id: &TxnId) {
+        self.file.commit(txn_id).await
+    }
+
+    async fn finalize(&self, txn_id: &TxnId) {
+        self.file.finalize(txn_id).await
+    }
+}
+
+#[async_trait]
+impl Transact for Elements {
+    async fn commit(&self, txn_id: &TxnId) {
+        self.file.commit(txn_id).await
+    }
+
+    async fn finalize(&self, txn_id: &TxnId) {
+        self.file.finalize(txn_id).await
+    }
+}
+
+/// Decodes a sequence of rows into [`Rows`], without holding the whole sequence in memory.
+pub struct LoadRows;
+
+#[async_trait]
+impl de::FromStream for LoadRows {
+    type Context = (Txn, Rows, Conflict);
+
+    async fn from_stream<D: de::Decoder>(
+        context: (Txn, Rows, Conflict),
+        decoder: &mut D,
+    ) -> Result<Self, D::Error> {
+        let (txn, rows, conflict) = context;
+        decoder
+            .decode_seq(LoadRowsVisitor {
+                txn,
+                rows,
+                conflict,
+            })
+            .await
+    }
+}
+
+struct LoadRowsVisitor {
+    txn: Txn,
+    rows: Rows,
+    conflict: Conflict,
+}
+
+#[async_trait]
+impl de::Visitor for LoadRowsVisitor {
+    type Value = LoadRows;
+
+    fn expecting() -> &'static str {
+        "a sequence of rows"
+    }
+
+    async fn visit_seq<A: de::SeqAccess>(self, seq: A) -> Result<LoadRows, A::Error> {
+        let source = stream::unfold(seq, |mut seq| async move {
+            match seq.next_element::<Key>(()).await {
+                Ok(Some(row)) => Some((Ok(row), seq)),
+                Ok(None) => None,
+                Err(cause) => Some((Err(TCError::bad_request("invalid row", cause)), seq)),
+            }
+        });
+
+        self.rows
+            .extend(&self.txn, Box::pin(source), self.conflict)
+            .map_err(de::Error::custom)
+            .await?;
+
+        Ok(LoadRows)
+    }
+}
+
+/// Decodes a sequence of elements into [`Elements`], one block at a time.
+pub struct LoadElements;
+
+#[async_trait]
+impl de::FromStream for LoadElements {
+    type Context = (TxnId, Elements);
+
+    async fn from_stream<D: de::Decoder>(
+        context: (TxnId, Elements),
+        decoder: &mut D,
+    ) -> Result<Self, D::Error> {
+        let (txn_id, elements) = context;
+        decoder
+            .decode_seq(LoadElementsVisitor { txn_id, elements })
+            .await
+    }
+}
+
+struct LoadElementsVisitor {
+    txn_id: TxnId,
+    elements: Elements,
+}
+
+#[async_trait]
+impl de::Visitor for LoadElementsVisitor {
+    type Value = LoadElements;
+
+    fn expecting() -> &'static str {
+        "a sequence of Tensor elements"
+    }
+
+    async fn visit_seq<A: de::SeqAccess>(self, mut seq: A) -> Result<LoadElements, A::Error> {
+        let size = self.elements.size;
+        let invalid_length =
+            |len| de::Error::invalid_length(len as usize, format!("{} Tensor elements", size));
+
+        let mut offset = 0;
+        let mut block = Vec::with_capacity(BLOCK_SIZE);
+        while let Some(value) = seq.next_element::<Value>(()).await? {
+            let element = match value {
+                Value::Number(n) => n,
+                other => Number::opt_cast_from(other.clone())
+                    .ok_or_else(|| de::Error::invalid_type(other, "a Number"))?,
+            };
+
+            if offset + block.len() as u64 >= size {
+                return Err(invalid_length(size + 1));
+            }
+
+            block.push(element);
+
+            if block.len() == BLOCK_SIZE {
+                self.elements
+                    .write(self.txn_id, offset, &block)
+                    .map_err(de::Error::custom)
+                    .await?;
+
+                offset += block.len() as u64;
+                block.clear();
+            }
+        }
+
+        let len = offset + block.len() as u64;
+        if len != size {
+            return Err(invalid_length(len));
+        }
+
+        self.elements
+            .write(self.txn_id, offset, &block)
+            .map_err(de::Error::custom)
+            .await?;
+
+        Ok(LoadElements)
+    }
+}
+
+// open the only file in `dir`, or create it
+async fn open(dir: &fs::Dir, txn_id: TxnId) -> TCResult<fs::File<Bytes>> {
+    let file = if let Some(file) = dir.get_file(&txn_id, &CONTENTS.into()).await? {
+        file
+    } else {
+        let class = StateType::Scalar(ScalarType::Value(ValueType::Bytes));
+        dir.create_file(txn_id, CONTENTS.into(), class).await?
+    };
+
+    fs::File::<Bytes>::try_from(file)
+}
+
+fn entry(id: u64, rows: &[Key]) -> Entry {
+    Entry {
+        id,
+        len: rows.len(),
+        first: rows.first().cloned().unwrap_or_default(),
+    }
+}
+
+// remove the rows of a sorted `batch` which have the same key as a preceding row
+fn dedup<C>(batch: Vec<Key>, compare: &C, conflict: Conflict) -> TCResult<Vec<Key>>
+where
+    C: Fn(&Key, &Key) -> Ordering,
+{
+    let mut deduped: Vec<Key> = Vec::with_capacity(batch.len());
+    for row in batch {
+        match deduped.last_mut() {
+            Some(last) if compare(last, &row) == Ordering::Equal => match conflict {
+                Conflict::Ignore => {}
+                Conflict::Replace => *last = row,
+                Conflict::Reject => return Err(duplicate(row)),
+            },
+            _ => deduped.push(row),
+        }
+    }
+
+    Ok(deduped)
+}
+
+// merge the sorted, deduplicated rows `batch` into the sorted, deduplicated rows `existing`
+fn merge_rows<C>(
+    existing: Vec<Key>,
+    batch: Vec<Key>,
+    compare: &C,
+    conflict: Conflict,
+) -> TCResult<Vec<Key>>
+where
+    C: Fn(&Key, &Key) -> Ordering,
+{
+    let mut merged = Vec::with_capacity(existing.len() + batch.len());
+    let mut existing = existing.into_iter().peekable();
+    let mut batch = batch.into_iter().peekable();
+
+    loop {
+        let order = match (existing.peek(), batch.peek()) {
+            (Some(old), Some(new)) => compare(old, new),
+            (Some(_), None) => Ordering::Less,
+            (None, Some(_)) => Ordering::Greater,
+            (None, None) => return Ok(merged),
+        };
+
+        match order {
+            Ordering::Less => merged.extend(existing.next()),
+            Ordering::Greater => merged.extend(batch.next()),
+            Ordering::Equal => {
+                let (old, new) = (existing.next(), batch.next());
+                match conflict {
+                    Conflict::Ignore => merged.extend(old),
+                    Conflict::Replace => merged.extend(new),
+                    Conflict::Reject => return Err(duplicate(new.unwrap_or_default())),
+                }
+            }
+        }
+    }
+}
+
+async fn read_json<T: DeserializeOwned>(
+    file: &fs::File<Bytes>,
+    txn_id: &TxnId,
+    block_id: &Id,
+) -> TCResult<T> {
+    let block = file.get_block(txn_id, block_id).await?;
+    serde_json::from_slice(&block).map_err(corrupted)
+}
+
+async fn write_json<T: Serialize + ?Sized>(
+    file: &fs::File<Bytes>,
+    txn_id: TxnId,
+    block_id: Id,
+    data: &T,
+) -> TCResult<()> {
+    let data = serde_json::to_vec(data)
+        .map(Bytes::from)
+        .map_err(|e| TCError::internal(format!("unable to encode collection block: {}", e)))?;
+
+    if file.block_exists(&txn_id, &block_id).await? {
+        let mut block = file.get_block_mut(&txn_id, &block_id).await?;
+        *block = data;
+    } else {
+        file.create_block(txn_id, block_id, data).await?;
+    }
+
+    Ok(())
+}
+
+#[inline]
+fn block_id(block: u64) -> TCResult<Id> {
+    block.to_string().parse()
+}
+
+fn corrupted<E: std::fmt::Display>(cause: E) -> TCError {
+    TCError::internal(format!("collection block corrupted! {}", cause))
+}
+
+fn duplicate(row: Key) -> TCError {
+    TCError::bad_request("duplicate key", Value::Tuple(row.into()))
+}
@@ -0,0 +1,589 @@
+//! A [`Table`], a collection of rows with a primary key.
+
+use std::cmp::Ordering;
+use std::fmt;
+
+use async_trait::async_trait;
+use destream::{de, en};
+use futures::future::{self, TryFutureExt};
+use futures::stream::{BoxStream, StreamExt, TryStreamExt};
+use safecast::CastFrom;
+
+use tc_error::*;
+use tc_transact::fs::Persist;
+use tc_transact::{IntoView, Transact, Transaction, TxnId};
+use tc_value::{Number, Value};
+use tcgeneric::{Id, Map};
+
+use crate::fs;
+use crate::txn::Txn;
+
+use super::aggregate::{self, Aggregate};
+use super::schema::{self, Key, TableSchema};
+use super::sort;
+use super::store::{Conflict, LoadRows, Rows};
+use super::Contents;
+
+/// A collection of rows matching a [`TableSchema`], unique and ordered by their key columns.
+///
+/// Like a [`super::BTree`], a `Table` keeps its rows in key order in blocks of a transactional
+/// file, so a lookup by key prefix only reads the blocks which hold matching rows.
+/// INCOMPLETE AND UNSTABLE.
+#[derive(Clone)]
+pub struct Table {
+    schema: TableSchema,
+    rows: Rows,
+}
+
+impl Table {
+    /// Create a new, empty `Table` with the given [`TableSchema`] in the workspace of `txn`.
+    pub async fn create(txn: &Txn, schema: TableSchema) -> TCResult<Self> {
+        let dir = sort::temp_dir(txn).await?;
+        Self::load(schema, dir, *txn.id()).await
+    }
+
+    /// The [`TableSchema`] of this `Table`.
+    pub fn schema(&'_ self) -> &'_ TableSchema {
+        &self.schema
+    }
+
+    /// Return the number of rows in this `Table` as of the given [`TxnId`].
+    pub async fn count(&self, txn_id: &TxnId) -> TCResult<u64> {
+        self.rows.count(txn_id).await
+    }
+
+    /// Return the row with the given primary key, if present.
+    pub async fn get_row(&self, txn_id: &TxnId, key: Key) -> TCResult<Option<Key>> {
+        let key = schema::validate_row(self.schema.key(), key)?;
+
+        let mut rows = self
+            .rows
+            .range(txn_id, |row| schema::collate(row, &key))
+            .await?;
+
+        Ok(rows.pop())
+    }
+
+    /// Return up to `limit` rows of this `Table`, in key order, starting at `offset`.
+    pub async fn page(&self, txn_id: &TxnId, offset: u64, limit: usize) -> TCResult<Vec<Key>> {
+        self.rows.page(txn_id, offset, limit).await
+    }
+
+    /// Stream every row of this `Table`, in key order.
+    pub fn rows(&self, txn_id: TxnId) -> BoxStream<'static, TCResult<Key>> {
+        self.rows.clone().stream(txn_id)
+    }
+
+    /// Insert a row with the given key and values, replacing any existing row with the same key.
+    pub async fn upsert(&self, txn_id: TxnId, key: Key, values: Key) -> TCResult<()> {
+        let key = schema::validate_row(self.schema.key(), key)?;
+        let values = schema::validate_row(self.schema.values(), values)?;
+
+        let mut row = key;
+        row.extend(values);
+
+        self.rows.insert(txn_id, vec![row], Conflict::Replace).await
+    }
+
+    /// Delete every row whose key begins with `prefix`.
+    ///
+    /// Only the blocks which may hold a matching row are read.
+    pub async fn delete(&self, txn_id: TxnId, prefix: Key) -> TCResult<()> {
+        let prefix = schema::validate_prefix(self.schema.key(), prefix)?;
+
+        self.rows
+            .delete(txn_id, |row| schema::collate(row, &prefix))
+            .await
+    }
+
+    /// Set the given value columns of every row whose key begins with `prefix`.
+    ///
+    /// The key columns are never changed, so each updated row stays in the same block.
+    pub async fn update(&self, txn_id: TxnId, prefix: Key, values: Map<Value>) -> TCResult<()> {
+        let prefix = schema::validate_prefix(self.schema.key(), prefix)?;
+
+        let key_len = self.schema.key().len();
+        let values = values
+            .into_iter()
+            .map(|(name, value)| {
+                let offset = self.schema.value_offset(&name).ok_or_else(|| {
+                    TCError::bad_request("Table has no value column", name.clone())
+                })?;
+
+                let value = self.schema.values()[offset - key_len].validate(value)?;
+                Ok((offset, value))
+            })
+            .collect::<TCResult<Vec<(usize, Value)>>>()?;
+
+        if values.is_empty() {
+            return Ok(());
+        }
+
+        let range = |row: &Key| schema::collate(row, &prefix);
+        self.rows
+            .update(txn_id, range, |row| {
+                for (offset, value) in &values {
+                    row[*offset] = value.clone();
+                }
+            })
+            .await
+    }
+
+    /// Stream every row of this `Table`, ordered by the given columns rather than by key.
+    ///
+    /// Rows which are equal in every one of the given `columns` stay in key order. If the rows
+    /// don't fit in the sort budget, sorted runs are spilled to the workspace of the given `txn`.
+    pub async fn order_by(
+        &self,
+        txn: &Txn,
+        columns: Vec<Id>,
+        reverse: bool,
+    ) -> TCResult<BoxStream<'static, TCResult<Key>>> {
+        let offsets = self.offsets(&columns)?;
+
+        sort::external_sort_by(txn, self.rows(*txn.id()), move |l, r| {
+            let order = offsets
+                .iter()
+                .map(|i| schema::collate(&l[*i..*i + 1], &r[*i..*i + 1]))
+                .find(|order| *order != Ordering::Equal)
+                .unwrap_or(Ordering::Equal);
+
+            if reverse {
+                order.reverse()
+            } else {
+                order
+            }
+        })
+        .await
+    }
+
+    /// Stream each distinct combination of values of the given `columns`.
+    ///
+    /// If the given `columns` are a prefix of the key, in any order, the combinations are returned
+    /// in key order, without holding more than one of them at a time. Otherwise they're returned
+    /// in no particular order, and spilled to the workspace of the given `txn` if they don't fit
+    /// in the sort budget.
+    pub async fn distinct(
+        &self,
+        txn: &Txn,
+        columns: Vec<Id>,
+    ) -> TCResult<BoxStream<'static, TCResult<Key>>> {
+        let offsets = self.offsets(&columns)?;
+
+        let mut sorted = offsets.to_vec();
+        sorted.sort_unstable();
+        let is_prefix = sorted.len() <= self.schema.key().len()
+            && sorted.iter().enumerate().all(|(i, offset)| i == *offset);
+
+        let rows = self.rows(*txn.id());
+
+        if is_prefix {
+            let mut last: Option<Key> = None;
+            let distinct = rows
+                .map_ok(move |row| offsets.iter().map(|i| row[*i].clone()).collect::<Key>())
+                .try_filter(move |row| {
+                    let distinct = match &last {
+                        Some(last) => schema::collate(last, row) != Ordering::Equal,
+                        None => true,
+                    };
+
+                    if distinct {
+                        last = Some(row.clone());
+                    }
+
+                    future::ready(distinct)
+                });
+
+            Ok(distinct.boxed())
+        } else {
+            aggregate::hash_aggregate(txn, rows, Distinct { offsets }).await
+        }
+    }
+
+    /// Stream each distinct combination of values of the given `columns`, followed by the number
+    /// of rows of this `Table` which have those values.
+    ///
+    /// The rows don't need to be ordered by the given `columns`. Groups are returned in no
+    /// particular order. If the groups don't fit in the sort budget, the rows of some groups are
+    /// spilled to the workspace of the given `txn`.
+    pub async fn group_by(
+        &self,
+        txn: &Txn,
+        columns: Vec<Id>,
+    ) -> TCResult<BoxStream<'static, TCResult<Key>>> {
+        let offsets = self.offsets(&columns)?;
+
+        let rows = self.rows(*txn.id());
+        aggregate::hash_aggregate(txn, rows, Count { offsets }).await
+    }
+
+    /// Construct a new `Table`, in the workspace of `txn`, with a copy of only the rows of this
+    /// `Table` whose key begins with `prefix`.
+    pub async fn slice(&self, txn: &Txn, prefix: Key) -> TCResult<Table> {
+        let prefix = schema::validate_prefix(self.schema.key(), prefix)?;
+
+        let slice = Self::create(txn, self.schema.clone()).await?;
+        self.rows
+            .copy_range(*txn.id(), |row| schema::collate(row, &prefix), &slice.rows)
+            .await?;
+
+        Ok(slice)
+    }
+
+    fn offsets(&self, columns: &[Id]) -> TCResult<Vec<usize>> {
+        columns
+            .iter()
+            .map(|name| {
+                self.schema
+                    .offset(name)
+                    .ok_or_else(|| TCError::bad_request("Table has no column", name))
+            })
+            .collect()
+    }
+}
+
+// counts the rows of each group of the columns at the given offsets
+#[derive(Clone)]
+struct Count {
+    offsets: Vec<usize>,
+}
+
+impl Aggregate for Count {
+    fn group(&self, row: &Key) -> Key {
+        self.offsets.iter().map(|i| row[*i].clone()).collect()
+    }
+
+    fn init(&self, _row: &Key) -> Key {
+        vec![Value::from(Number::from(1u64))]
+    }
+
+    fn fold(&self, state: &mut Key, _row: &Key) {
+        if let Value::Number(count) = &mut state[0] {
+            *count = Number::from(u64::cast_from(*count) + 1);
+        }
+    }
+}
+
+// the distinct values of the columns at the given offsets
+#[derive(Clone)]
+struct Distinct {
+    offsets: Vec<usize>,
+}
+
+impl Aggregate for Distinct {
+    fn group(&self, row: &Key) -> Key {
+        self.offsets.iter().map(|i| row[*i].clone()).collect()
+    }
+
+    fn init(&self, _row: &Key) -> Key {
+        vec![]
+    }
+
+    fn fold(&self, _state: &mut Key, _row: &Key) {
+        // a distinct group has no state
+    }
+}
+
+#[async_trait]
+impl Persist for Table {
+    type Schema = TableSchema;
+    type Store = fs::Dir;
+
+    fn schema(&'_ self) -> &'_ TableSchema {
+        &self.schema
+    }
+
+    async fn load(schema: TableSchema, dir: fs::Dir, txn_id: TxnId) -> TCResult<Self> {
+        let key_len = schema.key().len();
+        let rows = Rows::load(schema.primary(), key_len, &dir, txn_id).await?;
+        Ok(Self { schema, rows })
+    }
+}
+
+#[async_trait]
+impl Transact for Table {
+    async fn commit(&self, txn_id: &TxnId) {
+        self.rows.commit(txn_id).await
+    }
+
+    async fn finalize(&self, txn_id: &TxnId) {
+        self.rows.finalize(txn_id).await
+    }
+}
+
+#[async_trait]
+impl de::FromStream for Table {
+    type Context = Txn;
+
+    async fn from_stream<D: de::Decoder>(txn: Txn, decoder: &mut D) -> Result<Self, D::Error> {
+        decoder.decode_seq(TableVisitor { txn }).await
+    }
+}
+
+struct TableVisitor {
+    txn: Txn,
+}
+
+#[async_trait]
+impl de::Visitor for TableVisitor {
+    type Value = Table;
+
+    fn expecting() -> &'static str {
+        "a Table schema followed by its rows"
+    }
+
+    async fn visit_seq<A: de::SeqAccess>(self, mut seq: A) -> Result<Table, A::Error> {
+        let schema: TableSchema = seq
+            .next_element(())
+            .await?
+            .ok_or_else(|| de::Error::invalid_length(0, Self::expecting()))?;
+
+        let table = Table::create(&self.txn, schema)
+            .map_err(de::Error::custom)
+            .await?;
+
+        let context = (self.txn, table.rows.clone(), Conflict::Reject);
+        seq.next_element::<LoadRows>(context).await?;
+
+        Ok(table)
+    }
+}
+
+impl<'en> IntoView<'en, fs::Dir> for Table {
+    type Txn = Txn;
+    type View = TableView;
+
+    fn into_view(self, txn: Txn) -> TableView {
+        TableView {
+            table: self,
+            txn_id: *txn.id(),
+        }
+    }
+}
+
+impl fmt::Display for Table {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Table with schema {}", self.schema)
+    }
+}
+
+/// A view of a [`Table`] as of a single transaction, used to encode its schema and contents.
+pub struct TableView {
+    table: Table,
+    txn_id: TxnId,
+}
+
+impl<'en> en::IntoStream<'en> for TableView {
+    fn into_stream<E: en::Encoder<'en>>(self, encoder: E) -> Result<E::Ok, E::Error> {
+        let contents = Contents::new(self.table.rows(self.txn_id));
+        (self.table.schema, contents).into_stream(encoder)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::{future, stream, TryStreamExt};
+
+    use tc_value::{NumberType, UIntType, ValueType};
+    use tcgeneric::label;
+
+    use crate::test::TestHost;
+
+    use super::schema::Column;
+    use super::*;
+
+    fn uint(n: u64) -> Value {
+        Value::from(Number::from(n))
+    }
+
+    fn schema() -> TableSchema {
+        let dtype = ValueType::Number(NumberType::UInt(UIntType::U64));
+        let key = vec![
+            Column::from((label("a"), dtype)),
+            Column::from((label("b"), dtype)),
+        ];
+
+        let values = vec![Column::from((label("v"), dtype))];
+        TableSchema::from((key, values))
+    }
+
+    #[tokio::test]
+    async fn test_upsert_update_delete() -> TCResult<()> {
+        let host = TestHost::new(vec![]).await?;
+        let txn = host.new_txn(true).await?;
+        let txn_id = *txn.id();
+
+        let table = Table::create(&txn, schema()).await?;
+        for a in 0..3 {
+            for b in 0..super::super::store::BLOCK_SIZE as u64 {
+                table
+                    .upsert(txn_id, vec![uint(a), uint(b)], vec![uint(0)])
+                    .await?;
+            }
+        }
+
+        table
+            .upsert(txn_id, vec![uint(1), uint(2)], vec![uint(5)])
+            .await?;
+        let row = table.get_row(&txn_id, vec![uint(1), uint(2)]).await?;
+        assert!(row == Some(vec![uint(1), uint(2), uint(5)]));
+
+        let mut values = Map::default();
+        values.insert(label("v").into(), uint(9));
+        table.update(txn_id, vec![uint(2)], values).await?;
+        let row = table.get_row(&txn_id, vec![uint(2), uint(7)]).await?;
+        assert!(row == Some(vec![uint(2), uint(7), uint(9)]));
+
+        table.delete(txn_id, vec![uint(1)]).await?;
+        assert_eq!(
+            table.count(&txn_id).await?,
+            2 * super::super::store::BLOCK_SIZE as u64
+        );
+        assert!(table
+            .get_row(&txn_id, vec![uint(1), uint(2)])
+            .await?
+            .is_none());
+
+        let slice = table.slice(&txn, vec![uint(2)]).await?;
+        let rows: Vec<Key> = slice.rows(txn_id).try_collect().await?;
+        assert_eq!(rows.len(), super::super::store::BLOCK_SIZE);
+        assert!(rows
+            .iter()
+            .all(|row| row[0] == uint(2) && row[2] == uint(9)));
+
+        let distinct: Vec<Key> = table
+            .distinct(&txn, vec![label("a").into()])
+            .await?
+            .try_collect()
+            .await?;
+        assert!(distinct == vec![vec![uint(0)], vec![uint(2)]]);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_null_key() -> TCResult<()> {
+        let host = TestHost::new(vec![]).await?;
+        let txn = host.new_txn(true).await?;
+        let txn_id = *txn.id();
+
+        // a key column may be NULL, in at most one row per distinct key
+        let table = Table::create(&txn, schema()).await?;
+        for a in [Value::None, uint(0)] {
+            for b in [Value::None, uint(0)] {
+                table
+                    .upsert(txn_id, vec![a.clone(), b], vec![uint(1)])
+                    .await?;
+            }
+        }
+
+        table
+            .upsert(txn_id, vec![Value::None, Value::None], vec![uint(2)])
+            .await?;
+        assert_eq!(table.count(&txn_id).await?, 4);
+
+        let row = table
+            .get_row(&txn_id, vec![Value::None, Value::None])
+            .await?;
+        assert!(row == Some(vec![Value::None, Value::None, uint(2)]));
+
+        // NULL sorts first
+        let rows: Vec<Key> = table.rows(txn_id).try_collect().await?;
+        assert!(rows[1] == vec![Value::None, uint(0), uint(1)]);
+
+        // a NULL prefix matches only the rows which are NULL in that column, like IS NULL
+        let slice = table.slice(&txn, vec![Value::None]).await?;
+        assert_eq!(slice.count(&txn_id).await?, 2);
+
+        table.delete(txn_id, vec![Value::None]).await?;
+        let rows: Vec<Key> = table.rows(txn_id).try_collect().await?;
+        assert!(
+            rows == vec![
+                vec![uint(0), Value::None, uint(1)],
+                vec![uint(0), uint(0), uint(1)]
+            ]
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_distinct() -> TCResult<()> {
+        let host = TestHost::new(vec![]).await?;
+        let txn = host.new_txn(false).await?;
+        let txn_id = *txn.id();
+
+        let table = Table::create(&txn, schema()).await?;
+        for a in 0..3 {
+            for b in 0..4 {
+                table
+                    .upsert(txn_id, vec![uint(a), uint(b)], vec![uint(b % 2)])
+                    .await?;
+            }
+        }
+
+        // a prefix of the key in any order is deduplicated as it's streamed, in key order
+        let distinct: Vec<Key> = table
+            .distinct(&txn, vec![label("b").into(), label("a").into()])
+            .await?
+            .try_collect()
+            .await?;
+        assert_eq!(distinct.len(), 12);
+        assert!(distinct[1] == vec![uint(1), uint(0)]);
+
+        // any other columns are hashed
+        let mut distinct: Vec<Key> = table
+            .distinct(&txn, vec![label("v").into()])
+            .await?
+            .try_collect()
+            .await?;
+        distinct.sort_by(|l, r| schema::collate(l, r));
+        assert!(distinct == vec![vec![uint(0)], vec![uint(1)]]);
+
+        let mut distinct: Vec<Key> = table
+            .distinct(&txn, vec![label("b").into()])
+            .await?
+            .try_collect()
+            .await?;
+        distinct.sort_by(|l, r| schema::collate(l, r));
+        assert!(distinct == (0..4).map(|b| vec![uint(b)]).collect::<Vec<Key>>());
+
+        let result = table.distinct(&txn, vec![label("c").into()]).await;
+        assert_eq!(
+            result.map(|_| ()).unwrap_err().code(),
+            ErrorType::BadRequest
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_decode_rejects_duplicate_keys() -> TCResult<()> {
+        let host = TestHost::new(vec![]).await?;
+        let txn = host.new_txn(false).await?;
+
+        let dtype = r#""/state/scalar/value/number/uint/64""#;
+        let schema = format!(r#"[[["a", {0}], ["b", {0}]], [["v", {0}]]]"#, dtype);
+
+        let encoded = format!("[{}, [[1, 2, 3], [0, 1, 2]]]", schema);
+        let encoded = stream::once(future::ready(encoded.into_bytes()));
+        let table: Table = destream_json::decode(txn.clone(), encoded)
+            .map_err(|e| TCError::bad_request("invalid Table", e))
+            .await?;
+
+        let rows: Vec<Key> = table.rows(*txn.id()).try_collect().await?;
+        assert!(
+            rows == vec![
+                vec![uint(0), uint(1), uint(2)],
+                vec![uint(1), uint(2), uint(3)]
+            ]
+        );
+
+        let encoded = format!("[{}, [[1, 2, 3], [1, 2, 4]]]", schema);
+        let encoded = stream::once(future::ready(encoded.into_bytes()));
+        let result: Result<Table, _> = destream_json::decode(txn.clone(), encoded).await;
+        assert!(result.is_err());
+
+        Ok(())
+    }
+}
@@ -0,0 +1,440 @@
+//! A [`Tensor`], an n-dimensional array of [`Number`]s.
+
+use std::fmt;
+use std::ops::Range;
+
+use async_trait::async_trait;
+use destream::{de, en};
+use futures::stream::BoxStream;
+use futures::TryFutureExt;
+
+use tc_error::*;
+use tc_transact::fs::Persist;
+use tc_transact::{IntoView, Transact, Transaction, TxnId};
+use tc_value::{Link, Number, NumberInstance, NumberType, Value, ValueType};
+use tcgeneric::NativeClass;
+
+use crate::fs;
+use crate::txn::Txn;
+
+use super::schema;
+use super::sort;
+use super::store::{Elements, LoadElements, BLOCK_SIZE};
+use super::Contents;
+
+/// The maximum number of elements in a single [`Tensor`].
+pub const MAX_SIZE: u64 = 1 << 40;
+
+/// The shape of a [`Tensor`].
+pub type Shape = Vec<u64>;
+
+/// A dense n-dimensional array of [`Number`]s of a single [`NumberType`].
+///
+/// The elements of a `Tensor` are stored in row-major order, in fixed-size blocks of a
+/// transactional file. A block which has never been written is read as zeros.
+/// INCOMPLETE AND UNSTABLE.
+#[derive(Clone)]
+pub struct Tensor {
+    schema: (NumberType, Shape),
+    size: u64,
+    elements: Elements,
+}
+
+impl Tensor {
+    /// Create a new `Tensor` of zeros with the given [`NumberType`] and [`Shape`] in the
+    /// workspace of `txn`.
+    pub async fn create(txn: &Txn, dtype: NumberType, shape: Shape) -> TCResult<Self> {
+        let dir = sort::temp_dir(txn).await?;
+        Self::load((dtype, shape), dir, *txn.id()).await
+    }
+
+    /// The [`NumberType`] of the elements of this `Tensor`.
+    pub fn dtype(&self) -> NumberType {
+        self.schema.0
+    }
+
+    /// The [`Shape`] of this `Tensor`.
+    pub fn shape(&'_ self) -> &'_ [u64] {
+        &self.schema.1
+    }
+
+    /// The number of elements in this `Tensor`.
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+
+    /// Read the element at the given coordinate.
+    pub async fn read_value(&self, txn_id: &TxnId, coord: &[u64]) -> TCResult<Number> {
+        if coord.len() != self.shape().len() {
+            return Err(TCError::bad_request(
+                format!(
+                    "expected a coordinate with {} axes but found",
+                    self.shape().len()
+                ),
+                coord.len(),
+            ));
+        }
+
+        let offset = self.offsets(coord)?.start;
+        self.elements.get(txn_id, offset).await
+    }
+
+    /// Return up to `limit` elements of this `Tensor`, in row-major order, starting at `offset`.
+    pub async fn page(&self, txn_id: &TxnId, offset: u64, limit: usize) -> TCResult<Vec<Number>> {
+        let end = offset.saturating_add(limit as u64);
+        self.elements.read(txn_id, offset..end).await
+    }
+
+    /// Stream every element of this `Tensor`, in row-major order.
+    pub fn elements(&self, txn_id: TxnId) -> BoxStream<'static, TCResult<Number>> {
+        self.elements.clone().stream(txn_id)
+    }
+
+    /// Compute the matrix product of this `Tensor` and `other`, which must both be matrices, in
+    /// the workspace of `txn`.
+    ///
+    /// `other` is read into memory in full, so it must fit in the sort budget. The rows of this
+    /// `Tensor` and of the product are read and written one at a time.
+    pub async fn matmul(&self, txn: &Txn, other: &Tensor) -> TCResult<Tensor> {
+        let (m, k, n) = match (self.shape(), other.shape()) {
+            ([m, k], [j, n]) if k == j => (*m, *k, *n),
+            _ => {
+                return Err(TCError::bad_request(
+                    format!("cannot multiply a matrix of shape {:?} by", self.shape()),
+                    format!("{:?}", other.shape()),
+                ))
+            }
+        };
+
+        let budget = sort::sort_budget() as u64;
+        let bytes = other
+            .size
+            .checked_mul(std::mem::size_of::<Number>() as u64)
+            .filter(|bytes| *bytes <= budget);
+
+        if bytes.is_none() {
+            return Err(TCError::bad_request(
+                format!(
+                    "matrix multiplication is limited to {} bytes of the right operand, too small for shape",
+                    budget
+                ),
+                format!("{:?}", other.shape()),
+            ));
+        }
+
+        let product = Self::create(txn, self.dtype(), vec![m, n]).await?;
+
+        let txn_id = *txn.id();
+        let right = other.elements.read(&txn_id, 0..other.size).await?;
+        let zero = Number::from(false).into_type(self.dtype());
+
+        let (k, n) = (k as usize, n as usize);
+        for i in 0..m {
+            let left = self
+                .elements
+                .read(&txn_id, (i * k as u64)..((i + 1) * k as u64))
+                .await?;
+
+            let mut row = vec![zero; n];
+            for (x, l) in left.into_iter().enumerate() {
+                for j in 0..n {
+                    row[j] += l * right[x * n + j];
+                }
+            }
+
+            product.elements.write(txn_id, i * n as u64, &row).await?;
+        }
+
+        Ok(product)
+    }
+
+    /// Construct a new `Tensor`, in the workspace of `txn`, with a copy of the elements at the
+    /// given coordinate prefix.
+    pub async fn slice(&self, txn: &Txn, coord: &[u64]) -> TCResult<Tensor> {
+        let offsets = self.offsets(coord)?;
+        let shape = self.shape()[coord.len()..].to_vec();
+        let slice = Self::create(txn, self.dtype(), shape).await?;
+
+        let txn_id = *txn.id();
+        let mut offset = offsets.start;
+        while offset < offsets.end {
+            let end = offsets.end.min(offset + BLOCK_SIZE as u64);
+            let chunk = self.elements.read(&txn_id, offset..end).await?;
+            slice
+                .elements
+                .write(txn_id, offset - offsets.start, &chunk)
+                .await?;
+
+            offset = end;
+        }
+
+        Ok(slice)
+    }
+
+    /// Set every element at the given coordinate prefix to `value`.
+    pub async fn write_value(&self, txn_id: TxnId, coord: &[u64], value: Number) -> TCResult<()> {
+        let offsets = self.offsets(coord)?;
+        let value = value.into_type(self.dtype());
+        self.elements.fill(txn_id, offsets, value).await
+    }
+
+    fn offsets(&self, coord: &[u64]) -> TCResult<Range<u64>> {
+        if coord.len() > self.shape().len() {
+            return Err(TCError::bad_request(
+                format!("Tensor of shape {:?} has no coordinate", self.shape()),
+                format!("{:?}", coord),
+            ));
+        }
+
+        let mut offset = 0;
+        let mut stride = self.size;
+        for (i, (x, dim)) in coord.iter().zip(self.shape()).enumerate() {
+            if x >= dim {
+                return Err(TCError::bad_request(
+                    format!("index out of bounds for axis {} with dimension {}", i, dim),
+                    x,
+                ));
+            }
+
+            stride /= dim;
+            offset += x * stride;
+        }
+
+        Ok(offset..(offset + stride))
+    }
+}
+
+#[async_trait]
+impl Persist for Tensor {
+    type Schema = (NumberType, Shape);
+    type Store = fs::Dir;
+
+    fn schema(&'_ self) -> &'_ Self::Schema {
+        &self.schema
+    }
+
+    async fn load(schema: Self::Schema, dir: fs::Dir, txn_id: TxnId) -> TCResult<Self> {
+        let (dtype, shape) = schema;
+        let size = size_of(&shape)?;
+        let elements = Elements::load(dtype, size, &dir, txn_id).await?;
+
+        Ok(Self {
+            schema: (dtype, shape),
+            size,
+            elements,
+        })
+    }
+}
+
+#[async_trait]
+impl Transact for Tensor {
+    async fn commit(&self, txn_id: &TxnId) {
+        self.elements.commit(txn_id).await
+    }
+
+    async fn finalize(&self, txn_id: &TxnId) {
+        self.elements.finalize(txn_id).await
+    }
+}
+
+#[async_trait]
+impl de::FromStream for Tensor {
+    type Context = Txn;
+
+    async fn from_stream<D: de::Decoder>(txn: Txn, decoder: &mut D) -> Result<Self, D::Error> {
+        decoder.decode_seq(TensorVisitor { txn }).await
+    }
+}
+
+struct TensorVisitor {
+    txn: Txn,
+}
+
+#[async_trait]
+impl de::Visitor for TensorVisitor {
+    type Value = Tensor;
+
+    fn expecting() -> &'static str {
+        "a Tensor data type and shape followed by its elements"
+    }
+
+    async fn visit_seq<A: de::SeqAccess>(self, mut seq: A) -> Result<Tensor, A::Error> {
+        let (dtype, shape): (Value, Shape) = seq
+            .next_element(())
+            .await?
+            .ok_or_else(|| de::Error::invalid_length(0, Self::expecting()))?;
+
+        let dtype = match schema::value_type(&dtype) {
+            Some(ValueType::Number(dtype)) => dtype,
+            _ => return Err(de::Error::invalid_value(dtype, "a Number type")),
+        };
+
+        let tensor = Tensor::create(&self.txn, dtype, shape)
+            .map_err(de::Error::custom)
+            .await?;
+
+        let context = (*self.txn.id(), tensor.elements.clone());
+        seq.next_element::<LoadElements>(context)
+            .await?
+            .ok_or_else(|| de::Error::invalid_length(1, Self::expecting()))?;
+
+        Ok(tensor)
+    }
+}
+
+impl<'en> IntoView<'en, fs::Dir> for Tensor {
+    type Txn = Txn;
+    type View = TensorView;
+
+    fn into_view(self, txn: Txn) -> TensorView {
+        TensorView {
+            tensor: self,
+            txn_id: *txn.id(),
+        }
+    }
+}
+
+impl fmt::Display for Tensor {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "Tensor of {} with shape {:?}",
+            self.dtype(),
+            self.shape()
+        )
+    }
+}
+
+/// A view of a [`Tensor`] as of a single transaction, used to encode its schema and contents.
+pub struct TensorView {
+    tensor: Tensor,
+    txn_id: TxnId,
+}
+
+impl<'en> en::IntoStream<'en> for TensorView {
+    fn into_stream<E: en::Encoder<'en>>(self, encoder: E) -> Result<E::Ok, E::Error> {
+        let contents = Contents::new(self.tensor.elements(self.txn_id));
+        let (dtype, shape) = self.tensor.schema;
+        let dtype = Link::from(ValueType::Number(dtype).path());
+        ((dtype, shape), contents).into_stream(encoder)
+    }
+}
+
+// the number of elements in a `Tensor` of the given shape, which must be at most `MAX_SIZE`
+fn size_of(shape: &[u64]) -> TCResult<u64> {
+    if shape.contains(&0) {
+        return Err(TCError::bad_request(
+            "Tensor dimensions must be nonzero, not",
+            format!("{:?}", shape),
+        ));
+    }
+
+    shape
+        .iter()
+        .try_fold(1u64, |size, dim| size.checked_mul(*dim))
+        .filter(|size| *size <= MAX_SIZE)
+        .ok_or_else(|| {
+            TCError::bad_request(
+                format!("a Tensor may have at most {} elements, not shape", MAX_SIZE),
+                format!("{:?}", shape),
+            )
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::{future, stream, TryStreamExt};
+
+    use tc_value::{FloatType, IntType};
+
+    use crate::test::TestHost;
+
+    use super::*;
+
+    fn int(n: i64) -> Number {
+        Number::from(n)
+    }
+
+    #[test]
+    fn test_size_of() {
+        assert_eq!(size_of(&[2, 3, 4]).ok(), Some(24));
+        assert!(size_of(&[2, 0]).is_err());
+        assert!(size_of(&[1 << 32, 1 << 32]).is_err());
+        assert!(size_of(&[u64::MAX, 2]).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_write_and_slice() -> TCResult<()> {
+        let host = TestHost::new(vec![]).await?;
+        let txn = host.new_txn(true).await?;
+        let txn_id = *txn.id();
+
+        let dtype = NumberType::Int(IntType::I64);
+        let shape = vec![3, BLOCK_SIZE as u64];
+        let tensor = Tensor::create(&txn, dtype, shape).await?;
+        assert!(tensor.read_value(&txn_id, &[2, 5]).await? == int(0));
+
+        tensor.write_value(txn_id, &[1], int(7)).await?;
+        tensor.write_value(txn_id, &[1, 3], int(9)).await?;
+        assert!(tensor.read_value(&txn_id, &[1, 3]).await? == int(9));
+        assert!(tensor.read_value(&txn_id, &[1, 4]).await? == int(7));
+        assert!(tensor.read_value(&txn_id, &[2, 4]).await? == int(0));
+
+        let slice = tensor.slice(&txn, &[1]).await?;
+        assert_eq!(slice.shape(), &[BLOCK_SIZE as u64]);
+        let page = slice.page(&txn_id, 2, 3).await?;
+        assert!(page == vec![int(7), int(9), int(7)]);
+
+        assert!(tensor.read_value(&txn_id, &[3, 0]).await.is_err());
+        assert!(tensor
+            .write_value(txn_id, &[0, 0, 0], int(1))
+            .await
+            .is_err());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_matmul() -> TCResult<()> {
+        let host = TestHost::new(vec![]).await?;
+        let txn = host.new_txn(true).await?;
+        let txn_id = *txn.id();
+
+        let dtype = NumberType::Float(FloatType::F64);
+        let left = Tensor::create(&txn, dtype, vec![2, 3]).await?;
+        let right = Tensor::create(&txn, dtype, vec![3, 2]).await?;
+        left.write_value(txn_id, &[], Number::from(2.)).await?;
+        right.write_value(txn_id, &[1], Number::from(3.)).await?;
+
+        let product = left.matmul(&txn, &right).await?;
+        assert_eq!(product.shape(), &[2, 2]);
+
+        let elements: Vec<Number> = product.elements(txn_id).try_collect().await?;
+        assert!(elements == vec![Number::from(6.); 4]);
+
+        assert!(left.matmul(&txn, &left).await.is_err());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_decode() -> TCResult<()> {
+        let host = TestHost::new(vec![]).await?;
+        let txn = host.new_txn(false).await?;
+
+        let encoded = r#"[["/state/scalar/value/number/int/64", [2, 2]], [1, 2, 3, 4]]"#;
+        let encoded = stream::once(future::ready(encoded.as_bytes().to_vec()));
+        let tensor: Tensor = destream_json::decode(txn.clone(), encoded)
+            .map_err(|e| TCError::bad_request("invalid Tensor", e))
+            .await?;
+
+        assert!(tensor.read_value(txn.id(), &[1, 0]).await? == int(3));
+
+        let encoded = r#"[["/state/scalar/value/number/int/64", [2, 2]], [1, 2, 3]]"#;
+        let encoded = stream::once(future::ready(encoded.as_bytes().to_vec()));
+        let result: Result<Tensor, _> = destream_json::decode(txn.clone(), encoded).await;
+        assert!(result.is_err());
+
+        Ok(())
+    }
+}
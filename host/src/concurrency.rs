@@ -0,0 +1,82 @@
+//! Limits on the number of futures which this host polls concurrently on behalf of one request.
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// The default maximum number of elements of a `Map` or `Tuple` to resolve concurrently.
+pub const DEFAULT_RESOLVE: usize = 32;
+
+/// The default maximum number of collections of a `Cluster` to commit or finalize concurrently.
+pub const DEFAULT_COLLECTION: usize = 8;
+
+/// The default maximum number of workers to sort the rows of one bulk load concurrently.
+pub const DEFAULT_SORT: usize = 4;
+
+static RESOLVE: AtomicUsize = AtomicUsize::new(DEFAULT_RESOLVE);
+static COLLECTION: AtomicUsize = AtomicUsize::new(DEFAULT_COLLECTION);
+static SORT: AtomicUsize = AtomicUsize::new(DEFAULT_SORT);
+
+/// Limits on concurrency, to tune this host for the number of cores and the IO bandwidth of its
+/// hardware.
+///
+/// A limit of zero is treated as a limit of one.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Concurrency {
+    /// The maximum number of elements of a `Map` or `Tuple` to resolve concurrently.
+    pub resolve: usize,
+
+    /// The maximum number of collections of a `Cluster` to commit or finalize concurrently.
+    pub collection: usize,
+
+    /// The maximum number of workers to sort the rows of one bulk load concurrently.
+    pub sort: usize,
+}
+
+impl Concurrency {
+    /// Return the limits currently configured for this process.
+    pub fn current() -> Self {
+        Self {
+            resolve: RESOLVE.load(Ordering::Relaxed),
+            collection: COLLECTION.load(Ordering::Relaxed),
+            sort: SORT.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Set the limits used by every subsequent request to this process.
+    pub fn configure(self) {
+        RESOLVE.store(self.resolve.max(1), Ordering::Relaxed);
+        COLLECTION.store(self.collection.max(1), Ordering::Relaxed);
+        SORT.store(self.sort.max(1), Ordering::Relaxed);
+    }
+}
+
+impl Default for Concurrency {
+    fn default() -> Self {
+        Self {
+            resolve: DEFAULT_RESOLVE,
+            collection: DEFAULT_COLLECTION,
+            sort: DEFAULT_SORT,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_configure() {
+        let limits = Concurrency {
+            resolve: 0,
+            collection: 4,
+            ..Concurrency::default()
+        };
+
+        limits.configure();
+        let current = Concurrency::current();
+        assert_eq!(current.resolve, 1);
+        assert_eq!(current.collection, 4);
+
+        Concurrency::default().configure();
+        assert_eq!(Concurrency::current(), Concurrency::default());
+    }
+}
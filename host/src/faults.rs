@@ -0,0 +1,175 @@
+//! Simulated failures, to test the resilience of a cluster of hosts against realistic faults.
+//!
+//! Each fault is disabled by default and is controlled at runtime by a PUT request to
+//! `/sbin/faults`, e.g. `PUT /sbin/faults?key="block_io"` with a value of `100` to fail one in
+//! every 100 block reads and writes at random, or a value of `0` to disable the fault again.
+//! `GET /sbin/faults` returns the current settings of every fault.
+//!
+//! The supported faults are:
+//!  - `block_io`: fail one in every N block reads and writes with an internal error
+//!  - `commit_delay`: delay the commit of every `Cluster` by N milliseconds
+//!  - `peer_drop`: drop one in every N responses from other hosts with a bad gateway error
+
+use std::convert::TryFrom;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+use log::warn;
+use safecast::{CastFrom, TryCastFrom};
+use uuid::Uuid;
+
+use tc_error::*;
+use tcgeneric::{label, path_label, Id, Label, Map, PathLabel};
+
+use crate::scalar::{Link, Number, Value};
+use crate::state::State;
+
+/// The path at which faults are controlled.
+pub const PATH: PathLabel = path_label(&["sbin", "faults"]);
+
+const BLOCK_IO: Label = label("block_io");
+const COMMIT_DELAY: Label = label("commit_delay");
+const PEER_DROP: Label = label("peer_drop");
+
+static BLOCK_IO_RATE: AtomicU64 = AtomicU64::new(0);
+static COMMIT_DELAY_MILLIS: AtomicU64 = AtomicU64::new(0);
+static PEER_DROP_RATE: AtomicU64 = AtomicU64::new(0);
+
+/// Handle `GET /sbin/faults` by returning the current setting of each fault.
+pub fn get(key: Value) -> TCResult<State> {
+    if !key.is_none() {
+        return Err(TCError::bad_request("/sbin/faults has no key", key));
+    }
+
+    let faults: Vec<(Id, &AtomicU64)> = vec![
+        (BLOCK_IO.into(), &BLOCK_IO_RATE),
+        (COMMIT_DELAY.into(), &COMMIT_DELAY_MILLIS),
+        (PEER_DROP.into(), &PEER_DROP_RATE),
+    ];
+
+    let faults: Map<State> = faults
+        .into_iter()
+        .map(|(name, setting)| {
+            let setting = Value::from(Number::from(setting.load(Ordering::Relaxed)));
+            (name, State::from(setting))
+        })
+        .collect();
+
+    Ok(State::Map(faults))
+}
+
+/// Handle `PUT /sbin/faults` by updating the setting of the fault named by `key`.
+pub fn put(key: Value, value: State) -> TCResult<()> {
+    let name: Id = match key {
+        Value::String(name) => name.parse()?,
+        other => {
+            return Err(TCError::bad_request(
+                "expected the name of a fault, not",
+                other,
+            ))
+        }
+    };
+
+    let setting = if name == BLOCK_IO {
+        &BLOCK_IO_RATE
+    } else if name == COMMIT_DELAY {
+        &COMMIT_DELAY_MILLIS
+    } else if name == PEER_DROP {
+        &PEER_DROP_RATE
+    } else {
+        return Err(TCError::bad_request("unknown fault", name));
+    };
+
+    let value = Value::try_from(value)?;
+    let value = Number::try_cast_from(value, |v| {
+        TCError::bad_request("expected a number to configure a fault, not", v)
+    })?;
+
+    setting.store(u64::cast_from(value), Ordering::Relaxed);
+    Ok(())
+}
+
+/// Fail a block read or write at random, if the `block_io` fault is enabled.
+pub fn block_io(path: &PathBuf) -> TCResult<()> {
+    if occurs(&BLOCK_IO_RATE) {
+        warn!("injecting a block IO error at {:?}", path);
+        Err(TCError::internal(format!(
+            "simulated IO error at {:?}",
+            path
+        )))
+    } else {
+        Ok(())
+    }
+}
+
+/// Delay the commit of a transaction, if the `commit_delay` fault is enabled.
+pub async fn commit_delay() {
+    let millis = COMMIT_DELAY_MILLIS.load(Ordering::Relaxed);
+    if millis > 0 {
+        warn!("injecting a commit delay of {}ms", millis);
+        tokio::time::sleep(Duration::from_millis(millis)).await;
+    }
+}
+
+/// Drop a response from another host at random, if the `peer_drop` fault is enabled.
+pub fn peer_response(source: &Link) -> TCResult<()> {
+    if occurs(&PEER_DROP_RATE) {
+        warn!("injecting a dropped response from {}", source);
+        Err(TCError::bad_gateway(format!(
+            "simulated dropped response from {}",
+            source
+        )))
+    } else {
+        Ok(())
+    }
+}
+
+// a fault with a rate of N occurs one in every N times, at random, or never if N is zero
+fn occurs(rate: &AtomicU64) -> bool {
+    match rate.load(Ordering::Relaxed) {
+        0 => false,
+        rate => Uuid::new_v4().as_u128().is_multiple_of(rate as u128),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn setting(faults: &State, name: Label) -> Value {
+        match faults {
+            State::Map(faults) => {
+                let name: Id = name.into();
+                Value::try_from(faults[&name].clone()).expect("setting")
+            }
+            other => panic!("expected a Map but found {}", other),
+        }
+    }
+
+    #[test]
+    fn test_faults() {
+        let link: Link = "http://127.0.0.1:8702/app".parse().unwrap();
+        let path = PathBuf::from("/tmp/block");
+        let rate = |rate: u64| State::from(Value::from(Number::from(rate)));
+
+        put(Value::String("peer_drop".into()), rate(1)).unwrap();
+        let faults = get(Value::None).unwrap();
+        assert!(setting(&faults, PEER_DROP) == Value::from(Number::from(1u64)));
+        assert!(peer_response(&link).unwrap_err().code() == ErrorType::BadGateway);
+
+        put(Value::String("peer_drop".into()), rate(0)).unwrap();
+        assert!(peer_response(&link).is_ok());
+
+        // enabling block_io here would fail the block reads and writes of concurrent tests
+        assert!(occurs(&AtomicU64::new(1)));
+        assert!(!occurs(&AtomicU64::new(0)));
+
+        put(Value::String("block_io".into()), rate(0)).unwrap();
+        assert!(block_io(&path).is_ok());
+
+        assert!(put(Value::String("unknown".into()), rate(1)).is_err());
+        assert!(put(Value::None, rate(1)).is_err());
+        assert!(get(Value::String("block_io".into())).is_err());
+    }
+}
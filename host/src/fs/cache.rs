@@ -5,6 +5,9 @@ use std::convert::{TryFrom, TryInto};
 use std::hash::Hash;
 use std::io;
 use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
 
 use bytes::Bytes;
 use futures::TryFutureExt;
@@ -20,6 +23,9 @@ use crate::chain::ChainBlock;
 
 use super::{create_parent, io_err};
 
+// how long a health check waits to acquire the cache lock before reporting the cache as stuck
+const HEALTH_TIMEOUT: Duration = Duration::from_secs(5);
+
 /// A [`CacheLock`] representing a single filesystem block.
 #[derive(Clone)]
 pub enum CacheBlock {
@@ -80,12 +86,14 @@ impl TryFrom<CacheBlock> for CacheLock<ChainBlock> {
 /// A filesystem cache lock.
 pub struct CacheLock<T> {
     lock: RwLock<T>,
+    writers: Arc<AtomicUsize>,
 }
 
 impl<T> CacheLock<T> {
     fn new(value: T) -> Self {
         Self {
             lock: RwLock::new(value),
+            writers: Arc::new(AtomicUsize::new(0)),
         }
     }
 
@@ -96,9 +104,15 @@ impl<T> CacheLock<T> {
 
     /// Lock this value mutably and exclusively for writing.
     pub async fn write(&self) -> RwLockWriteGuard<T> {
+        let _waiting = Waiting::new(&self.writers);
         self.lock.write().await
     }
 
+    /// Return `true` if a writer is waiting to lock this value.
+    pub fn writers_waiting(&self) -> bool {
+        self.writers.load(Ordering::Acquire) > 0
+    }
+
     /// Return the number of references to this cache entry.
     pub fn ref_count(&self) -> usize {
         self.lock.ref_count()
@@ -109,10 +123,29 @@ impl<T> Clone for CacheLock<T> {
     fn clone(&self) -> Self {
         Self {
             lock: self.lock.clone(),
+            writers: self.writers.clone(),
         }
     }
 }
 
+// counts a writer as waiting until it acquires its lock, or gives up
+struct Waiting<'a> {
+    writers: &'a AtomicUsize,
+}
+
+impl<'a> Waiting<'a> {
+    fn new(writers: &'a AtomicUsize) -> Self {
+        writers.fetch_add(1, Ordering::AcqRel);
+        Self { writers }
+    }
+}
+
+impl<'a> Drop for Waiting<'a> {
+    fn drop(&mut self) {
+        self.writers.fetch_sub(1, Ordering::AcqRel);
+    }
+}
+
 struct Evict;
 
 struct Inner {
@@ -185,6 +218,9 @@ impl Cache {
             log::info!("cache miss: {:?}", path);
         }
 
+        #[cfg(feature = "faults")]
+        crate::faults::block_io(path)?;
+
         let block = match fs::read(path).await {
             Ok(block) => Bytes::from(block),
             Err(err) if err.kind() == io::ErrorKind::NotFound => {
@@ -246,6 +282,19 @@ impl Cache {
         inner.remove(&path).await
     }
 
+    /// Check that this cache can be locked and that its eviction task is still running.
+    pub async fn check_health(&self) -> TCResult<()> {
+        let inner = tokio::time::timeout(HEALTH_TIMEOUT, self.inner.read())
+            .await
+            .map_err(|_| TCError::unavailable("the block cache is locked"))?;
+
+        // eviction is a no-op unless the cache is over its maximum size
+        inner
+            .tx
+            .send(Evict)
+            .map_err(|_| TCError::unavailable("the block cache eviction task has stopped"))
+    }
+
     /// Synchronize a cached block with the filesystem.
     pub async fn sync(&self, path: &PathBuf) -> TCResult<()> {
         debug!("sync block at {:?} with filesystem", &path);
@@ -254,6 +303,9 @@ impl Cache {
         if let Some(block) = inner.entries.get(path) {
             let as_bytes = block.clone().into_bytes().await;
 
+            #[cfg(feature = "faults")]
+            crate::faults::block_io(path)?;
+
             create_parent(path).await?;
 
             fs::write(path, as_bytes)
@@ -302,6 +354,11 @@ impl<T: Clone + Eq + Hash> LFU<T> {
     fn remove(&mut self, id: &T) {
         if let Some(i) = self.entries.remove(id) {
             self.priority.remove(i);
+
+            // every entry after the one removed moves up by one place
+            for id in &self.priority[i..] {
+                *self.entries.get_mut(id).unwrap() -= 1;
+            }
         }
     }
 }
@@ -189,6 +189,23 @@ impl Dir {
         })
     }
 
+    /// Check that this directory is writable, and that its block cache is operational.
+    ///
+    /// The directory is checked by writing and then removing a hidden probe file.
+    pub async fn check_health(&self) -> TCResult<()> {
+        let probe = self.path.join(format!(".health-{}", uuid::Uuid::new_v4()));
+
+        tokio::fs::write(&probe, b"ok")
+            .map_err(|e| TCError::unavailable(format!("{:?} is not writable: {}", self.path, e)))
+            .await?;
+
+        tokio::fs::remove_file(&probe)
+            .map_err(|e| io_err(e, &probe))
+            .await?;
+
+        self.cache.check_health().await
+    }
+
     pub async fn get_or_create_dir(&self, txn_id: TxnId, name: PathSegment) -> TCResult<Self> {
         match fs::Dir::get_dir(self, &txn_id, &name).await? {
             Some(dir) => Ok(dir),
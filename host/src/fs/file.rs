@@ -5,20 +5,61 @@ use std::convert::TryFrom;
 use std::marker::PhantomData;
 use std::ops::Deref;
 use std::path::PathBuf;
+use std::time::Instant;
 
 use async_trait::async_trait;
 use futures::future::{join_all, try_join_all, FutureExt, TryFutureExt};
+use futures::stream::{self, Stream, StreamExt};
 use futures::try_join;
 use log::{debug, error};
 
 use tc_error::*;
 use tc_transact::fs;
 use tc_transact::lock::{Mutable, TxnLock};
-use tc_transact::{Transact, TxnId};
+use tc_transact::{stats, Transact, TxnId};
 use tcgeneric::Id;
+use uplock::RwLockReadGuard;
 
 use super::{file_name, Cache, CacheBlock, CacheLock, DirContents};
 
+/// A pinned read lease on one block of a [`File`], for an iterator which reads the same block
+/// many times over the course of a scan.
+///
+/// A lease keeps its block in the cache, and keeps the read lock it acquires between reads, so
+/// that each read doesn't have to look the block up again. To be fair to writers, the lease gives
+/// up its read lock and yields as soon as a writer is waiting on the block.
+pub struct BlockLease<B> {
+    block_id: fs::BlockId,
+    lock: CacheLock<B>,
+    guard: Option<RwLockReadGuard<B>>,
+}
+
+impl<B> BlockLease<B> {
+    /// The ID of the leased block.
+    pub fn block_id(&self) -> &fs::BlockId {
+        &self.block_id
+    }
+
+    /// Read the leased block.
+    pub async fn read(&mut self) -> &B {
+        if self.lock.writers_waiting() {
+            self.guard = None;
+            tokio::task::yield_now().await;
+        }
+
+        if self.guard.is_none() {
+            self.guard = Some(self.lock.read().await);
+        }
+
+        self.guard.as_deref().expect("block lease read lock")
+    }
+
+    /// Release the read lock held by this lease, without giving up the lease itself.
+    pub fn release(&mut self) {
+        self.guard = None;
+    }
+}
+
 /// A transactional file.
 #[derive(Clone)]
 pub struct File<B> {
@@ -77,18 +118,57 @@ impl<B: fs::BlockData + 'static> File<B> {
         CacheLock<B>: TryFrom<CacheBlock, Error = TCError>,
         CacheBlock: From<CacheLock<B>>,
     {
+        let start = Instant::now();
         let path = block_path(&self.path, txn_id, block_id);
-        if let Some(lock) = self.cache.read(&path).await? {
-            Ok(Some(lock))
+        let lock = if let Some(lock) = self.cache.read(&path).await? {
+            Some(lock)
         } else if let Some(block) = self.cache.read(&canonical(&self.path, block_id)).await? {
             let contents = block.read().await;
-            self.cache
-                .write(path, contents.deref().clone())
-                .map_ok(Some)
-                .await
+            Some(self.cache.write(path, contents.deref().clone()).await?)
         } else {
-            Ok(None)
+            None
+        };
+
+        stats::device_time(txn_id, start.elapsed());
+        if lock.is_some() {
+            stats::block_read(txn_id);
         }
+
+        Ok(lock)
+    }
+}
+
+impl<B: fs::BlockData + 'static> File<B>
+where
+    CacheBlock: From<CacheLock<B>>,
+    CacheLock<B>: TryFrom<CacheBlock, Error = TCError>,
+{
+    /// Pin the block with the given ID in the cache and return a read lease on it.
+    pub async fn pin(&self, txn_id: &TxnId, block_id: fs::BlockId) -> TCResult<BlockLease<B>> {
+        if let Some(lock) = self.get_block_lock(txn_id, &block_id).await? {
+            Ok(BlockLease {
+                block_id,
+                lock,
+                guard: None,
+            })
+        } else {
+            Err(TCError::not_found(block_id))
+        }
+    }
+
+    /// Read the blocks with the given IDs, in order, as of the given [`TxnId`].
+    ///
+    /// Up to `read_ahead` blocks are read concurrently ahead of the block being consumed, so that
+    /// a sequential scan doesn't wait on a read from the filesystem for every block.
+    pub fn read_ahead(
+        self,
+        txn_id: TxnId,
+        block_ids: Vec<fs::BlockId>,
+        read_ahead: usize,
+    ) -> impl Stream<Item = TCResult<fs::BlockOwned<Self>>> {
+        stream::iter(block_ids)
+            .map(move |block_id| fs::File::get_block_owned(self.clone(), txn_id, block_id))
+            .buffered(read_ahead.max(1))
     }
 }
 
@@ -132,6 +212,7 @@ where
         debug!("create block at {:?}", &path);
 
         let lock = self.cache.write(path, initial_value).await?;
+        stats::block_written(&txn_id);
 
         listing.insert(name.clone());
         mutated.insert(name.clone());
@@ -165,6 +246,8 @@ where
                 mutated.insert(name.clone());
             }
 
+            stats::block_written(txn_id);
+
             Ok(fs::BlockMut::new(self, txn_id, name, lock))
         } else {
             Err(TCError::not_found(name))
@@ -191,6 +274,8 @@ where
                 mutated.insert(name.clone());
             }
 
+            stats::block_written(&txn_id);
+
             Ok(fs::BlockOwnedMut::new(self, txn_id, name, lock))
         } else {
             Err(TCError::not_found(name))
@@ -214,6 +299,7 @@ where
                 .expect("file mutated block list"),
         ) {
             let _listing = self.listing.read(txn_id).await.expect("lock file listing");
+            let start = Instant::now();
 
             if !self.path.exists() {
                 tokio::fs::create_dir(&self.path)
@@ -251,6 +337,7 @@ where
             });
 
             try_join_all(commit).await.expect("commit mutated blocks");
+            stats::device_time(txn_id, start.elapsed());
         }
 
         self.listing.commit(txn_id).await;
@@ -308,3 +395,95 @@ fn version_path(file_path: &PathBuf, txn_id: &TxnId) -> PathBuf {
     path.push(txn_id.to_string());
     path
 }
+
+#[cfg(test)]
+mod tests {
+    use bytes::Bytes;
+    use futures::TryStreamExt;
+    use tc_transact::fs::Dir;
+    use tc_transact::Transaction;
+    use tcgeneric::label;
+
+    use crate::scalar::{ScalarType, ValueType};
+    use crate::state::StateType;
+    use crate::test::TestHost;
+    use crate::txn::Txn;
+
+    use super::*;
+
+    async fn create_file(txn: &Txn) -> TCResult<File<Bytes>> {
+        let class = StateType::Scalar(ScalarType::Value(ValueType::Bytes));
+        let file = txn
+            .context()
+            .create_file(*txn.id(), label("blocks").into(), class)
+            .await?;
+
+        File::try_from(file)
+    }
+
+    #[tokio::test]
+    async fn test_read_ahead() -> TCResult<()> {
+        let host = TestHost::new(vec![]).await?;
+        let txn = host.new_txn(true).await?;
+        let txn_id = *txn.id();
+
+        let file = create_file(&txn).await?;
+        let mut block_ids = Vec::with_capacity(5);
+        for i in 0..5u8 {
+            let block_id: fs::BlockId = i.to_string().parse()?;
+            fs::File::create_block(&file, txn_id, block_id.clone(), Bytes::from(vec![i])).await?;
+
+            block_ids.push(block_id);
+        }
+
+        for read_ahead in &[0, 2, 8] {
+            let blocks = file
+                .clone()
+                .read_ahead(txn_id, block_ids.clone(), *read_ahead)
+                .map_ok(|block| block[0])
+                .try_collect::<Vec<u8>>()
+                .await?;
+
+            assert_eq!(blocks, vec![0, 1, 2, 3, 4]);
+        }
+
+        block_ids.push(label("none").into());
+        let results: Vec<_> = file.read_ahead(txn_id, block_ids, 2).collect().await;
+        assert!(results[..5].iter().all(Result::is_ok));
+        assert!(results[5].is_err());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_lease() -> TCResult<()> {
+        let host = TestHost::new(vec![]).await?;
+        let txn = host.new_txn(true).await?;
+        let txn_id = *txn.id();
+
+        let file = create_file(&txn).await?;
+        let block_id: fs::BlockId = label("block").into();
+        fs::File::create_block(&file, txn_id, block_id.clone(), Bytes::from(vec![0])).await?;
+
+        let mut lease = file.pin(&txn_id, block_id).await?;
+        assert_eq!(&lease.read().await[..], &[0]);
+
+        let lock = lease.lock.clone();
+        let writer = tokio::spawn(async move {
+            *lock.write().await = Bytes::from(vec![1]);
+        });
+
+        while !lease.lock.writers_waiting() {
+            tokio::task::yield_now().await;
+        }
+
+        // the lease gives up its read lock so that the waiting writer can go first
+        assert_eq!(&lease.read().await[..], &[1]);
+        writer.await.unwrap();
+
+        let unknown = file.pin(&txn_id, label("unknown").into()).await;
+        assert!(unknown.map(|_| ()).unwrap_err().code() == ErrorType::NotFound);
+
+        Ok(())
+    }
+}
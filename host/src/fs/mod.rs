@@ -77,7 +77,7 @@ fn file_name(handle: &fs::DirEntry) -> TCResult<PathSegment> {
 #[inline]
 fn fs_path(mount_point: &PathBuf, name: &PathSegment) -> PathBuf {
     let mut path = mount_point.clone();
-    path.push(name.to_string());
+    path.push(name.as_str());
     path
 }
 
@@ -0,0 +1,27 @@
+//! Entry points to fuzz test the decoders of this host, e.g. with `cargo fuzz`.
+//!
+//! Example fuzz target:
+//! ```no_run
+//! # fn fuzz_target(data: &[u8]) {
+//! let _ = tinychain::fuzz::decode_scalar(data, Default::default());
+//! # }
+//! ```
+
+use destream::de::Decoder;
+use futures::{executor, future, stream};
+
+use tc_error::*;
+
+use crate::scalar::{DecodeContext, DecodeLimits, Scalar, ScalarVisitor};
+
+/// Decode a JSON-encoded [`Scalar`] subject to the given [`DecodeLimits`].
+///
+/// This must return an error, never panic or overflow the stack, for any input.
+pub fn decode_scalar(data: &[u8], limits: DecodeLimits) -> TCResult<Scalar> {
+    let source = stream::once(future::ready(Ok(data.to_vec())));
+    let mut decoder = destream_json::de::Decoder::from(source);
+    let visitor = ScalarVisitor::new(DecodeContext::new(limits));
+
+    executor::block_on(decoder.decode_any(visitor))
+        .map_err(|e| TCError::bad_request("unable to decode Scalar", e))
+}
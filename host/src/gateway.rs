@@ -1,30 +1,88 @@
 //! [`Gateway`] handles network traffic.
 
 use std::net::{IpAddr, SocketAddr};
+use std::path::PathBuf;
 use std::pin::Pin;
 use std::sync::Arc;
 use std::time::Duration;
 
 use async_trait::async_trait;
-use bytes::Bytes;
 use futures::future::{try_join_all, Future, TryFutureExt};
 use log::debug;
 use serde::de::DeserializeOwned;
 
 use tc_error::*;
-use tcgeneric::{NetworkTime, TCPathBuf};
+use tc_transact::Transaction;
+use tcgeneric::{NetworkTime, PathSegment, TCPathBuf};
 
 use crate::http;
-use crate::kernel::Kernel;
+use crate::kernel::{Kernel, BENCH};
+use crate::replay::Recorder;
 use crate::scalar::{Link, LinkHost, LinkProtocol, Value};
 use crate::state::State;
 use crate::txn::*;
+use crate::ws;
+
+mod egress;
+mod keys;
+mod nonce;
+mod oidc;
+mod rate_limit;
+mod secrets;
+
+pub use crate::http::{Cors, UploadLimits};
+pub use egress::EgressRule;
+pub use keys::TrustedKey;
+pub use oidc::Oidc;
+pub use rate_limit::RateLimit;
+pub use secrets::Secrets;
+
+pub(crate) use keys::KEYS;
+pub(crate) use rate_limit::{retry_after, too_many_requests};
+pub(crate) use secrets::SECRETS;
+
+use egress::Egress;
+use keys::{Keyring, COMMIT_KEYS};
+use nonce::Nonces;
+use oidc::{Federation, AUTH};
+use rate_limit::RateLimiter;
+
+type ServerFuture = Pin<Box<dyn Future<Output = Result<(), Box<dyn std::error::Error>>>>>;
 
 /// Configuration for [`Gateway`].
 pub struct Config {
     pub addr: IpAddr,
     pub http_port: u16,
+    pub ws_port: Option<u16>,
     pub request_ttl: Duration,
+    pub record: Option<PathBuf>,
+    pub tls_cert: Option<PathBuf>,
+    pub tls_key: Option<PathBuf>,
+    pub grpc_port: Option<u16>,
+    /// Whether to call peers over gRPC, assuming that they serve it on the same port as this host.
+    pub grpc_peers: bool,
+    /// The size, in bytes, below which an HTTP response is not compressed.
+    pub compression_threshold: usize,
+    /// The maximum number of concurrent requests to multiplex over a single HTTP/2 connection.
+    pub http2_max_streams: u32,
+    pub cors: Cors,
+    /// The maximum size, in bytes, of a request body which isn't a multipart upload.
+    pub request_limit: u64,
+    /// The maximum size of each part of a `multipart/form-data` upload, and of the whole upload.
+    pub upload_limits: UploadLimits,
+    pub rate_limit: RateLimit,
+    /// Whether to reject an auth token which requests a mutation in more than one transaction.
+    pub single_use_tokens: bool,
+    /// The issuer keys to trust at startup, which may authorize updates to this host's key ring.
+    pub trusted_keys: Vec<TrustedKey>,
+    /// An external OIDC issuer whose ID tokens to accept as bearer tokens.
+    pub oidc: Option<Oidc>,
+    /// The store of secrets which ops may refer to, for example as the credentials of a service.
+    pub secrets: Option<Secrets>,
+    /// The networks which ops may send requests to; if empty, any host is allowed.
+    pub egress: Vec<EgressRule>,
+    /// The limits on the ops of particular clusters, which override the [`ExecLimits`] of this host.
+    pub exec_limits: Vec<ClusterLimits>,
 }
 
 /// A client used by [`Gateway`]
@@ -67,33 +125,98 @@ pub struct Gateway {
     txn_server: TxnServer,
     root: LinkHost,
     client: http::Client,
-    actor: Actor,
+    #[cfg(feature = "grpc")]
+    grpc: Option<crate::grpc::Client>,
+    keys: Keyring,
+    federation: Option<Federation>,
+    secrets: Option<Secrets>,
+    egress: Egress,
+    rate_limiter: RateLimiter,
+    nonces: Option<Nonces>,
 }
 
 impl Gateway {
     /// Return the current timestamp.
+    #[cfg(not(feature = "simulation"))]
     pub fn time() -> NetworkTime {
         NetworkTime::now()
     }
 
+    /// Return the current timestamp, according to the virtual clock of the running simulation.
+    #[cfg(feature = "simulation")]
+    pub fn time() -> NetworkTime {
+        tc_transact::sim::now()
+    }
+
     /// Initialize a new `Gateway`
-    pub fn new(config: Config, kernel: Kernel, txn_server: TxnServer) -> Arc<Self> {
+    pub fn new(mut config: Config, kernel: Kernel, txn_server: TxnServer) -> Arc<Self> {
         let root = LinkHost::from((
             LinkProtocol::HTTP,
             config.addr.clone(),
             Some(config.http_port),
         ));
 
+        #[cfg(feature = "grpc")]
+        let grpc = match config.grpc_port {
+            Some(port) if config.grpc_peers => Some(crate::grpc::Client::new(port)),
+            _ => None,
+        };
+
+        let keys = Keyring::new(config.trusted_keys.clone());
+        let federation = config.oidc.clone().map(Federation::new);
+        let secrets = config.secrets.take();
+        let egress = Egress::new(config.egress.clone());
+        let rate_limiter = RateLimiter::new(config.rate_limit);
+        let nonces = if config.single_use_tokens {
+            Some(Nonces::new())
+        } else {
+            None
+        };
+
         Arc::new(Self {
             config,
             kernel,
             txn_server,
             root,
             client: http::Client::new(),
-            actor: Actor::new(Link::default().into()),
+            #[cfg(feature = "grpc")]
+            grpc,
+            keys,
+            federation,
+            secrets,
+            egress,
+            rate_limiter,
+            nonces,
         })
     }
 
+    /// Count a request from the given `client`, or return how long it should wait to retry, if
+    /// it has exceeded its [`RateLimit`].
+    ///
+    /// This should be called before a new transaction is created for the request.
+    pub(crate) fn rate_limit(
+        &self,
+        client: IpAddr,
+        token: Option<&str>,
+        write: bool,
+    ) -> Result<(), Duration> {
+        self.rate_limiter.check(client, token, write)
+    }
+
+    /// Record that the given transaction has been committed at this host, so that the tokens used
+    /// to request a mutation in it can't be replayed into it, if this host accepts only
+    /// single-use tokens.
+    pub(crate) fn finish(&self, txn_id: &TxnId) {
+        if let Some(nonces) = &self.nonces {
+            nonces.finish(txn_id);
+        }
+    }
+
+    /// Return the key ring of this `Gateway`.
+    pub(crate) fn keys(&self) -> &Keyring {
+        &self.keys
+    }
+
     /// Return the network address of this `Gateway`
     pub fn root(&self) -> &LinkHost {
         &self.root
@@ -105,36 +228,87 @@ impl Gateway {
     }
 
     /// Authorize a transaction to execute on this host.
-    pub async fn new_txn(self: &Arc<Self>, txn_id: TxnId, token: Option<String>) -> TCResult<Txn> {
-        let token = if let Some(token) = token {
-            use rjwt::Resolve;
-            Resolver::new(self, &self.root().clone().into(), &txn_id)
-                .consume_and_sign(&self.actor, vec![], token, txn_id.time().into())
-                .map_err(TCError::unauthorized)
-                .await?
-        } else {
-            let token = Token::new(
-                self.root.clone().into(),
-                txn_id.time().into(),
-                self.config.request_ttl,
-                self.actor.id().clone(),
-                vec![],
-            );
-            let signed = self.actor.sign_token(&token).map_err(TCError::internal)?;
-            let claims = token.claims();
-            (signed, claims)
+    ///
+    /// If this host only accepts single-use tokens, the `token` of a `mutation` request is
+    /// rejected if it was already used to request a mutation in a different transaction, or in
+    /// the same transaction after it was committed.
+    pub async fn new_txn(
+        self: &Arc<Self>,
+        txn_id: TxnId,
+        token: Option<String>,
+        locale: Locale,
+        mutation: bool,
+    ) -> TCResult<Txn> {
+        let actor = self.keys.signing();
+        let token = match token {
+            Some(token) => {
+                let nonce = match &self.nonces {
+                    Some(nonces) if mutation => Some((nonces, token.clone())),
+                    _ => None,
+                };
+
+                let (signed, claims) = match &self.federation {
+                    Some(federation) if federation.is_issued(&token) => {
+                        // an ID token of a federated OIDC issuer is exchanged for this host's own
+                        let scopes = federation.verify(&token).await?;
+                        self.sign_token(&actor, &txn_id, scopes)?
+                    }
+                    _ => {
+                        use rjwt::Resolve;
+                        Resolver::new(self, &self.root().clone().into(), &txn_id)
+                            .consume_and_sign(&actor, vec![], token, txn_id.time().into())
+                            .map_err(TCError::unauthorized)
+                            .await?
+                    }
+                };
+
+                if let Some((nonces, token)) = nonce {
+                    nonces.check(&txn_id, &token, claims.expires())?;
+                }
+
+                (signed, claims)
+            }
+            None => self.sign_token(&actor, &txn_id, vec![])?,
         };
 
-        self.txn_server.new_txn(self.clone(), txn_id, token).await
+        self.txn_server
+            .new_txn(self.clone(), txn_id, token, locale)
+            .await
     }
 
-    /// Read a simple value.
+    // issue a new token signed by this host, which grants the given `scopes`
+    fn sign_token(
+        &self,
+        actor: &Actor,
+        txn_id: &TxnId,
+        scopes: Vec<Scope>,
+    ) -> TCResult<(String, Claims)> {
+        let token = Token::new(
+            self.root.clone().into(),
+            txn_id.time().into(),
+            self.config.request_ttl,
+            actor.id().clone(),
+            scopes,
+        );
+
+        let signed = actor.sign_token(&token).map_err(TCError::internal)?;
+        let claims = token.claims();
+        Ok((signed, claims))
+    }
+
+    /// Read a simple value, like the public key which signed an auth token, outside of any
+    /// transaction, subject to the egress policy of this host.
     pub async fn fetch<T: DeserializeOwned>(
         &self,
         txn_id: &TxnId,
         link: &Link,
         key: &Value,
     ) -> TCResult<T> {
+        match link.host() {
+            Some(host) if host != self.root() => self.egress.check_unscoped(host)?,
+            _ => {}
+        }
+
         self.client.fetch(txn_id, link, key).await
     }
 
@@ -142,13 +316,36 @@ impl Gateway {
     pub async fn get(&self, txn: &Txn, link: Link, key: Value) -> TCResult<State> {
         debug!("GET {}: {}", link, key);
         match link.host() {
-            None if link.path().is_empty() && key.is_none() => {
-                let public_key = Bytes::from(self.actor.public_key().as_bytes().to_vec());
-                Ok(State::from(Value::from(public_key)))
+            None if link.path().is_empty() => match self.keys.public_key(&key) {
+                Some(public_key) => Ok(State::from(Value::from(public_key))),
+                None => self.kernel.get(txn, link.path(), key).await,
+            },
+            _ if link.path()[..] == KEYS[..] && self.is_local(&link) => self.keys.get(key),
+            _ if link.path()[..] == AUTH[..] && self.is_local(&link) => self.federation()?.get(key),
+            _ if link.path()[..] == SECRETS[..] && self.is_local(&link) => {
+                self.secrets()?.get(txn, &self.keys, key)
+            }
+            _ if link.path().len() > 2 && link.path()[..2] == BENCH[..] && self.is_local(&link) => {
+                self.keys.authorize(txn, &BENCH.into())?;
+                self.kernel.get(txn, link.path(), key).await
             }
             None => self.kernel.get(txn, link.path(), key).await,
             Some(host) if host == self.root() => self.kernel.get(txn, link.path(), key).await,
-            _ => self.client.get(txn.clone(), link, key).await,
+            Some(host) => {
+                self.egress.check(txn, &self.root, host)?;
+
+                let key = match &self.secrets {
+                    Some(secrets) => secrets.unseal_value(txn, &self.keys, host, key)?,
+                    None => key,
+                };
+
+                #[cfg(feature = "grpc")]
+                if let Some(grpc) = &self.grpc {
+                    return grpc.get(txn.clone(), link, key).await;
+                }
+
+                self.client.get(txn.clone(), link, key).await
+            }
         }
     }
 
@@ -164,11 +361,66 @@ impl Gateway {
             debug!("PUT {}: {} <- {}", link, key, value);
 
             match link.host() {
+                _ if link.path()[..] == KEYS[..] && self.is_local(&link) => {
+                    self.keys.put(txn, key, value)?;
+                    self.commit_keys(txn).await
+                }
+                _ if link.path()[..] == AUTH[..] && self.is_local(&link) => {
+                    let federation = self.federation()?;
+                    federation.put(txn, &self.keys, key, value)?;
+
+                    // like a cluster, the mapping is committed by the owner of the transaction
+                    match txn.owner() {
+                        Some(owner) => {
+                            let link = self.link(AUTH.into());
+                            txn.put(owner.clone(), Value::default(), link.into()).await
+                        }
+                        None => {
+                            federation.commit(txn.id()).await?;
+                            self.finish(txn.id());
+                            Ok(())
+                        }
+                    }
+                }
+                _ if link.path()[..] == SECRETS[..] && self.is_local(&link) => {
+                    let secrets = self.secrets()?;
+                    secrets.put(txn, &self.keys, key, value)?;
+
+                    // like a cluster, the store is committed by the owner of the transaction
+                    match txn.owner() {
+                        Some(owner) => {
+                            let link = self.link(SECRETS.into());
+                            txn.put(owner.clone(), Value::default(), link.into()).await
+                        }
+                        None => {
+                            secrets.commit(txn.id()).await?;
+                            self.finish(txn.id());
+                            Ok(())
+                        }
+                    }
+                }
                 None => self.kernel.put(txn, link.path(), key, value).await,
                 Some(host) if host == self.root() => {
                     self.kernel.put(txn, link.path(), key, value).await
                 }
-                _ => self.client.put(txn.clone(), link, key, value).await,
+                Some(host) => {
+                    self.egress.check(txn, &self.root, host)?;
+
+                    let (key, value) = match &self.secrets {
+                        Some(secrets) => (
+                            secrets.unseal_value(txn, &self.keys, host, key)?,
+                            secrets.unseal(txn, &self.keys, host, value)?,
+                        ),
+                        None => (key, value),
+                    };
+
+                    #[cfg(feature = "grpc")]
+                    if let Some(grpc) = &self.grpc {
+                        return grpc.put(txn.clone(), link, key, value).await;
+                    }
+
+                    self.client.put(txn.clone(), link, key, value).await
+                }
             }
         })
     }
@@ -178,17 +430,136 @@ impl Gateway {
         debug!("POST to {} with params {}", link, params);
 
         match link.host() {
+            _ if link.path()[..] == KEYS[..] && self.is_local(&link) => {
+                let kid = self.keys.rotate(txn, params)?;
+                self.commit_keys(txn).await?;
+                Ok(kid)
+            }
+            _ if link.path()[..] == COMMIT_KEYS[..] && self.is_local(&link) => {
+                // the owner of a transaction which updated the key ring sends it a commit message
+                if !params.is_none() {
+                    return Err(TCError::bad_request(
+                        "unrecognized commit parameters",
+                        params,
+                    ));
+                }
+
+                self.keys.commit(txn.id());
+                self.finish(txn.id());
+                Ok(State::default())
+            }
+            _ if link.path()[..] == AUTH[..] && self.is_local(&link) => {
+                // the owner of a transaction which updated the mapping sends it a commit message
+                if !params.is_none() {
+                    return Err(TCError::bad_request(
+                        "unrecognized commit parameters",
+                        params,
+                    ));
+                }
+
+                self.federation()?.commit(txn.id()).await?;
+                self.finish(txn.id());
+                Ok(State::default())
+            }
+            _ if link.path()[..] == SECRETS[..] && self.is_local(&link) => {
+                // the owner of a transaction which updated the store sends it a commit message
+                if !params.is_none() {
+                    return Err(TCError::bad_request(
+                        "unrecognized commit parameters",
+                        params,
+                    ));
+                }
+
+                self.secrets()?.commit(txn.id()).await?;
+                self.finish(txn.id());
+                Ok(State::default())
+            }
             None => self.kernel.post(txn, link.path(), params).await,
             Some(host) if host == self.root() => self.kernel.post(txn, link.path(), params).await,
-            _ => self.client.post(txn.clone(), link, params).await,
+            Some(host) => {
+                self.egress.check(txn, &self.root, host)?;
+
+                let params = match &self.secrets {
+                    Some(secrets) => secrets.unseal(txn, &self.keys, host, params)?,
+                    None => params,
+                };
+
+                #[cfg(feature = "grpc")]
+                if let Some(grpc) = &self.grpc {
+                    return grpc.post(txn.clone(), link, params).await;
+                }
+
+                self.client.post(txn.clone(), link, params).await
+            }
         }
     }
 
+    // like a cluster, the key ring is committed by the owner of the transaction
+    async fn commit_keys(&self, txn: &Txn) -> TCResult<()> {
+        match txn.owner() {
+            Some(owner) => {
+                let link = self.link(COMMIT_KEYS.into());
+                txn.put(owner.clone(), Value::default(), link.into()).await
+            }
+            None => {
+                self.keys.commit(txn.id());
+                self.finish(txn.id());
+                Ok(())
+            }
+        }
+    }
+
+    fn federation(&self) -> TCResult<&Federation> {
+        self.federation
+            .as_ref()
+            .ok_or_else(|| TCError::not_found("this host has no OIDC issuer configured"))
+    }
+
+    fn secrets(&self) -> TCResult<&Secrets> {
+        self.secrets
+            .as_ref()
+            .ok_or_else(|| TCError::not_found("this host has no secrets store configured"))
+    }
+
+    /// Return the [`ExecLimits`] of the op at `path` on this host.
+    pub(crate) fn exec_limits(&self, path: &[PathSegment]) -> ExecLimits {
+        ClusterLimits::of(&self.config.exec_limits, path)
+    }
+
+    // whether the given link refers to this host
+    pub(crate) fn is_local(&self, link: &Link) -> bool {
+        link.host()
+            .as_ref()
+            .map(|host| host == self.root())
+            .unwrap_or(true)
+    }
+
+    /// Execute the maintenance `op` with exclusive access to `path`.
+    ///
+    /// In-flight requests which touch `path` are allowed to complete before `op` begins, and new
+    /// requests which touch `path` fail with an "unavailable" error until `op` completes.
+    pub async fn maintain<R, Fut, F>(&self, path: TCPathBuf, op: F) -> TCResult<R>
+    where
+        Fut: Future<Output = TCResult<R>>,
+        F: FnOnce() -> Fut,
+    {
+        let _lock = self.kernel.maintenance_lock(path).await?;
+        op().await
+    }
+
     /// Start this `Gateway`'s server
     pub fn listen(
         self: Arc<Self>,
     ) -> Pin<Box<impl Future<Output = Result<(), Box<dyn std::error::Error>>> + 'static>> {
-        let servers = vec![self.clone().http_listen()];
+        let mut servers: Vec<ServerFuture> = vec![self.clone().http_listen()];
+        if let Some(ws_port) = self.config.ws_port {
+            servers.push(self.clone().ws_listen(ws_port));
+        }
+
+        if let Some(grpc_port) = self.config.grpc_port {
+            servers.push(self.clone().grpc_listen(grpc_port));
+        }
+
         let txn_server = self.txn_server.clone();
 
         Box::pin(try_join_all(servers).map_ok(|_| ()).and_then(move |_| {
@@ -204,12 +575,81 @@ impl Gateway {
     ) -> std::pin::Pin<Box<impl futures::Future<Output = Result<(), Box<dyn std::error::Error>>>>>
     {
         let http_addr = (self.config.addr, self.config.http_port).into();
-        let server = crate::http::HTTPServer::new(self);
-        let listener = server.listen(http_addr).map_err(|e| {
-            let e: Box<dyn std::error::Error> = Box::new(e);
-            e
-        });
+        let compression_threshold = self.config.compression_threshold;
+        let http2_max_streams = self.config.http2_max_streams;
+        let cors = self.config.cors.clone();
+        let request_limit = self.config.request_limit;
+        let upload_limits = self.config.upload_limits;
+        let recorder = self.config.record.as_ref().map(Recorder::open).transpose();
+
+        let tls = match (&self.config.tls_cert, &self.config.tls_key) {
+            (Some(cert), Some(key)) => {
+                http::CertResolver::load(cert.clone(), key.clone()).map(Some)
+            }
+            (None, None) => Ok(None),
+            _ => Err(TCError::bad_request(
+                "TLS requires both a certificate and a private key",
+                "",
+            )),
+        };
+
+        Box::pin(async move {
+            let server = crate::http::HTTPServer::new(
+                self,
+                recorder?,
+                tls?,
+                compression_threshold,
+                http2_max_streams,
+                cors,
+                upload_limits,
+            )
+            .with_request_limit(request_limit);
+
+            server
+                .listen(http_addr)
+                .map_err(|e| {
+                    let e: Box<dyn std::error::Error> = Box::new(e);
+                    e
+                })
+                .await
+        })
+    }
+
+    fn ws_listen(self: Arc<Self>, port: u16) -> ServerFuture {
+        let ws_addr = (self.config.addr, port).into();
+
+        Box::pin(async move {
+            let server = ws::WebSocketServer::new(self);
+            server
+                .listen(ws_addr)
+                .map_err(|e| {
+                    let e: Box<dyn std::error::Error> = Box::new(e);
+                    e
+                })
+                .await
+        })
+    }
+
+    #[cfg(feature = "grpc")]
+    fn grpc_listen(self: Arc<Self>, port: u16) -> ServerFuture {
+        let grpc_addr = (self.config.addr, port).into();
+
+        Box::pin(async move {
+            let server = crate::grpc::GRPCServer::new(self);
+            server
+                .listen(grpc_addr)
+                .map_err(|e| {
+                    let e: Box<dyn std::error::Error> = Box::new(e);
+                    e
+                })
+                .await
+        })
+    }
 
-        Box::pin(listener)
+    #[cfg(not(feature = "grpc"))]
+    fn grpc_listen(self: Arc<Self>, _port: u16) -> ServerFuture {
+        let err = TCError::unsupported("this host was built without the \"grpc\" feature");
+        let err: Box<dyn std::error::Error> = Box::new(err);
+        Box::pin(futures::future::ready(Err(err)))
     }
 }
@@ -0,0 +1,247 @@
+//! The egress policy, which limits the hosts that ops on this host may send requests to.
+//!
+//! An op can resolve a link to any host, so without a policy, a user who can run an op could use
+//! this host to reach a service on its private network. If any egress rule is configured, an
+//! outbound request is only allowed to a host which either participates in the transaction
+//! already, like the host which owns it, or else matches a rule which applies to every cluster or
+//! to one of this host's clusters which participates in the transaction.
+//!
+//! A request which this host sends outside of any transaction, like a request for the public key
+//! which signed an incoming auth token, is only allowed to a host which matches a rule which
+//! applies to every cluster, since the token which names that host hasn't been verified yet.
+//!
+//! Note that this applies to the replicas of a cluster as well, so their addresses must be
+//! allowed explicitly.
+
+use std::fmt;
+use std::net::IpAddr;
+use std::str::FromStr;
+
+use tc_error::*;
+use tcgeneric::TCPathBuf;
+
+use crate::scalar::{LinkAddress, LinkHost};
+use crate::txn::Txn;
+
+/// A network which ops may send requests to, optionally only ops of the given cluster.
+///
+/// Parsed from a string of the form `[<cluster path>,]<address>[/<prefix length>]`,
+/// like "10.0.0.0/8" or "/app/billing,192.168.1.10".
+#[derive(Clone)]
+pub struct EgressRule {
+    cluster: Option<TCPathBuf>,
+    network: IpAddr,
+    prefix_len: u8,
+}
+
+impl EgressRule {
+    fn allows(&self, address: &IpAddr) -> bool {
+        match (self.network, address) {
+            (IpAddr::V4(network), IpAddr::V4(address)) => {
+                let shift = 32 - u32::from(self.prefix_len);
+                u32::from(network).checked_shr(shift).unwrap_or(0)
+                    == u32::from(*address).checked_shr(shift).unwrap_or(0)
+            }
+            (IpAddr::V6(network), IpAddr::V6(address)) => {
+                let shift = 128 - u32::from(self.prefix_len);
+                u128::from(network).checked_shr(shift).unwrap_or(0)
+                    == u128::from(*address).checked_shr(shift).unwrap_or(0)
+            }
+            _ => false,
+        }
+    }
+}
+
+impl FromStr for EgressRule {
+    type Err = TCError;
+
+    fn from_str(s: &str) -> TCResult<Self> {
+        let (cluster, network) = match s.find(',') {
+            Some(i) => (Some(s[..i].trim().parse()?), s[i + 1..].trim()),
+            None => (None, s.trim()),
+        };
+
+        let (network, prefix_len) = match network.find('/') {
+            Some(i) => (&network[..i], Some(&network[i + 1..])),
+            None => (network, None),
+        };
+
+        let network: IpAddr = network
+            .parse()
+            .map_err(|e| TCError::bad_request("invalid egress network address", e))?;
+
+        let max_prefix_len = if network.is_ipv4() { 32 } else { 128 };
+        let prefix_len = match prefix_len {
+            Some(prefix_len) => prefix_len
+                .parse()
+                .map_err(|e| TCError::bad_request("invalid egress network prefix length", e))?,
+            None => max_prefix_len,
+        };
+
+        if prefix_len > max_prefix_len {
+            return Err(TCError::bad_request(
+                "egress network prefix length is too long",
+                prefix_len,
+            ));
+        }
+
+        Ok(Self {
+            cluster,
+            network,
+            prefix_len,
+        })
+    }
+}
+
+impl fmt::Debug for EgressRule {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if let Some(cluster) = &self.cluster {
+            write!(f, "{}: ", cluster)?;
+        }
+
+        write!(f, "{}/{}", self.network, self.prefix_len)
+    }
+}
+
+/// The egress policy of a [`super::Gateway`].
+pub(crate) struct Egress {
+    rules: Vec<EgressRule>,
+}
+
+impl Egress {
+    pub fn new(rules: Vec<EgressRule>) -> Self {
+        Self { rules }
+    }
+
+    /// Return a "policy violation" error unless the given `txn` may send a request to `host`.
+    pub fn check(&self, txn: &Txn, root: &LinkHost, host: &LinkHost) -> TCResult<()> {
+        if self.rules.is_empty() {
+            return Ok(());
+        }
+
+        let mut clusters = Vec::new();
+        for (link, _actor_id, _scopes) in txn.request().scopes().iter() {
+            match link.host() {
+                Some(participant) if participant == host => return Ok(()),
+                Some(participant) if participant == root => clusters.push(link.path()),
+                _ => {}
+            }
+        }
+
+        let address = address(host);
+        let allowed = self.rules.iter().any(|rule| {
+            let applies = match &rule.cluster {
+                Some(cluster) => clusters.iter().any(|path| path.starts_with(&cluster[..])),
+                None => true,
+            };
+
+            applies && rule.allows(&address)
+        });
+
+        if allowed {
+            Ok(())
+        } else {
+            Err(violation(host))
+        }
+    }
+
+    /// Return a "policy violation" error unless this host may send a request to `host` outside
+    /// of any transaction.
+    pub fn check_unscoped(&self, host: &LinkHost) -> TCResult<()> {
+        if self.rules.is_empty() {
+            return Ok(());
+        }
+
+        let address = address(host);
+        let allowed = self
+            .rules
+            .iter()
+            .any(|rule| rule.cluster.is_none() && rule.allows(&address));
+
+        if allowed {
+            Ok(())
+        } else {
+            Err(violation(host))
+        }
+    }
+}
+
+fn address(host: &LinkHost) -> IpAddr {
+    match host.address() {
+        LinkAddress::IPv4(address) => IpAddr::V4(*address),
+        LinkAddress::IPv6(address) => IpAddr::V6(*address),
+    }
+}
+
+fn violation(host: &LinkHost) -> TCError {
+    TCError::policy_violation(format!(
+        "the egress policy of this host does not allow a request to {}",
+        host
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test::TestHost;
+
+    use super::*;
+
+    #[test]
+    fn test_rule() -> TCResult<()> {
+        let rule: EgressRule = "10.0.0.0/8".parse()?;
+        assert!(rule.cluster.is_none());
+        assert!(rule.allows(&"10.1.2.3".parse().unwrap()));
+        assert!(!rule.allows(&"11.0.0.1".parse().unwrap()));
+        assert!(!rule.allows(&"::1".parse().unwrap()));
+
+        let rule: EgressRule = "/app/billing, 192.168.1.10".parse()?;
+        assert_eq!(rule.cluster, Some("/app/billing".parse()?));
+        assert!(rule.allows(&"192.168.1.10".parse().unwrap()));
+        assert!(!rule.allows(&"192.168.1.11".parse().unwrap()));
+
+        let rule: EgressRule = "0.0.0.0/0".parse()?;
+        assert!(rule.allows(&"203.0.113.1".parse().unwrap()));
+
+        let rule: EgressRule = "fd00::/8".parse()?;
+        assert!(rule.allows(&"fd12::1".parse().unwrap()));
+        assert!(!rule.allows(&"fe80::1".parse().unwrap()));
+
+        assert!("10.0.0.0/33".parse::<EgressRule>().is_err());
+        assert!("10.0.0.0/x".parse::<EgressRule>().is_err());
+        assert!("example.com".parse::<EgressRule>().is_err());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_check() -> TCResult<()> {
+        let host = TestHost::new(vec![]).await?;
+        let txn = host.new_txn(false).await?;
+
+        let root: LinkHost = "http://127.0.0.1:8702".parse()?;
+        let private: LinkHost = "http://10.0.0.1:8702".parse()?;
+        let billing: LinkHost = "http://192.168.1.10:8702".parse()?;
+
+        // with no rules, any host is allowed
+        Egress::new(vec![]).check(&txn, &root, &billing)?;
+
+        let egress = Egress::new(vec![
+            "10.0.0.0/8".parse()?,
+            "/app/billing,192.168.1.10".parse()?,
+        ]);
+
+        egress.check(&txn, &root, &private)?;
+
+        // a cluster's rule doesn't apply to a transaction which that cluster isn't part of
+        let err = egress.check(&txn, &root, &billing).unwrap_err();
+        assert_eq!(err.code(), ErrorType::PolicyViolation);
+
+        // nor to a request outside of any transaction
+        egress.check_unscoped(&private)?;
+        let err = egress.check_unscoped(&billing).unwrap_err();
+        assert_eq!(err.code(), ErrorType::PolicyViolation);
+        Egress::new(vec![]).check_unscoped(&billing)?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,534 @@
+//! The keys which this host uses to sign auth tokens, and the issuer keys which it trusts.
+//!
+//! Each key has a key ID, which is the actor ID of the claims it signs, so that a token is
+//! always verified with the key which signed it. A GET request to `/sbin/keys` lists this host's
+//! own keys, newest first. A POST request to `/sbin/keys` rotates the signing key, and returns the
+//! ID of the new key; older keys are still used to verify the tokens which they signed until
+//! they're retired.
+//!
+//! A PUT request to `/sbin/keys` updates the key ring:
+//!  - with a key ID as the key and no value, it retires one of this host's own keys
+//!  - with a `(host, key ID)` tuple as the key and a public key as the value, it trusts a key of
+//!    the given issuer, or stops trusting it if the value is `None`
+//!
+//! Only a request with the `/sbin/keys` scope from a trusted issuer key may update the key ring,
+//! so at least one trusted issuer must be configured at startup to allow rotation.
+//!
+//! An update is staged until its transaction commits. If the transaction has an owner, the key
+//! ring is committed along with the other dependencies of the owner, which sends a commit message
+//! to `/sbin/keys/commit`; otherwise it's committed as soon as the update is staged.
+//!
+//! The key ring is not persisted. This host generates a new signing key each time it starts, so
+//! a token signed before a restart can't be verified after it, and any issuer key trusted at
+//! runtime must be trusted again, or configured at startup with `--trusted_key`.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::str::FromStr;
+use std::sync::{Arc, PoisonError, RwLock};
+
+use bytes::Bytes;
+use log::{info, warn};
+use safecast::TryCastInto;
+use uuid::Uuid;
+
+use tc_error::*;
+use tc_transact::{Transaction, TxnId};
+use tcgeneric::{path_label, NetworkTime, PathLabel, Tuple};
+
+use crate::scalar::{Link, Value};
+use crate::state::State;
+use crate::txn::{Actor, Scope, Txn};
+
+use super::Gateway;
+
+/// The path of the key ring.
+pub(crate) const KEYS: PathLabel = path_label(&["sbin", "keys"]);
+
+/// The path to which the owner of a transaction sends a message to commit the key ring.
+pub(crate) const COMMIT_KEYS: PathLabel = path_label(&["sbin", "keys", "commit"]);
+
+/// The public key of a trusted issuer of auth tokens.
+///
+/// Parsed from a string of the form `<host link>,<key ID>,<base64-encoded public key>`.
+#[derive(Clone)]
+pub struct TrustedKey {
+    host: Link,
+    kid: String,
+    public_key: Bytes,
+}
+
+impl FromStr for TrustedKey {
+    type Err = TCError;
+
+    fn from_str(s: &str) -> TCResult<Self> {
+        let mut parts = s.splitn(3, ',');
+        let (host, kid, public_key) = match (parts.next(), parts.next(), parts.next()) {
+            (Some(host), Some(kid), Some(public_key)) => (host, kid, public_key),
+            _ => {
+                return Err(TCError::bad_request(
+                    "expected a trusted key of the form <host>,<key ID>,<public key>, not",
+                    s,
+                ))
+            }
+        };
+
+        let public_key = base64::decode(public_key.trim())
+            .map_err(|e| TCError::bad_request("invalid trusted public key", e))?;
+
+        Ok(Self {
+            host: host.trim().parse()?,
+            kid: kid.trim().to_string(),
+            public_key: public_key.into(),
+        })
+    }
+}
+
+impl fmt::Debug for TrustedKey {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "trusted key {} of {}", self.kid, self.host)
+    }
+}
+
+// a change to the key ring
+enum Change {
+    Rotate(Arc<Actor>),
+    Retire(Value),
+    Trust((Link, String), Option<Bytes>),
+}
+
+// the changes staged by a transaction which hasn't committed yet, in order, and when it expires
+struct Staged {
+    expires: NetworkTime,
+    changes: Vec<Change>,
+}
+
+/// This host's own signing keys, and the issuer keys which it trusts.
+pub(crate) struct Keyring {
+    // the last key is the current signing key
+    own: RwLock<Vec<Arc<Actor>>>,
+    trusted: RwLock<HashMap<(Link, String), Bytes>>,
+    staged: RwLock<HashMap<TxnId, Staged>>,
+}
+
+impl Keyring {
+    pub fn new(trusted: Vec<TrustedKey>) -> Self {
+        let trusted = trusted
+            .into_iter()
+            .map(|key| ((key.host, key.kid), key.public_key))
+            .collect();
+
+        Self {
+            own: RwLock::new(vec![Arc::new(Actor::new(new_kid()))]),
+            trusted: RwLock::new(trusted),
+            staged: RwLock::new(HashMap::new()),
+        }
+    }
+
+    /// The key which this host currently uses to sign auth tokens.
+    pub fn signing(&self) -> Arc<Actor> {
+        let own = self.own.read().unwrap_or_else(PoisonError::into_inner);
+        own.last().expect("signing key").clone()
+    }
+
+    /// The public key with the given ID, or the current signing key if `kid` is `None`.
+    pub fn public_key(&self, kid: &Value) -> Option<Bytes> {
+        let own = self.own.read().unwrap_or_else(PoisonError::into_inner);
+        let actor = if kid.is_none() {
+            own.last()
+        } else {
+            own.iter().find(|actor| actor.id() == kid)
+        };
+
+        actor.map(|actor| Bytes::copy_from_slice(actor.public_key().as_bytes()))
+    }
+
+    /// The key to verify a claim signed by the given `host` with the given key ID, if it's known.
+    pub fn resolve(&self, host: &Link, kid: &Value, local: bool) -> TCResult<Option<Actor>> {
+        let public_key = if local {
+            self.public_key(kid)
+        } else if let Some(kid) = kid_of(kid) {
+            let trusted = self.trusted.read().unwrap_or_else(PoisonError::into_inner);
+            trusted.get(&(host.clone(), kid.to_string())).cloned()
+        } else {
+            None
+        };
+
+        if let Some(public_key) = public_key {
+            Actor::with_public_key(kid.clone(), &public_key)
+                .map(Some)
+                .map_err(TCError::internal)
+        } else {
+            Ok(None)
+        }
+    }
+
+    /// List this host's own keys, newest first.
+    pub fn get(&self, key: Value) -> TCResult<State> {
+        if key.is_some() {
+            return Err(TCError::bad_request("the key ring has no key, not", key));
+        }
+
+        let own = self.own.read().unwrap_or_else(PoisonError::into_inner);
+        let keys = own
+            .iter()
+            .rev()
+            .map(|actor| {
+                let public_key = Bytes::copy_from_slice(actor.public_key().as_bytes());
+                Value::Tuple(vec![actor.id().clone(), Value::from(public_key)].into())
+            })
+            .collect::<Tuple<Value>>();
+
+        Ok(State::from(Value::Tuple(keys)))
+    }
+
+    /// Stage the retirement of one of this host's own keys, or an update to the set of trusted
+    /// issuer keys, until the given `txn` commits.
+    pub fn put(&self, txn: &Txn, key: Value, value: State) -> TCResult<()> {
+        self.authorize(txn, &KEYS.into())?;
+
+        let value: Value =
+            value.try_cast_into(|s| TCError::bad_request("invalid public key", s))?;
+
+        let change = match key {
+            Value::Tuple(key) => {
+                let (host, kid): (Link, Value) = Value::Tuple(key)
+                    .try_cast_into(|k| TCError::bad_request("invalid trusted key ID", k))?;
+
+                let kid = kid_of(&kid)
+                    .ok_or_else(|| TCError::bad_request("invalid key ID", &kid))?
+                    .to_string();
+
+                if value.is_none() {
+                    Change::Trust((host, kid), None)
+                } else {
+                    let public_key: Bytes =
+                        value.try_cast_into(|v| TCError::bad_request("invalid public key", v))?;
+
+                    Actor::with_public_key(Value::None, &public_key)
+                        .map_err(|e| TCError::bad_request("invalid public key", e))?;
+
+                    Change::Trust((host, kid), Some(public_key))
+                }
+            }
+            kid if value.is_none() => {
+                // the keys as of this transaction, including any rotation it's already staged
+                let own = self.own_keys(txn.id());
+                match own.iter().position(|actor| actor.id() == &kid) {
+                    Some(i) if i + 1 == own.len() => {
+                        return Err(TCError::bad_request(
+                            "cannot retire the current signing key",
+                            kid,
+                        ))
+                    }
+                    Some(_) => Change::Retire(kid),
+                    None => return Err(TCError::not_found(format!("key {}", kid))),
+                }
+            }
+            kid => {
+                return Err(TCError::bad_request(
+                    "cannot set the public key of this host's own key",
+                    kid,
+                ))
+            }
+        };
+
+        self.stage(*txn.id(), txn.request().expires()?, change);
+        Ok(())
+    }
+
+    /// Stage the rotation of this host's signing key until the given `txn` commits, and return
+    /// the ID of the new key.
+    pub fn rotate(&self, txn: &Txn, params: State) -> TCResult<State> {
+        self.authorize(txn, &KEYS.into())?;
+
+        if !params.is_none() {
+            return Err(TCError::bad_request(
+                "rotating the signing key takes no parameters, not",
+                params,
+            ));
+        }
+
+        let actor = Arc::new(Actor::new(new_kid()));
+        let kid = actor.id().clone();
+
+        self.stage(*txn.id(), txn.request().expires()?, Change::Rotate(actor));
+        Ok(State::from(kid))
+    }
+
+    // this host's own keys, with the rotations and retirements staged by the given transaction
+    fn own_keys(&self, txn_id: &TxnId) -> Vec<Arc<Actor>> {
+        let mut own = self
+            .own
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clone();
+
+        let staged = self.staged.read().unwrap_or_else(PoisonError::into_inner);
+        if let Some(staged) = staged.get(txn_id) {
+            for change in &staged.changes {
+                match change {
+                    Change::Rotate(actor) => own.push(actor.clone()),
+                    Change::Retire(kid) => own.retain(|actor| actor.id() != kid),
+                    Change::Trust(..) => {}
+                }
+            }
+        }
+
+        own
+    }
+
+    // stage a change until the given transaction commits, and forget the changes of any
+    // transaction which has expired without committing
+    fn stage(&self, txn_id: TxnId, expires: NetworkTime, change: Change) {
+        let now = Gateway::time();
+        let mut staged = self.staged.write().unwrap_or_else(PoisonError::into_inner);
+        staged.retain(|_, staged| staged.expires > now);
+
+        staged
+            .entry(txn_id)
+            .or_insert_with(|| Staged {
+                expires,
+                changes: Vec::new(),
+            })
+            .changes
+            .push(change);
+    }
+
+    /// Apply the changes staged by the given transaction, if any, in the order they were staged.
+    pub fn commit(&self, txn_id: &TxnId) {
+        let staged = self
+            .staged
+            .write()
+            .unwrap_or_else(PoisonError::into_inner)
+            .remove(txn_id);
+
+        let staged = match staged {
+            Some(staged) => staged,
+            None => return,
+        };
+
+        let mut own = self.own.write().unwrap_or_else(PoisonError::into_inner);
+        let mut trusted = self.trusted.write().unwrap_or_else(PoisonError::into_inner);
+
+        for change in staged.changes {
+            match change {
+                Change::Rotate(actor) => {
+                    info!("rotating signing key to {}", actor.id());
+                    own.push(actor);
+                }
+                Change::Retire(kid) => match own.iter().position(|actor| actor.id() == &kid) {
+                    // another transaction may have rotated or retired a key since this one was
+                    // staged, but the current signing key is never retired
+                    Some(i) if i + 1 < own.len() => {
+                        info!("retiring key {}", kid);
+                        own.remove(i);
+                    }
+                    _ => warn!("not retiring key {}", kid),
+                },
+                Change::Trust((host, kid), Some(public_key)) => {
+                    info!("trusting key {} of {}", kid, host);
+                    trusted.insert((host, kid), public_key);
+                }
+                Change::Trust((host, kid), None) => {
+                    info!("no longer trusting key {} of {}", kid, host);
+                    trusted.remove(&(host, kid));
+                }
+            }
+        }
+    }
+
+    /// Return `Unauthorized` unless a trusted issuer key granted the given `scope` to the `txn`.
+    pub fn authorize(&self, txn: &Txn, scope: &Scope) -> TCResult<()> {
+        let trusted = self.trusted.read().unwrap_or_else(PoisonError::into_inner);
+
+        for (host, actor_id, scopes) in txn.request().scopes().iter() {
+            if let Some(kid) = kid_of(actor_id) {
+                if trusted.contains_key(&(host.clone(), kid.to_string())) && scopes.contains(scope)
+                {
+                    return Ok(());
+                }
+            }
+        }
+
+        Err(TCError::unauthorized(format!(
+            "no trusted issuer authorized the required scope \"{}\"",
+            scope
+        )))
+    }
+}
+
+fn kid_of(kid: &Value) -> Option<&str> {
+    match kid {
+        Value::String(kid) => Some(kid),
+        _ => None,
+    }
+}
+
+fn new_kid() -> Value {
+    Value::String(Uuid::new_v4().to_string().into())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::convert::TryFrom;
+
+    use crate::test::TestHost;
+
+    use super::*;
+
+    fn public_key(actor: &Actor) -> Bytes {
+        Bytes::copy_from_slice(actor.public_key().as_bytes())
+    }
+
+    fn expires() -> NetworkTime {
+        Gateway::time() + std::time::Duration::from_secs(30)
+    }
+
+    #[test]
+    fn test_trusted_key() -> TCResult<()> {
+        let issuer = Actor::new(new_kid());
+        let encoded = format!(
+            "http://10.0.0.1:8702, kid-1, {}",
+            base64::encode(public_key(&issuer))
+        );
+
+        let key: TrustedKey = encoded.parse()?;
+        assert_eq!(key.kid, "kid-1");
+        assert_eq!(key.host, "http://10.0.0.1:8702".parse::<Link>()?);
+        assert_eq!(key.public_key, public_key(&issuer));
+
+        assert!("http://10.0.0.1:8702,kid-1".parse::<TrustedKey>().is_err());
+        assert!("http://10.0.0.1:8702,kid-1,???"
+            .parse::<TrustedKey>()
+            .is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_resolve() -> TCResult<()> {
+        let issuer = Actor::new(new_kid());
+        let host: Link = "http://10.0.0.1:8702".parse()?;
+        let kid = Value::String("kid-1".into());
+
+        let keyring = Keyring::new(vec![TrustedKey {
+            host: host.clone(),
+            kid: "kid-1".to_string(),
+            public_key: public_key(&issuer),
+        }]);
+
+        let signing = keyring.signing();
+        assert_eq!(keyring.public_key(&Value::None), Some(public_key(&signing)));
+        assert_eq!(keyring.public_key(signing.id()), Some(public_key(&signing)));
+        assert_eq!(keyring.public_key(&kid), None);
+
+        let actor = keyring.resolve(&host, &kid, false)?.expect("trusted key");
+        assert!(actor.id() == &kid);
+        assert_eq!(public_key(&actor), public_key(&issuer));
+
+        // a trusted key is only trusted for its own issuer, and never for a local claim
+        let other: Link = "http://10.0.0.2:8702".parse()?;
+        assert!(keyring.resolve(&other, &kid, false)?.is_none());
+        assert!(keyring.resolve(&host, &kid, true)?.is_none());
+
+        let actor = keyring
+            .resolve(&host, signing.id(), true)?
+            .expect("own key");
+        assert_eq!(public_key(&actor), public_key(&signing));
+
+        match keyring.get(Value::None)? {
+            State::Scalar(scalar) => {
+                let keys = Value::try_from(scalar)?;
+                let expected = Value::Tuple(
+                    vec![Value::Tuple(
+                        vec![signing.id().clone(), Value::from(public_key(&signing))].into(),
+                    )]
+                    .into(),
+                );
+
+                assert!(keys == expected);
+            }
+            other => panic!("expected a list of keys but found {}", other),
+        }
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_unauthorized() -> TCResult<()> {
+        let host = TestHost::new(vec![]).await?;
+        let txn = host.new_txn(true).await?;
+
+        let keyring = Keyring::new(vec![]);
+        let signing = keyring.signing();
+
+        let err = keyring
+            .rotate(&txn, State::default())
+            .map(|_| ())
+            .unwrap_err();
+        assert_eq!(err.code(), ErrorType::Unauthorized);
+
+        let err = keyring
+            .put(&txn, signing.id().clone(), State::default())
+            .unwrap_err();
+        assert_eq!(err.code(), ErrorType::Unauthorized);
+
+        assert!(Arc::ptr_eq(&keyring.signing(), &signing));
+        assert!(keyring.staged.read().unwrap().is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_commit() -> TCResult<()> {
+        let keyring = Keyring::new(vec![]);
+        let old = keyring.signing();
+
+        let txn_id = TxnId::new(Gateway::time());
+
+        // a staged rotation isn't used to sign until its transaction commits, but the transaction
+        // which staged it may retire the old key
+        let new = Arc::new(Actor::new(new_kid()));
+        keyring.stage(txn_id, expires(), Change::Rotate(new.clone()));
+        assert!(Arc::ptr_eq(&keyring.signing(), &old));
+        assert_eq!(keyring.own_keys(&txn_id).len(), 2);
+
+        keyring.stage(txn_id, expires(), Change::Retire(old.id().clone()));
+        assert_eq!(keyring.own_keys(&txn_id).len(), 1);
+        assert!(keyring.public_key(old.id()).is_some());
+
+        let host: Link = "http://10.0.0.1:8702".parse()?;
+        let kid = Value::String("kid-1".into());
+        let issuer = Actor::new(kid.clone());
+        let trusted = Change::Trust(
+            (host.clone(), "kid-1".to_string()),
+            Some(public_key(&issuer)),
+        );
+
+        keyring.stage(txn_id, expires(), trusted);
+        assert!(keyring.resolve(&host, &kid, false)?.is_none());
+
+        keyring.commit(&txn_id);
+        assert!(Arc::ptr_eq(&keyring.signing(), &new));
+        assert!(keyring.public_key(old.id()).is_none());
+        assert!(keyring.resolve(&host, &kid, false)?.is_some());
+        assert!(keyring.staged.read().unwrap().is_empty());
+
+        // committing a transaction which staged nothing changes nothing
+        keyring.commit(&TxnId::new(Gateway::time()));
+        assert!(Arc::ptr_eq(&keyring.signing(), &new));
+
+        // the changes of an expired transaction are forgotten when another is staged
+        let expired = TxnId::new(NetworkTime::from_nanos(1));
+        keyring.stage(expired, Gateway::time(), Change::Retire(new.id().clone()));
+        keyring.stage(
+            txn_id,
+            expires(),
+            Change::Trust((host, "kid-1".to_string()), None),
+        );
+        assert!(!keyring.staged.read().unwrap().contains_key(&expired));
+
+        Ok(())
+    }
+}
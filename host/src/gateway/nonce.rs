@@ -0,0 +1,295 @@
+//! Replay protection for auth tokens.
+//!
+//! The signature of a token serves as its nonce. A token is bound to the first transaction in
+//! which it's used to request a mutation, until it expires, so that an intercepted token can't be
+//! replayed to request a mutation in a new transaction. Using the same token again within the
+//! same transaction is allowed, since that's how the hosts which participate in a transaction
+//! call one another, but only until the transaction is committed at this host, since the ID of
+//! the transaction is supplied by the caller and an intercepted request could otherwise be
+//! replayed unchanged.
+
+use std::collections::hash_map::RandomState;
+use std::collections::{HashMap, VecDeque};
+use std::hash::BuildHasher;
+use std::sync::{Mutex, PoisonError};
+use std::time::SystemTime;
+
+use log::warn;
+
+use tc_error::*;
+use tc_transact::TxnId;
+
+// the maximum number of unexpired tokens to track, after which a new token is turned away
+const MAX_NONCES: usize = 100_000;
+
+/// The set of auth tokens which have already been used to request a mutation.
+pub(crate) struct Nonces {
+    capacity: usize,
+    hasher: RandomState,
+    seen: Mutex<Seen>,
+}
+
+struct Nonce {
+    txn_id: TxnId,
+    expires: SystemTime,
+    finished: bool,
+}
+
+#[derive(Default)]
+struct Seen {
+    txns: HashMap<u64, Nonce>,
+    by_txn: HashMap<TxnId, Vec<u64>>,
+    order: VecDeque<u64>,
+}
+
+impl Nonces {
+    pub fn new() -> Self {
+        Self::with_capacity(MAX_NONCES)
+    }
+
+    fn with_capacity(capacity: usize) -> Self {
+        Self {
+            capacity,
+            hasher: RandomState::new(),
+            seen: Mutex::new(Seen::default()),
+        }
+    }
+
+    /// Record a mutation request in the given transaction, authorized by the given `token`, or
+    /// return an "unauthorized" error if the token was already used in a different transaction,
+    /// or in the same transaction after it finished.
+    ///
+    /// A token is never forgotten before it expires, so if there are too many unexpired tokens
+    /// to track, a new token is turned away with an "unavailable" error, which may be retried.
+    pub fn check(&self, txn_id: &TxnId, token: &str, expires: SystemTime) -> TCResult<()> {
+        let nonce = self.nonce(token);
+        let now = SystemTime::now();
+
+        let mut seen = self.seen.lock().unwrap_or_else(PoisonError::into_inner);
+        seen.expire(now);
+
+        match seen.txns.get(&nonce) {
+            Some(first) if &first.txn_id == txn_id && first.finished => Err(TCError::unauthorized(
+                "this auth token was already used in a transaction which has finished",
+            )),
+            Some(first) if &first.txn_id == txn_id => Ok(()),
+            Some(_) => Err(TCError::unauthorized(
+                "this auth token was already used in another transaction",
+            )),
+            None => {
+                if seen.order.len() >= self.capacity {
+                    seen.expire_all(now);
+                }
+
+                if seen.order.len() >= self.capacity {
+                    warn!("too many unexpired auth tokens to track a new one");
+
+                    return Err(TCError::unavailable(
+                        "too many auth tokens are in use, try again later",
+                    ));
+                }
+
+                let first = Nonce {
+                    txn_id: *txn_id,
+                    expires,
+                    finished: false,
+                };
+
+                seen.txns.insert(nonce, first);
+                seen.by_txn.entry(*txn_id).or_default().push(nonce);
+                seen.order.push_back(nonce);
+                Ok(())
+            }
+        }
+    }
+
+    /// Record that the given transaction has finished, so that the tokens used to request a
+    /// mutation in it can't be used again.
+    pub fn finish(&self, txn_id: &TxnId) {
+        let mut seen = self.seen.lock().unwrap_or_else(PoisonError::into_inner);
+
+        if let Some(nonces) = seen.by_txn.remove(txn_id) {
+            for nonce in nonces {
+                if let Some(first) = seen.txns.get_mut(&nonce) {
+                    first.finished = true;
+                }
+            }
+        }
+    }
+
+    fn nonce(&self, token: &str) -> u64 {
+        // the signature is the last segment of a JSON web token
+        let signature = token.rsplit('.').next().unwrap_or(token);
+
+        self.hasher.hash_one(signature)
+    }
+}
+
+impl Seen {
+    // forget every token at the front of the queue which has expired
+    fn expire(&mut self, now: SystemTime) {
+        while let Some(nonce) = self.order.front() {
+            match self.txns.get(nonce) {
+                Some(first) if first.expires > now => break,
+                _ => {
+                    let nonce = *nonce;
+                    self.order.pop_front();
+                    self.remove(nonce);
+                }
+            }
+        }
+    }
+
+    // forget every token which has expired, wherever it is in the queue
+    fn expire_all(&mut self, now: SystemTime) {
+        let txns = &self.txns;
+        let live = |nonce: &u64| txns.get(nonce).is_some_and(|first| first.expires > now);
+
+        let expired: Vec<u64> = self.order.iter().filter(|n| !live(n)).copied().collect();
+        self.order.retain(live);
+
+        for nonce in expired {
+            self.remove(nonce);
+        }
+    }
+
+    fn remove(&mut self, nonce: u64) {
+        let txn_id = match self.txns.remove(&nonce) {
+            Some(first) => first.txn_id,
+            None => return,
+        };
+
+        if let Some(nonces) = self.by_txn.get_mut(&txn_id) {
+            nonces.retain(|n| n != &nonce);
+            if nonces.is_empty() {
+                self.by_txn.remove(&txn_id);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use tcgeneric::NetworkTime;
+
+    use super::*;
+
+    #[test]
+    fn test_check() -> TCResult<()> {
+        let nonces = Nonces::new();
+        let first = TxnId::new(NetworkTime::from_nanos(1));
+        let second = TxnId::new(NetworkTime::from_nanos(2));
+        let expires = SystemTime::now() + Duration::from_secs(30);
+
+        nonces.check(&first, "header.claims.signature", expires)?;
+
+        // a token may be used again in the same transaction, but not in another
+        nonces.check(&first, "header.claims.signature", expires)?;
+        let err = nonces
+            .check(&second, "other-header.claims.signature", expires)
+            .unwrap_err();
+        assert_eq!(err.code(), ErrorType::Unauthorized);
+
+        nonces.check(&second, "header.claims.other-signature", expires)?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_expire() -> TCResult<()> {
+        let nonces = Nonces::new();
+        let first = TxnId::new(NetworkTime::from_nanos(1));
+        let second = TxnId::new(NetworkTime::from_nanos(2));
+
+        // an expired token is forgotten, so it's rejected by authentication rather than here
+        let expired = SystemTime::now() - Duration::from_secs(1);
+        nonces.check(&first, "token", expired)?;
+        nonces.check(&second, "token", expired)?;
+
+        let seen = nonces.seen.lock().unwrap();
+        assert_eq!(seen.order.len(), 1);
+        assert_eq!(seen.txns.len(), 1);
+        assert_eq!(seen.by_txn.len(), 1);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_replay_after_finish() -> TCResult<()> {
+        let nonces = Nonces::new();
+        let txn_id = TxnId::new(NetworkTime::from_nanos(1));
+        let expires = SystemTime::now() + Duration::from_secs(30);
+        let token = "header.claims.signature";
+
+        nonces.check(&txn_id, token, expires)?;
+        nonces.check(&txn_id, token, expires)?;
+        nonces.finish(&txn_id);
+
+        // replaying the identical request, with the same transaction ID, is rejected
+        let err = nonces.check(&txn_id, token, expires).unwrap_err();
+        assert_eq!(err.code(), ErrorType::Unauthorized);
+
+        // a different token may still be used in a new transaction
+        let next = TxnId::new(NetworkTime::from_nanos(2));
+        nonces.check(&next, "header.claims.other-signature", expires)?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_full() -> TCResult<()> {
+        let nonces = Nonces::with_capacity(2);
+        let txn_ids: Vec<TxnId> = (1..5)
+            .map(|n| TxnId::new(NetworkTime::from_nanos(n)))
+            .collect();
+
+        let expires = SystemTime::now() + Duration::from_secs(30);
+
+        nonces.check(&txn_ids[0], "header.claims.first", expires)?;
+        nonces.check(&txn_ids[1], "header.claims.second", expires)?;
+
+        // a new token is turned away, rather than forgetting one which hasn't expired
+        let err = nonces
+            .check(&txn_ids[2], "header.claims.third", expires)
+            .unwrap_err();
+        assert_eq!(err.code(), ErrorType::Unavailable);
+
+        // so the oldest token still can't be replayed in another transaction
+        let err = nonces
+            .check(&txn_ids[3], "header.claims.first", expires)
+            .unwrap_err();
+        assert_eq!(err.code(), ErrorType::Unauthorized);
+
+        // and a token already tracked may still be used in its own transaction
+        nonces.check(&txn_ids[1], "header.claims.second", expires)?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_full_expire_all() -> TCResult<()> {
+        let nonces = Nonces::with_capacity(2);
+        let txn_ids: Vec<TxnId> = (1..4)
+            .map(|n| TxnId::new(NetworkTime::from_nanos(n)))
+            .collect();
+
+        let later = SystemTime::now() + Duration::from_secs(30);
+        let soon = SystemTime::now() + Duration::from_millis(10);
+
+        // the second token expires first, behind one which hasn't expired
+        nonces.check(&txn_ids[0], "header.claims.first", later)?;
+        nonces.check(&txn_ids[1], "header.claims.second", soon)?;
+        std::thread::sleep(Duration::from_millis(20));
+
+        nonces.check(&txn_ids[2], "header.claims.third", later)?;
+
+        let seen = nonces.seen.lock().unwrap();
+        assert_eq!(seen.order.len(), 2);
+        assert_eq!(seen.txns.len(), 2);
+        assert!(!seen.by_txn.contains_key(&txn_ids[1]));
+
+        Ok(())
+    }
+}
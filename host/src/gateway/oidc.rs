@@ -0,0 +1,669 @@
+//! Federation with an external OpenID Connect (OIDC) issuer.
+//!
+//! A bearer token issued by the configured OIDC issuer is an ID token rather than a Tinychain
+//! auth token. It's validated with the issuer's published keys (its JWKS), and its claims are
+//! mapped to Tinychain scopes, which this host then grants in a token of its own.
+//!
+//! The mapping is stored under `/sbin/auth`. A GET request lists its rules. A PUT request with a
+//! `(claim, value)` key grants the given tuple of scopes to any ID token whose claim has the given
+//! value, or contains it if the claim is a list, like "groups"; a value of `None` removes the rule.
+//! Like the key ring, only a request with the `/sbin/auth` scope from a trusted issuer key may
+//! update the mapping. If a mapping file is configured, the mapping is loaded from it at startup
+//! and saved to it after each update.
+//!
+//! Like the secrets store, an update is staged until its transaction commits. If the transaction
+//! has an owner, the mapping is committed along with the other dependencies of the owner;
+//! otherwise it's committed as soon as the update is staged.
+
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::{PoisonError, RwLock};
+use std::time::{Duration, Instant};
+
+use futures::TryFutureExt;
+use hyper::client::HttpConnector;
+use hyper::{Body, Uri};
+use hyper_rustls::HttpsConnector;
+use jsonwebtoken::{Algorithm, DecodingKey, Validation};
+use log::{debug, info};
+use safecast::TryCastInto;
+use serde_json::Value as Json;
+
+use tc_error::*;
+use tc_transact::{Transaction, TxnId};
+use tcgeneric::{path_label, NetworkTime, PathLabel, TCPathBuf, Tuple};
+
+use crate::scalar::{Link, Value};
+use crate::state::State;
+use crate::txn::{Scope, Txn};
+
+use super::keys::Keyring;
+use super::Gateway;
+
+/// The path of the mapping from OIDC claims to Tinychain scopes.
+pub(crate) const AUTH: PathLabel = path_label(&["sbin", "auth"]);
+
+// how long to cache the issuer's keys before fetching them again
+const JWKS_TTL: Duration = Duration::from_secs(3600);
+
+// the minimum interval between fetches of the issuer's keys, when a token has an unknown key ID
+const JWKS_MIN_REFRESH: Duration = Duration::from_secs(60);
+
+// the scopes granted to an ID token with each (claim, value)
+type Mapping = HashMap<(String, String), Vec<Scope>>;
+
+/// An external OIDC issuer whose ID tokens this host trusts.
+#[derive(Clone, Debug)]
+pub struct Oidc {
+    issuer: String,
+    audience: String,
+    mapping: Option<(PathBuf, Mapping)>,
+}
+
+impl Oidc {
+    /// Trust ID tokens from the given `issuer`, like "https://accounts.example.com", which were
+    /// issued to the given `audience`, i.e. the client ID of this Tinychain deployment.
+    pub fn new(issuer: String, audience: String) -> Self {
+        Self {
+            issuer: issuer.trim_end_matches('/').to_string(),
+            audience,
+            mapping: None,
+        }
+    }
+
+    /// Persist the mapping of claims to scopes to the JSON file at `path`, starting with the
+    /// rules already stored there, if any.
+    pub async fn with_mapping(mut self, path: PathBuf) -> TCResult<Self> {
+        let mapping = match tokio::fs::read(&path).await {
+            Ok(contents) => parse(&contents)?,
+            Err(cause) if cause.kind() == std::io::ErrorKind::NotFound => HashMap::new(),
+            Err(cause) => return Err(file_error(&path, cause)),
+        };
+
+        self.mapping = Some((path, mapping));
+        Ok(self)
+    }
+}
+
+struct Jwks {
+    fetched: Instant,
+    keys: HashMap<String, (String, String)>,
+}
+
+// the rules staged by a transaction which hasn't committed yet, and when it expires
+struct Staged {
+    expires: NetworkTime,
+    changes: HashMap<(String, String), Option<Vec<Scope>>>,
+}
+
+/// Validates the ID tokens of an external OIDC issuer, and maps their claims to scopes.
+pub(crate) struct Federation {
+    oidc: Oidc,
+    client: hyper::Client<HttpsConnector<HttpConnector>, Body>,
+    jwks: RwLock<Option<Jwks>>,
+    path: Option<PathBuf>,
+    mapping: RwLock<Mapping>,
+    staged: RwLock<HashMap<TxnId, Staged>>,
+}
+
+impl Federation {
+    pub fn new(mut oidc: Oidc) -> Self {
+        let (path, mapping) = match oidc.mapping.take() {
+            Some((path, mapping)) => (Some(path), mapping),
+            None => (None, HashMap::new()),
+        };
+
+        Self {
+            oidc,
+            client: hyper::Client::builder().build(HttpsConnector::with_native_roots()),
+            jwks: RwLock::new(None),
+            path,
+            mapping: RwLock::new(mapping),
+            staged: RwLock::new(HashMap::new()),
+        }
+    }
+
+    /// Return `true` if the given bearer token claims to be an ID token of the OIDC issuer.
+    ///
+    /// This does not validate the token.
+    pub fn is_issued(&self, token: &str) -> bool {
+        unverified_issuer(token)
+            .map(|issuer| issuer.trim_end_matches('/') == self.oidc.issuer)
+            .unwrap_or(false)
+    }
+
+    /// Validate the given ID `token`, and return the scopes which its claims are mapped to.
+    pub async fn verify(&self, token: &str) -> TCResult<Vec<Scope>> {
+        let header = jsonwebtoken::decode_header(token)
+            .map_err(|e| TCError::unauthorized(format!("invalid ID token: {}", e)))?;
+
+        match header.alg {
+            Algorithm::RS256 | Algorithm::RS384 | Algorithm::RS512 => {}
+            Algorithm::PS256 | Algorithm::PS384 | Algorithm::PS512 => {}
+            other => {
+                return Err(TCError::unauthorized(format!(
+                    "unsupported ID token algorithm {:?}",
+                    other
+                )))
+            }
+        }
+
+        let kid = header
+            .kid
+            .ok_or_else(|| TCError::unauthorized("ID token has no key ID"))?;
+
+        let (n, e) = self.key(&kid).await?;
+
+        let mut validation = Validation::new(header.alg);
+        validation.iss = Some(self.oidc.issuer.clone());
+        validation.set_audience(&[&self.oidc.audience]);
+
+        let claims = jsonwebtoken::decode::<HashMap<String, Json>>(
+            token,
+            &DecodingKey::from_rsa_components(&n, &e),
+            &validation,
+        )
+        .map_err(|e| TCError::unauthorized(format!("invalid ID token: {}", e)))?
+        .claims;
+
+        debug!("validated ID token of {:?}", claims.get("sub"));
+
+        Ok(self.scopes(&claims))
+    }
+
+    // the scopes which the given validated claims are mapped to
+    fn scopes(&self, claims: &HashMap<String, Json>) -> Vec<Scope> {
+        let mapping = self.mapping.read().unwrap_or_else(PoisonError::into_inner);
+        let mut scopes = Vec::new();
+        for ((claim, value), granted) in mapping.iter() {
+            let matches = match claims.get(claim) {
+                Some(Json::String(actual)) => actual == value,
+                Some(Json::Array(actual)) => actual.iter().any(|v| v.as_str() == Some(value)),
+                _ => false,
+            };
+
+            if matches {
+                for scope in granted {
+                    if !scopes.contains(scope) {
+                        scopes.push(scope.clone());
+                    }
+                }
+            }
+        }
+
+        scopes
+    }
+
+    /// List the rules which map OIDC claims to scopes.
+    pub fn get(&self, key: Value) -> TCResult<State> {
+        if key.is_some() {
+            return Err(TCError::bad_request(
+                "the OIDC mapping has no key, not",
+                key,
+            ));
+        }
+
+        let mapping = self.mapping.read().unwrap_or_else(PoisonError::into_inner);
+        let rules = mapping
+            .iter()
+            .map(|((claim, value), scopes)| {
+                let key = Value::Tuple(
+                    vec![
+                        Value::String(claim.as_str().into()),
+                        Value::String(value.as_str().into()),
+                    ]
+                    .into(),
+                );
+
+                let scopes = scopes
+                    .iter()
+                    .cloned()
+                    .map(Link::from)
+                    .map(Value::from)
+                    .collect::<Tuple<Value>>();
+
+                Value::Tuple(vec![key, Value::Tuple(scopes)].into())
+            })
+            .collect::<Tuple<Value>>();
+
+        Ok(State::from(Value::Tuple(rules)))
+    }
+
+    /// Stage a rule granting the given tuple of scopes to ID tokens with the given
+    /// `(claim, value)`, or its removal if the `value` is `None`, until the given `txn` commits.
+    pub fn put(&self, txn: &Txn, keys: &Keyring, key: Value, value: State) -> TCResult<()> {
+        keys.authorize(txn, &AUTH.into())?;
+
+        let (claim, claim_value): (String, String) =
+            key.try_cast_into(|k| TCError::bad_request("invalid OIDC claim", k))?;
+
+        let scopes = if value.is_none() {
+            None
+        } else {
+            let scopes: Value =
+                value.try_cast_into(|v| TCError::bad_request("invalid scopes", v))?;
+
+            let scopes: Tuple<TCPathBuf> =
+                scopes.try_cast_into(|v| TCError::bad_request("invalid scopes", v))?;
+
+            Some(scopes.into_inner())
+        };
+
+        self.stage(
+            *txn.id(),
+            txn.request().expires()?,
+            (claim, claim_value),
+            scopes,
+        );
+
+        Ok(())
+    }
+
+    // stage a rule until the given transaction commits, and forget the rules of any transaction
+    // which has expired without committing
+    fn stage(
+        &self,
+        txn_id: TxnId,
+        expires: NetworkTime,
+        rule: (String, String),
+        scopes: Option<Vec<Scope>>,
+    ) {
+        let now = Gateway::time();
+        let mut staged = self.staged.write().unwrap_or_else(PoisonError::into_inner);
+        staged.retain(|_, staged| staged.expires > now);
+
+        staged
+            .entry(txn_id)
+            .or_insert_with(|| Staged {
+                expires,
+                changes: HashMap::new(),
+            })
+            .changes
+            .insert(rule, scopes);
+    }
+
+    /// Apply the rules staged by the given transaction, if any, and save the mapping.
+    pub async fn commit(&self, txn_id: &TxnId) -> TCResult<()> {
+        let staged = self
+            .staged
+            .write()
+            .unwrap_or_else(PoisonError::into_inner)
+            .remove(txn_id);
+
+        let staged = match staged {
+            Some(staged) => staged,
+            None => return Ok(()),
+        };
+
+        let contents = {
+            let mut mapping = self.mapping.write().unwrap_or_else(PoisonError::into_inner);
+            for ((claim, claim_value), scopes) in staged.changes {
+                match scopes {
+                    Some(scopes) => {
+                        info!("mapped OIDC claim {}={} to scopes", claim, claim_value);
+                        mapping.insert((claim, claim_value), scopes);
+                    }
+                    None => {
+                        info!("removed OIDC mapping for {}={}", claim, claim_value);
+                        mapping.remove(&(claim, claim_value));
+                    }
+                }
+            }
+
+            encode(&mapping)?
+        };
+
+        match &self.path {
+            Some(path) => save(path, &contents).await,
+            None => Ok(()),
+        }
+    }
+
+    // the RSA modulus and exponent of the issuer's key with the given ID
+    async fn key(&self, kid: &str) -> TCResult<(String, String)> {
+        let refresh = {
+            let jwks = self.jwks.read().unwrap_or_else(PoisonError::into_inner);
+            match &*jwks {
+                Some(jwks) => {
+                    if let Some(key) = jwks.keys.get(kid) {
+                        if jwks.fetched.elapsed() < JWKS_TTL {
+                            return Ok(key.clone());
+                        }
+                    }
+
+                    jwks.fetched.elapsed() >= JWKS_MIN_REFRESH
+                }
+                None => true,
+            }
+        };
+
+        if refresh {
+            let keys = self.fetch_jwks().await?;
+            *self.jwks.write().unwrap_or_else(PoisonError::into_inner) = Some(Jwks {
+                fetched: Instant::now(),
+                keys,
+            });
+        }
+
+        let jwks = self.jwks.read().unwrap_or_else(PoisonError::into_inner);
+        jwks.as_ref()
+            .and_then(|jwks| jwks.keys.get(kid))
+            .cloned()
+            .ok_or_else(|| TCError::unauthorized(format!("unknown ID token key {}", kid)))
+    }
+
+    async fn fetch_jwks(&self) -> TCResult<HashMap<String, (String, String)>> {
+        let discovery = format!("{}/.well-known/openid-configuration", self.oidc.issuer);
+        let discovery = self.fetch_json(&discovery).await?;
+        let jwks_uri = discovery["jwks_uri"]
+            .as_str()
+            .ok_or_else(|| TCError::bad_gateway("OIDC discovery document has no jwks_uri"))?;
+
+        let jwks = self.fetch_json(jwks_uri).await?;
+        let keys = jwks["keys"]
+            .as_array()
+            .ok_or_else(|| TCError::bad_gateway("OIDC JWKS has no keys"))?;
+
+        let keys = keys
+            .iter()
+            .filter(|key| key["kty"] == "RSA")
+            .filter_map(|key| {
+                let kid = key["kid"].as_str()?;
+                let n = key["n"].as_str()?;
+                let e = key["e"].as_str()?;
+                Some((kid.to_string(), (n.to_string(), e.to_string())))
+            })
+            .collect::<HashMap<_, _>>();
+
+        info!(
+            "fetched {} keys of OIDC issuer {}",
+            keys.len(),
+            self.oidc.issuer
+        );
+        Ok(keys)
+    }
+
+    async fn fetch_json(&self, url: &str) -> TCResult<Json> {
+        let uri: Uri = url
+            .parse()
+            .map_err(|e| TCError::bad_request("invalid OIDC URL", e))?;
+
+        let response = self
+            .client
+            .get(uri)
+            .await
+            .map_err(|e| TCError::bad_gateway(format!("unable to reach {}: {}", url, e)))?;
+
+        if !response.status().is_success() {
+            return Err(TCError::bad_gateway(format!(
+                "{} responded with {}",
+                url,
+                response.status()
+            )));
+        }
+
+        let body = hyper::body::to_bytes(response.into_body())
+            .await
+            .map_err(|e| TCError::bad_gateway(format!("error reading {}: {}", url, e)))?;
+
+        serde_json::from_slice(&body)
+            .map_err(|e| TCError::bad_gateway(format!("invalid JSON from {}: {}", url, e)))
+    }
+}
+
+fn encode(mapping: &Mapping) -> TCResult<Vec<u8>> {
+    let rules = mapping
+        .iter()
+        .map(|((claim, value), scopes)| {
+            let scopes = scopes.iter().map(|s| s.to_string()).collect::<Vec<_>>();
+            serde_json::json!({"claim": claim, "value": value, "scopes": scopes})
+        })
+        .collect::<Vec<Json>>();
+
+    serde_json::to_vec(&rules).map_err(TCError::internal)
+}
+
+fn parse(contents: &[u8]) -> TCResult<Mapping> {
+    let invalid = |cause: &dyn std::fmt::Display| {
+        TCError::internal(format!("invalid OIDC mapping file: {}", cause))
+    };
+
+    let rules: Vec<Json> = serde_json::from_slice(contents).map_err(|e| invalid(&e))?;
+
+    rules
+        .into_iter()
+        .map(|rule| {
+            let claim = rule["claim"].as_str().ok_or_else(|| invalid(&rule))?;
+            let value = rule["value"].as_str().ok_or_else(|| invalid(&rule))?;
+            let scopes = rule["scopes"]
+                .as_array()
+                .ok_or_else(|| invalid(&rule))?
+                .iter()
+                .map(|scope| {
+                    scope
+                        .as_str()
+                        .ok_or_else(|| invalid(&rule))
+                        .and_then(|scope| scope.parse())
+                })
+                .collect::<TCResult<Vec<Scope>>>()?;
+
+            Ok(((claim.to_string(), value.to_string()), scopes))
+        })
+        .collect()
+}
+
+// write the mapping to a temporary file, then move it into place
+async fn save(path: &PathBuf, contents: &[u8]) -> TCResult<()> {
+    let tmp = path.with_extension("tmp");
+    tokio::fs::write(&tmp, contents)
+        .map_err(|e| file_error(&tmp, e))
+        .await?;
+
+    tokio::fs::rename(&tmp, path)
+        .map_err(|e| file_error(path, e))
+        .await
+}
+
+fn file_error(path: &PathBuf, cause: std::io::Error) -> TCError {
+    TCError::internal(format!("unable to access {:?}: {}", path, cause))
+}
+
+// the issuer claimed by the given JSON web token, without validating it
+fn unverified_issuer(token: &str) -> Option<String> {
+    let payload = token.split('.').nth(1)?;
+    let payload = base64::decode_config(payload, base64::URL_SAFE_NO_PAD).ok()?;
+    let claims: Json = serde_json::from_slice(&payload).ok()?;
+    claims["iss"].as_str().map(String::from)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::SocketAddr;
+
+    use hyper::service::{make_service_fn, service_fn};
+    use hyper::Response;
+
+    use super::*;
+
+    fn token(claims: Json) -> String {
+        let payload = serde_json::to_vec(&claims).unwrap();
+        format!(
+            "header.{}.signature",
+            base64::encode_config(payload, base64::URL_SAFE_NO_PAD)
+        )
+    }
+
+    // serve an OIDC discovery document and JWKS on an unused local port
+    fn serve_jwks() -> SocketAddr {
+        let addr = ([127, 0, 0, 1], 0).into();
+        let server = hyper::Server::bind(&addr).serve(make_service_fn(|_| async {
+            Ok::<_, hyper::Error>(service_fn(|request: hyper::Request<Body>| async move {
+                let host = request.headers()[hyper::header::HOST].to_str().unwrap();
+                let body = match request.uri().path() {
+                    "/.well-known/openid-configuration" => {
+                        serde_json::json!({ "jwks_uri": format!("http://{}/jwks", host) })
+                    }
+                    "/jwks" => serde_json::json!({ "keys": [
+                        { "kty": "RSA", "kid": "kid-1", "n": "modulus", "e": "AQAB" },
+                        { "kty": "EC", "kid": "kid-2", "x": "x", "y": "y" },
+                    ]}),
+                    _ => Json::Null,
+                };
+
+                Ok::<_, hyper::Error>(Response::new(Body::from(body.to_string())))
+            }))
+        }));
+
+        let addr = server.local_addr();
+        tokio::spawn(server);
+        addr
+    }
+
+    #[test]
+    fn test_is_issued() {
+        let federation = Federation::new(Oidc::new(
+            "https://accounts.example.com/".to_string(),
+            "tinychain".to_string(),
+        ));
+
+        let issued = token(serde_json::json!({ "iss": "https://accounts.example.com" }));
+        assert!(federation.is_issued(&issued));
+
+        let other = token(serde_json::json!({ "iss": "https://other.example.com" }));
+        assert!(!federation.is_issued(&other));
+        assert!(!federation.is_issued("not a token"));
+    }
+
+    #[test]
+    fn test_scopes() {
+        let federation = Federation::new(Oidc::new(
+            "https://accounts.example.com".to_string(),
+            "tinychain".to_string(),
+        ));
+
+        let admin: Scope = "/admin".parse().unwrap();
+        let read: Scope = "/app/read".parse().unwrap();
+
+        {
+            let mut mapping = federation.mapping.write().unwrap();
+            let group = ("groups".to_string(), "admins".to_string());
+            mapping.insert(group, vec![admin.clone(), read.clone()]);
+            let email = ("email".to_string(), "a@example.com".to_string());
+            mapping.insert(email, vec![read.clone()]);
+        }
+
+        let claims: HashMap<String, Json> = serde_json::from_value(serde_json::json!({
+            "email": "a@example.com",
+            "groups": ["users", "admins"],
+        }))
+        .unwrap();
+
+        let scopes = federation.scopes(&claims);
+        assert_eq!(scopes.len(), 2);
+        assert!(scopes.contains(&admin));
+        assert!(scopes.contains(&read));
+
+        let claims: HashMap<String, Json> =
+            serde_json::from_value(serde_json::json!({ "email": "a@example.com" })).unwrap();
+        assert_eq!(federation.scopes(&claims), vec![read]);
+
+        let claims: HashMap<String, Json> =
+            serde_json::from_value(serde_json::json!({ "groups": "users" })).unwrap();
+        assert!(federation.scopes(&claims).is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_mapping_file() -> TCResult<()> {
+        let path = std::env::temp_dir().join(format!("oidc-mapping-{}.json", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+
+        let oidc = Oidc::new(
+            "https://accounts.example.com".to_string(),
+            "tinychain".to_string(),
+        );
+        let federation = Federation::new(oidc.clone().with_mapping(path.clone()).await?);
+        assert!(federation.mapping.read().unwrap().is_empty());
+
+        let read: Scope = "/app/read".parse().unwrap();
+        let rule = ("groups".to_string(), "users".to_string());
+        federation
+            .mapping
+            .write()
+            .unwrap()
+            .insert(rule.clone(), vec![read.clone()]);
+
+        let contents = encode(&federation.mapping.read().unwrap())?;
+        save(&path, &contents).await?;
+
+        let reloaded = Federation::new(oidc.with_mapping(path.clone()).await?);
+        assert_eq!(
+            reloaded.mapping.read().unwrap().get(&rule),
+            Some(&vec![read])
+        );
+
+        std::fs::write(&path, b"not json").unwrap();
+        assert!(parse(&std::fs::read(&path).unwrap()).is_err());
+
+        std::fs::remove_file(&path).unwrap();
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_commit() -> TCResult<()> {
+        let path = std::env::temp_dir().join(format!("oidc-staged-{}.json", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+
+        let oidc = Oidc::new(
+            "https://accounts.example.com".to_string(),
+            "tinychain".to_string(),
+        );
+        let federation = Federation::new(oidc.clone().with_mapping(path.clone()).await?);
+
+        let read: Scope = "/app/read".parse().unwrap();
+        let rule = ("groups".to_string(), "users".to_string());
+        let txn_id = TxnId::new(Gateway::time());
+        let expires = Gateway::time() + Duration::from_secs(30);
+
+        // a staged rule is neither used nor saved until its transaction commits
+        federation.stage(txn_id, expires, rule.clone(), Some(vec![read.clone()]));
+        assert!(federation.mapping.read().unwrap().is_empty());
+        assert!(!path.exists());
+
+        federation.commit(&txn_id).await?;
+        assert!(federation.staged.read().unwrap().is_empty());
+
+        let reloaded = Federation::new(oidc.with_mapping(path.clone()).await?);
+        assert_eq!(
+            reloaded.mapping.read().unwrap().get(&rule),
+            Some(&vec![read])
+        );
+
+        std::fs::remove_file(&path).unwrap();
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_jwks() -> TCResult<()> {
+        let addr = serve_jwks();
+        let federation = Federation::new(Oidc::new(
+            format!("http://{}", addr),
+            "tinychain".to_string(),
+        ));
+
+        let (n, e) = federation.key("kid-1").await?;
+        assert_eq!((n.as_str(), e.as_str()), ("modulus", "AQAB"));
+
+        // only RSA keys are used
+        let err = federation.key("kid-2").await.unwrap_err();
+        assert_eq!(err.code(), ErrorType::Unauthorized);
+
+        // a token signed with a symmetric key is never accepted
+        let header =
+            base64::encode_config(r#"{"alg":"HS256","kid":"kid-1"}"#, base64::URL_SAFE_NO_PAD);
+        let hs256 = format!("{}.e30.signature", header);
+        let err = federation.verify(&hs256).await.unwrap_err();
+        assert_eq!(err.code(), ErrorType::Unauthorized);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,400 @@
+//! A token-bucket rate limiter, keyed by client IP address and optionally by auth token too.
+//!
+//! An auth token has its own limit, which applies across every IP address that it's used from,
+//! so that a client can't get around its limit by spreading its requests over many addresses.
+
+use std::collections::{BTreeMap, HashMap};
+use std::net::IpAddr;
+use std::sync::{Mutex, PoisonError};
+use std::time::{Duration, Instant};
+
+use tc_error::*;
+
+// once this many clients are tracked, forget the client which was seen least recently
+const MAX_CLIENTS: usize = 10_000;
+
+/// The maximum rate of requests from a single IP address, and from a single auth token.
+///
+/// A rate of zero means that requests of that kind are not limited.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct RateLimit {
+    /// The sustained number of reads allowed per second from an IP address.
+    pub reads_per_second: f64,
+    /// The number of reads allowed in a burst from an IP address.
+    pub read_burst: u32,
+    /// The sustained number of writes allowed per second from an IP address.
+    pub writes_per_second: f64,
+    /// The number of writes allowed in a burst from an IP address.
+    pub write_burst: u32,
+    /// The sustained number of reads allowed per second with an auth token, from any address.
+    pub token_reads_per_second: f64,
+    /// The number of reads allowed in a burst with an auth token.
+    pub token_read_burst: u32,
+    /// The sustained number of writes allowed per second with an auth token, from any address.
+    pub token_writes_per_second: f64,
+    /// The number of writes allowed in a burst with an auth token.
+    pub token_write_burst: u32,
+}
+
+impl RateLimit {
+    fn is_unlimited(&self) -> bool {
+        self.reads_per_second <= 0.
+            && self.writes_per_second <= 0.
+            && self.token_reads_per_second <= 0.
+            && self.token_writes_per_second <= 0.
+    }
+
+    // the rate and burst of requests allowed from the given `client`
+    fn of(&self, client: &Client, write: bool) -> (f64, u32) {
+        match (client, write) {
+            (Client::Ip(_), false) => (self.reads_per_second, self.read_burst),
+            (Client::Ip(_), true) => (self.writes_per_second, self.write_burst),
+            (Client::Token(_), false) => (self.token_reads_per_second, self.token_read_burst),
+            (Client::Token(_), true) => (self.token_writes_per_second, self.token_write_burst),
+        }
+    }
+}
+
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+impl Bucket {
+    fn new(burst: u32) -> Self {
+        Self {
+            tokens: f64::from(burst.max(1)),
+            updated: Instant::now(),
+        }
+    }
+
+    fn refill(&mut self, rate: f64, burst: u32, now: Instant) {
+        let elapsed = now.duration_since(self.updated).as_secs_f64();
+        self.tokens = (self.tokens + (elapsed * rate)).min(f64::from(burst.max(1)));
+        self.updated = now;
+    }
+
+    // return how long to wait until a token is available, if there is none
+    fn wait(&mut self, rate: f64, burst: u32, now: Instant) -> Result<(), Duration> {
+        self.refill(rate, burst, now);
+
+        if self.tokens >= 1. {
+            Ok(())
+        } else {
+            Err(Duration::from_secs_f64((1. - self.tokens) / rate))
+        }
+    }
+
+    // take a token, or return how long to wait until one is available
+    fn take(&mut self, rate: f64, burst: u32, now: Instant) -> Result<(), Duration> {
+        self.wait(rate, burst, now)?;
+        self.tokens -= 1.;
+        Ok(())
+    }
+}
+
+struct Buckets {
+    reads: Bucket,
+    writes: Bucket,
+    used: u64,
+}
+
+impl Buckets {
+    fn new(limit: &RateLimit, client: &Client, used: u64) -> Self {
+        Self {
+            reads: Bucket::new(limit.of(client, false).1),
+            writes: Bucket::new(limit.of(client, true).1),
+            used,
+        }
+    }
+
+    fn get_mut(&mut self, write: bool) -> &mut Bucket {
+        if write {
+            &mut self.writes
+        } else {
+            &mut self.reads
+        }
+    }
+}
+
+#[derive(Clone, Eq, PartialEq, Hash)]
+enum Client {
+    Ip(IpAddr),
+    Token(String),
+}
+
+// the buckets of each client, and the clients in order of their last request
+#[derive(Default)]
+struct Clients {
+    buckets: HashMap<Client, Buckets>,
+    lru: BTreeMap<u64, Client>,
+    used: u64,
+}
+
+impl Clients {
+    // return the buckets of the given `client`, forgetting the least recently seen client
+    // if there are already too many
+    fn touch(&mut self, limit: &RateLimit, client: Client) -> &mut Buckets {
+        self.used += 1;
+        let used = self.used;
+
+        if let Some(buckets) = self.buckets.get_mut(&client) {
+            self.lru.remove(&buckets.used);
+            buckets.used = used;
+        } else {
+            if self.buckets.len() >= MAX_CLIENTS {
+                if let Some((_, oldest)) = self.lru.pop_first() {
+                    self.buckets.remove(&oldest);
+                }
+            }
+
+            let buckets = Buckets::new(limit, &client, used);
+            self.buckets.insert(client.clone(), buckets);
+        }
+
+        self.lru.insert(used, client.clone());
+        self.buckets.get_mut(&client).expect("client buckets")
+    }
+}
+
+/// Limits the rate of requests from each client.
+pub(crate) struct RateLimiter {
+    limit: RateLimit,
+    clients: Mutex<Clients>,
+}
+
+impl RateLimiter {
+    pub fn new(limit: RateLimit) -> Self {
+        Self {
+            limit,
+            clients: Mutex::new(Clients::default()),
+        }
+    }
+
+    /// Count a request from the given `client`, or return how long it should wait to retry.
+    ///
+    /// The `token` of a request is not verified yet, so a request with a token counts against
+    /// the limit of its IP address as well as the limit of its token.
+    pub fn check(&self, client: IpAddr, token: Option<&str>, write: bool) -> Result<(), Duration> {
+        let limit = &self.limit;
+        if limit.is_unlimited() {
+            return Ok(());
+        }
+
+        let client = Client::Ip(client);
+        let (rate, burst) = limit.of(&client, write);
+
+        let token = token
+            .map(|token| Client::Token(token.to_string()))
+            .filter(|token| limit.of(token, write).0 > 0.);
+
+        let now = Instant::now();
+        let mut clients = self.clients.lock().unwrap_or_else(PoisonError::into_inner);
+
+        // check the limit of the IP address first, so that a request which exceeds either limit
+        // doesn't use up the other
+        if rate > 0. {
+            clients
+                .touch(limit, client.clone())
+                .get_mut(write)
+                .wait(rate, burst, now)?;
+        }
+
+        if let Some(token) = token {
+            let (rate, burst) = limit.of(&token, write);
+            clients
+                .touch(limit, token)
+                .get_mut(write)
+                .take(rate, burst, now)?;
+        }
+
+        if rate > 0. {
+            clients
+                .touch(limit, client)
+                .get_mut(write)
+                .take(rate, burst, now)
+        } else {
+            Ok(())
+        }
+    }
+}
+
+/// The number of whole seconds a client should wait before retrying, for a `Retry-After` header.
+pub(crate) fn retry_after(delay: Duration) -> u64 {
+    delay.as_secs() + if delay.subsec_nanos() > 0 { 1 } else { 0 }
+}
+
+/// The error returned to a client which has exceeded its [`RateLimit`].
+pub(crate) fn too_many_requests(delay: Duration) -> TCError {
+    TCError::too_many_requests(format!("retry after {} seconds", retry_after(delay)))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::Ipv4Addr;
+
+    use super::*;
+
+    #[test]
+    fn test_bucket() {
+        let now = Instant::now();
+        let mut bucket = Bucket::new(2);
+
+        assert!(bucket.take(1., 2, now).is_ok());
+        assert!(bucket.take(1., 2, now).is_ok());
+        assert!(bucket.tokens < 1.);
+
+        let delay = bucket.take(1., 2, now).unwrap_err();
+        assert_eq!(delay, Duration::from_secs(1));
+
+        let later = now + Duration::from_millis(500);
+        assert_eq!(
+            bucket.take(1., 2, later).unwrap_err(),
+            Duration::from_millis(500)
+        );
+
+        let later = now + Duration::from_secs(10);
+        assert!(bucket.take(1., 2, later).is_ok());
+        bucket.refill(1., 2, later + Duration::from_secs(10));
+        assert!((bucket.tokens - 2.).abs() < f64::EPSILON);
+    }
+
+    #[test]
+    fn test_check() {
+        let client = IpAddr::V4(Ipv4Addr::LOCALHOST);
+        let other = IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1));
+
+        let unlimited = RateLimiter::new(RateLimit::default());
+        for _ in 0..100 {
+            assert!(unlimited.check(client, None, true).is_ok());
+        }
+
+        let limiter = RateLimiter::new(RateLimit {
+            reads_per_second: 0.001,
+            read_burst: 2,
+            writes_per_second: 0.001,
+            write_burst: 1,
+            token_writes_per_second: 0.001,
+            token_write_burst: 1,
+            ..RateLimit::default()
+        });
+
+        // reads and writes are limited separately
+        assert!(limiter.check(client, None, false).is_ok());
+        assert!(limiter.check(client, None, false).is_ok());
+        assert!(limiter.check(client, None, false).is_err());
+        assert!(limiter.check(client, None, true).is_ok());
+        assert!(limiter.check(client, None, true).is_err());
+
+        // and so is each client
+        assert!(limiter.check(other, None, true).is_ok());
+
+        // an auth token is limited separately, but still counts against its IP address
+        assert!(limiter.check(client, Some("token"), true).is_err());
+
+        let third = IpAddr::V4(Ipv4Addr::new(10, 0, 0, 3));
+        let limiter = RateLimiter::new(RateLimit {
+            reads_per_second: 0.001,
+            read_burst: 2,
+            token_reads_per_second: 0.001,
+            token_read_burst: 1,
+            ..RateLimit::default()
+        });
+
+        // a token which exceeds its own limit doesn't use up the limit of its IP address
+        assert!(limiter.check(third, Some("token"), false).is_ok());
+        assert!(limiter.check(third, Some("token"), false).is_err());
+        assert!(limiter.check(third, Some("other"), false).is_ok());
+        assert!(limiter.check(third, Some("third"), false).is_err());
+        assert!(limiter.check(client, Some("third"), false).is_ok());
+
+        let reads_only = RateLimiter::new(RateLimit {
+            reads_per_second: 0.001,
+            read_burst: 1,
+            ..RateLimit::default()
+        });
+
+        assert!(reads_only.check(client, Some("token"), false).is_ok());
+        assert!(reads_only.check(client, Some("other"), false).is_err());
+        assert!(reads_only.check(client, None, true).is_ok());
+        assert!(reads_only.check(client, None, true).is_ok());
+    }
+
+    #[test]
+    fn test_check_token() {
+        let limiter = RateLimiter::new(RateLimit {
+            reads_per_second: 0.001,
+            read_burst: 100,
+            token_reads_per_second: 0.001,
+            token_read_burst: 3,
+            ..RateLimit::default()
+        });
+
+        // a token is throttled even when each request comes from a different IP address
+        for i in 1..=3 {
+            let ip = IpAddr::V4(Ipv4Addr::new(10, 0, 0, i));
+            assert!(limiter.check(ip, Some("token"), false).is_ok());
+        }
+
+        let ip = IpAddr::V4(Ipv4Addr::new(10, 0, 0, 4));
+        assert!(limiter.check(ip, Some("token"), false).is_err());
+
+        // while another token, and a request without one, from the same address are not
+        assert!(limiter.check(ip, Some("other"), false).is_ok());
+        assert!(limiter.check(ip, None, false).is_ok());
+
+        // and a token with no limit of its own is only limited by its IP address
+        let ip_only = RateLimiter::new(RateLimit {
+            reads_per_second: 0.001,
+            read_burst: 2,
+            ..RateLimit::default()
+        });
+
+        for i in 1..=4 {
+            let ip = IpAddr::V4(Ipv4Addr::new(10, 0, 0, i));
+            assert!(ip_only.check(ip, Some("token"), false).is_ok());
+        }
+
+        assert_eq!(ip_only.clients.lock().unwrap().buckets.len(), 4);
+    }
+
+    #[test]
+    fn test_max_clients() {
+        let limit = RateLimit {
+            reads_per_second: 0.001,
+            read_burst: 1,
+            ..RateLimit::default()
+        };
+
+        let mut clients = Clients::default();
+        let first = IpAddr::V4(Ipv4Addr::LOCALHOST);
+        clients.touch(&limit, Client::Ip(first));
+
+        for i in 1..MAX_CLIENTS {
+            let ip = IpAddr::V4(Ipv4Addr::from(i as u32));
+            clients.touch(&limit, Client::Ip(ip));
+        }
+
+        // the first client was seen again most recently, so the second is forgotten instead
+        clients.touch(&limit, Client::Ip(first));
+        clients.touch(&limit, Client::Ip(IpAddr::V4(Ipv4Addr::BROADCAST)));
+
+        assert_eq!(clients.buckets.len(), MAX_CLIENTS);
+        assert_eq!(clients.lru.len(), MAX_CLIENTS);
+        assert!(clients.buckets.contains_key(&Client::Ip(first)));
+        assert!(!clients
+            .buckets
+            .contains_key(&Client::Ip(IpAddr::V4(Ipv4Addr::from(1)))));
+    }
+
+    #[test]
+    fn test_retry_after() {
+        assert_eq!(retry_after(Duration::from_secs(2)), 2);
+        assert_eq!(retry_after(Duration::from_millis(2001)), 3);
+        assert_eq!(retry_after(Duration::from_millis(1)), 1);
+
+        let err = too_many_requests(Duration::from_millis(1500));
+        assert_eq!(err.code(), ErrorType::TooManyRequests);
+        assert!(err.message().contains("2 seconds"));
+    }
+}
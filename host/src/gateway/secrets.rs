@@ -0,0 +1,637 @@
+//! A store of secrets, like the credentials of external services, which are encrypted at rest.
+//!
+//! Secrets are sealed with a key derived from this host's secret key file, and are never returned
+//! in plaintext. A GET request to `/sbin/secrets` lists their names, and a GET request with a
+//! name as the key lists the hosts to which that secret may be sent. A PUT request with a name as
+//! the key and a `(secret, (host...))` tuple as the value seals a secret, or deletes it if the
+//! value is `None`. Only a request with the `/sbin/secrets` scope from a trusted issuer key may
+//! read or update the store.
+//!
+//! An update is staged until its transaction commits. If the transaction has an owner, the store
+//! is committed along with the other dependencies of the owner; otherwise it's committed as soon
+//! as the update is staged.
+//!
+//! An op refers to a secret by the link `/sbin/secrets/<name>`. When such a link appears in the
+//! key, value, or parameters of a request to one of the hosts which the secret may be sent to,
+//! it's replaced with the secret itself, so the plaintext never leaves this host except on the
+//! wire to that host. Only a transaction with the `/sbin/secrets` scope, or the
+//! `/sbin/secrets/<name>` scope of that secret, from a trusted issuer key may send a secret.
+
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::{PoisonError, RwLock};
+
+use chacha20poly1305::aead::{Aead, NewAead, Payload};
+use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce};
+use futures::TryFutureExt;
+use log::info;
+use safecast::TryCastInto;
+use serde_json::{json, Value as Json};
+
+use tc_error::*;
+use tc_transact::{Transaction, TxnId};
+use tcgeneric::{path_label, Id, Map, NetworkTime, PathLabel, TCPathBuf, Tuple};
+
+use crate::scalar::{Link, LinkHost, Scalar, Value};
+use crate::state::State;
+use crate::txn::Txn;
+
+use super::keys::Keyring;
+use super::Gateway;
+
+/// The path of the secrets store.
+pub(crate) const SECRETS: PathLabel = path_label(&["sbin", "secrets"]);
+
+const KEY_CONTEXT: &str = "tinychain 2021-06 secrets store";
+const KEY_SIZE: usize = 32;
+const NONCE_SIZE: usize = 12;
+
+struct Sealed {
+    hosts: Vec<LinkHost>,
+    sealed: Vec<u8>,
+}
+
+// the changes staged by a transaction which hasn't committed yet, and when it expires
+struct Staged {
+    expires: NetworkTime,
+    changes: HashMap<Id, Option<Sealed>>,
+}
+
+/// A store of encrypted secrets, persisted to a JSON file.
+pub struct Secrets {
+    path: PathBuf,
+    cipher: ChaCha20Poly1305,
+    sealed: RwLock<HashMap<Id, Sealed>>,
+    staged: RwLock<HashMap<TxnId, Staged>>,
+    // held while the store is saved, so that concurrent commits never share the temporary file,
+    // and the last save to finish is always the latest
+    saving: tokio::sync::Mutex<()>,
+}
+
+impl Secrets {
+    /// Load the secrets stored at `path`, sealed with a key derived from the file at `host_key`.
+    ///
+    /// If there's no file at `host_key`, a new random key is written there. It should be kept
+    /// separately from the secrets file, and readable only by the user who runs this host.
+    pub async fn load(path: PathBuf, host_key: PathBuf) -> TCResult<Self> {
+        let key_material = match tokio::fs::read(&host_key).await {
+            Ok(key_material) => key_material,
+            Err(cause) if cause.kind() == std::io::ErrorKind::NotFound => {
+                let key_material = rand::random::<[u8; KEY_SIZE]>().to_vec();
+                write_private(&host_key, &key_material).await?;
+                info!("generated a new host key at {:?}", host_key);
+                key_material
+            }
+            Err(cause) => return Err(file_error(&host_key, cause)),
+        };
+
+        let mut key = [0u8; KEY_SIZE];
+        blake3::derive_key(KEY_CONTEXT, &key_material, &mut key);
+        let cipher = ChaCha20Poly1305::new(Key::from_slice(&key));
+
+        let sealed = match tokio::fs::read(&path).await {
+            Ok(contents) => parse(&contents)?,
+            Err(cause) if cause.kind() == std::io::ErrorKind::NotFound => HashMap::new(),
+            Err(cause) => return Err(file_error(&path, cause)),
+        };
+
+        Ok(Self {
+            path,
+            cipher,
+            sealed: RwLock::new(sealed),
+            staged: RwLock::new(HashMap::new()),
+            saving: tokio::sync::Mutex::new(()),
+        })
+    }
+
+    /// List the names of the stored secrets, or the hosts to which the named secret may be sent.
+    pub(crate) fn get(&self, txn: &Txn, keys: &Keyring, key: Value) -> TCResult<State> {
+        keys.authorize(txn, &SECRETS.into())?;
+
+        let sealed = self.sealed.read().unwrap_or_else(PoisonError::into_inner);
+
+        if key.is_none() {
+            let names = sealed
+                .keys()
+                .map(|name| Value::String(name.as_str().into()));
+            return Ok(State::from(Value::Tuple(names.collect())));
+        }
+
+        let name: Id = key.try_cast_into(|k| TCError::bad_request("invalid secret name", k))?;
+        let secret = sealed
+            .get(&name)
+            .ok_or_else(|| TCError::not_found(format!("secret {}", name)))?;
+
+        let hosts = secret
+            .hosts
+            .iter()
+            .map(|host| Value::from(Link::from(host.clone())))
+            .collect::<Tuple<Value>>();
+
+        Ok(State::from(Value::Tuple(hosts)))
+    }
+
+    /// Stage the sealing of the secret with the given name, or its deletion if the `value` is
+    /// `None`, until the given `txn` commits.
+    pub(crate) fn put(&self, txn: &Txn, keys: &Keyring, key: Value, value: State) -> TCResult<()> {
+        keys.authorize(txn, &SECRETS.into())?;
+
+        let name: Id = key.try_cast_into(|k| TCError::bad_request("invalid secret name", k))?;
+        let value: Value = value.try_cast_into(|v| TCError::bad_request("invalid secret", v))?;
+
+        let change = if value.is_none() {
+            None
+        } else {
+            let (secret, hosts): (Value, Tuple<Link>) = value.try_cast_into(|v| {
+                TCError::bad_request("expected a secret and the hosts to send it to, not", v)
+            })?;
+
+            let secret = match secret {
+                Value::String(secret) => secret,
+                other => {
+                    return Err(TCError::bad_request(
+                        "a secret must be a string, not",
+                        other,
+                    ))
+                }
+            };
+
+            let hosts = hosts
+                .into_iter()
+                .map(|link| {
+                    LinkHost::try_from(link)
+                        .map_err(|e| TCError::bad_request("invalid secret destination", e))
+                })
+                .collect::<TCResult<Vec<LinkHost>>>()?;
+
+            let sealed = self.seal(&name, secret.as_bytes())?;
+            Some(Sealed { hosts, sealed })
+        };
+
+        self.stage(*txn.id(), txn.request().expires()?, name, change);
+        Ok(())
+    }
+
+    // stage a change until the given transaction commits, and forget the changes of any
+    // transaction which has expired without committing
+    fn stage(&self, txn_id: TxnId, expires: NetworkTime, name: Id, change: Option<Sealed>) {
+        let now = Gateway::time();
+        let mut staged = self.staged.write().unwrap_or_else(PoisonError::into_inner);
+        staged.retain(|_, staged| staged.expires > now);
+
+        staged
+            .entry(txn_id)
+            .or_insert_with(|| Staged {
+                expires,
+                changes: HashMap::new(),
+            })
+            .changes
+            .insert(name, change);
+    }
+
+    /// Apply the changes staged by the given transaction, if any, and save the store.
+    pub(crate) async fn commit(&self, txn_id: &TxnId) -> TCResult<()> {
+        let staged = self
+            .staged
+            .write()
+            .unwrap_or_else(PoisonError::into_inner)
+            .remove(txn_id);
+
+        let staged = match staged {
+            Some(staged) => staged,
+            None => return Ok(()),
+        };
+
+        {
+            let mut sealed = self.sealed.write().unwrap_or_else(PoisonError::into_inner);
+            for (name, change) in staged.changes {
+                match change {
+                    Some(secret) => {
+                        info!("sealing secret {}", name);
+                        sealed.insert(name, secret);
+                    }
+                    None => {
+                        info!("deleting secret {}", name);
+                        sealed.remove(&name);
+                    }
+                }
+            }
+        }
+
+        self.save().await
+    }
+
+    /// Replace each reference to a secret in the given `state` with the secret itself, if the
+    /// `txn` may send it and it may be sent to the given `host`.
+    pub(crate) fn unseal(
+        &self,
+        txn: &Txn,
+        keys: &Keyring,
+        host: &LinkHost,
+        state: State,
+    ) -> TCResult<State> {
+        match state {
+            State::Map(map) => map
+                .into_iter()
+                .map(|(id, state)| self.unseal(txn, keys, host, state).map(|state| (id, state)))
+                .collect::<TCResult<Map<State>>>()
+                .map(State::Map),
+            State::Tuple(tuple) => tuple
+                .into_iter()
+                .map(|state| self.unseal(txn, keys, host, state))
+                .collect::<TCResult<Tuple<State>>>()
+                .map(State::Tuple),
+            State::Scalar(Scalar::Value(value)) => self
+                .unseal_value(txn, keys, host, value)
+                .map(Scalar::Value)
+                .map(State::Scalar),
+            other => Ok(other),
+        }
+    }
+
+    /// Replace each reference to a secret in the given `value` with the secret itself, if the
+    /// `txn` may send it and it may be sent to the given `host`.
+    pub(crate) fn unseal_value(
+        &self,
+        txn: &Txn,
+        keys: &Keyring,
+        host: &LinkHost,
+        value: Value,
+    ) -> TCResult<Value> {
+        match value {
+            Value::Link(link) if is_reference(&link) => {
+                let name = &link.path()[2];
+                authorize_send(txn, keys, name)?;
+
+                let plaintext = self.unseal_secret(host, name)?;
+                Ok(Value::String(plaintext.into()))
+            }
+            Value::Tuple(tuple) => tuple
+                .into_iter()
+                .map(|value| self.unseal_value(txn, keys, host, value))
+                .collect::<TCResult<Tuple<Value>>>()
+                .map(Value::Tuple),
+            other => Ok(other),
+        }
+    }
+
+    // the plaintext of the named secret, if it may be sent to the given host
+    fn unseal_secret(&self, host: &LinkHost, name: &Id) -> TCResult<String> {
+        let sealed = self.sealed.read().unwrap_or_else(PoisonError::into_inner);
+        let secret = sealed
+            .get(name)
+            .ok_or_else(|| TCError::not_found(format!("secret {}", name)))?;
+
+        if !secret.hosts.contains(host) {
+            return Err(TCError::forbidden(
+                format!("secret {} may not be sent to", name),
+                host,
+            ));
+        }
+
+        self.open(name, &secret.sealed)
+    }
+
+    // encrypt a secret, binding it to its name
+    fn seal(&self, name: &Id, secret: &[u8]) -> TCResult<Vec<u8>> {
+        let nonce = rand::random::<[u8; NONCE_SIZE]>();
+        let payload = Payload {
+            msg: secret,
+            aad: name.as_str().as_bytes(),
+        };
+
+        let ciphertext = self
+            .cipher
+            .encrypt(Nonce::from_slice(&nonce), payload)
+            .map_err(|_| TCError::internal("unable to seal secret"))?;
+
+        Ok([&nonce[..], &ciphertext[..]].concat())
+    }
+
+    fn open(&self, name: &Id, sealed: &[u8]) -> TCResult<String> {
+        if sealed.len() < NONCE_SIZE {
+            return Err(TCError::internal(format!("secret {} is corrupt", name)));
+        }
+
+        let (nonce, ciphertext) = sealed.split_at(NONCE_SIZE);
+        let payload = Payload {
+            msg: ciphertext,
+            aad: name.as_str().as_bytes(),
+        };
+
+        let plaintext = self
+            .cipher
+            .decrypt(Nonce::from_slice(nonce), payload)
+            .map_err(|_| TCError::internal(format!("unable to unseal secret {}", name)))?;
+
+        String::from_utf8(plaintext)
+            .map_err(|_| TCError::internal(format!("secret {} is corrupt", name)))
+    }
+
+    // write the sealed secrets to a temporary file, then move it into place
+    async fn save(&self) -> TCResult<()> {
+        let _saving = self.saving.lock().await;
+
+        let contents = {
+            let sealed = self.sealed.read().unwrap_or_else(PoisonError::into_inner);
+            let contents = sealed
+                .iter()
+                .map(|(name, secret)| {
+                    let hosts = secret
+                        .hosts
+                        .iter()
+                        .map(|h| h.to_string())
+                        .collect::<Vec<_>>();
+                    let secret = json!({"hosts": hosts, "sealed": base64::encode(&secret.sealed)});
+                    (name.as_str().to_string(), secret)
+                })
+                .collect::<serde_json::Map<String, Json>>();
+
+            serde_json::to_vec(&contents).map_err(TCError::internal)?
+        };
+
+        let tmp = self.path.with_extension("tmp");
+        write_private(&tmp, &contents).await?;
+
+        tokio::fs::rename(&tmp, &self.path)
+            .map_err(|e| file_error(&self.path, e))
+            .await
+    }
+}
+
+fn parse(contents: &[u8]) -> TCResult<HashMap<Id, Sealed>> {
+    let invalid = |cause: &dyn std::fmt::Display| {
+        TCError::internal(format!("invalid secrets file: {}", cause))
+    };
+
+    let contents: serde_json::Map<String, Json> =
+        serde_json::from_slice(contents).map_err(|e| invalid(&e))?;
+
+    contents
+        .into_iter()
+        .map(|(name, secret)| {
+            let hosts = secret["hosts"]
+                .as_array()
+                .ok_or_else(|| invalid(&name))?
+                .iter()
+                .map(|host| {
+                    host.as_str()
+                        .ok_or_else(|| invalid(&name))
+                        .and_then(LinkHost::from_str)
+                })
+                .collect::<TCResult<Vec<LinkHost>>>()?;
+
+            let sealed = secret["sealed"].as_str().ok_or_else(|| invalid(&name))?;
+            let sealed = base64::decode(sealed).map_err(|e| invalid(&e))?;
+
+            Ok((name.parse()?, Sealed { hosts, sealed }))
+        })
+        .collect()
+}
+
+// return `Unauthorized` unless a trusted issuer granted the `txn` the scope of the whole store,
+// or of the named secret
+fn authorize_send(txn: &Txn, keys: &Keyring, name: &Id) -> TCResult<()> {
+    keys.authorize(txn, &SECRETS.into()).or_else(|_| {
+        let scope = TCPathBuf::from(SECRETS).append(name.clone());
+        keys.authorize(txn, &scope)
+    })
+}
+
+// true if the given link refers to a secret by name
+fn is_reference(link: &Link) -> bool {
+    link.host().is_none() && link.path().len() == 3 && link.path()[..2] == SECRETS[..]
+}
+
+// write a file which only the user who runs this host may read, so that it's never readable
+// by anyone else, even briefly
+async fn write_private(path: &PathBuf, contents: &[u8]) -> TCResult<()> {
+    use tokio::io::AsyncWriteExt;
+
+    let mut options = tokio::fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+
+    #[cfg(unix)]
+    options.mode(0o600);
+
+    let mut file = options.open(path).map_err(|e| file_error(path, e)).await?;
+    file.write_all(contents)
+        .map_err(|e| file_error(path, e))
+        .await?;
+
+    file.sync_all().map_err(|e| file_error(path, e)).await
+}
+
+fn file_error(path: &PathBuf, cause: std::io::Error) -> TCError {
+    TCError::internal(format!("unable to access {:?}: {}", path, cause))
+}
+
+#[cfg(test)]
+mod tests {
+    use uuid::Uuid;
+
+    use crate::test::TestHost;
+
+    use super::*;
+
+    // stage the given secret without authorization, in a new transaction
+    fn stage(secrets: &Secrets, name: &str, secret: &str, host: &LinkHost) -> TCResult<TxnId> {
+        let txn_id = TxnId::new(Gateway::time());
+        let expires = Gateway::time() + std::time::Duration::from_secs(30);
+
+        let name: Id = name.parse()?;
+        let sealed = secrets.seal(&name, secret.as_bytes())?;
+        let hosts = vec![host.clone()];
+        secrets.stage(txn_id, expires, name, Some(Sealed { hosts, sealed }));
+
+        Ok(txn_id)
+    }
+
+    // seal the given secret without authorization, then save the store
+    async fn insert(secrets: &Secrets, name: &str, secret: &str, host: &LinkHost) -> TCResult<()> {
+        let txn_id = stage(secrets, name, secret, host)?;
+        secrets.commit(&txn_id).await
+    }
+
+    fn reference(name: &str) -> Value {
+        Value::Link(format!("/sbin/secrets/{}", name).parse().unwrap())
+    }
+
+    #[tokio::test]
+    async fn test_seal() -> TCResult<()> {
+        let dir = std::env::temp_dir().join(format!("tc-secrets-{}", Uuid::new_v4()));
+        tokio::fs::create_dir_all(&dir).await.unwrap();
+        let path = dir.join("secrets.json");
+        let host_key = dir.join("host.key");
+
+        let allowed: LinkHost = "http://10.0.0.1:8702".parse()?;
+        let other: LinkHost = "http://10.0.0.2:8702".parse()?;
+
+        let secrets = Secrets::load(path.clone(), host_key.clone()).await?;
+        insert(&secrets, "api_key", "hunter2", &allowed).await?;
+
+        // the plaintext is never written to disk
+        let contents = tokio::fs::read_to_string(&path).await.unwrap();
+        assert!(!contents.contains("hunter2"));
+
+        #[cfg(unix)]
+        for path in &[&host_key, &path] {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = std::fs::metadata(path).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+
+        // a reloaded store unseals a secret only for the hosts it may be sent to
+        let secrets = Secrets::load(path.clone(), host_key.clone()).await?;
+        let api_key: Id = "api_key".parse()?;
+        assert_eq!(secrets.unseal_secret(&allowed, &api_key)?, "hunter2");
+
+        let err = secrets.unseal_secret(&other, &api_key).unwrap_err();
+        assert_eq!(err.code(), ErrorType::Forbidden);
+
+        let err = secrets
+            .unseal_secret(&allowed, &"missing".parse()?)
+            .unwrap_err();
+        assert_eq!(err.code(), ErrorType::NotFound);
+
+        // a sealed secret is bound to its name
+        {
+            let mut sealed = secrets.sealed.write().unwrap();
+            let api_key: Id = "api_key".parse()?;
+            let copy = Sealed {
+                hosts: vec![allowed.clone()],
+                sealed: sealed[&api_key].sealed.clone(),
+            };
+
+            sealed.insert("copy".parse()?, copy);
+        }
+
+        assert!(secrets.unseal_secret(&allowed, &"copy".parse()?).is_err());
+
+        // a store loaded with a different host key can't unseal anything
+        let secrets = Secrets::load(path, dir.join("other.key")).await?;
+        assert!(secrets.unseal_secret(&allowed, &api_key).is_err());
+
+        tokio::fs::remove_dir_all(&dir).await.unwrap();
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_unauthorized() -> TCResult<()> {
+        let host = TestHost::new(vec![]).await?;
+        let txn = host.new_txn(true).await?;
+
+        let dir = std::env::temp_dir().join(format!("tc-secrets-{}", Uuid::new_v4()));
+        tokio::fs::create_dir_all(&dir).await.unwrap();
+        let secrets = Secrets::load(dir.join("secrets.json"), dir.join("host.key")).await?;
+
+        let keys = Keyring::new(vec![]);
+        let name = Value::String("api_key".into());
+        let value = Value::Tuple(
+            vec![
+                Value::String("hunter2".into()),
+                Value::Tuple(Tuple::default()),
+            ]
+            .into(),
+        );
+
+        let err = secrets
+            .put(&txn, &keys, name, State::from(value))
+            .unwrap_err();
+
+        assert_eq!(err.code(), ErrorType::Unauthorized);
+        assert!(secrets.staged.read().unwrap().is_empty());
+
+        let err = secrets
+            .get(&txn, &keys, Value::None)
+            .map(|_| ())
+            .unwrap_err();
+
+        assert_eq!(err.code(), ErrorType::Unauthorized);
+
+        // a transaction without the scope of a secret can't send it, even to an allowed host
+        let allowed: LinkHost = "http://10.0.0.1:8702".parse()?;
+        insert(&secrets, "api_key", "hunter2", &allowed).await?;
+
+        let err = secrets
+            .unseal_value(&txn, &keys, &allowed, reference("api_key"))
+            .map(|_| ())
+            .unwrap_err();
+
+        assert_eq!(err.code(), ErrorType::Unauthorized);
+
+        // a link to anything else is left as-is
+        let link = Value::Link("/sbin/other/api_key".parse()?);
+        assert!(secrets.unseal_value(&txn, &keys, &allowed, link.clone())? == link);
+
+        tokio::fs::remove_dir_all(&dir).await.unwrap();
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_commit() -> TCResult<()> {
+        let dir = std::env::temp_dir().join(format!("tc-secrets-{}", Uuid::new_v4()));
+        tokio::fs::create_dir_all(&dir).await.unwrap();
+        let path = dir.join("secrets.json");
+        let secrets = Secrets::load(path.clone(), dir.join("host.key")).await?;
+
+        let host: LinkHost = "http://10.0.0.1:8702".parse()?;
+        let api_key: Id = "api_key".parse()?;
+
+        // a staged secret is neither visible nor saved until its transaction commits
+        let txn_id = stage(&secrets, "api_key", "hunter2", &host)?;
+        assert!(!secrets.sealed.read().unwrap().contains_key(&api_key));
+        assert!(!path.exists());
+
+        secrets.commit(&txn_id).await?;
+        assert!(secrets.sealed.read().unwrap().contains_key(&api_key));
+        assert!(secrets.staged.read().unwrap().is_empty());
+
+        let reloaded = Secrets::load(path.clone(), dir.join("host.key")).await?;
+        assert!(reloaded.sealed.read().unwrap().contains_key(&api_key));
+
+        // committing a transaction which staged nothing changes nothing
+        secrets.commit(&TxnId::new(Gateway::time())).await?;
+        assert!(secrets.sealed.read().unwrap().contains_key(&api_key));
+
+        // the changes of an expired transaction are forgotten when another is staged; this doesn't
+        // wait for the clock, since a simulation build's virtual clock never advances in a test
+        let expired = TxnId::new(NetworkTime::from_nanos(1));
+        secrets.stage(expired, Gateway::time(), api_key.clone(), None);
+        stage(&secrets, "other", "hunter3", &host)?;
+        assert!(!secrets.staged.read().unwrap().contains_key(&expired));
+
+        tokio::fs::remove_dir_all(&dir).await.unwrap();
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_concurrent_commit() -> TCResult<()> {
+        let dir = std::env::temp_dir().join(format!("tc-secrets-{}", Uuid::new_v4()));
+        tokio::fs::create_dir_all(&dir).await.unwrap();
+        let path = dir.join("secrets.json");
+        let secrets = Secrets::load(path.clone(), dir.join("host.key")).await?;
+
+        let host: LinkHost = "http://10.0.0.1:8702".parse()?;
+        let names = ["a", "b", "c", "d", "e", "f", "g", "h"];
+        let commits = names
+            .iter()
+            .map(|name| insert(&secrets, name, "hunter2", &host));
+
+        futures::future::try_join_all(commits).await?;
+
+        // every secret is saved, and no temporary file is left behind
+        let reloaded = Secrets::load(path.clone(), dir.join("host.key")).await?;
+        assert_eq!(reloaded.sealed.read().unwrap().len(), names.len());
+        assert!(!path.with_extension("tmp").exists());
+
+        tokio::fs::remove_dir_all(&dir).await.unwrap();
+
+        Ok(())
+    }
+}
@@ -0,0 +1,209 @@
+use std::collections::HashMap;
+use std::sync::{PoisonError, RwLock};
+
+use async_trait::async_trait;
+use futures::TryFutureExt;
+use serde::de::DeserializeOwned;
+use tonic::metadata::AsciiMetadataValue;
+use tonic::transport::Channel;
+use tonic::Request;
+
+use tc_error::*;
+use tc_transact::{Transaction, TxnId};
+use tc_value::{Link, Value};
+use tcgeneric::label;
+
+use crate::state::State;
+use crate::txn::Txn;
+
+use super::proto::gateway_client::GatewayClient;
+use super::state::{decode, encode, encode_key, error, to_json};
+use super::{proto, DEPTH, TXN_ID};
+
+const ERR_NO_OWNER: &str = "an ownerless transaction may not make outgoing requests";
+
+/// A Tinychain gRPC client. Should only be used through a `Gateway`.
+///
+/// Each peer is assumed to serve gRPC on the same port as this host.
+pub struct Client {
+    port: u16,
+    channels: RwLock<HashMap<String, Channel>>,
+}
+
+impl Client {
+    /// Construct a new `Client` which calls peers on the given gRPC `port`.
+    pub fn new(port: u16) -> Self {
+        Self {
+            port,
+            channels: RwLock::new(HashMap::new()),
+        }
+    }
+
+    fn connect(&self, link: &Link) -> TCResult<GatewayClient<Channel>> {
+        let host = link
+            .host()
+            .as_ref()
+            .ok_or_else(|| TCError::bad_request("cannot call a peer without a host", link))?;
+
+        let authority = format!("{}:{}", host.address(), self.port);
+        if let Some(channel) = self
+            .channels
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .get(&authority)
+        {
+            return Ok(GatewayClient::new(channel.clone()));
+        }
+
+        let channel = Channel::from_shared(format!("http://{}", authority))
+            .map_err(|e| TCError::bad_request("invalid gRPC endpoint", e))?
+            .connect_lazy()
+            .map_err(TCError::bad_gateway)?;
+
+        let mut channels = self
+            .channels
+            .write()
+            .unwrap_or_else(PoisonError::into_inner);
+        channels.insert(authority, channel.clone());
+        Ok(GatewayClient::new(channel))
+    }
+}
+
+#[async_trait]
+impl crate::gateway::Client for Client {
+    async fn fetch<T: DeserializeOwned>(
+        &self,
+        txn_id: &TxnId,
+        link: &Link,
+        key: &Value,
+    ) -> TCResult<T> {
+        let mut client = self.connect(link)?;
+        let mut request = Request::new(proto::GetRequest {
+            path: link.path().to_string(),
+            key: Some(encode_key(key.clone())?),
+        });
+
+        request
+            .metadata_mut()
+            .insert(TXN_ID, metadata(txn_id.to_string())?);
+
+        let response = client
+            .get(request)
+            .map_err(|status| error(link, status))
+            .await?;
+
+        #[cfg(feature = "faults")]
+        crate::faults::peer_response(link)?;
+
+        let json = to_json(response.into_inner().state)?;
+        serde_json::from_value(json)
+            .map_err(|e| TCError::bad_request(format!("error decoding response from {}", link), e))
+    }
+
+    async fn get(&self, txn: Txn, link: Link, key: Value) -> TCResult<State> {
+        if txn.owner().is_none() {
+            return Err(TCError::unsupported(ERR_NO_OWNER));
+        }
+
+        let request = proto::GetRequest {
+            path: link.path().to_string(),
+            key: Some(encode_key(key)?),
+        };
+
+        let response = self
+            .connect(&link)?
+            .get(request_in(&txn, request)?)
+            .map_err(|status| error(&link, status))
+            .await?;
+
+        #[cfg(feature = "faults")]
+        crate::faults::peer_response(&link)?;
+
+        decode(txn, response.into_inner().state).await
+    }
+
+    async fn put(&self, txn: Txn, link: Link, key: Value, value: State) -> TCResult<()> {
+        if txn.owner().is_none() {
+            return Err(TCError::unsupported(ERR_NO_OWNER));
+        }
+
+        let request = proto::PutRequest {
+            path: link.path().to_string(),
+            key: Some(encode_key(key)?),
+            value: Some(encode(txn.clone(), value).await?),
+        };
+
+        self.connect(&link)?
+            .put(request_in(&txn, request)?)
+            .map_err(|status| error(&link, status))
+            .await?;
+
+        #[cfg(feature = "faults")]
+        crate::faults::peer_response(&link)?;
+
+        Ok(())
+    }
+
+    async fn post(&self, txn: Txn, link: Link, params: State) -> TCResult<State> {
+        if txn.owner().is_none() {
+            return Err(TCError::unsupported(ERR_NO_OWNER));
+        }
+
+        let subcontext = txn.subcontext(label("_params").into()).await?;
+        let request = proto::PostRequest {
+            path: link.path().to_string(),
+            params: Some(encode(subcontext, params).await?),
+        };
+
+        let response = self
+            .connect(&link)?
+            .post(request_in(&txn, request)?)
+            .map_err(|status| error(&link, status))
+            .await?;
+
+        #[cfg(feature = "faults")]
+        crate::faults::peer_response(&link)?;
+
+        decode(txn, response.into_inner().state).await
+    }
+
+    async fn delete(&self, txn: &Txn, link: Link, key: Value) -> TCResult<()> {
+        if txn.owner().is_none() {
+            return Err(TCError::unsupported(ERR_NO_OWNER));
+        }
+
+        let request = proto::DeleteRequest {
+            path: link.path().to_string(),
+            key: Some(encode_key(key)?),
+        };
+
+        self.connect(&link)?
+            .delete(request_in(txn, request)?)
+            .map_err(|status| error(&link, status))
+            .await?;
+
+        #[cfg(feature = "faults")]
+        crate::faults::peer_response(&link)?;
+
+        Ok(())
+    }
+}
+
+// a request with the ID and the auth token of the given transaction
+fn request_in<T>(txn: &Txn, message: T) -> TCResult<Request<T>> {
+    let mut request = Request::new(message);
+    let metadata_map = request.metadata_mut();
+    metadata_map.insert(TXN_ID, metadata(txn.id().to_string())?);
+    metadata_map.insert(DEPTH, metadata(txn.depth().to_string())?);
+
+    let token = format!("Bearer {}", txn.request().token());
+    metadata_map.insert("authorization", metadata(token)?);
+
+    Ok(request)
+}
+
+fn metadata(value: String) -> TCResult<AsciiMetadataValue> {
+    value
+        .parse()
+        .map_err(|e| TCError::bad_request("invalid gRPC metadata", e))
+}
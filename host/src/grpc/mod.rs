@@ -0,0 +1,21 @@
+//! The gRPC interface for `Gateway`, available with the "grpc" feature.
+//!
+//! The protocol is defined in `proto/tinychain.proto`. It has the same semantics as the HTTP
+//! interface, but `State`s are encoded as protobuf messages rather than JSON, which makes it a
+//! cheaper transport for calls between peers.
+
+mod client;
+mod server;
+mod state;
+
+pub use client::*;
+pub use server::*;
+
+mod proto {
+    tonic::include_proto!("tinychain");
+}
+
+const TXN_ID: &str = "txn-id";
+
+// the depth of the op call which sent a request, like the "x-tinychain-depth" HTTP header
+const DEPTH: &str = "call-depth";
@@ -0,0 +1,179 @@
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use futures::{future, TryFutureExt};
+use log::warn;
+use tonic::{Request, Response, Status};
+
+use tc_error::*;
+use tc_transact::TxnId;
+use tcgeneric::TCPathBuf;
+
+use crate::gateway::{self, Gateway};
+use crate::state::State;
+use crate::txn::*;
+
+use super::proto::gateway_server::{self, GatewayServer};
+use super::state::{decode, decode_key, encode, status};
+use super::{proto, DEPTH, TXN_ID};
+
+/// Tinychain's gRPC server. Should only be used through a [`Gateway`].
+pub struct GRPCServer {
+    gateway: Arc<Gateway>,
+}
+
+impl GRPCServer {
+    pub(crate) fn new(gateway: Arc<Gateway>) -> Self {
+        Self { gateway }
+    }
+
+    async fn txn<T>(&self, request: &Request<T>, write: bool) -> TCResult<Txn> {
+        let metadata = request.metadata();
+
+        let txn_id = if let Some(txn_id) = metadata.get(TXN_ID) {
+            txn_id
+                .to_str()
+                .map_err(|e| TCError::bad_request("unable to parse transaction ID", e))?
+                .parse()?
+        } else {
+            TxnId::new(Gateway::time())
+        };
+
+        let token = if let Some(header) = metadata.get("authorization") {
+            let token = header.to_str().map_err(|e| {
+                TCError::unauthorized(format!("unable to parse authorization metadata: {}", e))
+            })?;
+
+            if let Some(token) = token.strip_prefix("Bearer") {
+                Some(token.trim().to_string())
+            } else {
+                return Err(TCError::unauthorized(format!(
+                    "unable to parse authorization metadata: {} (should start with \"Bearer\"",
+                    token
+                )));
+            }
+        } else {
+            None
+        };
+
+        if let Some(client) = request.remote_addr() {
+            self.gateway
+                .rate_limit(client.ip(), token.as_deref(), write)
+                .map_err(gateway::too_many_requests)?;
+        }
+
+        let txn = self
+            .gateway
+            .new_txn(txn_id, token, Locale::default(), write)
+            .await?;
+
+        match metadata.get(DEPTH) {
+            Some(depth) => {
+                let depth = depth
+                    .to_str()
+                    .ok()
+                    .and_then(|depth| depth.parse().ok())
+                    .ok_or_else(|| TCError::bad_request("invalid call depth", DEPTH))?;
+
+                Ok(txn.resume(depth))
+            }
+            None => Ok(txn),
+        }
+    }
+}
+
+#[async_trait]
+impl gateway_server::Gateway for GRPCServer {
+    async fn get(
+        &self,
+        request: Request<proto::GetRequest>,
+    ) -> Result<Response<proto::Response>, Status> {
+        let txn = self.txn(&request, false).map_err(status).await?;
+        let request = request.into_inner();
+
+        let result = async {
+            let path: TCPathBuf = request.path.parse()?;
+            let key = decode_key(txn.clone(), request.key).await?;
+            self.gateway.get(&txn, path.into(), key).await
+        }
+        .await;
+
+        respond(txn, result).await
+    }
+
+    async fn put(
+        &self,
+        request: Request<proto::PutRequest>,
+    ) -> Result<Response<proto::Response>, Status> {
+        let txn = self.txn(&request, true).map_err(status).await?;
+        let request = request.into_inner();
+
+        let result = async {
+            let path: TCPathBuf = request.path.parse()?;
+            let key = decode_key(txn.clone(), request.key).await?;
+            let value = decode(txn.clone(), request.value).await?;
+            self.gateway
+                .put(&txn, path.into(), key, value)
+                .map_ok(State::from)
+                .await
+        }
+        .await;
+
+        respond(txn, result).await
+    }
+
+    async fn post(
+        &self,
+        request: Request<proto::PostRequest>,
+    ) -> Result<Response<proto::Response>, Status> {
+        let txn = self.txn(&request, true).map_err(status).await?;
+        let request = request.into_inner();
+
+        let result = async {
+            let path: TCPathBuf = request.path.parse()?;
+            let params = decode(txn.clone(), request.params).await?;
+            self.gateway.post(&txn, path.into(), params).await
+        }
+        .await;
+
+        respond(txn, result).await
+    }
+
+    async fn delete(
+        &self,
+        request: Request<proto::DeleteRequest>,
+    ) -> Result<Response<proto::Response>, Status> {
+        // like the HTTP server, this host doesn't yet support DELETE requests
+        let path = request.into_inner().path;
+        Err(status(TCError::method_not_allowed(path)))
+    }
+}
+
+#[async_trait]
+impl crate::gateway::Server for GRPCServer {
+    type Error = tonic::transport::Error;
+
+    async fn listen(self, addr: SocketAddr) -> Result<(), Self::Error> {
+        println!("gRPC server listening on {}", &addr);
+
+        tonic::transport::Server::builder()
+            .add_service(GatewayServer::new(self))
+            .serve_with_shutdown(addr, shutdown_signal())
+            .await
+    }
+}
+
+async fn respond(txn: Txn, result: TCResult<State>) -> Result<Response<proto::Response>, Status> {
+    let state = result.map_err(status)?;
+    let state = encode(txn, state).map_err(status).await?;
+    Ok(Response::new(proto::Response { state: Some(state) }))
+}
+
+// resolves on SIGINT, or never, if the signal handler can't be installed
+async fn shutdown_signal() {
+    if let Err(cause) = tokio::signal::ctrl_c().await {
+        warn!("unable to listen for a shutdown signal: {}", cause);
+        future::pending::<()>().await
+    }
+}
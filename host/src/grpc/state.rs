@@ -0,0 +1,322 @@
+//! The protobuf encoding of a `State`.
+
+use bytes::Bytes;
+use futures::future::{self, try_join_all, BoxFuture, FutureExt, TryFutureExt};
+use futures::{stream, TryStreamExt};
+use safecast::{CastFrom, TryCastFrom};
+use tonic::{Code, Status};
+
+use tc_error::*;
+use tc_transact::IntoView;
+use tcgeneric::{Id, Tuple};
+
+use crate::scalar::{DecodeContext, Link, Number, Scalar, Value};
+use crate::state::State;
+use crate::txn::Txn;
+
+use super::proto;
+use proto::state::Value as Native;
+
+/// Encode the given `state` as a protobuf message.
+pub(super) fn encode(txn: Txn, state: State) -> BoxFuture<'static, TCResult<proto::State>> {
+    async move {
+        let value = match state {
+            State::Map(map) => encode_map(txn, map.into_iter().collect()).await?,
+            State::Tuple(tuple) => encode_tuple(txn, tuple.into_iter().collect()).await?,
+            State::Scalar(Scalar::Map(map)) => {
+                let entries = map.into_iter().map(|(id, s)| (id, State::Scalar(s)));
+                encode_map(txn, entries.collect()).await?
+            }
+            State::Scalar(Scalar::Tuple(tuple)) => {
+                let items = tuple.into_iter().map(State::Scalar);
+                encode_tuple(txn, items.collect()).await?
+            }
+            State::Scalar(Scalar::Value(Value::Tuple(tuple))) => {
+                let items = tuple.into_iter().map(State::from);
+                encode_tuple(txn, items.collect()).await?
+            }
+            State::Scalar(Scalar::Value(value)) => match encode_value(value) {
+                Ok(native) => native,
+                Err(value) => encode_json(txn, value.into()).await?,
+            },
+            other => encode_json(txn, other).await?,
+        };
+
+        Ok(proto::State { value: Some(value) })
+    }
+    .boxed()
+}
+
+/// Encode the given `key` as a protobuf message.
+pub(super) fn encode_key(key: Value) -> TCResult<proto::State> {
+    let value = match key {
+        Value::Tuple(tuple) => Native::Tuple(proto::Tuple {
+            items: tuple
+                .into_iter()
+                .map(encode_key)
+                .collect::<TCResult<Vec<proto::State>>>()?,
+        }),
+        key => match encode_value(key) {
+            Ok(native) => native,
+            Err(key) => serde_json::to_vec(&key)
+                .map(Native::Json)
+                .map_err(|e| TCError::bad_request("unable to encode key", e))?,
+        },
+    };
+
+    Ok(proto::State { value: Some(value) })
+}
+
+/// Decode a `State` from the given protobuf message.
+pub(super) fn decode(txn: Txn, state: Option<proto::State>) -> BoxFuture<'static, TCResult<State>> {
+    async move {
+        let value = match state.and_then(|state| state.value) {
+            Some(value) => value,
+            None => return Ok(State::default()),
+        };
+
+        match value {
+            Native::None(_) => Ok(Value::None.into()),
+            Native::Bool(b) => Ok(Value::from(b).into()),
+            Native::Int(i) => Ok(Value::from(Number::from(i)).into()),
+            Native::Uint(u) => Ok(Value::from(Number::from(u)).into()),
+            Native::Float(f) => Ok(Value::from(Number::from(f)).into()),
+            Native::String(s) => Ok(Value::String(s.into()).into()),
+            Native::Bytes(bytes) => Ok(Value::from(Bytes::from(bytes)).into()),
+            Native::Link(link) => link.parse::<Link>().map(State::from),
+            Native::Tuple(tuple) => {
+                let items = tuple
+                    .items
+                    .into_iter()
+                    .map(|item| decode(txn.clone(), Some(item)));
+
+                try_join_all(items)
+                    .map_ok(Tuple::from)
+                    .map_ok(State::Tuple)
+                    .await
+            }
+            Native::Map(map) => {
+                let entries = map.entries.into_iter().map(|(id, state)| {
+                    let txn = txn.clone();
+                    async move {
+                        let id = Id::decode(&id)?;
+                        decode(txn, Some(state)).map_ok(|state| (id, state)).await
+                    }
+                });
+
+                let entries = try_join_all(entries).await?;
+                Ok(State::Map(entries.into_iter().collect()))
+            }
+            Native::Json(json) => decode_json(txn, json).await,
+        }
+    }
+    .boxed()
+}
+
+/// Decode a `Value` key from the given protobuf message.
+pub(super) async fn decode_key(txn: Txn, key: Option<proto::State>) -> TCResult<Value> {
+    let key = decode(txn, key).await?;
+    Value::try_cast_from(key, |k| TCError::bad_request("invalid key", k))
+}
+
+/// Convert the given protobuf message into JSON, without a transaction context.
+pub(super) fn to_json(state: Option<proto::State>) -> TCResult<serde_json::Value> {
+    let value = match state.and_then(|state| state.value) {
+        Some(value) => value,
+        None => return Ok(serde_json::Value::Null),
+    };
+
+    let as_json = |value: Value| {
+        serde_json::to_value(&value).map_err(|e| TCError::bad_request("invalid JSON", e))
+    };
+
+    match value {
+        Native::None(_) => Ok(serde_json::Value::Null),
+        Native::Bool(b) => Ok(b.into()),
+        Native::Int(i) => Ok(i.into()),
+        Native::Uint(u) => Ok(u.into()),
+        Native::Float(f) => Ok(f.into()),
+        Native::String(s) => Ok(s.into()),
+        Native::Bytes(bytes) => as_json(Value::from(Bytes::from(bytes))),
+        Native::Link(link) => link.parse::<Link>().map(Value::from).and_then(as_json),
+        Native::Tuple(tuple) => tuple
+            .items
+            .into_iter()
+            .map(|item| to_json(Some(item)))
+            .collect(),
+        Native::Map(map) => map
+            .entries
+            .into_iter()
+            .map(|(id, state)| to_json(Some(state)).map(|state| (id, state)))
+            .collect(),
+        Native::Json(json) => {
+            serde_json::from_slice(&json).map_err(|e| TCError::bad_request("invalid JSON", e))
+        }
+    }
+}
+
+/// Convert the given error into a gRPC [`Status`].
+pub(super) fn status(cause: TCError) -> Status {
+    use ErrorType::*;
+    let code = match cause.code() {
+        BadGateway => Code::Unknown,
+        BadRequest => Code::InvalidArgument,
+        Conflict => Code::Aborted,
+        Forbidden => Code::PermissionDenied,
+        Internal => Code::Internal,
+        MethodNotAllowed => Code::FailedPrecondition,
+        NotFound => Code::NotFound,
+        NotImplemented => Code::Unimplemented,
+        PayloadTooLarge => Code::OutOfRange,
+        PolicyViolation => Code::PermissionDenied,
+        Timeout => Code::DeadlineExceeded,
+        TooManyRequests => Code::ResourceExhausted,
+        Unauthorized => Code::Unauthenticated,
+        Unavailable => Code::Unavailable,
+    };
+
+    Status::new(code, cause.message())
+}
+
+/// Convert the given gRPC [`Status`] from the host at `source` into an error.
+pub(super) fn error(source: &Link, status: Status) -> TCError {
+    let code = match status.code() {
+        Code::InvalidArgument => ErrorType::BadRequest,
+        Code::Aborted => ErrorType::Conflict,
+        Code::PermissionDenied => ErrorType::Forbidden,
+        Code::Internal => ErrorType::Internal,
+        Code::FailedPrecondition => ErrorType::MethodNotAllowed,
+        Code::NotFound => ErrorType::NotFound,
+        Code::Unimplemented => ErrorType::NotImplemented,
+        Code::OutOfRange => ErrorType::PayloadTooLarge,
+        Code::DeadlineExceeded => ErrorType::Timeout,
+        Code::ResourceExhausted => ErrorType::TooManyRequests,
+        Code::Unauthenticated => ErrorType::Unauthorized,
+        Code::Unavailable => ErrorType::Unavailable,
+        _ => ErrorType::BadGateway,
+    };
+
+    let message = format!("error from upstream host {}: {}", source, status.message());
+    TCError::new(code, message)
+}
+
+// a native protobuf encoding of `value`, if it has one
+fn encode_value(value: Value) -> Result<Native, Value> {
+    match value {
+        Value::None => Ok(Native::None(proto::Empty {})),
+        Value::Bytes(bytes) => Ok(Native::Bytes(bytes.to_vec())),
+        Value::Link(link) => Ok(Native::Link(link.to_string())),
+        Value::Number(Number::Bool(b)) => Ok(Native::Bool(b.into())),
+        Value::Number(Number::Int(i)) => Ok(Native::Int(i64::cast_from(i))),
+        Value::Number(Number::UInt(u)) => Ok(Native::Uint(u64::cast_from(u))),
+        Value::Number(Number::Float(f)) => Ok(Native::Float(f64::cast_from(f))),
+        Value::String(s) => Ok(Native::String(s.to_string())),
+        other => Err(other),
+    }
+}
+
+async fn encode_map(txn: Txn, entries: Vec<(Id, State)>) -> TCResult<Native> {
+    let entries = entries
+        .into_iter()
+        .map(|(id, state)| encode(txn.clone(), state).map_ok(move |state| (id.to_string(), state)));
+
+    let entries = try_join_all(entries).await?;
+    Ok(Native::Map(proto::Map {
+        entries: entries.into_iter().collect(),
+    }))
+}
+
+async fn encode_tuple(txn: Txn, items: Vec<State>) -> TCResult<Native> {
+    let items = items.into_iter().map(|state| encode(txn.clone(), state));
+    let items = try_join_all(items).await?;
+    Ok(Native::Tuple(proto::Tuple { items }))
+}
+
+async fn encode_json(txn: Txn, state: State) -> TCResult<Native> {
+    let encoded = destream_json::encode(state.into_view(txn)).map_err(TCError::internal)?;
+    let json = encoded.map_err(TCError::internal).try_concat().await?;
+    Ok(Native::Json(json))
+}
+
+async fn decode_json(txn: Txn, json: Vec<u8>) -> TCResult<State> {
+    let data = stream::once(future::ready(Ok(json)));
+    let mut decoder = destream_json::de::Decoder::from(data);
+    State::decode(txn, DecodeContext::default(), &mut decoder)
+        .map_err(|e| TCError::bad_request("error deserializing gRPC message", e))
+        .await
+}
+
+#[cfg(test)]
+mod tests {
+    use std::convert::TryFrom;
+
+    use tcgeneric::{label, Map};
+
+    use crate::test::TestHost;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_encode_decode() -> TCResult<()> {
+        let host = TestHost::new(vec![]).await?;
+        let txn = host.new_txn(false).await?;
+
+        let name: Id = label("name").into();
+        let items: Id = label("items").into();
+        let mut map = Map::default();
+        map.insert(name.clone(), State::from(Value::String("alice".into())));
+        map.insert(
+            items.clone(),
+            State::Tuple(
+                vec![
+                    State::from(Value::from(Number::from(1u64))),
+                    State::from(Value::None),
+                ]
+                .into(),
+            ),
+        );
+
+        let encoded = encode(txn.clone(), State::Map(map)).await?;
+        assert_eq!(
+            to_json(Some(encoded.clone()))?,
+            serde_json::json!({"name": "alice", "items": [1, null]})
+        );
+
+        let map = match decode(txn.clone(), Some(encoded)).await? {
+            State::Map(map) => map,
+            other => panic!("expected a Map but found {}", other),
+        };
+
+        assert!(Value::try_from(map[&name].clone())? == Value::String("alice".into()));
+        match &map[&items] {
+            State::Tuple(items) => assert_eq!(items.len(), 2),
+            other => panic!("expected a Tuple but found {}", other),
+        }
+
+        let key = Value::Tuple(vec![Value::from(Number::from(2u64))].into());
+        let key = decode_key(txn, Some(encode_key(key)?)).await?;
+        assert!(key == Value::Tuple(vec![Value::from(Number::from(2u64))].into()));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_status() {
+        let source: Link = "http://127.0.0.1:8702/app".parse().unwrap();
+
+        for code in &[
+            ErrorType::BadRequest,
+            ErrorType::Conflict,
+            ErrorType::NotFound,
+            ErrorType::PayloadTooLarge,
+            ErrorType::TooManyRequests,
+            ErrorType::Unauthorized,
+        ] {
+            let cause = TCError::new(*code, "message".to_string());
+            assert!(error(&source, status(cause)).code() == *code);
+        }
+
+        let cause = TCError::bad_gateway("message");
+        assert!(error(&source, status(cause)).code() == ErrorType::BadGateway);
+    }
+}
@@ -0,0 +1,544 @@
+//! Streaming encoders for the binary encodings of a `State`.
+//!
+//! A CBOR or MessagePack response is encoded natively rather than transcoded from JSON, so that a
+//! byte string is written as a byte string instead of the base64 string it would be in JSON.
+//!
+//! A CBOR map or array whose length isn't known up front is written with an indefinite length, so
+//! that it's never buffered. MessagePack needs the length of each map and array up front, so only
+//! a map or array encoded from a stream is buffered, until its length is known.
+
+use std::collections::VecDeque;
+use std::convert::TryFrom;
+use std::fmt;
+use std::pin::Pin;
+
+use destream::en::{self, IntoStream};
+use futures::future::{self, TryFutureExt};
+use futures::stream::{self, Stream, StreamExt, TryStreamExt};
+
+pub(super) type ByteStream<'en> =
+    Pin<Box<dyn Stream<Item = Result<Vec<u8>, Error>> + Send + Unpin + 'en>>;
+
+// CBOR major types
+const UNSIGNED: u8 = 0;
+const NEGATIVE: u8 = 1;
+const BYTES: u8 = 2;
+const TEXT: u8 = 3;
+const ARRAY: u8 = 4;
+const MAP: u8 = 5;
+
+// CBOR simple values and markers
+const FALSE: u8 = 0xf4;
+const TRUE: u8 = 0xf5;
+const NULL: u8 = 0xf6;
+const FLOAT32: u8 = 0xfa;
+const FLOAT64: u8 = 0xfb;
+const BREAK: u8 = 0xff;
+
+/// An error encountered while encoding a stream.
+pub(super) struct Error {
+    message: String,
+}
+
+impl std::error::Error for Error {}
+
+impl en::Error for Error {
+    fn custom<I: fmt::Display>(info: I) -> Self {
+        let message = info.to_string();
+        Self { message }
+    }
+}
+
+impl fmt::Debug for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Display::fmt(self, f)
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+#[derive(Clone, Copy)]
+enum Format {
+    Cbor,
+    Msgpack,
+}
+
+impl Format {
+    fn map_head(self, len: usize) -> Result<Vec<u8>, Error> {
+        match self {
+            Self::Cbor => Ok(head(MAP, len as u64)),
+            Self::Msgpack => {
+                let len = length(len)?;
+                msgpack(|encoded| rmp::encode::write_map_len(encoded, len))
+            }
+        }
+    }
+
+    fn array_head(self, len: usize) -> Result<Vec<u8>, Error> {
+        match self {
+            Self::Cbor => Ok(head(ARRAY, len as u64)),
+            Self::Msgpack => {
+                let len = length(len)?;
+                msgpack(|encoded| rmp::encode::write_array_len(encoded, len))
+            }
+        }
+    }
+
+    // a map or array whose entries are only known as they're encoded
+    fn stream<'en, S>(self, major: u8, entries: S) -> ByteStream<'en>
+    where
+        S: Stream<Item = Result<ByteStream<'en>, Error>> + Send + Unpin + 'en,
+    {
+        match self {
+            Self::Cbor => {
+                // an indefinite-length item is terminated by a "break" marker
+                let begin = chunk(vec![(major << 5) | 31]);
+                let entries = entries.try_flatten();
+                Box::pin(begin.chain(entries).chain(chunk(vec![BREAK])))
+            }
+            Self::Msgpack => {
+                let buffered = entries.try_fold((0, Vec::new()), |(len, mut buffer), entry| {
+                    entry.try_concat().map_ok(move |encoded| {
+                        buffer.extend(encoded);
+                        (len + 1, buffer)
+                    })
+                });
+
+                let encoded = buffered.and_then(move |(len, buffer)| {
+                    let head = if major == MAP {
+                        self.map_head(len)
+                    } else {
+                        self.array_head(len)
+                    };
+
+                    future::ready(head.map(|mut encoded| {
+                        encoded.extend(buffer);
+                        encoded
+                    }))
+                });
+
+                Box::pin(stream::once(encoded))
+            }
+        }
+    }
+}
+
+struct MapEncoder<'en> {
+    format: Format,
+    pending_key: Option<ByteStream<'en>>,
+    entries: VecDeque<(ByteStream<'en>, ByteStream<'en>)>,
+}
+
+impl<'en> MapEncoder<'en> {
+    fn new(format: Format, size_hint: Option<usize>) -> Self {
+        Self {
+            format,
+            pending_key: None,
+            entries: VecDeque::with_capacity(size_hint.unwrap_or_default()),
+        }
+    }
+}
+
+impl<'en> en::EncodeMap<'en> for MapEncoder<'en> {
+    type Ok = ByteStream<'en>;
+    type Error = Error;
+
+    fn encode_key<T: IntoStream<'en> + 'en>(&mut self, key: T) -> Result<(), Self::Error> {
+        if self.pending_key.is_none() {
+            self.pending_key = Some(key.into_stream(Encoder::new(self.format))?);
+            Ok(())
+        } else {
+            Err(en::Error::custom(
+                "You must call encode_value before calling encode_key again",
+            ))
+        }
+    }
+
+    fn encode_value<T: IntoStream<'en> + 'en>(&mut self, value: T) -> Result<(), Self::Error> {
+        let key = self
+            .pending_key
+            .take()
+            .ok_or_else(|| en::Error::custom("You must call encode_key before encode_value"))?;
+
+        let value = value.into_stream(Encoder::new(self.format))?;
+        self.entries.push_back((key, value));
+        Ok(())
+    }
+
+    fn end(self) -> Result<Self::Ok, Self::Error> {
+        if self.pending_key.is_some() {
+            return Err(en::Error::custom(
+                "You must call encode_value after calling encode_key",
+            ));
+        }
+
+        let head = chunk(self.format.map_head(self.entries.len())?);
+        let entries = stream::iter(self.entries).flat_map(|(key, value)| key.chain(value));
+        Ok(Box::pin(head.chain(entries)))
+    }
+}
+
+struct SequenceEncoder<'en> {
+    format: Format,
+    items: VecDeque<ByteStream<'en>>,
+}
+
+impl<'en> SequenceEncoder<'en> {
+    fn new(format: Format, size_hint: Option<usize>) -> Self {
+        Self {
+            format,
+            items: VecDeque::with_capacity(size_hint.unwrap_or_default()),
+        }
+    }
+
+    fn push<T: IntoStream<'en> + 'en>(&mut self, value: T) -> Result<(), Error> {
+        let encoded = value.into_stream(Encoder::new(self.format))?;
+        self.items.push_back(encoded);
+        Ok(())
+    }
+
+    fn encode(self) -> Result<ByteStream<'en>, Error> {
+        let head = chunk(self.format.array_head(self.items.len())?);
+        Ok(Box::pin(head.chain(stream::iter(self.items).flatten())))
+    }
+}
+
+impl<'en> en::EncodeSeq<'en> for SequenceEncoder<'en> {
+    type Ok = ByteStream<'en>;
+    type Error = Error;
+
+    fn encode_element<T: IntoStream<'en> + 'en>(&mut self, value: T) -> Result<(), Self::Error> {
+        self.push(value)
+    }
+
+    fn end(self) -> Result<Self::Ok, Self::Error> {
+        self.encode()
+    }
+}
+
+impl<'en> en::EncodeTuple<'en> for SequenceEncoder<'en> {
+    type Ok = ByteStream<'en>;
+    type Error = Error;
+
+    fn encode_element<T: IntoStream<'en> + 'en>(&mut self, value: T) -> Result<(), Self::Error> {
+        self.push(value)
+    }
+
+    fn end(self) -> Result<Self::Ok, Self::Error> {
+        self.encode()
+    }
+}
+
+struct Encoder {
+    format: Format,
+}
+
+impl Encoder {
+    fn new(format: Format) -> Self {
+        Self { format }
+    }
+}
+
+impl<'en> en::Encoder<'en> for Encoder {
+    type Ok = ByteStream<'en>;
+    type Error = Error;
+    type EncodeMap = MapEncoder<'en>;
+    type EncodeSeq = SequenceEncoder<'en>;
+    type EncodeTuple = SequenceEncoder<'en>;
+
+    fn encode_bool(self, v: bool) -> Result<Self::Ok, Self::Error> {
+        match self.format {
+            Format::Cbor => Ok(chunk(vec![if v { TRUE } else { FALSE }])),
+            Format::Msgpack => msgpack(|encoded| rmp::encode::write_bool(encoded, v)).map(chunk),
+        }
+    }
+
+    fn encode_i8(self, v: i8) -> Result<Self::Ok, Self::Error> {
+        self.encode_i64(v.into())
+    }
+
+    fn encode_i16(self, v: i16) -> Result<Self::Ok, Self::Error> {
+        self.encode_i64(v.into())
+    }
+
+    fn encode_i32(self, v: i32) -> Result<Self::Ok, Self::Error> {
+        self.encode_i64(v.into())
+    }
+
+    fn encode_i64(self, v: i64) -> Result<Self::Ok, Self::Error> {
+        match self.format {
+            // a negative integer -1 - n is encoded as n
+            Format::Cbor if v < 0 => Ok(chunk(head(NEGATIVE, !v as u64))),
+            Format::Cbor => Ok(chunk(head(UNSIGNED, v as u64))),
+            Format::Msgpack => msgpack(|encoded| rmp::encode::write_sint(encoded, v)).map(chunk),
+        }
+    }
+
+    fn encode_u8(self, v: u8) -> Result<Self::Ok, Self::Error> {
+        self.encode_u64(v.into())
+    }
+
+    fn encode_u16(self, v: u16) -> Result<Self::Ok, Self::Error> {
+        self.encode_u64(v.into())
+    }
+
+    fn encode_u32(self, v: u32) -> Result<Self::Ok, Self::Error> {
+        self.encode_u64(v.into())
+    }
+
+    fn encode_u64(self, v: u64) -> Result<Self::Ok, Self::Error> {
+        match self.format {
+            Format::Cbor => Ok(chunk(head(UNSIGNED, v))),
+            Format::Msgpack => msgpack(|encoded| rmp::encode::write_uint(encoded, v)).map(chunk),
+        }
+    }
+
+    fn encode_f32(self, v: f32) -> Result<Self::Ok, Self::Error> {
+        match self.format {
+            Format::Cbor => {
+                let mut encoded = vec![FLOAT32];
+                encoded.extend_from_slice(&v.to_be_bytes());
+                Ok(chunk(encoded))
+            }
+            Format::Msgpack => msgpack(|encoded| rmp::encode::write_f32(encoded, v)).map(chunk),
+        }
+    }
+
+    fn encode_f64(self, v: f64) -> Result<Self::Ok, Self::Error> {
+        match self.format {
+            Format::Cbor => {
+                let mut encoded = vec![FLOAT64];
+                encoded.extend_from_slice(&v.to_be_bytes());
+                Ok(chunk(encoded))
+            }
+            Format::Msgpack => msgpack(|encoded| rmp::encode::write_f64(encoded, v)).map(chunk),
+        }
+    }
+
+    fn encode_str(self, v: &str) -> Result<Self::Ok, Self::Error> {
+        let mut encoded = match self.format {
+            Format::Cbor => head(TEXT, v.len() as u64),
+            Format::Msgpack => {
+                let len = length(v.len())?;
+                msgpack(|encoded| rmp::encode::write_str_len(encoded, len))?
+            }
+        };
+
+        encoded.extend_from_slice(v.as_bytes());
+        Ok(chunk(encoded))
+    }
+
+    fn encode_bytes(self, v: &[u8]) -> Result<Self::Ok, Self::Error> {
+        let mut encoded = match self.format {
+            Format::Cbor => head(BYTES, v.len() as u64),
+            Format::Msgpack => {
+                let len = length(v.len())?;
+                msgpack(|encoded| rmp::encode::write_bin_len(encoded, len))?
+            }
+        };
+
+        encoded.extend_from_slice(v);
+        Ok(chunk(encoded))
+    }
+
+    fn encode_none(self) -> Result<Self::Ok, Self::Error> {
+        match self.format {
+            Format::Cbor => Ok(chunk(vec![NULL])),
+            Format::Msgpack => msgpack(rmp::encode::write_nil).map(chunk),
+        }
+    }
+
+    fn encode_some<T: IntoStream<'en> + 'en>(self, value: T) -> Result<Self::Ok, Self::Error> {
+        value.into_stream(self)
+    }
+
+    fn encode_unit(self) -> Result<Self::Ok, Self::Error> {
+        self.encode_none()
+    }
+
+    fn encode_map(self, size_hint: Option<usize>) -> Result<Self::EncodeMap, Self::Error> {
+        Ok(MapEncoder::new(self.format, size_hint))
+    }
+
+    fn encode_map_stream<
+        K: IntoStream<'en> + 'en,
+        V: IntoStream<'en> + 'en,
+        S: Stream<Item = Result<(K, V), Self::Error>> + Send + Unpin + 'en,
+    >(
+        self,
+        map: S,
+    ) -> Result<Self::Ok, Self::Error> {
+        let format = self.format;
+        let entries = map.map(move |entry| {
+            entry.and_then(|(key, value)| {
+                let key = key.into_stream(Encoder::new(format))?;
+                let value = value.into_stream(Encoder::new(format))?;
+                Ok(Box::pin(key.chain(value)) as ByteStream<'en>)
+            })
+        });
+
+        Ok(format.stream(MAP, entries))
+    }
+
+    fn encode_seq(self, size_hint: Option<usize>) -> Result<Self::EncodeSeq, Self::Error> {
+        Ok(SequenceEncoder::new(self.format, size_hint))
+    }
+
+    fn encode_seq_stream<
+        T: IntoStream<'en> + 'en,
+        S: Stream<Item = Result<T, Self::Error>> + Send + Unpin + 'en,
+    >(
+        self,
+        seq: S,
+    ) -> Result<Self::Ok, Self::Error> {
+        let format = self.format;
+        let items =
+            seq.map(move |item| item.and_then(|item| item.into_stream(Encoder::new(format))));
+
+        Ok(format.stream(ARRAY, items))
+    }
+
+    fn encode_tuple(self, len: usize) -> Result<Self::EncodeTuple, Self::Error> {
+        Ok(SequenceEncoder::new(self.format, Some(len)))
+    }
+}
+
+// the head of a CBOR data item with the given major type and argument
+fn head(major: u8, arg: u64) -> Vec<u8> {
+    let major = major << 5;
+
+    if arg < 24 {
+        vec![major | arg as u8]
+    } else if arg <= u8::MAX as u64 {
+        vec![major | 24, arg as u8]
+    } else if arg <= u16::MAX as u64 {
+        let mut head = vec![major | 25];
+        head.extend_from_slice(&(arg as u16).to_be_bytes());
+        head
+    } else if arg <= u32::MAX as u64 {
+        let mut head = vec![major | 26];
+        head.extend_from_slice(&(arg as u32).to_be_bytes());
+        head
+    } else {
+        let mut head = vec![major | 27];
+        head.extend_from_slice(&arg.to_be_bytes());
+        head
+    }
+}
+
+// the length of a MessagePack string, byte string, map, or array, which must fit in 32 bits
+fn length(len: usize) -> Result<u32, Error> {
+    u32::try_from(len).map_err(|_| en::Error::custom("MessagePack data is too long"))
+}
+
+fn msgpack<T, E, W>(write: W) -> Result<Vec<u8>, Error>
+where
+    E: fmt::Display,
+    W: FnOnce(&mut Vec<u8>) -> Result<T, E>,
+{
+    let mut encoded = Vec::new();
+    write(&mut encoded).map_err(en::Error::custom)?;
+    Ok(encoded)
+}
+
+fn chunk<'en>(encoded: Vec<u8>) -> ByteStream<'en> {
+    Box::pin(stream::once(future::ready(Ok(encoded))))
+}
+
+/// Given an encodable value, return a CBOR-encoded stream.
+pub(super) fn encode_cbor<'en, T: IntoStream<'en> + 'en>(
+    value: T,
+) -> Result<ByteStream<'en>, Error> {
+    value.into_stream(Encoder::new(Format::Cbor))
+}
+
+/// Given an encodable value, return a MessagePack-encoded stream.
+pub(super) fn encode_msgpack<'en, T: IntoStream<'en> + 'en>(
+    value: T,
+) -> Result<ByteStream<'en>, Error> {
+    value.into_stream(Encoder::new(Format::Msgpack))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use destream::en::EncodeMap;
+    use serde_cbor::Value;
+
+    use super::*;
+
+    struct Entries(Vec<(String, u64)>);
+
+    impl<'en> IntoStream<'en> for Entries {
+        fn into_stream<E: en::Encoder<'en>>(self, encoder: E) -> Result<E::Ok, E::Error> {
+            encoder.encode_map_stream(stream::iter(self.0.into_iter().map(Ok)))
+        }
+    }
+
+    struct Bytes(Vec<u8>);
+
+    impl<'en> IntoStream<'en> for Bytes {
+        fn into_stream<E: en::Encoder<'en>>(self, encoder: E) -> Result<E::Ok, E::Error> {
+            encoder.encode_bytes(&self.0)
+        }
+    }
+
+    struct Message;
+
+    impl<'en> IntoStream<'en> for Message {
+        fn into_stream<E: en::Encoder<'en>>(self, encoder: E) -> Result<E::Ok, E::Error> {
+            let mut map = encoder.encode_map(Some(3))?;
+            map.encode_entry("bytes", Bytes(vec![0, 1, 255]))?;
+            map.encode_entry("numbers", (-1000i64, 24u8, 0.5f64, u64::MAX))?;
+            map.encode_entry(
+                "stream",
+                Entries(vec![("a".to_string(), 1), ("b".to_string(), 2)]),
+            )?;
+            map.end()
+        }
+    }
+
+    fn expected() -> Value {
+        let mut entries = BTreeMap::new();
+        entries.insert(Value::Text("a".into()), Value::Integer(1));
+        entries.insert(Value::Text("b".into()), Value::Integer(2));
+
+        let mut expected = BTreeMap::new();
+        expected.insert(Value::Text("bytes".into()), Value::Bytes(vec![0, 1, 255]));
+        expected.insert(
+            Value::Text("numbers".into()),
+            Value::Array(vec![
+                Value::Integer(-1000),
+                Value::Integer(24),
+                Value::Float(0.5),
+                Value::Integer(u64::MAX.into()),
+            ]),
+        );
+        expected.insert(Value::Text("stream".into()), Value::Map(entries));
+
+        Value::Map(expected)
+    }
+
+    #[tokio::test]
+    async fn test_encode_cbor() {
+        let encoded: Vec<u8> = encode_cbor(Message).unwrap().try_concat().await.unwrap();
+        let decoded: Value = serde_cbor::from_slice(&encoded).unwrap();
+        assert_eq!(decoded, expected());
+    }
+
+    #[tokio::test]
+    async fn test_encode_msgpack() {
+        let encoded: Vec<u8> = encode_msgpack(Message).unwrap().try_concat().await.unwrap();
+
+        // the byte string is encoded as MessagePack "bin 8"
+        assert!(encoded.windows(5).any(|data| data == [0xc4, 3, 0, 1, 255]));
+
+        let decoded: Value = rmp_serde::from_slice(&encoded).unwrap();
+        assert_eq!(decoded, expected());
+    }
+}
@@ -0,0 +1,388 @@
+//! Incremental decoding of HTTP request bodies.
+//!
+//! A large request body is spooled to the transaction workspace one block at a time as it's
+//! received, then read back as it's decoded, so that it's never buffered in memory all at once.
+//! A CBOR or MessagePack body is transcoded to JSON incrementally, on a blocking thread.
+//! A body larger than the configured request limit is rejected as soon as it's known to be.
+
+use std::convert::TryFrom;
+use std::io::{self, Read, Write};
+
+use bytes::{Bytes, BytesMut};
+use futures::stream::{self, BoxStream, StreamExt, TryStreamExt};
+use hyper::header::{HeaderMap, CONTENT_LENGTH};
+use hyper::Body;
+use tokio::sync::mpsc;
+use uuid::Uuid;
+
+use tc_error::*;
+use tc_transact::fs::{Dir, File};
+use tc_transact::{Transaction, TxnId};
+use tcgeneric::Id;
+
+use crate::fs;
+use crate::scalar::{ScalarType, ValueType};
+use crate::state::StateType;
+use crate::txn::Txn;
+
+use super::encoding::Encoding;
+
+/// The default maximum size of a request body which isn't a multipart upload, in bytes.
+pub const DEFAULT_REQUEST_LIMIT: u64 = 64_000_000;
+
+// the size of each block of a spooled request body
+pub(super) const BLOCK_SIZE: usize = 65_536;
+
+// the number of chunks to buffer between the transcoder and the network or the decoder
+const CHANNEL_SIZE: usize = 4;
+
+// the number of blocks of a spooled request body to read ahead of the decoder
+const READ_AHEAD: usize = 4;
+
+// a request body larger than this is spooled to the workspace before it's decoded
+const SPOOL_THRESHOLD: u64 = 1_000_000;
+
+type Chunks = BoxStream<'static, TCResult<Bytes>>;
+
+/// Stream the body of a request with the given `headers`, as JSON.
+///
+/// A body with no `Content-Length`, or one larger than one megabyte, is first spooled to the
+/// workspace of the given `txn`. A body larger than `limit` bytes is rejected.
+pub(crate) async fn json_chunks(
+    txn: &Txn,
+    headers: &HeaderMap,
+    body: Body,
+    encoding: Encoding,
+    limit: u64,
+) -> TCResult<BoxStream<'static, TCResult<Vec<u8>>>> {
+    let chunks = body
+        .map_err(|e| TCError::bad_request("error reading HTTP request body", e))
+        .boxed();
+
+    let chunks = match content_length(headers)? {
+        Some(length) if length > limit => return Err(too_large(limit)),
+        Some(length) if length <= SPOOL_THRESHOLD => chunks,
+        _ => spool(txn, chunks, limit).await?,
+    };
+
+    match encoding {
+        Encoding::Json => Ok(chunks.map_ok(|chunk| chunk.to_vec()).boxed()),
+        encoding => Ok(transcode(encoding, chunks)),
+    }
+}
+
+// write the given `chunks` to a new file in the workspace, then stream them back
+async fn spool(txn: &Txn, mut chunks: Chunks, limit: u64) -> TCResult<Chunks> {
+    let txn_id = *txn.id();
+    let dir = txn
+        .context()
+        .create_dir(txn_id, Uuid::new_v4().into())
+        .await?;
+
+    let class = StateType::Scalar(ScalarType::Value(ValueType::Bytes));
+    let file = dir.create_file(txn_id, block_id(0)?, class).await?;
+    let file = fs::File::<Bytes>::try_from(file)?;
+
+    let mut block_ids = Vec::new();
+    let mut buffer = BytesMut::with_capacity(BLOCK_SIZE);
+    let mut size = 0u64;
+    while let Some(chunk) = chunks.try_next().await? {
+        size += chunk.len() as u64;
+        if size > limit {
+            return Err(too_large(limit));
+        }
+
+        buffer.extend_from_slice(&chunk);
+
+        while buffer.len() >= BLOCK_SIZE {
+            let block = buffer.split_to(BLOCK_SIZE).freeze();
+            write_block(&file, txn_id, &mut block_ids, block).await?;
+        }
+    }
+
+    if !buffer.is_empty() {
+        write_block(&file, txn_id, &mut block_ids, buffer.freeze()).await?;
+    }
+
+    let blocks = file
+        .read_ahead(txn_id, block_ids, READ_AHEAD)
+        .map_ok(|block| Bytes::clone(&*block));
+
+    Ok(blocks.boxed())
+}
+
+pub(super) async fn write_block(
+    file: &fs::File<Bytes>,
+    txn_id: TxnId,
+    block_ids: &mut Vec<Id>,
+    block: Bytes,
+) -> TCResult<()> {
+    let block_id = block_id(block_ids.len())?;
+    file.create_block(txn_id, block_id.clone(), block).await?;
+    block_ids.push(block_id);
+    Ok(())
+}
+
+// transcode a CBOR or MessagePack message to JSON, one chunk at a time
+fn transcode(encoding: Encoding, mut chunks: Chunks) -> BoxStream<'static, TCResult<Vec<u8>>> {
+    let (input, reader) = mpsc::channel(CHANNEL_SIZE);
+    let (writer, output) = mpsc::channel(CHANNEL_SIZE);
+
+    tokio::spawn(async move {
+        while let Some(chunk) = chunks.next().await {
+            let failed = chunk.is_err();
+            if input.send(chunk).await.is_err() || failed {
+                break;
+            }
+        }
+    });
+
+    tokio::task::spawn_blocking(move || {
+        let reader = ChannelReader {
+            chunks: reader,
+            chunk: Bytes::new(),
+        };
+
+        let mut writer = ChannelWriter {
+            chunks: writer,
+            buffer: Vec::with_capacity(BLOCK_SIZE),
+        };
+
+        let result = transcode_to_json(encoding, reader, &mut writer);
+        let result = result.and_then(|()| {
+            writer
+                .flush()
+                .map_err(|e| TCError::internal(format!("unable to decode request: {}", e)))
+        });
+
+        if let Err(cause) = result {
+            writer.chunks.blocking_send(Err(cause)).ok();
+        }
+    });
+
+    stream::unfold(output, |mut output| async move {
+        output.recv().await.map(|chunk| (chunk, output))
+    })
+    .boxed()
+}
+
+fn transcode_to_json(
+    encoding: Encoding,
+    mut reader: ChannelReader,
+    writer: &mut ChannelWriter,
+) -> TCResult<()> {
+    match encoding {
+        Encoding::Cbor => {
+            let mut deserializer = serde_cbor::Deserializer::from_reader(reader);
+            let mut serializer = serde_json::Serializer::new(writer);
+
+            serde_transcode::transcode(&mut deserializer, &mut serializer)
+                .map_err(|e| TCError::bad_request("invalid CBOR", e))?;
+
+            deserializer
+                .end()
+                .map_err(|e| TCError::bad_request("invalid CBOR", e))
+        }
+        Encoding::Json => io::copy(&mut reader, writer)
+            .map(|_| ())
+            .map_err(|e| TCError::bad_request("error reading HTTP request body", e)),
+        Encoding::Msgpack => {
+            let mut deserializer = rmp_serde::Deserializer::new(reader);
+            let mut serializer = serde_json::Serializer::new(writer);
+
+            serde_transcode::transcode(&mut deserializer, &mut serializer)
+                .map_err(|e| TCError::bad_request("invalid MessagePack", e))
+        }
+    }
+}
+
+// a blocking reader of the chunks of a request body
+struct ChannelReader {
+    chunks: mpsc::Receiver<TCResult<Bytes>>,
+    chunk: Bytes,
+}
+
+impl Read for ChannelReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.chunk.is_empty() {
+            match self.chunks.blocking_recv() {
+                Some(Ok(chunk)) => self.chunk = chunk,
+                Some(Err(cause)) => return Err(io::Error::other(cause)),
+                None => return Ok(0),
+            }
+        }
+
+        let len = buf.len().min(self.chunk.len());
+        buf[..len].copy_from_slice(&self.chunk.split_to(len));
+        Ok(len)
+    }
+}
+
+// a blocking writer of the chunks of a transcoded request body
+struct ChannelWriter {
+    chunks: mpsc::Sender<TCResult<Vec<u8>>>,
+    buffer: Vec<u8>,
+}
+
+impl Write for ChannelWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.buffer.extend_from_slice(buf);
+        if self.buffer.len() >= BLOCK_SIZE {
+            self.flush()?;
+        }
+
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        if self.buffer.is_empty() {
+            return Ok(());
+        }
+
+        let chunk = std::mem::replace(&mut self.buffer, Vec::with_capacity(BLOCK_SIZE));
+        self.chunks
+            .blocking_send(Ok(chunk))
+            .map_err(|_| io::Error::new(io::ErrorKind::BrokenPipe, "request decoder hung up"))
+    }
+}
+
+fn content_length(headers: &HeaderMap) -> TCResult<Option<u64>> {
+    match headers.get(CONTENT_LENGTH) {
+        Some(length) => length
+            .to_str()
+            .ok()
+            .and_then(|length| length.parse().ok())
+            .map(Some)
+            .ok_or_else(|| {
+                TCError::bad_request("invalid Content-Length header", format!("{:?}", length))
+            }),
+        None => Ok(None),
+    }
+}
+
+fn too_large(limit: u64) -> TCError {
+    TCError::payload_too_large(format!(
+        "a request body may not be larger than {} bytes",
+        limit
+    ))
+}
+
+#[inline]
+pub(super) fn block_id(i: usize) -> TCResult<Id> {
+    i.to_string().parse()
+}
+
+#[cfg(test)]
+mod tests {
+    use hyper::header::HeaderValue;
+
+    use crate::test::TestHost;
+
+    use super::*;
+
+    async fn collect(chunks: BoxStream<'static, TCResult<Vec<u8>>>) -> TCResult<Vec<u8>> {
+        chunks
+            .try_fold(Vec::new(), |mut body, chunk| async move {
+                body.extend(chunk);
+                Ok(body)
+            })
+            .await
+    }
+
+    #[tokio::test]
+    async fn test_spool() -> TCResult<()> {
+        let host = TestHost::new(vec![]).await?;
+        let txn = host.new_txn(false).await?;
+
+        // a body with no Content-Length is spooled, across several blocks
+        let body: Vec<u8> = (0..(2 * BLOCK_SIZE + 7)).map(|i| (i % 251) as u8).collect();
+        let chunks = json_chunks(
+            &txn,
+            &HeaderMap::new(),
+            Body::from(body.clone()),
+            Encoding::Json,
+            DEFAULT_REQUEST_LIMIT,
+        )
+        .await?;
+
+        assert_eq!(collect(chunks).await?, body);
+
+        // a small body with a Content-Length is streamed as-is
+        let mut headers = HeaderMap::new();
+        headers.insert(CONTENT_LENGTH, HeaderValue::from_static("2"));
+        let chunks = json_chunks(
+            &txn,
+            &headers,
+            Body::from("{}"),
+            Encoding::Json,
+            DEFAULT_REQUEST_LIMIT,
+        )
+        .await?;
+        assert_eq!(collect(chunks).await?, b"{}".to_vec());
+
+        headers.insert(CONTENT_LENGTH, HeaderValue::from_static("two"));
+        let result = json_chunks(
+            &txn,
+            &headers,
+            Body::from("{}"),
+            Encoding::Json,
+            DEFAULT_REQUEST_LIMIT,
+        )
+        .await;
+        assert_eq!(
+            result.map(|_| ()).unwrap_err().code(),
+            ErrorType::BadRequest
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_request_limit() -> TCResult<()> {
+        let host = TestHost::new(vec![]).await?;
+        let txn = host.new_txn(false).await?;
+        let limit = 2 * BLOCK_SIZE as u64;
+
+        // a chunked body with no Content-Length is rejected once it exceeds the limit
+        let chunks = (0..3).map(|_| Ok::<_, io::Error>(Bytes::from(vec![0u8; BLOCK_SIZE])));
+        let body = Body::wrap_stream(stream::iter(chunks));
+        let result = json_chunks(&txn, &HeaderMap::new(), body, Encoding::Json, limit).await;
+        assert_eq!(
+            result.map(|_| ()).unwrap_err().code(),
+            ErrorType::PayloadTooLarge
+        );
+
+        // a body whose Content-Length exceeds the limit is rejected before it's read
+        let mut headers = HeaderMap::new();
+        headers.insert(CONTENT_LENGTH, HeaderValue::from_static("131073"));
+        let result = json_chunks(&txn, &headers, Body::empty(), Encoding::Json, limit).await;
+        assert_eq!(
+            result.map(|_| ()).unwrap_err().code(),
+            ErrorType::PayloadTooLarge
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_transcode() -> TCResult<()> {
+        let json = br#"{"a":[1,-2,3.5,"four",null,true]}"#.to_vec();
+        let cbor = serde_json::from_slice::<serde_json::Value>(&json)
+            .map(|json| serde_cbor::to_vec(&json).unwrap())
+            .unwrap();
+
+        // split the message into single bytes to check that it's decoded incrementally
+        let chunks = stream::iter(cbor.into_iter().map(|byte| Ok(Bytes::from(vec![byte]))));
+        let decoded = collect(transcode(Encoding::Cbor, chunks.boxed())).await?;
+        assert_eq!(
+            serde_json::from_slice::<serde_json::Value>(&decoded).unwrap(),
+            serde_json::from_slice::<serde_json::Value>(&json).unwrap()
+        );
+
+        let invalid = stream::once(async { Ok(Bytes::from_static(&[0xff, 0x00])) });
+        let result = collect(transcode(Encoding::Cbor, invalid.boxed())).await;
+        assert_eq!(result.unwrap_err().code(), ErrorType::BadRequest);
+
+        Ok(())
+    }
+}
@@ -1,7 +1,7 @@
 use std::time::Duration;
 
 use async_trait::async_trait;
-use futures::{StreamExt, TryFutureExt, TryStreamExt};
+use futures::{future, stream, StreamExt, TryFutureExt, TryStreamExt};
 use hyper::body::{Body, HttpBody};
 use hyper::client::HttpConnector;
 use log::debug;
@@ -16,9 +16,17 @@ use tcgeneric::label;
 use crate::state::State;
 use crate::txn::Txn;
 
+use super::compression;
+use super::encoding::Encoding;
+use super::DEPTH;
+
 const IDLE_TIMEOUT: u64 = 30;
+const KEEP_ALIVE: u64 = 20;
 const ERR_NO_OWNER: &str = "an ownerless transaction may not make outgoing requests";
 
+// prefer a binary response from a peer, which may be an older host that only responds with JSON
+const ACCEPT: &str = "application/cbor, application/json";
+
 /// A Tinychain HTTP client. Should only be used through a `Gateway`.
 pub struct Client {
     client: hyper::Client<HttpConnector, Body>,
@@ -26,10 +34,15 @@ pub struct Client {
 
 impl Client {
     /// Construct a new `Client`.
+    ///
+    /// Peers are called over HTTP/2, so that concurrent requests to the same peer are multiplexed
+    /// over a single connection.
     pub fn new() -> Self {
         let client = hyper::Client::builder()
             .pool_idle_timeout(Duration::from_secs(IDLE_TIMEOUT))
             .http2_only(true)
+            .http2_adaptive_window(true)
+            .http2_keep_alive_interval(Duration::from_secs(KEEP_ALIVE))
             .build_http();
 
         Self { client }
@@ -44,7 +57,7 @@ impl crate::gateway::Client for Client {
         link: &Link,
         key: &Value,
     ) -> TCResult<T> {
-        let uri = url(link, txn_id, key)?;
+        let uri = url(link, Some(txn_id), key)?;
         debug!("FETCH {}", uri);
         let req = req_builder("GET", uri, None);
 
@@ -54,6 +67,9 @@ impl crate::gateway::Client for Client {
             .map_err(|e| TCError::bad_gateway(e))
             .await?;
 
+        #[cfg(feature = "faults")]
+        crate::faults::peer_response(link)?;
+
         if response.status().is_success() {
             let mut response = response.into_body();
             let mut body = Vec::new();
@@ -81,8 +97,10 @@ impl crate::gateway::Client for Client {
             return Err(TCError::unsupported(ERR_NO_OWNER));
         }
 
-        let uri = url(&link, txn.id(), &key)?;
-        let req = req_builder("GET", uri, Some(txn.request().token()));
+        let uri = url(&link, Some(txn.id()), &key)?;
+        let req = call_builder("GET", uri, &txn)
+            .header(hyper::header::ACCEPT, ACCEPT)
+            .header(hyper::header::ACCEPT_ENCODING, compression::ACCEPT);
 
         let response = self
             .client
@@ -90,12 +108,11 @@ impl crate::gateway::Client for Client {
             .map_err(|e| TCError::bad_gateway(e))
             .await?;
 
+        #[cfg(feature = "faults")]
+        crate::faults::peer_response(&link)?;
+
         if response.status().is_success() {
-            destream_json::try_decode(txn, response.into_body().map_ok(|bytes| bytes.to_vec()))
-                .map_err(|e| {
-                    TCError::bad_request(format!("error decoding response from {}", link), e)
-                })
-                .await
+            decode_response(txn, &link, response).await
         } else {
             let err = transform_error(&link, response).await;
             Err(err)
@@ -107,8 +124,8 @@ impl crate::gateway::Client for Client {
             return Err(TCError::unsupported(ERR_NO_OWNER));
         }
 
-        let uri = url(&link, txn.id(), &key)?;
-        let req = req_builder("PUT", uri, Some(txn.request().token()));
+        let uri = url(&link, Some(txn.id()), &key)?;
+        let req = call_builder("PUT", uri, &txn);
 
         let body = destream_json::encode(value.into_view(txn))
             .map_err(|e| TCError::bad_request("unable to encode stream", e))?;
@@ -119,6 +136,9 @@ impl crate::gateway::Client for Client {
             .map_err(|e| TCError::bad_gateway(e))
             .await?;
 
+        #[cfg(feature = "faults")]
+        crate::faults::peer_response(&link)?;
+
         if response.status().is_success() {
             Ok(())
         } else {
@@ -132,8 +152,10 @@ impl crate::gateway::Client for Client {
             return Err(TCError::unsupported(ERR_NO_OWNER));
         }
 
-        let uri = url(&link, txn.id(), &Value::default())?;
-        let req = req_builder("POST", uri, Some(txn.request().token()));
+        let uri = url(&link, Some(txn.id()), &Value::default())?;
+        let req = call_builder("POST", uri, &txn)
+            .header(hyper::header::ACCEPT, ACCEPT)
+            .header(hyper::header::ACCEPT_ENCODING, compression::ACCEPT);
 
         let subcontext = txn.subcontext(label("_params").into()).await?;
         let body = destream_json::encode(params.into_view(subcontext))
@@ -145,12 +167,11 @@ impl crate::gateway::Client for Client {
             .map_err(|e| TCError::bad_gateway(e))
             .await?;
 
+        #[cfg(feature = "faults")]
+        crate::faults::peer_response(&link)?;
+
         if response.status().is_success() {
-            destream_json::try_decode(txn, response.into_body().map_ok(|bytes| bytes.to_vec()))
-                .map_err(|e| {
-                    TCError::bad_request(format!("error decoding response from {}", link), e)
-                })
-                .await
+            decode_response(txn, &link, response).await
         } else {
             let err = transform_error(&link, response).await;
             Err(err)
@@ -162,8 +183,8 @@ impl crate::gateway::Client for Client {
             return Err(TCError::unsupported(ERR_NO_OWNER));
         }
 
-        let uri = url(&link, txn.id(), &key)?;
-        let req = req_builder("GET", uri, Some(txn.request().token()));
+        let uri = url(&link, Some(txn.id()), &key)?;
+        let req = call_builder("GET", uri, txn);
 
         let response = self
             .client
@@ -171,6 +192,9 @@ impl crate::gateway::Client for Client {
             .map_err(|e| TCError::bad_gateway(e))
             .await?;
 
+        #[cfg(feature = "faults")]
+        crate::faults::peer_response(&link)?;
+
         if response.status().is_success() {
             Ok(())
         } else {
@@ -180,12 +204,47 @@ impl crate::gateway::Client for Client {
     }
 }
 
-fn url(link: &Link, txn_id: &TxnId, key: &Value) -> TCResult<Url> {
+async fn decode_response(
+    txn: Txn,
+    source: &Link,
+    response: hyper::Response<Body>,
+) -> TCResult<State> {
+    let decode_err =
+        |e| TCError::bad_request(format!("error decoding response from {}", source), e);
+
+    let encoding = Encoding::of(response.headers())?;
+    let (parts, body) = response.into_parts();
+    let body = body
+        .map_ok(|bytes| bytes.to_vec())
+        .map_err(TCError::bad_gateway)
+        .boxed();
+
+    let body = compression::decompress(&parts.headers, body)?;
+
+    match encoding {
+        Encoding::Json => {
+            destream_json::try_decode(txn, body)
+                .map_err(decode_err)
+                .await
+        }
+        encoding => {
+            let data = body.try_concat().await?;
+            let json = encoding.decode_json(data)?;
+            destream_json::try_decode(txn, stream::once(future::ready(Ok::<_, TCError>(json))))
+                .map_err(decode_err)
+                .await
+        }
+    }
+}
+
+pub(crate) fn url(link: &Link, txn_id: Option<&TxnId>, key: &Value) -> TCResult<Url> {
     let mut url =
         Url::parse(&link.to_string()).map_err(|e| TCError::bad_request("invalid URL", e))?;
 
-    url.query_pairs_mut()
-        .append_pair("txn_id", &txn_id.to_string());
+    if let Some(txn_id) = txn_id {
+        url.query_pairs_mut()
+            .append_pair("txn_id", &txn_id.to_string());
+    }
 
     if key.is_some() {
         let key_json = serde_json::to_string(&key)
@@ -197,7 +256,7 @@ fn url(link: &Link, txn_id: &TxnId, key: &Value) -> TCResult<Url> {
     Ok(url)
 }
 
-fn req_builder(method: &str, url: Url, auth: Option<&str>) -> http::request::Builder {
+pub(crate) fn req_builder(method: &str, url: Url, auth: Option<&str>) -> http::request::Builder {
     let req = hyper::Request::builder()
         .method(method)
         .uri(url.to_string());
@@ -209,7 +268,12 @@ fn req_builder(method: &str, url: Url, auth: Option<&str>) -> http::request::Bui
     }
 }
 
-async fn transform_error(source: &Link, response: hyper::Response<Body>) -> TCError {
+// a request within the given `txn`, with its auth token and the depth of the op which sends it
+fn call_builder(method: &str, url: Url, txn: &Txn) -> http::request::Builder {
+    req_builder(method, url, Some(txn.request().token())).header(DEPTH, txn.depth())
+}
+
+pub(crate) async fn transform_error(source: &Link, response: hyper::Response<Body>) -> TCError {
     const MAX_ERR_SIZE: usize = 5000;
 
     let status = response.status();
@@ -240,10 +304,41 @@ async fn transform_error(source: &Link, response: hyper::Response<Body>) -> TCEr
         StatusCode::METHOD_NOT_ALLOWED => ErrorType::MethodNotAllowed,
         StatusCode::NOT_FOUND => ErrorType::NotFound,
         StatusCode::NOT_IMPLEMENTED => ErrorType::NotImplemented,
+        StatusCode::PAYLOAD_TOO_LARGE => ErrorType::PayloadTooLarge,
         StatusCode::UNAUTHORIZED => ErrorType::Unauthorized,
         StatusCode::REQUEST_TIMEOUT => ErrorType::Timeout,
+        StatusCode::TOO_MANY_REQUESTS => ErrorType::TooManyRequests,
+        StatusCode::SERVICE_UNAVAILABLE => ErrorType::Unavailable,
         _ => ErrorType::BadGateway,
     };
 
     TCError::new(code, message)
 }
+
+#[cfg(test)]
+mod tests {
+    use tc_value::Number;
+
+    use super::*;
+
+    #[test]
+    fn test_url() -> TCResult<()> {
+        let link: Link = "http://127.0.0.1:8702/app/users".parse()?;
+
+        let plain = url(&link, None, &Value::None)?;
+        assert_eq!(plain.as_str(), "http://127.0.0.1:8702/app/users");
+        assert_eq!(plain.query(), None);
+
+        let keyed = url(&link, None, &Value::from(Number::from(1u64)))?;
+        assert_eq!(keyed.query(), Some("key=1"));
+
+        let req = req_builder("GET", keyed, Some("secret"))
+            .body(())
+            .map_err(TCError::internal)?;
+
+        assert_eq!(req.method(), "GET");
+        assert_eq!(req.headers()[hyper::header::AUTHORIZATION], "Bearer secret");
+
+        Ok(())
+    }
+}
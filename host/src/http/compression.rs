@@ -0,0 +1,315 @@
+//! Compression of HTTP response bodies with gzip or zstd, negotiated by `Accept-Encoding`.
+
+use std::io::{self, Write};
+
+use futures::stream::{self, BoxStream, StreamExt};
+use hyper::header::{HeaderMap, ACCEPT_ENCODING, CONTENT_ENCODING};
+use log::warn;
+
+use tc_error::*;
+
+/// The value of the `Accept-Encoding` header of a request to a peer.
+pub(crate) const ACCEPT: &str = "zstd, gzip";
+
+const GZIP: &str = "gzip";
+const ZSTD: &str = "zstd";
+
+// the default zstd compression level
+const ZSTD_LEVEL: i32 = 3;
+
+type Chunks = BoxStream<'static, TCResult<Vec<u8>>>;
+
+/// The content coding of an HTTP message body.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub(crate) enum Compression {
+    Gzip,
+    Zstd,
+}
+
+impl Compression {
+    /// The compression requested by the `Accept-Encoding` header of a request with the given
+    /// headers, if any.
+    ///
+    /// The first of gzip or zstd to be listed is chosen, unless its quality value is zero.
+    pub fn accept(headers: &HeaderMap) -> TCResult<Option<Self>> {
+        let accept = match headers.get(ACCEPT_ENCODING) {
+            Some(accept) => accept
+                .to_str()
+                .map_err(|e| TCError::bad_request("unable to parse Accept-Encoding header", e))?,
+            None => return Ok(None),
+        };
+
+        let compression = accept
+            .split(',')
+            .filter(|coding| !is_refused(coding))
+            .filter_map(|coding| Self::from_name(coding.split(';').next().unwrap_or_default()))
+            .next();
+
+        Ok(compression)
+    }
+
+    /// The content coding of a message with the given headers, according to its
+    /// `Content-Encoding`.
+    pub fn of(headers: &HeaderMap) -> TCResult<Option<Self>> {
+        match headers.get(CONTENT_ENCODING) {
+            Some(coding) => {
+                let coding = coding.to_str().map_err(|e| {
+                    TCError::bad_request("unable to parse Content-Encoding header", e)
+                })?;
+
+                match coding.trim() {
+                    "identity" => Ok(None),
+                    name => Self::from_name(name)
+                        .map(Some)
+                        .ok_or_else(|| TCError::unsupported(format!("content coding {}", name))),
+                }
+            }
+            None => Ok(None),
+        }
+    }
+
+    fn from_name(name: &str) -> Option<Self> {
+        let name = name.trim();
+        if name.eq_ignore_ascii_case(GZIP) {
+            Some(Self::Gzip)
+        } else if name.eq_ignore_ascii_case(ZSTD) {
+            Some(Self::Zstd)
+        } else {
+            None
+        }
+    }
+
+    /// The name of this content coding, for a `Content-Encoding` header.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Gzip => GZIP,
+            Self::Zstd => ZSTD,
+        }
+    }
+}
+
+/// Compress the given `body`, unless it ends before reaching `threshold` bytes.
+///
+/// Returns the content coding of the returned body, if it's compressed.
+pub(crate) async fn compress(
+    mut body: Chunks,
+    compression: Option<Compression>,
+    threshold: usize,
+) -> (Option<Compression>, Chunks) {
+    let compression = match compression {
+        Some(compression) => compression,
+        None => return (None, body),
+    };
+
+    let mut head = Vec::new();
+    let mut size = 0;
+    while size < threshold {
+        match body.next().await {
+            Some(Ok(chunk)) => {
+                size += chunk.len();
+                head.push(Ok(chunk));
+            }
+            Some(Err(cause)) => {
+                head.push(Err(cause));
+                return (None, stream::iter(head).boxed());
+            }
+            None => return (None, stream::iter(head).boxed()),
+        }
+    }
+
+    let body = stream::iter(head).chain(body).boxed();
+
+    let encoder = match compression {
+        Compression::Gzip => Ok(Coder::Gzip(flate2::write::GzEncoder::new(
+            Vec::new(),
+            flate2::Compression::default(),
+        ))),
+        Compression::Zstd => {
+            zstd::stream::write::Encoder::new(Vec::new(), ZSTD_LEVEL).map(Coder::Zstd)
+        }
+    };
+
+    match encoder {
+        Ok(encoder) => (Some(compression), transcode(encoder, body)),
+        Err(cause) => {
+            warn!(
+                "unable to compress response with {}: {}",
+                compression.name(),
+                cause
+            );
+            (None, body)
+        }
+    }
+}
+
+/// Decompress the given `body` of a message with the given headers, if it's compressed.
+pub(crate) fn decompress(headers: &HeaderMap, body: Chunks) -> TCResult<Chunks> {
+    let decoder = match Compression::of(headers)? {
+        Some(Compression::Gzip) => Coder::Gunzip(flate2::write::GzDecoder::new(Vec::new())),
+        Some(Compression::Zstd) => zstd::stream::write::Decoder::new(Vec::new())
+            .map(Coder::Unzstd)
+            .map_err(|e| TCError::internal(format!("unable to decompress zstd: {}", e)))?,
+        None => return Ok(body),
+    };
+
+    Ok(transcode(decoder, body))
+}
+
+// a streaming compressor or decompressor, which buffers its output in memory
+enum Coder {
+    Gzip(flate2::write::GzEncoder<Vec<u8>>),
+    Gunzip(flate2::write::GzDecoder<Vec<u8>>),
+    Zstd(zstd::stream::write::Encoder<'static, Vec<u8>>),
+    Unzstd(zstd::stream::write::Decoder<'static, Vec<u8>>),
+}
+
+impl Coder {
+    // write the given `chunk` and return whatever output is ready
+    fn write(&mut self, chunk: &[u8]) -> io::Result<Vec<u8>> {
+        let output = match self {
+            Self::Gzip(coder) => {
+                coder.write_all(chunk)?;
+                coder.get_mut()
+            }
+            Self::Gunzip(coder) => {
+                coder.write_all(chunk)?;
+                coder.get_mut()
+            }
+            Self::Zstd(coder) => {
+                coder.write_all(chunk)?;
+                coder.get_mut()
+            }
+            Self::Unzstd(coder) => {
+                coder.write_all(chunk)?;
+                coder.get_mut()
+            }
+        };
+
+        Ok(std::mem::take(output))
+    }
+
+    // flush any buffered input and return the rest of the output
+    fn finish(self) -> io::Result<Vec<u8>> {
+        match self {
+            Self::Gzip(coder) => coder.finish(),
+            Self::Gunzip(coder) => coder.finish(),
+            Self::Zstd(coder) => coder.finish(),
+            Self::Unzstd(mut coder) => coder.flush().map(|()| coder.into_inner()),
+        }
+    }
+}
+
+fn transcode(coder: Coder, body: Chunks) -> Chunks {
+    stream::unfold(Some((coder, body)), |state| async move {
+        let (mut coder, mut body) = state?;
+
+        loop {
+            match body.next().await {
+                Some(Ok(chunk)) => match coder.write(&chunk) {
+                    Ok(output) if output.is_empty() => {}
+                    Ok(output) => return Some((Ok(output), Some((coder, body)))),
+                    Err(cause) => return Some((Err(coding_error(cause)), None)),
+                },
+                Some(Err(cause)) => return Some((Err(cause), None)),
+                None => return Some((coder.finish().map_err(coding_error), None)),
+            }
+        }
+    })
+    .boxed()
+}
+
+fn coding_error(cause: io::Error) -> TCError {
+    TCError::bad_request("invalid compressed message body", cause)
+}
+
+// true if the given member of an Accept-Encoding header has a quality value of zero
+fn is_refused(coding: &str) -> bool {
+    coding.split(';').skip(1).any(|param| {
+        let param = param.trim();
+        param.starts_with("q=") && param[2..].parse::<f32>().map(|q| q == 0.).unwrap_or(false)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::TryStreamExt;
+    use hyper::header::HeaderValue;
+
+    use super::*;
+
+    fn headers(name: hyper::header::HeaderName, value: &'static str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(name, HeaderValue::from_static(value));
+        headers
+    }
+
+    fn chunks(body: &[u8]) -> Chunks {
+        let chunks: Vec<TCResult<Vec<u8>>> = body.chunks(100).map(|c| Ok(c.to_vec())).collect();
+        stream::iter(chunks).boxed()
+    }
+
+    async fn collect(chunks: Chunks) -> TCResult<Vec<u8>> {
+        chunks
+            .try_fold(Vec::new(), |mut body, chunk| async move {
+                body.extend(chunk);
+                Ok(body)
+            })
+            .await
+    }
+
+    #[test]
+    fn test_accept() -> TCResult<()> {
+        assert_eq!(Compression::accept(&HeaderMap::new())?, None);
+
+        let accept = headers(ACCEPT_ENCODING, "br, ZSTD;q=0.5, gzip");
+        assert_eq!(Compression::accept(&accept)?, Some(Compression::Zstd));
+
+        let accept = headers(ACCEPT_ENCODING, "zstd;q=0, gzip;q=0.1");
+        assert_eq!(Compression::accept(&accept)?, Some(Compression::Gzip));
+
+        let accept = headers(ACCEPT_ENCODING, "identity");
+        assert_eq!(Compression::accept(&accept)?, None);
+
+        assert_eq!(
+            Compression::of(&headers(CONTENT_ENCODING, "identity"))?,
+            None
+        );
+        assert_eq!(
+            Compression::of(&headers(CONTENT_ENCODING, "gzip"))?,
+            Some(Compression::Gzip)
+        );
+
+        let result = Compression::of(&headers(CONTENT_ENCODING, "br"));
+        assert_eq!(result.unwrap_err().code(), ErrorType::BadRequest);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_compress() -> TCResult<()> {
+        let body = br#"[1, 2, 3, 4, 5, 6, 7, 8]"#.repeat(100);
+
+        for compression in [Compression::Gzip, Compression::Zstd] {
+            let (coding, compressed) = compress(chunks(&body), Some(compression), 1_000).await;
+            assert_eq!(coding, Some(compression));
+
+            let compressed = collect(compressed).await?;
+            assert!(compressed.len() < body.len());
+
+            let headers = headers(CONTENT_ENCODING, compression.name());
+            let decompressed = collect(decompress(&headers, chunks(&compressed))?).await?;
+            assert_eq!(decompressed, body);
+        }
+
+        // a body smaller than the threshold is not compressed
+        let (coding, small) = compress(chunks(&body), Some(Compression::Gzip), 10_000).await;
+        assert_eq!(coding, None);
+        assert_eq!(collect(small).await?, body);
+
+        let headers = headers(CONTENT_ENCODING, "gzip");
+        let result = collect(decompress(&headers, chunks(b"not gzip"))?).await;
+        assert_eq!(result.unwrap_err().code(), ErrorType::BadRequest);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,259 @@
+//! Cross-origin resource sharing (CORS), so that a browser-based client can call this host.
+
+use std::time::Duration;
+
+use hyper::header::{self, HeaderMap, HeaderValue};
+use hyper::{Body, Method, Request, Response, StatusCode};
+
+const DEFAULT_MAX_AGE: Duration = Duration::from_secs(3600);
+
+const DEFAULT_METHODS: [&str; 3] = ["GET", "PUT", "POST"];
+
+const DEFAULT_HEADERS: [&str; 7] = [
+    "accept",
+    "authorization",
+    "content-type",
+    "if-none-match",
+    "x-tinychain-decode",
+    "x-tinychain-stats",
+    "x-tinychain-timezone",
+];
+
+// response headers which a browser-based client may read, besides the CORS-safelisted headers
+const EXPOSE_HEADERS: &str = "content-encoding, etag, x-tinychain-stats";
+
+/// The CORS policy of the HTTP server.
+///
+/// By default, no origin is allowed, so a browser will refuse to share any response with a
+/// client served from another origin.
+#[derive(Clone, Debug)]
+pub struct Cors {
+    origins: Vec<String>,
+    methods: Vec<String>,
+    headers: Vec<String>,
+    max_age: Duration,
+}
+
+impl Default for Cors {
+    fn default() -> Self {
+        Self::allow(vec![])
+    }
+}
+
+impl Cors {
+    /// Allow requests from the given `origins`, like "https://example.com", or "*" for any origin.
+    pub fn allow(origins: Vec<String>) -> Self {
+        Self {
+            origins,
+            methods: DEFAULT_METHODS.iter().map(|m| m.to_string()).collect(),
+            headers: DEFAULT_HEADERS.iter().map(|h| h.to_string()).collect(),
+            max_age: DEFAULT_MAX_AGE,
+        }
+    }
+
+    /// Allow only the given request `methods`, rather than GET, PUT, and POST.
+    pub fn with_methods(mut self, methods: Vec<String>) -> Self {
+        self.methods = methods
+            .into_iter()
+            .map(|method| method.to_ascii_uppercase())
+            .collect();
+
+        self
+    }
+
+    /// Allow only the given request `headers`, rather than the headers which Tinychain reads.
+    pub fn with_headers(mut self, headers: Vec<String>) -> Self {
+        self.headers = headers
+            .into_iter()
+            .map(|header| header.to_ascii_lowercase())
+            .collect();
+
+        self
+    }
+
+    /// Allow a browser to cache the result of a preflight request for the given duration.
+    pub fn with_max_age(mut self, max_age: Duration) -> Self {
+        self.max_age = max_age;
+        self
+    }
+
+    /// Return `true` if the given request is a CORS preflight request.
+    pub(crate) fn is_preflight(request: &Request<Body>) -> bool {
+        request.method() == Method::OPTIONS
+            && request.headers().contains_key(header::ORIGIN)
+            && request
+                .headers()
+                .contains_key(header::ACCESS_CONTROL_REQUEST_METHOD)
+    }
+
+    /// The value of the `Access-Control-Allow-Origin` header of a response to a request with the
+    /// given headers, if its origin is allowed.
+    pub(crate) fn allow_origin(&self, headers: &HeaderMap) -> Option<HeaderValue> {
+        let origin = headers.get(header::ORIGIN)?;
+
+        if self.origins.iter().any(|allowed| allowed == "*") {
+            Some(HeaderValue::from_static("*"))
+        } else if self
+            .origins
+            .iter()
+            .any(|allowed| origin == allowed.as_str())
+        {
+            Some(origin.clone())
+        } else {
+            None
+        }
+    }
+
+    /// Add CORS headers to a `response` to a request from an allowed origin.
+    pub(crate) fn apply(&self, allow_origin: HeaderValue, response: &mut Response<Body>) {
+        let headers = response.headers_mut();
+        headers.insert(header::ACCESS_CONTROL_ALLOW_ORIGIN, allow_origin);
+        headers.insert(
+            header::ACCESS_CONTROL_EXPOSE_HEADERS,
+            HeaderValue::from_static(EXPOSE_HEADERS),
+        );
+
+        headers.append(header::VARY, HeaderValue::from_static("origin"));
+    }
+
+    /// Respond to a CORS preflight `request`.
+    ///
+    /// A request from an origin which is not allowed, or for a method or header which is not
+    /// allowed, is refused with a 403 Forbidden response.
+    pub(crate) fn preflight(&self, request: &Request<Body>) -> Response<Body> {
+        let mut response = Response::new(Body::empty());
+
+        let allow_origin = match self.allow_origin(request.headers()) {
+            Some(allow_origin) if self.allows(request.headers()) => allow_origin,
+            _ => {
+                *response.status_mut() = StatusCode::FORBIDDEN;
+                return response;
+            }
+        };
+
+        *response.status_mut() = StatusCode::NO_CONTENT;
+        self.apply(allow_origin, &mut response);
+
+        let headers = response.headers_mut();
+        if let Ok(methods) = self.methods.join(", ").parse() {
+            headers.insert(header::ACCESS_CONTROL_ALLOW_METHODS, methods);
+        }
+
+        if let Ok(allow_headers) = self.headers.join(", ").parse() {
+            headers.insert(header::ACCESS_CONTROL_ALLOW_HEADERS, allow_headers);
+        }
+
+        headers.insert(
+            header::ACCESS_CONTROL_MAX_AGE,
+            HeaderValue::from(self.max_age.as_secs()),
+        );
+
+        response
+    }
+
+    // whether the method and headers of a preflight request are allowed
+    fn allows(&self, headers: &HeaderMap) -> bool {
+        let method = headers
+            .get(header::ACCESS_CONTROL_REQUEST_METHOD)
+            .and_then(|method| method.to_str().ok())
+            .map(|method| self.methods.iter().any(|allowed| allowed == method))
+            .unwrap_or(false);
+
+        let requested = match headers.get(header::ACCESS_CONTROL_REQUEST_HEADERS) {
+            Some(requested) => match requested.to_str() {
+                Ok(requested) => requested,
+                Err(_) => return false,
+            },
+            None => "",
+        };
+
+        method
+            && requested
+                .split(',')
+                .map(|name| name.trim())
+                .filter(|name| !name.is_empty())
+                .all(|name| {
+                    self.headers
+                        .iter()
+                        .any(|allowed| allowed.eq_ignore_ascii_case(name))
+                })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ORIGIN: &str = "https://example.com";
+
+    fn preflight(
+        origin: &'static str,
+        method: &'static str,
+        headers: &'static str,
+    ) -> Request<Body> {
+        Request::builder()
+            .method(Method::OPTIONS)
+            .header(header::ORIGIN, origin)
+            .header(header::ACCESS_CONTROL_REQUEST_METHOD, method)
+            .header(header::ACCESS_CONTROL_REQUEST_HEADERS, headers)
+            .body(Body::empty())
+            .expect("preflight request")
+    }
+
+    #[test]
+    fn test_allow_origin() {
+        let mut headers = HeaderMap::new();
+        assert!(Cors::allow(vec!["*".to_string()])
+            .allow_origin(&headers)
+            .is_none());
+
+        headers.insert(header::ORIGIN, HeaderValue::from_static(ORIGIN));
+        assert!(Cors::default().allow_origin(&headers).is_none());
+        assert_eq!(
+            Cors::allow(vec!["*".to_string()]).allow_origin(&headers),
+            Some(HeaderValue::from_static("*"))
+        );
+        assert_eq!(
+            Cors::allow(vec![ORIGIN.to_string()]).allow_origin(&headers),
+            Some(HeaderValue::from_static(ORIGIN))
+        );
+
+        let cors = Cors::allow(vec!["https://example.org".to_string()]);
+        assert!(cors.allow_origin(&headers).is_none());
+    }
+
+    #[test]
+    fn test_preflight() {
+        let cors = Cors::allow(vec![ORIGIN.to_string()])
+            .with_methods(vec!["get".to_string(), "post".to_string()])
+            .with_max_age(Duration::from_secs(60));
+
+        let request = preflight(ORIGIN, "POST", "Content-Type, X-Tinychain-Stats");
+        assert!(Cors::is_preflight(&request));
+
+        let response = cors.preflight(&request);
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+
+        let headers = response.headers();
+        assert_eq!(headers[header::ACCESS_CONTROL_ALLOW_ORIGIN], ORIGIN);
+        assert_eq!(headers[header::ACCESS_CONTROL_ALLOW_METHODS], "GET, POST");
+        assert_eq!(headers[header::ACCESS_CONTROL_MAX_AGE], "60");
+
+        let response = cors.preflight(&preflight(ORIGIN, "PUT", ""));
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+
+        let response = cors.preflight(&preflight(ORIGIN, "GET", "x-custom"));
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+
+        let response = cors.preflight(&preflight("https://example.org", "GET", ""));
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+
+        let request = Request::builder()
+            .method(Method::OPTIONS)
+            .header(header::ORIGIN, ORIGIN)
+            .body(Body::empty())
+            .expect("OPTIONS request");
+
+        assert!(!Cors::is_preflight(&request));
+    }
+}
@@ -0,0 +1,321 @@
+//! Content negotiation between JSON, CBOR, and MessagePack.
+//!
+//! A `State` is always decoded by `destream_json`, so a CBOR or MessagePack message is transcoded
+//! to JSON as it passes through the HTTP interface. A CBOR or MessagePack response is streamed
+//! natively.
+
+use std::convert::TryFrom;
+
+use destream::en::IntoStream;
+use futures::stream::{BoxStream, StreamExt, TryStreamExt};
+use hyper::header::{HeaderMap, ACCEPT, CONTENT_TYPE};
+
+use tc_error::*;
+
+use super::binary;
+
+pub(crate) const CBOR: &str = "application/cbor";
+pub(crate) const JSON: &str = "application/json";
+pub(crate) const MSGPACK: &str = "application/msgpack";
+
+// the unofficial name of the MessagePack MIME type, which some clients still use
+const X_MSGPACK: &str = "application/x-msgpack";
+
+/// The encoding of the body of an HTTP request or response.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub(crate) enum Encoding {
+    Cbor,
+    Json,
+    Msgpack,
+}
+
+impl Encoding {
+    /// The encoding of a message with the given headers, according to its `Content-Type`.
+    ///
+    /// A message with no `Content-Type`, or one other than CBOR or MessagePack, is assumed to be
+    /// JSON.
+    pub fn of(headers: &HeaderMap) -> TCResult<Self> {
+        match headers.get(CONTENT_TYPE) {
+            Some(content_type) => {
+                let content_type = content_type
+                    .to_str()
+                    .map_err(|e| TCError::bad_request("unable to parse Content-Type header", e))?;
+
+                Ok(Self::from_media_type(media_type(content_type)).unwrap_or(Self::Json))
+            }
+            None => Ok(Self::Json),
+        }
+    }
+
+    /// The encoding requested by the `Accept` header of a request with the given headers.
+    ///
+    /// Whichever of CBOR, JSON, or MessagePack has the highest quality value is chosen, or the
+    /// first to be listed in case of a tie. JSON is the default if none of them is acceptable.
+    pub fn accept(headers: &HeaderMap) -> TCResult<Self> {
+        let accept = match headers.get(ACCEPT) {
+            Some(accept) => accept
+                .to_str()
+                .map_err(|e| TCError::bad_request("unable to parse Accept header", e))?,
+            None => return Ok(Self::Json),
+        };
+
+        let mut preferred: Option<(Self, f32)> = None;
+        for media_range in accept.split(',') {
+            let encoding = match Self::from_media_type(media_type(media_range)) {
+                Some(encoding) => encoding,
+                None => continue,
+            };
+
+            let quality = quality(media_range)?;
+            if quality > preferred.map(|(_, quality)| quality).unwrap_or(0.) {
+                preferred = Some((encoding, quality));
+            }
+        }
+
+        Ok(preferred
+            .map(|(encoding, _)| encoding)
+            .unwrap_or(Self::Json))
+    }
+
+    fn from_media_type(media_type: &str) -> Option<Self> {
+        if media_type.eq_ignore_ascii_case(CBOR) {
+            Some(Self::Cbor)
+        } else if media_type.eq_ignore_ascii_case(JSON) {
+            Some(Self::Json)
+        } else if media_type.eq_ignore_ascii_case(MSGPACK)
+            || media_type.eq_ignore_ascii_case(X_MSGPACK)
+        {
+            Some(Self::Msgpack)
+        } else {
+            None
+        }
+    }
+
+    /// The MIME type of this encoding, for a `Content-Type` header.
+    pub fn mime_type(&self) -> &'static str {
+        match self {
+            Self::Cbor => CBOR,
+            Self::Json => JSON,
+            Self::Msgpack => MSGPACK,
+        }
+    }
+
+    /// Encode the given value as a stream in this encoding.
+    pub fn encode<'en, T: IntoStream<'en> + 'en>(
+        self,
+        value: T,
+    ) -> TCResult<BoxStream<'en, TCResult<Vec<u8>>>> {
+        match self {
+            Self::Cbor => binary::encode_cbor(value)
+                .map(|encoded| encoded.map_err(TCError::internal).boxed())
+                .map_err(TCError::internal),
+            Self::Json => destream_json::encode(value)
+                .map(|encoded| encoded.map_err(TCError::internal).boxed())
+                .map_err(TCError::internal),
+            Self::Msgpack => binary::encode_msgpack(value)
+                .map(|encoded| encoded.map_err(TCError::internal).boxed())
+                .map_err(TCError::internal),
+        }
+    }
+
+    /// Transcode a complete message in this encoding into JSON.
+    ///
+    /// A byte string becomes a base64 string, the way `destream_json` would have encoded it.
+    pub fn decode_json(self, data: Vec<u8>) -> TCResult<Vec<u8>> {
+        match self {
+            Self::Cbor => {
+                let cbor: serde_cbor::Value = serde_cbor::from_slice(&data)
+                    .map_err(|e| TCError::bad_request("invalid CBOR", e))?;
+
+                let json = cbor_to_json(cbor)?;
+                serde_json::to_vec(&json).map_err(|e| TCError::bad_request("invalid CBOR", e))
+            }
+            Self::Json => Ok(data),
+            Self::Msgpack => {
+                // a MessagePack message decodes to the same data model as CBOR
+                let msgpack: serde_cbor::Value = rmp_serde::from_slice(&data)
+                    .map_err(|e| TCError::bad_request("invalid MessagePack", e))?;
+
+                let json = cbor_to_json(msgpack)?;
+                serde_json::to_vec(&json)
+                    .map_err(|e| TCError::bad_request("invalid MessagePack", e))
+            }
+        }
+    }
+}
+
+fn cbor_to_json(cbor: serde_cbor::Value) -> TCResult<serde_json::Value> {
+    use serde_cbor::Value as Cbor;
+    use serde_json::Value as Json;
+
+    match cbor {
+        Cbor::Null => Ok(Json::Null),
+        Cbor::Bool(b) => Ok(Json::Bool(b)),
+        Cbor::Integer(i) => {
+            if let Ok(i) = i64::try_from(i) {
+                Ok(i.into())
+            } else if let Ok(u) = u64::try_from(i) {
+                Ok(u.into())
+            } else {
+                Err(TCError::bad_request("integer out of range", i))
+            }
+        }
+        Cbor::Float(f) => serde_json::Number::from_f64(f)
+            .map(Json::Number)
+            .ok_or_else(|| TCError::bad_request("JSON does not support the number", f)),
+        Cbor::Bytes(bytes) => Ok(Json::String(base64::encode(bytes))),
+        Cbor::Text(s) => Ok(Json::String(s)),
+        Cbor::Array(items) => items
+            .into_iter()
+            .map(cbor_to_json)
+            .collect::<TCResult<Vec<Json>>>()
+            .map(Json::Array),
+        Cbor::Map(map) => map
+            .into_iter()
+            .map(|(key, value)| match key {
+                Cbor::Text(key) => cbor_to_json(value).map(|value| (key, value)),
+                other => Err(TCError::bad_request(
+                    "a map key must be a string, not",
+                    format!("{:?}", other),
+                )),
+            })
+            .collect::<TCResult<serde_json::Map<String, Json>>>()
+            .map(Json::Object),
+        Cbor::Tag(_, value) => cbor_to_json(*value),
+        other => Err(TCError::bad_request(
+            "unsupported CBOR value",
+            format!("{:?}", other),
+        )),
+    }
+}
+
+// the media type of a header value like "application/json; charset=utf-8"
+fn media_type(value: &str) -> &str {
+    value.split(';').next().unwrap_or_default().trim()
+}
+
+// the quality value of a media range like "application/cbor;q=0.5", which defaults to 1
+fn quality(media_range: &str) -> TCResult<f32> {
+    for param in media_range.split(';').skip(1) {
+        if let Some((name, value)) = param.split_once('=') {
+            if name.trim().eq_ignore_ascii_case("q") {
+                let value = value.trim();
+                return value
+                    .parse()
+                    .ok()
+                    .filter(|quality| (0f32..=1.).contains(quality))
+                    .ok_or_else(|| {
+                        TCError::bad_request("invalid quality value in Accept header", value)
+                    });
+            }
+        }
+    }
+
+    Ok(1.)
+}
+
+#[cfg(test)]
+mod tests {
+    use hyper::header::HeaderValue;
+
+    use super::*;
+
+    fn headers(name: hyper::header::HeaderName, value: &'static str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(name, HeaderValue::from_static(value));
+        headers
+    }
+
+    async fn encode<'en, T: IntoStream<'en> + 'en>(
+        encoding: Encoding,
+        value: T,
+    ) -> TCResult<Vec<u8>> {
+        encoding.encode(value)?.try_concat().await
+    }
+
+    #[test]
+    fn test_negotiate() -> TCResult<()> {
+        assert_eq!(Encoding::of(&HeaderMap::new())?, Encoding::Json);
+        assert_eq!(Encoding::accept(&HeaderMap::new())?, Encoding::Json);
+
+        let cbor = headers(CONTENT_TYPE, "Application/CBOR; charset=binary");
+        assert_eq!(Encoding::of(&cbor)?, Encoding::Cbor);
+
+        let other = headers(CONTENT_TYPE, "text/plain");
+        assert_eq!(Encoding::of(&other)?, Encoding::Json);
+
+        let accept = headers(ACCEPT, "text/html, application/cbor, application/json");
+        assert_eq!(Encoding::accept(&accept)?, Encoding::Cbor);
+
+        let accept = headers(
+            ACCEPT,
+            "text/html, application/cbor;q=0.5, application/json",
+        );
+        assert_eq!(Encoding::accept(&accept)?, Encoding::Json);
+
+        let accept = headers(ACCEPT, "application/cbor;q=0, application/json");
+        assert_eq!(Encoding::accept(&accept)?, Encoding::Json);
+
+        let accept = headers(
+            ACCEPT,
+            "application/json; q=0.8, application/msgpack; Q=0.9",
+        );
+        assert_eq!(Encoding::accept(&accept)?, Encoding::Msgpack);
+
+        let accept = headers(ACCEPT, "*/*");
+        assert_eq!(Encoding::accept(&accept)?, Encoding::Json);
+
+        let accept = headers(ACCEPT, "application/cbor;q=2");
+        assert!(Encoding::accept(&accept).is_err());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_cbor() -> TCResult<()> {
+        let value = ("a", (1u8, -2i32, 3.5f64, "four", (), true));
+        let cbor = encode(Encoding::Cbor, value).await?;
+        let json = encode(Encoding::Json, value).await?;
+        assert_ne!(cbor, json);
+
+        let decoded: serde_json::Value =
+            serde_json::from_slice(&Encoding::Cbor.decode_json(cbor)?).unwrap();
+        assert_eq!(
+            decoded,
+            serde_json::from_slice::<serde_json::Value>(&json).unwrap()
+        );
+
+        let bytes = serde_cbor::to_vec(&serde_cbor::Value::Bytes(vec![1, 2, 3])).unwrap();
+        assert_eq!(Encoding::Cbor.decode_json(bytes)?, br#""AQID""#.to_vec());
+
+        assert!(Encoding::Cbor.decode_json(vec![0xff, 0x00]).is_err());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_msgpack() -> TCResult<()> {
+        assert_eq!(
+            Encoding::of(&headers(CONTENT_TYPE, "application/x-msgpack"))?,
+            Encoding::Msgpack
+        );
+
+        let value = ("a", (1u8, -2i32, 3.5f64, "four", (), true));
+        let msgpack = encode(Encoding::Msgpack, value).await?;
+        let json = encode(Encoding::Json, value).await?;
+
+        let decoded: serde_json::Value =
+            serde_json::from_slice(&Encoding::Msgpack.decode_json(msgpack)?).unwrap();
+        assert_eq!(
+            decoded,
+            serde_json::from_slice::<serde_json::Value>(&json).unwrap()
+        );
+
+        let bytes = vec![0xc4, 3, 1, 2, 3];
+        assert_eq!(Encoding::Msgpack.decode_json(bytes)?, br#""AQID""#.to_vec());
+
+        assert!(Encoding::Msgpack.decode_json(vec![0xc1]).is_err());
+
+        Ok(())
+    }
+}
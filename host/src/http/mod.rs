@@ -1,7 +1,23 @@
 //! The HTTP interface for `Gateway`.
 
+mod binary;
+mod body;
 mod client;
+mod compression;
+mod cors;
+mod encoding;
+mod multipart;
 mod server;
+mod tls;
 
+// the depth of the op call which sent a request, so that the host which serves it can continue
+// to count the depth of nested calls from there
+const DEPTH: &str = "x-tinychain-depth";
+
+pub use body::DEFAULT_REQUEST_LIMIT;
 pub use client::*;
+pub use cors::Cors;
+pub use multipart::UploadLimits;
 pub use server::*;
+
+pub(crate) use tls::CertResolver;
@@ -0,0 +1,273 @@
+//! Uploads of `multipart/form-data` request bodies.
+//!
+//! Each part of the body is streamed into a new file in the transaction workspace, one block at a
+//! time, without being decoded. The response maps the name of each part to a link to its contents,
+//! which can be read with a GET request in the same transaction.
+//!
+//! An upload requires the `/transact/upload` scope from a trusted issuer key, and is rejected as
+//! soon as one of its parts, or the whole body, exceeds the configured [`UploadLimits`].
+
+use std::convert::TryFrom;
+
+use bytes::{Bytes, BytesMut};
+use hyper::header::{HeaderMap, CONTENT_TYPE};
+use hyper::Body;
+use uuid::Uuid;
+
+use tc_error::*;
+use tc_transact::fs::Dir;
+use tc_transact::Transaction;
+use tcgeneric::{Id, Map};
+
+use crate::fs;
+use crate::scalar::{ScalarType, Value, ValueType};
+use crate::state::{State, StateType};
+use crate::txn::{Txn, Upload};
+
+use super::body::{write_block, BLOCK_SIZE};
+
+/// The default maximum size of one part of a multipart upload, in bytes.
+pub const DEFAULT_MAX_PART_SIZE: u64 = 64_000_000;
+
+/// The default maximum size of a whole multipart upload, in bytes.
+pub const DEFAULT_MAX_SIZE: u64 = 256_000_000;
+
+/// Limits on the size of a `multipart/form-data` upload, to prevent one request from filling the
+/// workspace of this host.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct UploadLimits {
+    pub max_part_size: u64,
+    pub max_size: u64,
+}
+
+impl Default for UploadLimits {
+    fn default() -> Self {
+        Self {
+            max_part_size: DEFAULT_MAX_PART_SIZE,
+            max_size: DEFAULT_MAX_SIZE,
+        }
+    }
+}
+
+/// Return the boundary of a request body with the given `headers`, if it's `multipart/form-data`.
+pub(super) fn boundary(headers: &HeaderMap) -> Option<String> {
+    let content_type = headers.get(CONTENT_TYPE)?.to_str().ok()?;
+    multer::parse_boundary(content_type).ok()
+}
+
+/// Store each part of the given multipart `body` in the workspace of the given `txn`.
+///
+/// Returns a map of the name of each part to a link to its contents.
+pub(super) async fn upload(
+    txn: &Txn,
+    boundary: String,
+    body: Body,
+    limits: UploadLimits,
+) -> TCResult<Map<State>> {
+    let txn_id = *txn.id();
+    let class = StateType::Scalar(ScalarType::Value(ValueType::Bytes));
+
+    let constraints = multer::Constraints::new().size_limit(
+        multer::SizeLimit::new()
+            .per_field(limits.max_part_size)
+            .whole_stream(limits.max_size),
+    );
+
+    let mut multipart = multer::Multipart::with_constraints(body, boundary, constraints);
+    let mut links = Map::<State>::default();
+    while let Some(mut part) = multipart.next_field().await.map_err(invalid)? {
+        let name: Id = part
+            .name()
+            .ok_or_else(|| TCError::bad_request("multipart upload has a part with no name", ""))?
+            .parse()?;
+
+        if links.contains_key(&name) {
+            return Err(TCError::bad_request(
+                "multipart upload has more than one part named",
+                name,
+            ));
+        }
+
+        // each part needs a directory of its own, since the versions of the blocks of a file are
+        // stored in its parent directory
+        let dir = txn
+            .context()
+            .create_dir(txn_id, Uuid::new_v4().into())
+            .await?;
+
+        let file = dir.create_file(txn_id, name.clone(), class.clone()).await?;
+        let file = fs::File::<Bytes>::try_from(file)?;
+
+        let mut block_ids = Vec::new();
+        let mut buffer = BytesMut::with_capacity(BLOCK_SIZE);
+        while let Some(chunk) = part.chunk().await.map_err(invalid)? {
+            buffer.extend_from_slice(&chunk);
+
+            while buffer.len() >= BLOCK_SIZE {
+                let block = buffer.split_to(BLOCK_SIZE).freeze();
+                write_block(&file, txn_id, &mut block_ids, block).await?;
+            }
+        }
+
+        if !buffer.is_empty() {
+            write_block(&file, txn_id, &mut block_ids, buffer.freeze()).await?;
+        }
+
+        let link = txn.upload(Upload::new(file, block_ids));
+        links.insert(name, State::from(Value::from(link)));
+    }
+
+    Ok(links)
+}
+
+fn invalid(cause: multer::Error) -> TCError {
+    match cause {
+        multer::Error::FieldSizeExceeded { .. } | multer::Error::StreamSizeExceeded { .. } => {
+            TCError::bad_request("multipart upload is too large", cause)
+        }
+        cause => TCError::bad_request("invalid multipart request body", cause),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use hyper::header::HeaderValue;
+
+    use crate::test::TestHost;
+
+    use super::*;
+
+    const BOUNDARY: &str = "tc-boundary";
+
+    fn form(parts: &[(&str, &[u8])]) -> Body {
+        let mut body = Vec::new();
+        for (name, contents) in parts {
+            body.extend_from_slice(format!("--{}\r\n", BOUNDARY).as_bytes());
+            body.extend_from_slice(
+                format!(
+                    "Content-Disposition: form-data; name=\"{}\"; filename=\"{}.bin\"\r\n\r\n",
+                    name, name
+                )
+                .as_bytes(),
+            );
+
+            body.extend_from_slice(contents);
+            body.extend_from_slice(b"\r\n");
+        }
+
+        body.extend_from_slice(format!("--{}--\r\n", BOUNDARY).as_bytes());
+        Body::from(body)
+    }
+
+    #[test]
+    fn test_boundary() {
+        let mut headers = HeaderMap::new();
+        assert_eq!(boundary(&headers), None);
+
+        headers.insert(CONTENT_TYPE, HeaderValue::from_static("application/json"));
+        assert_eq!(boundary(&headers), None);
+
+        headers.insert(
+            CONTENT_TYPE,
+            HeaderValue::from_static("multipart/form-data; boundary=tc-boundary"),
+        );
+
+        assert_eq!(boundary(&headers), Some(BOUNDARY.to_string()));
+    }
+
+    #[tokio::test]
+    async fn test_upload() -> TCResult<()> {
+        let host = TestHost::new(vec![]).await?;
+        let txn = host.new_txn(true).await?;
+
+        // a part larger than a block is split across several blocks
+        let large: Vec<u8> = (0..(BLOCK_SIZE + 100)).map(|i| (i % 251) as u8).collect();
+        let body = form(&[("small", b"hello"), ("large", &large)]);
+
+        let links = upload(&txn, BOUNDARY.to_string(), body, UploadLimits::default()).await?;
+        assert_eq!(links.len(), 2);
+
+        for (name, expected) in &[("small", &b"hello"[..]), ("large", &large[..])] {
+            let name: Id = name.parse()?;
+            let link = match Value::try_from(links[&name].clone())? {
+                Value::Link(link) => link,
+                other => panic!("expected a link to an upload but found {}", other),
+            };
+
+            let id = link.path().last().expect("upload ID");
+            let contents = txn.uploaded(id).expect("upload").read(*txn.id()).await?;
+            assert_eq!(&contents[..], *expected);
+
+            let contents = host.gateway().get(&txn, link, Value::None).await?;
+            assert!(Value::try_from(contents)? == Value::from(Bytes::copy_from_slice(expected)));
+        }
+
+        let duplicate = form(&[("part", b"one"), ("part", b"two")]);
+        let result = upload(
+            &txn,
+            BOUNDARY.to_string(),
+            duplicate,
+            UploadLimits::default(),
+        )
+        .await;
+        assert_eq!(
+            result.map(|_| ()).unwrap_err().code(),
+            ErrorType::BadRequest
+        );
+
+        let truncated = Body::from(format!("--{}\r\nContent-Disposition: form-data", BOUNDARY));
+        let result = upload(
+            &txn,
+            BOUNDARY.to_string(),
+            truncated,
+            UploadLimits::default(),
+        )
+        .await;
+        assert_eq!(
+            result.map(|_| ()).unwrap_err().code(),
+            ErrorType::BadRequest
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_upload_limits() -> TCResult<()> {
+        let host = TestHost::new(vec![]).await?;
+        let txn = host.new_txn(true).await?;
+        let limits = UploadLimits {
+            max_part_size: 10,
+            max_size: 1_000,
+        };
+
+        let body = form(&[("small", b"hello")]);
+        assert_eq!(
+            upload(&txn, BOUNDARY.to_string(), body, limits)
+                .await?
+                .len(),
+            1
+        );
+
+        // one part which is larger than the limit of a part
+        let body = form(&[("large", &[0u8; 11])]);
+        let result = upload(&txn, BOUNDARY.to_string(), body, limits).await;
+        assert_eq!(
+            result.map(|_| ()).unwrap_err().code(),
+            ErrorType::BadRequest
+        );
+
+        // many parts, each within the limit of a part, which together exceed the limit of a request
+        let names: Vec<String> = (0..100).map(|i| format!("part{}", i)).collect();
+        let parts: Vec<(&str, &[u8])> = names
+            .iter()
+            .map(|name| (name.as_str(), &b"0123456789"[..]))
+            .collect();
+        let result = upload(&txn, BOUNDARY.to_string(), form(&parts), limits).await;
+        assert_eq!(
+            result.map(|_| ()).unwrap_err().code(),
+            ErrorType::BadRequest
+        );
+
+        Ok(())
+    }
+}
@@ -1,62 +1,290 @@
 use std::collections::HashMap;
-use std::net::SocketAddr;
+use std::io;
+use std::net::{IpAddr, SocketAddr};
 use std::sync::Arc;
+use std::time::{Duration, Instant};
 
 use async_trait::async_trait;
-use destream::de::FromStream;
 use futures::{future, stream, StreamExt, TryFutureExt, TryStreamExt};
+use hyper::header::{HeaderMap, HeaderValue};
+use hyper::server::accept::{self, Accept};
+use hyper::server::conn::{AddrIncoming, AddrStream};
 use hyper::service::{make_service_fn, service_fn};
 use hyper::{Body, Response};
 use log::debug;
 use serde::de::DeserializeOwned;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::{TcpListener, TcpStream};
+use tokio_rustls::server::TlsStream;
 
 use tc_error::*;
-use tc_transact::{IntoView, TxnId};
-use tcgeneric::{NetworkTime, TCPathBuf};
+use tc_transact::{stats, IntoView, Transaction, TxnId};
+use tcgeneric::TCPathBuf;
 
-use crate::gateway::Gateway;
+use crate::gateway::{self, Gateway};
+use crate::replay::Recorder;
+use crate::scalar::DecodeContext;
 use crate::state::State;
 use crate::txn::*;
 
-const CONTENT_TYPE: &str = "application/json";
+use super::body;
+use super::compression::{self, Compression};
+use super::cors::Cors;
+use super::encoding::Encoding;
+use super::multipart;
+use super::tls::{self, CertResolver};
+use super::DEPTH;
+
+const DECODE_MODE: &str = "x-tinychain-decode";
+const STATS: &str = "x-tinychain-stats";
+const TIMEZONE: &str = "x-tinychain-timezone";
+
+// how often to ping an idle HTTP/2 connection, to detect a dead peer
+const HTTP2_KEEP_ALIVE: Duration = Duration::from_secs(20);
 
 type GetParams = HashMap<String, String>;
 
 /// Tinychain's HTTP server. Should only be used through a [`Gateway`].
 pub struct HTTPServer {
     gateway: Arc<Gateway>,
+    recorder: Option<Recorder>,
+    tls: Option<Arc<CertResolver>>,
+    compression_threshold: usize,
+    http2_max_streams: u32,
+    cors: Cors,
+    request_limit: u64,
+    upload_limits: multipart::UploadLimits,
 }
 
 impl HTTPServer {
-    pub fn new(gateway: Arc<Gateway>) -> Self {
-        Self { gateway }
+    pub(crate) fn new(
+        gateway: Arc<Gateway>,
+        recorder: Option<Recorder>,
+        tls: Option<Arc<CertResolver>>,
+        compression_threshold: usize,
+        http2_max_streams: u32,
+        cors: Cors,
+        upload_limits: multipart::UploadLimits,
+    ) -> Self {
+        Self {
+            gateway,
+            recorder,
+            tls,
+            compression_threshold,
+            http2_max_streams,
+            cors,
+            request_limit: body::DEFAULT_REQUEST_LIMIT,
+            upload_limits,
+        }
+    }
+
+    /// Reject a request body, other than a multipart upload, which is larger than `limit` bytes.
+    pub(crate) fn with_request_limit(mut self, limit: u64) -> Self {
+        self.request_limit = limit;
+        self
+    }
+
+    async fn serve<I>(self: Arc<Self>, incoming: I) -> Result<(), hyper::Error>
+    where
+        I: Accept,
+        I::Conn: RemoteAddr + AsyncRead + AsyncWrite + Unpin + Send + 'static,
+        I::Error: Into<Box<dyn std::error::Error + Send + Sync>>,
+    {
+        let http2_max_streams = self.http2_max_streams;
+        let new_service = make_service_fn(move |conn: &I::Conn| {
+            let server = self.clone();
+            let client = conn.remote_addr();
+            async move {
+                Ok::<_, hyper::Error>(service_fn(move |req| {
+                    let server = server.clone();
+                    HTTPServer::handle(server, client, req)
+                }))
+            }
+        });
+
+        // a client which sends the HTTP/2 connection preface is served over HTTP/2,
+        // so that one connection from a peer can multiplex many concurrent requests
+        hyper::Server::builder(incoming)
+            .http2_max_concurrent_streams(http2_max_streams)
+            .http2_adaptive_window(true)
+            .http2_keep_alive_interval(HTTP2_KEEP_ALIVE)
+            .serve(new_service)
+            .with_graceful_shutdown(shutdown_signal())
+            .await
     }
 
     async fn handle(
         self: Arc<Self>,
+        client: Option<IpAddr>,
         request: hyper::Request<Body>,
     ) -> Result<Response<Body>, hyper::Error> {
+        if Cors::is_preflight(&request) {
+            return Ok(self.cors.preflight(&request));
+        }
+
+        let allow_origin = self.cors.allow_origin(request.headers());
+        let mut response = match self.rate_limit(client, &request) {
+            Ok(()) => self.clone().handle_request(request).await?,
+            Err(retry_after) => too_many_requests(retry_after),
+        };
+
+        if let Some(allow_origin) = allow_origin {
+            self.cors.apply(allow_origin, &mut response);
+        }
+
+        Ok(response)
+    }
+
+    async fn handle_request(
+        self: Arc<Self>,
+        request: hyper::Request<Body>,
+    ) -> Result<Response<Body>, hyper::Error> {
+        let request = match &self.recorder {
+            Some(recorder) => match recorder.record(request).await {
+                Ok(request) => request,
+                Err(cause) => return Ok(transform_error(cause)),
+            },
+            None => request,
+        };
+
         let (params, txn) = match self.process_headers(&request).await {
             Ok((params, txn)) => (params, txn),
             Err(cause) => return Ok(transform_error(cause)),
         };
 
-        match self.route(&txn, params, request).await {
-            Ok(state) => match destream_json::encode(state.into_view(txn)) {
-                Ok(response) => {
-                    let mut response = Response::new(Body::wrap_stream(
-                        response.chain(stream::once(future::ready(Ok(b"\n".to_vec())))),
-                    ));
+        let encoding = match Encoding::accept(request.headers()) {
+            Ok(encoding) => encoding,
+            Err(cause) => return Ok(transform_error(cause)),
+        };
 
-                    response
-                        .headers_mut()
-                        .insert(hyper::header::CONTENT_TYPE, CONTENT_TYPE.parse().unwrap());
+        let compression = match Compression::accept(request.headers()) {
+            Ok(compression) => compression,
+            Err(cause) => return Ok(transform_error(cause)),
+        };
+
+        let tracker = match stats_requested(&request) {
+            Ok(true) => Some(stats::track(*txn.id())),
+            Ok(false) => None,
+            Err(cause) => return Ok(transform_error(cause)),
+        };
+
+        let conditional = request.method() == hyper::Method::GET;
+        let if_none_match = request.headers().get(hyper::header::IF_NONE_MATCH).cloned();
 
-                    Ok(response)
+        let start = Instant::now();
+        let result = self.route(&txn, params, request).await;
+
+        let etag = match &result {
+            Ok(state) if conditional && state.is_hashable() => {
+                match etag(txn.clone(), state.clone()).await {
+                    Ok(etag) => Some(etag),
+                    Err(cause) => return Ok(transform_error(cause)),
+                }
+            }
+            _ => None,
+        };
+
+        let stats = tracker.map(|tracker| {
+            let stats = tracker.stats();
+            let millis = |elapsed: Duration| elapsed.as_secs_f64() * 1000.;
+
+            serde_json::json!({
+                "wall_time_ms": millis(start.elapsed()),
+                "lock_wait_ms": millis(stats.lock_wait()),
+                "blocks_read": stats.blocks_read(),
+                "blocks_written": stats.blocks_written(),
+                "device_time_ms": millis(stats.device_time()),
+            })
+            .to_string()
+        });
+
+        let mut response = match etag {
+            Some(etag) if is_unchanged(if_none_match.as_ref(), &etag) => not_modified(etag),
+            Some(etag) => {
+                let mut response = self.respond(txn, result, encoding, compression).await;
+                if response.status().is_success() {
+                    response.headers_mut().insert(hyper::header::ETAG, etag);
                 }
-                Err(cause) => Ok(transform_error(TCError::internal(cause))),
+
+                response
+            }
+            None => self.respond(txn, result, encoding, compression).await,
+        };
+
+        if let Some(stats) = stats {
+            if let Ok(stats) = stats.parse() {
+                response.headers_mut().insert(STATS, stats);
+            }
+        }
+
+        Ok(response)
+    }
+
+    // count a request from the given `client`, which is not limited if its address is unknown
+    fn rate_limit(
+        &self,
+        client: Option<IpAddr>,
+        request: &hyper::Request<Body>,
+    ) -> Result<(), Duration> {
+        let client = match client {
+            Some(client) => client,
+            None => return Ok(()),
+        };
+
+        let token = request
+            .headers()
+            .get(hyper::header::AUTHORIZATION)
+            .and_then(|header| header.to_str().ok());
+
+        let write = request.method() != hyper::Method::GET;
+        self.gateway.rate_limit(client, token, write)
+    }
+
+    async fn respond(
+        &self,
+        txn: Txn,
+        result: TCResult<State>,
+        encoding: Encoding,
+        compression: Option<Compression>,
+    ) -> Response<Body> {
+        match result {
+            Ok(state) => match encoding.encode(state.into_view(txn)) {
+                Ok(body) => {
+                    let body = match encoding {
+                        Encoding::Json => body
+                            .chain(stream::once(future::ready(Ok(b"\n".to_vec()))))
+                            .boxed(),
+                        _ => body,
+                    };
+
+                    let (compression, body) =
+                        compression::compress(body, compression, self.compression_threshold).await;
+
+                    let mut response = Response::new(Body::wrap_stream(body));
+                    let headers = response.headers_mut();
+
+                    headers.insert(
+                        hyper::header::CONTENT_TYPE,
+                        encoding.mime_type().parse().unwrap(),
+                    );
+
+                    headers.insert(
+                        hyper::header::VARY,
+                        hyper::header::ACCEPT_ENCODING.as_str().parse().unwrap(),
+                    );
+
+                    if let Some(compression) = compression {
+                        headers.insert(
+                            hyper::header::CONTENT_ENCODING,
+                            compression.name().parse().unwrap(),
+                        );
+                    }
+
+                    response
+                }
+                Err(cause) => transform_error(cause),
             },
-            Err(cause) => Ok(transform_error(cause)),
+            Err(cause) => transform_error(cause),
         }
     }
 
@@ -95,10 +323,29 @@ impl HTTPServer {
         let txn_id = if let Some(txn_id) = params.remove("txn_id") {
             txn_id.parse()?
         } else {
-            TxnId::new(NetworkTime::now())
+            TxnId::new(Gateway::time())
+        };
+
+        let locale = locale(http_request)?;
+        let mutation = http_request.method() != hyper::Method::GET;
+        let txn = self
+            .gateway
+            .new_txn(txn_id, token, locale, mutation)
+            .await?;
+
+        let txn = match http_request.headers().get(DEPTH) {
+            Some(depth) => {
+                let depth = depth
+                    .to_str()
+                    .ok()
+                    .and_then(|depth| depth.parse().ok())
+                    .ok_or_else(|| TCError::bad_request("invalid call depth", DEPTH))?;
+
+                txn.resume(depth)
+            }
+            None => txn,
         };
 
-        let txn = self.gateway.new_txn(txn_id, token).await?;
         Ok((params, txn))
     }
 
@@ -109,8 +356,11 @@ impl HTTPServer {
         http_request: hyper::Request<Body>,
     ) -> TCResult<State> {
         let path: TCPathBuf = http_request.uri().path().parse()?;
+        let strict = strict_decoding(&http_request)?;
+        let encoding = Encoding::of(http_request.headers())?;
+        let (parts, body) = http_request.into_parts();
 
-        match http_request.method() {
+        match &parts.method {
             &hyper::Method::GET => {
                 let key = get_param(&mut params, "key")?.unwrap_or_default();
                 self.gateway.get(txn, path.into(), key).await
@@ -118,15 +368,30 @@ impl HTTPServer {
 
             &hyper::Method::PUT => {
                 let key = get_param(&mut params, "key")?.unwrap_or_default();
-                let value = destream_body(http_request.into_body(), txn.clone()).await?;
+                let limit = self.request_limit;
+                let value =
+                    destream_body(txn, &parts.headers, body, strict, encoding, limit).await?;
                 self.gateway
                     .put(txn, path.into(), key, value)
                     .map_ok(State::from)
                     .await
             }
 
+            &hyper::Method::POST if path.as_slice() == &UPLOADS[..] => {
+                let boundary = multipart::boundary(&parts.headers).ok_or_else(|| {
+                    TCError::bad_request("an upload requires a multipart/form-data body", path)
+                })?;
+
+                self.gateway.keys().authorize(txn, &UPLOADS.into())?;
+                multipart::upload(txn, boundary, body, self.upload_limits)
+                    .map_ok(State::Map)
+                    .await
+            }
+
             &hyper::Method::POST => {
-                let data = destream_body(http_request.into_body(), txn.clone()).await?;
+                let limit = self.request_limit;
+                let data =
+                    destream_body(txn, &parts.headers, body, strict, encoding, limit).await?;
                 self.gateway.post(txn, path.into(), data).await
             }
 
@@ -137,40 +402,158 @@ impl HTTPServer {
 
 #[async_trait]
 impl crate::gateway::Server for HTTPServer {
-    type Error = hyper::Error;
+    type Error = io::Error;
 
     async fn listen(self, addr: SocketAddr) -> Result<(), Self::Error> {
-        println!("HTTP server listening on {}", &addr);
         let server = Arc::new(self);
 
-        let new_service = make_service_fn(move |_| {
-            let server = server.clone();
-            async {
-                Ok::<_, hyper::Error>(service_fn(move |req| {
-                    let server = server.clone();
-                    HTTPServer::handle(server, req)
-                }))
-            }
-        });
+        let served = if let Some(resolver) = &server.tls {
+            resolver.watch();
 
-        hyper::Server::bind(&addr)
-            .serve(new_service)
-            .with_graceful_shutdown(shutdown_signal())
-            .await
+            let listener = TcpListener::bind(&addr).await?;
+            let incoming = tls::incoming(listener, resolver.clone());
+            println!("HTTPS server listening on {}", &addr);
+            server.serve(accept::from_stream(incoming)).await
+        } else {
+            let incoming = AddrIncoming::bind(&addr).map_err(hyper_error)?;
+            println!("HTTP server listening on {}", &addr);
+            server.serve(incoming).await
+        };
+
+        served.map_err(hyper_error)
+    }
+}
+
+/// A connection whose remote address may be known.
+trait RemoteAddr {
+    fn remote_addr(&self) -> Option<IpAddr>;
+}
+
+impl RemoteAddr for AddrStream {
+    fn remote_addr(&self) -> Option<IpAddr> {
+        Some(AddrStream::remote_addr(self).ip())
+    }
+}
+
+impl RemoteAddr for TlsStream<TcpStream> {
+    fn remote_addr(&self) -> Option<IpAddr> {
+        self.get_ref().0.peer_addr().map(|addr| addr.ip()).ok()
     }
 }
 
-async fn destream_body(body: hyper::Body, txn: Txn) -> TCResult<State> {
-    let data = body
-        .map_ok(|bytes| bytes.to_vec())
+// a 429 Too Many Requests response, which asks the client to retry after the given delay
+fn too_many_requests(delay: Duration) -> Response<Body> {
+    let mut response = transform_error(gateway::too_many_requests(delay));
+    response.headers_mut().insert(
+        hyper::header::RETRY_AFTER,
+        gateway::retry_after(delay).into(),
+    );
+
+    response
+}
+
+// a weak entity tag, since the hash identifies the contents of the state, not its encoding
+async fn etag(txn: Txn, state: State) -> TCResult<HeaderValue> {
+    let hash = state.hash(txn).await?;
+    format!("W/\"{}\"", hash.to_hex())
+        .parse()
+        .map_err(TCError::internal)
+}
+
+// true if the client's copy of the response, per its `If-None-Match` header, is up to date
+fn is_unchanged(if_none_match: Option<&HeaderValue>, etag: &HeaderValue) -> bool {
+    let if_none_match = match if_none_match.and_then(|header| header.to_str().ok()) {
+        Some(if_none_match) => if_none_match,
+        None => return false,
+    };
+
+    let etag = etag.to_str().expect("ETag");
+    let opaque = |tag: &str| tag.trim().trim_start_matches("W/").to_string();
+    if_none_match
+        .split(',')
+        .any(|tag| tag.trim() == "*" || opaque(tag) == opaque(etag))
+}
+
+fn not_modified(etag: HeaderValue) -> Response<Body> {
+    let mut response = Response::new(Body::empty());
+    *response.status_mut() = hyper::StatusCode::NOT_MODIFIED;
+
+    let headers = response.headers_mut();
+    headers.insert(hyper::header::ETAG, etag);
+    headers.insert(
+        hyper::header::VARY,
+        hyper::header::ACCEPT_ENCODING.as_str().parse().unwrap(),
+    );
+
+    response
+}
+
+async fn destream_body(
+    txn: &Txn,
+    headers: &HeaderMap,
+    body: hyper::Body,
+    strict: bool,
+    encoding: Encoding,
+    limit: u64,
+) -> TCResult<State> {
+    let data = body::json_chunks(txn, headers, body, encoding, limit)
+        .await?
         .map_err(destream::de::Error::custom);
 
+    let context = DecodeContext::default().with_strict(strict);
     let mut decoder = destream_json::de::Decoder::from(data);
-    State::from_stream(txn, &mut decoder)
+    State::decode(txn.clone(), context, &mut decoder)
         .map_err(|e| TCError::bad_request("error deserializing HTTP request body", e))
         .await
 }
 
+fn locale(http_request: &hyper::Request<Body>) -> TCResult<Locale> {
+    let header = |name| {
+        http_request
+            .headers()
+            .get(name)
+            .map(|value| {
+                value.to_str().map_err(|e| {
+                    TCError::bad_request(format!("unable to parse {} header", name), e)
+                })
+            })
+            .transpose()
+    };
+
+    let accept_language = header(hyper::header::ACCEPT_LANGUAGE.as_str())?;
+    let timezone = header(TIMEZONE)?;
+    Locale::parse(accept_language, timezone)
+}
+
+// the client may request execution statistics with an "X-Tinychain-Stats: true" header
+fn stats_requested(http_request: &hyper::Request<Body>) -> TCResult<bool> {
+    match http_request.headers().get(STATS) {
+        None => Ok(false),
+        Some(flag) => match flag.to_str() {
+            Ok("true") => Ok(true),
+            Ok("false") => Ok(false),
+            _ => Err(TCError::bad_request(
+                "stats header should be \"true\" or \"false\", not",
+                format!("{:?}", flag),
+            )),
+        },
+    }
+}
+
+fn strict_decoding(http_request: &hyper::Request<Body>) -> TCResult<bool> {
+    match http_request.headers().get(DECODE_MODE) {
+        None => Ok(false),
+        Some(mode) => match mode.to_str() {
+            Ok("strict") => Ok(true),
+            Ok("lenient") => Ok(false),
+            _ => Err(TCError::bad_request(
+                "decode mode should be \"strict\" or \"lenient\", not",
+                format!("{:?}", mode),
+            )),
+        },
+    }
+}
+
 fn get_param<T: DeserializeOwned>(
     params: &mut HashMap<String, String>,
     name: &str,
@@ -200,13 +583,192 @@ fn transform_error(err: TCError) -> hyper::Response<Body> {
         MethodNotAllowed => StatusCode::METHOD_NOT_ALLOWED,
         NotFound => StatusCode::NOT_FOUND,
         NotImplemented => StatusCode::NOT_IMPLEMENTED,
+        PayloadTooLarge => StatusCode::PAYLOAD_TOO_LARGE,
+        PolicyViolation => StatusCode::FORBIDDEN,
         Timeout => StatusCode::REQUEST_TIMEOUT,
+        TooManyRequests => StatusCode::TOO_MANY_REQUESTS,
         Unauthorized => StatusCode::UNAUTHORIZED,
+        Unavailable => StatusCode::SERVICE_UNAVAILABLE,
     };
 
     response
 }
 
+fn hyper_error(cause: hyper::Error) -> io::Error {
+    io::Error::other(cause)
+}
+
 async fn shutdown_signal() {
     tokio::signal::ctrl_c().await.expect("SIGTERM handler")
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::test::TestHost;
+
+    use super::*;
+
+    // serve the gateway of the given test host on an unused local port
+    fn serve(host: &TestHost) -> TCResult<SocketAddr> {
+        let server = HTTPServer::new(
+            host.gateway().clone(),
+            None,
+            None,
+            1_000,
+            4,
+            Cors::default(),
+            multipart::UploadLimits::default(),
+        );
+
+        let incoming =
+            AddrIncoming::bind(&([127, 0, 0, 1], 0).into()).map_err(TCError::internal)?;
+        let addr = incoming.local_addr();
+        tokio::spawn(Arc::new(server).serve(incoming));
+        Ok(addr)
+    }
+
+    #[tokio::test]
+    async fn test_http2() -> TCResult<()> {
+        let host = TestHost::new(vec![]).await?;
+        let addr = serve(&host)?;
+        let uri = format!("http://{}/state/scalar/value/number/bool?key=1", addr);
+
+        let client = hyper::Client::builder()
+            .http2_only(true)
+            .build_http::<Body>();
+
+        // open the connection, so that the client knows the maximum number of streams
+        let response = client
+            .get(uri.parse().unwrap())
+            .map_err(TCError::internal)
+            .await?;
+
+        assert_eq!(response.version(), hyper::Version::HTTP_2);
+
+        // then send more concurrent requests than that, over the same connection
+        let responses = future::try_join_all((0..16).map(|_| client.get(uri.parse().unwrap())))
+            .map_err(TCError::internal)
+            .await?;
+
+        for response in responses {
+            assert_eq!(response.version(), hyper::Version::HTTP_2);
+            assert_eq!(response.status(), hyper::StatusCode::OK);
+
+            let body = hyper::body::to_bytes(response.into_body())
+                .map_err(TCError::internal)
+                .await?;
+
+            assert_eq!(&body[..], b"true\n");
+        }
+
+        // a client which doesn't support HTTP/2 is still served over HTTP/1.1
+        let response = hyper::Client::new()
+            .get(uri.parse().unwrap())
+            .map_err(TCError::internal)
+            .await?;
+
+        assert_eq!(response.version(), hyper::Version::HTTP_11);
+        assert_eq!(response.status(), hyper::StatusCode::OK);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_upload_unauthorized() -> TCResult<()> {
+        let host = TestHost::new(vec![]).await?;
+        let addr = serve(&host)?;
+
+        let body = "--tc-boundary\r\nContent-Disposition: form-data; name=\"part\"\r\n\r\nhello\r\n--tc-boundary--\r\n";
+        let request = hyper::Request::post(format!("http://{}/transact/upload", addr))
+            .header(
+                hyper::header::CONTENT_TYPE,
+                "multipart/form-data; boundary=tc-boundary",
+            )
+            .body(Body::from(body))
+            .unwrap();
+
+        let response = hyper::Client::new()
+            .request(request)
+            .map_err(TCError::internal)
+            .await?;
+
+        assert_eq!(response.status(), hyper::StatusCode::UNAUTHORIZED);
+        Ok(())
+    }
+
+    #[test]
+    fn test_is_unchanged() {
+        let etag = HeaderValue::from_static("W/\"abc\"");
+        let header = |value: &'static str| Some(HeaderValue::from_static(value));
+
+        assert!(!is_unchanged(None, &etag));
+        assert!(is_unchanged(header("W/\"abc\"").as_ref(), &etag));
+        assert!(is_unchanged(header("\"abc\"").as_ref(), &etag));
+        assert!(is_unchanged(header("\"xyz\", W/\"abc\"").as_ref(), &etag));
+        assert!(is_unchanged(header("*").as_ref(), &etag));
+        assert!(!is_unchanged(header("\"xyz\"").as_ref(), &etag));
+    }
+
+    #[tokio::test]
+    async fn test_etag() -> TCResult<()> {
+        let host = TestHost::new(vec![]).await?;
+        let addr = serve(&host)?;
+        let uri = |key: u64| {
+            format!(
+                "http://{}/state/scalar/value/number/uint/64?key={}",
+                addr, key
+            )
+            .parse::<hyper::Uri>()
+            .unwrap()
+        };
+
+        let conditional = |key: u64, etag: HeaderValue| {
+            hyper::Request::get(uri(key))
+                .header(hyper::header::IF_NONE_MATCH, etag)
+                .body(Body::empty())
+                .unwrap()
+        };
+
+        // a client only learns an ETag from an unconditional request
+        let client = hyper::Client::new();
+        let response = client.get(uri(1)).map_err(TCError::internal).await?;
+        assert_eq!(response.status(), hyper::StatusCode::OK);
+        let etag = response.headers()[hyper::header::ETAG].clone();
+        assert!(etag.to_str().unwrap().starts_with("W/\""));
+
+        let unknown = HeaderValue::from_static("W/\"unknown\"");
+        let response = client
+            .request(conditional(1, unknown))
+            .map_err(TCError::internal)
+            .await?;
+
+        assert_eq!(response.status(), hyper::StatusCode::OK);
+        assert_eq!(response.headers()[hyper::header::ETAG], etag);
+
+        // the same state has the same ETag, so the client's copy is up to date
+        let response = client
+            .request(conditional(1, etag.clone()))
+            .map_err(TCError::internal)
+            .await?;
+
+        assert_eq!(response.status(), hyper::StatusCode::NOT_MODIFIED);
+        assert_eq!(response.headers()[hyper::header::ETAG], etag);
+
+        let body = hyper::body::to_bytes(response.into_body())
+            .map_err(TCError::internal)
+            .await?;
+
+        assert!(body.is_empty());
+
+        // a different state has a different ETag
+        let response = client
+            .request(conditional(2, etag.clone()))
+            .map_err(TCError::internal)
+            .await?;
+
+        assert_eq!(response.status(), hyper::StatusCode::OK);
+        assert_ne!(response.headers()[hyper::header::ETAG], etag);
+
+        Ok(())
+    }
+}
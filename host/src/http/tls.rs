@@ -0,0 +1,198 @@
+//! TLS for the HTTP server, with a certificate which is reloaded when it changes on disk.
+
+use std::fs::File;
+use std::io::{self, BufReader};
+use std::path::PathBuf;
+use std::sync::{Arc, PoisonError, RwLock};
+use std::time::{Duration, SystemTime};
+
+use futures::{future, stream, Stream, StreamExt};
+use log::{debug, info, warn};
+use tokio::net::{TcpListener, TcpStream};
+use tokio_rustls::rustls::internal::pemfile;
+use tokio_rustls::rustls::sign::{self, CertifiedKey};
+use tokio_rustls::rustls::{ClientHello, NoClientAuth, ResolvesServerCert, ServerConfig};
+use tokio_rustls::server::TlsStream;
+use tokio_rustls::TlsAcceptor;
+
+use tc_error::*;
+
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+const MAX_HANDSHAKES: usize = 64;
+const RELOAD_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Resolves the certificate of this host, reloading it from disk when it changes so that it can
+/// be renewed without a restart.
+pub(crate) struct CertResolver {
+    cert: PathBuf,
+    key: PathBuf,
+    current: RwLock<(SystemTime, CertifiedKey)>,
+}
+
+impl CertResolver {
+    /// Load the PEM-encoded certificate chain and private key at the given paths.
+    pub fn load(cert: PathBuf, key: PathBuf) -> TCResult<Arc<Self>> {
+        let modified = modified(&cert, &key)?;
+        let certified = certified_key(&cert, &key)?;
+
+        Ok(Arc::new(Self {
+            cert,
+            key,
+            current: RwLock::new((modified, certified)),
+        }))
+    }
+
+    /// Check for a new certificate every few seconds, for as long as this resolver is in use.
+    pub fn watch(self: &Arc<Self>) {
+        let resolver = Arc::downgrade(self);
+
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(RELOAD_INTERVAL);
+            loop {
+                interval.tick().await;
+
+                match resolver.upgrade() {
+                    Some(resolver) => {
+                        if let Err(cause) = resolver.reload() {
+                            warn!("unable to reload TLS certificate: {}", cause);
+                        }
+                    }
+                    None => break,
+                }
+            }
+        });
+    }
+
+    fn reload(&self) -> TCResult<()> {
+        let modified = modified(&self.cert, &self.key)?;
+        if modified
+            == self
+                .current
+                .read()
+                .unwrap_or_else(PoisonError::into_inner)
+                .0
+        {
+            return Ok(());
+        }
+
+        let certified = certified_key(&self.cert, &self.key)?;
+        *self.current.write().unwrap_or_else(PoisonError::into_inner) = (modified, certified);
+        info!("reloaded TLS certificate from {:?}", self.cert);
+        Ok(())
+    }
+}
+
+impl ResolvesServerCert for CertResolver {
+    fn resolve(&self, _client_hello: ClientHello) -> Option<CertifiedKey> {
+        let current = self.current.read().unwrap_or_else(PoisonError::into_inner);
+        Some(current.1.clone())
+    }
+}
+
+/// Accept TLS connections on the given `listener`, skipping any which fail their handshake.
+///
+/// HTTP/2 is negotiated by ALPN, with a fallback to HTTP/1.1.
+pub(crate) fn incoming(
+    listener: TcpListener,
+    resolver: Arc<CertResolver>,
+) -> impl Stream<Item = Result<TlsStream<TcpStream>, io::Error>> {
+    let mut config = ServerConfig::new(NoClientAuth::new());
+    config.cert_resolver = resolver;
+    config.set_protocols(&[b"h2".to_vec(), b"http/1.1".to_vec()]);
+    let acceptor = TlsAcceptor::from(Arc::new(config));
+
+    stream::unfold(listener, |listener| async move {
+        let accepted = listener.accept().await;
+        Some((accepted, listener))
+    })
+    .filter_map(|accepted| {
+        future::ready(match accepted {
+            Ok((stream, _)) => Some(stream),
+            Err(cause) => {
+                warn!("unable to accept TCP connection: {}", cause);
+                None
+            }
+        })
+    })
+    .map(move |stream| tokio::time::timeout(HANDSHAKE_TIMEOUT, acceptor.accept(stream)))
+    .buffer_unordered(MAX_HANDSHAKES)
+    .filter_map(|handshake| {
+        future::ready(match handshake {
+            Ok(Ok(stream)) => Some(Ok(stream)),
+            Ok(Err(cause)) => {
+                debug!("TLS handshake failed: {}", cause);
+                None
+            }
+            Err(_) => {
+                debug!("TLS handshake timed out");
+                None
+            }
+        })
+    })
+}
+
+fn certified_key(cert: &PathBuf, key: &PathBuf) -> TCResult<CertifiedKey> {
+    let certs = pemfile::certs(&mut open(cert)?)
+        .map_err(|()| TCError::bad_request("invalid TLS certificate at", cert.display()))?;
+
+    if certs.is_empty() {
+        return Err(TCError::bad_request(
+            "no TLS certificate found at",
+            cert.display(),
+        ));
+    }
+
+    let invalid_key = |()| TCError::bad_request("invalid TLS private key at", key.display());
+    let mut keys = pemfile::pkcs8_private_keys(&mut open(key)?).map_err(invalid_key)?;
+    if keys.is_empty() {
+        keys = pemfile::rsa_private_keys(&mut open(key)?).map_err(invalid_key)?;
+    }
+
+    let key = keys.into_iter().next().ok_or_else(|| invalid_key(()))?;
+    let key = sign::any_supported_type(&key).map_err(invalid_key)?;
+
+    Ok(CertifiedKey::new(certs, Arc::new(key)))
+}
+
+// the time of the most recent change to either the certificate or the private key
+fn modified(cert: &PathBuf, key: &PathBuf) -> TCResult<SystemTime> {
+    let modified = |path: &PathBuf| {
+        std::fs::metadata(path)
+            .and_then(|meta| meta.modified())
+            .map_err(|e| TCError::internal(format!("unable to read {:?}: {}", path, e)))
+    };
+
+    Ok(modified(cert)?.max(modified(key)?))
+}
+
+fn open(path: &PathBuf) -> TCResult<BufReader<File>> {
+    File::open(path)
+        .map(BufReader::new)
+        .map_err(|e| TCError::internal(format!("unable to open {:?}: {}", path, e)))
+}
+
+#[cfg(test)]
+mod tests {
+    use uuid::Uuid;
+
+    use super::*;
+
+    #[test]
+    fn test_load_invalid() {
+        let root = std::env::temp_dir().join(format!("tc-test-{}", Uuid::new_v4()));
+        std::fs::create_dir_all(&root).unwrap();
+
+        let cert = root.join("cert.pem");
+        let key = root.join("key.pem");
+
+        let err = CertResolver::load(cert.clone(), key.clone()).map(|_| ());
+        assert!(err.unwrap_err().code() == ErrorType::Internal);
+
+        std::fs::write(&cert, "not a certificate").unwrap();
+        std::fs::write(&key, "not a key").unwrap();
+        let err = CertResolver::load(cert.clone(), key.clone()).map(|_| ());
+        assert!(err.unwrap_err().code() == ErrorType::BadRequest);
+
+        std::fs::remove_dir_all(root).unwrap();
+    }
+}
@@ -0,0 +1,332 @@
+//! Built-in micro-benchmarks, to compare the performance of hosts with different hardware and
+//! configurations.
+//!
+//! Each benchmark is run on demand by a GET request to `/sbin/bench/<name>`, with an optional key
+//! to set the size of the workload, and returns a [`Map`] of its results. Since a benchmark can
+//! occupy this host for a long time, it requires the `/sbin/bench` scope from a trusted issuer
+//! key. The benchmarks are:
+//!  - `btree_insert`: insert N rows (default 10,000) into a new `BTree` in random order
+//!  - `block_io`: write N blocks (default 100) of 64 KiB to the workspace, then read them back,
+//!    one at a time and then as a scan with read-ahead
+//!  - `tensor_matmul`: multiply two N x N (default 64) matrices of 64-bit floats
+//!  - `buffer_pool`: fill and drain a map of op results N times (default 100,000), first with
+//!    a map borrowed from the transaction's `BufferPool` and then with a newly allocated map
+
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::time::{Duration, Instant};
+
+use bytes::Bytes;
+use futures::StreamExt;
+use log::info;
+use safecast::{CastFrom, TryCastFrom};
+
+use tc_error::*;
+use tc_transact::fs::{Dir, File};
+use tc_transact::{Transact, Transaction};
+use tcgeneric::{label, Id, Label, Map, PathSegment};
+
+use crate::collection::{BTree, Column, Tensor};
+use crate::fs;
+use crate::scalar::{FloatType, Number, NumberType, ScalarType, UIntType, Value, ValueType};
+use crate::state::{State, StateType};
+use crate::txn::Txn;
+
+const BLOCK_SIZE: usize = 65_536;
+const READ_AHEAD: usize = 8;
+const STEPS: usize = 16;
+
+const BENCH: Label = label("bench");
+const BLOCKS: Label = label("blocks");
+
+/// Run the benchmark with the given `name`, with a workload of the given `size`.
+pub async fn run(txn: &Txn, name: &PathSegment, size: Value) -> TCResult<State> {
+    let results = match name.as_str() {
+        "btree_insert" => btree_insert(txn, size_of(size, 10_000, 1_000_000)?).await?,
+        "block_io" => block_io(txn, size_of(size, 100, 10_000)?).await?,
+        "tensor_matmul" => tensor_matmul(txn, size_of(size, 64, 512)?).await?,
+        "buffer_pool" => buffer_pool(txn, size_of(size, 100_000, 10_000_000)?)?,
+        _ => return Err(TCError::not_found(format!("benchmark {}", name))),
+    };
+
+    info!("benchmark {}: {}", name, results);
+
+    Ok(State::Map(
+        results
+            .into_iter()
+            .map(|(name, result)| (name, State::from(Value::from(result))))
+            .collect(),
+    ))
+}
+
+async fn btree_insert(txn: &Txn, rows: u64) -> TCResult<Map<Number>> {
+    let dtype = ValueType::Number(NumberType::UInt(UIntType::U64));
+    let btree = BTree::create(txn, vec![Column::from((label("key"), dtype))]).await?;
+
+    let start = Instant::now();
+    for i in 0..rows {
+        // an odd multiplier visits every u64 exactly once, in a scattered order
+        let key = i.wrapping_mul(0x9E37_79B9_7F4A_7C15);
+        btree
+            .insert(*txn.id(), vec![Value::from(Number::from(key))])
+            .await?;
+    }
+
+    let elapsed = start.elapsed();
+
+    Ok(results(vec![
+        ("rows", Number::from(rows)),
+        ("elapsed", seconds(elapsed)),
+        ("rows_per_second", per_second(rows, elapsed)),
+    ]))
+}
+
+async fn block_io(txn: &Txn, blocks: u64) -> TCResult<Map<Number>> {
+    let txn_id = *txn.id();
+    let subcontext = txn.subcontext(BENCH.into()).await?;
+    let class = StateType::Scalar(ScalarType::Value(ValueType::Bytes));
+    let file = subcontext
+        .context()
+        .create_file(txn_id, BLOCKS.into(), class)
+        .await?;
+    let file = fs::File::<Bytes>::try_from(file)?;
+
+    let block_ids = (0..blocks)
+        .map(|i| i.to_string().parse())
+        .collect::<TCResult<Vec<Id>>>()?;
+
+    let contents = Bytes::from(vec![0u8; BLOCK_SIZE]);
+
+    // writes are only synchronized with the filesystem when the file is committed, which has to
+    // start from the transaction's own directory since it may not exist on disk yet
+    let start = Instant::now();
+    for block_id in &block_ids {
+        file.create_block(txn_id, block_id.clone(), contents.clone())
+            .await?;
+    }
+
+    txn.context().commit(&txn_id).await;
+    let write_elapsed = start.elapsed();
+
+    let start = Instant::now();
+    for block_id in &block_ids {
+        file.get_block(&txn_id, block_id).await?;
+    }
+
+    let read_elapsed = start.elapsed();
+
+    let start = Instant::now();
+    let mut scan = Box::pin(file.clone().read_ahead(txn_id, block_ids, READ_AHEAD));
+    while let Some(block) = scan.next().await {
+        block?;
+    }
+
+    let scan_elapsed = start.elapsed();
+
+    file.finalize(&txn_id).await;
+
+    let bytes = blocks * BLOCK_SIZE as u64;
+
+    Ok(results(vec![
+        ("blocks", Number::from(blocks)),
+        ("block_size", Number::from(BLOCK_SIZE as u64)),
+        ("write_elapsed", seconds(write_elapsed)),
+        ("write_bytes_per_second", per_second(bytes, write_elapsed)),
+        ("read_elapsed", seconds(read_elapsed)),
+        ("read_bytes_per_second", per_second(bytes, read_elapsed)),
+        ("scan_elapsed", seconds(scan_elapsed)),
+        ("scan_bytes_per_second", per_second(bytes, scan_elapsed)),
+    ]))
+}
+
+async fn tensor_matmul(txn: &Txn, size: u64) -> TCResult<Map<Number>> {
+    let dtype = NumberType::Float(FloatType::F64);
+    let left = Tensor::create(txn, dtype, vec![size, size]).await?;
+    let right = Tensor::create(txn, dtype, vec![size, size]).await?;
+    left.write_value(*txn.id(), &[], Number::from(1.5f64))
+        .await?;
+    right
+        .write_value(*txn.id(), &[], Number::from(2.5f64))
+        .await?;
+
+    let start = Instant::now();
+    left.matmul(txn, &right).await?;
+    let elapsed = start.elapsed();
+
+    // one multiplication and one addition for each term of each element of the product
+    let flops = 2 * size * size * size;
+
+    Ok(results(vec![
+        ("size", Number::from(size)),
+        ("elapsed", seconds(elapsed)),
+        ("flops", per_second(flops, elapsed)),
+    ]))
+}
+
+fn buffer_pool(txn: &Txn, rounds: u64) -> TCResult<Map<Number>> {
+    // each round resolves one level of an op with this many steps, like `Executor::execute`
+    let steps = (0..STEPS)
+        .map(|i| format!("step_{}", i).parse())
+        .collect::<TCResult<Vec<Id>>>()?;
+
+    let mut scope = Vec::with_capacity(STEPS);
+    let mut resolve = |resolved: &mut HashMap<Id, State>| {
+        for id in &steps {
+            resolved.insert(id.clone(), State::default());
+        }
+
+        scope.extend(resolved.drain());
+        scope.clear();
+    };
+
+    let start = Instant::now();
+    for _ in 0..rounds {
+        resolve(&mut txn.buffers().map(STEPS));
+    }
+
+    let pooled_elapsed = start.elapsed();
+
+    let start = Instant::now();
+    for _ in 0..rounds {
+        resolve(&mut HashMap::with_capacity(STEPS));
+    }
+
+    let allocated_elapsed = start.elapsed();
+
+    let speedup = if pooled_elapsed.as_secs_f64() > 0. {
+        allocated_elapsed.as_secs_f64() / pooled_elapsed.as_secs_f64()
+    } else {
+        0.
+    };
+
+    Ok(results(vec![
+        ("rounds", Number::from(rounds)),
+        ("pooled_elapsed", seconds(pooled_elapsed)),
+        ("allocated_elapsed", seconds(allocated_elapsed)),
+        ("speedup", Number::from(speedup)),
+    ]))
+}
+
+fn results(results: Vec<(&'static str, Number)>) -> Map<Number> {
+    results
+        .into_iter()
+        .map(|(name, result)| (label(name).into(), result))
+        .collect()
+}
+
+fn seconds(elapsed: Duration) -> Number {
+    Number::from(elapsed.as_secs_f64())
+}
+
+fn per_second(count: u64, elapsed: Duration) -> Number {
+    let elapsed = elapsed.as_secs_f64();
+    if elapsed > 0. {
+        Number::from(count as f64 / elapsed)
+    } else {
+        Number::from(0f64)
+    }
+}
+
+fn size_of(size: Value, default: u64, max: u64) -> TCResult<u64> {
+    if size.is_none() {
+        return Ok(default);
+    }
+
+    let size = u64::cast_from(Number::try_cast_from(size, |v| {
+        TCError::bad_request("invalid benchmark size", v)
+    })?);
+
+    if (1..=max).contains(&size) {
+        Ok(size)
+    } else {
+        Err(TCError::bad_request(
+            format!("benchmark size must be between 1 and {}, not", max),
+            size,
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tcgeneric::TCPathBuf;
+
+    use crate::kernel::BENCH;
+    use crate::test::TestHost;
+
+    use super::*;
+
+    async fn bench(txn: &Txn, name: &'static str, size: u64) -> TCResult<Map<State>> {
+        let name: PathSegment = label(name).into();
+        match run(txn, &name, Value::from(Number::from(size))).await? {
+            State::Map(results) => Ok(results),
+            other => panic!("expected a Map of results but found {}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_bench() -> TCResult<()> {
+        let host = TestHost::new(vec![]).await?;
+        let txn = host.new_txn(true).await?;
+
+        let results = bench(&txn, "btree_insert", 10).await?;
+        let rows: Id = label("rows").into();
+        let rows = Value::try_from(results[&rows].clone())?;
+        assert!(rows == Value::from(Number::from(10u64)));
+
+        let results = bench(&txn, "block_io", 2).await?;
+        for name in &["write_elapsed", "read_elapsed", "scan_elapsed"] {
+            let name: Id = label(name).into();
+            assert!(results.contains_key(&name));
+        }
+
+        let results = bench(&txn, "tensor_matmul", 4).await?;
+        let flops: Id = label("flops").into();
+        assert!(results.contains_key(&flops));
+
+        let results = bench(&txn, "buffer_pool", 10).await?;
+        for name in &["pooled_elapsed", "allocated_elapsed", "speedup"] {
+            let name: Id = label(name).into();
+            assert!(results.contains_key(&name));
+        }
+
+        let unknown: PathSegment = label("unknown").into();
+        let err = run(&txn, &unknown, Value::None)
+            .await
+            .map(|_| ())
+            .unwrap_err();
+        assert!(err.code() == ErrorType::NotFound);
+
+        assert!(bench(&txn, "tensor_matmul", 513).await.is_err());
+        assert!(bench(&txn, "btree_insert", 0).await.is_err());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_unauthorized() -> TCResult<()> {
+        let host = TestHost::new(vec![]).await?;
+        let txn = host.new_txn(false).await?;
+
+        let path = TCPathBuf::from(BENCH).append(label("btree_insert"));
+        let err = host
+            .gateway()
+            .get(&txn, path.into(), Value::None)
+            .await
+            .map(|_| ())
+            .unwrap_err();
+
+        assert!(err.code() == ErrorType::Unauthorized);
+        Ok(())
+    }
+
+    #[test]
+    fn test_size_of() {
+        assert_eq!(size_of(Value::None, 10, 100).ok(), Some(10));
+        assert_eq!(
+            size_of(Value::from(Number::from(100u64)), 10, 100).ok(),
+            Some(100)
+        );
+        assert!(size_of(Value::from(Number::from(101u64)), 10, 100).is_err());
+        assert!(size_of(Value::String("ten".into()), 10, 100).is_err());
+    }
+}
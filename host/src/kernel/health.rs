@@ -0,0 +1,102 @@
+//! Health and readiness checks, so that an orchestrator can probe a host without issuing a real
+//! transaction.
+//!
+//! A GET request to `/health` checks that the data directory is writable and that the block cache
+//! is operational. A GET request to `/ready` also lists the hosted clusters, which are always
+//! fully loaded before the [`super::Kernel`] which hosts them is constructed. Either responds
+//! with an "unavailable" error if a check fails.
+
+use log::warn;
+
+use tc_error::*;
+use tcgeneric::{label, Id, Label, Map, TCPathBuf, Tuple};
+
+use crate::fs;
+use crate::scalar::{Link, Value};
+use crate::state::State;
+
+use super::hosted::Hosted;
+
+const CLUSTERS: Label = label("clusters");
+const DATA_DIR: Label = label("data_dir");
+const STATUS: Label = label("status");
+
+/// Check that the given `data_dir`, if any, is writable and that its cache is operational.
+pub async fn health(data_dir: Option<&fs::Dir>) -> TCResult<Map<State>> {
+    if let Some(data_dir) = data_dir {
+        if let Err(cause) = data_dir.check_health().await {
+            warn!("health check failed: {}", cause);
+            return Err(cause);
+        }
+    }
+
+    let status: Vec<(Id, State)> = vec![
+        (STATUS.into(), Value::String("ok".into()).into()),
+        (DATA_DIR.into(), Value::from(data_dir.is_some()).into()),
+    ];
+
+    Ok(status.into_iter().collect())
+}
+
+/// Check the [`health`] of this host, then list its hosted clusters.
+pub async fn ready(data_dir: Option<&fs::Dir>, hosted: &Hosted) -> TCResult<Map<State>> {
+    let mut status = health(data_dir).await?;
+
+    let clusters = hosted
+        .clusters()
+        .map(|cluster| Link::from(TCPathBuf::from(cluster.path().to_vec())))
+        .map(Value::from)
+        .collect::<Tuple<Value>>();
+
+    status.insert(Id::from(CLUSTERS), Value::from(clusters).into());
+    Ok(status)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::convert::TryFrom;
+
+    use tcgeneric::Map;
+
+    use crate::object::InstanceClass;
+    use crate::test::TestHost;
+
+    use super::*;
+
+    fn status(state: State) -> Map<State> {
+        match state {
+            State::Map(status) => status,
+            other => panic!("expected a status Map but found {}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_health() -> TCResult<()> {
+        let status = health(None).await?;
+        let data_dir: Id = DATA_DIR.into();
+        assert!(Value::try_from(status[&data_dir].clone())? == Value::from(false));
+
+        let cluster = InstanceClass::new(Some("/app/test".parse()?), Map::default());
+        let host = TestHost::new(vec![cluster]).await?;
+
+        let status = self::status(host.get("/health".parse()?, Value::None).await?);
+        let ok: Id = STATUS.into();
+        assert!(Value::try_from(status[&ok].clone())? == Value::String("ok".into()));
+        assert!(Value::try_from(status[&data_dir].clone())? == Value::from(true));
+
+        let status = self::status(host.get("/ready".parse()?, Value::None).await?);
+        let clusters: Id = CLUSTERS.into();
+        let expected = Tuple::from(vec![Value::from(Link::from(
+            "/app/test".parse::<TCPathBuf>()?,
+        ))]);
+        assert!(Value::try_from(status[&clusters].clone())? == Value::from(expected));
+
+        let result = host.get("/health".parse()?, Value::from(true)).await;
+        assert_eq!(
+            result.map(|_| ()).unwrap_err().code(),
+            ErrorType::BadRequest
+        );
+
+        Ok(())
+    }
+}
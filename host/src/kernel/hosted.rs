@@ -8,7 +8,7 @@ use tcgeneric::{label, Label, PathSegment, TCPath, TCPathBuf};
 use crate::cluster::Cluster;
 use crate::object::InstanceExt;
 
-const RESERVED: [Label; 57] = [
+const RESERVED: [Label; 59] = [
     label("actor"),
     label("actors"),
     label("admin"),
@@ -33,6 +33,7 @@ const RESERVED: [Label; 57] = [
     label("encryption"),
     label("encrypted"),
     label("error"),
+    label("health"),
     label("https"),
     label("host"),
     label("internal"),
@@ -45,6 +46,7 @@ const RESERVED: [Label; 57] = [
     label("ops"),
     label("operations"),
     label("operator"),
+    label("ready"),
     label("recycle"),
     label("recycling"),
     label("secure"),
@@ -88,6 +90,11 @@ impl Hosted {
         }
     }
 
+    /// Iterate over the hosted [`Cluster`]s.
+    pub fn clusters(&self) -> impl Iterator<Item = &InstanceExt<Cluster>> {
+        self.hosted.values()
+    }
+
     pub fn get<'a>(
         &self,
         path: &'a [PathSegment],
@@ -0,0 +1,186 @@
+//! Exclusive locks on a path for maintenance operations like compaction and restoration.
+
+use std::collections::HashMap;
+use std::sync::{Mutex, PoisonError};
+use std::time::Duration;
+
+use log::{debug, info};
+
+use tc_error::*;
+use tcgeneric::{PathSegment, TCPath, TCPathBuf};
+
+const DRAIN_INTERVAL: Duration = Duration::from_millis(100);
+
+#[derive(Default)]
+struct Paths {
+    locked: Vec<TCPathBuf>,
+    in_flight: HashMap<TCPathBuf, usize>,
+}
+
+impl Paths {
+    fn is_locked(&self, path: &[PathSegment]) -> bool {
+        self.locked.iter().any(|locked| overlaps(locked, path))
+    }
+
+    fn is_busy(&self, path: &[PathSegment]) -> bool {
+        self.in_flight
+            .keys()
+            .any(|in_flight| overlaps(in_flight, path))
+    }
+}
+
+/// Keeps track of in-flight requests and the paths currently locked for maintenance.
+///
+/// A request touches a path if either path is a prefix of the other, so that locking a
+/// [`crate::cluster::Cluster`] blocks requests to each of its members, and locking one member
+/// blocks requests to the whole `Cluster`.
+#[derive(Default)]
+pub struct Maintenance {
+    paths: Mutex<Paths>,
+}
+
+impl Maintenance {
+    /// Register a new in-flight request to `path`, or return an "unavailable" error if `path`
+    /// is locked for maintenance. The request is complete when the returned guard is dropped.
+    pub fn enter(&self, path: &[PathSegment]) -> TCResult<RequestGuard<'_>> {
+        let mut paths = self.paths.lock().unwrap_or_else(PoisonError::into_inner);
+        if paths.is_locked(path) {
+            return Err(TCError::unavailable(format!(
+                "{} is temporarily unavailable for maintenance",
+                TCPath::from(path)
+            )));
+        }
+
+        let path = TCPathBuf::from(path.to_vec());
+        *paths.in_flight.entry(path.clone()).or_insert(0) += 1;
+        Ok(RequestGuard {
+            maintenance: self,
+            path,
+        })
+    }
+
+    /// Lock `path` for maintenance, then wait for every in-flight request which touches it to
+    /// complete. New requests which touch `path` are rejected until the returned guard is dropped.
+    pub async fn lock(&self, path: TCPathBuf) -> TCResult<MaintenanceGuard<'_>> {
+        {
+            let mut paths = self.paths.lock().unwrap_or_else(PoisonError::into_inner);
+            if paths.is_locked(&path) {
+                return Err(TCError::conflict());
+            }
+
+            paths.locked.push(path.clone());
+        }
+
+        info!("locked {} for maintenance", path);
+
+        let guard = MaintenanceGuard {
+            maintenance: self,
+            path,
+        };
+
+        loop {
+            if self
+                .paths
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner)
+                .is_busy(&guard.path)
+            {
+                debug!("waiting for requests to {} to drain", guard.path);
+                tokio::time::sleep(DRAIN_INTERVAL).await;
+            } else {
+                break Ok(guard);
+            }
+        }
+    }
+}
+
+/// A guard which marks a request as in-flight until dropped.
+pub struct RequestGuard<'a> {
+    maintenance: &'a Maintenance,
+    path: TCPathBuf,
+}
+
+impl<'a> Drop for RequestGuard<'a> {
+    fn drop(&mut self) {
+        let mut paths = self
+            .maintenance
+            .paths
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        if let Some(count) = paths.in_flight.get_mut(&self.path) {
+            *count -= 1;
+            if *count == 0 {
+                paths.in_flight.remove(&self.path);
+            }
+        }
+    }
+}
+
+/// A guard which holds a path locked for maintenance until dropped.
+pub struct MaintenanceGuard<'a> {
+    maintenance: &'a Maintenance,
+    path: TCPathBuf,
+}
+
+impl<'a> Drop for MaintenanceGuard<'a> {
+    fn drop(&mut self) {
+        let mut paths = self
+            .maintenance
+            .paths
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        paths.locked.retain(|locked| locked != &self.path);
+        info!("released maintenance lock on {}", self.path);
+    }
+}
+
+fn overlaps(left: &[PathSegment], right: &[PathSegment]) -> bool {
+    left.starts_with(right) || right.starts_with(left)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn path(path: &str) -> TCPathBuf {
+        path.parse().expect("path")
+    }
+
+    #[tokio::test]
+    async fn test_lock() -> TCResult<()> {
+        let maintenance = Maintenance::default();
+        let cluster = path("/app/cluster");
+        let member = path("/app/cluster/member");
+        let other = path("/app/other");
+
+        let request = maintenance.enter(&member)?;
+
+        let lock = maintenance.lock(cluster.clone());
+        tokio::pin!(lock);
+
+        // the lock can't be acquired until the in-flight request completes
+        let pending = tokio::time::timeout(DRAIN_INTERVAL * 2, &mut lock).await;
+        assert!(pending.is_err());
+
+        assert!(maintenance.enter(&other).is_ok());
+        let err = maintenance.enter(&member).map(|_| ()).unwrap_err();
+        assert!(err.code() == ErrorType::Unavailable);
+
+        std::mem::drop(request);
+        let guard = lock.await?;
+
+        let err = maintenance
+            .lock(member.clone())
+            .await
+            .map(|_| ())
+            .unwrap_err();
+        assert!(err.code() == ErrorType::Conflict);
+        assert!(maintenance.enter(&cluster).is_err());
+
+        std::mem::drop(guard);
+        assert!(maintenance.enter(&member).is_ok());
+        assert!(maintenance.lock(member).await.is_ok());
+
+        Ok(())
+    }
+}
@@ -4,7 +4,7 @@ use std::convert::{TryFrom, TryInto};
 use std::pin::Pin;
 
 use bytes::Bytes;
-use futures::Future;
+use futures::{Future, TryFutureExt};
 use log::debug;
 use safecast::{TryCastFrom, TryCastInto};
 
@@ -13,17 +13,28 @@ use tc_transact::{Transact, Transaction};
 use tcgeneric::*;
 
 use crate::cluster::Cluster;
+use crate::collection::Collection;
+use crate::fs;
 use crate::object::InstanceExt;
 use crate::route::Public;
 use crate::scalar::*;
 use crate::state::*;
 use crate::txn::*;
 
+mod bench;
+mod health;
 mod hosted;
+mod maintenance;
 
 use hosted::Hosted;
+use maintenance::Maintenance;
 
+pub use maintenance::MaintenanceGuard;
+
+pub(crate) const BENCH: PathLabel = path_label(&["sbin", "bench"]);
+const HEALTH: PathLabel = path_label(&["health"]);
 const HYPOTHETICAL: PathLabel = path_label(&["transact", "hypothetical"]);
+const READY: PathLabel = path_label(&["ready"]);
 
 type ExeScope<'a> = crate::scalar::Scope<'a, State>;
 
@@ -31,6 +42,8 @@ type ExeScope<'a> = crate::scalar::Scope<'a, State>;
 pub struct Kernel {
     actor: Actor,
     hosted: Hosted,
+    maintenance: Maintenance,
+    data_dir: Option<fs::Dir>,
 }
 
 impl Kernel {
@@ -39,18 +52,60 @@ impl Kernel {
         Self {
             actor: Actor::new(Link::default().into()),
             hosted: clusters.into_iter().collect(),
+            maintenance: Maintenance::default(),
+            data_dir: None,
         }
     }
 
+    /// Check that the given `data_dir` is writable when this host's health is probed.
+    pub fn with_data_dir(mut self, data_dir: fs::Dir) -> Self {
+        self.data_dir = Some(data_dir);
+        self
+    }
+
+    /// Lock `path` for a maintenance operation like compaction or restoration.
+    ///
+    /// Waits for every in-flight request which touches `path` to complete, and rejects new
+    /// requests which touch `path` with an "unavailable" error until the returned guard is dropped.
+    /// The maintenance operation itself must access the locked path directly, not via this
+    /// `Kernel`.
+    pub async fn maintenance_lock(&self, path: TCPathBuf) -> TCResult<MaintenanceGuard<'_>> {
+        self.maintenance.lock(path).await
+    }
+
     /// Route a GET request.
     pub async fn get(&self, txn: &Txn, path: &[PathSegment], key: Value) -> TCResult<State> {
+        #[cfg(feature = "faults")]
+        if path == &crate::faults::PATH[..] {
+            return crate::faults::get(key);
+        }
+
         if path.is_empty() {
             if key.is_none() {
                 Ok(Value::from(Bytes::copy_from_slice(self.actor.public_key().as_bytes())).into())
             } else {
                 Err(TCError::method_not_allowed(TCPath::from(path)))
             }
+        } else if path == &HEALTH[..] || path == &READY[..] {
+            if key.is_some() {
+                return Err(TCError::bad_request("a health check has no key, not", key));
+            }
+
+            let status = if path == &HEALTH[..] {
+                health::health(self.data_dir.as_ref()).await?
+            } else {
+                health::ready(self.data_dir.as_ref(), &self.hosted).await?
+            };
+
+            Ok(State::Map(status))
         } else if let Some(class) = StateType::from_path(path) {
+            if let StateType::Collection(class) = class {
+                // the key is the schema of a new collection
+                return Collection::create(txn, class, key)
+                    .map_ok(State::Collection)
+                    .await;
+            }
+
             let err = format!("Cannot cast into {} from {}", class, key);
             State::Scalar(Scalar::Value(key))
                 .into_type(class)
@@ -63,7 +118,21 @@ impl Kernel {
                 cluster
             );
 
+            let _request = self.maintenance.enter(path)?;
             cluster.get(&txn, suffix, key).await
+        } else if path.len() == 3 && path[..2] == BENCH[..] {
+            bench::run(txn, &path[2], key).await
+        } else if path.len() == 3 && path[..2] == UPLOADS[..] {
+            if key.is_some() {
+                return Err(TCError::bad_request("an upload has no key, not", key));
+            }
+
+            let upload = txn
+                .uploaded(&path[2])
+                .ok_or_else(|| TCError::not_found(TCPath::from(path)))?;
+
+            let contents = upload.read(*txn.id()).await?;
+            Ok(Value::from(contents).into())
         } else if &path[0] == "error" && path.len() == 2 {
             let message = String::try_cast_from(key, |v| {
                 TCError::bad_request("cannot cast into error message string from", v)
@@ -87,6 +156,11 @@ impl Kernel {
         key: Value,
         value: State,
     ) -> TCResult<()> {
+        #[cfg(feature = "faults")]
+        if path == &crate::faults::PATH[..] {
+            return crate::faults::put(key, value);
+        }
+
         if path.is_empty() {
             if key.is_none() {
                 if Link::can_cast_from(&value) {
@@ -107,6 +181,7 @@ impl Kernel {
                 cluster
             );
 
+            let _request = self.maintenance.enter(path)?;
             execute(txn, cluster, |txn, cluster| async move {
                 cluster.put(&txn, suffix, key, value).await
             })
@@ -146,9 +221,11 @@ impl Kernel {
             );
 
             if suffix.is_empty() && params.is_empty() {
-                // it's a "commit" instruction
+                // it's a "commit" instruction, which is allowed even during maintenance
+                // so that a transaction which began before the path was locked can complete
                 cluster.post(&txn, suffix, params).await
             } else {
+                let _request = self.maintenance.enter(path)?;
                 execute(txn, cluster, |txn, cluster| async move {
                     cluster.post(&txn, suffix, params).await
                 })
@@ -190,6 +267,7 @@ fn execute<
 
             owner.commit(&txn).await?;
             cluster.commit(txn.id()).await;
+            txn.finish();
 
             Ok(state)
         }
@@ -206,8 +284,12 @@ fn error_type(err_type: &Id) -> Option<ErrorType> {
         "method_not_allowed" => Some(ErrorType::MethodNotAllowed),
         "not_found" => Some(ErrorType::NotFound),
         "not_implemented" => Some(ErrorType::NotImplemented),
+        "payload_too_large" => Some(ErrorType::PayloadTooLarge),
+        "policy_violation" => Some(ErrorType::PolicyViolation),
         "timeout" => Some(ErrorType::Timeout),
+        "too_many_requests" => Some(ErrorType::TooManyRequests),
         "unauthorized" => Some(ErrorType::Unauthorized),
+        "unavailable" => Some(ErrorType::Unavailable),
         _ => None,
     }
 }
@@ -9,16 +9,29 @@
 use std::path::PathBuf;
 
 mod fs;
+#[cfg(feature = "grpc")]
+mod grpc;
 mod http;
 mod route;
+mod ws;
 
 pub mod chain;
+#[cfg(feature = "client")]
+pub mod client;
 pub mod cluster;
+pub mod collection;
+pub mod concurrency;
+#[cfg(feature = "faults")]
+pub mod faults;
+#[cfg(feature = "fuzz")]
+pub mod fuzz;
 pub mod gateway;
 pub mod kernel;
 pub mod object;
+pub mod replay;
 pub mod scalar;
 pub mod state;
+pub mod test;
 pub mod txn;
 
 pub use kernel::*;
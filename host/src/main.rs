@@ -63,25 +63,317 @@ struct Config {
 
     #[structopt(long = "http_port", default_value = "8702")]
     pub http_port: u16,
+
+    #[structopt(long = "ws_port")]
+    pub ws_port: Option<u16>,
+
+    #[structopt(long = "tls_cert")]
+    pub tls_cert: Option<PathBuf>,
+
+    #[structopt(long = "tls_key")]
+    pub tls_key: Option<PathBuf>,
+
+    #[structopt(long = "grpc_port")]
+    pub grpc_port: Option<u16>,
+
+    #[structopt(long = "grpc_peers")]
+    pub grpc_peers: bool,
+
+    #[structopt(
+        long = "compression_threshold",
+        default_value = "1K",
+        parse(try_from_str = data_size)
+    )]
+    pub compression_threshold: usize,
+
+    #[structopt(long = "cors_origin")]
+    pub cors_origins: Vec<String>,
+
+    #[structopt(long = "cors_method")]
+    pub cors_methods: Vec<String>,
+
+    #[structopt(long = "cors_header")]
+    pub cors_headers: Vec<String>,
+
+    #[structopt(long = "cors_max_age", default_value = "3600", parse(try_from_str = duration))]
+    pub cors_max_age: Duration,
+
+    #[structopt(long = "request_limit", default_value = "64M", parse(try_from_str = data_size))]
+    pub request_limit: usize,
+
+    #[structopt(
+        long = "upload_max_part_size",
+        default_value = "64M",
+        parse(try_from_str = data_size)
+    )]
+    pub upload_max_part_size: usize,
+
+    #[structopt(
+        long = "upload_max_size",
+        default_value = "256M",
+        parse(try_from_str = data_size)
+    )]
+    pub upload_max_size: usize,
+
+    #[structopt(long = "rate_limit_reads", default_value = "0")]
+    pub rate_limit_reads: f64,
+
+    #[structopt(long = "rate_limit_read_burst", default_value = "100")]
+    pub rate_limit_read_burst: u32,
+
+    #[structopt(long = "rate_limit_writes", default_value = "0")]
+    pub rate_limit_writes: f64,
+
+    #[structopt(long = "rate_limit_write_burst", default_value = "20")]
+    pub rate_limit_write_burst: u32,
+
+    #[structopt(long = "rate_limit_token_reads", default_value = "0")]
+    pub rate_limit_token_reads: f64,
+
+    #[structopt(long = "rate_limit_token_read_burst", default_value = "100")]
+    pub rate_limit_token_read_burst: u32,
+
+    #[structopt(long = "rate_limit_token_writes", default_value = "0")]
+    pub rate_limit_token_writes: f64,
+
+    #[structopt(long = "rate_limit_token_write_burst", default_value = "20")]
+    pub rate_limit_token_write_burst: u32,
+
+    #[structopt(long = "http2_max_streams", default_value = "256")]
+    pub http2_max_streams: u32,
+
+    #[structopt(long = "single_use_tokens")]
+    pub single_use_tokens: bool,
+
+    #[structopt(long = "oidc_issuer")]
+    pub oidc_issuer: Option<String>,
+
+    #[structopt(long = "oidc_audience")]
+    pub oidc_audience: Option<String>,
+
+    #[structopt(long = "oidc_mapping")]
+    pub oidc_mapping: Option<PathBuf>,
+
+    #[structopt(long = "secrets")]
+    pub secrets: Option<PathBuf>,
+
+    #[structopt(long = "host_key")]
+    pub host_key: Option<PathBuf>,
+
+    #[structopt(long = "trusted_key")]
+    pub trusted_keys: Vec<gateway::TrustedKey>,
+
+    #[structopt(long = "egress_allow")]
+    pub egress: Vec<gateway::EgressRule>,
+
+    #[structopt(long = "id_trim")]
+    pub id_trim: bool,
+
+    #[structopt(long = "id_nfc")]
+    pub id_nfc: bool,
+
+    #[structopt(long = "id_case_fold")]
+    pub id_case_fold: bool,
+
+    #[structopt(long = "max_decode_depth", default_value = "64")]
+    pub max_decode_depth: usize,
+
+    #[structopt(long = "max_decode_elements", default_value = "1000000")]
+    pub max_decode_elements: usize,
+
+    #[structopt(long = "max_call_depth", default_value = "64")]
+    pub max_call_depth: usize,
+
+    #[structopt(long = "max_resolutions", default_value = "1000000")]
+    pub max_resolutions: usize,
+
+    #[structopt(long = "cluster_limits")]
+    pub cluster_limits: Vec<txn::ClusterLimits>,
+
+    #[structopt(long = "max_concurrent_resolve", default_value = "32")]
+    pub max_concurrent_resolve: usize,
+
+    #[structopt(long = "max_concurrent_collections", default_value = "8")]
+    pub max_concurrent_collections: usize,
+
+    #[structopt(long = "max_sort_workers", default_value = "4")]
+    pub max_sort_workers: usize,
+
+    #[structopt(long = "sort_budget", default_value = "64M", parse(try_from_str = data_size))]
+    pub sort_budget: usize,
+
+    #[structopt(long = "precise_integers")]
+    pub precise_integers: bool,
+
+    #[structopt(long = "record")]
+    pub record: Option<PathBuf>,
+
+    #[structopt(long = "replay")]
+    pub replay: Option<PathBuf>,
+
+    #[structopt(long = "replay_target", default_value = "127.0.0.1:8702")]
+    pub replay_target: String,
+
+    #[structopt(long = "replay_speed", default_value = "1")]
+    pub replay_speed: f64,
 }
 
 impl Config {
-    fn gateway(&self) -> gateway::Config {
-        gateway::Config {
+    fn gateway(&self) -> TCResult<gateway::Config> {
+        Ok(gateway::Config {
             addr: self.address,
             http_port: self.http_port,
+            ws_port: self.ws_port,
             request_ttl: self.request_ttl,
+            record: self.record.clone(),
+            tls_cert: self.tls_cert.clone(),
+            tls_key: self.tls_key.clone(),
+            grpc_port: self.grpc_port,
+            grpc_peers: self.grpc_peers,
+            compression_threshold: self.compression_threshold,
+            http2_max_streams: self.http2_max_streams,
+            cors: self.cors(),
+            request_limit: self.request_limit as u64,
+            upload_limits: gateway::UploadLimits {
+                max_part_size: self.upload_max_part_size as u64,
+                max_size: self.upload_max_size as u64,
+            },
+            rate_limit: gateway::RateLimit {
+                reads_per_second: self.rate_limit_reads,
+                read_burst: self.rate_limit_read_burst,
+                writes_per_second: self.rate_limit_writes,
+                write_burst: self.rate_limit_write_burst,
+                token_reads_per_second: self.rate_limit_token_reads,
+                token_read_burst: self.rate_limit_token_read_burst,
+                token_writes_per_second: self.rate_limit_token_writes,
+                token_write_burst: self.rate_limit_token_write_burst,
+            },
+            single_use_tokens: self.single_use_tokens,
+            trusted_keys: self.trusted_keys.clone(),
+            oidc: self.oidc()?,
+            secrets: None,
+            egress: self.egress.clone(),
+            exec_limits: self.cluster_limits.clone(),
+        })
+    }
+
+    fn exec_limits(&self) -> txn::ExecLimits {
+        txn::ExecLimits {
+            max_depth: self.max_call_depth,
+            max_resolutions: self.max_resolutions,
+        }
+    }
+
+    // the stack size of a worker thread, which must fit an op call at the maximum depth allowed
+    // by this host or by any of its clusters
+    fn stack_size(&self) -> usize {
+        self.cluster_limits
+            .iter()
+            .map(|cluster| cluster.limits().stack_size())
+            .fold(self.exec_limits().stack_size(), usize::max)
+    }
+
+    fn cors(&self) -> gateway::Cors {
+        let mut cors =
+            gateway::Cors::allow(self.cors_origins.clone()).with_max_age(self.cors_max_age);
+
+        if !self.cors_methods.is_empty() {
+            cors = cors.with_methods(self.cors_methods.clone());
+        }
+
+        if !self.cors_headers.is_empty() {
+            cors = cors.with_headers(self.cors_headers.clone());
         }
+
+        cors
+    }
+
+    fn oidc(&self) -> TCResult<Option<gateway::Oidc>> {
+        let issuer = match &self.oidc_issuer {
+            Some(issuer) => issuer.clone(),
+            None => return Ok(None),
+        };
+
+        let audience = self.oidc_audience.clone().ok_or_else(|| {
+            TCError::bad_request(
+                "the --oidc_audience option is required to trust an OIDC issuer",
+                &issuer,
+            )
+        })?;
+
+        Ok(Some(gateway::Oidc::new(issuer, audience)))
+    }
+
+    fn host_key(&self) -> TCResult<PathBuf> {
+        self.host_key.clone().ok_or_else(|| {
+            TCError::bad_request(
+                "the --host_key option is required to store secrets in",
+                format!("{:?}", self.secrets),
+            )
+        })
     }
 }
 
-#[tokio::main]
-async fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
     let config = Config::from_args();
-    let gateway_config = config.gateway();
 
-    env_logger::Builder::from_env(env_logger::Env::default().default_filter_or(config.log_level))
-        .init();
+    tokio::runtime::Builder::new_multi_thread()
+        .enable_all()
+        .thread_stack_size(config.stack_size())
+        .build()?
+        .block_on(run(config))
+}
+
+async fn run(config: Config) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let mut gateway_config = config.gateway()?;
+
+    env_logger::Builder::from_env(
+        env_logger::Env::default().default_filter_or(config.log_level.as_str()),
+    )
+    .init();
+
+    scalar::DecodeLimits {
+        max_depth: config.max_decode_depth,
+        max_elements: config.max_decode_elements,
+    }
+    .configure();
+
+    config.exec_limits().configure();
+
+    tcgeneric::IdNormalization {
+        trim: config.id_trim,
+        nfc: config.id_nfc,
+        case_fold: config.id_case_fold,
+    }
+    .configure();
+
+    concurrency::Concurrency {
+        resolve: config.max_concurrent_resolve,
+        collection: config.max_concurrent_collections,
+        sort: config.max_sort_workers,
+    }
+    .configure();
+
+    collection::set_sort_budget(config.sort_budget);
+    value::set_precise_integers(config.precise_integers);
+
+    if let Some(path) = config.replay.clone() {
+        let summary = replay::replay(path, config.replay_target, config.replay_speed).await?;
+        println!("{}", summary);
+        return Ok(());
+    }
+
+    if let Some(mapping) = config.oidc_mapping.clone() {
+        if let Some(oidc) = gateway_config.oidc.take() {
+            gateway_config.oidc = Some(oidc.with_mapping(mapping).await?);
+        }
+    }
+
+    if let Some(secrets) = config.secrets.clone() {
+        let host_key = config.host_key()?;
+
+        gateway_config.secrets = Some(gateway::Secrets::load(secrets, host_key).await?);
+    }
 
     let (workspace, data_dir) =
         mount(config.workspace.clone(), config.data_dir, config.cache_size).await?;
@@ -92,7 +384,7 @@ async fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
     if !config.clusters.is_empty() {
         let txn_id = TxnId::new(Gateway::time());
 
-        let data_dir = data_dir.ok_or_else(|| {
+        let data_dir = data_dir.as_ref().ok_or_else(|| {
             TCError::internal("the --data_dir option is required to host a Cluster")
         })?;
 
@@ -115,7 +407,11 @@ async fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
         data_dir.commit(&txn_id).await;
     }
 
-    let kernel = tinychain::Kernel::new(clusters);
+    let mut kernel = tinychain::Kernel::new(clusters);
+    if let Some(data_dir) = data_dir {
+        kernel = kernel.with_data_dir(data_dir);
+    }
+
     let gateway = tinychain::gateway::Gateway::new(gateway_config, kernel, txn_server);
 
     if let Err(cause) = gateway.listen().await {
@@ -134,3 +430,32 @@ async fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(args: &[&str]) -> Config {
+        Config::from_iter(std::iter::once("tinychain").chain(args.iter().copied()))
+    }
+
+    #[test]
+    fn test_oidc_requires_audience() {
+        let issuer = ["--oidc_issuer", "https://auth.example.com"];
+        assert!(config(&issuer).gateway().is_err());
+
+        let audience = ["--oidc_audience", "tinychain"];
+        assert!(config(&[&issuer[..], &audience[..]].concat())
+            .gateway()
+            .is_ok());
+        assert!(config(&[]).gateway().is_ok());
+    }
+
+    #[test]
+    fn test_secrets_require_host_key() {
+        assert!(config(&["--secrets", "/tmp/secrets"]).host_key().is_err());
+
+        let host_key = config(&["--secrets", "/tmp/secrets", "--host_key", "/tmp/key"]).host_key();
+        assert_eq!(host_key.ok(), Some(PathBuf::from("/tmp/key")));
+    }
+}
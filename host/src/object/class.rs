@@ -6,26 +6,42 @@ use std::fmt;
 use async_trait::async_trait;
 use destream::{de, en};
 
+use tc_error::*;
 use tcgeneric::{path_label, Id, Map, PathLabel, TCPathBuf};
 
 use crate::scalar::*;
 use crate::state::State;
 
+use super::interface::{self, IMPLEMENTS};
 use super::{InstanceExt, ObjectType};
 
 const PATH: PathLabel = path_label(&["state", "class"]);
 
 /// A user-defined class.
+///
+/// A class may extend another user-defined class, in which case members not defined in its own
+/// prototype are looked up in its superclass, and so on up the inheritance chain.
 #[derive(Clone, Default, Eq, PartialEq)]
 pub struct InstanceClass {
     extends: Option<Link>,
     proto: Map<Scalar>,
+    superclass: Option<Box<InstanceClass>>,
 }
 
 impl InstanceClass {
     /// Construct a new subclass of the class at `extends` with the given instance data.
     pub fn new(extends: Option<Link>, proto: Map<Scalar>) -> Self {
-        Self { extends, proto }
+        Self {
+            extends,
+            proto,
+            superclass: None,
+        }
+    }
+
+    /// Set the user-defined superclass of this class, i.e. the class at the path it `extends`.
+    pub fn with_superclass(mut self, superclass: InstanceClass) -> Self {
+        self.superclass = Some(Box::new(superclass));
+        self
     }
 
     /// Return the parent class of this class.
@@ -46,6 +62,49 @@ impl InstanceClass {
     pub fn proto(&'_ self) -> &'_ Map<Scalar> {
         &self.proto
     }
+
+    /// Return the user-defined superclass of this class, if any.
+    pub fn superclass(&self) -> Option<&InstanceClass> {
+        self.superclass.as_deref()
+    }
+
+    /// Look up the member with the given `name`, walking the inheritance chain from this class
+    /// upward, so that a member defined in a subclass overrides one defined in its superclass.
+    ///
+    /// Returns the class which defines the member along with the member itself.
+    pub fn get_member(&self, name: &Id) -> Option<(&InstanceClass, &Scalar)> {
+        let mut class = self;
+        loop {
+            if let Some(member) = class.proto.get(name) {
+                return Some((class, member));
+            }
+
+            class = class.superclass()?;
+        }
+    }
+
+    /// Return the links to the interfaces which this class, or one of its superclasses, claims
+    /// to implement.
+    pub fn interfaces(&self) -> TCResult<Vec<Link>> {
+        let mut interfaces = Vec::new();
+
+        let mut class = Some(self);
+        while let Some(this) = class {
+            if let Some(member) = this.proto.get(&Id::from(IMPLEMENTS)) {
+                interfaces.extend(interface::interfaces(member)?);
+            }
+
+            class = this.superclass();
+        }
+
+        Ok(interfaces)
+    }
+
+    /// Return `true` if this class claims to implement the interface at the given [`Link`].
+    pub fn implements(&self, interface: &Link) -> TCResult<bool> {
+        self.interfaces()
+            .map(|interfaces| interfaces.contains(interface))
+    }
 }
 
 impl tcgeneric::Class for InstanceClass {
@@ -119,10 +178,7 @@ impl de::Visitor for InstanceClassVisitor {
                 log::debug!("Class extends {}", extends);
                 let proto = access.next_value(()).await?;
                 log::debug!("prototype is {}", proto);
-                return Ok(InstanceClass {
-                    extends: Some(extends),
-                    proto,
-                });
+                return Ok(InstanceClass::new(Some(extends), proto));
             }
 
             let mut proto = if let Some(len) = access.size_hint() {
@@ -131,7 +187,7 @@ impl de::Visitor for InstanceClassVisitor {
                 HashMap::new()
             };
 
-            let id: Id = key.parse().map_err(de::Error::custom)?;
+            let id = Id::decode(&key).map_err(de::Error::custom)?;
             proto.insert(id, access.next_value(()).await?);
 
             while let Some(id) = access.next_key(()).await? {
@@ -139,15 +195,9 @@ impl de::Visitor for InstanceClassVisitor {
                 proto.insert(id, value);
             }
 
-            Ok(InstanceClass {
-                extends: None,
-                proto: proto.into(),
-            })
+            Ok(InstanceClass::new(None, proto.into()))
         } else {
-            Ok(InstanceClass {
-                extends: None,
-                proto: Map::default(),
-            })
+            Ok(InstanceClass::default())
         }
     }
 }
@@ -7,7 +7,7 @@ use std::ops::Deref;
 use destream::{en, EncodeMap};
 
 use tc_transact::IntoView;
-use tcgeneric::Map;
+use tcgeneric::{Id, Map};
 
 use crate::fs::Dir;
 use crate::scalar::Scalar;
@@ -42,6 +42,11 @@ impl<T: tcgeneric::Instance> InstanceExt<T> {
         self.class.proto()
     }
 
+    /// Look up a member of this instance's class, or of one of its superclasses.
+    pub fn get_member(&self, name: &Id) -> Option<(&InstanceClass, &Scalar)> {
+        self.class.get_member(name)
+    }
+
     /// Convert the native type of this instance, if possible.
     pub fn try_into<E, O: tcgeneric::Instance + TryFrom<T, Error = E>>(
         self,
@@ -0,0 +1,174 @@
+//! User-defined interface implementation.
+
+use std::convert::TryFrom;
+use std::fmt;
+
+use tc_error::*;
+use tcgeneric::{label, path_label, Instance, Label, Map, NativeClass, PathLabel, PathSegment};
+
+use crate::scalar::{Link, OpDefType, Scalar, Value};
+
+use super::InstanceClass;
+
+const PATH: PathLabel = path_label(&["state", "object", "interface"]);
+
+/// The name of the class member which lists the interfaces a class claims to implement.
+pub const IMPLEMENTS: Label = label("implements");
+
+/// A user-defined interface, i.e. a set of methods which an implementing class must define.
+///
+/// An interface is declared as a map of method names to op types, like
+/// `{"/state/object/interface": {"area": {"/state/scalar/op/get": []}}}`, and a class claims to
+/// implement it by listing a link to it in its `implements` member.
+#[derive(Clone, Default)]
+pub struct Interface {
+    methods: Map<OpDefType>,
+}
+
+impl Interface {
+    /// Return `true` if the given path is the path of the interface type.
+    pub fn is_interface(path: &[PathSegment]) -> bool {
+        path == &PATH[..]
+    }
+
+    /// Check that the given class, including its superclasses, defines every method of this
+    /// interface with the expected op type.
+    pub fn validate(&self, class: &InstanceClass) -> TCResult<()> {
+        for (name, op_type) in self.methods.iter() {
+            match class.get_member(name) {
+                Some((_, Scalar::Op(op_def))) if &op_def.class() == op_type => {}
+                Some((_, other)) => {
+                    return Err(TCError::bad_request(
+                        format!("{} requires {} to be a {}, not", class, name, op_type),
+                        other,
+                    ))
+                }
+                None => {
+                    return Err(TCError::bad_request(
+                        format!("{} is missing a method required by its interface", class),
+                        name,
+                    ))
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl TryFrom<Map<Scalar>> for Interface {
+    type Error = TCError;
+
+    fn try_from(proto: Map<Scalar>) -> TCResult<Self> {
+        let methods = proto
+            .into_inner()
+            .into_iter()
+            .map(|(name, op_type)| {
+                let path = match op_type {
+                    Scalar::Value(Value::Link(link)) if link.host().is_none() => link.into_path(),
+                    other => {
+                        return Err(TCError::bad_request("expected an op type but found", other))
+                    }
+                };
+
+                OpDefType::from_path(&path)
+                    .map(|op_type| (name, op_type))
+                    .ok_or_else(|| TCError::bad_request("expected an op type but found", path))
+            })
+            .collect::<TCResult<Map<OpDefType>>>()?;
+
+        Ok(Self { methods })
+    }
+}
+
+impl fmt::Display for Interface {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let methods = self
+            .methods
+            .iter()
+            .map(|(name, op_type)| format!("{}: {}", name, op_type))
+            .collect::<Vec<String>>();
+
+        write!(f, "interface {{{}}}", methods.join(", "))
+    }
+}
+
+/// Parse the `implements` member of a class prototype into a list of interface [`Link`]s.
+pub(super) fn interfaces(member: &Scalar) -> TCResult<Vec<Link>> {
+    match member {
+        Scalar::Value(Value::Link(link)) => Ok(vec![link.clone()]),
+        Scalar::Value(Value::Tuple(links)) => links
+            .iter()
+            .map(|link| match link {
+                Value::Link(link) => Ok(link.clone()),
+                other => Err(TCError::bad_request(
+                    "expected a link to an interface, not",
+                    other,
+                )),
+            })
+            .collect(),
+        Scalar::Tuple(links) => links
+            .iter()
+            .map(|link| match link {
+                Scalar::Value(Value::Link(link)) => Ok(link.clone()),
+                other => Err(TCError::bad_request(
+                    "expected a link to an interface, not",
+                    other,
+                )),
+            })
+            .collect(),
+        other => Err(TCError::bad_request(
+            "expected a list of links to interfaces, not",
+            other,
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::scalar::{Number, OpDef};
+
+    use super::*;
+
+    fn op_type(path: &str) -> Scalar {
+        Scalar::Value(Value::Link(path.parse().expect("op type")))
+    }
+
+    #[test]
+    fn test_validate() -> TCResult<()> {
+        let mut methods = Map::default();
+        methods.insert(label("area").into(), op_type("/state/scalar/op/get"));
+        let interface = Interface::try_from(methods)?;
+
+        let shape: Link = "/app/shape".parse()?;
+
+        let mut proto = Map::default();
+        proto.insert(IMPLEMENTS.into(), Scalar::Value(Value::Link(shape.clone())));
+        proto.insert(label("area").into(), OpDef::get().build()?.into());
+        let class = InstanceClass::new(None, proto);
+
+        interface.validate(&class)?;
+        assert!(class.implements(&shape)?);
+
+        // a subclass inherits both the method and the claim to implement the interface
+        let subclass = InstanceClass::new(None, Map::default()).with_superclass(class);
+        interface.validate(&subclass)?;
+        assert!(subclass.implements(&shape)?);
+
+        let mut proto = Map::default();
+        proto.insert(label("area").into(), OpDef::post().build()?.into());
+        assert!(interface
+            .validate(&InstanceClass::new(None, proto))
+            .is_err());
+        assert!(interface.validate(&InstanceClass::default()).is_err());
+
+        let mut methods = Map::default();
+        methods.insert(
+            label("area").into(),
+            Scalar::Value(Value::from(Number::from(1u64))),
+        );
+        assert!(Interface::try_from(methods).is_err());
+
+        Ok(())
+    }
+}
@@ -15,9 +15,11 @@ use crate::txn::Txn;
 
 mod class;
 mod instance;
+mod interface;
 
 pub use class::*;
 pub use instance::*;
+pub use interface::*;
 
 const ERR_DECODE_INSTANCE: &str = "Instance does not support direct decoding; use an OpRef instead";
 const PREFIX: PathLabel = path_label(&["state", "object"]);
@@ -0,0 +1,481 @@
+//! Record the requests received by a host, and replay them against another host.
+//!
+//! Each recorded request is written to the record file as one line of JSON, with the method,
+//! path and query, `Content-Type`, body, and the time it was received in milliseconds since the
+//! host started, e.g.
+//! `{"method":"PUT","offset":1520,"path":"/app/users","content_type":"application/json","body":"WzFd"}`.
+//!
+//! A body of at most 64 KiB is recorded inline, encoded as base64. A larger body, or one with no
+//! `Content-Length`, is not buffered: it's written to its own file in the directory
+//! `<record file>.bodies` as the host reads it, and the line records the name of that file as
+//! `body_file` instead.
+//!
+//! The `txn_id` query parameter and the `Authorization` header are not recorded, since a
+//! transaction can't be resumed and a token can't be reused after it expires, so each request
+//! is replayed in its own anonymous transaction.
+//!
+//! A request to `/sbin/secrets` or `/sbin/keys` is never recorded, since it may carry a secret or
+//! a key. The record file and the recorded bodies are only readable by the user who runs the host.
+
+use std::fmt;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, PoisonError};
+use std::time::{Duration, Instant};
+
+use futures::future::try_join_all;
+use futures::stream::{self, StreamExt};
+use hyper::header::{HeaderValue, CONTENT_LENGTH, CONTENT_TYPE};
+use hyper::{Body, Method, Request};
+use log::{debug, warn};
+use tokio::io::AsyncWriteExt;
+
+use tc_error::*;
+use tcgeneric::{PathLabel, TCPathBuf};
+
+use crate::gateway::{KEYS, SECRETS};
+
+const TXN_ID: &str = "txn_id";
+
+// the largest request body to record inline in the record file
+const INLINE_LIMIT: u64 = 65_536;
+
+// the paths of requests which may carry a secret or a key
+const SENSITIVE: &[PathLabel] = &[KEYS, SECRETS];
+
+/// Appends each request received by the HTTP server to a record file.
+pub(crate) struct Recorder {
+    file: Mutex<File>,
+    bodies: PathBuf,
+    started: Instant,
+}
+
+impl Recorder {
+    /// Open the record file at `path`, appending to it if it already exists.
+    pub fn open(path: &PathBuf) -> TCResult<Self> {
+        let mut options = OpenOptions::new();
+        options.create(true).append(true);
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::OpenOptionsExt;
+            options.mode(0o600);
+        }
+
+        let file = options
+            .open(path)
+            .map_err(|e| TCError::internal(format!("unable to open {:?}: {}", path, e)))?;
+
+        Ok(Self {
+            file: Mutex::new(file),
+            bodies: bodies_dir(path),
+            started: Instant::now(),
+        })
+    }
+
+    /// Record the given `request`, and return an equivalent request.
+    ///
+    /// A small body is buffered and recorded inline, and a large one is written to its own file
+    /// as it's read from the returned request. A request which may carry a secret or a key is
+    /// returned as-is, without being recorded.
+    pub async fn record(&self, request: Request<Body>) -> TCResult<Request<Body>> {
+        if is_sensitive(request.uri().path()) {
+            debug!(
+                "not recording {} {}",
+                request.method(),
+                request.uri().path()
+            );
+            return Ok(request);
+        }
+
+        let offset = self.started.elapsed().as_millis() as u64;
+        let (parts, body) = request.into_parts();
+
+        let mut path = parts.uri.path().to_string();
+        if let Some(query) = parts.uri.query() {
+            let query =
+                url::form_urlencoded::parse(query.as_bytes()).filter(|(name, _)| *name != TXN_ID);
+
+            let query = url::form_urlencoded::Serializer::new(String::new())
+                .extend_pairs(query)
+                .finish();
+
+            if !query.is_empty() {
+                path.push('?');
+                path.push_str(&query);
+            }
+        }
+
+        let mut entry = serde_json::json!({
+            "method": parts.method.as_str(),
+            "offset": offset,
+            "path": path,
+        });
+
+        if let Some(content_type) = parts.headers.get(CONTENT_TYPE) {
+            let content_type = content_type
+                .to_str()
+                .map_err(|e| TCError::bad_request("invalid Content-Type header", e))?;
+
+            entry["content_type"] = content_type.into();
+        }
+
+        let content_length = parts
+            .headers
+            .get(CONTENT_LENGTH)
+            .and_then(|length| length.to_str().ok())
+            .and_then(|length| length.parse::<u64>().ok());
+
+        let body = match content_length {
+            Some(length) if length <= INLINE_LIMIT => {
+                let body = hyper::body::to_bytes(body)
+                    .await
+                    .map_err(|e| TCError::bad_request("unable to read request body", e))?;
+
+                entry["body"] = base64::encode(&body).into();
+                Body::from(body)
+            }
+            _ => {
+                let name = uuid::Uuid::new_v4().to_string();
+                let body = self.tee(body, &name).await?;
+                entry["body_file"] = name.into();
+                body
+            }
+        };
+
+        {
+            let mut file = self.file.lock().unwrap_or_else(PoisonError::into_inner);
+            writeln!(file, "{}", entry)
+                .map_err(|e| TCError::internal(format!("unable to record request: {}", e)))?;
+        }
+
+        Ok(Request::from_parts(parts, body))
+    }
+
+    // return a body which writes each chunk of `body` to the file `name` as it's read
+    async fn tee(&self, body: Body, name: &str) -> TCResult<Body> {
+        let path = self.bodies.join(name);
+
+        let mut dir = tokio::fs::DirBuilder::new();
+        dir.recursive(true);
+
+        let mut options = tokio::fs::OpenOptions::new();
+        options.write(true).create_new(true);
+
+        #[cfg(unix)]
+        {
+            dir.mode(0o700);
+            options.mode(0o600);
+        }
+
+        dir.create(&self.bodies)
+            .await
+            .map_err(|e| TCError::internal(format!("unable to record request body: {}", e)))?;
+
+        let file = options
+            .open(&path)
+            .await
+            .map_err(|e| TCError::internal(format!("unable to record request body: {}", e)))?;
+
+        let chunks = stream::try_unfold((body, file), |(mut body, mut file)| async move {
+            match body.next().await {
+                Some(Ok(chunk)) => {
+                    file.write_all(&chunk).await?;
+                    Ok(Some((chunk, (body, file))))
+                }
+                Some(Err(cause)) => Err(io::Error::other(cause)),
+                None => file.flush().await.map(|()| None),
+            }
+        });
+
+        Ok(Body::wrap_stream(chunks))
+    }
+}
+
+// true if a request to the given URI path may carry a secret or a key, or if the path is invalid,
+// since then there's no telling where the request was meant to go
+fn is_sensitive(path: &str) -> bool {
+    let path: TCPathBuf = match path.parse() {
+        Ok(path) => path,
+        Err(_) => return true,
+    };
+
+    SENSITIVE.iter().any(|prefix| {
+        let prefix = &prefix[..];
+        path.len() >= prefix.len() && path[..prefix.len()] == *prefix
+    })
+}
+
+// the directory which holds the large request bodies recorded in the record file at `path`
+fn bodies_dir(path: &Path) -> PathBuf {
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push(".bodies");
+    path.with_file_name(name)
+}
+
+/// A summary of the results of [`replay`].
+pub struct Summary {
+    /// The number of requests replayed.
+    pub requests: usize,
+
+    /// The number of requests which returned an error.
+    pub errors: usize,
+
+    /// The total time taken to replay every request.
+    pub elapsed: Duration,
+
+    /// The mean latency of a single request.
+    pub mean_latency: Duration,
+}
+
+impl fmt::Display for Summary {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "replayed {} requests ({} errors) in {:?}, with a mean latency of {:?}",
+            self.requests, self.errors, self.elapsed, self.mean_latency
+        )
+    }
+}
+
+/// Replay the requests in the record file at `path` against the host at `target`
+/// (e.g. "127.0.0.1:8702"), at `speed` times the recorded rate.
+///
+/// A `speed` of zero sends each request as soon as the last one was sent, without waiting.
+pub async fn replay(path: PathBuf, target: String, speed: f64) -> TCResult<Summary> {
+    if !(speed >= 0. && speed.is_finite()) {
+        return Err(TCError::bad_request("invalid replay speed", speed));
+    }
+
+    let log = tokio::fs::read_to_string(&path)
+        .await
+        .map_err(|e| TCError::internal(format!("unable to read {:?}: {}", path, e)))?;
+
+    let bodies = bodies_dir(&path);
+    let mut entries = Vec::new();
+    for (i, line) in log.lines().enumerate() {
+        if !line.trim().is_empty() {
+            let entry = parse_entry(line, &target, &bodies)
+                .map_err(|e| e.consume(format!("line {} of {:?}", i + 1, path)))?;
+
+            entries.push(entry);
+        }
+    }
+
+    let client = hyper::Client::new();
+    let started = Instant::now();
+    let first = entries.first().map(|(offset, _)| *offset).unwrap_or(0);
+
+    let mut pending = Vec::with_capacity(entries.len());
+    for (offset, request) in entries {
+        if speed > 0. {
+            let due = Duration::from_millis(offset.saturating_sub(first)).div_f64(speed);
+            if let Some(wait) = due.checked_sub(started.elapsed()) {
+                tokio::time::sleep(wait).await;
+            }
+        }
+
+        debug!("replay {} {}", request.method(), request.uri());
+        let client = client.clone();
+        pending.push(tokio::spawn(async move {
+            let sent = Instant::now();
+            let success = match client.request(request).await {
+                Ok(response) => response.status().is_success(),
+                Err(cause) => {
+                    warn!("replayed request failed: {}", cause);
+                    false
+                }
+            };
+
+            (success, sent.elapsed())
+        }));
+    }
+
+    let results = try_join_all(pending)
+        .await
+        .map_err(|e| TCError::internal(format!("replay task failed: {}", e)))?;
+
+    let requests = results.len();
+    let errors = results.iter().filter(|(success, _)| !success).count();
+    let latency: Duration = results.iter().map(|(_, latency)| *latency).sum();
+
+    Ok(Summary {
+        requests,
+        errors,
+        elapsed: started.elapsed(),
+        mean_latency: latency / requests.max(1) as u32,
+    })
+}
+
+fn parse_entry(line: &str, target: &str, bodies: &Path) -> TCResult<(u64, Request<Body>)> {
+    let invalid = |field| TCError::bad_request("recorded request has an invalid", field);
+
+    let entry: serde_json::Value = serde_json::from_str(line)
+        .map_err(|e| TCError::bad_request("invalid recorded request", e))?;
+
+    let method = entry["method"].as_str().ok_or_else(|| invalid("method"))?;
+    let method: Method = method.parse().map_err(|_| invalid("method"))?;
+    let offset = entry["offset"].as_u64().ok_or_else(|| invalid("offset"))?;
+    let path = entry["path"].as_str().ok_or_else(|| invalid("path"))?;
+
+    let body = if let Some(name) = entry["body_file"].as_str() {
+        if name.contains(std::path::is_separator) || name.starts_with('.') {
+            return Err(invalid("body_file"));
+        }
+
+        let path = bodies.join(name);
+        std::fs::read(&path)
+            .map_err(|e| TCError::internal(format!("unable to read {:?}: {}", path, e)))?
+    } else {
+        let body = entry["body"].as_str().ok_or_else(|| invalid("body"))?;
+        base64::decode(body).map_err(|_| invalid("body"))?
+    };
+
+    let mut request = Request::builder()
+        .method(method)
+        .uri(format!("http://{}{}", target, path));
+
+    if let Some(content_type) = entry.get("content_type") {
+        let content_type = content_type
+            .as_str()
+            .ok_or_else(|| invalid("content_type"))?;
+        let content_type =
+            HeaderValue::from_str(content_type).map_err(|_| invalid("content_type"))?;
+
+        request = request.header(CONTENT_TYPE, content_type);
+    }
+
+    let request = request
+        .body(Body::from(body))
+        .map_err(|e| TCError::bad_request("invalid recorded request", e))?;
+
+    Ok((offset, request))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_sensitive() {
+        assert!(is_sensitive("/sbin/secrets"));
+        assert!(is_sensitive("/sbin/secrets/db_password"));
+        assert!(is_sensitive("/sbin/keys"));
+        assert!(is_sensitive("/sbin/keys/issuer"));
+
+        // a path which isn't valid might still carry a secret, so it's not recorded either
+        assert!(is_sensitive("/sbin/secrets/"));
+        assert!(is_sensitive("/sbin/keys/"));
+        assert!(is_sensitive("/sbin//secrets"));
+        assert!(is_sensitive("/app/users/"));
+
+        assert!(!is_sensitive("/"));
+        assert!(!is_sensitive("/sbin"));
+        assert!(!is_sensitive("/sbin/metrics"));
+        assert!(!is_sensitive("/app/users"));
+        assert!(!is_sensitive("/app/sbin/secrets"));
+    }
+
+    #[tokio::test]
+    async fn test_record() -> TCResult<()> {
+        let path = std::env::temp_dir().join(format!("record-{}.log", uuid::Uuid::new_v4()));
+        let recorder = Recorder::open(&path)?;
+
+        let request = Request::builder()
+            .method(Method::PUT)
+            .uri("http://127.0.0.1:8702/app/users?key=%221%22&txn_id=123")
+            .header(CONTENT_TYPE, "application/cbor")
+            .header(CONTENT_LENGTH, 3)
+            .body(Body::from(vec![0xa1, 0xff, 0x00]))
+            .expect("request");
+
+        let request = recorder.record(request).await?;
+        let body = hyper::body::to_bytes(request.into_body()).await.unwrap();
+        assert_eq!(&body[..], &[0xa1, 0xff, 0x00]);
+
+        // a body with no Content-Length is written to its own file as it's read
+        let chunks: Vec<Result<&'static [u8], io::Error>> = vec![Ok(b"[1, "), Ok(b"2]")];
+        let request = Request::builder()
+            .method(Method::POST)
+            .uri("http://127.0.0.1:8702/app/users")
+            .body(Body::wrap_stream(stream::iter(chunks)))
+            .expect("request");
+
+        let request = recorder.record(request).await?;
+        let body = hyper::body::to_bytes(request.into_body()).await.unwrap();
+        assert_eq!(&body[..], b"[1, 2]");
+
+        let log = std::fs::read_to_string(&path).unwrap();
+        let bodies = bodies_dir(&path);
+
+        let lines: Vec<&str> = log.lines().collect();
+        assert_eq!(lines.len(), 2);
+
+        let (_offset, replayed) = parse_entry(lines[0], "127.0.0.1:8703", &bodies)?;
+        assert_eq!(replayed.method(), Method::PUT);
+        assert_eq!(
+            replayed.uri().to_string(),
+            "http://127.0.0.1:8703/app/users?key=%221%22"
+        );
+        assert_eq!(replayed.headers()[CONTENT_TYPE], "application/cbor");
+
+        let body = hyper::body::to_bytes(replayed.into_body()).await.unwrap();
+        assert_eq!(&body[..], &[0xa1, 0xff, 0x00]);
+
+        let (_offset, replayed) = parse_entry(lines[1], "127.0.0.1:8703", &bodies)?;
+        assert_eq!(replayed.method(), Method::POST);
+        assert!(replayed.headers().get(CONTENT_TYPE).is_none());
+
+        let body = hyper::body::to_bytes(replayed.into_body()).await.unwrap();
+        assert_eq!(&body[..], b"[1, 2]");
+
+        // only the user who runs the host may read what's recorded
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+
+            let entry: serde_json::Value = serde_json::from_str(lines[1]).unwrap();
+            let body_file = bodies.join(entry["body_file"].as_str().unwrap());
+
+            for path in &[&path, &body_file] {
+                let mode = std::fs::metadata(path).unwrap().permissions().mode();
+                assert_eq!(mode & 0o777, 0o600);
+            }
+        }
+
+        // a request which may carry a secret or a key isn't recorded at all
+        for path in &["/sbin/secrets", "/sbin/keys/commit"] {
+            let request = Request::builder()
+                .method(Method::PUT)
+                .uri(format!("http://127.0.0.1:8702{}?key=%22api_key%22", path))
+                .header(CONTENT_LENGTH, 9)
+                .body(Body::from("\"hunter2\""))
+                .expect("request");
+
+            let request = recorder.record(request).await?;
+            let body = hyper::body::to_bytes(request.into_body()).await.unwrap();
+            assert_eq!(&body[..], b"\"hunter2\"");
+        }
+
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), log);
+
+        std::fs::remove_file(&path).unwrap();
+        std::fs::remove_dir_all(&bodies).unwrap();
+
+        assert!(parse_entry("{\"method\": \"GET\"}", "127.0.0.1:8703", &bodies).is_err());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_replay_speed() {
+        let path = std::env::temp_dir().join("unused.log");
+        assert!(replay(path.clone(), "127.0.0.1:8702".into(), -1.)
+            .await
+            .is_err());
+        assert!(replay(path, "127.0.0.1:8702".into(), f64::NAN)
+            .await
+            .is_err());
+    }
+}
@@ -53,11 +53,15 @@ impl<'a> Handler<'a> for SubjectHandler<'a> {
         Some(Box::new(|txn, key, value| {
             Box::pin(async move {
                 debug!("Subject::put {} <- {}", key, value);
-                if self.path.is_empty() {
-                    self.subject.put(txn.id(), key, value).await
-                } else {
-                    let subject = self.subject.at(txn.id()).await?;
-                    subject.put(&txn, self.path, key, value).await
+                match self.subject {
+                    Subject::Value(_) if self.path.is_empty() => {
+                        self.subject.put(txn.id(), key, value).await
+                    }
+                    _ => {
+                        // a Collection handles its own writes, which its Chain commits
+                        let subject = self.subject.at(txn.id()).await?;
+                        subject.put(&txn, self.path, key, value).await
+                    }
                 }
             })
         }))
@@ -75,6 +75,7 @@ impl<'a> Handler<'a> for ClusterHandler<'a> {
                 }
 
                 self.cluster.commit(txn.id()).await;
+                txn.finish();
                 Ok(State::default())
             })
         }))
@@ -0,0 +1,506 @@
+use std::convert::TryFrom;
+
+use async_trait::async_trait;
+use futures::TryStreamExt;
+use log::debug;
+use safecast::{CastFrom, TryCastFrom};
+
+use tc_error::*;
+use tc_transact::{Transaction, TxnId};
+use tcgeneric::{Id, Map, NativeClass, PathSegment, TCPath};
+
+use crate::collection::{BTree, Collection, Key, Table, Tensor};
+use crate::scalar::{Link, Number, Value, ValueType};
+use crate::state::State;
+
+use super::merkle::{MerkleHandler, RangeHandler, ResyncHandler};
+use super::{
+    ChecksumHandler, DeleteHandler, GetHandler, Handler, PageHandler, PutHandler, Route,
+    StreamResponse,
+};
+
+struct BTreeHandler<'a> {
+    btree: &'a BTree,
+}
+
+impl<'a> Handler<'a> for BTreeHandler<'a> {
+    fn get(self: Box<Self>) -> Option<GetHandler<'a>> {
+        Some(Box::new(|txn, key| {
+            Box::pin(async move {
+                if key.is_none() {
+                    Ok(Collection::BTree(self.btree.clone()).into())
+                } else {
+                    let slice = self.btree.slice(&txn, key_of(key)).await?;
+                    Ok(Collection::BTree(slice).into())
+                }
+            })
+        }))
+    }
+
+    fn put(self: Box<Self>) -> Option<PutHandler<'a>> {
+        Some(Box::new(|txn, key, value| {
+            Box::pin(async move {
+                if key.is_some() {
+                    return Err(TCError::bad_request(
+                        "BTree::insert takes no key, but found",
+                        key,
+                    ));
+                }
+
+                let row = Value::try_from(value)?;
+                self.btree.insert(*txn.id(), key_of(row)).await
+            })
+        }))
+    }
+}
+
+struct TableHandler<'a> {
+    table: &'a Table,
+}
+
+impl<'a> Handler<'a> for TableHandler<'a> {
+    fn get(self: Box<Self>) -> Option<GetHandler<'a>> {
+        Some(Box::new(|txn, key| {
+            Box::pin(async move {
+                let key = key_of(key);
+
+                if key.is_empty() {
+                    Ok(Collection::Table(self.table.clone()).into())
+                } else if key.len() == self.table.schema().key().len() {
+                    let row = self.table.get_row(txn.id(), key.clone()).await?;
+                    row.map(|row| Value::Tuple(row.into()).into())
+                        .ok_or_else(|| TCError::not_found(Value::Tuple(key.into())))
+                } else {
+                    let slice = self.table.slice(&txn, key).await?;
+                    Ok(Collection::Table(slice).into())
+                }
+            })
+        }))
+    }
+
+    fn put(self: Box<Self>) -> Option<PutHandler<'a>> {
+        Some(Box::new(|txn, key, value| {
+            Box::pin(async move {
+                let values = Value::try_from(value)?;
+                self.table
+                    .upsert(*txn.id(), key_of(key), key_of(values))
+                    .await
+            })
+        }))
+    }
+
+    fn delete(self: Box<Self>) -> Option<DeleteHandler<'a>> {
+        Some(Box::new(|txn, key| {
+            Box::pin(async move { self.table.delete(*txn.id(), key_of(key)).await })
+        }))
+    }
+}
+
+struct UpdateHandler<'a> {
+    table: &'a Table,
+}
+
+impl<'a> Handler<'a> for UpdateHandler<'a> {
+    fn put(self: Box<Self>) -> Option<PutHandler<'a>> {
+        Some(Box::new(|txn, key, values| {
+            Box::pin(async move {
+                let values = match values {
+                    State::Map(values) => values
+                        .into_iter()
+                        .map(|(name, value)| Value::try_from(value).map(|value| (name, value)))
+                        .collect::<TCResult<Map<Value>>>()?,
+                    other => {
+                        return Err(TCError::bad_request(
+                            "expected a Map of column values to update, not",
+                            other,
+                        ))
+                    }
+                };
+
+                self.table.update(*txn.id(), key_of(key), values).await
+            })
+        }))
+    }
+}
+
+struct DistinctHandler<'a> {
+    table: &'a Table,
+}
+
+impl<'a> Handler<'a> for DistinctHandler<'a> {
+    fn get(self: Box<Self>) -> Option<GetHandler<'a>> {
+        Some(Box::new(|txn, key| {
+            Box::pin(async move {
+                let columns = columns_of(key)?;
+                let rows = self.table.distinct(&txn, columns).await?;
+                let rows: Vec<Key> = rows.try_collect().await?;
+                let rows = rows.into_iter().map(|row| Value::Tuple(row.into()));
+                Ok(Value::Tuple(rows.collect()).into())
+            })
+        }))
+    }
+}
+
+struct GroupByHandler<'a> {
+    table: &'a Table,
+}
+
+impl<'a> Handler<'a> for GroupByHandler<'a> {
+    fn get(self: Box<Self>) -> Option<GetHandler<'a>> {
+        Some(Box::new(|txn, key| {
+            Box::pin(async move {
+                let columns = columns_of(key)?;
+
+                let groups = self.table.group_by(&txn, columns).await?;
+                let groups: Vec<Key> = groups.try_collect().await?;
+                let groups = groups.into_iter().map(|group| Value::Tuple(group.into()));
+                Ok(Value::Tuple(groups.collect()).into())
+            })
+        }))
+    }
+}
+
+struct OrderByHandler<'a> {
+    table: &'a Table,
+}
+
+impl<'a> Handler<'a> for OrderByHandler<'a> {
+    fn get(self: Box<Self>) -> Option<GetHandler<'a>> {
+        Some(Box::new(|txn, key| {
+            Box::pin(async move {
+                let columns = columns_of(key)?;
+
+                let rows = self.table.order_by(&txn, columns, false).await?;
+                let rows: Vec<Key> = rows.try_collect().await?;
+                let rows = rows.into_iter().map(|row| Value::Tuple(row.into()));
+                Ok(Value::Tuple(rows.collect()).into())
+            })
+        }))
+    }
+}
+
+struct TensorHandler<'a> {
+    tensor: &'a Tensor,
+}
+
+impl<'a> Handler<'a> for TensorHandler<'a> {
+    fn get(self: Box<Self>) -> Option<GetHandler<'a>> {
+        Some(Box::new(|txn, key| {
+            Box::pin(async move {
+                let coord = coord_of(key)?;
+
+                if coord.is_empty() {
+                    Ok(Collection::Tensor(self.tensor.clone()).into())
+                } else if coord.len() == self.tensor.shape().len() {
+                    let value = self.tensor.read_value(txn.id(), &coord).await?;
+                    Ok(Value::from(value).into())
+                } else {
+                    let slice = self.tensor.slice(&txn, &coord).await?;
+                    Ok(Collection::Tensor(slice).into())
+                }
+            })
+        }))
+    }
+
+    fn put(self: Box<Self>) -> Option<PutHandler<'a>> {
+        Some(Box::new(|txn, key, value| {
+            Box::pin(async move {
+                let coord = coord_of(key)?;
+                let value = Value::try_from(value)?;
+                let value = Number::try_cast_from(value, |v| {
+                    TCError::bad_request("a Tensor element must be a Number, not", v)
+                })?;
+
+                self.tensor.write_value(*txn.id(), &coord, value).await
+            })
+        }))
+    }
+}
+
+struct CountHandler<'a> {
+    collection: &'a Collection,
+}
+
+impl<'a> Handler<'a> for CountHandler<'a> {
+    fn get(self: Box<Self>) -> Option<GetHandler<'a>> {
+        Some(Box::new(|txn, key| {
+            Box::pin(async move {
+                if key.is_some() {
+                    return Err(TCError::bad_request("count takes no key, but found", key));
+                }
+
+                let count = match self.collection {
+                    Collection::BTree(btree) => btree.count(txn.id()).await?,
+                    Collection::Table(table) => table.count(txn.id()).await?,
+                    Collection::Tensor(tensor) => tensor.size(),
+                };
+
+                Ok(Value::from(Number::from(count)).into())
+            })
+        }))
+    }
+}
+
+struct SchemaHandler<'a> {
+    tensor: &'a Tensor,
+    attr: &'a str,
+}
+
+impl<'a> Handler<'a> for SchemaHandler<'a> {
+    fn get(self: Box<Self>) -> Option<GetHandler<'a>> {
+        Some(Box::new(|_txn, key| {
+            Box::pin(async move {
+                if key.is_some() {
+                    return Err(TCError::bad_request(
+                        format!("Tensor::{} takes no key, but found", self.attr),
+                        key,
+                    ));
+                }
+
+                if self.attr == "dtype" {
+                    let dtype = ValueType::Number(self.tensor.dtype());
+                    Ok(Link::from(dtype.path()).into())
+                } else {
+                    let shape: Vec<Value> = self
+                        .tensor
+                        .shape()
+                        .iter()
+                        .map(|dim| Value::from(Number::from(*dim)))
+                        .collect();
+
+                    Ok(Value::Tuple(shape.into()).into())
+                }
+            })
+        }))
+    }
+}
+
+#[async_trait]
+impl StreamResponse for BTree {
+    async fn size_hint(&self, txn_id: &TxnId) -> TCResult<u64> {
+        self.count(txn_id).await
+    }
+
+    async fn page(&self, txn_id: &TxnId, offset: u64, limit: usize) -> TCResult<Vec<Value>> {
+        let rows = BTree::page(self, txn_id, offset, limit).await?;
+        Ok(rows
+            .into_iter()
+            .map(|row| Value::Tuple(row.into()))
+            .collect())
+    }
+}
+
+#[async_trait]
+impl StreamResponse for Table {
+    async fn size_hint(&self, txn_id: &TxnId) -> TCResult<u64> {
+        self.count(txn_id).await
+    }
+
+    async fn page(&self, txn_id: &TxnId, offset: u64, limit: usize) -> TCResult<Vec<Value>> {
+        let rows = Table::page(self, txn_id, offset, limit).await?;
+        Ok(rows
+            .into_iter()
+            .map(|row| Value::Tuple(row.into()))
+            .collect())
+    }
+}
+
+#[async_trait]
+impl StreamResponse for Tensor {
+    async fn size_hint(&self, _txn_id: &TxnId) -> TCResult<u64> {
+        Ok(self.size())
+    }
+
+    async fn page(&self, txn_id: &TxnId, offset: u64, limit: usize) -> TCResult<Vec<Value>> {
+        let elements = Tensor::page(self, txn_id, offset, limit).await?;
+        Ok(elements.into_iter().map(Value::from).collect())
+    }
+}
+
+impl Route for Collection {
+    fn route<'a>(&'a self, path: &'a [PathSegment]) -> Option<Box<dyn Handler<'a> + 'a>> {
+        debug!("Collection::route {}", TCPath::from(path));
+
+        if path.is_empty() {
+            match self {
+                Self::BTree(btree) => Some(Box::new(BTreeHandler { btree })),
+                Self::Table(table) => Some(Box::new(TableHandler { table })),
+                Self::Tensor(tensor) => Some(Box::new(TensorHandler { tensor })),
+            }
+        } else if path.len() == 1 {
+            match (self, path[0].as_str()) {
+                (Self::BTree(btree), "checksum") => Some(Box::new(ChecksumHandler::new(btree))),
+                (Self::Table(table), "checksum") => Some(Box::new(ChecksumHandler::new(table))),
+                (Self::Tensor(tensor), "checksum") => Some(Box::new(ChecksumHandler::new(tensor))),
+                (_, "count") => Some(Box::new(CountHandler { collection: self })),
+                (Self::Table(table), "distinct") => Some(Box::new(DistinctHandler { table })),
+                (Self::Table(table), "group_by") => Some(Box::new(GroupByHandler { table })),
+                (Self::BTree(btree), "merkle") => Some(Box::new(MerkleHandler::new(btree))),
+                (Self::Table(table), "order_by") => Some(Box::new(OrderByHandler { table })),
+                (Self::BTree(btree), "page") => Some(Box::new(PageHandler::new(btree))),
+                (Self::Table(table), "page") => Some(Box::new(PageHandler::new(table))),
+                (Self::Tensor(tensor), "page") => Some(Box::new(PageHandler::new(tensor))),
+                (Self::BTree(btree), "range") => Some(Box::new(RangeHandler::new(btree))),
+                (Self::BTree(btree), "resync") => Some(Box::new(ResyncHandler::new(btree))),
+                (Self::Table(table), "update") => Some(Box::new(UpdateHandler { table })),
+                (Self::Tensor(tensor), attr) if attr == "dtype" || attr == "shape" => {
+                    Some(Box::new(SchemaHandler { tensor, attr }))
+                }
+                _ => None,
+            }
+        } else {
+            None
+        }
+    }
+}
+
+fn key_of(key: Value) -> Vec<Value> {
+    match key {
+        Value::None => vec![],
+        Value::Tuple(tuple) => tuple.into_inner(),
+        other => vec![other],
+    }
+}
+
+fn columns_of(key: Value) -> TCResult<Vec<Id>> {
+    key_of(key)
+        .into_iter()
+        .map(|name| Id::try_cast_from(name, |v| TCError::bad_request("invalid column name", v)))
+        .collect()
+}
+
+fn coord_of(key: Value) -> TCResult<Vec<u64>> {
+    key_of(key)
+        .into_iter()
+        .map(|i| {
+            Number::try_cast_from(i, |v| TCError::bad_request("invalid Tensor index", v))
+                .map(u64::cast_from)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use tc_value::{NumberType, UIntType};
+    use tcgeneric::label;
+
+    use crate::collection::{Column, TableSchema};
+    use crate::route::Public;
+    use crate::test::TestHost;
+
+    use super::*;
+
+    fn uint(n: u64) -> Value {
+        Value::from(Number::from(n))
+    }
+
+    fn column(name: &'static str) -> Column {
+        let dtype = ValueType::Number(NumberType::UInt(UIntType::U64));
+        Column::from((label(name), dtype))
+    }
+
+    fn tuple(values: Vec<Value>) -> Value {
+        Value::Tuple(values.into())
+    }
+
+    #[tokio::test]
+    async fn test_btree_routes() -> TCResult<()> {
+        let host = TestHost::new(vec![]).await?;
+        let txn = host.new_txn(true).await?;
+
+        let btree = BTree::create(&txn, vec![column("n")]).await?;
+        let btree = Collection::BTree(btree);
+
+        for n in &[3, 1, 2] {
+            btree.put(&txn, &[], Value::None, uint(*n).into()).await?;
+        }
+
+        assert!(btree.put(&txn, &[], uint(1), uint(4).into()).await.is_err());
+
+        let count = btree
+            .get(&txn, &[label("count").into()], Value::None)
+            .await?;
+        assert!(Value::try_from(count)? == uint(3));
+
+        match btree.get(&txn, &[], uint(2)).await? {
+            State::Collection(Collection::BTree(slice)) => {
+                assert_eq!(slice.count(txn.id()).await?, 1)
+            }
+            other => panic!("expected a BTree but found {}", other),
+        }
+
+        let json = State::from(btree).canonical_json(txn.clone()).await?;
+        let json: serde_json::Value = serde_json::from_slice(&json).unwrap();
+        assert_eq!(
+            json["/state/collection/btree"][1],
+            serde_json::json!([[1], [2], [3]])
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_table_routes() -> TCResult<()> {
+        let host = TestHost::new(vec![]).await?;
+        let txn = host.new_txn(true).await?;
+
+        let schema = TableSchema::from((vec![column("k")], vec![column("v")]));
+        let table = Collection::Table(Table::create(&txn, schema).await?);
+
+        for k in 0..3 {
+            table.put(&txn, &[], uint(k), uint(k * 10).into()).await?;
+        }
+
+        let row = table.get(&txn, &[], uint(1)).await?;
+        assert!(Value::try_from(row)? == tuple(vec![uint(1), uint(10)]));
+
+        let mut values = Map::default();
+        values.insert(label("v").into(), State::from(uint(7)));
+        table
+            .put(&txn, &[label("update").into()], uint(1), State::Map(values))
+            .await?;
+
+        let row = table.get(&txn, &[], uint(1)).await?;
+        assert!(Value::try_from(row)? == tuple(vec![uint(1), uint(7)]));
+
+        let handler = table.route(&[]).expect("Table handler");
+        let delete = handler.delete().expect("Table::delete");
+        delete(txn.clone(), uint(0)).await?;
+
+        assert!(table.get(&txn, &[], uint(0)).await.is_err());
+
+        let count = table
+            .get(&txn, &[label("count").into()], Value::None)
+            .await?;
+        assert!(Value::try_from(count)? == uint(2));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_tensor_routes() -> TCResult<()> {
+        let host = TestHost::new(vec![]).await?;
+        let txn = host.new_txn(true).await?;
+
+        let dtype = NumberType::UInt(UIntType::U64);
+        let tensor = Collection::Tensor(Tensor::create(&txn, dtype, vec![2, 3]).await?);
+
+        let coord = tuple(vec![uint(1), uint(2)]);
+        tensor.put(&txn, &[], coord.clone(), uint(5).into()).await?;
+
+        let value = tensor.get(&txn, &[], coord).await?;
+        assert!(Value::try_from(value)? == uint(5));
+
+        let shape = tensor
+            .get(&txn, &[label("shape").into()], Value::None)
+            .await?;
+        assert!(Value::try_from(shape)? == tuple(vec![uint(2), uint(3)]));
+
+        match tensor.get(&txn, &[], uint(1)).await? {
+            State::Collection(Collection::Tensor(row)) => assert!(row.shape() == [3]),
+            other => panic!("expected a Tensor but found {}", other),
+        }
+
+        Ok(())
+    }
+}
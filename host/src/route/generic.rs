@@ -31,7 +31,7 @@ where
                         .get(&key)
                         .cloned()
                         .map(State::from)
-                        .ok_or_else(|| TCError::not_found(key))
+                        .ok_or_else(|| TCError::not_found(key.original()))
                 }
             })
         }))
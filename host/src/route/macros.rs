@@ -0,0 +1,192 @@
+//! The [`handler!`] macro, to define a native [`Handler`] with typed parameters.
+
+use super::{DeleteHandler, GetHandler, Handler, PostHandler, PutHandler};
+
+/// Construct a boxed [`Handler`] from one or more methods with typed parameters.
+///
+/// The key and value of each method, and each named parameter of a POST method, are cast into
+/// the given type before the body of the method runs. A parameter which can't be cast, and a
+/// POST parameter which is missing or unrecognized, is rejected with a "bad request" error.
+///
+/// Example:
+/// ```ignore
+/// "add" => Some(handler! {
+///     get(_txn, other: Number) => { Ok(Value::from(*self + other).into()) },
+///     post(_txn, { left: Number, right: Number }) => { Ok(Value::from(left + right).into()) },
+/// })
+/// ```
+macro_rules! handler {
+    ($($method:ident ($($params:tt)*) => $body:block),+ $(,)?) => {{
+        let mut handler = $crate::route::MethodHandler::default();
+        $( handler!(@method handler, $method ($($params)*) => $body); )+
+        Box::new(handler)
+    }};
+
+    (@method $handler:ident, get($txn:pat, $key:ident: $key_type:ty) => $body:block) => {
+        $handler.get = Some(Box::new(move |$txn, $key| {
+            Box::pin(async move {
+                let $key: $key_type = handler!(@cast $key, $key_type);
+                $body
+            })
+        }));
+    };
+
+    (@method $handler:ident,
+        put($txn:pat, $key:ident: $key_type:ty, $value:ident: $value_type:ty) => $body:block) => {
+        $handler.put = Some(Box::new(move |$txn, $key, $value| {
+            Box::pin(async move {
+                let $key: $key_type = handler!(@cast $key, $key_type);
+                let $value: $value_type = handler!(@cast $value, $value_type);
+                $body
+            })
+        }));
+    };
+
+    (@method $handler:ident,
+        post($txn:pat, { $($param:ident: $param_type:ty),* $(,)? }) => $body:block) => {
+        $handler.post = Some(Box::new(move |$txn, mut params| {
+            Box::pin(async move {
+                $(
+                    let $param = params
+                        .remove(&tcgeneric::label(stringify!($param)).into())
+                        .ok_or_else(|| {
+                            tc_error::TCError::bad_request(
+                                "missing required parameter",
+                                stringify!($param),
+                            )
+                        })?;
+
+                    let $param: $param_type = handler!(@cast $param, $param_type);
+                )*
+
+                if let Some(name) = params.keys().next() {
+                    return Err(tc_error::TCError::bad_request("unrecognized parameter", name));
+                }
+
+                $body
+            })
+        }));
+    };
+
+    (@method $handler:ident, delete($txn:pat, $key:ident: $key_type:ty) => $body:block) => {
+        $handler.delete = Some(Box::new(move |$txn, $key| {
+            Box::pin(async move {
+                let $key: $key_type = handler!(@cast $key, $key_type);
+                $body
+            })
+        }));
+    };
+
+    (@cast $param:ident, $param_type:ty) => {
+        safecast::TryCastInto::<$param_type>::try_cast_into($param, |v| {
+            tc_error::TCError::bad_request(
+                concat!(
+                    "invalid ",
+                    stringify!($param),
+                    ", expected ",
+                    stringify!($param_type),
+                    " but found"
+                ),
+                v,
+            )
+        })?
+    };
+}
+
+/// A [`Handler`] whose methods are given as closures, constructed by [`handler!`].
+#[derive(Default)]
+pub struct MethodHandler<'a> {
+    pub get: Option<GetHandler<'a>>,
+    pub put: Option<PutHandler<'a>>,
+    pub post: Option<PostHandler<'a>>,
+    pub delete: Option<DeleteHandler<'a>>,
+}
+
+impl<'a> Handler<'a> for MethodHandler<'a> {
+    fn get(self: Box<Self>) -> Option<GetHandler<'a>> {
+        self.get
+    }
+
+    fn put(self: Box<Self>) -> Option<PutHandler<'a>> {
+        self.put
+    }
+
+    fn post(self: Box<Self>) -> Option<PostHandler<'a>> {
+        self.post
+    }
+
+    fn delete(self: Box<Self>) -> Option<DeleteHandler<'a>> {
+        self.delete
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::convert::TryFrom;
+    use std::fmt;
+
+    use tc_error::*;
+    use tcgeneric::{label, Map, PathSegment};
+
+    use crate::route::{Handler, Public, Route};
+    use crate::scalar::{Number, Value};
+    use crate::state::State;
+    use crate::test::TestHost;
+
+    struct Echo;
+
+    impl Route for Echo {
+        fn route<'a>(&'a self, path: &'a [PathSegment]) -> Option<Box<dyn Handler<'a> + 'a>> {
+            if path.is_empty() {
+                Some(handler! {
+                    get(_txn, key: Number) => { Ok(Value::from(key).into()) },
+                    post(_txn, { left: Value, right: Value }) => {
+                        Ok(Value::from(left == right).into())
+                    },
+                })
+            } else {
+                None
+            }
+        }
+    }
+
+    impl fmt::Display for Echo {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            f.write_str("echo")
+        }
+    }
+
+    fn params(names: &[&'static str]) -> Map<State> {
+        names
+            .iter()
+            .map(|name| (label(name).into(), Value::from(Number::from(1u64)).into()))
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn test_handler() -> TCResult<()> {
+        let host = TestHost::new(vec![]).await?;
+        let txn = host.new_txn(false).await?;
+
+        let key = Value::from(Number::from(2u64));
+        let echo = Value::try_from(Echo.get(&txn, &[], key.clone()).await?)?;
+        assert!(echo == key);
+
+        let err = Echo.get(&txn, &[], Value::String("two".into())).await;
+        assert!(err.map(|_| ()).unwrap_err().code() == ErrorType::BadRequest);
+
+        let same = Value::try_from(Echo.post(&txn, &[], params(&["left", "right"])).await?)?;
+        assert!(same == Value::from(true));
+
+        let err = Echo.post(&txn, &[], params(&["left"])).await;
+        assert!(err.map(|_| ()).unwrap_err().code() == ErrorType::BadRequest);
+
+        let err = Echo.post(&txn, &[], params(&["left", "right", "up"])).await;
+        assert!(err.map(|_| ()).unwrap_err().code() == ErrorType::BadRequest);
+
+        let err = Echo.put(&txn, &[], Value::None, State::default()).await;
+        assert!(err.unwrap_err().code() == ErrorType::MethodNotAllowed);
+
+        Ok(())
+    }
+}
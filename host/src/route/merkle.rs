@@ -0,0 +1,638 @@
+//! Merkle trees over the key ranges of a [`BTree`], to find and repair divergent replicas.
+//!
+//! The source replica splits its rows into `2^depth` consecutive key ranges of (nearly) equal
+//! length, and returns the bounds of each range along with a Merkle tree whose leaves are the
+//! digests of the rows in each range, and each node above them the digest of its children.
+//! Another replica computes its own tree over the same bounds, compares the two from the root
+//! down to find exactly which ranges diverge, then replaces the contents of each divergent range
+//! with the rows of the same range from the source. Rows which are missing, extra, or different
+//! are all repaired this way.
+//!
+//! Either tree is computed in a single streaming pass over the rows of its replica, so only the
+//! bounds and one digest per range are held in memory at once.
+
+use std::cmp::Ordering;
+use std::convert::TryFrom;
+use std::ops::Bound;
+
+use async_trait::async_trait;
+use bytes::Bytes;
+use futures::{StreamExt, TryStreamExt};
+use safecast::{CastFrom, TryCastFrom, TryCastInto};
+
+use tc_error::*;
+use tc_transact::{Transaction, TxnId};
+use tcgeneric::{label, Label, PathSegment};
+
+use crate::collection::{collate, BTree, Key, KeyRange};
+use crate::scalar::{Link, Number, Value};
+use crate::state::State;
+use crate::txn::Txn;
+
+use super::stream::{digest, MAX_PAGE_SIZE};
+use super::{GetHandler, Handler, PostHandler};
+
+/// The depth of a Merkle tree, if the client doesn't specify one.
+pub const DEFAULT_DEPTH: u32 = 8;
+
+/// The maximum depth of a Merkle tree, which has up to `2^depth` leaves.
+pub const MAX_DEPTH: u32 = 12;
+
+const MERKLE: Label = label("merkle");
+const RANGE: Label = label("range");
+const SOURCE: Label = label("source");
+
+// the levels of a Merkle tree from the root down, so that the last level is the leaves
+type Tree = Vec<Vec<blake3::Hash>>;
+
+/// Handles `GET <btree>/merkle`, with a key of `depth`.
+///
+/// Returns a tuple of the bounds of each key range after the first, and the levels of the Merkle
+/// tree over those ranges from the root down, each a tuple of digests.
+pub struct MerkleHandler<'a> {
+    btree: &'a BTree,
+}
+
+impl<'a> MerkleHandler<'a> {
+    pub fn new(btree: &'a BTree) -> Self {
+        Self { btree }
+    }
+}
+
+impl<'a> Handler<'a> for MerkleHandler<'a> {
+    fn get(self: Box<Self>) -> Option<GetHandler<'a>> {
+        Some(Box::new(|txn, key| {
+            Box::pin(async move {
+                let depth = depth_of(key)?;
+                let (bounds, tree) = source_tree(self.btree, *txn.id(), depth).await?;
+                Ok(encode_merkle(bounds, tree).into())
+            })
+        }))
+    }
+}
+
+/// Handles `GET <btree>/range`, with a key of `(start, end)`.
+///
+/// Returns up to [`MAX_PAGE_SIZE`] rows of the `BTree` in key order, beginning with `start` and
+/// ending before `end`. Either bound may be `None`, to begin or end with the `BTree` itself.
+pub struct RangeHandler<'a> {
+    btree: &'a BTree,
+}
+
+impl<'a> RangeHandler<'a> {
+    pub fn new(btree: &'a BTree) -> Self {
+        Self { btree }
+    }
+}
+
+impl<'a> Handler<'a> for RangeHandler<'a> {
+    fn get(self: Box<Self>) -> Option<GetHandler<'a>> {
+        Some(Box::new(|txn, key| {
+            Box::pin(async move {
+                let (start, end) = match key {
+                    Value::Tuple(bounds) if bounds.len() == 2 => {
+                        let mut bounds = bounds.into_inner().into_iter();
+                        let start = bound_of(bounds.next().unwrap())?;
+                        let end = bound_of(bounds.next().unwrap())?;
+                        (start, end)
+                    }
+                    other => {
+                        return Err(TCError::bad_request(
+                            "expected a (start, end) key but found",
+                            other,
+                        ))
+                    }
+                };
+
+                let rows = self.btree.rows_between(&txn, start, end).await?;
+                Ok(encode_rows(rows).into())
+            })
+        }))
+    }
+}
+
+/// Handles `POST <btree>/resync`, with a `source` link to another replica of the same `BTree`.
+///
+/// Compares the Merkle trees of the two replicas, replaces the rows of each divergent key range
+/// with the rows of the same range of the `source`, then returns the number of ranges replaced.
+pub struct ResyncHandler<'a> {
+    btree: &'a BTree,
+}
+
+impl<'a> ResyncHandler<'a> {
+    pub fn new(btree: &'a BTree) -> Self {
+        Self { btree }
+    }
+}
+
+impl<'a> Handler<'a> for ResyncHandler<'a> {
+    fn post(self: Box<Self>) -> Option<PostHandler<'a>> {
+        Some(Box::new(|txn, mut params| {
+            Box::pin(async move {
+                let source = params
+                    .remove(&SOURCE.into())
+                    .ok_or_else(|| TCError::bad_request("missing required parameter", SOURCE))?;
+
+                let source: Link = Value::try_from(source)?
+                    .try_cast_into(|v| TCError::bad_request("invalid source link", v))?;
+
+                if let Some(name) = params.keys().next() {
+                    return Err(TCError::bad_request("unrecognized parameter", name));
+                }
+
+                let replaced = resync(self.btree, &txn, &source, DEFAULT_DEPTH).await?;
+                Ok(Value::from(Number::from(replaced as u64)).into())
+            })
+        }))
+    }
+}
+
+/// A replica of a [`BTree`] to resync from.
+#[async_trait]
+trait Source: Send + Sync {
+    /// Return the bounds of each key range of this replica after the first, and its Merkle tree
+    /// over those ranges.
+    async fn merkle(&self, txn: &Txn, depth: u32) -> TCResult<(Vec<Key>, Tree)>;
+
+    /// Return up to [`MAX_PAGE_SIZE`] rows in key order, beginning with `start` and ending before
+    /// `end`.
+    async fn rows_between(
+        &self,
+        txn: &Txn,
+        start: Option<Key>,
+        end: Option<Key>,
+    ) -> TCResult<Vec<Key>>;
+}
+
+#[async_trait]
+impl Source for BTree {
+    async fn merkle(&self, txn: &Txn, depth: u32) -> TCResult<(Vec<Key>, Tree)> {
+        source_tree(self, *txn.id(), depth).await
+    }
+
+    async fn rows_between(
+        &self,
+        txn: &Txn,
+        start: Option<Key>,
+        end: Option<Key>,
+    ) -> TCResult<Vec<Key>> {
+        self.range(*txn.id(), key_range(start, end))?
+            .take(MAX_PAGE_SIZE)
+            .try_collect()
+            .await
+    }
+}
+
+#[async_trait]
+impl Source for Link {
+    async fn merkle(&self, txn: &Txn, depth: u32) -> TCResult<(Vec<Key>, Tree)> {
+        let depth = Value::from(Number::from(depth as u64));
+        let merkle = txn.get(append(self, MERKLE.into()), depth).await?;
+        decode_merkle(value_of(merkle)?)
+    }
+
+    async fn rows_between(
+        &self,
+        txn: &Txn,
+        start: Option<Key>,
+        end: Option<Key>,
+    ) -> TCResult<Vec<Key>> {
+        let bounds = vec![encode_bound(start), encode_bound(end)];
+        let rows = txn
+            .get(append(self, RANGE.into()), Value::Tuple(bounds.into()))
+            .await?;
+
+        match value_of(rows)? {
+            Value::Tuple(rows) => Ok(rows.into_inner().into_iter().map(key_of).collect()),
+            other => Err(TCError::bad_request(
+                "expected a tuple of rows but found",
+                other,
+            )),
+        }
+    }
+}
+
+// replace the rows of each key range of `btree` which differs from `source`,
+// and return the number of ranges replaced
+async fn resync<S: Source>(btree: &BTree, txn: &Txn, source: &S, depth: u32) -> TCResult<usize> {
+    let txn_id = *txn.id();
+
+    let (bounds, remote) = source.merkle(txn, depth).await?;
+    let local = replica_tree(btree, txn_id, &bounds).await?;
+
+    let divergent = diff(&local, &remote);
+    for leaf in &divergent {
+        let (mut start, end) = leaf_range(&bounds, *leaf);
+        btree
+            .delete(txn_id, key_range(start.clone(), end.clone()))
+            .await?;
+
+        loop {
+            let rows = source.rows_between(txn, start.clone(), end.clone()).await?;
+            if rows.len() < MAX_PAGE_SIZE {
+                btree.insert_all(txn_id, rows).await?;
+                break;
+            }
+
+            // the next page begins with the last row of this one, which must come after `start`
+            let last = rows[rows.len() - 1].clone();
+            if let Some(start) = &start {
+                if collate(&last, start) != Ordering::Greater {
+                    return Err(TCError::bad_request(
+                        "resync source returned rows out of order, ending with",
+                        Value::Tuple(last.into()),
+                    ));
+                }
+            }
+
+            btree.insert_all(txn_id, rows).await?;
+            start = Some(last);
+        }
+    }
+
+    Ok(divergent.len())
+}
+
+// split the rows of `btree` into `2^depth` key ranges of (nearly) equal length, and return the
+// bounds of each range after the first, along with the Merkle tree over those ranges
+async fn source_tree(btree: &BTree, txn_id: TxnId, depth: u32) -> TCResult<(Vec<Key>, Tree)> {
+    let count = btree.count(&txn_id).await? as u128;
+    let ranges = 1u128 << depth;
+
+    // the offset of the first row of each range after the first
+    let starts: Vec<u64> = if count == 0 {
+        vec![]
+    } else {
+        (1..ranges).map(|i| ((i * count) / ranges) as u64).collect()
+    };
+
+    let mut bounds = Vec::with_capacity(starts.len());
+    let mut leaves = vec![blake3::Hasher::new(); starts.len() + 1];
+
+    let mut rows = btree.rows(txn_id);
+    let mut offset = 0;
+    while let Some(row) = rows.try_next().await? {
+        while bounds.len() < starts.len() && starts[bounds.len()] == offset {
+            bounds.push(row.clone());
+        }
+
+        digest_row(&mut leaves[bounds.len()], row);
+        offset += 1;
+    }
+
+    if bounds.len() == starts.len() {
+        Ok((bounds, build_tree(leaves)))
+    } else {
+        Err(TCError::internal(format!(
+            "expected {} rows to build a Merkle tree but found {}",
+            count, offset
+        )))
+    }
+}
+
+// compute the Merkle tree of `btree` over the key ranges of another replica with the given bounds
+async fn replica_tree(btree: &BTree, txn_id: TxnId, bounds: &[Key]) -> TCResult<Tree> {
+    let mut leaves = vec![blake3::Hasher::new(); bounds.len() + 1];
+
+    let mut rows = btree.rows(txn_id);
+    let mut leaf = 0;
+    while let Some(row) = rows.try_next().await? {
+        while leaf < bounds.len() && collate(&row, &bounds[leaf]) != Ordering::Less {
+            leaf += 1;
+        }
+
+        digest_row(&mut leaves[leaf], row);
+    }
+
+    Ok(build_tree(leaves))
+}
+
+fn digest_row(hasher: &mut blake3::Hasher, row: Key) {
+    digest(hasher, &Value::Tuple(row.into()))
+}
+
+fn build_tree(leaves: Vec<blake3::Hasher>) -> Tree {
+    let mut level: Vec<blake3::Hash> = leaves.iter().map(|leaf| leaf.finalize()).collect();
+
+    let mut tree = vec![];
+    while level.len() > 1 {
+        // the last node of a level with an odd length is the only child of its parent
+        let parent = level
+            .chunks(2)
+            .map(|children| {
+                let mut hasher = blake3::Hasher::new();
+                for child in children {
+                    hasher.update(child.as_bytes());
+                }
+
+                hasher.finalize()
+            })
+            .collect();
+
+        tree.push(level);
+        level = parent;
+    }
+
+    tree.push(level);
+    tree.reverse();
+    tree
+}
+
+// the length of each level of a Merkle tree with the given number of leaves, from the root down
+fn shape(leaves: usize) -> Vec<usize> {
+    let mut shape = vec![leaves];
+    while shape[shape.len() - 1] > 1 {
+        shape.push(shape[shape.len() - 1].div_ceil(2));
+    }
+
+    shape.reverse();
+    shape
+}
+
+// the leaves which differ between two trees of the same shape, found from the root down
+fn diff(local: &Tree, remote: &Tree) -> Vec<usize> {
+    let mut divergent = vec![0];
+    for depth in 0..local.len() {
+        divergent.retain(|i| local[depth][*i] != remote[depth][*i]);
+
+        if depth + 1 < local.len() {
+            let len = local[depth + 1].len();
+            divergent = divergent
+                .into_iter()
+                .flat_map(|i| vec![2 * i, 2 * i + 1])
+                .filter(|child| *child < len)
+                .collect();
+        }
+    }
+
+    divergent
+}
+
+// the inclusive start and exclusive end of the key range of the given leaf
+fn leaf_range(bounds: &[Key], leaf: usize) -> (Option<Key>, Option<Key>) {
+    let start = if leaf == 0 {
+        None
+    } else {
+        Some(bounds[leaf - 1].clone())
+    };
+
+    (start, bounds.get(leaf).cloned())
+}
+
+fn key_range(start: Option<Key>, end: Option<Key>) -> KeyRange {
+    let start = start.map(Bound::Included).unwrap_or(Bound::Unbounded);
+    let end = end.map(Bound::Excluded).unwrap_or(Bound::Unbounded);
+    (start, end)
+}
+
+fn depth_of(key: Value) -> TCResult<u32> {
+    if key.is_none() {
+        return Ok(DEFAULT_DEPTH);
+    }
+
+    let depth = u64::cast_from(Number::try_cast_from(key, |v| {
+        TCError::bad_request("invalid Merkle tree depth", v)
+    })?);
+
+    if depth > MAX_DEPTH as u64 {
+        Err(TCError::bad_request(
+            format!("Merkle tree depth must be at most {}, not", MAX_DEPTH),
+            depth,
+        ))
+    } else {
+        Ok(depth as u32)
+    }
+}
+
+fn bound_of(bound: Value) -> TCResult<Option<Key>> {
+    match bound {
+        Value::None => Ok(None),
+        Value::Tuple(key) => Ok(Some(key.into_inner())),
+        other => Err(TCError::bad_request(
+            "expected a key or None as the bound of a range but found",
+            other,
+        )),
+    }
+}
+
+fn encode_bound(bound: Option<Key>) -> Value {
+    bound
+        .map(|key| Value::Tuple(key.into()))
+        .unwrap_or(Value::None)
+}
+
+fn encode_rows(rows: Vec<Key>) -> Value {
+    let rows: Vec<Value> = rows
+        .into_iter()
+        .map(|row| Value::Tuple(row.into()))
+        .collect();
+    Value::Tuple(rows.into())
+}
+
+// a row with a single column may be decoded as that column's value rather than a tuple
+fn key_of(row: Value) -> Key {
+    match row {
+        Value::Tuple(row) => row.into_inner(),
+        other => vec![other],
+    }
+}
+
+fn encode_merkle(bounds: Vec<Key>, tree: Tree) -> Value {
+    let levels: Vec<Value> = tree
+        .into_iter()
+        .map(|level| {
+            let level: Vec<Value> = level
+                .iter()
+                .map(|hash| Value::from(Bytes::copy_from_slice(hash.as_bytes())))
+                .collect();
+
+            Value::Tuple(level.into())
+        })
+        .collect();
+
+    Value::Tuple(vec![encode_rows(bounds), Value::Tuple(levels.into())].into())
+}
+
+fn decode_merkle(merkle: Value) -> TCResult<(Vec<Key>, Tree)> {
+    let invalid = |cause: &str| TCError::bad_request("invalid Merkle tree", cause);
+
+    let (bounds, levels) = match merkle {
+        Value::Tuple(merkle) if merkle.len() == 2 => {
+            let mut merkle = merkle.into_inner().into_iter();
+            match (merkle.next().unwrap(), merkle.next().unwrap()) {
+                (Value::Tuple(bounds), Value::Tuple(levels)) => {
+                    (bounds.into_inner(), levels.into_inner())
+                }
+                _ => return Err(invalid("expected a tuple of (bounds, levels)")),
+            }
+        }
+        _ => return Err(invalid("expected a tuple of (bounds, levels)")),
+    };
+
+    if bounds.len() >= 1 << MAX_DEPTH {
+        return Err(invalid("too many key ranges"));
+    }
+
+    let bounds: Vec<Key> = bounds.into_iter().map(key_of).collect();
+    if bounds
+        .windows(2)
+        .any(|pair| collate(&pair[0], &pair[1]) == Ordering::Greater)
+    {
+        return Err(invalid("the bounds of its key ranges are out of order"));
+    }
+
+    let shape = shape(bounds.len() + 1);
+    if levels.len() != shape.len() {
+        return Err(invalid("wrong number of levels"));
+    }
+
+    let mut tree = Vec::with_capacity(levels.len());
+    for (level, len) in levels.into_iter().zip(shape) {
+        let level = match level {
+            Value::Tuple(level) if level.len() == len => level.into_inner(),
+            _ => return Err(invalid("wrong number of nodes in a level")),
+        };
+
+        let level = level
+            .into_iter()
+            .map(|hash| match hash {
+                Value::Bytes(hash) if hash.len() == blake3::OUT_LEN => {
+                    let mut bytes = [0u8; blake3::OUT_LEN];
+                    bytes.copy_from_slice(&hash);
+                    Ok(blake3::Hash::from(bytes))
+                }
+                _ => Err(invalid("expected a digest")),
+            })
+            .collect::<TCResult<Vec<blake3::Hash>>>()?;
+
+        tree.push(level);
+    }
+
+    Ok((bounds, tree))
+}
+
+fn append(link: &Link, segment: PathSegment) -> Link {
+    let path = link.path().clone().append(segment);
+    match link.host() {
+        Some(host) => (host.clone(), path).into(),
+        None => path.into(),
+    }
+}
+
+// a response from another host may be decoded as a tuple of states rather than a single value
+fn value_of(state: State) -> TCResult<Value> {
+    match state {
+        State::Tuple(tuple) => tuple
+            .into_inner()
+            .into_iter()
+            .map(value_of)
+            .collect::<TCResult<Vec<Value>>>()
+            .map(|values| Value::Tuple(values.into())),
+
+        other => Value::try_from(other),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tc_value::{NumberType, UIntType, ValueType};
+    use tcgeneric::label;
+
+    use crate::collection::{Column, RowSchema};
+    use crate::test::TestHost;
+
+    use super::*;
+
+    fn row(k: u64, v: u64) -> Key {
+        vec![Value::from(Number::from(k)), Value::from(Number::from(v))]
+    }
+
+    fn schema() -> RowSchema {
+        let dtype = ValueType::Number(NumberType::UInt(UIntType::U64));
+        vec![
+            Column::from((label("k"), dtype)),
+            Column::from((label("v"), dtype)),
+        ]
+    }
+
+    #[tokio::test]
+    async fn test_resync() -> TCResult<()> {
+        let host = TestHost::new(vec![]).await?;
+        let txn = host.new_txn(true).await?;
+        let txn_id = *txn.id();
+
+        let source = BTree::create(&txn, schema()).await?;
+        source
+            .insert_all(txn_id, (0..5000).map(|n| row(n, n)).collect())
+            .await?;
+
+        // the replica is missing one row, has one extra, and has a different value in another
+        let replica = BTree::create(&txn, schema()).await?;
+        let rows = (0..5000)
+            .filter(|n| *n != 10)
+            .map(|n| if n == 2600 { row(n, 0) } else { row(n, n) })
+            .chain(Some(row(6000, 0)))
+            .collect();
+
+        replica.insert_all(txn_id, rows).await?;
+
+        let replaced = resync(&replica, &txn, &source, 4).await?;
+        assert!(replaced > 0 && replaced <= 3);
+
+        let expected: Vec<Key> = source.rows(txn_id).try_collect().await?;
+        let actual: Vec<Key> = replica.rows(txn_id).try_collect().await?;
+        assert!(actual == expected);
+
+        assert_eq!(resync(&replica, &txn, &source, 4).await?, 0);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_resync_empty() -> TCResult<()> {
+        let host = TestHost::new(vec![]).await?;
+        let txn = host.new_txn(true).await?;
+        let txn_id = *txn.id();
+
+        let source = BTree::create(&txn, schema()).await?;
+        let replica = BTree::create(&txn, schema()).await?;
+        replica.insert_all(txn_id, vec![row(1, 1)]).await?;
+
+        assert_eq!(resync(&replica, &txn, &source, 2).await?, 1);
+        assert_eq!(replica.count(&txn_id).await?, 0);
+
+        // with fewer rows than ranges, some ranges are empty on both replicas
+        source
+            .insert_all(txn_id, vec![row(1, 1), row(2, 2)])
+            .await?;
+        assert_eq!(resync(&replica, &txn, &source, 2).await?, 2);
+        assert_eq!(replica.count(&txn_id).await?, 2);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_encode_merkle() -> TCResult<()> {
+        let host = TestHost::new(vec![]).await?;
+        let txn = host.new_txn(true).await?;
+        let txn_id = *txn.id();
+
+        let btree = BTree::create(&txn, schema()).await?;
+        btree
+            .insert_all(txn_id, (0..3).map(|n| row(n, n)).collect())
+            .await?;
+
+        let (bounds, tree) = source_tree(&btree, txn_id, 3).await?;
+        assert_eq!(bounds.len(), 7);
+        assert!(tree.iter().map(Vec::len).collect::<Vec<usize>>() == vec![1, 2, 4, 8]);
+
+        let (decoded_bounds, decoded_tree) = decode_merkle(encode_merkle(bounds, tree.clone()))?;
+        assert_eq!(decoded_bounds.len(), 7);
+        assert!(decoded_tree == tree);
+        assert!(decode_merkle(Value::None).is_err());
+
+        assert!(shape(5) == vec![1, 2, 3, 5]);
+
+        Ok(())
+    }
+}
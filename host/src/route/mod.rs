@@ -11,12 +11,21 @@ use crate::scalar::Value;
 use crate::state::State;
 use crate::txn::Txn;
 
+#[macro_use]
+mod macros;
+
 mod chain;
 mod cluster;
+mod collection;
 mod generic;
+mod merkle;
 mod object;
 mod scalar;
 mod state;
+mod stream;
+
+pub use macros::MethodHandler;
+pub use stream::{ChecksumHandler, PageHandler, StreamResponse};
 
 pub type GetFuture<'a> = Pin<Box<dyn Future<Output = TCResult<State>> + Send + 'a>>;
 pub type GetHandler<'a> = Box<dyn FnOnce(Txn, Value) -> GetFuture<'a> + Send + 'a>;
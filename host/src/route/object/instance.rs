@@ -1,34 +1,55 @@
 use std::collections::HashMap;
+use std::sync::Arc;
 
 use log::debug;
 
 use tc_error::*;
 use tcgeneric::{Id, Instance, Map, PathSegment, TCPath};
 
-use crate::object::InstanceExt;
+use crate::cluster::Cluster;
+use crate::object::{InstanceClass, InstanceExt, IMPLEMENTS};
 use crate::route::{GetHandler, Handler, PostHandler, PutHandler, Route};
 use crate::scalar::*;
 use crate::state::State;
 use crate::txn::Txn;
 
+use super::ImplementsHandler;
+
 struct GetMethod<'a, T: Instance> {
     subject: &'a InstanceExt<T>,
+    superclass: Option<State>,
     method: GetOp,
     path: &'a [PathSegment],
+    plan: Option<Arc<Plan>>,
 }
 
-impl<'a, T: Instance + Route + 'a> GetMethod<'a, T> {
+impl<'a, T: Instance + Route + 'a> GetMethod<'a, T>
+where
+    InstanceExt<T>: Route,
+{
     async fn call(self, txn: Txn, key: Value) -> TCResult<State> {
         let (key_name, op_def) = self.method;
 
-        let mut context = HashMap::with_capacity(1);
+        let mut context = HashMap::with_capacity(2);
         context.insert(key_name, key.into());
+        bind_super(&mut context, self.superclass);
 
-        call_method(txn, self.subject, self.path, context.into(), op_def).await
+        call_method(
+            txn,
+            self.subject,
+            self.path,
+            context.into(),
+            op_def,
+            self.plan,
+        )
+        .await
     }
 }
 
-impl<'a, T: Instance + Route + 'a> Handler<'a> for GetMethod<'a, T> {
+impl<'a, T: Instance + Route + 'a> Handler<'a> for GetMethod<'a, T>
+where
+    InstanceExt<T>: Route,
+{
     fn get(self: Box<Self>) -> Option<GetHandler<'a>> {
         Some(Box::new(move |txn, key| Box::pin(self.call(txn, key))))
     }
@@ -36,19 +57,33 @@ impl<'a, T: Instance + Route + 'a> Handler<'a> for GetMethod<'a, T> {
 
 struct PutMethod<'a, T: Instance> {
     subject: &'a InstanceExt<T>,
+    superclass: Option<State>,
     method: PutOp,
     path: &'a [PathSegment],
+    plan: Option<Arc<Plan>>,
 }
 
-impl<'a, T: Instance + Route + 'a> PutMethod<'a, T> {
+impl<'a, T: Instance + Route + 'a> PutMethod<'a, T>
+where
+    InstanceExt<T>: Route,
+{
     async fn call(self, txn: Txn, key: Value, value: State) -> TCResult<()> {
         let (key_name, value_name, op_def) = self.method;
 
-        let mut context = HashMap::with_capacity(2);
+        let mut context = HashMap::with_capacity(3);
         context.insert(key_name, key.into());
         context.insert(value_name, value);
+        bind_super(&mut context, self.superclass);
 
-        let state = call_method(txn, self.subject, self.path, context.into(), op_def).await?;
+        let state = call_method(
+            txn,
+            self.subject,
+            self.path,
+            context.into(),
+            op_def,
+            self.plan,
+        )
+        .await?;
         if state.is_none() {
             Ok(())
         } else {
@@ -60,7 +95,10 @@ impl<'a, T: Instance + Route + 'a> PutMethod<'a, T> {
     }
 }
 
-impl<'a, T: Instance + Route + 'a> Handler<'a> for PutMethod<'a, T> {
+impl<'a, T: Instance + Route + 'a> Handler<'a> for PutMethod<'a, T>
+where
+    InstanceExt<T>: Route,
+{
     fn put(self: Box<Self>) -> Option<PutHandler<'a>> {
         Some(Box::new(move |txn, key, value| {
             Box::pin(self.call(txn, key, value))
@@ -70,17 +108,36 @@ impl<'a, T: Instance + Route + 'a> Handler<'a> for PutMethod<'a, T> {
 
 struct PostMethod<'a, T: Instance> {
     subject: &'a InstanceExt<T>,
+    superclass: Option<State>,
     method: PostOp,
     path: &'a [PathSegment],
+    plan: Option<Arc<Plan>>,
 }
 
-impl<'a, T: Instance + Route + 'a> PostMethod<'a, T> {
+impl<'a, T: Instance + Route + 'a> PostMethod<'a, T>
+where
+    InstanceExt<T>: Route,
+{
     async fn call(self, txn: Txn, params: Map<State>) -> TCResult<State> {
-        call_method(txn, self.subject, self.path, params, self.method).await
+        let mut params = params.into_inner();
+        bind_super(&mut params, self.superclass);
+
+        call_method(
+            txn,
+            self.subject,
+            self.path,
+            params.into(),
+            self.method,
+            self.plan,
+        )
+        .await
     }
 }
 
-impl<'a, T: Instance + Route + 'a> Handler<'a> for PostMethod<'a, T> {
+impl<'a, T: Instance + Route + 'a> Handler<'a> for PostMethod<'a, T>
+where
+    InstanceExt<T>: Route,
+{
     fn post(self: Box<Self>) -> Option<PostHandler<'a>> {
         Some(Box::new(move |txn, params| {
             Box::pin(self.call(txn, params))
@@ -88,38 +145,92 @@ impl<'a, T: Instance + Route + 'a> Handler<'a> for PostMethod<'a, T> {
     }
 }
 
-impl<T: Instance + Route> Route for InstanceExt<T> {
+impl Route for InstanceExt<Cluster> {
     fn route<'a>(&'a self, path: &'a [PathSegment]) -> Option<Box<dyn Handler<'a> + 'a>> {
-        debug!("InstanceExt::route {}", TCPath::from(path));
-
-        if path.is_empty() {
-            self.parent().route(path)
-        } else if let Some(member) = self.proto().get(&path[0]) {
-            match member {
-                Scalar::Op(OpDef::Get(get_op)) => Some(Box::new(GetMethod {
-                    subject: self,
-                    method: get_op.clone(),
-                    path: &path[1..],
-                })),
-                Scalar::Op(OpDef::Put(put_op)) => Some(Box::new(PutMethod {
-                    subject: self,
-                    method: put_op.clone(),
-                    path: &path[1..],
-                })),
-                Scalar::Op(OpDef::Post(post_op)) => Some(Box::new(PostMethod {
-                    subject: self,
-                    method: post_op.clone(),
-                    path: &path[1..],
-                })),
-                other => other.route(&path[1..]),
-            }
-        } else {
-            debug!(
-                "{} not found in instance prototype, routing to parent",
-                &path[0]
-            );
-            self.parent().route(path)
+        // a Cluster cannot extend a user-defined class, so there is never a superclass to bind
+        route_instance(
+            self,
+            path,
+            |_, _| None,
+            |cluster, name| cluster.parent().plan(name),
+        )
+    }
+}
+
+impl Route for InstanceExt<State> {
+    fn route<'a>(&'a self, path: &'a [PathSegment]) -> Option<Box<dyn Handler<'a> + 'a>> {
+        let as_superclass = |instance: &InstanceExt<State>, superclass: &InstanceClass| {
+            let parent = instance.parent().clone();
+            Some(State::Object(
+                InstanceExt::new(parent, superclass.clone()).into(),
+            ))
+        };
+
+        // the methods of an object aren't compiled ahead of time, so they're resolved dynamically
+        route_instance(self, path, as_superclass, |_, _| None)
+    }
+}
+
+fn route_instance<'a, T: Instance + Route>(
+    instance: &'a InstanceExt<T>,
+    path: &'a [PathSegment],
+    as_superclass: fn(&InstanceExt<T>, &InstanceClass) -> Option<State>,
+    plan: fn(&InstanceExt<T>, &Id) -> Option<Arc<Plan>>,
+) -> Option<Box<dyn Handler<'a> + 'a>>
+where
+    InstanceExt<T>: Route,
+{
+    debug!("InstanceExt::route {}", TCPath::from(path));
+
+    if path.is_empty() {
+        instance.parent().route(path)
+    } else if path.len() == 1 && path[0] == IMPLEMENTS {
+        Some(Box::new(ImplementsHandler::from(instance.class())))
+    } else if let Some((class, member)) = instance.get_member(&path[0]) {
+        // methods are resolved from the class which defines them, so `super` refers to its parent
+        let superclass = || {
+            class
+                .superclass()
+                .and_then(|superclass| as_superclass(instance, superclass))
+        };
+
+        match member {
+            Scalar::Op(OpDef::Get(get_op)) => Some(Box::new(GetMethod {
+                subject: instance,
+                superclass: superclass(),
+                method: get_op.clone(),
+                path: &path[1..],
+                plan: plan(instance, &path[0]),
+            })),
+            Scalar::Op(OpDef::Put(put_op)) => Some(Box::new(PutMethod {
+                subject: instance,
+                superclass: superclass(),
+                method: put_op.clone(),
+                path: &path[1..],
+                plan: plan(instance, &path[0]),
+            })),
+            Scalar::Op(OpDef::Post(post_op)) => Some(Box::new(PostMethod {
+                subject: instance,
+                superclass: superclass(),
+                method: post_op.clone(),
+                path: &path[1..],
+                plan: plan(instance, &path[0]),
+            })),
+            other => other.route(&path[1..]),
         }
+    } else {
+        debug!(
+            "{} not found in instance class hierarchy, routing to parent",
+            &path[0]
+        );
+
+        instance.parent().route(path)
+    }
+}
+
+fn bind_super(context: &mut HashMap<Id, State>, superclass: Option<State>) {
+    if let Some(superclass) = superclass {
+        context.insert(SUPER.into(), superclass);
     }
 }
 
@@ -129,7 +240,11 @@ async fn call_method<T: Instance + Route>(
     path: &[PathSegment],
     context: Map<State>,
     form: Vec<(Id, Scalar)>,
-) -> TCResult<State> {
+    plan: Option<Arc<Plan>>,
+) -> TCResult<State>
+where
+    InstanceExt<T>: Route,
+{
     debug!(
         "call method with form {:?}",
         form.iter()
@@ -148,7 +263,9 @@ async fn call_method<T: Instance + Route>(
         return Ok(State::default());
     };
 
-    Executor::with_context(txn, subject, context.into(), form)
-        .capture(capture)
-        .await
+    let executor = Executor::with_context(txn, subject, context, form);
+    match plan {
+        Some(plan) => executor.execute(&plan).await,
+        None => executor.capture(capture).await,
+    }
 }
@@ -1,9 +1,13 @@
+use safecast::TryCastFrom;
+
+use tc_error::*;
 use tcgeneric::PathSegment;
 
-use crate::object::{InstanceClass, InstanceExt, Object};
+use crate::object::{InstanceClass, InstanceExt, Object, IMPLEMENTS};
+use crate::scalar::{Link, Value};
 use crate::state::State;
 
-use super::{GetHandler, Handler, Route};
+use super::{DeleteHandler, GetHandler, Handler, PostHandler, PutHandler, Route};
 
 mod instance;
 
@@ -24,21 +28,178 @@ impl<'a> Handler<'a> for ClassHandler<'a> {
     }
 }
 
+/// Check whether a class, or an instance of it, claims to implement the interface given as
+/// the key of a GET request.
+struct ImplementsHandler {
+    class: InstanceClass,
+}
+
+impl<'a> Handler<'a> for ImplementsHandler {
+    fn get(self: Box<Self>) -> Option<GetHandler<'a>> {
+        Some(Box::new(|_txn, key| {
+            Box::pin(async move {
+                let interface = Link::try_cast_from(key, |v| {
+                    TCError::bad_request("expected a link to an interface, not", v)
+                })?;
+
+                self.class
+                    .implements(&interface)
+                    .map(Value::from)
+                    .map(State::from)
+            })
+        }))
+    }
+}
+
+impl From<InstanceClass> for ImplementsHandler {
+    fn from(class: InstanceClass) -> Self {
+        Self { class }
+    }
+}
+
 impl Route for InstanceClass {
     fn route<'a>(&'a self, path: &'a [PathSegment]) -> Option<Box<dyn Handler<'a> + 'a>> {
         if path.is_empty() {
             Some(Box::new(ClassHandler { class: self }))
+        } else if path.len() == 1 && path[0] == IMPLEMENTS {
+            Some(Box::new(ImplementsHandler::from(self.clone())))
         } else {
             None
         }
     }
 }
 
+struct InstanceHandler<'a> {
+    instance: &'a InstanceExt<State>,
+    parent: Option<Box<dyn Handler<'a> + 'a>>,
+}
+
+impl<'a> Handler<'a> for InstanceHandler<'a> {
+    fn get(self: Box<Self>) -> Option<GetHandler<'a>> {
+        let instance = self.instance;
+        let parent = self.parent.and_then(|handler| handler.get());
+
+        Some(Box::new(move |txn, key| {
+            Box::pin(async move {
+                if key.is_none() {
+                    // return the instance itself, so that `$self` keeps its class
+                    Ok(State::Object(instance.clone().into()))
+                } else if let Some(get_handler) = parent {
+                    get_handler(txn, key).await
+                } else {
+                    Err(TCError::method_not_allowed(instance))
+                }
+            })
+        }))
+    }
+
+    fn put(self: Box<Self>) -> Option<PutHandler<'a>> {
+        self.parent.and_then(|handler| handler.put())
+    }
+
+    fn post(self: Box<Self>) -> Option<PostHandler<'a>> {
+        self.parent.and_then(|handler| handler.post())
+    }
+
+    fn delete(self: Box<Self>) -> Option<DeleteHandler<'a>> {
+        self.parent.and_then(|handler| handler.delete())
+    }
+}
+
 impl Route for Object {
     fn route<'a>(&'a self, path: &'a [PathSegment]) -> Option<Box<dyn Handler<'a> + 'a>> {
         match self {
             Self::Class(class) => class.route(path),
+            Self::Instance(instance) if path.is_empty() => Some(Box::new(InstanceHandler {
+                instance,
+                parent: instance.parent().route(path),
+            })),
             Self::Instance(instance) => instance.route(path),
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::convert::TryFrom;
+
+    use tcgeneric::{label, Id, Instance, Map};
+
+    use crate::route::Public;
+    use crate::scalar::{Number, OpDef, Ref, Scalar};
+    use crate::test::TestHost;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_get_instance() -> TCResult<()> {
+        let host = TestHost::new(vec![]).await?;
+        let txn = host.new_txn(false).await?;
+
+        let class = InstanceClass::new(None, Map::default());
+        let key = Value::from(Number::from(1u64));
+        let instance = match Object::Class(class.clone()).get(&txn, &[], key).await? {
+            State::Object(instance) => instance,
+            other => panic!("expected an instance but found {}", other),
+        };
+
+        match instance.get(&txn, &[], Value::None).await? {
+            State::Object(Object::Instance(instance)) => assert!(instance.class() == class),
+            other => panic!("expected an instance but found {}", other),
+        }
+
+        Ok(())
+    }
+
+    fn returns(value: u64) -> Scalar {
+        let value = Value::from(Number::from(value));
+        OpDef::get()
+            .then(label("result").into(), value)
+            .build()
+            .expect("GET op")
+            .into()
+    }
+
+    fn instance_of(class: InstanceClass) -> Object {
+        Object::Instance(InstanceExt::new(State::default(), class))
+    }
+
+    #[tokio::test]
+    async fn test_inherited_methods() -> TCResult<()> {
+        let host = TestHost::new(vec![]).await?;
+        let txn = host.new_txn(false).await?;
+
+        let mut proto = Map::default();
+        proto.insert(label("f").into(), returns(1));
+        proto.insert(label("g").into(), returns(2));
+        let superclass = InstanceClass::new(None, proto);
+
+        let call_super = OpDef::get()
+            .then(
+                label("result").into(),
+                Ref::path("$super/g")?.get(Value::None),
+            )
+            .build()?;
+
+        let mut proto = Map::default();
+        proto.insert(label("f").into(), returns(3));
+        proto.insert(label("h").into(), call_super.into());
+        let class = InstanceClass::new(Some("/test/superclass".parse()?), proto)
+            .with_superclass(superclass);
+
+        let instance = instance_of(class);
+        let call = |name: &'static str| {
+            let path: [Id; 1] = [label(name).into()];
+            let instance = instance.clone();
+            let txn = txn.clone();
+            async move { instance.get(&txn, &path, Value::None).await }
+        };
+
+        // the subclass overrides `f`, inherits `g`, and calls its superclass's `g` from `h`
+        assert!(Value::try_from(call("f").await?)? == Value::from(Number::from(3u64)));
+        assert!(Value::try_from(call("g").await?)? == Value::from(Number::from(2u64)));
+        assert!(Value::try_from(call("h").await?)? == Value::from(Number::from(2u64)));
+
+        Ok(())
+    }
+}
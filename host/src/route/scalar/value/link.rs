@@ -0,0 +1,212 @@
+use std::convert::TryFrom;
+use std::str::FromStr;
+
+use tc_error::*;
+use tc_value::{Link, Value};
+use tcgeneric::{Id, Map, PathSegment, TCPathBuf};
+
+use crate::route::{Handler, PostHandler, Route};
+use crate::state::State;
+
+impl Route for Link {
+    fn route<'a>(&'a self, path: &'a [PathSegment]) -> Option<Box<dyn Handler<'a> + 'a>> {
+        if path.len() != 1 {
+            return None;
+        }
+
+        match path[0].as_str() {
+            "append" => Some(handler! {
+                get(_txn, suffix: Value) => {
+                    let suffix = match suffix {
+                        Value::Tuple(tuple) => tuple.into_inner(),
+                        segment => vec![segment],
+                    };
+
+                    let mut path = self.path().clone().into_vec();
+                    for segment in suffix {
+                        path.push(segment_of(segment)?);
+                    }
+
+                    Ok(Value::Link(with_path(self, path.into())).into())
+                }
+            }),
+            "resolve" => Some(handler! {
+                get(_txn, relative: Value) => {
+                    let relative = match relative {
+                        Value::String(relative) => relative,
+                        other => {
+                            return Err(TCError::bad_request(
+                                "Link::resolve expects a relative path, not",
+                                other,
+                            ))
+                        }
+                    };
+
+                    let path = resolve(self.path(), &relative)?;
+                    Ok(Value::Link(with_path(self, path)).into())
+                }
+            }),
+            _ => None,
+        }
+    }
+}
+
+/// Handles `POST <template>/format_link`, where the template is a string like
+/// `http://127.0.0.1:8702/app/{user_id}/profile` and each `{name}` is replaced by the parameter
+/// of the same name. Every parameter must replace exactly one whole path segment, so that a
+/// parameter value can't change the structure of the resulting [`Link`].
+pub struct TemplateHandler<'a> {
+    template: &'a str,
+}
+
+impl<'a> TemplateHandler<'a> {
+    pub fn new(template: &'a str) -> Self {
+        Self { template }
+    }
+}
+
+impl<'a> Handler<'a> for TemplateHandler<'a> {
+    fn post(self: Box<Self>) -> Option<PostHandler<'a>> {
+        Some(Box::new(|_txn, params| {
+            Box::pin(async move {
+                let link = format_link(self.template, params)?;
+                Ok(Value::Link(link).into())
+            })
+        }))
+    }
+}
+
+fn format_link(template: &str, mut params: Map<State>) -> TCResult<Link> {
+    let mut formatted = Vec::new();
+    for segment in template.split('/') {
+        if segment.starts_with('{') && segment.ends_with('}') && segment.len() > 2 {
+            let name = Id::from_str(&segment[1..segment.len() - 1])?;
+            let value = params.remove(&name).ok_or_else(|| {
+                TCError::bad_request("missing a value for the link template parameter", &name)
+            })?;
+
+            let value = segment_of(Value::try_from(value)?)?;
+            formatted.push(value.to_string());
+        } else if segment.contains('{') || segment.contains('}') {
+            return Err(TCError::bad_request(
+                "a link template parameter must be a whole path segment, not",
+                segment,
+            ));
+        } else {
+            formatted.push(segment.to_string());
+        }
+    }
+
+    if let Some(name) = params.keys().next() {
+        return Err(TCError::bad_request("link template has no parameter", name));
+    }
+
+    Link::from_str(&formatted.join("/"))
+}
+
+// resolve a relative path like "../users/1" against `base`, the same way a URL is resolved
+// against the path of a directory: "." is ignored, ".." refers to the parent, and a path
+// which starts with "/" replaces the base path entirely
+fn resolve(base: &TCPathBuf, relative: &str) -> TCResult<TCPathBuf> {
+    let (mut path, relative) = if let Some(absolute) = relative.strip_prefix('/') {
+        (vec![], absolute)
+    } else {
+        (base.clone().into_vec(), relative)
+    };
+
+    for segment in relative.split('/') {
+        match segment {
+            "" | "." => {}
+            ".." => {
+                if path.pop().is_none() {
+                    return Err(TCError::bad_request(
+                        "relative path refers to a parent of the root",
+                        relative,
+                    ));
+                }
+            }
+            segment => path.push(segment.parse()?),
+        }
+    }
+
+    Ok(path.into())
+}
+
+fn with_path(link: &Link, path: TCPathBuf) -> Link {
+    match link.host() {
+        Some(host) => (host.clone(), path).into(),
+        None => path.into(),
+    }
+}
+
+// a path segment may be given as a string, or as a number like a row ID
+fn segment_of(segment: Value) -> TCResult<PathSegment> {
+    match segment {
+        Value::String(s) => s.parse(),
+        Value::Number(n) => n.to_string().parse(),
+        other => Err(TCError::bad_request(
+            "expected a path segment but found",
+            other,
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tc_value::Number;
+    use tcgeneric::label;
+
+    use super::*;
+
+    fn path(path: &str) -> TCPathBuf {
+        path.parse().expect("path")
+    }
+
+    #[test]
+    fn test_format_link() {
+        let template = "http://127.0.0.1:8702/app/{user_id}/profile";
+
+        let mut params = Map::default();
+        params.insert(
+            label("user_id").into(),
+            Value::from(Number::from(42u64)).into(),
+        );
+        let link = format_link(template, params).unwrap();
+        assert_eq!(link.to_string(), "http://127.0.0.1:8702/app/42/profile");
+
+        assert!(format_link(template, Map::default()).is_err());
+
+        let mut params = Map::default();
+        params.insert(label("user_id").into(), Value::String("a/b".into()).into());
+        assert!(format_link(template, params).is_err());
+
+        let mut params = Map::default();
+        params.insert(label("user_id").into(), Value::String("1".into()).into());
+        params.insert(label("other").into(), Value::String("2".into()).into());
+        assert!(format_link(template, params).is_err());
+
+        let mut params = Map::default();
+        params.insert(label("id").into(), Value::String("1".into()).into());
+        assert!(format_link("/app/user_{id}", params).is_err());
+    }
+
+    #[test]
+    fn test_resolve() {
+        let base = path("/app/users/1");
+
+        assert_eq!(resolve(&base, "../2").unwrap(), path("/app/users/2"));
+        assert_eq!(
+            resolve(&base, "./posts/").unwrap(),
+            path("/app/users/1/posts")
+        );
+        assert_eq!(resolve(&base, "/state").unwrap(), path("/state"));
+        assert!(resolve(&base, "../../../..").is_err());
+    }
+
+    #[test]
+    fn test_with_path() {
+        let link: Link = "http://127.0.0.1:8702/app".parse().unwrap();
+        let link = with_path(&link, path("/app/users"));
+        assert_eq!(link.to_string(), "http://127.0.0.1:8702/app/users");
+    }
+}
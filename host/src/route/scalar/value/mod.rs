@@ -1,32 +1,19 @@
 use tcgeneric::PathSegment;
 
-use crate::route::{GetHandler, Handler, Route};
+use crate::route::{Handler, Route};
 use crate::scalar::Value;
 
+mod link;
 mod number;
-
-struct EqHandler<F> {
-    call: F,
-}
-
-impl<'a, F: FnOnce(Value) -> bool + Send + 'a> Handler<'a> for EqHandler<F> {
-    fn get(self: Box<Self>) -> Option<GetHandler<'a>> {
-        Some(Box::new(|_txn, key| {
-            Box::pin(async move { Ok(Value::from((self.call)(key)).into()) })
-        }))
-    }
-}
-
-impl<F> From<F> for EqHandler<F> {
-    fn from(call: F) -> Self {
-        Self { call }
-    }
-}
+mod string;
+mod time;
 
 impl Route for Value {
     fn route<'a>(&'a self, path: &'a [PathSegment]) -> Option<Box<dyn Handler<'a> + 'a>> {
         let child_handler = match self {
+            Self::Link(link) => link.route(path),
             Self::Number(number) => number.route(path),
+            Self::String(string) => string.route(path),
             Self::Tuple(tuple) => tuple.route(path),
             _ => None,
         };
@@ -35,8 +22,12 @@ impl Route for Value {
             child_handler
         } else if path.len() == 1 {
             match path[0].as_str() {
-                "eq" => Some(Box::new(EqHandler::from(move |other| self == &other))),
-                "ne" => Some(Box::new(EqHandler::from(move |other| self != &other))),
+                "eq" => Some(handler! {
+                    get(_txn, other: Value) => { Ok(Value::from(self == &other).into()) }
+                }),
+                "ne" => Some(handler! {
+                    get(_txn, other: Value) => { Ok(Value::from(self != &other).into()) }
+                }),
                 _ => None,
             }
         } else {
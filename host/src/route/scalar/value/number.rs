@@ -1,4 +1,4 @@
-use safecast::TryCastInto;
+use safecast::{CastFrom, TryCastInto};
 
 use tc_error::*;
 use tc_value::{Number, NumberInstance, Value};
@@ -6,6 +6,8 @@ use tcgeneric::PathSegment;
 
 use crate::route::{GetHandler, Handler, Route};
 
+use super::time::format_time;
+
 struct Dual<'a> {
     handler: GetHandler<'a>,
 }
@@ -71,6 +73,13 @@ impl Route for Number {
             "abs" => Box::new(Unary::from(move || self.abs())),
             "add" => Box::new(Dual::from(move |other| *self + other)),
             "div" => Box::new(Dual::from(move |other| *self / other)),
+            "format_time" => handler! {
+                get(txn, format: Value) => {
+                    let seconds = u64::cast_from(*self);
+                    let formatted = format_time(seconds, txn.locale(), format)?;
+                    Ok(Value::String(formatted.into()).into())
+                }
+            },
             "mul" => Box::new(Dual::from(move |other| *self * other)),
             "sub" => Box::new(Dual::from(move |other| *self - other)),
             "pow" => Box::new(Dual::from(move |other| self.pow(other))),
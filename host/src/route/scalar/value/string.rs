@@ -0,0 +1,245 @@
+use std::str::FromStr;
+
+use safecast::{CastFrom, TryCastFrom};
+
+use tc_error::*;
+use tc_value::{Number, Value};
+use tcgeneric::PathSegment;
+
+use crate::route::{Handler, Route};
+
+use super::link::TemplateHandler;
+
+impl Route for str {
+    fn route<'a>(&'a self, path: &'a [PathSegment]) -> Option<Box<dyn Handler<'a> + 'a>> {
+        if path.len() != 1 {
+            return None;
+        }
+
+        match path[0].as_str() {
+            "format_link" => Some(Box::new(TemplateHandler::new(self))),
+            "parse_float" => Some(handler! {
+                get(_txn, format: Value) => {
+                    let (decimal, group) = float_format(format)?;
+                    let float = parse_float(self, decimal, group)?;
+                    Ok(Value::from(float).into())
+                }
+            }),
+            "parse_int" => Some(handler! {
+                get(_txn, format: Value) => {
+                    let (radix, group) = int_format(format)?;
+                    let int = parse_int(self, radix, group)?;
+                    Ok(Value::from(int).into())
+                }
+            }),
+            _ => None,
+        }
+    }
+}
+
+// the key of `parse_int` is `radix` or `(radix, group_separator)`, with a default radix of 10
+fn int_format(format: Value) -> TCResult<(u32, Option<char>)> {
+    let (radix, group) = match format {
+        Value::Tuple(tuple) if tuple.len() == 2 => {
+            let mut tuple = tuple.into_inner().into_iter();
+            (tuple.next().unwrap(), tuple.next().unwrap())
+        }
+        radix => (radix, Value::None),
+    };
+
+    let radix = if radix.is_none() {
+        10
+    } else {
+        let radix = Number::try_cast_from(radix, |v| TCError::bad_request("invalid radix", v))?;
+        u64::cast_from(radix)
+    };
+
+    if !(2..=36).contains(&radix) {
+        return Err(TCError::bad_request(
+            "radix must be between 2 and 36, not",
+            radix,
+        ));
+    }
+
+    Ok((radix as u32, separator(group)?))
+}
+
+// the key of `parse_float` is `decimal_separator` or `(decimal_separator, group_separator)`,
+// so that a string like "1.234,5" can be parsed with the key `(",", ".")`
+fn float_format(format: Value) -> TCResult<(char, Option<char>)> {
+    let (decimal, group) = match format {
+        Value::Tuple(tuple) if tuple.len() == 2 => {
+            let mut tuple = tuple.into_inner().into_iter();
+            (tuple.next().unwrap(), tuple.next().unwrap())
+        }
+        decimal => (decimal, Value::None),
+    };
+
+    let decimal = separator(decimal)?.unwrap_or('.');
+    let group = separator(group)?;
+
+    if Some(decimal) == group {
+        return Err(TCError::bad_request(
+            "the decimal and group separators must be different, but both are",
+            decimal,
+        ));
+    }
+
+    Ok((decimal, group))
+}
+
+fn separator(separator: Value) -> TCResult<Option<char>> {
+    match separator {
+        Value::None => Ok(None),
+        Value::String(s) => {
+            let mut chars = s.chars();
+            match (chars.next(), chars.next()) {
+                (Some(c), None) if !c.is_alphanumeric() && c != '-' && c != '+' => Ok(Some(c)),
+                _ => Err(TCError::bad_request(
+                    "a separator must be a single punctuation or space character, not",
+                    s,
+                )),
+            }
+        }
+        other => Err(TCError::bad_request(
+            "a separator must be a string, not",
+            other,
+        )),
+    }
+}
+
+/// Parse an integer in the given `radix`, with an optional sign and an optional `0x`, `0o`, or
+/// `0b` prefix matching the radix, ignoring any `group` separators between digits.
+fn parse_int(s: &str, radix: u32, group: Option<char>) -> TCResult<Number> {
+    let invalid = || {
+        TCError::bad_request(
+            format!("cannot parse an integer in base {} from", radix),
+            format!("\"{}\"", s),
+        )
+    };
+
+    let trimmed = s.trim();
+    let (negative, unsigned) = if let Some(unsigned) = trimmed.strip_prefix('-') {
+        (true, unsigned)
+    } else {
+        (false, trimmed.strip_prefix('+').unwrap_or(trimmed))
+    };
+
+    let prefix = match radix {
+        2 => Some("0b"),
+        8 => Some("0o"),
+        16 => Some("0x"),
+        _ => None,
+    };
+
+    let unsigned = match prefix {
+        Some(prefix) if unsigned.to_lowercase().starts_with(prefix) => &unsigned[prefix.len()..],
+        _ => unsigned,
+    };
+
+    let digits = strip_groups(unsigned, group).ok_or_else(invalid)?;
+
+    if negative {
+        i64::from_str_radix(&format!("-{}", digits), radix)
+            .map(Number::from)
+            .map_err(|_| invalid())
+    } else if let Ok(i) = i64::from_str_radix(&digits, radix) {
+        Ok(Number::from(i))
+    } else {
+        u64::from_str_radix(&digits, radix)
+            .map(Number::from)
+            .map_err(|_| invalid())
+    }
+}
+
+/// Parse a finite floating-point number, optionally in scientific notation like `1.5e-3`, with
+/// the given `decimal` separator, ignoring any `group` separators between digits.
+fn parse_float(s: &str, decimal: char, group: Option<char>) -> TCResult<Number> {
+    let invalid = || TCError::bad_request("cannot parse a number from", format!("\"{}\"", s));
+
+    let trimmed = s.trim();
+    let (mantissa, exponent) = match trimmed.find(['e', 'E']) {
+        Some(i) => (&trimmed[..i], &trimmed[i..]),
+        None => (trimmed, ""),
+    };
+
+    let mantissa = strip_groups(mantissa, group).ok_or_else(invalid)?;
+    if mantissa.matches(decimal).count() > 1 {
+        return Err(invalid());
+    }
+
+    let normalized = format!("{}{}", mantissa.replace(decimal, "."), exponent);
+    let float = f64::from_str(&normalized).map_err(|_| invalid())?;
+    if float.is_finite() {
+        Ok(Number::from(float))
+    } else {
+        Err(TCError::bad_request(
+            "cannot parse a finite number from",
+            format!("\"{}\"", s),
+        ))
+    }
+}
+
+// remove the given group separator, which may only appear between two digits
+fn strip_groups(s: &str, group: Option<char>) -> Option<String> {
+    let group = match group {
+        Some(group) => group,
+        None => return Some(s.to_string()),
+    };
+
+    let chars: Vec<char> = s.chars().collect();
+    let mut stripped = String::with_capacity(s.len());
+    for (i, c) in chars.iter().enumerate() {
+        if *c == group {
+            let before = i > 0 && chars[i - 1].is_ascii_alphanumeric();
+            let after = i + 1 < chars.len() && chars[i + 1].is_ascii_alphanumeric();
+            if !before || !after {
+                return None;
+            }
+        } else {
+            stripped.push(*c);
+        }
+    }
+
+    Some(stripped)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_int() {
+        assert!(parse_int("1,234", 10, Some(',')).unwrap() == Number::from(1234i64));
+        assert!(parse_int(" -0xFF ", 16, None).unwrap() == Number::from(-255i64));
+        assert!(parse_int("0b1010", 2, None).unwrap() == Number::from(10i64));
+        assert!(parse_int(&u64::MAX.to_string(), 10, None).unwrap() == Number::from(u64::MAX));
+
+        assert!(parse_int("12", 2, None).is_err());
+        assert!(parse_int("1,,234", 10, Some(',')).is_err());
+        assert!(parse_int(",1234", 10, Some(',')).is_err());
+        assert!(parse_int("--1", 10, None).is_err());
+    }
+
+    #[test]
+    fn test_parse_float() {
+        assert!(parse_float("1.234,5", ',', Some('.')).unwrap() == Number::from(1234.5f64));
+        assert!(parse_float("1.5e-3", '.', None).unwrap() == Number::from(0.0015f64));
+        assert!(parse_float("1 000.25", '.', Some(' ')).unwrap() == Number::from(1000.25f64));
+
+        assert!(parse_float("1.2.3", '.', None).is_err());
+        assert!(parse_float("1e999", '.', None).is_err());
+        assert!(parse_float("NaN", '.', None).is_err());
+    }
+
+    #[test]
+    fn test_format() {
+        assert!(int_format(Value::None).unwrap() == (10, None));
+        assert!(int_format(Value::from(Number::from(37u64))).is_err());
+        assert!(float_format(Value::String(",".into())).unwrap() == (',', None));
+
+        let same = Value::Tuple(vec![Value::String(".".into()), Value::String(".".into())].into());
+        assert!(float_format(same).is_err());
+        assert!(separator(Value::String("ab".into())).is_err());
+    }
+}
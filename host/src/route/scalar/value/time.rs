@@ -0,0 +1,114 @@
+use tc_error::*;
+use tc_value::Value;
+
+use crate::txn::Locale;
+
+/// Format a Unix timestamp, in seconds, in the timezone of the given [`Locale`].
+///
+/// The `format` is one of "datetime" (the default, an RFC 3339 timestamp like
+/// `2021-03-04T05:06:07+05:30`), "date" (like `2021-03-04`), or "time" (like `05:06:07`).
+pub fn format_time(seconds: u64, locale: &Locale, format: Value) -> TCResult<String> {
+    let format = match format {
+        Value::None => "datetime".to_string(),
+        Value::String(format) => format.to_string(),
+        other => {
+            return Err(TCError::bad_request(
+                "expected a time format (\"datetime\", \"date\", or \"time\") but found",
+                other,
+            ))
+        }
+    };
+
+    let local = seconds as i64 + locale.utc_offset() as i64;
+    let days = local.div_euclid(86400);
+    let secs = local.rem_euclid(86400);
+
+    let (year, month, day) = civil_from_days(days);
+    let date = format!("{:04}-{:02}-{:02}", year, month, day);
+    let time = format!(
+        "{:02}:{:02}:{:02}",
+        secs / 3600,
+        (secs % 3600) / 60,
+        secs % 60
+    );
+
+    match format.as_str() {
+        "date" => Ok(date),
+        "time" => Ok(time),
+        "datetime" => Ok(format!(
+            "{}T{}{}",
+            date,
+            time,
+            utc_offset(locale.utc_offset())
+        )),
+        _ => Err(TCError::bad_request(
+            "expected a time format (\"datetime\", \"date\", or \"time\") but found",
+            format,
+        )),
+    }
+}
+
+fn utc_offset(offset: i32) -> String {
+    if offset == 0 {
+        return "Z".to_string();
+    }
+
+    let sign = if offset < 0 { '-' } else { '+' };
+    let offset = offset.abs();
+    format!("{}{:02}:{:02}", sign, offset / 3600, (offset % 3600) / 60)
+}
+
+// convert a number of days since 1970-01-01 into a (year, month, day) in the proleptic
+// Gregorian calendar, cf. http://howardhinnant.github.io/date_algorithms.html#civil_from_days
+fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TIMESTAMP: u64 = 1614834367;
+
+    #[test]
+    fn test_format_time() {
+        let utc = Locale::default();
+        assert_eq!(
+            format_time(TIMESTAMP, &utc, Value::None).unwrap(),
+            "2021-03-04T05:06:07Z"
+        );
+
+        let locale = Locale::parse(None, Some("-08:00")).unwrap();
+        let format = |format: &str| Value::String(format.into());
+        assert_eq!(
+            format_time(TIMESTAMP, &locale, format("datetime")).unwrap(),
+            "2021-03-03T21:06:07-08:00"
+        );
+        assert_eq!(
+            format_time(TIMESTAMP, &locale, format("date")).unwrap(),
+            "2021-03-03"
+        );
+        assert_eq!(
+            format_time(TIMESTAMP, &locale, format("time")).unwrap(),
+            "21:06:07"
+        );
+
+        assert!(format_time(TIMESTAMP, &locale, format("week")).is_err());
+    }
+
+    #[test]
+    fn test_civil_from_days() {
+        assert_eq!(civil_from_days(0), (1970, 1, 1));
+        assert_eq!(civil_from_days(-1), (1969, 12, 31));
+        assert_eq!(civil_from_days(11016), (2000, 2, 29));
+    }
+}
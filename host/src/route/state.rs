@@ -1,12 +1,15 @@
+use bytes::Bytes;
+
 use tc_error::*;
 use tcgeneric::{path_label, Instance, NativeClass, PathLabel, PathSegment};
 
-use crate::scalar::Link;
+use crate::scalar::{Link, Value};
 use crate::state::State;
 
 use super::*;
 
 const CLASS: PathLabel = path_label(&["class"]);
+const HASH: PathLabel = path_label(&["hash"]);
 
 struct SelfHandler<'a> {
     subject: &'a State,
@@ -38,10 +41,30 @@ impl<'a> Handler<'a> for ClassHandler<'a> {
     }
 }
 
+struct HashHandler<'a> {
+    subject: &'a State,
+}
+
+impl<'a> Handler<'a> for HashHandler<'a> {
+    fn get(self: Box<Self>) -> Option<GetHandler<'a>> {
+        Some(Box::new(|txn, key| {
+            Box::pin(async move {
+                if key.is_some() {
+                    return Err(TCError::bad_request("hash takes no key, but found", key));
+                }
+
+                let hash = self.subject.clone().hash(txn).await?;
+                Ok(Value::from(Bytes::copy_from_slice(hash.as_bytes())).into())
+            })
+        }))
+    }
+}
+
 impl Route for State {
     fn route<'a>(&'a self, path: &'a [PathSegment]) -> Option<Box<dyn Handler<'a> + 'a>> {
         let child_handler = match self {
             Self::Chain(chain) => chain.route(path),
+            Self::Collection(collection) => collection.route(path),
             Self::Map(map) => map.route(path),
             Self::Object(object) => object.route(path),
             Self::Scalar(scalar) => scalar.route(path),
@@ -56,6 +79,8 @@ impl Route for State {
             Some(Box::new(SelfHandler { subject: self }))
         } else if path == &CLASS[..] {
             Some(Box::new(ClassHandler { subject: self }))
+        } else if path == &HASH[..] {
+            Some(Box::new(HashHandler { subject: self }))
         } else {
             None
         }
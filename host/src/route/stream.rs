@@ -0,0 +1,328 @@
+//! Paginated responses, so that a client reads every kind of collection the same way.
+
+use async_trait::async_trait;
+use bytes::Bytes;
+use safecast::{CastFrom, TryCastFrom};
+
+use tc_error::*;
+use tc_transact::{Transaction, TxnId};
+use tcgeneric::{label, Instance, Label, Map, NativeClass};
+
+use crate::scalar::{Number, Value};
+use crate::state::State;
+
+use super::{GetHandler, Handler};
+
+/// The number of elements in a page, if the client doesn't specify a limit.
+pub const DEFAULT_PAGE_SIZE: usize = 1_000;
+
+/// The maximum number of elements in a page.
+pub const MAX_PAGE_SIZE: usize = 10_000;
+
+const ELEMENTS: Label = label("elements");
+const NEXT: Label = label("next");
+const SIZE: Label = label("size");
+
+/// A state whose elements can be read one page at a time.
+#[async_trait]
+pub trait StreamResponse: Send + Sync {
+    /// The total number of elements in this state.
+    async fn size_hint(&self, txn_id: &TxnId) -> TCResult<u64>;
+
+    /// Read up to `limit` elements starting at `offset`, in a stable order.
+    async fn page(&self, txn_id: &TxnId, offset: u64, limit: usize) -> TCResult<Vec<Value>>;
+}
+
+/// Handles `GET <subject>/page`, with a key of `limit` or `(limit, token)`.
+///
+/// The response is a map of the `elements` in the page, the total `size` of the subject, and
+/// a continuation token `next` to pass in order to read the next page, or `None` if this is the
+/// last page. The token is opaque to the client.
+pub struct PageHandler<'a, T> {
+    source: &'a T,
+}
+
+impl<'a, T> PageHandler<'a, T> {
+    pub fn new(source: &'a T) -> Self {
+        Self { source }
+    }
+}
+
+impl<'a, T: StreamResponse> Handler<'a> for PageHandler<'a, T> {
+    fn get(self: Box<Self>) -> Option<GetHandler<'a>> {
+        Some(Box::new(|txn, key| {
+            Box::pin(async move {
+                let (limit, offset) = page_of(key)?;
+
+                let size = self.source.size_hint(txn.id()).await?;
+                let elements = self.source.page(txn.id(), offset, limit).await?;
+
+                let end = offset + elements.len() as u64;
+                let next = if end < size {
+                    Value::from(Number::from(end))
+                } else {
+                    Value::None
+                };
+
+                let mut page = Map::default();
+                page.insert(ELEMENTS.into(), Value::Tuple(elements.into()).into());
+                page.insert(NEXT.into(), next.into());
+                page.insert(SIZE.into(), Value::from(Number::from(size)).into());
+                Ok(State::Map(page))
+            })
+        }))
+    }
+}
+
+/// Handles `GET <subject>/checksum`, which reads every element of the subject in order, one page
+/// at a time, and returns the Blake3 digest of their contents.
+///
+/// Two replicas of a collection have the same checksum if and only if they have the same
+/// elements in the same order, so replicas can be compared without transferring their contents.
+pub struct ChecksumHandler<'a, T> {
+    source: &'a T,
+}
+
+impl<'a, T> ChecksumHandler<'a, T> {
+    pub fn new(source: &'a T) -> Self {
+        Self { source }
+    }
+}
+
+impl<'a, T: StreamResponse> Handler<'a> for ChecksumHandler<'a, T> {
+    fn get(self: Box<Self>) -> Option<GetHandler<'a>> {
+        Some(Box::new(|txn, key| {
+            Box::pin(async move {
+                if key.is_some() {
+                    return Err(TCError::bad_request(
+                        "checksum takes no key, but found",
+                        key,
+                    ));
+                }
+
+                let size = self.source.size_hint(txn.id()).await?;
+
+                let mut hasher = blake3::Hasher::new();
+                hasher.update(&size.to_be_bytes());
+
+                let mut offset = 0;
+                while offset < size {
+                    let page = self.source.page(txn.id(), offset, MAX_PAGE_SIZE).await?;
+                    if page.is_empty() {
+                        break;
+                    }
+
+                    offset += page.len() as u64;
+                    for element in &page {
+                        digest(&mut hasher, element);
+                    }
+                }
+
+                let checksum = hasher.finalize();
+                Ok(Value::from(Bytes::copy_from_slice(checksum.as_bytes())).into())
+            })
+        }))
+    }
+}
+
+// feed a canonical, unambiguous encoding of `value` into the `hasher`, which doesn't depend on
+// any encoding options which might differ between hosts
+pub(super) fn digest(hasher: &mut blake3::Hasher, value: &Value) {
+    let mut update = |tag: &[u8], contents: &[u8]| {
+        hasher.update(tag);
+        hasher.update(&(contents.len() as u64).to_be_bytes());
+        hasher.update(contents);
+    };
+
+    match value {
+        Value::Bytes(bytes) => update(b"b", bytes),
+        Value::Link(link) => update(b"l", link.to_string().as_bytes()),
+        Value::None => update(b"n", &[]),
+        Value::Number(n) => {
+            let class = value.class().path().to_string();
+            update(b"c", class.as_bytes());
+            update(b"#", n.to_string().as_bytes());
+        }
+        Value::String(s) => update(b"s", s.as_bytes()),
+        Value::Tuple(tuple) => {
+            update(b"t", &(tuple.len() as u64).to_be_bytes());
+            for element in tuple.iter() {
+                digest(hasher, element);
+            }
+        }
+    }
+}
+
+fn page_of(key: Value) -> TCResult<(usize, u64)> {
+    let (limit, token) = match key {
+        Value::None => (Value::None, Value::None),
+        Value::Tuple(tuple) if tuple.len() == 2 => {
+            let mut tuple = tuple.into_inner().into_iter();
+            (tuple.next().unwrap(), tuple.next().unwrap())
+        }
+        limit => (limit, Value::None),
+    };
+
+    let limit = if limit.is_none() {
+        DEFAULT_PAGE_SIZE
+    } else {
+        let limit = u64::cast_from(Number::try_cast_from(limit, |v| {
+            TCError::bad_request("invalid page size", v)
+        })?);
+
+        if limit == 0 || limit > MAX_PAGE_SIZE as u64 {
+            return Err(TCError::bad_request(
+                format!("page size must be between 1 and {}, not", MAX_PAGE_SIZE),
+                limit,
+            ));
+        }
+
+        limit as usize
+    };
+
+    let offset = if token.is_none() {
+        0
+    } else {
+        u64::cast_from(Number::try_cast_from(token, |v| {
+            TCError::bad_request("invalid continuation token", v)
+        })?)
+    };
+
+    Ok((limit, offset))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::convert::TryFrom;
+    use std::fmt;
+
+    use tcgeneric::PathSegment;
+
+    use crate::route::{Public, Route};
+    use crate::test::TestHost;
+    use crate::txn::Txn;
+
+    use super::*;
+
+    struct Elements(Vec<Value>);
+
+    #[async_trait]
+    impl StreamResponse for Elements {
+        async fn size_hint(&self, _txn_id: &TxnId) -> TCResult<u64> {
+            Ok(self.0.len() as u64)
+        }
+
+        async fn page(&self, _txn_id: &TxnId, offset: u64, limit: usize) -> TCResult<Vec<Value>> {
+            Ok(self
+                .0
+                .iter()
+                .skip(offset as usize)
+                .take(limit)
+                .cloned()
+                .collect())
+        }
+    }
+
+    impl Route for Elements {
+        fn route<'a>(&'a self, path: &'a [PathSegment]) -> Option<Box<dyn Handler<'a> + 'a>> {
+            if path.len() != 1 {
+                return None;
+            }
+
+            match path[0].as_str() {
+                "checksum" => Some(Box::new(ChecksumHandler::new(self))),
+                "page" => Some(Box::new(PageHandler::new(self))),
+                _ => None,
+            }
+        }
+    }
+
+    impl fmt::Display for Elements {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            f.write_str("a list of elements")
+        }
+    }
+
+    fn uint(n: u64) -> Value {
+        Value::from(Number::from(n))
+    }
+
+    fn field(page: &mut Map<State>, name: Label) -> TCResult<Value> {
+        let field = page.remove(&name.into()).expect("page field");
+        Value::try_from(field)
+    }
+
+    #[tokio::test]
+    async fn test_page() -> TCResult<()> {
+        let host = TestHost::new(vec![]).await?;
+        let txn = host.new_txn(false).await?;
+
+        let elements = Elements((0..5).map(uint).collect());
+        let path = [label("page").into()];
+
+        let mut token = Value::None;
+        let mut read = Vec::new();
+        loop {
+            let key = Value::Tuple(vec![uint(2), token].into());
+            let mut page = match elements.get(&txn, &path, key).await? {
+                State::Map(page) => page,
+                other => panic!("expected a page but found {}", other),
+            };
+
+            assert!(field(&mut page, SIZE)? == uint(5));
+
+            match field(&mut page, ELEMENTS)? {
+                Value::Tuple(page) => read.extend(page.into_inner()),
+                other => panic!("expected a tuple but found {}", other),
+            }
+
+            token = field(&mut page, NEXT)?;
+            if token.is_none() {
+                break;
+            }
+        }
+
+        assert!(read == elements.0);
+
+        for limit in &[0, MAX_PAGE_SIZE as u64 + 1] {
+            let err = elements.get(&txn, &path, uint(*limit)).await;
+            assert!(err.map(|_| ()).unwrap_err().code() == ErrorType::BadRequest);
+        }
+
+        Ok(())
+    }
+
+    async fn checksum(txn: &Txn, elements: Vec<Value>) -> TCResult<Value> {
+        let path = [label("checksum").into()];
+        let checksum = Elements(elements).get(txn, &path, Value::None).await?;
+        Value::try_from(checksum)
+    }
+
+    #[tokio::test]
+    async fn test_checksum() -> TCResult<()> {
+        let host = TestHost::new(vec![]).await?;
+        let txn = host.new_txn(false).await?;
+
+        let string = |s: &str| Value::String(s.into());
+        let nested = Value::Tuple(vec![uint(1), uint(2)].into());
+
+        let expected = checksum(&txn, vec![uint(1), string("two"), nested.clone()]).await?;
+        let actual = checksum(&txn, vec![uint(1), string("two"), nested.clone()]).await?;
+        assert!(actual == expected);
+
+        let reordered = checksum(&txn, vec![string("two"), uint(1), nested.clone()]).await?;
+        assert!(reordered != expected);
+
+        let flattened = checksum(&txn, vec![uint(1), string("two"), uint(1), uint(2)]).await?;
+        assert!(flattened != expected);
+
+        let retyped = checksum(&txn, vec![string("1"), string("two"), nested]).await?;
+        assert!(retyped != expected);
+
+        let path = [label("checksum").into()];
+        let err = Elements(vec![]).get(&txn, &path, uint(1)).await;
+        assert!(err.map(|_| ()).unwrap_err().code() == ErrorType::BadRequest);
+
+        Ok(())
+    }
+}
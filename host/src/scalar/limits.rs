@@ -0,0 +1,159 @@
+//! Limits and options for decoding a [`Scalar`] or [`crate::state::State`].
+
+use std::fmt;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+use destream::de;
+
+/// The default maximum nesting depth of a decoded value.
+pub const DEFAULT_MAX_DEPTH: usize = 64;
+
+/// The default maximum number of elements in a decoded value, counting every nested element.
+pub const DEFAULT_MAX_ELEMENTS: usize = 1_000_000;
+
+static MAX_DEPTH: AtomicUsize = AtomicUsize::new(DEFAULT_MAX_DEPTH);
+static MAX_ELEMENTS: AtomicUsize = AtomicUsize::new(DEFAULT_MAX_ELEMENTS);
+
+/// Limits on decoding a [`Scalar`] or [`crate::state::State`], to prevent a deeply nested or very
+/// large payload from exhausting the stack or the memory of this host.
+///
+/// Nesting is counted across maps, tuples, and type-annotated maps and tuples; the body of an
+/// op definition or reference is decoded as a new top-level value.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct DecodeLimits {
+    pub max_depth: usize,
+    pub max_elements: usize,
+}
+
+impl DecodeLimits {
+    /// Return the limits currently configured for this process.
+    pub fn current() -> Self {
+        Self {
+            max_depth: MAX_DEPTH.load(Ordering::Relaxed),
+            max_elements: MAX_ELEMENTS.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Set the limits used to decode every subsequent request to this process.
+    pub fn configure(self) {
+        MAX_DEPTH.store(self.max_depth, Ordering::Relaxed);
+        MAX_ELEMENTS.store(self.max_elements, Ordering::Relaxed);
+    }
+}
+
+impl Default for DecodeLimits {
+    fn default() -> Self {
+        Self {
+            max_depth: DEFAULT_MAX_DEPTH,
+            max_elements: DEFAULT_MAX_ELEMENTS,
+        }
+    }
+}
+
+/// The error returned when a decoded value exceeds its [`DecodeLimits`].
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum DecodeLimitError {
+    TooDeep(usize),
+    TooManyElements(usize),
+}
+
+impl std::error::Error for DecodeLimitError {}
+
+impl fmt::Display for DecodeLimitError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::TooDeep(max) => write!(f, "value exceeds the maximum nesting depth of {}", max),
+            Self::TooManyElements(max) => {
+                write!(f, "value exceeds the maximum of {} elements", max)
+            }
+        }
+    }
+}
+
+/// The position of a value being decoded, relative to the [`DecodeLimits`] of its top-level value.
+#[derive(Clone)]
+pub struct DecodeContext {
+    limits: DecodeLimits,
+    strict: bool,
+    depth: usize,
+    elements: Arc<AtomicUsize>,
+}
+
+impl DecodeContext {
+    /// Construct a context for decoding a new top-level value.
+    pub fn new(limits: DecodeLimits) -> Self {
+        Self {
+            limits,
+            strict: false,
+            depth: 0,
+            elements: Arc::new(AtomicUsize::new(0)),
+        }
+    }
+
+    /// Enable or disable strict decoding.
+    ///
+    /// In strict mode, a map with a single key which parses as a [`crate::scalar::Link`] or
+    /// reference is an error, rather than an implicit op or reference: refs and ops must be
+    /// annotated with an explicit class, like `{"/state/scalar/ref/op/get": [...]}`.
+    pub fn with_strict(mut self, strict: bool) -> Self {
+        self.strict = strict;
+        self
+    }
+
+    /// Return `true` if this context requires explicit class annotations for refs and ops.
+    pub fn is_strict(&self) -> bool {
+        self.strict
+    }
+
+    /// The [`DecodeLimits`] of the top-level value.
+    pub fn limits(&self) -> &DecodeLimits {
+        &self.limits
+    }
+
+    /// Return an error if `key` would be decoded as an implicit op or reference in strict mode.
+    pub fn check_subject<E: de::Error>(&self, key: &str) -> Result<(), E> {
+        if self.strict {
+            Err(de::Error::custom(format!(
+                "strict decoding requires an explicit class annotation for {}",
+                key
+            )))
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Return the context of a value nested one level below this one.
+    pub fn nested<E: de::Error>(&self) -> Result<Self, E> {
+        if self.depth >= self.limits.max_depth {
+            Err(de::Error::custom(DecodeLimitError::TooDeep(
+                self.limits.max_depth,
+            )))
+        } else {
+            Ok(Self {
+                limits: self.limits,
+                strict: self.strict,
+                depth: self.depth + 1,
+                elements: self.elements.clone(),
+            })
+        }
+    }
+
+    /// Count one more element toward the total size of the top-level value.
+    pub fn count<E: de::Error>(&self) -> Result<(), E> {
+        let count = self.elements.fetch_add(1, Ordering::Relaxed) + 1;
+        if count > self.limits.max_elements {
+            Err(de::Error::custom(DecodeLimitError::TooManyElements(
+                self.limits.max_elements,
+            )))
+        } else {
+            Ok(())
+        }
+    }
+}
+
+impl Default for DecodeContext {
+    fn default() -> Self {
+        Self::new(DecodeLimits::current())
+    }
+}
@@ -9,27 +9,38 @@ use std::str::FromStr;
 
 use async_trait::async_trait;
 use destream::de::{self, Decoder, FromStream};
-use destream::en::{Encoder, IntoStream, ToStream};
-use futures::future::{try_join_all, TryFutureExt};
+use destream::en::{EncodeMap, Encoder, IntoStream, ToStream};
+use futures::future::TryFutureExt;
+use futures::stream::{self, StreamExt, TryStreamExt};
 use log::debug;
 use safecast::{Match, TryCastFrom, TryCastInto};
 
 use tc_error::*;
 use tcgeneric::*;
 
+use crate::concurrency::Concurrency;
 use crate::route::Public;
 use crate::state::State;
 use crate::txn::Txn;
 
+mod limits;
+
 pub mod op;
 pub mod reference;
 
+pub use limits::*;
 pub use op::*;
 pub use reference::*;
 pub use tc_value::*;
 
 const PREFIX: PathLabel = path_label(&["state", "scalar"]);
 pub const SELF: Label = label("self");
+pub const SUPER: Label = label("super");
+
+/// The prefix of an escaped map key, e.g. `"\\$name"` to store the literal key `"$name"`
+/// rather than a reference to `$name`. To store a literal key which begins with a backslash,
+/// escape it with a second backslash.
+pub const ESCAPE: char = '\\';
 
 /// The [`Class`] of a [`Scalar`].
 #[derive(Clone, Copy, Debug, Eq, PartialEq)]
@@ -105,6 +116,44 @@ pub enum Scalar {
 }
 
 impl Scalar {
+    /// Escape an arbitrary map key, like `"$name"` or `"/path/to/data"`, as a valid [`Id`].
+    ///
+    /// The escaped [`Id`] begins with [`ESCAPE`] and is encoded on the wire as [`ESCAPE`]
+    /// followed by the original `key`.
+    pub fn escape_key(key: &str) -> Id {
+        let mut escaped = String::with_capacity(key.len() + 1);
+        escaped.push(ESCAPE);
+
+        for byte in key.bytes() {
+            if byte.is_ascii_alphanumeric() || byte == b'_' || byte == b'-' {
+                escaped.push(byte as char);
+            } else {
+                escaped.push_str(&format!("%{:02X}", byte));
+            }
+        }
+
+        escaped.parse().expect("escaped map key")
+    }
+
+    /// Return the original map key of an [`Id`] constructed by [`Scalar::escape_key`], if any.
+    pub fn unescape_key(id: &Id) -> Option<String> {
+        let escaped = id.as_str().strip_prefix(ESCAPE)?;
+
+        let mut bytes = Vec::with_capacity(escaped.len());
+        let mut chars = escaped.bytes();
+        while let Some(byte) = chars.next() {
+            if byte == b'%' {
+                let hex = [chars.next()?, chars.next()?];
+                let hex = std::str::from_utf8(&hex).ok()?;
+                bytes.push(u8::from_str_radix(hex, 16).ok()?);
+            } else {
+                bytes.push(byte);
+            }
+        }
+
+        String::from_utf8(bytes).ok()
+    }
+
     /// Return true if self is an empty tuple, default link, or `Value::None`.
     pub fn is_none(&self) -> bool {
         match self {
@@ -269,18 +318,24 @@ impl Refer for Scalar {
 
         match self {
             Self::Map(map) => {
-                let resolved =
-                    try_join_all(map.into_iter().map(|(id, scalar)| {
+                let concurrency = Concurrency::current().resolve;
+                let resolved: Vec<(Id, State)> =
+                    stream::iter(map.into_iter().map(|(id, scalar)| {
                         scalar.resolve(context, txn).map_ok(|state| (id, state))
                     }))
+                    .buffer_unordered(concurrency)
+                    .try_collect()
                     .await?;
 
                 Ok(State::Map(Map::from_iter(resolved)))
             }
             Self::Ref(tc_ref) => tc_ref.resolve(context, txn).await,
             Self::Tuple(tuple) => {
-                let resolved =
-                    try_join_all(tuple.into_iter().map(|scalar| scalar.resolve(context, txn)))
+                let concurrency = Concurrency::current().resolve;
+                let resolved: Vec<State> =
+                    stream::iter(tuple.into_iter().map(|scalar| scalar.resolve(context, txn)))
+                        .buffered(concurrency)
+                        .try_collect()
                         .await?;
 
                 Ok(State::Tuple(resolved.into()))
@@ -663,15 +718,25 @@ impl<T1: TryCastFrom<Scalar>, T2: TryCastFrom<Scalar>, T3: TryCastFrom<Scalar>>
 #[derive(Default)]
 pub struct ScalarVisitor {
     value: tc_value::ValueVisitor,
+    context: DecodeContext,
 }
 
 impl ScalarVisitor {
+    /// Construct a new visitor to decode a [`Scalar`] at the given position in its parent value.
+    pub fn new(context: DecodeContext) -> Self {
+        Self {
+            value: tc_value::ValueVisitor,
+            context,
+        }
+    }
+
     pub async fn visit_map_value<A: de::MapAccess>(
+        &self,
         class: ScalarType,
         access: &mut A,
     ) -> Result<Scalar, A::Error> {
         debug!("ScalarVisitor::visit_map_value {}", class);
-        let scalar = access.next_value::<Scalar>(()).await?;
+        let scalar = self.next_value(access).await?;
         debug!("value {}", scalar);
 
         if let Some(scalar) = scalar.clone().into_type(class) {
@@ -700,6 +765,18 @@ impl ScalarVisitor {
         Ok(TCRef::Op(op_ref).into())
     }
 
+    pub(crate) async fn next_value<A: de::MapAccess>(
+        &self,
+        access: &mut A,
+    ) -> Result<Scalar, A::Error> {
+        self.context.count()?;
+        let context = self.context.nested()?;
+        access
+            .next_value::<Nested>(context)
+            .map_ok(|nested| nested.0)
+            .await
+    }
+
     pub fn visit_subject<E: de::Error>(subject: Subject, params: Scalar) -> Result<Scalar, E> {
         debug!("ScalarVisitor::visit_subject {} {}", subject, params);
 
@@ -799,24 +876,26 @@ impl de::Visitor for ScalarVisitor {
 
         if let Ok(path) = TCPathBuf::from_str(&key) {
             if let Some(class) = ScalarType::from_path(&path) {
-                if let Ok(scalar) = Self::visit_map_value(class, &mut access).await {
+                if let Ok(scalar) = self.visit_map_value(class, &mut access).await {
                     return Ok(scalar);
                 }
             }
         }
 
         if let Ok(subject) = Subject::from_str(&key) {
-            let params = access.next_value(()).await?;
+            self.context.check_subject(&key)?;
+            let params = self.next_value(&mut access).await?;
             return Self::visit_subject(subject, params);
         }
 
         let mut map = HashMap::new();
-        let key = Id::from_str(&key).map_err(de::Error::custom)?;
-        let value = access.next_value(()).await?;
+        let key = decode_key(&key)?;
+        let value = self.next_value(&mut access).await?;
         map.insert(key, value);
 
-        while let Some(key) = access.next_key(()).await? {
-            let value = access.next_value(()).await?;
+        while let Some(key) = access.next_key::<String>(()).await? {
+            let key = decode_key(&key)?;
+            let value = self.next_value(&mut access).await?;
             map.insert(key, value);
         }
 
@@ -825,12 +904,14 @@ impl de::Visitor for ScalarVisitor {
 
     async fn visit_seq<A: de::SeqAccess>(self, mut access: A) -> Result<Self::Value, A::Error> {
         let mut items: Vec<Scalar> = if let Some(size) = access.size_hint() {
-            Vec::with_capacity(size)
+            Vec::with_capacity(size.min(self.context.limits().max_elements))
         } else {
             vec![]
         };
 
-        while let Some(value) = access.next_element(()).await? {
+        let context = self.context.nested()?;
+        while let Some(Nested(value)) = access.next_element(context.clone()).await? {
+            self.context.count()?;
             items.push(value)
         }
 
@@ -847,10 +928,48 @@ impl FromStream for Scalar {
     }
 }
 
+/// Decode a map key, which may be escaped with a leading [`ESCAPE`].
+pub(crate) fn decode_key<E: de::Error>(key: &str) -> Result<Id, E> {
+    if let Some(key) = key.strip_prefix(ESCAPE) {
+        Ok(Scalar::escape_key(key))
+    } else {
+        Id::decode(key).map_err(de::Error::custom)
+    }
+}
+
+/// Encode a map key, restoring the original form of an escaped key.
+pub(crate) fn encode_key(key: &Id) -> String {
+    if let Some(unescaped) = Scalar::unescape_key(key) {
+        format!("{}{}", ESCAPE, unescaped)
+    } else {
+        key.to_string()
+    }
+}
+
+/// A [`Scalar`] nested within another value, decoded subject to the limits of its parent.
+struct Nested(Scalar);
+
+#[async_trait]
+impl FromStream for Nested {
+    type Context = DecodeContext;
+
+    async fn from_stream<D: Decoder>(context: DecodeContext, d: &mut D) -> Result<Self, D::Error> {
+        d.decode_any(ScalarVisitor::new(context))
+            .map_ok(Nested)
+            .await
+    }
+}
+
 impl<'en> ToStream<'en> for Scalar {
     fn to_stream<E: Encoder<'en>>(&'en self, e: E) -> Result<E::Ok, E::Error> {
         match self {
-            Scalar::Map(map) => map.to_stream(e),
+            Scalar::Map(map) => {
+                let mut encoder = e.encode_map(Some(map.len()))?;
+                for (key, value) in map.iter() {
+                    encoder.encode_entry(encode_key(key), value)?;
+                }
+                encoder.end()
+            }
             Scalar::Op(op_def) => op_def.to_stream(e),
             Scalar::Ref(tc_ref) => tc_ref.to_stream(e),
             Scalar::Tuple(tuple) => tuple.to_stream(e),
@@ -862,7 +981,13 @@ impl<'en> ToStream<'en> for Scalar {
 impl<'en> IntoStream<'en> for Scalar {
     fn into_stream<E: Encoder<'en>>(self, e: E) -> Result<E::Ok, E::Error> {
         match self {
-            Scalar::Map(map) => map.into_inner().into_stream(e),
+            Scalar::Map(map) => {
+                let mut encoder = e.encode_map(Some(map.len()))?;
+                for (key, value) in map.into_inner().into_iter() {
+                    encoder.encode_entry(encode_key(&key), value)?;
+                }
+                encoder.end()
+            }
             Scalar::Op(op_def) => op_def.into_stream(e),
             Scalar::Ref(tc_ref) => tc_ref.into_stream(e),
             Scalar::Tuple(tuple) => tuple.into_inner().into_stream(e),
@@ -928,7 +1053,7 @@ impl<'a, T: Instance + Public> Scope<'a, T> {
                 .deref()
                 .get(id)
                 .cloned()
-                .ok_or_else(|| TCError::not_found(id))
+                .ok_or_else(|| TCError::not_found(id.original()))
         }
     }
 
@@ -1002,3 +1127,117 @@ impl<'a, T> DerefMut for Scope<'a, T> {
         &mut self.data
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use destream::de::Decoder as _;
+    use futures::{future, stream};
+
+    use super::*;
+
+    async fn decode(json: &str, context: DecodeContext) -> TCResult<Scalar> {
+        let source = stream::once(future::ready(Ok(json.as_bytes().to_vec())));
+        let mut decoder = destream_json::de::Decoder::from(source);
+
+        decoder
+            .decode_any(ScalarVisitor::new(context))
+            .map_err(|e| TCError::bad_request("unable to decode Scalar", e))
+            .await
+    }
+
+    fn limits(max_depth: usize, max_elements: usize) -> DecodeContext {
+        DecodeContext::new(DecodeLimits {
+            max_depth,
+            max_elements,
+        })
+    }
+
+    #[tokio::test]
+    async fn test_decode_limits() {
+        assert!(decode("[[[[1]]]]", limits(8, 100)).await.is_ok());
+        assert!(decode("[[[[1]]]]", limits(2, 100)).await.is_err());
+        assert!(decode(r#"{"a": {"b": {"c": 1}}}"#, limits(2, 100))
+            .await
+            .is_err());
+
+        assert!(decode("[1, 2, 3]", limits(8, 3)).await.is_ok());
+        assert!(decode("[[1, 2], [3]]", limits(8, 3)).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_decode_strict() {
+        let implicit = r#"{"$x": []}"#;
+        let scalar = decode(implicit, DecodeContext::default()).await.unwrap();
+        assert!(scalar.is_ref());
+
+        let strict = DecodeContext::default().with_strict(true);
+        assert!(decode(implicit, strict.clone()).await.is_err());
+
+        let explicit = r#"{"/state/scalar/value/number": 1}"#;
+        assert!(decode(explicit, strict).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_escaped_key() {
+        // destream_json reads and writes strings verbatim, so the wire format has one backslash
+        let scalar = decode(r#"{"\$name": 1, "\/path": 2}"#, DecodeContext::default())
+            .await
+            .unwrap();
+
+        let map = match scalar {
+            Scalar::Map(map) => map,
+            other => panic!("expected a Map but found {}", other),
+        };
+
+        assert!(!map.is_empty());
+        assert!(map.contains_key(&Scalar::escape_key("$name")));
+        assert!(map.contains_key(&Scalar::escape_key("/path")));
+
+        let keys: Vec<String> = map.keys().map(encode_key).collect();
+        assert!(keys.contains(&"\\$name".to_string()));
+        assert!(keys.contains(&"\\/path".to_string()));
+
+        assert_eq!(
+            Scalar::unescape_key(&Scalar::escape_key("/path/to data")).as_deref(),
+            Some("/path/to data")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_precise_integers() {
+        let large = Value::from(Number::from(u64::MAX));
+        let small = Value::from(Number::from(MAX_SAFE_INTEGER));
+
+        set_precise_integers(true);
+        let encoded = serde_json::to_string(&large).unwrap();
+        let encoded_small = serde_json::to_string(&small).unwrap();
+        set_precise_integers(false);
+
+        assert_eq!(
+            encoded,
+            r#"{"/state/scalar/value/number/uint/64":"18446744073709551615"}"#
+        );
+        assert_eq!(encoded_small, MAX_SAFE_INTEGER.to_string());
+
+        let decoded: Value = serde_json::from_str(&encoded).unwrap();
+        assert!(decoded == large);
+
+        let source = stream::once(future::ready(encoded.into_bytes()));
+        let decoded: Value = destream_json::decode((), source).await.unwrap();
+        assert!(decoded == large);
+
+        let invalid = r#"{"/state/scalar/value/number/uint/64":"-1"}"#;
+        assert!(serde_json::from_str::<Value>(invalid).is_err());
+
+        // an integer which doesn't fit in the given type is rejected rather than truncated
+        let overflow = r#"{"/state/scalar/value/number/uint/8":"300"}"#;
+        assert!(serde_json::from_str::<Value>(overflow).is_err());
+
+        let source = stream::once(future::ready(overflow.as_bytes().to_vec()));
+        let decoded: Result<Value, _> = destream_json::decode((), source).await;
+        assert!(decoded.is_err());
+
+        let underflow = r#"{"/state/scalar/value/number/int/16":"-40000"}"#;
+        assert!(serde_json::from_str::<Value>(underflow).is_err());
+    }
+}
@@ -0,0 +1,276 @@
+//! Builders to construct [`OpDef`]s and [`OpRef`]s programmatically.
+//!
+//! Example:
+//! ```no_run
+//! # fn example() -> tinychain::error::TCResult<()> {
+//! use tinychain::generic::label;
+//! use tinychain::scalar::{OpDef, Ref};
+//!
+//! let user_id = Ref::id(label("user_id").into());
+//! let op = OpDef::get()
+//!     .key(label("user_id").into())
+//!     .then(label("user").into(), Ref::path("/app/users")?.get(user_id))
+//!     .build()?;
+//! # Ok(())
+//! # }
+//! ```
+
+use std::collections::HashSet;
+use std::marker::PhantomData;
+use std::str::FromStr;
+
+use tc_error::*;
+use tcgeneric::{label, Id, Label, Map};
+
+use crate::scalar::{IdRef, OpRef, Scalar, Subject, TCRef};
+
+use super::OpDef;
+
+const KEY: Label = label("key");
+const VALUE: Label = label("value");
+
+/// Marks an [`OpDefBuilder`] which builds a GET op.
+pub enum GetDef {}
+
+/// Marks an [`OpDefBuilder`] which builds a PUT op.
+pub enum PutDef {}
+
+/// Marks an [`OpDefBuilder`] which builds a POST op.
+pub enum PostDef {}
+
+/// Marks an [`OpDefBuilder`] which builds a DELETE op.
+pub enum DeleteDef {}
+
+/// Builds an [`OpDef`] of the type `T`, one step at a time.
+///
+/// Only the builder of an op which takes a key has a `key` method, and only the builder of a PUT
+/// op has a `value` method, so giving an op the wrong arguments is a compile-time error.
+pub struct OpDefBuilder<T> {
+    key: Id,
+    value: Id,
+    steps: Vec<(Id, Scalar)>,
+    class: PhantomData<T>,
+}
+
+impl<T> OpDefBuilder<T> {
+    fn new() -> Self {
+        Self {
+            key: KEY.into(),
+            value: VALUE.into(),
+            steps: Vec::new(),
+            class: PhantomData,
+        }
+    }
+
+    /// Add a step to this op. The last step is the op's return value.
+    pub fn then<S: Into<Scalar>>(mut self, id: Id, step: S) -> Self {
+        self.steps.push((id, step.into()));
+        self
+    }
+
+    fn validate(&self, params: &[&Id]) -> TCResult<()> {
+        let mut ids: HashSet<&Id> = params.iter().cloned().collect();
+        if ids.len() < params.len() {
+            return Err(TCError::bad_request(
+                "op parameters must have distinct names, not",
+                params
+                    .iter()
+                    .map(|id| id.to_string())
+                    .collect::<Vec<_>>()
+                    .join(", "),
+            ));
+        }
+
+        for (id, _) in &self.steps {
+            if !ids.insert(id) {
+                return Err(TCError::bad_request("duplicate op step", id));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl OpDefBuilder<GetDef> {
+    /// Set the name of the key of this op (the default is `key`).
+    pub fn key(mut self, key: Id) -> Self {
+        self.key = key;
+        self
+    }
+
+    /// Construct the [`OpDef`].
+    pub fn build(self) -> TCResult<OpDef> {
+        self.validate(&[&self.key])?;
+        Ok(OpDef::Get((self.key, self.steps)))
+    }
+}
+
+impl OpDefBuilder<PutDef> {
+    /// Set the name of the key of this op (the default is `key`).
+    pub fn key(mut self, key: Id) -> Self {
+        self.key = key;
+        self
+    }
+
+    /// Set the name of the value of this op (the default is `value`).
+    pub fn value(mut self, value: Id) -> Self {
+        self.value = value;
+        self
+    }
+
+    /// Construct the [`OpDef`].
+    pub fn build(self) -> TCResult<OpDef> {
+        self.validate(&[&self.key, &self.value])?;
+        Ok(OpDef::Put((self.key, self.value, self.steps)))
+    }
+}
+
+impl OpDefBuilder<PostDef> {
+    /// Construct the [`OpDef`].
+    pub fn build(self) -> TCResult<OpDef> {
+        self.validate(&[])?;
+        Ok(OpDef::Post(self.steps))
+    }
+}
+
+impl OpDefBuilder<DeleteDef> {
+    /// Set the name of the key of this op (the default is `key`).
+    pub fn key(mut self, key: Id) -> Self {
+        self.key = key;
+        self
+    }
+
+    /// Construct the [`OpDef`].
+    pub fn build(self) -> TCResult<OpDef> {
+        self.validate(&[&self.key])?;
+        Ok(OpDef::Delete((self.key, self.steps)))
+    }
+}
+
+impl OpDef {
+    /// Start building a GET op.
+    pub fn get() -> OpDefBuilder<GetDef> {
+        OpDefBuilder::new()
+    }
+
+    /// Start building a PUT op.
+    pub fn put() -> OpDefBuilder<PutDef> {
+        OpDefBuilder::new()
+    }
+
+    /// Start building a POST op.
+    pub fn post() -> OpDefBuilder<PostDef> {
+        OpDefBuilder::new()
+    }
+
+    /// Start building a DELETE op.
+    pub fn delete() -> OpDefBuilder<DeleteDef> {
+        OpDefBuilder::new()
+    }
+}
+
+impl From<OpDef> for Scalar {
+    fn from(op_def: OpDef) -> Self {
+        Scalar::Op(op_def)
+    }
+}
+
+/// Builds a reference to an op, or to another state in the same transaction context.
+pub struct Ref {
+    subject: Subject,
+}
+
+impl Ref {
+    /// Refer to the ops of the state at the given path or link, like `/app/users`, or of a
+    /// state in the same transaction context, like `$users/filter`.
+    pub fn path(path: &str) -> TCResult<Self> {
+        let subject = Subject::from_str(path)?;
+        Ok(Self { subject })
+    }
+
+    /// Refer to the state with the given [`Id`] in the same transaction context, like `$id`.
+    pub fn id(id: Id) -> Scalar {
+        TCRef::Id(IdRef::from(id)).into()
+    }
+
+    /// Construct a reference to a GET op.
+    pub fn get<K: Into<Scalar>>(self, key: K) -> Scalar {
+        op_ref(OpRef::Get((self.subject, key.into())))
+    }
+
+    /// Construct a reference to a PUT op.
+    pub fn put<K: Into<Scalar>, V: Into<Scalar>>(self, key: K, value: V) -> Scalar {
+        op_ref(OpRef::Put((self.subject, key.into(), value.into())))
+    }
+
+    /// Construct a reference to a POST op.
+    pub fn post(self, params: Map<Scalar>) -> Scalar {
+        op_ref(OpRef::Post((self.subject, params)))
+    }
+
+    /// Construct a reference to a DELETE op.
+    pub fn delete<K: Into<Scalar>>(self, key: K) -> Scalar {
+        op_ref(OpRef::Delete((self.subject, key.into())))
+    }
+}
+
+fn op_ref(op_ref: OpRef) -> Scalar {
+    TCRef::Op(op_ref).into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_build() -> TCResult<()> {
+        let user_id: Id = label("user_id").into();
+        let user: Id = label("user").into();
+
+        let op = OpDef::get()
+            .key(user_id.clone())
+            .then(
+                user.clone(),
+                Ref::path("/app/users")?.get(Ref::id(user_id.clone())),
+            )
+            .build()?;
+
+        match op {
+            OpDef::Get((key, steps)) => {
+                assert_eq!(key, user_id);
+                assert_eq!(steps.len(), 1);
+                assert_eq!(steps[0].0, user);
+                assert!(steps[0].1.is_ref());
+            }
+            _ => panic!("expected a GET op"),
+        }
+
+        match OpDef::put().build()? {
+            OpDef::Put((key, value, steps)) => {
+                assert_eq!(key, Id::from(KEY));
+                assert_eq!(value, Id::from(VALUE));
+                assert!(steps.is_empty());
+            }
+            _ => panic!("expected a PUT op"),
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_invalid() {
+        let key: Id = label("key").into();
+        let step = || Ref::id(label("x").into());
+
+        // a step may not shadow a parameter or another step
+        assert!(OpDef::get().then(key.clone(), step()).build().is_err());
+        assert!(OpDef::post()
+            .then(label("x").into(), step())
+            .then(label("x").into(), step())
+            .build()
+            .is_err());
+
+        // the key and value of a PUT op must have different names
+        assert!(OpDef::put().value(key).build().is_err());
+    }
+}
@@ -15,6 +15,8 @@ use crate::scalar::{Executor, Scalar};
 use crate::state::State;
 use crate::txn::Txn;
 
+use super::Plan;
+
 const PREFIX: PathLabel = path_label(&["state", "scalar", "op"]);
 
 /// The [`Class`] of a user-defined [`OpDef`].
@@ -116,21 +118,31 @@ impl OpDef {
         txn: Txn,
         context: I,
     ) -> TCResult<State> {
-        let capture = if let Some((id, _)) = op_def.last() {
-            id.clone()
-        } else {
+        if op_def.is_empty() {
             return Ok(State::default());
-        };
+        }
+
+        let plan = Plan::new(&op_def)?;
 
         let context = context
             .into_iter()
             .chain(op_def.into_iter().map(|(id, s)| (id, State::Scalar(s))));
 
         Executor::new(txn, &State::default(), context)
-            .capture(capture)
+            .execute(&plan)
             .await
     }
 
+    /// Borrow the steps of this `OpDef`.
+    pub fn steps(&self) -> &[(Id, Scalar)] {
+        match self {
+            Self::Get((_, def)) => def,
+            Self::Put((_, _, def)) => def,
+            Self::Post(def) => def,
+            Self::Delete((_, def)) => def,
+        }
+    }
+
     pub fn into_def(self) -> Vec<(Id, Scalar)> {
         match self {
             Self::Get((_, def)) => def,
@@ -1,6 +1,6 @@
 //! An executor for an `OpDef`
 
-use std::collections::{HashMap, HashSet};
+use std::collections::HashSet;
 
 use futures::future::FutureExt;
 use futures::stream::{FuturesUnordered, StreamExt};
@@ -10,7 +10,7 @@ use tc_error::*;
 use tcgeneric::{Id, Instance, Map};
 
 use crate::route::Public;
-use crate::scalar::{Refer, Scope};
+use crate::scalar::{Plan, Refer, Scope};
 use crate::state::State;
 use crate::txn::Txn;
 
@@ -41,14 +41,55 @@ impl<'a, T: Instance + Public> Executor<'a, T> {
         Self { txn, scope }
     }
 
+    /// Resolve the steps of the given [`Plan`] one level at a time, then return its captured state.
+    ///
+    /// If a step resolves to another reference, the remaining dependencies are resolved
+    /// dynamically, as by [`Self::capture`].
+    pub async fn execute(mut self, plan: &Plan) -> TCResult<State> {
+        debug!("execute op with a plan of {} levels", plan.levels().len());
+
+        for level in plan.levels() {
+            let mut resolved = self.txn.buffers().map(level.len());
+            {
+                let mut providers = FuturesUnordered::new();
+                for id in level {
+                    let state = self.scope.resolve_id(id)?;
+                    if state.is_ref() {
+                        self.txn.count_resolution()?;
+                        providers.push(state.resolve(&self.scope, &self.txn).map(move |r| (id, r)));
+                    }
+                }
+
+                while let Some((id, r)) = providers.next().await {
+                    match r {
+                        Ok(state) => {
+                            resolved.insert(id.clone(), state);
+                        }
+                        Err(cause) => return Err(cause.consume(format!("error resolving {}", id))),
+                    }
+                }
+            }
+
+            let complete = resolved.values().all(|state| !state.is_ref());
+            self.scope.extend(resolved.drain());
+
+            if !complete {
+                debug!("plan resolved a reference, falling back to dynamic resolution");
+                break;
+            }
+        }
+
+        self.capture(plan.capture().clone()).await
+    }
+
     /// Resolve the state of the variable `capture`, including any of its dependencies.
     pub async fn capture(mut self, capture: Id) -> TCResult<State> {
         debug!("execute op & capture {}", capture);
 
         while self.scope.resolve_id(&capture)?.is_ref() {
             let mut visited = HashSet::with_capacity(self.scope.len());
-            let mut pending = Vec::with_capacity(self.scope.len());
-            let mut unvisited = Vec::with_capacity(self.scope.len());
+            let mut pending = self.txn.buffers().ids(self.scope.len());
+            let mut unvisited = self.txn.buffers().ids(self.scope.len());
             unvisited.push(capture.clone());
 
             while let Some(id) = unvisited.pop() {
@@ -90,11 +131,12 @@ impl<'a, T: Instance + Public> Executor<'a, T> {
                 ));
             }
 
-            let mut resolved = HashMap::with_capacity(pending.len());
+            let mut resolved = self.txn.buffers().map(pending.len());
             {
                 let mut providers = FuturesUnordered::new();
-                for id in pending.into_iter() {
+                for id in pending.drain(..) {
                     let state = self.scope.resolve_id(&id)?.clone();
+                    self.txn.count_resolution()?;
                     providers.push(state.resolve(&self.scope, &self.txn).map(|r| (id, r)));
                 }
 
@@ -108,7 +150,7 @@ impl<'a, T: Instance + Public> Executor<'a, T> {
                 }
             }
 
-            self.scope.extend(resolved);
+            self.scope.extend(resolved.drain());
         }
 
         self.scope
@@ -1,6 +1,11 @@
 /// User-defined [`OpDef`]s.
+mod builder;
 mod def;
 mod executor;
 
+pub mod plan;
+
+pub use builder::*;
 pub use def::*;
 pub use executor::*;
+pub use plan::{Plan, Plans};
@@ -0,0 +1,192 @@
+//! A compiled execution [`Plan`] for an `OpDef`, compiled once per version of the `Cluster`
+//! which hosts it, so that its dependency order isn't computed again on every call.
+
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+
+use log::debug;
+
+use tc_error::*;
+use tcgeneric::Id;
+
+use crate::scalar::{Refer, Scalar};
+
+/// The order in which to resolve the steps of an `OpDef`.
+pub struct Plan {
+    capture: Id,
+    levels: Vec<Vec<Id>>,
+}
+
+impl Plan {
+    /// Compute the order in which to resolve the steps of the given `OpDef` in order to
+    /// resolve its last step, ignoring any steps which the last step doesn't depend on.
+    ///
+    /// Each level of the plan depends only on the levels before it, so the steps of a level can
+    /// be resolved concurrently.
+    pub fn new(op_def: &[(Id, Scalar)]) -> TCResult<Self> {
+        let capture = match op_def.last() {
+            Some((id, _)) => id.clone(),
+            None => return Err(TCError::bad_request("cannot plan an empty", "OpDef")),
+        };
+
+        // a later step with the same ID replaces an earlier step
+        let steps: HashMap<&Id, &Scalar> = op_def.iter().map(|(id, step)| (id, step)).collect();
+
+        let mut deps: HashMap<Id, HashSet<Id>> = HashMap::new();
+        let mut unvisited = vec![capture.clone()];
+        while let Some(id) = unvisited.pop() {
+            if deps.contains_key(&id) {
+                continue;
+            }
+
+            if let Some(step) = steps.get(&id) {
+                let mut requires = HashSet::new();
+                step.requires(&mut requires);
+                requires.retain(|dep| steps.contains_key(dep));
+                unvisited.extend(requires.iter().cloned());
+                deps.insert(id, requires);
+            }
+        }
+
+        let mut levels = vec![];
+        let mut planned = HashSet::with_capacity(deps.len());
+        while planned.len() < deps.len() {
+            let level: Vec<Id> = deps
+                .iter()
+                .filter(|(id, _)| !planned.contains(*id))
+                .filter(|(_, requires)| requires.iter().all(|dep| planned.contains(dep)))
+                .map(|(id, _)| id.clone())
+                .collect();
+
+            if level.is_empty() {
+                return Err(TCError::bad_request(
+                    "circular dependency detected in the steps required by",
+                    capture,
+                ));
+            }
+
+            planned.extend(level.iter().cloned());
+            levels.push(level);
+        }
+
+        Ok(Self { capture, levels })
+    }
+
+    /// The ID of the step whose result is the result of the `OpDef`.
+    pub fn capture(&'_ self) -> &'_ Id {
+        &self.capture
+    }
+
+    /// The levels of this `Plan`, in the order they should be resolved.
+    pub fn levels(&'_ self) -> &'_ [Vec<Id>] {
+        &self.levels
+    }
+}
+
+/// The compiled [`Plan`] of each method of one version of a `Cluster`, by name.
+///
+/// The methods of a `Cluster` can only change by loading a new version of it, so their plans are
+/// compiled once, when it's loaded, and dropped along with it.
+#[derive(Default)]
+pub struct Plans {
+    plans: HashMap<Id, Arc<Plan>>,
+}
+
+impl Plans {
+    /// Compile a [`Plan`] for each non-empty `OpDef` among the given `members` of a class.
+    pub fn compile<'a, I: IntoIterator<Item = (&'a Id, &'a Scalar)>>(members: I) -> TCResult<Self> {
+        let mut plans = HashMap::new();
+        for (name, member) in members {
+            if let Scalar::Op(op_def) = member {
+                if !op_def.steps().is_empty() {
+                    let plan = Plan::new(op_def.steps())
+                        .map_err(|cause| cause.consume(format!("invalid method {}", name)))?;
+
+                    plans.insert(name.clone(), Arc::new(plan));
+                }
+            }
+        }
+
+        debug!("compiled the plans of {} methods", plans.len());
+
+        Ok(Self { plans })
+    }
+
+    /// The compiled [`Plan`] of the method with the given `name`, if any.
+    pub fn get(&self, name: &Id) -> Option<Arc<Plan>> {
+        self.plans.get(name).cloned()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tcgeneric::label;
+
+    use crate::scalar::{Number, OpDef, Ref, Value};
+
+    use super::*;
+
+    fn id(id: &'static str) -> Id {
+        label(id).into()
+    }
+
+    fn value(n: u64) -> Scalar {
+        Value::from(Number::from(n)).into()
+    }
+
+    fn sorted(level: &[Id]) -> Vec<Id> {
+        let mut level = level.to_vec();
+        level.sort();
+        level
+    }
+
+    #[test]
+    fn test_plan() -> TCResult<()> {
+        let op_def = vec![
+            (id("a"), value(1)),
+            (id("unused"), value(2)),
+            (id("c"), Ref::id(id("a"))),
+            (id("d"), Ref::id(id("a"))),
+            (id("e"), Ref::path("$c/add")?.get(Ref::id(id("d")))),
+        ];
+
+        let plan = Plan::new(&op_def)?;
+        assert_eq!(plan.capture(), &id("e"));
+        assert_eq!(plan.levels().len(), 3);
+        assert_eq!(plan.levels()[0], vec![id("a")]);
+        assert_eq!(sorted(&plan.levels()[1]), vec![id("c"), id("d")]);
+        assert_eq!(plan.levels()[2], vec![id("e")]);
+
+        let circular = vec![(id("x"), Ref::id(id("y"))), (id("y"), Ref::id(id("x")))];
+        assert!(Plan::new(&circular).is_err());
+        assert!(Plan::new(&[]).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_compile() -> TCResult<()> {
+        // two methods with the same step IDs must not share a plan
+        let dependent = vec![(id("a"), value(1)), (id("b"), Ref::id(id("a")))];
+        let independent = vec![(id("a"), value(1)), (id("b"), value(2))];
+
+        let members = [
+            (id("dependent"), Scalar::Op(OpDef::Post(dependent))),
+            (id("independent"), Scalar::Op(OpDef::Post(independent))),
+            (id("empty"), Scalar::Op(OpDef::Post(vec![]))),
+            (id("constant"), value(3)),
+        ];
+
+        let plans = Plans::compile(members.iter().map(|(name, member)| (name, member)))?;
+        assert_eq!(plans.get(&id("dependent")).unwrap().levels().len(), 2);
+        assert_eq!(plans.get(&id("independent")).unwrap().levels().len(), 1);
+        assert!(plans.get(&id("empty")).is_none());
+        assert!(plans.get(&id("constant")).is_none());
+
+        let circular = vec![(id("x"), Ref::id(id("y"))), (id("y"), Ref::id(id("x")))];
+        let members = [(id("circular"), Scalar::Op(OpDef::Post(circular)))];
+        assert!(Plans::compile(members.iter().map(|(name, member)| (name, member))).is_err());
+
+        Ok(())
+    }
+}
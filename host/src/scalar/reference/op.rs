@@ -301,55 +301,68 @@ impl Refer for OpRef {
         txn: &'a Txn,
     ) -> TCResult<State> {
         match self {
-            Self::Get((subject, key)) => match subject {
-                Subject::Link(link) => {
-                    let key = key.resolve(context, txn).await?;
-                    txn.get(
-                        link,
-                        key.try_cast_into(|v| {
-                            TCError::bad_request("GET key must be a Value, not", v)
-                        })?,
-                    )
-                    .await
-                }
-                Subject::Ref(id_ref, path) => {
-                    let key = key.resolve(context, txn).await?;
-                    context
-                        .resolve_get(txn, id_ref.id(), &path, key.try_into()?)
-                        .await
-                }
-            },
-            Self::Put((subject, key, value)) => match subject {
-                Subject::Link(link) => {
-                    let key = key.resolve(context, txn).await?;
-                    let value = value.resolve(context, txn).await?;
-                    txn.put(link, key.try_into()?, value)
-                        .map_ok(State::from)
-                        .await
-                }
-                Subject::Ref(id_ref, path) => {
-                    let key = key.resolve(context, txn);
-                    let value = value.resolve(context, txn);
-                    let (key, value) = try_join!(key, value)?;
-
-                    context
-                        .resolve_put(txn, id_ref.id(), &path, key.try_into()?, value)
-                        .map_ok(State::from)
-                        .await
+            Self::Get((subject, key)) => {
+                let callee = txn.enter(&subject)?;
+                match subject {
+                    Subject::Link(link) => {
+                        let key = key.resolve(context, txn).await?;
+                        callee
+                            .get(
+                                link,
+                                key.try_cast_into(|v| {
+                                    TCError::bad_request("GET key must be a Value, not", v)
+                                })?,
+                            )
+                            .await
+                    }
+                    Subject::Ref(id_ref, path) => {
+                        let key = key.resolve(context, txn).await?;
+                        context
+                            .resolve_get(&callee, id_ref.id(), &path, key.try_into()?)
+                            .await
+                    }
                 }
-            },
-            Self::Post((subject, params)) => match subject {
-                Subject::Link(link) => {
-                    let params = Scalar::Map(params).resolve(context, txn).await?;
-                    txn.post(link, params).await
+            }
+            Self::Put((subject, key, value)) => {
+                let callee = txn.enter(&subject)?;
+                match subject {
+                    Subject::Link(link) => {
+                        let key = key.resolve(context, txn).await?;
+                        let value = value.resolve(context, txn).await?;
+                        callee
+                            .put(link, key.try_into()?, value)
+                            .map_ok(State::from)
+                            .await
+                    }
+                    Subject::Ref(id_ref, path) => {
+                        let key = key.resolve(context, txn);
+                        let value = value.resolve(context, txn);
+                        let (key, value) = try_join!(key, value)?;
+
+                        context
+                            .resolve_put(&callee, id_ref.id(), &path, key.try_into()?, value)
+                            .map_ok(State::from)
+                            .await
+                    }
                 }
-                Subject::Ref(id_ref, path) => {
-                    let params = Scalar::Map(params).resolve(context, txn).await?;
-                    let params = params.try_into()?;
-
-                    context.resolve_post(txn, id_ref.id(), &path, params).await
+            }
+            Self::Post((subject, params)) => {
+                let callee = txn.enter(&subject)?;
+                match subject {
+                    Subject::Link(link) => {
+                        let params = Scalar::Map(params).resolve(context, txn).await?;
+                        callee.post(link, params).await
+                    }
+                    Subject::Ref(id_ref, path) => {
+                        let params = Scalar::Map(params).resolve(context, txn).await?;
+                        let params = params.try_into()?;
+
+                        context
+                            .resolve_post(&callee, id_ref.id(), &path, params)
+                            .await
+                    }
                 }
-            },
+            }
             _ => Err(TCError::not_implemented("OpRef::Delete")),
         }
     }
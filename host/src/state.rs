@@ -8,8 +8,8 @@ use std::str::FromStr;
 
 use async_trait::async_trait;
 use destream::{de, en};
-use futures::future::try_join_all;
-use futures::{stream, StreamExt, TryFutureExt};
+use futures::future::{self, try_join_all};
+use futures::{stream, StreamExt, TryFutureExt, TryStreamExt};
 use log::debug;
 use safecast::TryCastFrom;
 
@@ -18,6 +18,7 @@ use tc_transact::{IntoView, Transaction};
 use tcgeneric::*;
 
 use crate::chain::*;
+use crate::collection::{Collection, CollectionType, CollectionVisitor};
 use crate::fs::Dir;
 use crate::object::{Object, ObjectType};
 use crate::route::Public;
@@ -28,6 +29,7 @@ use crate::txn::Txn;
 #[derive(Clone, Eq, PartialEq)]
 pub enum StateType {
     Chain(ChainType),
+    Collection(CollectionType),
     Map,
     Object(ObjectType),
     Scalar(ScalarType),
@@ -54,6 +56,7 @@ impl NativeClass for StateType {
             } else if path.len() > 2 {
                 match path[1].as_str() {
                     "chain" => ChainType::from_path(path).map(Self::Chain),
+                    "collection" => CollectionType::from_path(path).map(Self::Collection),
                     "scalar" => ScalarType::from_path(path).map(Self::Scalar),
                     _ => None,
                 }
@@ -68,6 +71,7 @@ impl NativeClass for StateType {
     fn path(&self) -> TCPathBuf {
         match self {
             Self::Chain(ct) => ct.path(),
+            Self::Collection(ct) => ct.path(),
             Self::Map => path_label(&["state", "map"]).into(),
             Self::Object(ot) => ot.path(),
             Self::Scalar(st) => st.path(),
@@ -82,6 +86,12 @@ impl From<ChainType> for StateType {
     }
 }
 
+impl From<CollectionType> for StateType {
+    fn from(ct: CollectionType) -> Self {
+        Self::Collection(ct)
+    }
+}
+
 impl From<ValueType> for StateType {
     fn from(vt: ValueType) -> Self {
         Self::Scalar(vt.into())
@@ -92,6 +102,7 @@ impl fmt::Display for StateType {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
             Self::Chain(ct) => fmt::Display::fmt(ct, f),
+            Self::Collection(ct) => fmt::Display::fmt(ct, f),
             Self::Map => f.write_str("Map<Id, State>"),
             Self::Object(ot) => fmt::Display::fmt(ot, f),
             Self::Scalar(st) => fmt::Display::fmt(st, f),
@@ -104,6 +115,7 @@ impl fmt::Display for StateType {
 #[derive(Clone)]
 pub enum State {
     Chain(Chain),
+    Collection(Collection),
     Map(Map<Self>),
     Object(Object),
     Scalar(Scalar),
@@ -111,6 +123,51 @@ pub enum State {
 }
 
 impl State {
+    /// Decode a `State` in the given [`DecodeContext`], for example to decode it strictly.
+    pub async fn decode<D: de::Decoder>(
+        txn: Txn,
+        context: DecodeContext,
+        decoder: &mut D,
+    ) -> Result<Self, D::Error> {
+        decoder.decode_any(StateVisitor::new(txn, context)).await
+    }
+
+    /// Encode this `State` as canonical JSON, i.e. with sorted map keys and normalized numbers,
+    /// so that equal states always have the same encoding.
+    pub async fn canonical_json(self, txn: Txn) -> TCResult<Vec<u8>> {
+        let encoded = destream_json::encode(self.into_view(txn)).map_err(TCError::internal)?;
+        let encoded = encoded
+            .map_err(TCError::internal)
+            .try_fold(Vec::new(), |mut encoded, chunk| {
+                encoded.extend(chunk);
+                future::ready(Ok(encoded))
+            })
+            .await?;
+
+        let json: serde_json::Value = serde_json::from_slice(&encoded)
+            .map_err(|e| TCError::internal(format!("invalid JSON encoding: {}", e)))?;
+
+        serde_json::to_vec(&canonicalize(json)).map_err(TCError::internal)
+    }
+
+    /// Compute the Blake3 digest of the canonical JSON encoding of this `State`.
+    pub async fn hash(self, txn: Txn) -> TCResult<blake3::Hash> {
+        let encoded = self.canonical_json(txn).await?;
+        Ok(blake3::hash(&encoded))
+    }
+
+    /// Return true if the [`hash`] of this `State` identifies its contents, i.e. it's a
+    /// [`Scalar`] or a [`Chain`], or a [`Map`] or [`Tuple`] of these.
+    pub fn is_hashable(&self) -> bool {
+        match self {
+            Self::Chain(_) => true,
+            Self::Map(map) => map.values().all(Self::is_hashable),
+            Self::Scalar(_) => true,
+            Self::Tuple(tuple) => tuple.iter().all(Self::is_hashable),
+            _ => false,
+        }
+    }
+
     /// Return true if this `State` is an empty [`Tuple`], default [`Link`], or `Value::None`
     pub fn is_none(&self) -> bool {
         match self {
@@ -211,6 +268,7 @@ impl Instance for State {
     fn class(&self) -> StateType {
         match self {
             Self::Chain(chain) => StateType::Chain(chain.class()),
+            Self::Collection(collection) => StateType::Collection(collection.class()),
             Self::Map(_) => StateType::Map,
             Self::Object(object) => StateType::Object(object.class()),
             Self::Scalar(scalar) => StateType::Scalar(scalar.class()),
@@ -240,6 +298,12 @@ impl From<Chain> for State {
     }
 }
 
+impl From<Collection> for State {
+    fn from(collection: Collection) -> Self {
+        Self::Collection(collection)
+    }
+}
+
 impl From<Link> for State {
     fn from(link: Link) -> Self {
         Scalar::from(link).into()
@@ -577,6 +641,7 @@ impl fmt::Display for State {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
             Self::Chain(chain) => fmt::Display::fmt(chain, f),
+            Self::Collection(collection) => fmt::Display::fmt(collection, f),
             Self::Map(map) => fmt::Display::fmt(map, f),
             Self::Object(object) => fmt::Display::fmt(object, f),
             Self::Scalar(scalar) => fmt::Display::fmt(scalar, f),
@@ -585,12 +650,63 @@ impl fmt::Display for State {
     }
 }
 
+fn canonicalize(json: serde_json::Value) -> serde_json::Value {
+    use serde_json::Value as Json;
+
+    match json {
+        Json::Array(items) => Json::Array(items.into_iter().map(canonicalize).collect()),
+        Json::Number(n) if n.is_f64() => {
+            let f = n.as_f64().expect("f64");
+            if f.fract() == 0. && f.abs() < (1u64 << 53) as f64 {
+                // an integral float, including -0.0, is encoded as an integer
+                Json::Number((f as i64).into())
+            } else {
+                Json::Number(n)
+            }
+        }
+        Json::Object(map) => {
+            let mut entries = map.into_iter().collect::<Vec<_>>();
+            entries.sort_by(|(l, _), (r, _)| l.cmp(r));
+
+            Json::Object(
+                entries
+                    .into_iter()
+                    .map(|(key, value)| (key, canonicalize(value)))
+                    .collect(),
+            )
+        }
+        other => other,
+    }
+}
+
 struct StateVisitor {
     txn: Txn,
     scalar: ScalarVisitor,
+    context: DecodeContext,
 }
 
 impl StateVisitor {
+    fn new(txn: Txn, context: DecodeContext) -> Self {
+        Self {
+            txn,
+            scalar: ScalarVisitor::new(context.clone()),
+            context,
+        }
+    }
+
+    async fn next_value<A: de::MapAccess>(
+        &self,
+        txn: Txn,
+        access: &mut A,
+    ) -> Result<State, A::Error> {
+        self.context.count()?;
+        let context = self.context.nested()?;
+        access
+            .next_value::<Nested>((txn, context))
+            .map_ok(|nested| nested.0)
+            .await
+    }
+
     async fn visit_map_value<A: de::MapAccess>(
         &self,
         class: StateType,
@@ -600,7 +716,12 @@ impl StateVisitor {
             StateType::Chain(_ct) => {
                 Err(de::Error::custom("decoding a Chain is not yet implemented"))
             }
-            StateType::Map => access.next_value(self.txn.clone()).await,
+            StateType::Collection(ct) => {
+                CollectionVisitor::visit_map_value(self.txn.clone(), ct, access)
+                    .map_ok(State::Collection)
+                    .await
+            }
+            StateType::Map => self.next_value(self.txn.clone(), access).await,
             StateType::Object(ot) => match ot {
                 ObjectType::Class => {
                     access
@@ -615,11 +736,12 @@ impl StateVisitor {
                 }
             },
             StateType::Scalar(st) => {
-                ScalarVisitor::visit_map_value(st, access)
+                self.scalar
+                    .visit_map_value(st, access)
                     .map_ok(State::Scalar)
                     .await
             }
-            StateType::Tuple => access.next_value(self.txn.clone()).await,
+            StateType::Tuple => self.next_value(self.txn.clone(), access).await,
         }
     }
 }
@@ -705,28 +827,30 @@ impl<'a> de::Visitor for StateVisitor {
             }
 
             if let Ok(subject) = reference::Subject::from_str(&key) {
-                let params = access.next_value(()).await?;
+                self.context.check_subject(&key)?;
+                let params = self.scalar.next_value(&mut access).await?;
                 return ScalarVisitor::visit_subject(subject, params).map(State::Scalar);
             }
 
             let mut map = HashMap::new();
 
-            let id = Id::from_str(&key).map_err(de::Error::custom)?;
+            let id = decode_key(&key)?;
             let txn = self
                 .txn
                 .subcontext(id.clone())
                 .map_err(de::Error::custom)
                 .await?;
-            let value = access.next_value(txn).await?;
+            let value = self.next_value(txn, &mut access).await?;
             map.insert(id, value);
 
-            while let Some(id) = access.next_key::<Id>(()).await? {
+            while let Some(id) = access.next_key::<String>(()).await? {
+                let id = decode_key(&id)?;
                 let txn = self
                     .txn
                     .subcontext(id.clone())
                     .map_err(de::Error::custom)
                     .await?;
-                let state = access.next_value(txn).await?;
+                let state = self.next_value(txn, &mut access).await?;
                 map.insert(id, state);
             }
 
@@ -738,11 +862,13 @@ impl<'a> de::Visitor for StateVisitor {
 
     async fn visit_seq<A: de::SeqAccess>(self, mut access: A) -> Result<Self::Value, A::Error> {
         let mut seq = if let Some(len) = access.size_hint() {
-            Vec::with_capacity(len)
+            Vec::with_capacity(len.min(self.context.limits().max_elements))
         } else {
             Vec::new()
         };
 
+        let context = self.context.nested()?;
+
         let mut i = 0usize;
         loop {
             let txn = self
@@ -751,7 +877,8 @@ impl<'a> de::Visitor for StateVisitor {
                 .map_err(de::Error::custom)
                 .await?;
 
-            if let Some(next) = access.next_element(txn).await? {
+            if let Some(Nested(next)) = access.next_element((txn, context.clone())).await? {
+                self.context.count()?;
                 seq.push(next);
                 i += 1;
             } else {
@@ -768,8 +895,27 @@ impl de::FromStream for State {
     type Context = Txn;
 
     async fn from_stream<D: de::Decoder>(txn: Txn, decoder: &mut D) -> Result<Self, D::Error> {
-        let scalar = ScalarVisitor::default();
-        decoder.decode_any(StateVisitor { txn, scalar }).await
+        let visitor = StateVisitor::new(txn, DecodeContext::default());
+        decoder.decode_any(visitor).await
+    }
+}
+
+/// A [`State`] nested within another value, decoded subject to the limits of its parent.
+struct Nested(State);
+
+#[async_trait]
+impl de::FromStream for Nested {
+    type Context = (Txn, DecodeContext);
+
+    async fn from_stream<D: de::Decoder>(
+        context: (Txn, DecodeContext),
+        decoder: &mut D,
+    ) -> Result<Self, D::Error> {
+        let (txn, context) = context;
+        decoder
+            .decode_any(StateVisitor::new(txn, context))
+            .map_ok(Nested)
+            .await
     }
 }
 
@@ -784,10 +930,11 @@ impl<'en> en::IntoStream<'en> for StateView {
             State::Chain(_chain) => {
                 Err(en::Error::custom("encoding a Chain is not yet implemented"))
             }
+            State::Collection(collection) => collection.into_view(self.txn).into_stream(encoder),
             State::Map(map) => {
                 let txn = self.txn.clone();
                 let map = stream::iter(map.into_iter())
-                    .map(move |(id, state)| (id, state.into_view(txn.clone())))
+                    .map(move |(id, state)| (encode_key(&id), state.into_view(txn.clone())))
                     .map(Ok);
 
                 encoder.encode_map_stream(map)
@@ -805,3 +952,51 @@ impl<'en> en::IntoStream<'en> for StateView {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::test::TestHost;
+
+    use super::*;
+
+    fn map(entries: Vec<(&'static str, Number)>) -> State {
+        let mut map = Map::<State>::default();
+        for (name, value) in entries {
+            map.insert(label(name).into(), Value::from(value).into());
+        }
+
+        State::Map(map)
+    }
+
+    #[tokio::test]
+    async fn test_canonical_json() -> TCResult<()> {
+        let host = TestHost::new(vec![]).await?;
+        let txn = host.new_txn(false).await?;
+
+        let state = map(vec![
+            ("b", Number::from(2.)),
+            ("a", Number::from(1.5)),
+            ("c", Number::from(-0.)),
+        ]);
+
+        assert!(state.is_hashable());
+
+        let json = state.clone().canonical_json(txn.clone()).await?;
+        assert_eq!(json, br#"{"a":1.5,"b":2,"c":0}"#.to_vec());
+
+        // an integral float has the same encoding, and therefore the same hash, as an integer
+        let same = map(vec![
+            ("c", Number::from(0u64)),
+            ("a", Number::from(1.5)),
+            ("b", Number::from(2u64)),
+        ]);
+
+        let hash = state.hash(txn.clone()).await?;
+        assert_eq!(hash, same.hash(txn.clone()).await?);
+
+        let different = map(vec![("a", Number::from(1.5))]);
+        assert_ne!(hash, different.hash(txn).await?);
+
+        Ok(())
+    }
+}
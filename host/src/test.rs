@@ -0,0 +1,183 @@
+//! An in-process host for integration tests, which doesn't listen on the network.
+//!
+//! Example:
+//! ```no_run
+//! # async fn example() -> tinychain::error::TCResult<()> {
+//! use tinychain::test::TestHost;
+//!
+//! let host = TestHost::new(vec![]).await?;
+//! let state = host.get("/state/scalar/value/number/bool".parse()?, true.into()).await?;
+//! # Ok(())
+//! # }
+//! ```
+
+use std::net::{IpAddr, Ipv4Addr};
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+
+use futures::TryFutureExt;
+use tc_error::*;
+use tc_transact::{Transact, TxnId};
+use tcgeneric::{NetworkTime, TCPathBuf};
+use uuid::Uuid;
+
+use crate::cluster;
+use crate::gateway::{self, Gateway};
+use crate::kernel::Kernel;
+use crate::object::InstanceClass;
+use crate::scalar::Value;
+use crate::state::State;
+use crate::txn::{Locale, Txn, TxnServer};
+
+const CACHE_SIZE: usize = 10_000_000;
+const HTTP_PORT: u16 = 8702;
+const REQUEST_TTL: Duration = Duration::from_secs(30);
+
+/// An in-process [`Gateway`] backed by temporary directories, which are deleted on drop.
+pub struct TestHost {
+    gateway: Arc<Gateway>,
+    root: PathBuf,
+}
+
+impl TestHost {
+    /// Start a new `TestHost` which hosts the given clusters.
+    pub async fn new(clusters: Vec<InstanceClass>) -> TCResult<Self> {
+        let root = std::env::temp_dir().join(format!("tc-test-{}", Uuid::new_v4()));
+        let workspace = root.join("tmp");
+        let data_dir = root.join("data");
+
+        for dir in &[&workspace, &data_dir] {
+            tokio::fs::create_dir_all(dir)
+                .map_err(|e| TCError::internal(format!("unable to create {:?}: {}", dir, e)))
+                .await?;
+        }
+
+        let (workspace, data_dir) = crate::mount(workspace, Some(data_dir), CACHE_SIZE).await?;
+        let data_dir = data_dir.expect("data dir");
+
+        let txn_id = new_txn_id();
+        let mut hosted = Vec::with_capacity(clusters.len());
+        for class in clusters {
+            hosted.push(cluster::instantiate(class, data_dir.clone(), txn_id).await?);
+        }
+
+        data_dir.commit(&txn_id).await;
+
+        let config = gateway::Config {
+            addr: IpAddr::V4(Ipv4Addr::LOCALHOST),
+            http_port: HTTP_PORT,
+            ws_port: None,
+            request_ttl: REQUEST_TTL,
+            record: None,
+            tls_cert: None,
+            tls_key: None,
+            grpc_port: None,
+            grpc_peers: false,
+            compression_threshold: 1_000,
+            http2_max_streams: 256,
+            cors: gateway::Cors::default(),
+            request_limit: crate::http::DEFAULT_REQUEST_LIMIT,
+            upload_limits: gateway::UploadLimits::default(),
+            rate_limit: gateway::RateLimit::default(),
+            single_use_tokens: false,
+            trusted_keys: vec![],
+            oidc: None,
+            secrets: None,
+            egress: vec![],
+            exec_limits: vec![],
+        };
+
+        let txn_server = TxnServer::new(workspace).await;
+        let kernel = Kernel::new(hosted).with_data_dir(data_dir);
+        let gateway = Gateway::new(config, kernel, txn_server);
+
+        Ok(Self { gateway, root })
+    }
+
+    /// Borrow the [`Gateway`] of this `TestHost`.
+    pub fn gateway(&self) -> &Arc<Gateway> {
+        &self.gateway
+    }
+
+    /// Read the [`State`] `key` at `path`, in a new transaction.
+    pub async fn get(&self, path: TCPathBuf, key: Value) -> TCResult<State> {
+        let txn = self.new_txn(false).await?;
+        self.gateway.get(&txn, path.into(), key).await
+    }
+
+    /// Set `key` = `value` in the [`State`] at `path`, in a new transaction.
+    pub async fn put(&self, path: TCPathBuf, key: Value, value: State) -> TCResult<()> {
+        let txn = self.new_txn(true).await?;
+        self.gateway.put(&txn, path.into(), key, value).await
+    }
+
+    /// Execute the POST op at `path` with the given `params`, in a new transaction.
+    pub async fn post(&self, path: TCPathBuf, params: State) -> TCResult<State> {
+        let txn = self.new_txn(true).await?;
+        self.gateway.post(&txn, path.into(), params).await
+    }
+
+    /// Begin a new transaction, which may write to hosted state if `mutation` is true.
+    pub async fn new_txn(&self, mutation: bool) -> TCResult<Txn> {
+        let txn_id = new_txn_id();
+        self.gateway
+            .new_txn(txn_id, None, Locale::default(), mutation)
+            .await
+    }
+}
+
+// a test host reads the system clock even in a simulation build, since a test runs outside of any
+// `Simulation` and so would otherwise begin every transaction at the same virtual time
+fn new_txn_id() -> TxnId {
+    TxnId::new(NetworkTime::now())
+}
+
+impl Drop for TestHost {
+    fn drop(&mut self) {
+        if let Err(cause) = std::fs::remove_dir_all(&self.root) {
+            log::warn!("unable to remove test directory {:?}: {}", self.root, cause);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::convert::TryFrom;
+
+    use tcgeneric::{label, Map};
+
+    use crate::scalar::{Number, OpDef};
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_host() -> TCResult<()> {
+        let mut proto = Map::default();
+        let greeting = OpDef::get()
+            .then(label("greeting").into(), Value::String("hello".into()))
+            .build()?;
+
+        proto.insert(label("greet").into(), greeting.into());
+        let cluster = InstanceClass::new(Some("/app/test".parse()?), proto);
+
+        let host = TestHost::new(vec![cluster]).await?;
+
+        let n = Value::from(Number::from(5u64));
+        let state = host
+            .get("/state/scalar/value/number/uint/64".parse()?, n.clone())
+            .await?;
+
+        assert!(Value::try_from(state)? == n);
+
+        let state = host.get("/app/test/greet".parse()?, Value::None).await?;
+        assert!(Value::try_from(state)? == Value::String("hello".into()));
+
+        assert!(host
+            .get("/app/missing".parse()?, Value::None)
+            .await
+            .is_err());
+
+        Ok(())
+    }
+}
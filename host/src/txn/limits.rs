@@ -0,0 +1,248 @@
+//! Limits on the execution of ops within a transaction.
+
+use std::fmt;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use tc_error::*;
+use tcgeneric::{PathSegment, TCPathBuf};
+
+/// The default maximum depth of nested op calls within a transaction.
+pub const DEFAULT_MAX_DEPTH: usize = 64;
+
+/// The default maximum number of references to resolve within a transaction.
+pub const DEFAULT_MAX_RESOLUTIONS: usize = 1_000_000;
+
+// the stack used by one nested op call, which is several times larger in an unoptimized build
+#[cfg(debug_assertions)]
+const STACK_PER_CALL: usize = 192 * 1024;
+#[cfg(not(debug_assertions))]
+const STACK_PER_CALL: usize = 32 * 1024;
+
+// the stack used to serve a request, apart from its nested op calls
+const BASE_STACK: usize = 2 * 1024 * 1024;
+
+static MAX_DEPTH: AtomicUsize = AtomicUsize::new(DEFAULT_MAX_DEPTH);
+static MAX_RESOLUTIONS: AtomicUsize = AtomicUsize::new(DEFAULT_MAX_RESOLUTIONS);
+
+/// Limits on the ops executed within one transaction on this host, so that an `OpDef` which
+/// recurses, directly or through other ops, fails with a descriptive error rather than running
+/// until its transaction times out or this host runs out of memory.
+///
+/// A call is counted when an op resolves a reference to another op, whether by [`Link`] or on a
+/// local subject like `$self`. The depth of a call is sent along with a request to another host,
+/// which continues to count from there, but every host counts the resolutions of a transaction
+/// separately.
+///
+/// Each nested call uses more of the stack of the thread which serves it, so a host sizes the
+/// stack of its threads with [`ExecLimits::stack_size`].
+///
+/// [`Link`]: crate::scalar::Link
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct ExecLimits {
+    pub max_depth: usize,
+    pub max_resolutions: usize,
+}
+
+impl ExecLimits {
+    /// Return the limits currently configured for this process.
+    pub fn current() -> Self {
+        Self {
+            max_depth: MAX_DEPTH.load(Ordering::Relaxed),
+            max_resolutions: MAX_RESOLUTIONS.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Set the limits of every subsequent transaction on this process.
+    pub fn configure(self) {
+        MAX_DEPTH.store(self.max_depth, Ordering::Relaxed);
+        MAX_RESOLUTIONS.store(self.max_resolutions, Ordering::Relaxed);
+    }
+
+    /// The stack size of a thread which can serve an op call at the maximum depth of these limits.
+    pub fn stack_size(&self) -> usize {
+        BASE_STACK + self.max_depth * STACK_PER_CALL
+    }
+}
+
+impl Default for ExecLimits {
+    fn default() -> Self {
+        Self {
+            max_depth: DEFAULT_MAX_DEPTH,
+            max_resolutions: DEFAULT_MAX_RESOLUTIONS,
+        }
+    }
+}
+
+/// The [`ExecLimits`] of the ops of one cluster, which override the limits of this host.
+///
+/// Parsed from a string of the form `<cluster path>,<max depth>,<max resolutions>`,
+/// like "/app/billing,16,10000".
+#[derive(Clone)]
+pub struct ClusterLimits {
+    cluster: TCPathBuf,
+    limits: ExecLimits,
+}
+
+impl ClusterLimits {
+    /// Return the limits of the ops at `path`: the limits of the innermost cluster which contains
+    /// it, or else the limits of this host.
+    pub fn of(clusters: &[Self], path: &[PathSegment]) -> ExecLimits {
+        clusters
+            .iter()
+            .filter(|cluster| path.starts_with(&cluster.cluster))
+            .max_by_key(|cluster| cluster.cluster.len())
+            .map(|cluster| cluster.limits)
+            .unwrap_or_else(ExecLimits::current)
+    }
+
+    /// Borrow the [`ExecLimits`] of this cluster.
+    pub fn limits(&self) -> &ExecLimits {
+        &self.limits
+    }
+}
+
+impl FromStr for ClusterLimits {
+    type Err = TCError;
+
+    fn from_str(s: &str) -> TCResult<Self> {
+        let parts: Vec<&str> = s.split(',').map(|part| part.trim()).collect();
+        if parts.len() != 3 {
+            return Err(TCError::bad_request(
+                "expected cluster limits of the form <cluster path>,<max depth>,<max resolutions>, not",
+                s,
+            ));
+        }
+
+        let cluster = parts[0].parse()?;
+        let max_depth = parts[1]
+            .parse()
+            .map_err(|e| TCError::bad_request("invalid maximum call depth", e))?;
+        let max_resolutions = parts[2]
+            .parse()
+            .map_err(|e| TCError::bad_request("invalid maximum reference resolutions", e))?;
+
+        Ok(Self {
+            cluster,
+            limits: ExecLimits {
+                max_depth,
+                max_resolutions,
+            },
+        })
+    }
+}
+
+impl fmt::Debug for ClusterLimits {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}: {:?}", self.cluster, self.limits)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tc_error::*;
+    use tcgeneric::{label, Map};
+
+    use crate::object::InstanceClass;
+    use crate::scalar::{Link, OpDef, OpRef, Scalar, Subject, TCRef, Value};
+
+    use crate::test::TestHost;
+
+    use super::*;
+
+    fn subject(path: String) -> Subject {
+        Subject::Link(path.parse().expect("link"))
+    }
+
+    #[tokio::test]
+    async fn test_enter() -> TCResult<()> {
+        let host = TestHost::new(vec![]).await?;
+        let txn = host.new_txn(false).await?;
+
+        let mut callee = txn.clone();
+        for depth in 0..DEFAULT_MAX_DEPTH {
+            callee = callee.enter(&subject(format!("/app/op/{}", depth)))?;
+        }
+
+        let err = callee
+            .enter(&subject("/app/op/last".to_string()))
+            .map(|_| ())
+            .unwrap_err();
+        assert_eq!(err.code(), ErrorType::PolicyViolation);
+        assert!(err.message().contains("/app/op/last"));
+        assert!(err
+            .message()
+            .contains(&format!("/app/op/{}", DEFAULT_MAX_DEPTH - 1)));
+
+        // the depth of a call doesn't affect its caller
+        txn.enter(&subject("/app/op/other".to_string()))?;
+
+        // a transaction continued from another host starts at the depth of its caller there
+        let resumed = txn.clone().resume(DEFAULT_MAX_DEPTH);
+        let err = resumed
+            .enter(&subject("/app/op/remote".to_string()))
+            .map(|_| ())
+            .unwrap_err();
+        assert_eq!(err.code(), ErrorType::PolicyViolation);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_cluster_limits() -> TCResult<()> {
+        let clusters: Vec<ClusterLimits> =
+            vec!["/app,16,1000".parse()?, "/app/billing, 4, 100".parse()?];
+
+        let limits = |path: &str| ClusterLimits::of(&clusters, &path.parse::<TCPathBuf>().unwrap());
+        assert_eq!(limits("/app/billing/charge").max_depth, 4);
+        assert_eq!(limits("/app/users").max_depth, 16);
+        assert_eq!(limits("/app/users").max_resolutions, 1000);
+        assert_eq!(limits("/state/scalar"), ExecLimits::current());
+
+        assert!("/app,16".parse::<ClusterLimits>().is_err());
+        assert!("/app,deep,1000".parse::<ClusterLimits>().is_err());
+
+        Ok(())
+    }
+
+    // this runs on a thread with the same stack size as a worker thread of a host, to check that
+    // a call at the maximum depth doesn't overflow it
+    #[test]
+    fn test_recursion() -> TCResult<()> {
+        std::thread::Builder::new()
+            .stack_size(ExecLimits::default().stack_size())
+            .spawn(|| {
+                tokio::runtime::Builder::new_current_thread()
+                    .enable_all()
+                    .build()
+                    .expect("runtime")
+                    .block_on(recurse())
+            })
+            .expect("thread")
+            .join()
+            .expect("recursion test")
+    }
+
+    async fn recurse() -> TCResult<()> {
+        // an op which calls itself
+        let link: Link = "/app/test/recurse".parse()?;
+        let call = OpRef::Get((Subject::Link(link), Scalar::Value(Value::None)));
+        let recurse = OpDef::get()
+            .then(label("result").into(), Scalar::from(TCRef::Op(call)))
+            .build()?;
+
+        let mut proto = Map::default();
+        proto.insert(label("recurse").into(), recurse.into());
+        let cluster = InstanceClass::new(Some("/app/test".parse()?), proto);
+
+        let host = TestHost::new(vec![cluster]).await?;
+        let result = host.get("/app/test/recurse".parse()?, Value::None).await;
+
+        let err = result.map(|_| ()).unwrap_err();
+        assert_eq!(err.code(), ErrorType::PolicyViolation);
+        assert!(err.message().contains("maximum call depth"));
+        assert!(err.message().contains("/app/test/recurse"));
+
+        Ok(())
+    }
+}
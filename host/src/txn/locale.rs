@@ -0,0 +1,168 @@
+//! The locale and timezone of a request, used to format values for display.
+
+use std::fmt;
+
+use tc_error::*;
+
+const DEFAULT_LANGUAGE: &str = "en";
+
+/// The locale and timezone of the request which initiated a transaction.
+///
+/// These are read from the `Accept-Language` and `X-Tinychain-Timezone` headers of an HTTP
+/// request. Only fixed UTC offsets like `+05:30` are supported as a timezone, since this host
+/// doesn't ship a timezone database.
+#[derive(Clone, Eq, PartialEq)]
+pub struct Locale {
+    language: String,
+    utc_offset: i32,
+}
+
+impl Locale {
+    /// Construct a new `Locale` from the values of the `Accept-Language` and
+    /// `X-Tinychain-Timezone` headers, if present.
+    pub fn parse(accept_language: Option<&str>, timezone: Option<&str>) -> TCResult<Self> {
+        let language = match accept_language {
+            Some(accept_language) => parse_language(accept_language)?,
+            None => DEFAULT_LANGUAGE.to_string(),
+        };
+
+        let utc_offset = match timezone {
+            Some(timezone) => parse_utc_offset(timezone)?,
+            None => 0,
+        };
+
+        Ok(Self {
+            language,
+            utc_offset,
+        })
+    }
+
+    /// The preferred language of this `Locale`, as a BCP 47 language tag like `en-US`.
+    pub fn language(&self) -> &str {
+        &self.language
+    }
+
+    /// The offset of this `Locale`'s timezone from UTC, in seconds.
+    pub fn utc_offset(&self) -> i32 {
+        self.utc_offset
+    }
+}
+
+impl Default for Locale {
+    fn default() -> Self {
+        Self {
+            language: DEFAULT_LANGUAGE.to_string(),
+            utc_offset: 0,
+        }
+    }
+}
+
+impl fmt::Display for Locale {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let sign = if self.utc_offset < 0 { '-' } else { '+' };
+        let offset = self.utc_offset.abs();
+
+        write!(
+            f,
+            "{} (UTC{}{:02}:{:02})",
+            self.language,
+            sign,
+            offset / 3600,
+            (offset % 3600) / 60
+        )
+    }
+}
+
+// take the first language in a header like "fr-CH, fr;q=0.9, en;q=0.8", ignoring its weight
+fn parse_language(accept_language: &str) -> TCResult<String> {
+    let language = accept_language
+        .split(',')
+        .next()
+        .and_then(|language| language.split(';').next())
+        .map(|language| language.trim())
+        .unwrap_or_default();
+
+    if language == "*" {
+        return Ok(DEFAULT_LANGUAGE.to_string());
+    }
+
+    let valid = !language.is_empty()
+        && language.split('-').all(|subtag| {
+            !subtag.is_empty()
+                && subtag.len() <= 8
+                && subtag.chars().all(|c| c.is_ascii_alphanumeric())
+        });
+
+    if valid {
+        Ok(language.to_string())
+    } else {
+        Err(TCError::bad_request(
+            "invalid language tag in Accept-Language header",
+            accept_language,
+        ))
+    }
+}
+
+// parse a timezone like "UTC", "Z", "+05:30", or "-0800" into an offset in seconds
+fn parse_utc_offset(timezone: &str) -> TCResult<i32> {
+    let timezone = timezone.trim();
+    if timezone.eq_ignore_ascii_case("utc") || timezone.eq_ignore_ascii_case("z") {
+        return Ok(0);
+    }
+
+    let invalid = || {
+        TCError::bad_request(
+            "expected a timezone like \"UTC\" or \"+05:30\", not",
+            timezone,
+        )
+    };
+
+    let (sign, offset) = if let Some(offset) = timezone.strip_prefix('+') {
+        (1, offset)
+    } else if let Some(offset) = timezone.strip_prefix('-') {
+        (-1, offset)
+    } else {
+        return Err(invalid());
+    };
+
+    let (hours, minutes) = match offset.find(':') {
+        Some(i) => (&offset[..i], &offset[i + 1..]),
+        None if offset.len() == 4 => offset.split_at(2),
+        None => (offset, "0"),
+    };
+
+    let hours: i32 = hours.parse().map_err(|_| invalid())?;
+    let minutes: i32 = minutes.parse().map_err(|_| invalid())?;
+    if !(0..=14).contains(&hours) || !(0..60).contains(&minutes) {
+        return Err(invalid());
+    }
+
+    Ok(sign * (hours * 3600 + minutes * 60))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let locale = Locale::parse(Some("fr-CH, fr;q=0.9, en;q=0.8"), Some("+05:30")).unwrap();
+        assert_eq!(locale.language(), "fr-CH");
+        assert_eq!(locale.utc_offset(), 5 * 3600 + 30 * 60);
+        assert_eq!(locale.to_string(), "fr-CH (UTC+05:30)");
+
+        assert!(Locale::parse(None, None).unwrap() == Locale::default());
+        assert_eq!(Locale::parse(Some("*"), None).unwrap().language(), "en");
+        assert_eq!(
+            Locale::parse(None, Some("-0800")).unwrap().utc_offset(),
+            -8 * 3600
+        );
+        assert_eq!(Locale::parse(None, Some("utc")).unwrap().utc_offset(), 0);
+
+        assert!(Locale::parse(Some("en_US"), None).is_err());
+        assert!(Locale::parse(Some(""), None).is_err());
+        assert!(Locale::parse(None, Some("05:30")).is_err());
+        assert!(Locale::parse(None, Some("+15:00")).is_err());
+        assert!(Locale::parse(None, Some("+05:60")).is_err());
+    }
+}
@@ -1,39 +1,90 @@
 //! The transaction context [`Txn`].
 
+use std::collections::HashMap;
 use std::hash::{Hash, Hasher};
 use std::iter::FromIterator;
-use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, PoisonError};
 
 use async_trait::async_trait;
+use bytes::{Bytes, BytesMut};
 use futures::future::TryFutureExt;
+use futures::TryStreamExt;
 use log::debug;
+use uuid::Uuid;
 
 use tc_error::*;
 use tc_transact::fs::Dir;
 use tc_transact::Transaction;
-use tcgeneric::{Id, NetworkTime, PathSegment, TCPathBuf, Tuple};
+use tcgeneric::{path_label, Id, NetworkTime, PathLabel, PathSegment, TCPathBuf, Tuple};
 
 use crate::fs;
 use crate::gateway::Gateway;
-use crate::scalar::{Link, Value};
+use crate::scalar::{Link, Subject, Value};
 use crate::state::State;
 
+mod buffers;
+mod limits;
+mod locale;
 mod request;
 mod server;
 
+pub use buffers::BufferPool;
+pub use limits::{ClusterLimits, ExecLimits};
+pub use locale::Locale;
 pub use request::*;
 pub use server::*;
 pub use tc_transact::TxnId;
 
+/// The path of the files uploaded within a transaction.
+pub const UPLOADS: PathLabel = path_label(&["transact", "upload"]);
+
+// the number of blocks of an uploaded file to read ahead
+const UPLOAD_READ_AHEAD: usize = 4;
+
+/// A file uploaded within a transaction, stored as a sequence of blocks in its workspace.
+#[derive(Clone)]
+pub(crate) struct Upload {
+    file: fs::File<Bytes>,
+    block_ids: Vec<Id>,
+}
+
+impl Upload {
+    pub fn new(file: fs::File<Bytes>, block_ids: Vec<Id>) -> Self {
+        Self { file, block_ids }
+    }
+
+    /// Read the contents of this upload.
+    pub async fn read(self, txn_id: TxnId) -> TCResult<Bytes> {
+        self.file
+            .read_ahead(txn_id, self.block_ids, UPLOAD_READ_AHEAD)
+            .try_fold(BytesMut::new(), |mut contents, block| {
+                contents.extend_from_slice(&block);
+                futures::future::ready(Ok(contents))
+            })
+            .map_ok(BytesMut::freeze)
+            .await
+    }
+}
+
 struct Active {
+    buffers: BufferPool,
     expires: NetworkTime,
     scope: Scope,
+    uploads: Mutex<HashMap<Id, Upload>>,
+    resolutions: AtomicUsize,
 }
 
 impl Active {
     fn new(txn_id: &TxnId, expires: NetworkTime) -> Self {
         let scope = TCPathBuf::from(txn_id.to_id());
-        Self { expires, scope }
+        Self {
+            buffers: BufferPool::default(),
+            expires,
+            scope,
+            uploads: Mutex::new(HashMap::new()),
+            resolutions: AtomicUsize::new(0),
+        }
     }
 
     fn expires(&self) -> &NetworkTime {
@@ -51,26 +102,51 @@ pub struct Txn {
     active: Arc<Active>,
     gateway: Arc<Gateway>,
     request: Arc<Request>,
+    locale: Arc<Locale>,
     dir: fs::Dir,
+    depth: usize,
+    limits: ExecLimits,
+    op: Option<Arc<String>>,
 }
 
 impl Txn {
-    fn new(active: Arc<Active>, gateway: Arc<Gateway>, dir: fs::Dir, request: Request) -> Self {
+    fn new(
+        active: Arc<Active>,
+        gateway: Arc<Gateway>,
+        dir: fs::Dir,
+        request: Request,
+        locale: Locale,
+    ) -> Self {
         let request = Arc::new(request);
+        let locale = Arc::new(locale);
 
         Self {
             active,
             gateway,
             request,
+            locale,
             dir,
+            depth: 0,
+            limits: ExecLimits::current(),
+            op: None,
         }
     }
 
+    /// Borrow the [`BufferPool`] of this transaction, which is freed when it's finalized.
+    pub fn buffers(&'_ self) -> &'_ BufferPool {
+        &self.active.buffers
+    }
+
     /// Return the current number of strong references to this `Txn`.
     pub fn ref_count(&self) -> usize {
         Arc::strong_count(&self.active)
     }
 
+    /// Record that this transaction has been committed at this host.
+    pub(crate) fn finish(&self) {
+        self.gateway.finish(self.id());
+    }
+
     /// Claim ownership of this transaction.
     pub async fn claim(self, actor: &Actor, cluster_path: TCPathBuf) -> TCResult<Self> {
         debug!("{} claims transaction {}", cluster_path, self.id());
@@ -122,6 +198,10 @@ impl Txn {
             gateway: self.gateway.clone(),
             dir: self.dir.clone(),
             request: Arc::new(Request::new(*txn_id, token, claims)),
+            locale: self.locale.clone(),
+            depth: self.depth,
+            limits: self.limits,
+            op: self.op.clone(),
         })
     }
 
@@ -151,11 +231,107 @@ impl Txn {
         self.gateway.link(path)
     }
 
+    /// The depth of the op call which this context belongs to, or zero outside of any op.
+    pub fn depth(&self) -> usize {
+        self.depth
+    }
+
+    /// Continue a transaction from another host, where it was called at the given `depth`.
+    pub(crate) fn resume(mut self, depth: usize) -> Self {
+        self.depth = depth;
+        self
+    }
+
+    /// Return the context in which to call an op on the given `subject`, one level deeper than
+    /// this one.
+    ///
+    /// Returns a "policy violation" error if the call would exceed the [`ExecLimits`] of the
+    /// cluster which owns the op, or of this host if no cluster has limits of its own.
+    pub fn enter(&self, subject: &Subject) -> TCResult<Self> {
+        let op = subject.to_string();
+        let limits = match subject {
+            Subject::Link(link) if self.gateway.is_local(link) => {
+                self.gateway.exec_limits(link.path())
+            }
+            _ => self.limits,
+        };
+
+        let max_depth = limits.max_depth;
+        if self.depth >= max_depth {
+            return Err(TCError::policy_violation(format!(
+                "op {} exceeds the maximum call depth of {}, called from {}",
+                op,
+                max_depth,
+                self.op
+                    .as_ref()
+                    .map(|op| op.as_str())
+                    .unwrap_or("the request")
+            )));
+        }
+
+        let mut txn = self.clone();
+        txn.depth += 1;
+        txn.limits = limits;
+        txn.op = Some(Arc::new(op));
+        Ok(txn)
+    }
+
+    /// Count the resolution of one more reference within this transaction.
+    ///
+    /// Returns a "policy violation" error which names the op being executed, if this exceeds the
+    /// [`ExecLimits`] of that op.
+    pub fn count_resolution(&self) -> TCResult<()> {
+        let max_resolutions = self.limits.max_resolutions;
+        let count = self.active.resolutions.fetch_add(1, Ordering::Relaxed) + 1;
+        if count > max_resolutions {
+            let op = match &self.op {
+                Some(op) => format!("op {}", op),
+                None => format!("transaction {}", self.id()),
+            };
+
+            Err(TCError::policy_violation(format!(
+                "{} exceeds the maximum of {} reference resolutions per transaction",
+                op, max_resolutions
+            )))
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Register a file uploaded within this transaction, and return a link to it.
+    pub(crate) fn upload(&self, upload: Upload) -> Link {
+        let id: Id = Uuid::new_v4().into();
+        let path = TCPathBuf::from(UPLOADS).append(id.clone());
+
+        self.active
+            .uploads
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .insert(id, upload);
+
+        self.link(path)
+    }
+
+    /// Return the file with the given ID uploaded within this transaction, if there is one.
+    pub(crate) fn uploaded(&self, id: &Id) -> Option<Upload> {
+        let uploads = self
+            .active
+            .uploads
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        uploads.get(id).cloned()
+    }
+
     /// Return the [`Request`] which initiated this transaction on this host.
     pub fn request(&'_ self) -> &'_ Request {
         &self.request
     }
 
+    /// Return the [`Locale`] of the request which initiated this transaction on this host.
+    pub fn locale(&'_ self) -> &'_ Locale {
+        &self.locale
+    }
+
     /// Resolve a GET op within this transaction context.
     pub async fn get(&self, link: Link, key: Value) -> TCResult<State> {
         self.gateway.get(self, link, key).await
@@ -189,7 +365,11 @@ impl Transaction<fs::Dir> for Txn {
             active: self.active.clone(),
             gateway: self.gateway.clone(),
             request: self.request.clone(),
+            locale: self.locale.clone(),
             dir,
+            depth: self.depth,
+            limits: self.limits,
+            op: self.op.clone(),
         })
     }
 }
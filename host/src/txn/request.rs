@@ -85,6 +85,18 @@ impl<'a> rjwt::Resolve for Resolver<'a> {
     }
 
     async fn resolve(&self, host: &Link, actor_id: &Value) -> Result<Actor, rjwt::Error> {
+        // pick the key which signed the claim by its key ID, if this host already knows it
+        let local = host.host().as_ref() == Some(self.gateway.root());
+        let known = self
+            .gateway
+            .keys()
+            .resolve(host, actor_id, local)
+            .map_err(|e| rjwt::Error::new(rjwt::ErrorKind::Format, e))?;
+
+        if let Some(actor) = known {
+            return Ok(actor);
+        }
+
         let public_key: String = self
             .gateway
             .fetch(&self.txn_id, host, actor_id)
@@ -16,7 +16,7 @@ use crate::fs;
 use crate::gateway::Gateway;
 
 use super::request::*;
-use super::{Active, Txn, TxnId};
+use super::{Active, Locale, Txn, TxnId};
 use std::convert::TryInto;
 
 /// Server to keep track of the transactions currently active for this host.
@@ -42,6 +42,7 @@ impl TxnServer {
         gateway: Arc<Gateway>,
         txn_id: TxnId,
         token: (String, Claims),
+        locale: Locale,
     ) -> TCResult<Txn> {
         let expires = token.1.expires().try_into()?;
         let dir = self.txn_dir(&txn_id).await?;
@@ -51,11 +52,17 @@ impl TxnServer {
         match active.entry(txn_id) {
             Entry::Occupied(entry) => {
                 let active = entry.get();
-                Ok(Txn::new(active.clone(), gateway, dir, request))
+                Ok(Txn::new(active.clone(), gateway, dir, request, locale))
             }
             Entry::Vacant(entry) => {
                 let active = Arc::new(Active::new(&txn_id, expires));
-                let txn = Txn::new(active.clone(), gateway, self.workspace.clone(), request);
+                let txn = Txn::new(
+                    active.clone(),
+                    gateway,
+                    self.workspace.clone(),
+                    request,
+                    locale,
+                );
                 entry.insert(active);
                 Ok(txn)
             }
@@ -0,0 +1,291 @@
+//! The WebSocket interface for `Gateway`, which allows a client to submit many requests over a
+//! single persistent connection.
+//!
+//! Each request is a text message containing a JSON object with an `"id"` chosen by the client,
+//! a `"method"` (`GET`, `PUT`, or `POST`), and a `"path"`, plus a `"key"` for a GET or PUT
+//! request, a `"value"` for a PUT request, or `"params"` for a POST request, e.g.
+//! `{"id": 1, "method": "GET", "path": "/app/users", "key": "alice"}`. A request may also
+//! specify a `"txn_id"` and a bearer `"token"`.
+//!
+//! The requests sent over one connection are handled in order. If a request succeeds, the host
+//! responds with the text message `{"id": 1, "status": "ok"}`, then the JSON encoding of the
+//! result in one or more binary messages as it's encoded, then `{"id": 1, "status": "end"}`.
+//! Otherwise it responds with a single text message like
+//! `{"id": 1, "status": "error", "code": "not found", "message": "..."}`.
+
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use futures::{future, stream, Sink, SinkExt, StreamExt, TryFutureExt};
+use log::{debug, warn};
+use tokio::net::{TcpListener, TcpStream};
+use tokio_tungstenite::tungstenite::{Error as WsError, Message};
+
+use tc_error::*;
+use tc_transact::{IntoView, TxnId};
+use tcgeneric::TCPathBuf;
+
+use crate::gateway::{self, Gateway};
+use crate::scalar::{DecodeContext, Value};
+use crate::state::State;
+use crate::txn::*;
+
+/// Tinychain's WebSocket server. Should only be used through a [`Gateway`].
+pub struct WebSocketServer {
+    gateway: Arc<Gateway>,
+}
+
+impl WebSocketServer {
+    pub(crate) fn new(gateway: Arc<Gateway>) -> Self {
+        Self { gateway }
+    }
+
+    async fn handle(self: Arc<Self>, stream: TcpStream, peer: SocketAddr) {
+        let socket = match tokio_tungstenite::accept_async(stream).await {
+            Ok(socket) => socket,
+            Err(cause) => {
+                warn!("WebSocket handshake with {} failed: {}", peer, cause);
+                return;
+            }
+        };
+
+        let (mut sink, mut source) = socket.split();
+        while let Some(message) = source.next().await {
+            let request = match message {
+                Ok(Message::Text(request)) => request,
+                Ok(Message::Close(_)) => break,
+                Ok(Message::Binary(_)) => {
+                    let cause = TCError::bad_request("expected a text message", "binary");
+                    if sink
+                        .send(error(&serde_json::Value::Null, cause))
+                        .await
+                        .is_err()
+                    {
+                        break;
+                    } else {
+                        continue;
+                    }
+                }
+                Ok(_) => continue,
+                Err(cause) => {
+                    debug!("WebSocket connection to {} failed: {}", peer, cause);
+                    break;
+                }
+            };
+
+            if let Err(cause) = self.respond(&mut sink, peer, request).await {
+                debug!("WebSocket connection to {} failed: {}", peer, cause);
+                break;
+            }
+        }
+    }
+
+    async fn respond<S>(
+        &self,
+        sink: &mut S,
+        peer: SocketAddr,
+        request: String,
+    ) -> Result<(), WsError>
+    where
+        S: Sink<Message, Error = WsError> + Unpin,
+    {
+        let request: serde_json::Value = match serde_json::from_str(&request) {
+            Ok(request) => request,
+            Err(cause) => {
+                let cause = TCError::bad_request("invalid WebSocket request", cause);
+                return sink.send(error(&serde_json::Value::Null, cause)).await;
+            }
+        };
+
+        let id = request["id"].clone();
+        let (txn, state) = match self.execute(peer, &request).await {
+            Ok(result) => result,
+            Err(cause) => return sink.send(error(&id, cause)).await,
+        };
+
+        let encoded = match destream_json::encode(state.into_view(txn)) {
+            Ok(encoded) => encoded,
+            Err(cause) => return sink.send(error(&id, TCError::internal(cause))).await,
+        };
+
+        sink.send(status(&id, "ok")).await?;
+
+        let mut encoded = Box::pin(encoded);
+        while let Some(chunk) = encoded.next().await {
+            match chunk {
+                Ok(chunk) => sink.send(Message::Binary(chunk)).await?,
+                Err(cause) => return sink.send(error(&id, TCError::internal(cause))).await,
+            }
+        }
+
+        sink.send(status(&id, "end")).await
+    }
+
+    async fn execute(
+        &self,
+        peer: SocketAddr,
+        request: &serde_json::Value,
+    ) -> TCResult<(Txn, State)> {
+        let field = |name: &str| {
+            request[name]
+                .as_str()
+                .ok_or_else(|| TCError::bad_request("WebSocket request is missing the field", name))
+        };
+
+        let method = field("method")?;
+        let path: TCPathBuf = field("path")?.parse()?;
+
+        let txn_id = match request["txn_id"].as_str() {
+            Some(txn_id) => txn_id.parse()?,
+            None => TxnId::new(Gateway::time()),
+        };
+
+        let token = request["token"].as_str().map(String::from);
+
+        self.gateway
+            .rate_limit(peer.ip(), token.as_deref(), method != "GET")
+            .map_err(gateway::too_many_requests)?;
+
+        let txn = self
+            .gateway
+            .new_txn(txn_id, token, Locale::default(), method != "GET")
+            .await?;
+
+        let state = match method {
+            "GET" => {
+                let key = key(request)?;
+                self.gateway.get(&txn, path.into(), key).await
+            }
+            "PUT" => {
+                let key = key(request)?;
+                let value = decode(txn.clone(), &request["value"]).await?;
+                self.gateway
+                    .put(&txn, path.into(), key, value)
+                    .map_ok(State::from)
+                    .await
+            }
+            "POST" => {
+                let params = decode(txn.clone(), &request["params"]).await?;
+                self.gateway.post(&txn, path.into(), params).await
+            }
+            other => Err(TCError::method_not_allowed(other)),
+        }?;
+
+        Ok((txn, state))
+    }
+}
+
+#[async_trait]
+impl crate::gateway::Server for WebSocketServer {
+    type Error = std::io::Error;
+
+    async fn listen(self, addr: SocketAddr) -> Result<(), Self::Error> {
+        let listener = TcpListener::bind(&addr).await?;
+        println!("WebSocket server listening on {}", &addr);
+
+        let server = Arc::new(self);
+        loop {
+            tokio::select! {
+                connection = listener.accept() => {
+                    let (stream, peer) = connection?;
+                    tokio::spawn(server.clone().handle(stream, peer));
+                }
+                _ = tokio::signal::ctrl_c() => break Ok(()),
+            }
+        }
+    }
+}
+
+fn key(request: &serde_json::Value) -> TCResult<Value> {
+    serde_json::from_value(request["key"].clone())
+        .map_err(|e| TCError::bad_request("invalid key in WebSocket request", e))
+}
+
+async fn decode(txn: Txn, data: &serde_json::Value) -> TCResult<State> {
+    let data = stream::once(future::ready(Ok(data.to_string().into_bytes())));
+    let mut decoder = destream_json::de::Decoder::from(data);
+    State::decode(txn, DecodeContext::default(), &mut decoder)
+        .map_err(|e| TCError::bad_request("error deserializing WebSocket request", e))
+        .await
+}
+
+fn status(id: &serde_json::Value, status: &str) -> Message {
+    Message::Text(serde_json::json!({"id": id, "status": status}).to_string())
+}
+
+fn error(id: &serde_json::Value, cause: TCError) -> Message {
+    let error = serde_json::json!({
+        "id": id,
+        "status": "error",
+        "code": cause.code().to_string(),
+        "message": cause.message(),
+    });
+
+    Message::Text(error.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::channel::mpsc;
+    use serde_json::json;
+
+    use crate::test::TestHost;
+
+    use super::*;
+
+    async fn respond(server: &WebSocketServer, request: serde_json::Value) -> Vec<Message> {
+        let (sink, source) = mpsc::unbounded();
+        let mut sink = sink.sink_map_err(|_| WsError::ConnectionClosed);
+        let peer = "127.0.0.1:8702".parse().unwrap();
+
+        server
+            .respond(&mut sink, peer, request.to_string())
+            .await
+            .expect("send response");
+
+        drop(sink);
+        source.collect().await
+    }
+
+    fn text(message: &Message) -> serde_json::Value {
+        match message {
+            Message::Text(text) => serde_json::from_str(text).expect("JSON status"),
+            other => panic!("expected a text message but found {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_respond() -> TCResult<()> {
+        let host = TestHost::new(vec![]).await?;
+        let server = WebSocketServer::new(host.gateway().clone());
+
+        let path = "/state/scalar/value/number/bool";
+        let request = json!({"id": 1, "method": "GET", "path": path, "key": 1});
+        let messages = respond(&server, request).await;
+        assert_eq!(text(&messages[0]), json!({"id": 1, "status": "ok"}));
+        assert_eq!(
+            text(&messages[messages.len() - 1]),
+            json!({"id": 1, "status": "end"})
+        );
+
+        let body: Vec<u8> = messages[1..messages.len() - 1]
+            .iter()
+            .flat_map(|message| match message {
+                Message::Binary(chunk) => chunk.clone(),
+                other => panic!("expected a binary message but found {:?}", other),
+            })
+            .collect();
+
+        let body: serde_json::Value = serde_json::from_slice(&body).expect("JSON body");
+        assert_eq!(body, json!(true));
+
+        let request = json!({"id": 2, "method": "DELETE", "path": path});
+        let messages = respond(&server, request).await;
+        assert_eq!(messages.len(), 1);
+        assert_eq!(text(&messages[0])["status"], "error");
+        assert_eq!(text(&messages[0])["id"], 2);
+
+        Ok(())
+    }
+}
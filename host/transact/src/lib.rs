@@ -12,6 +12,10 @@ mod id;
 
 pub mod fs;
 pub mod lock;
+pub mod stats;
+
+#[cfg(feature = "simulation")]
+pub mod sim;
 
 pub use id::TxnId;
 
@@ -6,6 +6,7 @@ use std::fmt;
 use std::ops::{Deref, DerefMut};
 use std::pin::Pin;
 use std::sync::{Arc, Mutex};
+use std::time::Instant;
 
 use async_trait::async_trait;
 use futures::future::{self, Future};
@@ -14,7 +15,7 @@ use log::debug;
 
 use tc_error::*;
 
-use super::{Transact, TxnId};
+use super::{stats, Transact, TxnId};
 
 /// Define a way to manage transaction-specific versions of a state.
 #[async_trait]
@@ -71,6 +72,7 @@ impl<T: Mutate> TxnLockReadGuard<T> {
         TxnLockWriteFuture {
             txn_id: self.txn_id,
             lock: self.lock.clone(),
+            waiting: None,
         }
     }
 }
@@ -271,6 +273,7 @@ impl<T: Mutate> TxnLock<T> {
         TxnLockReadFuture {
             txn_id,
             lock: self.clone(),
+            waiting: None,
         }
     }
 
@@ -321,6 +324,7 @@ impl<T: Mutate> TxnLock<T> {
         TxnLockWriteFuture {
             txn_id,
             lock: self.clone(),
+            waiting: None,
         }
     }
 }
@@ -379,17 +383,26 @@ impl<T: Mutate> Transact for TxnLock<T> {
 pub struct TxnLockReadFuture<'a, T: Mutate> {
     txn_id: &'a TxnId,
     lock: TxnLock<T>,
+    waiting: Option<Instant>,
 }
 
 impl<'a, T: Mutate> Future for TxnLockReadFuture<'a, T> {
     type Output = TCResult<TxnLockReadGuard<T>>;
 
     fn poll(self: Pin<&mut Self>, context: &mut Context) -> Poll<Self::Output> {
-        match self.lock.try_read(&self.txn_id) {
-            Ok(Some(guard)) => Poll::Ready(Ok(guard)),
+        let this = self.get_mut();
+        match this.lock.try_read(this.txn_id) {
+            Ok(Some(guard)) => {
+                if let Some(waiting) = this.waiting {
+                    stats::lock_wait(this.txn_id, waiting.elapsed());
+                }
+
+                Poll::Ready(Ok(guard))
+            }
             Err(cause) => Poll::Ready(Err(cause)),
             Ok(None) => {
-                self.lock
+                this.waiting.get_or_insert_with(Instant::now);
+                this.lock
                     .inner
                     .lock()
                     .unwrap()
@@ -407,17 +420,26 @@ impl<'a, T: Mutate> Future for TxnLockReadFuture<'a, T> {
 pub struct TxnLockWriteFuture<T: Mutate> {
     txn_id: TxnId,
     lock: TxnLock<T>,
+    waiting: Option<Instant>,
 }
 
 impl<T: Mutate> Future for TxnLockWriteFuture<T> {
     type Output = TCResult<TxnLockWriteGuard<T>>;
 
     fn poll(self: Pin<&mut Self>, context: &mut Context) -> Poll<Self::Output> {
-        match self.lock.try_write(&self.txn_id) {
-            Ok(Some(guard)) => Poll::Ready(Ok(guard)),
+        let this = self.get_mut();
+        match this.lock.try_write(&this.txn_id) {
+            Ok(Some(guard)) => {
+                if let Some(waiting) = this.waiting {
+                    stats::lock_wait(&this.txn_id, waiting.elapsed());
+                }
+
+                Poll::Ready(Ok(guard))
+            }
             Err(cause) => Poll::Ready(Err(cause)),
             Ok(None) => {
-                self.lock
+                this.waiting.get_or_insert_with(Instant::now);
+                this.lock
                     .inner
                     .lock()
                     .unwrap()
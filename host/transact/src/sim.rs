@@ -0,0 +1,263 @@
+//! A deterministic, single-threaded executor with virtual time, for reproducing concurrency
+//! bugs (deadlocks, lost updates, etc.) in code which uses [`crate::lock`]. UNSTABLE.
+//!
+//! Tasks are polled in an order chosen by a seeded pseudo-random number generator, so a given
+//! seed always produces the same interleaving. Time only advances when every task is blocked
+//! waiting on a [`sleep`], at which point the clock skips ahead to the earliest deadline.
+//!
+//! Example:
+//! ```
+//! use tc_transact::sim::Simulation;
+//!
+//! let mut sim = Simulation::new(42);
+//! sim.spawn(async { /* ... */ });
+//! sim.spawn(async { /* ... */ });
+//! sim.run().expect("deadlock");
+//! ```
+
+use std::cell::{Cell, RefCell};
+use std::collections::BTreeSet;
+use std::fmt;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::Duration;
+
+use futures::future::{Future, LocalBoxFuture};
+use futures::task::{self, ArcWake, Context, Poll, Waker};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+use tcgeneric::NetworkTime;
+
+thread_local! {
+    static CLOCK: Cell<u64> = Cell::new(0);
+    static TIMERS: RefCell<Vec<(u64, Waker)>> = RefCell::new(Vec::new());
+}
+
+/// Return the current virtual time of the simulation running on this thread.
+pub fn now() -> NetworkTime {
+    NetworkTime::from_nanos(CLOCK.with(|clock| clock.get()))
+}
+
+/// Return a future which resolves once the virtual clock has advanced by `duration`.
+pub fn sleep(duration: Duration) -> Sleep {
+    Sleep {
+        deadline: now().as_nanos() + duration.as_nanos() as u64,
+    }
+}
+
+/// A future which resolves at a deadline in virtual time; see [`sleep`].
+pub struct Sleep {
+    deadline: u64,
+}
+
+impl Future for Sleep {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, cxt: &mut Context) -> Poll<()> {
+        if now().as_nanos() >= self.deadline {
+            Poll::Ready(())
+        } else {
+            let timer = (self.deadline, cxt.waker().clone());
+            TIMERS.with(|timers| timers.borrow_mut().push(timer));
+            Poll::Pending
+        }
+    }
+}
+
+/// The error returned when every remaining task is blocked and no timer can wake any of them.
+#[derive(Debug)]
+pub struct Deadlock {
+    pending: usize,
+}
+
+impl Deadlock {
+    /// The number of tasks which never completed.
+    pub fn pending(&self) -> usize {
+        self.pending
+    }
+}
+
+impl std::error::Error for Deadlock {}
+
+impl fmt::Display for Deadlock {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "deadlock: {} tasks can never complete", self.pending)
+    }
+}
+
+struct TaskWaker {
+    id: usize,
+    ready: Arc<Mutex<BTreeSet<usize>>>,
+}
+
+impl ArcWake for TaskWaker {
+    fn wake_by_ref(arc_self: &Arc<Self>) {
+        let mut ready = arc_self
+            .ready
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        ready.insert(arc_self.id);
+    }
+}
+
+/// A deterministic executor; see the [module-level documentation](self).
+pub struct Simulation {
+    rng: StdRng,
+    tasks: Vec<Option<LocalBoxFuture<'static, ()>>>,
+    ready: Arc<Mutex<BTreeSet<usize>>>,
+}
+
+impl Simulation {
+    /// Construct a new `Simulation` whose task ordering is determined by the given `seed`.
+    ///
+    /// This resets the virtual clock of the current thread to zero.
+    pub fn new(seed: u64) -> Self {
+        CLOCK.with(|clock| clock.set(0));
+        TIMERS.with(|timers| timers.borrow_mut().clear());
+
+        Self {
+            rng: StdRng::seed_from_u64(seed),
+            tasks: Vec::new(),
+            ready: Arc::new(Mutex::new(BTreeSet::new())),
+        }
+    }
+
+    /// Add a task to this `Simulation`.
+    pub fn spawn<F: Future<Output = ()> + 'static>(&mut self, task: F) {
+        let id = self.tasks.len();
+        self.tasks.push(Some(Box::pin(task)));
+        self.ready
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .insert(id);
+    }
+
+    /// Run every task to completion, or return a [`Deadlock`] if that's impossible.
+    pub fn run(mut self) -> Result<(), Deadlock> {
+        loop {
+            let next = {
+                let mut ready = self.ready.lock().unwrap_or_else(PoisonError::into_inner);
+                if ready.is_empty() {
+                    None
+                } else {
+                    let i = self.rng.gen_range(0, ready.len());
+                    let id = *ready.iter().nth(i).unwrap();
+                    ready.remove(&id);
+                    Some(id)
+                }
+            };
+
+            if let Some(id) = next {
+                self.poll(id);
+            } else if !self.advance_clock() {
+                break;
+            }
+        }
+
+        let pending = self.tasks.iter().filter(|task| task.is_some()).count();
+        if pending == 0 {
+            Ok(())
+        } else {
+            Err(Deadlock { pending })
+        }
+    }
+
+    fn poll(&mut self, id: usize) {
+        let waker = task::waker(Arc::new(TaskWaker {
+            id,
+            ready: self.ready.clone(),
+        }));
+
+        let mut cxt = Context::from_waker(&waker);
+
+        if let Some(task) = &mut self.tasks[id] {
+            if task.as_mut().poll(&mut cxt).is_ready() {
+                self.tasks[id] = None;
+            }
+        }
+    }
+
+    // advance the clock to the earliest pending deadline and wake its timers, if there are any
+    fn advance_clock(&mut self) -> bool {
+        let expired = TIMERS.with(|timers| {
+            let mut timers = timers.borrow_mut();
+            let deadline = if let Some(deadline) = timers.iter().map(|(d, _)| *d).min() {
+                deadline
+            } else {
+                return Vec::new();
+            };
+
+            CLOCK.with(|clock| clock.set(deadline));
+
+            let (expired, pending): (Vec<_>, Vec<_>) =
+                timers.drain(..).partition(|(d, _)| *d <= deadline);
+            *timers = pending;
+            expired
+        });
+
+        if expired.is_empty() {
+            false
+        } else {
+            for (_, waker) in expired {
+                waker.wake();
+            }
+
+            true
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::rc::Rc;
+
+    use super::*;
+
+    fn interleaving(seed: u64) -> Vec<usize> {
+        let order = Rc::new(RefCell::new(Vec::new()));
+
+        let mut sim = Simulation::new(seed);
+        for id in 0..4 {
+            let order = order.clone();
+            sim.spawn(async move {
+                for _ in 0..3 {
+                    order.borrow_mut().push(id);
+                    sleep(Duration::from_millis(1)).await;
+                }
+            });
+        }
+
+        sim.run().expect("deadlock");
+
+        let order = order.borrow().clone();
+        order
+    }
+
+    #[test]
+    fn test_same_seed_same_order() {
+        let order = interleaving(7);
+        assert_eq!(order.len(), 12);
+        assert_eq!(order, interleaving(7));
+    }
+
+    #[test]
+    fn test_virtual_time() {
+        let mut sim = Simulation::new(0);
+        sim.spawn(async {
+            sleep(Duration::from_secs(3600)).await;
+            assert_eq!(now().as_nanos(), 3_600_000_000_000);
+        });
+
+        sim.run().expect("deadlock");
+    }
+
+    #[test]
+    fn test_deadlock() {
+        let mut sim = Simulation::new(0);
+        sim.spawn(futures::future::pending());
+        sim.spawn(async {});
+
+        assert_eq!(sim.run().unwrap_err().pending(), 1);
+    }
+}
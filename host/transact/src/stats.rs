@@ -0,0 +1,163 @@
+//! Execution statistics of a transaction, collected only on request.
+
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::Duration;
+
+use super::TxnId;
+
+// the number of transactions currently tracked, so that recording a statistic of an untracked
+// transaction doesn't need to acquire the registry lock
+static TRACKED: AtomicUsize = AtomicUsize::new(0);
+
+static REGISTRY: Mutex<Vec<(TxnId, usize, Arc<Stats>)>> = Mutex::new(Vec::new());
+
+/// The execution statistics of a transaction.
+#[derive(Default)]
+pub struct Stats {
+    lock_wait: AtomicU64,
+    blocks_read: AtomicU64,
+    blocks_written: AtomicU64,
+    device_time: AtomicU64,
+}
+
+impl Stats {
+    /// The total time spent waiting to acquire a transactional lock.
+    pub fn lock_wait(&self) -> Duration {
+        Duration::from_nanos(self.lock_wait.load(Ordering::Relaxed))
+    }
+
+    /// The number of blocks read.
+    pub fn blocks_read(&self) -> u64 {
+        self.blocks_read.load(Ordering::Relaxed)
+    }
+
+    /// The number of blocks written.
+    pub fn blocks_written(&self) -> u64 {
+        self.blocks_written.load(Ordering::Relaxed)
+    }
+
+    /// The total time spent reading and writing blocks, including any filesystem IO.
+    pub fn device_time(&self) -> Duration {
+        Duration::from_nanos(self.device_time.load(Ordering::Relaxed))
+    }
+}
+
+/// Collects the [`Stats`] of a transaction until dropped.
+pub struct Tracker {
+    txn_id: TxnId,
+    stats: Arc<Stats>,
+}
+
+impl Tracker {
+    /// The [`Stats`] collected so far.
+    pub fn stats(&self) -> &Stats {
+        &self.stats
+    }
+}
+
+impl Drop for Tracker {
+    fn drop(&mut self) {
+        let mut registry = REGISTRY.lock().unwrap_or_else(PoisonError::into_inner);
+        if let Some(i) = registry.iter().position(|(id, _, _)| id == &self.txn_id) {
+            registry[i].1 -= 1;
+            if registry[i].1 == 0 {
+                registry.swap_remove(i);
+                TRACKED.fetch_sub(1, Ordering::Relaxed);
+            }
+        }
+    }
+}
+
+/// Begin collecting the [`Stats`] of the given transaction.
+///
+/// Concurrent requests which belong to the same transaction share the same `Stats`.
+pub fn track(txn_id: TxnId) -> Tracker {
+    let mut registry = REGISTRY.lock().unwrap_or_else(PoisonError::into_inner);
+    let stats = if let Some(entry) = registry.iter_mut().find(|(id, _, _)| id == &txn_id) {
+        entry.1 += 1;
+        entry.2.clone()
+    } else {
+        let stats = Arc::new(Stats::default());
+        registry.push((txn_id, 1, stats.clone()));
+        TRACKED.fetch_add(1, Ordering::Relaxed);
+        stats
+    };
+
+    Tracker { txn_id, stats }
+}
+
+/// Record time spent waiting to acquire a transactional lock.
+pub fn lock_wait(txn_id: &TxnId, elapsed: Duration) {
+    record(txn_id, |stats| add_duration(&stats.lock_wait, elapsed))
+}
+
+/// Record a block read.
+pub fn block_read(txn_id: &TxnId) {
+    record(txn_id, |stats| {
+        stats.blocks_read.fetch_add(1, Ordering::Relaxed);
+    })
+}
+
+/// Record a block written.
+pub fn block_written(txn_id: &TxnId) {
+    record(txn_id, |stats| {
+        stats.blocks_written.fetch_add(1, Ordering::Relaxed);
+    })
+}
+
+/// Record time spent reading or writing a block.
+pub fn device_time(txn_id: &TxnId, elapsed: Duration) {
+    record(txn_id, |stats| add_duration(&stats.device_time, elapsed))
+}
+
+fn record<F: FnOnce(&Stats)>(txn_id: &TxnId, f: F) {
+    if TRACKED.load(Ordering::Relaxed) == 0 {
+        return;
+    }
+
+    let registry = REGISTRY.lock().unwrap_or_else(PoisonError::into_inner);
+    if let Some((_, _, stats)) = registry.iter().find(|(id, _, _)| id == txn_id) {
+        f(stats)
+    }
+}
+
+fn add_duration(counter: &AtomicU64, elapsed: Duration) {
+    counter.fetch_add(elapsed.as_nanos() as u64, Ordering::Relaxed);
+}
+
+#[cfg(test)]
+mod tests {
+    use tcgeneric::NetworkTime;
+
+    use super::*;
+
+    #[test]
+    fn test_track() {
+        let txn_id = TxnId::new(NetworkTime::from_nanos(1));
+        let other = TxnId::new(NetworkTime::from_nanos(2));
+
+        // statistics of an untracked transaction are discarded
+        block_read(&txn_id);
+
+        let tracker = track(txn_id);
+        let shared = track(txn_id);
+        block_read(&txn_id);
+        block_written(&txn_id);
+        block_written(&txn_id);
+        block_written(&other);
+        lock_wait(&txn_id, Duration::from_millis(3));
+
+        assert_eq!(tracker.stats().blocks_read(), 1);
+        assert_eq!(shared.stats().blocks_written(), 2);
+        assert_eq!(tracker.stats().lock_wait(), Duration::from_millis(3));
+
+        // the stats are kept until the last tracker of the transaction is dropped
+        std::mem::drop(shared);
+        block_read(&txn_id);
+        assert_eq!(tracker.stats().blocks_read(), 2);
+
+        std::mem::drop(tracker);
+        assert_eq!(track(txn_id).stats().blocks_read(), 0);
+    }
+}
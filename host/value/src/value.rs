@@ -2,7 +2,10 @@
 
 use std::cmp::Ordering;
 use std::fmt;
+use std::num::IntErrorKind;
 use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering as AtomicOrdering};
+use std::sync::Arc;
 
 use async_trait::async_trait;
 use bytes::Bytes;
@@ -23,6 +26,71 @@ const EMPTY_SEQ: [u8; 0] = [];
 const EXPECTING: &'static str = "a Tinychain value, e.g. 1 or \"two\" or [3]";
 const PREFIX: PathLabel = path_label(&["state", "scalar", "value"]);
 
+/// The largest integer which a JavaScript client can represent exactly, `2^53 - 1`.
+pub const MAX_SAFE_INTEGER: u64 = (1 << 53) - 1;
+
+static PRECISE_INTEGERS: AtomicBool = AtomicBool::new(false);
+
+/// Set whether to encode an integer larger in magnitude than [`MAX_SAFE_INTEGER`] as a string
+/// with a type annotation, like `{"/state/scalar/value/number/uint/64": "18446744073709551615"}`,
+/// so that a JavaScript client doesn't silently lose precision. A typed string like this is
+/// always accepted when decoding, regardless of this setting.
+pub fn set_precise_integers(precise: bool) {
+    PRECISE_INTEGERS.store(precise, AtomicOrdering::Relaxed);
+}
+
+// the decimal representation of `n`, if it should be encoded as a string
+fn precise_integer(n: &Number) -> Option<String> {
+    if !PRECISE_INTEGERS.load(AtomicOrdering::Relaxed) {
+        return None;
+    }
+
+    match n {
+        Number::UInt(u) => {
+            let u = u64::cast_from(*u);
+            if u > MAX_SAFE_INTEGER {
+                Some(u.to_string())
+            } else {
+                None
+            }
+        }
+        Number::Int(i) => {
+            let i = i64::cast_from(*i);
+            if i > MAX_SAFE_INTEGER as i64 || i < -(MAX_SAFE_INTEGER as i64) {
+                Some(i.to_string())
+            } else {
+                None
+            }
+        }
+        _ => None,
+    }
+}
+
+// parse an integer of the given type from its decimal representation, which must be in range
+fn parse_integer(s: &str, class: NumberType) -> Result<Number, String> {
+    use IntType as IT;
+    use NumberType as NT;
+    use UIntType as UT;
+
+    let n = match class {
+        NT::UInt(UT::U8) => s.parse::<u8>().map(Number::from),
+        NT::UInt(UT::U16) => s.parse::<u16>().map(Number::from),
+        NT::UInt(UT::U32) => s.parse::<u32>().map(Number::from),
+        NT::UInt(_) => s.parse::<u64>().map(Number::from),
+        NT::Int(IT::I16) => s.parse::<i16>().map(Number::from),
+        NT::Int(IT::I32) => s.parse::<i32>().map(Number::from),
+        NT::Int(_) => s.parse::<i64>().map(Number::from),
+        _ => return Err(format!("invalid {}: {}", class, s)),
+    };
+
+    n.map(|n| n.into_type(class)).map_err(|cause| match cause.kind() {
+        IntErrorKind::PosOverflow | IntErrorKind::NegOverflow => {
+            format!("{} is out of range for {}", s, class)
+        }
+        _ => format!("invalid {}: {}", class, s),
+    })
+}
+
 /// The class of a [`Value`].
 #[derive(Clone, Copy, Debug, Eq, PartialEq)]
 pub enum ValueType {
@@ -234,7 +302,8 @@ pub enum Value {
     Link(Link),
     None,
     Number(Number),
-    String(String),
+    /// A string, shared between clones so that it's never copied after it's decoded.
+    String(Arc<str>),
     Tuple(Tuple<Self>),
 }
 
@@ -266,7 +335,7 @@ impl Value {
             VT::Number(nt) => Number::opt_cast_from(self)
                 .map(|n| n.into_type(nt))
                 .map(Self::Number),
-            VT::String => Some(Value::String(self.to_string())),
+            VT::String => Some(Value::String(self.to_string().into())),
             VT::Tuple => match self {
                 Self::Tuple(tuple) => Some(Self::Tuple(tuple)),
                 _ => None,
@@ -324,9 +393,16 @@ impl Serialize for Value {
                     map.serialize_entry(&self.class().path().to_string(), &Number::Complex(*c))?;
                     map.end()
                 }
-                n => n.serialize(serializer),
+                n => match precise_integer(n) {
+                    Some(precise) => {
+                        let mut map = serializer.serialize_map(Some(1))?;
+                        map.serialize_entry(&self.class().path().to_string(), &precise)?;
+                        map.end()
+                    }
+                    None => n.serialize(serializer),
+                },
             },
-            Self::String(s) => s.serialize(serializer),
+            Self::String(s) => serializer.serialize_str(s),
             Self::Tuple(t) => t.as_slice().serialize(serializer),
         }
     }
@@ -357,9 +433,16 @@ impl<'en> ToStream<'en> for Value {
                     map.encode_entry(self.class().path().to_string(), Number::Complex(*c))?;
                     map.end()
                 }
-                n => n.to_stream(encoder),
+                n => match precise_integer(n) {
+                    Some(precise) => {
+                        let mut map = encoder.encode_map(Some(1))?;
+                        map.encode_entry(self.class().path().to_string(), precise)?;
+                        map.end()
+                    }
+                    None => n.to_stream(encoder),
+                },
             },
-            Self::String(s) => s.to_stream(encoder),
+            Self::String(s) => encoder.encode_str(s),
             Self::Tuple(t) => t.to_stream(encoder),
         }
     }
@@ -381,9 +464,16 @@ impl<'en> IntoStream<'en> for Value {
                     map.encode_entry(self.class().path().to_string(), Number::Complex(c))?;
                     map.end()
                 }
-                n => n.into_stream(encoder),
+                n => match precise_integer(&n) {
+                    Some(precise) => {
+                        let mut map = encoder.encode_map(Some(1))?;
+                        map.encode_entry(self.class().path().to_string(), precise)?;
+                        map.end()
+                    }
+                    None => n.into_stream(encoder),
+                },
             },
-            Self::String(s) => s.into_stream(encoder),
+            Self::String(s) => encoder.encode_str(&s),
             Self::Tuple(t) => t.into_inner().into_stream(encoder),
         }
     }
@@ -446,14 +536,14 @@ impl TryCastFrom<Value> for Bytes {
 impl TryCastFrom<Value> for Id {
     fn can_cast_from(value: &Value) -> bool {
         match value {
-            Value::String(s) => Self::can_cast_from(s),
+            Value::String(s) => Self::decode(s).is_ok(),
             _ => false,
         }
     }
 
     fn opt_cast_from(value: Value) -> Option<Self> {
         match value {
-            Value::String(s) => Self::opt_cast_from(s),
+            Value::String(s) => Self::decode(&s).ok(),
             _ => None,
         }
     }
@@ -662,13 +752,19 @@ impl ValueVisitor {
                 let _ = map.next_value::<()>()?;
                 Ok(Value::None)
             }
-            VT::Number(nt) => {
-                let n = map.next_value::<Number>()?;
-                Ok(Value::Number(n.into_type(nt)))
-            }
+            VT::Number(nt) => match map.next_value::<Value>()? {
+                Value::Number(n) => Ok(Value::Number(n.into_type(nt))),
+                Value::String(s) => parse_integer(&s, nt)
+                    .map(Value::Number)
+                    .map_err(serde::de::Error::custom),
+                other => Err(serde::de::Error::custom(format!(
+                    "expected {} but found {}",
+                    nt, other
+                ))),
+            },
             VT::String => {
-                let s = map.next_value()?;
-                Ok(Value::String(s))
+                let s: String = map.next_value()?;
+                Ok(Value::String(s.into()))
             }
             VT::Tuple => {
                 let t = map.next_value::<Vec<Value>>()?;
@@ -700,13 +796,16 @@ impl ValueVisitor {
                 let _ = map.next_value::<()>(()).await?;
                 Ok(Value::None)
             }
-            VT::Number(nt) => {
-                let n = map.next_value::<Number>(()).await?;
-                Ok(Value::Number(n.into_type(nt)))
-            }
+            VT::Number(nt) => match map.next_value::<Value>(()).await? {
+                Value::Number(n) => Ok(Value::Number(n.into_type(nt))),
+                Value::String(s) => parse_integer(&s, nt)
+                    .map(Value::Number)
+                    .map_err(DestreamError::custom),
+                other => Err(DestreamError::invalid_type(other, nt)),
+            },
             VT::String => {
-                let s = map.next_value(()).await?;
-                Ok(Value::String(s))
+                let s: String = map.next_value(()).await?;
+                Ok(Value::String(s.into()))
             }
             VT::Tuple => {
                 let t = map.next_value::<Vec<Value>>(()).await?;
@@ -772,15 +871,15 @@ impl<'de> serde::de::Visitor<'de> for ValueVisitor {
     }
 
     fn visit_str<E: SerdeError>(self, s: &str) -> Result<Self::Value, E> {
-        Ok(Value::String(s.to_string()))
+        Ok(Value::String(s.into()))
     }
 
     fn visit_borrowed_str<E: SerdeError>(self, s: &'de str) -> Result<Self::Value, E> {
-        Ok(Value::String(s.to_string()))
+        Ok(Value::String(s.into()))
     }
 
     fn visit_string<E: SerdeError>(self, s: String) -> Result<Self::Value, E> {
-        Ok(Value::String(s))
+        Ok(Value::String(s.into()))
     }
 
     fn visit_unit<E: SerdeError>(self) -> Result<Self::Value, E> {
@@ -882,7 +981,7 @@ impl destream::de::Visitor for ValueVisitor {
     }
 
     fn visit_string<E: DestreamError>(self, s: String) -> Result<Self::Value, E> {
-        Ok(Value::String(s))
+        Ok(Value::String(s.into()))
     }
 
     fn visit_byte_buf<E: DestreamError>(self, _buf: Vec<u8>) -> Result<Self::Value, E> {
@@ -936,3 +1035,27 @@ impl destream::de::Visitor for ValueVisitor {
         Ok(Value::Tuple(value.into()))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_shared_string() {
+        let value = Value::String("name".into());
+        match (&value, &value.clone()) {
+            (Value::String(left), Value::String(right)) => assert!(Arc::ptr_eq(left, right)),
+            _ => panic!("expected a String"),
+        }
+
+        assert!(Id::can_cast_from(&value));
+        let id: Id = value.opt_cast_into().expect("Id");
+        assert_eq!(id, "name");
+
+        let path = Value::String("/state/scalar".into());
+        let path: TCPathBuf = path.opt_cast_into().expect("path");
+        assert_eq!(path.to_string(), "/state/scalar");
+
+        assert!(!Id::can_cast_from(&Value::String("$name".into())));
+    }
+}
@@ -0,0 +1,583 @@
+use std::collections::HashSet;
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime};
+
+use async_trait::async_trait;
+use bytes::Bytes;
+use futures::stream::{self, Stream, StreamExt};
+use log::{debug, warn};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use uuid::Uuid;
+
+use crate::class::State;
+use crate::error;
+use crate::general::Map;
+use crate::handler::Public;
+use crate::lock::RwLock;
+use crate::request::Request;
+use crate::scalar::*;
+use crate::transaction::Txn;
+use crate::{TCResult, TCStream, TryCastInto};
+
+const CHUNK_SIZE: usize = 64 * 1024;
+const HASH_LEN: usize = 64;
+const REFS_EXT: &str = "refs";
+const TMP_DIR: &str = "tmp";
+
+/// An immutable, content-addressed store of binary blobs, available at `/sbin/blobs`.
+///
+/// A blob is uploaded by streaming its contents to `POST /sbin/blobs`, which responds with its
+/// Blake3 hash, and downloaded by streaming `GET /sbin/blobs/<hash>`. A cluster which depends on a
+/// blob registers a reference to it with `PUT /sbin/blobs/<hash>/refs?key=<cluster path>`, and
+/// releases it with `DELETE`. `POST /sbin/blobs/gc` deletes every blob which has no references
+/// and which was last uploaded longer ago than the grace period, so that a blob can't be
+/// collected before its uploader has a chance to reference it.
+///
+/// Blobs and their references are written directly to disk, outside of any transaction.
+pub struct BlobStore {
+    root: PathBuf,
+    grace_period: Duration,
+    lock: RwLock<()>,
+}
+
+impl BlobStore {
+    pub fn new(root: PathBuf, grace_period: Duration) -> BlobStore {
+        BlobStore {
+            root,
+            grace_period,
+            lock: RwLock::new(()),
+        }
+    }
+
+    /// Write the contents of `data` to this store, and return their hash.
+    pub async fn upload<S: Stream<Item = Result<Bytes, hyper::Error>> + Unpin>(
+        &self,
+        mut data: S,
+    ) -> TCResult<String> {
+        let tmp_dir = self.root.join(TMP_DIR);
+        tokio::fs::create_dir_all(&tmp_dir)
+            .await
+            .map_err(|e| error::internal(format!("Unable to create blob directory: {}", e)))?;
+
+        let tmp_path = tmp_dir.join(Uuid::new_v4().to_string());
+        let mut file = tokio::fs::File::create(&tmp_path)
+            .await
+            .map_err(|e| error::internal(format!("Unable to create blob: {}", e)))?;
+
+        let mut hasher = blake3::Hasher::new();
+        let mut size = 0;
+        while let Some(chunk) = data.next().await {
+            let chunk = match chunk {
+                Ok(chunk) => chunk,
+                Err(cause) => {
+                    remove_file(&tmp_path).await;
+                    return Err(cause.into());
+                }
+            };
+
+            hasher.update(&chunk);
+            size += chunk.len();
+            if let Err(cause) = file.write_all(&chunk).await {
+                remove_file(&tmp_path).await;
+                return Err(error::internal(format!("Unable to write blob: {}", cause)));
+            }
+        }
+
+        file.sync_all()
+            .await
+            .map_err(|e| error::internal(format!("Unable to write blob: {}", e)))?;
+
+        let hash = hasher.finalize().to_hex().to_string();
+        let path = self.root.join(&hash);
+
+        let _lock = self.lock.write().await;
+        if tokio::fs::metadata(&path).await.is_ok() {
+            debug!("blob {} already exists", hash);
+            remove_file(&tmp_path).await;
+
+            // uploading a blob again restarts its grace period, so that it can't be collected
+            // before this uploader has a chance to reference it
+            touch(path).await?;
+        } else {
+            tokio::fs::rename(&tmp_path, &path)
+                .await
+                .map_err(|e| error::internal(format!("Unable to write blob: {}", e)))?;
+
+            debug!("uploaded blob {} of {} bytes", hash, size);
+        }
+
+        Ok(hash)
+    }
+
+    /// Stream the contents of the blob with the given hash.
+    pub async fn download<'a>(&self, hash: &Id) -> TCResult<TCStream<'a, TCResult<Bytes>>> {
+        let path = self.blob_path(hash)?;
+        let file = tokio::fs::File::open(&path)
+            .await
+            .map_err(|_| error::not_found(hash))?;
+
+        let contents = stream::try_unfold(file, |mut file| async move {
+            let mut chunk = vec![0; CHUNK_SIZE];
+            let size = file
+                .read(&mut chunk)
+                .await
+                .map_err(|e| error::internal(format!("Unable to read blob: {}", e)))?;
+
+            if size == 0 {
+                Ok(None)
+            } else {
+                chunk.truncate(size);
+                Ok(Some((Bytes::from(chunk), file)))
+            }
+        });
+
+        Ok(Box::pin(contents))
+    }
+
+    async fn hashes(&self) -> TCResult<Vec<String>> {
+        let mut hashes = vec![];
+
+        let mut entries = match tokio::fs::read_dir(&self.root).await {
+            Ok(entries) => entries,
+            Err(_) => return Ok(hashes),
+        };
+
+        while let Some(entry) = entries
+            .next_entry()
+            .await
+            .map_err(|e| error::internal(format!("Unable to list blobs: {}", e)))?
+        {
+            if let Some(name) = entry.file_name().to_str() {
+                if is_hash(name) {
+                    hashes.push(name.to_string());
+                }
+            }
+        }
+
+        hashes.sort();
+        Ok(hashes)
+    }
+
+    async fn refs(&self, hash: &str) -> TCResult<HashSet<TCPathBuf>> {
+        let path = self.root.join(hash).with_extension(REFS_EXT);
+        let refs = match tokio::fs::read(&path).await {
+            Ok(refs) => refs,
+            Err(_) => return Ok(HashSet::new()),
+        };
+
+        let refs: Vec<String> = serde_json::from_slice(&refs)?;
+        refs.into_iter().map(|path| path.parse()).collect()
+    }
+
+    async fn write_refs(&self, hash: &str, refs: &HashSet<TCPathBuf>) -> TCResult<()> {
+        let path = self.root.join(hash).with_extension(REFS_EXT);
+        if refs.is_empty() {
+            remove_file(&path).await;
+            return Ok(());
+        }
+
+        let mut refs: Vec<String> = refs.iter().map(|path| path.to_string()).collect();
+        refs.sort();
+
+        let refs = serde_json::to_vec(&refs)?;
+        tokio::fs::write(&path, refs)
+            .await
+            .map_err(|e| error::internal(format!("Unable to write blob references: {}", e)))
+    }
+
+    async fn collect_garbage(&self) -> TCResult<Vec<String>> {
+        let _lock = self.lock.write().await;
+
+        let now = SystemTime::now();
+        let mut deleted = vec![];
+        for hash in self.hashes().await? {
+            if !self.refs(&hash).await?.is_empty() {
+                continue;
+            }
+
+            let path = self.root.join(&hash);
+            let modified = tokio::fs::metadata(&path)
+                .await
+                .and_then(|meta| meta.modified())
+                .map_err(|e| error::internal(format!("Unable to read blob metadata: {}", e)))?;
+
+            let age = now.duration_since(modified).unwrap_or_default();
+            if age > self.grace_period {
+                debug!("deleting unreferenced blob {}", hash);
+                remove_file(&path).await;
+                deleted.push(hash);
+            }
+        }
+
+        Ok(deleted)
+    }
+
+    /// Check this store for inconsistencies, and return a description of each one found.
+    ///
+    /// This finds blobs whose contents don't match their hash, reference lists which are
+    /// unreadable or which belong to a blob that doesn't exist, uploads which never finished,
+    /// and unrecognized files. If `repair` is `true`, each problem is fixed by deleting the
+    /// offending file, except for unrecognized files, which are left for an operator to inspect.
+    pub async fn fsck(&self, repair: bool) -> TCResult<Vec<String>> {
+        let _lock = self.lock.write().await;
+
+        let mut problems = vec![];
+        let mut entries = match tokio::fs::read_dir(&self.root).await {
+            Ok(entries) => entries,
+            Err(_) => return Ok(problems),
+        };
+
+        while let Some(entry) = entries
+            .next_entry()
+            .await
+            .map_err(|e| error::internal(format!("Unable to list blobs: {}", e)))?
+        {
+            let path = entry.path();
+            let name = entry.file_name().to_string_lossy().to_string();
+
+            if name == TMP_DIR {
+                for upload in self.incomplete_uploads().await? {
+                    problems.push(format!("incomplete blob upload {:?}", upload));
+                    if repair {
+                        remove_file(&upload).await;
+                    }
+                }
+            } else if is_hash(&name) {
+                let hash = self.checksum(&path).await?;
+                if hash != name {
+                    problems.push(format!("blob {} has contents with hash {}", name, hash));
+                    if repair {
+                        remove_file(&path).await;
+                        remove_file(&path.with_extension(REFS_EXT)).await;
+                    }
+                }
+            } else if let Some(hash) = name.strip_suffix(&format!(".{}", REFS_EXT)) {
+                if !is_hash(hash) {
+                    problems.push(format!("unrecognized file {:?}", path));
+                } else if tokio::fs::metadata(self.root.join(hash)).await.is_err() {
+                    problems.push(format!("references to missing blob {}", hash));
+                    if repair {
+                        remove_file(&path).await;
+                    }
+                } else if let Err(cause) = self.refs(hash).await {
+                    problems.push(format!("unreadable references to blob {}: {}", hash, cause));
+                    if repair {
+                        remove_file(&path).await;
+                    }
+                }
+            } else {
+                problems.push(format!("unrecognized file {:?}", path));
+            }
+        }
+
+        Ok(problems)
+    }
+
+    async fn checksum(&self, path: &PathBuf) -> TCResult<String> {
+        let mut file = tokio::fs::File::open(path)
+            .await
+            .map_err(|e| error::internal(format!("Unable to read blob: {}", e)))?;
+
+        let mut hasher = blake3::Hasher::new();
+        let mut chunk = vec![0; CHUNK_SIZE];
+        loop {
+            let size = file
+                .read(&mut chunk)
+                .await
+                .map_err(|e| error::internal(format!("Unable to read blob: {}", e)))?;
+
+            if size == 0 {
+                break;
+            } else {
+                hasher.update(&chunk[..size]);
+            }
+        }
+
+        Ok(hasher.finalize().to_hex().to_string())
+    }
+
+    async fn incomplete_uploads(&self) -> TCResult<Vec<PathBuf>> {
+        let mut uploads = vec![];
+        let mut entries = match tokio::fs::read_dir(self.root.join(TMP_DIR)).await {
+            Ok(entries) => entries,
+            Err(_) => return Ok(uploads),
+        };
+
+        while let Some(entry) = entries
+            .next_entry()
+            .await
+            .map_err(|e| error::internal(format!("Unable to list blob uploads: {}", e)))?
+        {
+            uploads.push(entry.path());
+        }
+
+        Ok(uploads)
+    }
+
+    fn blob_path(&self, hash: &Id) -> TCResult<PathBuf> {
+        if is_hash(hash.as_str()) {
+            Ok(self.root.join(hash.as_str()))
+        } else {
+            Err(error::bad_request("Invalid blob hash", hash))
+        }
+    }
+
+    async fn exists(&self, hash: &Id) -> TCResult<PathBuf> {
+        let path = self.blob_path(hash)?;
+        if tokio::fs::metadata(&path).await.is_ok() {
+            Ok(path)
+        } else {
+            Err(error::not_found(hash))
+        }
+    }
+}
+
+#[async_trait]
+impl Public for BlobStore {
+    async fn get(
+        &self,
+        _request: &Request,
+        _txn: &Txn,
+        path: &[PathSegment],
+        key: Value,
+    ) -> TCResult<State> {
+        if !key.is_none() {
+            return Err(error::bad_request(
+                "/sbin/blobs takes no key, but found",
+                key,
+            ));
+        }
+
+        if path.is_empty() {
+            let hashes = self.hashes().await?;
+            let hashes: Vec<Value> = hashes
+                .into_iter()
+                .map(|hash| Value::from(TCString::UString(hash)))
+                .collect();
+
+            Ok(State::from(Value::from(hashes)))
+        } else if path.len() == 2 && &path[1] == "refs" {
+            self.exists(&path[0]).await?;
+
+            let _lock = self.lock.read().await;
+            let refs: Vec<Value> = self
+                .refs(path[0].as_str())
+                .await?
+                .into_iter()
+                .map(Link::from)
+                .map(Value::from)
+                .collect();
+
+            Ok(State::from(Value::from(refs)))
+        } else if path.len() == 2 && &path[1] == "size" {
+            let blob = self.exists(&path[0]).await?;
+            let meta = tokio::fs::metadata(&blob)
+                .await
+                .map_err(|e| error::internal(format!("Unable to read blob metadata: {}", e)))?;
+
+            Ok(State::from(Value::from(meta.len())))
+        } else {
+            Err(error::path_not_found(path))
+        }
+    }
+
+    async fn put(
+        &self,
+        _request: &Request,
+        _txn: &Txn,
+        path: &[PathSegment],
+        key: Value,
+        _value: State,
+    ) -> TCResult<()> {
+        if path.len() != 2 || &path[1] != "refs" {
+            return Err(error::method_not_allowed(TCPath::from(path)));
+        }
+
+        let cluster: TCPathBuf =
+            key.try_cast_into(|v| error::bad_request("Invalid cluster path", v))?;
+
+        self.exists(&path[0]).await?;
+
+        let _lock = self.lock.write().await;
+        let mut refs = self.refs(path[0].as_str()).await?;
+        if refs.insert(cluster) {
+            self.write_refs(path[0].as_str(), &refs).await?;
+        }
+
+        Ok(())
+    }
+
+    async fn post(
+        &self,
+        _request: &Request,
+        _txn: &Txn,
+        path: &[PathSegment],
+        params: Map<Scalar>,
+    ) -> TCResult<State> {
+        if path.len() != 1 || &path[0] != "gc" {
+            return Err(error::method_not_allowed(TCPath::from(path)));
+        } else if !params.is_empty() {
+            return Err(error::bad_request(
+                "BlobStore::gc got unrecognized parameters",
+                Value::from(params.keys().cloned().collect::<Vec<Id>>()),
+            ));
+        }
+
+        let deleted: Vec<Value> = self
+            .collect_garbage()
+            .await?
+            .into_iter()
+            .map(|hash| Value::from(TCString::UString(hash)))
+            .collect();
+
+        Ok(State::from(Value::from(deleted)))
+    }
+
+    async fn delete(
+        &self,
+        _request: &Request,
+        _txn: &Txn,
+        path: &[PathSegment],
+        key: Value,
+    ) -> TCResult<()> {
+        if path.len() != 2 || &path[1] != "refs" {
+            return Err(error::method_not_allowed(TCPath::from(path)));
+        }
+
+        let cluster: TCPathBuf =
+            key.try_cast_into(|v| error::bad_request("Invalid cluster path", v))?;
+
+        let _lock = self.lock.write().await;
+        let mut refs = self.refs(path[0].as_str()).await?;
+        if refs.remove(&cluster) {
+            self.write_refs(path[0].as_str(), &refs).await
+        } else {
+            Err(error::not_found(cluster))
+        }
+    }
+}
+
+fn is_hash(name: &str) -> bool {
+    name.len() == HASH_LEN && name.chars().all(|c| c.is_ascii_hexdigit())
+}
+
+async fn remove_file(path: &PathBuf) {
+    if let Err(cause) = tokio::fs::remove_file(path).await {
+        warn!("Unable to remove {:?}: {}", path, cause);
+    }
+}
+
+async fn touch(path: PathBuf) -> TCResult<()> {
+    let touched = tokio::task::spawn_blocking(move || {
+        std::fs::OpenOptions::new()
+            .write(true)
+            .open(&path)
+            .and_then(|file| file.set_modified(SystemTime::now()))
+    })
+    .await
+    .map_err(|e| error::internal(format!("Unable to update blob: {}", e)))?;
+
+    touched.map_err(|e| error::internal(format!("Unable to update blob: {}", e)))
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::TryStreamExt;
+
+    use super::*;
+
+    fn store(grace_period: Duration) -> BlobStore {
+        let root = std::env::temp_dir().join(Uuid::new_v4().to_string());
+        BlobStore::new(root, grace_period)
+    }
+
+    async fn upload(store: &BlobStore, chunks: &[&'static [u8]]) -> TCResult<String> {
+        let chunks: Vec<Result<Bytes, hyper::Error>> = chunks
+            .iter()
+            .map(|chunk| Ok(Bytes::from_static(chunk)))
+            .collect();
+
+        store.upload(stream::iter(chunks)).await
+    }
+
+    #[tokio::test]
+    async fn test_upload_and_download() {
+        let store = store(Duration::from_secs(60));
+
+        let hash = upload(&store, &[b"hello, ", b"world"]).await.unwrap();
+        assert!(is_hash(&hash));
+        assert_eq!(hash, blake3::hash(b"hello, world").to_hex().to_string());
+
+        // uploading the same contents again, in different chunks, finds the same blob
+        assert_eq!(upload(&store, &[b"hello, world"]).await.unwrap(), hash);
+        assert_eq!(store.hashes().await.unwrap(), vec![hash.clone()]);
+
+        let id: Id = hash.parse().unwrap();
+        let contents: Vec<Bytes> = store
+            .download(&id)
+            .await
+            .unwrap()
+            .try_collect()
+            .await
+            .unwrap();
+        assert_eq!(contents.concat(), b"hello, world".to_vec());
+
+        let missing: Id = "0".repeat(HASH_LEN).parse().unwrap();
+        assert!(store.download(&missing).await.is_err());
+
+        let invalid: Id = "invalid".parse().unwrap();
+        assert!(store.blob_path(&invalid).is_err());
+
+        std::fs::remove_dir_all(&store.root).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_collect_garbage() {
+        let store = store(Duration::from_secs(0));
+
+        let referenced = upload(&store, &[b"referenced"]).await.unwrap();
+        let unreferenced = upload(&store, &[b"unreferenced"]).await.unwrap();
+
+        let cluster: TCPathBuf = "/app/cluster".parse().unwrap();
+        let mut refs = HashSet::new();
+        refs.insert(cluster.clone());
+        store.write_refs(&referenced, &refs).await.unwrap();
+        assert_eq!(store.refs(&referenced).await.unwrap(), refs);
+
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        assert_eq!(store.collect_garbage().await.unwrap(), vec![unreferenced]);
+        assert_eq!(store.hashes().await.unwrap(), vec![referenced.clone()]);
+
+        // releasing the last reference deletes the list of references
+        store
+            .write_refs(&referenced, &HashSet::new())
+            .await
+            .unwrap();
+        assert!(store.refs(&referenced).await.unwrap().is_empty());
+        assert_eq!(store.collect_garbage().await.unwrap(), vec![referenced]);
+
+        std::fs::remove_dir_all(&store.root).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_fsck() {
+        let store = store(Duration::from_secs(60));
+        let hash = upload(&store, &[b"contents"]).await.unwrap();
+        assert!(store.fsck(false).await.unwrap().is_empty());
+
+        let corrupt = blake3::hash(b"other contents").to_hex().to_string();
+        std::fs::write(store.root.join(&corrupt), "contents").unwrap();
+        std::fs::write(store.root.join(TMP_DIR).join("upload"), "cont").unwrap();
+        std::fs::write(store.root.join(&hash).with_extension(REFS_EXT), "[1").unwrap();
+        std::fs::write(store.root.join("README"), "").unwrap();
+        assert_eq!(store.fsck(true).await.unwrap().len(), 4);
+
+        // every problem is repaired except the unrecognized file
+        let remaining = store.fsck(false).await.unwrap();
+        assert_eq!(remaining.len(), 1);
+        assert!(remaining[0].starts_with("unrecognized file"));
+        assert_eq!(store.hashes().await.unwrap(), vec![hash]);
+
+        std::fs::remove_dir_all(&store.root).unwrap();
+    }
+}
@@ -1,12 +1,21 @@
 use std::collections::HashMap;
+use std::sync::atomic::{AtomicUsize, Ordering};
 
 use async_trait::async_trait;
 use futures::future::join_all;
+use log::debug;
 
 use crate::transaction::lock::TxnLock;
 use crate::transaction::{Transact, TxnId};
 
-use super::{BlockData, BlockId};
+use super::{hostfs, BlockData, BlockId};
+
+static CACHE_SIZE: AtomicUsize = AtomicUsize::new(usize::MAX);
+
+/// Set the maximum number of bytes of decoded blocks which each file keeps in its cache.
+pub fn set_cache_size(size: usize) {
+    CACHE_SIZE.store(size, Ordering::Relaxed);
+}
 
 pub struct Cache<T: BlockData> {
     blocks: HashMap<BlockId, TxnLock<T>>,
@@ -36,6 +45,47 @@ impl<T: BlockData> Cache<T> {
     pub fn len(&self) -> usize {
         self.blocks.len()
     }
+
+    /// Forget the versions of the blocks in this cache at the finalized `txn_id`, then drop
+    /// decoded blocks while this cache is larger than the configured cache size.
+    ///
+    /// Only a block which no transaction is using and which is stored in `dir` is dropped, so
+    /// that it can be decoded again the next time it's read.
+    pub fn evict(&mut self, txn_id: &TxnId, dir: &hostfs::Dir) {
+        for lock in self.blocks.values() {
+            lock.forget(txn_id);
+        }
+
+        let limit = CACHE_SIZE.load(Ordering::Relaxed);
+        let mut size: usize = self
+            .blocks
+            .values()
+            .map(|lock| lock.canonical().size())
+            .sum();
+
+        if size <= limit {
+            return;
+        }
+
+        let evictable: Vec<BlockId> = self
+            .blocks
+            .iter()
+            .filter(|(_, lock)| lock.is_idle())
+            .filter(|(block_id, _)| matches!(dir.get_block(block_id), Ok(Some(_))))
+            .map(|(block_id, _)| block_id.clone())
+            .collect();
+
+        for block_id in evictable {
+            if size <= limit {
+                break;
+            }
+
+            if let Some(lock) = self.blocks.remove(&block_id) {
+                size -= lock.canonical().size();
+                debug!("evicted block {} from cache", block_id);
+            }
+        }
+    }
 }
 
 #[async_trait]
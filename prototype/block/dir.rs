@@ -10,6 +10,7 @@ use uuid::Uuid;
 
 use crate::chain;
 use crate::collection::btree;
+use crate::collection::table;
 use crate::collection::tensor;
 use crate::error;
 use crate::lock::RwLock;
@@ -27,6 +28,7 @@ pub enum DirEntry {
     Dir(Arc<Dir>),
     BTree(Arc<File<btree::Node>>),
     Chain(Arc<File<chain::ChainBlock>>),
+    Overflow(Arc<File<table::Chunk>>),
     Tensor(Arc<File<tensor::Array>>),
 }
 
@@ -42,6 +44,12 @@ impl From<Arc<File<chain::ChainBlock>>> for DirEntry {
     }
 }
 
+impl From<Arc<File<table::Chunk>>> for DirEntry {
+    fn from(file: Arc<File<table::Chunk>>) -> DirEntry {
+        DirEntry::Overflow(file)
+    }
+}
+
 impl From<Arc<File<tensor::Array>>> for DirEntry {
     fn from(file: Arc<File<tensor::Array>>) -> DirEntry {
         DirEntry::Tensor(file)
@@ -81,6 +89,17 @@ impl TryFrom<DirEntry> for Arc<File<chain::ChainBlock>> {
     }
 }
 
+impl TryFrom<DirEntry> for Arc<File<table::Chunk>> {
+    type Error = error::TCError;
+
+    fn try_from(entry: DirEntry) -> TCResult<Arc<File<table::Chunk>>> {
+        match entry {
+            DirEntry::Overflow(overflow) => Ok(overflow),
+            other => Err(error::bad_request("Expected Dir but found", other)),
+        }
+    }
+}
+
 impl TryFrom<DirEntry> for Arc<File<tensor::Array>> {
     type Error = error::TCError;
 
@@ -98,6 +117,7 @@ impl fmt::Display for DirEntry {
             DirEntry::Dir(_) => write!(f, "(directory)"),
             DirEntry::BTree(_) => write!(f, "(BTree file)"),
             DirEntry::Chain(_) => write!(f, "(Chain file)"),
+            DirEntry::Overflow(_) => write!(f, "(table overflow file)"),
             DirEntry::Tensor(_) => write!(f, "(Tensor file)"),
         }
     }
@@ -95,6 +95,57 @@ impl<T: BlockData> File<T> {
         Ok(BlockOwned::new(self, block_id, lock))
     }
 
+    /// Create a new block in the host filesystem cache, without caching its contents as `T`.
+    ///
+    /// The block is loaded back into the cache the first time it's read.
+    pub async fn spill_block(&self, txn_id: TxnId, block_id: BlockId, data: T) -> TCResult<()> {
+        if &block_id == TXN_CACHE {
+            return Err(error::bad_request("This name is reserved", block_id));
+        }
+
+        let mut listing = self.listing.write(txn_id).await?;
+        if listing.contains(&block_id) {
+            return Err(error::bad_request(
+                "There is already a block called",
+                block_id,
+            ));
+        }
+
+        let txn_dir = self
+            .pending
+            .write()
+            .await
+            .create_or_get_dir(&txn_id.to_path())?;
+
+        txn_dir
+            .write()
+            .await
+            .create_block(block_id.clone(), data.into())?;
+
+        listing.insert(block_id.clone());
+        self.mutate(txn_id, block_id).await
+    }
+
+    /// Delete the block with the given ID, whether or not it was spilled to the host filesystem.
+    pub async fn delete_block(&self, txn_id: TxnId, block_id: BlockId) -> TCResult<()> {
+        let mut listing = self.listing.write(txn_id).await?;
+        if !listing.remove(&block_id) {
+            return Err(error::not_found(block_id));
+        }
+
+        self.mutated.write(txn_id).await?.remove(&block_id);
+
+        // a block spilled by this transaction must not be copied into the main Dir on commit
+        if let Some(txn_dir) = self.pending.read().await.get_dir(&txn_id.to_path())? {
+            let mut txn_dir = txn_dir.write().await;
+            if txn_dir.get_block(&block_id)?.is_some() {
+                txn_dir.delete_block(&block_id)?;
+            }
+        }
+
+        Ok(())
+    }
+
     pub async fn get_block<'a>(
         &'a self,
         txn_id: &'a TxnId,
@@ -220,5 +271,8 @@ impl<T: BlockData> Transact for File<T> {
             .unwrap();
 
         self.listing.finalize(txn_id).await;
+
+        let mut cache = self.cache.write().await;
+        cache.evict(txn_id, self.dir.read().await.deref());
     }
 }
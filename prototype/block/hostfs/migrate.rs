@@ -0,0 +1,311 @@
+use std::io;
+use std::path::{Path, PathBuf};
+
+use async_trait::async_trait;
+use log::info;
+
+use crate::auth::SCOPE_WRITE;
+use crate::class::State;
+use crate::error;
+use crate::general::Map;
+use crate::handler::Public;
+use crate::request::Request;
+use crate::scalar::*;
+use crate::transaction::Txn;
+use crate::TCResult;
+
+/// The version of the on-disk format written by this version of Tinychain.
+pub const FORMAT_VERSION: u64 = 1;
+
+const FORMAT_FILE: &str = ".format";
+
+type Migration = fn(&Path) -> io::Result<()>;
+
+// MIGRATIONS[i] upgrades a mount point from format version i to version i + 1
+const MIGRATIONS: [Migration; FORMAT_VERSION as usize] = [mark_unversioned];
+
+// version 0 is any data_dir written before the format marker was added. The only state which a
+// data_dir holds on disk is the `/sbin` state and the blob store, whose layouts did not change.
+// Collection blocks, including BTree nodes, are held in the in-memory filesystem cache and never
+// written under the data_dir, and a BTree node in the layout written before version 1 is decoded
+// and recounted when it's read (see `collection::btree::file`), so only the marker is written.
+fn mark_unversioned(_mount_point: &Path) -> io::Result<()> {
+    Ok(())
+}
+
+/// Return the format version of the data at `mount_point`.
+///
+/// A mount point which has no format marker is assumed to be empty, if it doesn't exist or has
+/// no contents, or else to have been written before the marker was added.
+pub async fn version(mount_point: &Path) -> TCResult<u64> {
+    match tokio::fs::read_to_string(mount_point.join(FORMAT_FILE)).await {
+        Ok(version) => version
+            .trim()
+            .parse()
+            .map_err(|_| error::internal(format!("Invalid format marker in {:?}", mount_point))),
+        Err(cause) if cause.kind() == io::ErrorKind::NotFound => {
+            if is_empty(mount_point).await? {
+                Ok(FORMAT_VERSION)
+            } else {
+                Ok(0)
+            }
+        }
+        Err(cause) => Err(io_error(mount_point, cause)),
+    }
+}
+
+/// Upgrade the data at `mount_point` to the current [`FORMAT_VERSION`], one version at a time,
+/// and return the versions which were applied.
+///
+/// The format marker is rewritten after each step, so an upgrade which is interrupted resumes
+/// from the last completed step.
+pub async fn migrate(mount_point: &Path) -> TCResult<Vec<u64>> {
+    let current = version(mount_point).await?;
+    if current > FORMAT_VERSION {
+        return Err(error::unsupported(format!(
+            "{:?} has format version {}, which is newer than this host supports ({})",
+            mount_point, current, FORMAT_VERSION
+        )));
+    }
+
+    tokio::fs::create_dir_all(mount_point)
+        .await
+        .map_err(|e| io_error(mount_point, e))?;
+
+    let mut applied = vec![];
+    for from in current..FORMAT_VERSION {
+        info!("migrating {:?} from format version {}", mount_point, from);
+
+        let step = MIGRATIONS[from as usize];
+        let path = mount_point.to_path_buf();
+        tokio::task::spawn_blocking(move || step(&path))
+            .await
+            .map_err(|e| error::internal(format!("Migration of {:?} failed: {}", mount_point, e)))?
+            .map_err(|e| io_error(mount_point, e))?;
+
+        write_version(mount_point, from + 1).await?;
+        applied.push(from + 1);
+    }
+
+    if current == FORMAT_VERSION {
+        write_version(mount_point, FORMAT_VERSION).await?;
+    }
+
+    Ok(applied)
+}
+
+async fn write_version(mount_point: &Path, version: u64) -> TCResult<()> {
+    tokio::fs::write(mount_point.join(FORMAT_FILE), version.to_string())
+        .await
+        .map_err(|e| io_error(mount_point, e))
+}
+
+async fn is_empty(mount_point: &Path) -> TCResult<bool> {
+    match tokio::fs::read_dir(mount_point).await {
+        Ok(mut entries) => entries
+            .next_entry()
+            .await
+            .map(|entry| entry.is_none())
+            .map_err(|e| io_error(mount_point, e)),
+        Err(cause) if cause.kind() == io::ErrorKind::NotFound => Ok(true),
+        Err(cause) => Err(io_error(mount_point, cause)),
+    }
+}
+
+fn io_error(mount_point: &Path, cause: io::Error) -> error::TCError {
+    error::internal(format!("Unable to migrate {:?}: {}", mount_point, cause))
+}
+
+/// The on-disk format of the host's `data_dir`, available at `/sbin/migrate`.
+///
+/// `GET` returns the format version of the data_dir and the version which this host writes.
+/// `POST` upgrades the data_dir to the current version and returns the versions applied, and
+/// requires the write scope. The host also does this at startup, so `POST` is only needed to
+/// resume a failed migration.
+pub struct Migrations {
+    mount_point: PathBuf,
+}
+
+impl Migrations {
+    pub fn new(mount_point: PathBuf) -> Migrations {
+        Migrations { mount_point }
+    }
+}
+
+#[async_trait]
+impl Public for Migrations {
+    async fn get(
+        &self,
+        _request: &Request,
+        _txn: &Txn,
+        path: &[PathSegment],
+        key: Value,
+    ) -> TCResult<State> {
+        if !path.is_empty() {
+            return Err(error::path_not_found(path));
+        } else if !key.is_none() {
+            return Err(error::bad_request(
+                "/sbin/migrate takes no key, but found",
+                key,
+            ));
+        }
+
+        let version = version(&self.mount_point).await?;
+        Ok(State::from(Value::Tuple(
+            vec![Value::from(version), Value::from(FORMAT_VERSION)].into(),
+        )))
+    }
+
+    async fn put(
+        &self,
+        _request: &Request,
+        _txn: &Txn,
+        path: &[PathSegment],
+        _key: Value,
+        _value: State,
+    ) -> TCResult<()> {
+        Err(error::method_not_allowed(TCPath::from(path)))
+    }
+
+    async fn post(
+        &self,
+        request: &Request,
+        _txn: &Txn,
+        path: &[PathSegment],
+        params: Map<Scalar>,
+    ) -> TCResult<State> {
+        match request.auth() {
+            Some(token) => token.validate(SCOPE_WRITE.into(), "/sbin/migrate")?,
+            None => {
+                return Err(error::unauthorized(
+                    "Migrating the data directory requires a bearer token",
+                ))
+            }
+        }
+
+        if !path.is_empty() {
+            return Err(error::path_not_found(path));
+        } else if let Some(name) = params.keys().next() {
+            return Err(error::bad_request("Unrecognized parameter", name));
+        }
+
+        let applied = migrate(&self.mount_point).await?;
+        let applied: Vec<Value> = applied.into_iter().map(Value::from).collect();
+        Ok(State::from(Value::from(applied)))
+    }
+
+    async fn delete(
+        &self,
+        _request: &Request,
+        _txn: &Txn,
+        path: &[PathSegment],
+        _key: Value,
+    ) -> TCResult<()> {
+        Err(error::method_not_allowed(TCPath::from(path)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use uuid::Uuid;
+
+    use crate::error::ErrorType;
+
+    use super::*;
+
+    fn mount_point() -> PathBuf {
+        std::env::temp_dir().join(Uuid::new_v4().to_string())
+    }
+
+    async fn marker(mount_point: &Path) -> String {
+        tokio::fs::read_to_string(mount_point.join(FORMAT_FILE))
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_empty() {
+        let mount_point = mount_point();
+        assert_eq!(version(&mount_point).await.unwrap(), FORMAT_VERSION);
+
+        tokio::fs::create_dir_all(&mount_point).await.unwrap();
+        assert_eq!(version(&mount_point).await.unwrap(), FORMAT_VERSION);
+
+        assert!(migrate(&mount_point).await.unwrap().is_empty());
+        assert_eq!(marker(&mount_point).await, FORMAT_VERSION.to_string());
+
+        tokio::fs::remove_dir_all(&mount_point).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_missing() {
+        let mount_point = mount_point();
+        assert!(migrate(&mount_point).await.unwrap().is_empty());
+        assert_eq!(marker(&mount_point).await, FORMAT_VERSION.to_string());
+
+        tokio::fs::remove_dir_all(&mount_point).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_unversioned() {
+        let mount_point = mount_point();
+        tokio::fs::create_dir_all(mount_point.join("sbin"))
+            .await
+            .unwrap();
+        tokio::fs::write(mount_point.join("sbin").join("state"), b"state")
+            .await
+            .unwrap();
+
+        assert_eq!(version(&mount_point).await.unwrap(), 0);
+
+        let expected: Vec<u64> = (1..=FORMAT_VERSION).collect();
+        assert_eq!(migrate(&mount_point).await.unwrap(), expected);
+        assert_eq!(version(&mount_point).await.unwrap(), FORMAT_VERSION);
+
+        let state = tokio::fs::read(mount_point.join("sbin").join("state"))
+            .await
+            .unwrap();
+        assert_eq!(state, b"state");
+
+        assert!(migrate(&mount_point).await.unwrap().is_empty());
+
+        tokio::fs::remove_dir_all(&mount_point).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_newer() {
+        let mount_point = mount_point();
+        tokio::fs::create_dir_all(&mount_point).await.unwrap();
+        write_version(&mount_point, FORMAT_VERSION + 1)
+            .await
+            .unwrap();
+
+        assert_eq!(version(&mount_point).await.unwrap(), FORMAT_VERSION + 1);
+
+        let cause = migrate(&mount_point).await.unwrap_err();
+        assert!(cause.reason() == &ErrorType::BadRequest);
+        assert_eq!(marker(&mount_point).await, (FORMAT_VERSION + 1).to_string());
+
+        tokio::fs::remove_dir_all(&mount_point).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_invalid_marker() {
+        let mount_point = mount_point();
+        tokio::fs::create_dir_all(&mount_point).await.unwrap();
+        tokio::fs::write(mount_point.join(FORMAT_FILE), "one")
+            .await
+            .unwrap();
+
+        let cause = version(&mount_point).await.unwrap_err();
+        assert!(cause.reason() == &ErrorType::Internal);
+        assert!(migrate(&mount_point).await.is_err());
+
+        tokio::fs::write(mount_point.join(FORMAT_FILE), " 1\n")
+            .await
+            .unwrap();
+        assert_eq!(version(&mount_point).await.unwrap(), 1);
+
+        tokio::fs::remove_dir_all(&mount_point).await.unwrap();
+    }
+}
@@ -3,8 +3,10 @@ use std::path::PathBuf;
 use crate::lock::RwLock;
 
 mod dir;
+mod migrate;
 
 pub use dir::Dir;
+pub use migrate::{migrate, Migrations};
 
 pub fn mount(mount_point: PathBuf) -> RwLock<Dir> {
     RwLock::new(Dir::new(mount_point))
@@ -17,6 +17,8 @@ mod dir;
 mod file;
 pub mod hostfs;
 
+pub use cache::set_cache_size;
+
 pub type BlockId = PathSegment;
 pub type Dir = dir::Dir;
 pub type DirEntry = dir::DirEntry;
@@ -4,6 +4,9 @@ use async_trait::async_trait;
 use futures::TryFutureExt;
 
 use crate::class::{Class, Instance, NativeClass, TCType};
+use crate::collection::tensor::dense::{BlockListFile, DenseTensor};
+use crate::collection::tensor::sparse::{SparseTable, SparseTensor};
+use crate::collection::Collection;
 use crate::error;
 use crate::handler::*;
 use crate::scalar::{label, Link, MethodType, PathSegment, TCPathBuf, Value};
@@ -99,6 +102,15 @@ pub enum Chain {
     Null(Box<null::NullChain>),
 }
 
+impl Chain {
+    /// The collection whose mutations this chain records.
+    pub fn subject(&self) -> Collection {
+        match self {
+            Self::Null(nc) => nc.subject(),
+        }
+    }
+}
+
 impl Instance for Chain {
     type Class = ChainType;
 
@@ -159,6 +171,18 @@ impl From<null::NullChain> for Chain {
     }
 }
 
+impl From<DenseTensor<BlockListFile>> for Chain {
+    fn from(tensor: DenseTensor<BlockListFile>) -> Chain {
+        null::NullChain::from(tensor).into()
+    }
+}
+
+impl From<SparseTensor<SparseTable>> for Chain {
+    fn from(tensor: SparseTensor<SparseTable>) -> Chain {
+        null::NullChain::from(tensor).into()
+    }
+}
+
 impl fmt::Display for Chain {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
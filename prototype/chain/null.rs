@@ -4,9 +4,11 @@ use futures::stream;
 use crate::auth::{Scope, SCOPE_WRITE};
 use crate::class::{Instance, TCType};
 use crate::collection::btree::BTreeFile;
-use crate::collection::table::TableIndex;
+use crate::collection::table::{TableIndex, TableInstance};
 use crate::collection::tensor::dense::{BlockListFile, DenseTensor};
 use crate::collection::tensor::sparse::{SparseTable, SparseTensor};
+use crate::collection::tensor::Tensor;
+use crate::collection::Collection;
 use crate::error;
 use crate::handler::*;
 use crate::scalar::{MethodType, PathSegment, Value};
@@ -62,6 +64,30 @@ impl NullChain {
     pub async fn create(_txn: &Txn, _dtype: TCType, _schema: Value) -> TCResult<NullChain> {
         Err(error::not_implemented("NullChain::create"))
     }
+
+    /// The collection whose mutations this chain records.
+    pub fn subject(&self) -> Collection {
+        match &self.state {
+            ChainState::Tree(tree) => tree.clone().into(),
+            ChainState::Table(table) => table.clone().into_table().into(),
+            ChainState::DenseTensor(tensor) => Tensor::from(tensor.clone()).into(),
+            ChainState::SparseTensor(tensor) => tensor.clone().into(),
+        }
+    }
+}
+
+impl From<DenseTensor<BlockListFile>> for NullChain {
+    fn from(tensor: DenseTensor<BlockListFile>) -> NullChain {
+        let state = ChainState::DenseTensor(tensor);
+        NullChain { state }
+    }
+}
+
+impl From<SparseTensor<SparseTable>> for NullChain {
+    fn from(tensor: SparseTensor<SparseTable>) -> NullChain {
+        let state = ChainState::SparseTensor(tensor);
+        NullChain { state }
+    }
 }
 
 impl Instance for NullChain {
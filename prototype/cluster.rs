@@ -1,38 +1,205 @@
-use std::collections::HashSet;
+use std::collections::HashMap;
+use std::convert::TryFrom;
 use std::fmt;
-use std::sync::Arc;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::Duration;
 
 use async_trait::async_trait;
-use log::debug;
+use futures::TryStreamExt;
+use log::{debug, warn};
 
 use crate::block::Dir;
+use crate::chain::Chain;
 use crate::class::State;
+use crate::collection::tensor::dense::{dense_constant, BlockListFile, DenseAccess, DenseTensor};
+use crate::collection::tensor::sparse::{self, SparseTensor};
+use crate::collection::tensor::{Shape, Tensor, TensorAccess, TensorInstance};
+use crate::collection::Collection;
 use crate::error;
+use crate::gateway::Gateway;
 use crate::general::Map;
 use crate::handler::Public;
+use crate::persist::{sbin_dir, Persistent, JOURNAL, SNAPSHOT};
+use crate::registry::SchemaRegistry;
+use crate::replication::{self, Follower, Replicator, Role, Write, REPLICATE};
 use crate::request::Request;
 use crate::scalar::*;
 use crate::transaction::lock::{Mutate, TxnLock};
 use crate::transaction::{Transact, Txn, TxnId};
-use crate::TCResult;
+use crate::{TCResult, TryCastFrom, TryCastInto};
 
 const ERR_ID: &str = "Invalid Id for Cluster member";
 
+const TENSOR: Label = label("tensor");
+const DENSE: Label = label("dense");
+const SPARSE: Label = label("sparse");
+
 #[derive(Clone)]
 enum ClusterReplica {
-    Director(HashSet<LinkHost>), // set of all hosts replicating this cluster
-    Actor(LinkHost),             // link to the director
+    Director(Replicator), // replicates the committed writes of this cluster to its actors
+    Actor(Follower),      // applies the writes replicated by the director
+}
+
+impl ClusterReplica {
+    fn new(role: &Role) -> ClusterReplica {
+        match role {
+            Role::Director {
+                replicas,
+                window,
+                shed_after,
+            } => ClusterReplica::Director(Replicator::new(replicas.to_vec(), *window, *shed_after)),
+            Role::Actor {
+                director,
+                session_wait,
+            } => ClusterReplica::Actor(Follower::new(director.clone(), *session_wait)),
+        }
+    }
+}
+
+// The tensors of a cluster are held in memory, so they're stored under
+// `data_dir/sbin/cluster/<cluster path>` as the writes which recreate them: a snapshot, plus a
+// journal with one entry per transaction committed since. Mounting the cluster replays both and
+// replaces them with a new snapshot.
+struct Journal {
+    dir: PathBuf,
+    snapshot: Persistent,
+    pending: Mutex<HashMap<TxnId, Vec<Write>>>,
+}
+
+impl Journal {
+    fn new(data_dir: &Path, cluster: &TCPathBuf) -> Journal {
+        let mut dir = sbin_dir(data_dir, "cluster");
+        for segment in cluster.iter() {
+            dir.push(segment.as_str());
+        }
+
+        Journal {
+            snapshot: Persistent::new(dir.clone(), SNAPSHOT),
+            dir: dir.join(JOURNAL),
+            pending: Mutex::new(HashMap::new()),
+        }
+    }
+
+    fn pending(&self) -> TCResult<MutexGuard<HashMap<TxnId, Vec<Write>>>> {
+        self.pending
+            .lock()
+            .map_err(|_| error::internal(format!("the journal at {:?} is poisoned", self.dir)))
+    }
+
+    // stage `writes` to be journaled when `txn_id` commits, and return `true` if they're the first
+    fn stage(&self, txn_id: TxnId, writes: &[Write]) -> TCResult<bool> {
+        let mut pending = self.pending()?;
+        let first = !pending.contains_key(&txn_id);
+        pending
+            .entry(txn_id)
+            .or_insert_with(Vec::new)
+            .extend(writes.iter().cloned());
+
+        Ok(first)
+    }
+
+    async fn commit(&self, txn_id: &TxnId) -> TCResult<()> {
+        let writes = match self.pending()?.remove(txn_id) {
+            Some(writes) if !writes.is_empty() => writes,
+            _ => return Ok(()),
+        };
+
+        let entry = Persistent::new(self.dir.clone(), &txn_id.to_string());
+        entry.save(|| encode_writes(&writes)).await
+    }
+
+    fn rollback(&self, txn_id: &TxnId) -> TCResult<()> {
+        self.pending()?.remove(txn_id);
+        Ok(())
+    }
+
+    // read the snapshot and then each journal entry committed after it, in order
+    //
+    // this blocks the current thread, so it should only be called while the host starts
+    fn load(&self) -> TCResult<Vec<(TxnId, Vec<Write>)>> {
+        let mut entries = vec![];
+
+        let mut since = TxnId::zero();
+        if let Some(snapshot) = self.snapshot.load::<Scalar>()? {
+            let (txn_id, writes) = match snapshot {
+                Scalar::Tuple(snapshot) if snapshot.len() == 2 => {
+                    let mut snapshot = snapshot.into_inner().into_iter();
+                    (snapshot.next().unwrap(), snapshot.next().unwrap())
+                }
+                other => {
+                    return Err(error::internal(format!(
+                        "Invalid snapshot of a Cluster: {}",
+                        other
+                    )))
+                }
+            };
+
+            let txn_id = Value::try_cast_from(txn_id, |s| {
+                error::internal(format!("Invalid snapshot of a Cluster: {}", s))
+            })?;
+
+            since = String::try_from(txn_id)?.parse()?;
+            entries.push((since, decode_writes(writes)?));
+        }
+
+        let mut journal = Persistent::list(&self.dir)?
+            .into_iter()
+            .map(|name| name.parse())
+            .collect::<TCResult<Vec<TxnId>>>()?;
+
+        journal.sort();
+
+        for txn_id in journal.into_iter().filter(|txn_id| txn_id > &since) {
+            let entry = Persistent::new(self.dir.clone(), &txn_id.to_string());
+            if let Some(writes) = entry.load()? {
+                entries.push((txn_id, decode_writes(writes)?));
+            }
+        }
+
+        Ok(entries)
+    }
+
+    // replace the journal up to and including `txn_id` with a snapshot of `writes`
+    async fn compact(&self, txn_id: TxnId, writes: &[Write]) -> TCResult<()> {
+        let txn_id_value = Value::TCString(TCString::UString(txn_id.to_string()));
+        self.snapshot
+            .save(|| Scalar::Tuple(vec![txn_id_value.into(), encode_writes(writes)].into()))
+            .await?;
+
+        for name in Persistent::list(&self.dir)? {
+            if name.parse::<TxnId>()? <= txn_id {
+                Persistent::new(self.dir.clone(), &name).delete().await?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+fn encode_writes(writes: &[Write]) -> Scalar {
+    Scalar::Tuple(
+        writes
+            .iter()
+            .map(Write::encode)
+            .collect::<Vec<Scalar>>()
+            .into(),
+    )
 }
 
-impl Default for ClusterReplica {
-    fn default() -> ClusterReplica {
-        ClusterReplica::Director(HashSet::new())
+fn decode_writes(encoded: Scalar) -> TCResult<Vec<Write>> {
+    match encoded {
+        Scalar::Tuple(writes) => writes.into_inner().into_iter().map(Write::decode).collect(),
+        other => Err(error::internal(format!(
+            "Invalid journal entry of a Cluster: {}",
+            other
+        ))),
     }
 }
 
 #[derive(Clone)]
 struct ClusterState {
-    replica: ClusterReplica,
+    tensors: HashMap<Id, Chain>,
 }
 
 #[async_trait]
@@ -53,47 +220,392 @@ pub struct Cluster {
     path: TCPathBuf,
     data_dir: Arc<Dir>,
     workspace: Arc<Dir>,
+    schemas: SchemaRegistry,
+    replica: ClusterReplica,
     state: TxnLock<ClusterState>,
+    journal: Arc<Journal>,
 }
 
 impl Cluster {
-    pub fn create(path: TCPathBuf, data_dir: Arc<Dir>, workspace: Arc<Dir>) -> TCResult<Cluster> {
-        let replica = ClusterReplica::default();
+    pub fn create(
+        path: TCPathBuf,
+        data_path: &Path,
+        data_dir: Arc<Dir>,
+        workspace: Arc<Dir>,
+        schemas: SchemaRegistry,
+        role: &Role,
+    ) -> TCResult<Cluster> {
         let state = TxnLock::new(
             format!("State of Cluster at {}", path),
-            ClusterState { replica },
+            ClusterState {
+                tensors: HashMap::new(),
+            },
         );
 
+        let journal = Journal::new(data_path, &path);
+
         Ok(Cluster {
             path,
             data_dir,
             workspace,
+            schemas,
+            replica: ClusterReplica::new(role),
             state,
+            journal: Arc::new(journal),
         })
     }
+
+    /// Reload the tensors of this cluster from `data_dir`.
+    pub async fn mount(&self, gateway: &Arc<Gateway>) -> TCResult<()> {
+        let entries = self.journal.load()?;
+        let latest = match entries.last() {
+            Some((txn_id, _)) => *txn_id,
+            None => return Ok(()),
+        };
+
+        debug!("{} replays {} journal entries", self, entries.len());
+
+        let request = Request::new(gateway.config().request_ttl()?, None, None, None);
+        let txn = gateway.transaction(&request).await?;
+        for (_, writes) in entries {
+            for write in writes {
+                self.apply(&request, &txn, write).await?;
+            }
+        }
+
+        let mut snapshot = vec![];
+        {
+            let state = self.state.read(txn.id()).await?;
+            for (name, chain) in state.tensors.iter() {
+                let path = [TENSOR.into(), name.clone()];
+                snapshot.extend(contents(&txn, &path, chain).await?);
+            }
+        }
+
+        txn.commit().await;
+        self.journal.compact(latest, &snapshot).await
+    }
+
+    /// Start replicating this cluster to its actors, if this host is its director.
+    pub fn replicate(&self, gateway: &Arc<Gateway>, ttl: Duration) {
+        if let ClusterReplica::Director(replicator) = &self.replica {
+            replicator.start(gateway, &self.path, ttl);
+        }
+    }
+
+    /// Return the lag of each actor of this cluster, if this host is its director.
+    pub async fn lag(&self) -> Vec<Value> {
+        match &self.replica {
+            ClusterReplica::Director(replicator) => replicator.lag().await,
+            ClusterReplica::Actor(_) => vec![],
+        }
+    }
+
+    /// The registry of the collection schemas of this cluster.
+    pub fn schemas(&'_ self) -> &'_ SchemaRegistry {
+        &self.schemas
+    }
+
+    async fn tensor(&self, txn: &Txn, name: &Id) -> TCResult<Chain> {
+        let state = self.state.read(txn.id()).await?;
+        state
+            .tensors
+            .get(name)
+            .cloned()
+            .ok_or_else(|| error::not_found(format!("{} tensor {}", self, name)))
+    }
+
+    // copy `tensor` into a new file under this cluster's data directory
+    async fn persist(&self, txn: &Txn, name: &Id, tensor: Tensor) -> TCResult<Chain> {
+        let path = self.path.clone().append(TENSOR);
+        let dir = self.data_dir.get_or_create_dir(txn.id(), &path).await?;
+        let persistent = txn.with_dir(dir, name.clone());
+
+        let shape = tensor.shape().clone();
+        let dtype = tensor.dtype();
+
+        match tensor {
+            Tensor::Dense(dense) => {
+                let source = dense.into_inner();
+                let blocks = source.block_stream(txn).await?;
+                let file = BlockListFile::from_blocks(&persistent, shape, dtype, blocks).await?;
+                Ok(DenseTensor::from(file).into())
+            }
+            Tensor::Sparse(sparse) => {
+                let entries = sparse.filled(txn).await?;
+                SparseTensor::from_entries(&persistent, shape, dtype, entries)
+                    .await
+                    .map(Chain::from)
+            }
+        }
+    }
+
+    // write to this cluster, and return the write as it should be journaled and replicated to the
+    // actors of this cluster
+    async fn write(
+        &self,
+        request: &Request,
+        txn: &Txn,
+        path: &[PathSegment],
+        key: Value,
+        value: State,
+    ) -> TCResult<Vec<Write>> {
+        let name = match path {
+            [tensor, name] if tensor == &TENSOR => name,
+            _ => return Err(error::not_implemented("Cluster::put")),
+        };
+
+        let mut state = self.state.write(*txn.id()).await?;
+        if let Some(chain) = state.tensors.get(name).cloned() {
+            debug!("write to {} tensor {} at {}", self, name, key);
+
+            let write = Scalar::try_cast_from(value.clone(), |s| {
+                error::bad_request("Cannot persist a write of", s)
+            })?;
+
+            let writes = vec![Write::new(path, key.clone(), write)];
+            chain.subject().put(request, txn, &[], key, value).await?;
+            txn.enlist(Box::new(chain)).await;
+            return Ok(writes);
+        } else if !key.is_none() {
+            return Err(error::not_found(format!("{} tensor {}", self, name)));
+        }
+
+        let tensor = match value {
+            State::Collection(Collection::Tensor(tensor)) => tensor,
+            other => {
+                return Err(error::bad_request(
+                    "Expected a Tensor to persist but found",
+                    other,
+                ))
+            }
+        };
+
+        debug!("persist new tensor {} in {}", name, self);
+
+        let chain = self.persist(txn, name, tensor).await?;
+        state.tensors.insert(name.clone(), chain.clone());
+        txn.enlist(Box::new(chain.clone())).await;
+        txn.enlist(Box::new(self.clone())).await;
+
+        contents(txn, path, &chain).await
+    }
+
+    // apply a write returned by `write`
+    async fn apply(&self, request: &Request, txn: &Txn, write: Write) -> TCResult<Vec<Write>> {
+        let value = if write.key.is_none() {
+            recreate(txn, write.value).await?
+        } else {
+            State::Scalar(write.value)
+        };
+
+        self.write(request, txn, &write.path, write.key, value)
+            .await
+    }
+
+    // stage `writes` to be journaled when `txn` commits
+    async fn record(&self, txn: &Txn, writes: &[Write]) -> TCResult<()> {
+        if self.journal.stage(*txn.id(), writes)? {
+            txn.enlist(Box::new(self.clone())).await;
+        }
+
+        Ok(())
+    }
+
+    // apply a transaction replicated by the director of this cluster
+    async fn replay(
+        &self,
+        request: &Request,
+        txn: &Txn,
+        follower: &Follower,
+        key: Value,
+        value: State,
+    ) -> TCResult<()> {
+        match request.peer() {
+            Some(peer) if follower.is_director(peer).await => {}
+            Some(peer) => {
+                return Err(error::forbidden(
+                    "Only the director of this cluster can replicate to it, not",
+                    peer,
+                ))
+            }
+            None => {
+                return Err(error::forbidden(
+                    "Only the director of this cluster can replicate to it",
+                    follower.director(),
+                ))
+            }
+        }
+
+        let (txn_id, writes) = replication::decode(key, value)?;
+
+        let mut replayed = follower.replay().await;
+        if *replayed == txn_id {
+            debug!("{} already applied {}", self, txn_id);
+            return Ok(());
+        } else if txn_id < *replayed {
+            return Err(error::bad_request(
+                format!("{} has already applied a later transaction than", self),
+                txn_id,
+            ));
+        }
+
+        debug!("{} replays {} writes of {}", self, writes.len(), txn_id);
+
+        for write in writes {
+            let applied = self.apply(request, txn, write).await?;
+            self.record(txn, &applied).await?;
+        }
+
+        txn.commit().await;
+        *replayed = txn_id;
+        follower.apply(txn_id);
+        Ok(())
+    }
+}
+
+// encode a new tensor as the writes which recreate it on an actor: its class, shape and dtype,
+// followed by each of its filled elements
+async fn contents(txn: &Txn, path: &[PathSegment], chain: &Chain) -> TCResult<Vec<Write>> {
+    let tensor = match chain.subject() {
+        Collection::Tensor(tensor) => tensor,
+        other => return Err(error::internal(format!("{} is not a Tensor", other))),
+    };
+
+    let class = match &tensor {
+        Tensor::Dense(_) => DENSE,
+        Tensor::Sparse(_) => SPARSE,
+    };
+
+    let definition = Value::Tuple(
+        vec![
+            Value::from(Id::from(class)),
+            Value::from(tensor.shape().to_vec()),
+            Value::from(Link::from(tensor.dtype())),
+        ]
+        .into(),
+    );
+
+    let mut writes = vec![Write::new(path, Value::None, definition.into())];
+
+    let sparse = match tensor.into_sparse() {
+        Tensor::Sparse(sparse) => sparse,
+        Tensor::Dense(_) => return Err(error::internal("Unable to read a Tensor as sparse")),
+    };
+
+    let mut filled = sparse.filled(txn).await?;
+    while let Some((coord, value)) = filled.try_next().await? {
+        writes.push(Write::new(
+            path,
+            Value::from(coord),
+            Value::from(value).into(),
+        ));
+    }
+
+    Ok(writes)
+}
+
+// construct the new, empty tensor defined by a replicated write, or return any other value as-is
+async fn recreate(txn: &Txn, value: Scalar) -> TCResult<State> {
+    let definition: Option<(Id, Shape, Link)> =
+        Value::opt_cast_from(value.clone()).and_then(|value| value.opt_cast_into());
+    let (class, shape, dtype) = match definition {
+        Some(definition) => definition,
+        None => return Ok(State::Scalar(value)),
+    };
+
+    let dtype = NumberType::try_cast_from(dtype, |l| {
+        error::bad_request("Invalid dtype for a replicated Tensor", l)
+    })?;
+
+    let tensor = if class == DENSE {
+        Tensor::from(dense_constant(txn, shape, dtype.zero()).await?)
+    } else if class == SPARSE {
+        Tensor::from(sparse::create(txn, shape, dtype).await?)
+    } else {
+        return Err(error::bad_request(
+            "Invalid class for a replicated Tensor",
+            class,
+        ));
+    };
+
+    Ok(State::Collection(Collection::Tensor(tensor)))
 }
 
 #[async_trait]
 impl Public for Cluster {
     async fn get(
         &self,
-        _request: &Request,
-        _txn: &Txn,
-        _path: &[PathSegment],
-        _key: Value,
+        request: &Request,
+        txn: &Txn,
+        path: &[PathSegment],
+        key: Value,
     ) -> TCResult<State> {
-        Err(error::not_implemented("Cluster::get"))
+        let session = request
+            .session()
+            .as_ref()
+            .and_then(|session| session.get(&self.path));
+
+        if let (ClusterReplica::Actor(follower), Some(session)) = (&self.replica, session) {
+            if !follower.wait_for(session, request.ttl()).await {
+                let mut dest = self.path.clone();
+                dest.extend(path.iter().cloned());
+                let dest: Link = (follower.director().clone(), dest).into();
+                debug!(
+                    "{} has not applied {}, so read from {}",
+                    self, session, dest
+                );
+                return txn.gateway().get(request, txn, &dest, key).await;
+            }
+        }
+
+        match path {
+            [tensor] if tensor == &TENSOR => {
+                let state = self.state.read(txn.id()).await?;
+                let names: Vec<Id> = state.tensors.keys().cloned().collect();
+                Ok(State::from(Value::from(names)))
+            }
+            [tensor, name, suffix @ ..] if tensor == &TENSOR => {
+                let chain = self.tensor(txn, name).await?;
+                chain.subject().get(request, txn, suffix, key).await
+            }
+            _ => Err(error::not_implemented("Cluster::get")),
+        }
     }
 
     async fn put(
         &self,
-        _request: &Request,
-        _txn: &Txn,
-        _path: &[PathSegment],
-        _key: Value,
-        _value: State,
+        request: &Request,
+        txn: &Txn,
+        path: &[PathSegment],
+        key: Value,
+        value: State,
     ) -> TCResult<()> {
-        Err(error::not_implemented("Cluster::put"))
+        match &self.replica {
+            ClusterReplica::Director(replicator) => {
+                let writes = self.write(request, txn, path, key, value).await?;
+                self.record(txn, &writes).await?;
+                replicator.stage(request, txn, writes).await?;
+                txn.wrote(&self.path).await;
+                Ok(())
+            }
+            ClusterReplica::Actor(follower) => match path {
+                [replicate] if replicate == &REPLICATE => {
+                    self.replay(request, txn, follower, key, value).await
+                }
+                _ => {
+                    let mut dest = self.path.clone();
+                    dest.extend(path.iter().cloned());
+                    let dest: Link = (follower.director().clone(), dest).into();
+                    debug!("{} forwards a write to {}", self, dest);
+                    txn.gateway().put(request, txn, &dest, key, value).await?;
+
+                    // the director writes with the same TxnId, so it's the session token
+                    txn.wrote(&self.path).await;
+                    Ok(())
+                }
+            },
+        }
     }
 
     async fn post(
@@ -121,17 +633,32 @@ impl Public for Cluster {
 impl Transact for Cluster {
     async fn commit(&self, txn_id: &TxnId) {
         debug!("Cluster::commit!");
-        self.state.commit(txn_id).await
+        self.state.commit(txn_id).await;
+
+        if let Err(cause) = self.journal.commit(txn_id).await {
+            warn!(
+                "unable to journal the commit of {} to {}: {}",
+                txn_id, self, cause
+            );
+        }
     }
 
     async fn rollback(&self, txn_id: &TxnId) {
         debug!("Cluster::rollback!");
-        self.state.rollback(txn_id).await
+        self.state.rollback(txn_id).await;
+
+        if let Err(cause) = self.journal.rollback(txn_id) {
+            warn!("unable to roll back the journal of {}: {}", self, cause);
+        }
     }
 
     async fn finalize(&self, txn_id: &TxnId) {
         debug!("Cluster::finalize!");
-        self.state.finalize(txn_id).await
+        self.state.finalize(txn_id).await;
+
+        if let Err(cause) = self.journal.rollback(txn_id) {
+            warn!("unable to finalize the journal of {}: {}", self, cause);
+        }
     }
 }
 
@@ -140,3 +667,112 @@ impl fmt::Display for Cluster {
         write!(f, "Cluster at {}", self.path)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use uuid::Uuid;
+
+    use super::*;
+
+    fn journal() -> Journal {
+        let data_dir = std::env::temp_dir().join(Uuid::new_v4().to_string());
+        let cluster: TCPathBuf = "/app/cluster".parse().unwrap();
+        Journal::new(&data_dir, &cluster)
+    }
+
+    fn txn_id(timestamp: u64) -> TxnId {
+        format!("{}-0", timestamp).parse().unwrap()
+    }
+
+    fn write(n: u64) -> Write {
+        let path: TCPathBuf = "/tensor/weights".parse().unwrap();
+        Write::new(&path, Value::from(n), Value::from(n).into())
+    }
+
+    fn keys(entries: Vec<(TxnId, Vec<Write>)>) -> Vec<(TxnId, Vec<Value>)> {
+        entries
+            .into_iter()
+            .map(|(txn_id, writes)| (txn_id, writes.into_iter().map(|w| w.key).collect()))
+            .collect()
+    }
+
+    #[test]
+    fn test_encode_writes() {
+        let writes = vec![write(1), write(2)];
+        let decoded = decode_writes(encode_writes(&writes)).unwrap();
+        assert_eq!(decoded.len(), 2);
+        assert_eq!(decoded[1].path, writes[1].path);
+        assert_eq!(decoded[1].key, writes[1].key);
+
+        assert!(decode_writes(Value::from(1u64).into()).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_journal() {
+        let journal = journal();
+        assert!(journal.load().unwrap().is_empty());
+
+        // only the first writes of a transaction are reported as the first
+        assert!(journal.stage(txn_id(1), &[write(1)]));
+        assert!(!journal.stage(txn_id(1), &[write(2)]));
+        journal.commit(&txn_id(1)).await.unwrap();
+
+        // a rolled back transaction leaves no entry
+        assert!(journal.stage(txn_id(2), &[write(3)]));
+        journal.rollback(&txn_id(2));
+        journal.commit(&txn_id(2)).await.unwrap();
+
+        // so does a transaction which staged no writes
+        journal.stage(txn_id(3), &[]);
+        journal.commit(&txn_id(3)).await.unwrap();
+
+        journal.stage(txn_id(5), &[write(5)]);
+        journal.stage(txn_id(4), &[write(4)]);
+        journal.commit(&txn_id(5)).await.unwrap();
+        journal.commit(&txn_id(4)).await.unwrap();
+
+        assert_eq!(Persistent::list(&journal.dir).unwrap().len(), 3);
+        assert_eq!(
+            keys(journal.load().unwrap()),
+            vec![
+                (txn_id(1), vec![Value::from(1u64), Value::from(2u64)]),
+                (txn_id(4), vec![Value::from(4u64)]),
+                (txn_id(5), vec![Value::from(5u64)]),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_compact() {
+        let journal = journal();
+        for n in 1..=3 {
+            journal.stage(txn_id(n), &[write(n)]);
+            journal.commit(&txn_id(n)).await.unwrap();
+        }
+
+        journal.compact(txn_id(2), &[write(12)]).await.unwrap();
+        assert_eq!(
+            Persistent::list(&journal.dir).unwrap(),
+            vec![txn_id(3).to_string()]
+        );
+        assert_eq!(
+            keys(journal.load().unwrap()),
+            vec![
+                (txn_id(2), vec![Value::from(12u64)]),
+                (txn_id(3), vec![Value::from(3u64)]),
+            ]
+        );
+
+        // an entry left behind by a crash during compaction isn't replayed on top of the snapshot
+        let stale = Persistent::new(journal.dir.clone(), &txn_id(1).to_string());
+        stale.save(|| encode_writes(&[write(1)])).await.unwrap();
+        assert_eq!(journal.load().unwrap().len(), 2);
+
+        journal.compact(txn_id(3), &[write(13)]).await.unwrap();
+        assert!(Persistent::list(&journal.dir).unwrap().is_empty());
+        assert_eq!(
+            keys(journal.load().unwrap()),
+            vec![(txn_id(3), vec![Value::from(13u64)])]
+        );
+    }
+}
@@ -36,6 +36,10 @@ pub trait BTreeInstance: Instance<Class = BTreeType> + Transact {
 
     async fn len(&self, txn_id: &TxnId, range: BTreeRange) -> TCResult<u64>;
 
+    async fn max_key(&self, txn_id: &TxnId, range: BTreeRange) -> TCResult<Option<Key>>;
+
+    async fn min_key(&self, txn_id: &TxnId, range: BTreeRange) -> TCResult<Option<Key>>;
+
     fn schema(&'_ self) -> &'_ [Column];
 
     async fn stream<'a>(
@@ -0,0 +1,177 @@
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+
+use crate::collection::schema::Compression;
+use crate::error;
+use crate::scalar::Value;
+use crate::TCResult;
+
+/// The values of a single column of the keys in a BTree node, as stored.
+#[derive(Deserialize, Serialize)]
+pub enum EncodedColumn {
+    Plain(Vec<Value>),
+    Dictionary {
+        dictionary: Vec<Value>,
+        indices: Vec<u32>,
+    },
+    RunLength(Vec<(Value, u32)>),
+}
+
+impl EncodedColumn {
+    pub fn encode(values: Vec<Value>, compression: Option<Compression>) -> TCResult<Self> {
+        match compression {
+            None => Ok(Self::Plain(values)),
+            Some(Compression::Dictionary) => {
+                let mut dictionary = vec![];
+                let mut positions = HashMap::new();
+                let mut indices = Vec::with_capacity(values.len());
+
+                for value in values {
+                    let serialized = bincode::serialize(&value)?;
+                    let index = match positions.get(&serialized) {
+                        Some(index) => *index,
+                        None => {
+                            let index = dictionary.len() as u32;
+                            positions.insert(serialized, index);
+                            dictionary.push(value);
+                            index
+                        }
+                    };
+
+                    indices.push(index);
+                }
+
+                Ok(Self::Dictionary {
+                    dictionary,
+                    indices,
+                })
+            }
+            Some(Compression::RunLength) => {
+                let mut runs: Vec<(Value, u32)> = vec![];
+                for value in values {
+                    match runs.last_mut() {
+                        Some((last, len)) if last == &value => *len += 1,
+                        _ => runs.push((value, 1)),
+                    }
+                }
+
+                Ok(Self::RunLength(runs))
+            }
+        }
+    }
+
+    pub fn compression(&self) -> Option<Compression> {
+        match self {
+            Self::Plain(_) => None,
+            Self::Dictionary { .. } => Some(Compression::Dictionary),
+            Self::RunLength(_) => Some(Compression::RunLength),
+        }
+    }
+
+    pub fn decode(self) -> TCResult<Vec<Value>> {
+        match self {
+            Self::Plain(values) => Ok(values),
+            Self::Dictionary {
+                dictionary,
+                indices,
+            } => indices
+                .into_iter()
+                .map(|i| {
+                    dictionary.get(i as usize).cloned().ok_or_else(|| {
+                        error::internal(format!("BTree node dictionary has no entry {}", i))
+                    })
+                })
+                .collect(),
+            Self::RunLength(runs) => {
+                let mut values = vec![];
+                for (value, len) in runs {
+                    values.extend(std::iter::repeat(value).take(len as usize));
+                }
+
+                Ok(values)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::error::ErrorType;
+    use crate::scalar::TCString;
+
+    use super::*;
+
+    fn string(s: &str) -> Value {
+        Value::TCString(TCString::UString(s.to_string()))
+    }
+
+    #[test]
+    fn test_dictionary() {
+        let values = vec![string("us"), string("fr"), string("us"), string("us")];
+        let encoded = EncodedColumn::encode(values.clone(), Some(Compression::Dictionary)).unwrap();
+        assert!(encoded.compression() == Some(Compression::Dictionary));
+
+        match &encoded {
+            EncodedColumn::Dictionary {
+                dictionary,
+                indices,
+            } => {
+                assert_eq!(dictionary, &vec![string("us"), string("fr")]);
+                assert_eq!(indices, &vec![0, 1, 0, 0]);
+            }
+            _ => panic!("expected a dictionary-encoded column"),
+        }
+
+        assert_eq!(encoded.decode().unwrap(), values);
+    }
+
+    #[test]
+    fn test_run_length() {
+        let values = vec![
+            Value::from(1u64),
+            Value::from(1u64),
+            Value::from(2u64),
+            Value::from(1u64),
+        ];
+
+        let encoded = EncodedColumn::encode(values.clone(), Some(Compression::RunLength)).unwrap();
+        assert!(encoded.compression() == Some(Compression::RunLength));
+
+        match &encoded {
+            EncodedColumn::RunLength(runs) => assert_eq!(
+                runs,
+                &vec![
+                    (Value::from(1u64), 2),
+                    (Value::from(2u64), 1),
+                    (Value::from(1u64), 1)
+                ]
+            ),
+            _ => panic!("expected a run-length-encoded column"),
+        }
+
+        assert_eq!(encoded.decode().unwrap(), values);
+    }
+
+    #[test]
+    fn test_plain() {
+        let values = vec![string("a"), string("a")];
+        let encoded = EncodedColumn::encode(values.clone(), None).unwrap();
+        assert!(encoded.compression().is_none());
+        assert_eq!(encoded.decode().unwrap(), values);
+
+        let empty = EncodedColumn::encode(vec![], Some(Compression::RunLength)).unwrap();
+        assert!(empty.decode().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_corrupt_dictionary() {
+        let encoded = EncodedColumn::Dictionary {
+            dictionary: vec![string("us")],
+            indices: vec![0, 1],
+        };
+
+        let cause = encoded.decode().unwrap_err();
+        assert!(cause.reason() == &ErrorType::Internal);
+    }
+}
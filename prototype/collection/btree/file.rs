@@ -7,7 +7,7 @@ use std::sync::Arc;
 
 use async_trait::async_trait;
 use bytes::Bytes;
-use futures::future::{self, join, try_join, try_join_all, Future};
+use futures::future::{self, join, try_join_all, Future};
 use futures::stream::{self, FuturesOrdered, Stream, StreamExt, TryStreamExt};
 use log::debug;
 use serde::{Deserialize, Serialize};
@@ -16,7 +16,7 @@ use uuid::Uuid;
 use crate::block::File;
 use crate::block::{Block, BlockData, BlockId, BlockMut};
 use crate::class::Instance;
-use crate::collection::schema::{Column, RowSchema};
+use crate::collection::schema::{Column, Compression, RowSchema};
 use crate::collection::Collection;
 use crate::error;
 use crate::scalar::*;
@@ -25,6 +25,7 @@ use crate::transaction::{Transact, Txn, TxnId};
 use crate::{TCBoxTryFuture, TCResult, TCTryStream};
 
 use super::collator::Collator;
+use super::compress::EncodedColumn;
 use super::{validate_key, validate_range, BTreeInstance, BTreeRange, BTreeType, Key};
 
 type Selection<'a> = FuturesOrdered<
@@ -33,6 +34,11 @@ type Selection<'a> = FuturesOrdered<
 
 const DEFAULT_BLOCK_SIZE: usize = 4_000;
 const BLOCK_ID_SIZE: usize = 128; // UUIDs are 128-bit
+const CHILD_SIZE: usize = BLOCK_ID_SIZE + 8; // each child also has a u64 count of its live keys
+
+// a legacy node starts with its "leaf" boolean, which is encoded as 0 or 1
+const PLAIN: u8 = 0xb1;
+const COMPRESSED: u8 = 0xc0;
 
 type NodeId = BlockId;
 
@@ -82,17 +88,151 @@ pub struct Node {
     keys: Vec<NodeKey>,
     parent: Option<NodeId>,
     children: Vec<NodeId>,
-    rebalance: bool, // TODO: implement rebalancing to clear deleted values
+    counts: Vec<u64>, // the number of live keys under each child
+    rebalance: bool,  // TODO: implement rebalancing to clear deleted values
+    #[serde(skip)]
+    compression: Vec<Option<Compression>>,
+    #[serde(skip)]
+    stale: bool, // true if this node was decoded from the legacy layout and not yet recounted
 }
 
 impl Node {
-    fn new(leaf: bool, parent: Option<NodeId>) -> Node {
+    fn new(leaf: bool, parent: Option<NodeId>, compression: Vec<Option<Compression>>) -> Node {
         Node {
             leaf,
             keys: vec![],
             parent,
             children: vec![],
+            counts: vec![],
             rebalance: false,
+            compression,
+            stale: false,
+        }
+    }
+
+    // the number of live keys in the subtree rooted at this node
+    fn count(&self) -> u64 {
+        let live = self.keys.iter().filter(|key| !key.deleted).count() as u64;
+        live + self.counts.iter().sum::<u64>()
+    }
+}
+
+/// A [`Node`] whose keys are stored column-by-column, so that each column can be compressed.
+#[derive(Deserialize, Serialize)]
+struct CompressedNode {
+    leaf: bool,
+    deleted: Vec<bool>,
+    columns: Vec<EncodedColumn>,
+    parent: Option<NodeId>,
+    children: Vec<NodeId>,
+    counts: Vec<u64>,
+    rebalance: bool,
+}
+
+impl CompressedNode {
+    fn encode(node: Node) -> TCResult<CompressedNode> {
+        let mut deleted = Vec::with_capacity(node.keys.len());
+        let mut columns = vec![Vec::with_capacity(node.keys.len()); node.compression.len()];
+        for key in node.keys {
+            deleted.push(key.deleted);
+            for (column, value) in columns.iter_mut().zip(key.value) {
+                column.push(value);
+            }
+        }
+
+        let columns = columns
+            .into_iter()
+            .zip(node.compression)
+            .map(|(values, compression)| EncodedColumn::encode(values, compression))
+            .collect::<TCResult<Vec<EncodedColumn>>>()?;
+
+        Ok(CompressedNode {
+            leaf: node.leaf,
+            deleted,
+            columns,
+            parent: node.parent,
+            children: node.children,
+            counts: node.counts,
+            rebalance: node.rebalance,
+        })
+    }
+
+    fn decode(self) -> TCResult<Node> {
+        let compression = self
+            .columns
+            .iter()
+            .map(EncodedColumn::compression)
+            .collect();
+
+        let mut keys: Vec<NodeKey> = self
+            .deleted
+            .into_iter()
+            .map(|deleted| NodeKey {
+                value: vec![],
+                deleted,
+            })
+            .collect();
+
+        for column in self.columns {
+            let values = column.decode()?;
+            if values.len() != keys.len() {
+                return Err(error::internal(
+                    "BTree node column has the wrong number of values",
+                ));
+            }
+
+            for (key, value) in keys.iter_mut().zip(values) {
+                key.value.push(value);
+            }
+        }
+
+        Ok(Node {
+            leaf: self.leaf,
+            keys,
+            parent: self.parent,
+            children: self.children,
+            counts: self.counts,
+            rebalance: self.rebalance,
+            compression,
+            stale: false,
+        })
+    }
+}
+
+/// The layout of a plain [`Node`] written before per-child live key counts were added.
+#[derive(Deserialize, Serialize)]
+struct LegacyNode {
+    leaf: bool,
+    keys: Vec<NodeKey>,
+    parent: Option<NodeId>,
+    children: Vec<NodeId>,
+    rebalance: bool,
+}
+
+impl From<LegacyNode> for Node {
+    fn from(node: LegacyNode) -> Node {
+        // a leaf has no children to count, but a non-leaf node must be recounted when it's read
+        Node {
+            leaf: node.leaf,
+            keys: node.keys,
+            parent: node.parent,
+            counts: vec![0; node.children.len()],
+            children: node.children,
+            rebalance: node.rebalance,
+            compression: vec![],
+            stale: !node.leaf,
+        }
+    }
+}
+
+impl From<Node> for LegacyNode {
+    fn from(node: Node) -> LegacyNode {
+        LegacyNode {
+            leaf: node.leaf,
+            keys: node.keys,
+            parent: node.parent,
+            children: node.children,
+            rebalance: node.rebalance,
         }
     }
 }
@@ -101,13 +241,35 @@ impl TryFrom<Bytes> for Node {
     type Error = error::TCError;
 
     fn try_from(serialized: Bytes) -> TCResult<Node> {
-        bincode::deserialize(&serialized).map_err(|e| e.into())
+        match serialized.first() {
+            Some(&PLAIN) => bincode::deserialize(&serialized[1..]).map_err(|e| e.into()),
+            Some(&COMPRESSED) => {
+                let node: CompressedNode = bincode::deserialize(&serialized[1..])?;
+                node.decode()
+            }
+            _ => {
+                let node: LegacyNode = bincode::deserialize(&serialized)?;
+                Ok(node.into())
+            }
+        }
     }
 }
 
 impl From<Node> for Bytes {
     fn from(node: Node) -> Bytes {
-        bincode::serialize(&node).unwrap().into()
+        if node.stale {
+            // the counts of a legacy node are not known until it's recounted
+            bincode::serialize(&LegacyNode::from(node)).unwrap().into()
+        } else if node.compression.iter().any(Option::is_some) {
+            let node = CompressedNode::encode(node).expect("compress BTree node");
+            let mut serialized = vec![COMPRESSED];
+            serialized.extend(bincode::serialize(&node).unwrap());
+            serialized.into()
+        } else {
+            let mut serialized = vec![PLAIN];
+            serialized.extend(bincode::serialize(&node).unwrap());
+            serialized.into()
+        }
     }
 }
 
@@ -151,8 +313,11 @@ pub struct BTreeFile {
 impl BTreeFile {
     pub async fn create(txn: &Txn, schema: RowSchema) -> TCResult<Self> {
         let file = txn.context().await?;
+        Self::create_in(file, *txn.id(), schema).await
+    }
 
-        if !file.is_empty(txn.id()).await? {
+    async fn create_in(file: Arc<File<Node>>, txn_id: TxnId, schema: RowSchema) -> TCResult<Self> {
+        if !file.is_empty(&txn_id).await? {
             return Err(error::internal(
                 "Tried to create a new BTree without a new File",
             ));
@@ -180,20 +345,24 @@ impl BTreeFile {
         // the "leaf" and "deleted" booleans each add one byte to a key as-stored
         key_size += 2;
 
-        let order = if DEFAULT_BLOCK_SIZE > (key_size * 2) + (BLOCK_ID_SIZE * 3) {
+        let order = if DEFAULT_BLOCK_SIZE > (key_size * 2) + (CHILD_SIZE * 3) {
             // let m := order
-            // maximum block size = (m * key_size) + ((m + 1) * block_id_size)
-            // therefore block_size = (m * (key_size + block_id_size)) + block_id_size
-            // therefore block_size - block_id_size = m * (key_size + block_id_size)
-            // therefore m = floor((block_size - block_id_size) / (key_size + block_id_size))
-            (DEFAULT_BLOCK_SIZE - BLOCK_ID_SIZE) / (key_size + BLOCK_ID_SIZE)
+            // maximum block size = (m * key_size) + ((m + 1) * child_size)
+            // therefore block_size = (m * (key_size + child_size)) + child_size
+            // therefore block_size - child_size = m * (key_size + child_size)
+            // therefore m = floor((block_size - child_size) / (key_size + child_size))
+            (DEFAULT_BLOCK_SIZE - CHILD_SIZE) / (key_size + CHILD_SIZE)
         } else {
             2
         };
 
         let root: BlockId = Uuid::new_v4().into();
         file.clone()
-            .create_block(txn.id().clone(), root.clone(), Node::new(true, None))
+            .create_block(
+                txn_id,
+                root.clone(),
+                Node::new(true, None, compression(&schema)),
+            )
             .await?;
 
         let collator = Collator::new(schema.iter().map(|c| c.dtype()).collect())?;
@@ -211,6 +380,209 @@ impl BTreeFile {
         &self.collator
     }
 
+    /// Stream every key in this `BTreeFile` in order, e.g. to copy it with [`BTreeFile::restore`].
+    pub async fn dump<'a>(&'a self, txn_id: &'a TxnId) -> TCResult<TCTryStream<'a, Key>> {
+        self.stream(txn_id, BTreeRange::default(), false).await
+    }
+
+    /// Construct a new `BTreeFile` from a stream of keys in strictly increasing order, like the
+    /// output of [`BTreeFile::dump`].
+    ///
+    /// The tree is bulk-loaded bottom-up: every leaf is full except the last two, which share
+    /// their keys evenly if needed, and each level above is split evenly into as few nodes as
+    /// possible. This is much faster than inserting the keys one at a time, and leaves no
+    /// half-empty nodes behind.
+    pub async fn restore<S: Stream<Item = TCResult<Key>> + Send + Unpin>(
+        txn: &Txn,
+        schema: RowSchema,
+        keys: S,
+    ) -> TCResult<Self> {
+        let file = txn.context().await?;
+        Self::restore_into(file, *txn.id(), schema, keys).await
+    }
+
+    async fn restore_into<S: Stream<Item = TCResult<Key>> + Send + Unpin>(
+        file: Arc<File<Node>>,
+        txn_id: TxnId,
+        schema: RowSchema,
+        mut keys: S,
+    ) -> TCResult<Self> {
+        let btree = Self::create_in(file, txn_id, schema).await?;
+        let max_keys = (2 * btree.order) - 1;
+
+        // the ids and key counts of each leaf written so far, and the keys which separate them
+        let mut children = vec![];
+        let mut separators = vec![];
+
+        // the previous full leaf is held back, with the key that follows it,
+        // in case the last leaf needs to borrow some of its keys
+        let mut prev: Option<(Vec<NodeKey>, NodeKey)> = None;
+        let mut leaf = Vec::with_capacity(max_keys);
+        let mut last: Option<Key> = None;
+
+        while let Some(key) = keys.try_next().await? {
+            let key = validate_key(key, btree.schema())?;
+            if let Some(last) = &last {
+                if btree.collator.compare(last, &key) != Ordering::Less {
+                    return Err(error::bad_request(
+                        "BTree restore requires keys in strictly increasing order, but found",
+                        Value::Tuple(key.into()),
+                    ));
+                }
+            }
+            last = Some(key.to_vec());
+
+            if leaf.len() == max_keys {
+                if let Some((keys, separator)) = prev.take() {
+                    children.push(btree.write_node(txn_id, true, keys, vec![], vec![]).await?);
+                    separators.push(separator);
+                }
+
+                let full = std::mem::replace(&mut leaf, Vec::with_capacity(max_keys));
+                prev = Some((full, key.into()));
+            } else {
+                leaf.push(NodeKey::from(key));
+            }
+        }
+
+        let (mut prev, mut separator) = match prev {
+            Some(prev) => prev,
+            None => {
+                // all the keys fit in the root
+                let mut root = Node::new(true, None, compression(&btree.schema));
+                root.keys = leaf;
+                btree.write_root(&txn_id, root).await?;
+                return Ok(btree);
+            }
+        };
+
+        if leaf.len() < btree.order - 1 {
+            // the last leaf is short of the minimum, so split the keys of the last two evenly
+            prev.push(separator);
+            prev.extend(leaf);
+            let len = prev.len();
+            leaf = prev.split_off(((len - 1) / 2) + 1);
+            separator = prev.pop().unwrap();
+        }
+
+        children.push(btree.write_node(txn_id, true, prev, vec![], vec![]).await?);
+        separators.push(separator);
+        children.push(btree.write_node(txn_id, true, leaf, vec![], vec![]).await?);
+
+        while children.len() > 2 * btree.order {
+            let (level, promoted) = btree.write_level(txn_id, children, separators).await?;
+            children = level;
+            separators = promoted;
+        }
+
+        let mut root = Node::new(false, None, compression(&btree.schema));
+        let (ids, counts): (Vec<NodeId>, Vec<u64>) = children.into_iter().unzip();
+        root.keys = separators;
+        root.children = ids;
+        root.counts = counts;
+        btree.write_root(&txn_id, root).await?;
+
+        Ok(btree)
+    }
+
+    // write one level of internal nodes over the given children, splitting them as evenly as
+    // possible, and return the new nodes with the separators between them
+    async fn write_level(
+        &self,
+        txn_id: TxnId,
+        children: Vec<(NodeId, u64)>,
+        separators: Vec<NodeKey>,
+    ) -> TCResult<(Vec<(NodeId, u64)>, Vec<NodeKey>)> {
+        use num::integer::div_ceil;
+
+        let num_nodes = div_ceil(children.len(), 2 * self.order);
+        let (size, extra) = (children.len() / num_nodes, children.len() % num_nodes);
+
+        let mut children = children.into_iter();
+        let mut separators = separators.into_iter();
+        let mut level = Vec::with_capacity(num_nodes);
+        let mut promoted = Vec::with_capacity(num_nodes - 1);
+
+        for i in 0..num_nodes {
+            let size = if i < extra { size + 1 } else { size };
+            let (ids, counts): (Vec<NodeId>, Vec<u64>) = children.by_ref().take(size).unzip();
+            let keys = separators.by_ref().take(size - 1).collect();
+            level.push(self.write_node(txn_id, false, keys, ids, counts).await?);
+
+            if let Some(separator) = separators.next() {
+                promoted.push(separator);
+            }
+        }
+
+        Ok((level, promoted))
+    }
+
+    // read the node with the given ID, counting the live keys under each of its children first
+    // if it was decoded from the legacy layout
+    fn get_node<'a>(
+        &'a self,
+        txn_id: &'a TxnId,
+        node_id: NodeId,
+    ) -> TCBoxTryFuture<'a, Block<'a, Node>> {
+        Box::pin(async move {
+            let node = self.file.get_block(txn_id, node_id).await?;
+            if !node.stale {
+                return Ok(node);
+            }
+
+            let mut counts = Vec::with_capacity(node.children.len());
+            for child_id in &node.children {
+                let child = self.get_node(txn_id, child_id.clone()).await?;
+                counts.push(child.count());
+            }
+
+            debug!(
+                "recounted a legacy BTree node with {} children",
+                counts.len()
+            );
+
+            let mut node = node.upgrade().await?;
+            node.counts = counts;
+            node.stale = false;
+            node.downgrade(txn_id).await
+        })
+    }
+
+    async fn write_node(
+        &self,
+        txn_id: TxnId,
+        leaf: bool,
+        keys: Vec<NodeKey>,
+        children: Vec<NodeId>,
+        counts: Vec<u64>,
+    ) -> TCResult<(NodeId, u64)> {
+        let mut node = Node::new(leaf, None, compression(&self.schema));
+        node.keys = keys;
+        node.children = children;
+        node.counts = counts;
+        let count = node.count();
+
+        let node_id = self.file.unique_id(&txn_id).await?;
+        self.file
+            .clone()
+            .create_block(txn_id, node_id.clone(), node)
+            .await?;
+
+        Ok((node_id, count))
+    }
+
+    async fn write_root(&self, txn_id: &TxnId, node: Node) -> TCResult<()> {
+        let root_id = self.root.read(txn_id).await?;
+        let mut root = self
+            .get_node(txn_id, root_id.deref().clone())
+            .await?
+            .upgrade()
+            .await?;
+
+        *root = node;
+        Ok(())
+    }
+
     fn _slice<'a>(
         &'a self,
         txn_id: &'a TxnId,
@@ -252,7 +624,7 @@ impl BTreeFile {
                 let range_clone = range.clone();
 
                 let selection = Box::pin(async move {
-                    let node = self.file.get_block(&txn_id, child_id).await?;
+                    let node = self.get_node(&txn_id, child_id).await?;
 
                     self._slice(txn_id, node, range_clone)
                 });
@@ -270,7 +642,7 @@ impl BTreeFile {
             let last_child_id = node.children[r].clone();
 
             let selection = Box::pin(async move {
-                let node: Block<'a, Node> = self.file.get_block(&txn_id, last_child_id).await?;
+                let node: Block<'a, Node> = self.get_node(&txn_id, last_child_id).await?;
 
                 self._slice(txn_id, node, range)
             });
@@ -306,7 +678,7 @@ impl BTreeFile {
             let last_child = node.children[r].clone();
             let range_clone = range.clone();
             let selection = Box::pin(async move {
-                let node: Block<'a, Node> = self.file.get_block(txn_id, last_child).await?;
+                let node: Block<'a, Node> = self.get_node(txn_id, last_child).await?;
 
                 self._slice_reverse(txn_id, node, range_clone)
             });
@@ -317,7 +689,7 @@ impl BTreeFile {
                 let range_clone = range.clone();
 
                 let selection = Box::pin(async move {
-                    let node: Block<'a, Node> = self.file.get_block(txn_id, child_id).await?;
+                    let node: Block<'a, Node> = self.get_node(txn_id, child_id).await?;
 
                     self._slice_reverse(txn_id, node, range_clone)
                 });
@@ -336,61 +708,185 @@ impl BTreeFile {
         }
     }
 
+    // count the live keys in `range` under `node`, using the stored count of each child which
+    // lies entirely within `range` instead of reading it
+    fn _count<'a>(
+        &'a self,
+        txn_id: &'a TxnId,
+        node: Block<'a, Node>,
+        range: &'a BTreeRange,
+    ) -> TCBoxTryFuture<'a, u64> {
+        Box::pin(async move {
+            let (l, r) = bisect(range, &node.keys, &self.collator);
+            let live = node.keys[l..r].iter().filter(|k| !k.deleted).count() as u64;
+
+            if node.leaf {
+                if l == r && l < node.keys.len() {
+                    let contains = !node.keys[l].deleted
+                        && self
+                            .collator
+                            .contains(range.start(), range.end(), &node.keys[l]);
+
+                    return Ok(if contains { 1 } else { 0 });
+                }
+
+                Ok(live)
+            } else {
+                let mut count = live;
+                if r > l + 1 {
+                    count += node.counts[(l + 1)..r].iter().sum::<u64>();
+                }
+
+                let first = self.get_node(txn_id, node.children[l].clone()).await?;
+                count += self._count(txn_id, first, range).await?;
+
+                if r > l {
+                    let last = self.get_node(txn_id, node.children[r].clone()).await?;
+                    count += self._count(txn_id, last, range).await?;
+                }
+
+                Ok(count)
+            }
+        })
+    }
+
+    // find the first (or last, if `reverse`) live key in `range` under `node`, skipping any
+    // child whose stored count is zero
+    fn _first<'a>(
+        &'a self,
+        txn_id: &'a TxnId,
+        node: Block<'a, Node>,
+        range: &'a BTreeRange,
+        reverse: bool,
+    ) -> TCBoxTryFuture<'a, Option<Key>> {
+        Box::pin(async move {
+            let (l, r) = bisect(range, &node.keys, &self.collator);
+
+            if node.leaf {
+                if l == r && l < node.keys.len() {
+                    let key = &node.keys[l];
+                    if !key.deleted && self.collator.contains(range.start(), range.end(), key) {
+                        return Ok(Some(key.value.to_vec()));
+                    }
+                }
+
+                let mut keys = node.keys[l..r].iter().filter(|k| !k.deleted);
+                let key = if reverse {
+                    keys.next_back()
+                } else {
+                    keys.next()
+                };
+                return Ok(key.map(|k| k.value.to_vec()));
+            }
+
+            // (index, is_key) of each child and key in range, in order
+            let mut entries = Vec::with_capacity(((r - l) * 2) + 1);
+            for i in l..r {
+                entries.push((i, false));
+                entries.push((i, true));
+            }
+            entries.push((r, false));
+
+            if reverse {
+                entries.reverse();
+            }
+
+            for (i, is_key) in entries {
+                if is_key {
+                    if !node.keys[i].deleted {
+                        return Ok(Some(node.keys[i].value.to_vec()));
+                    }
+                } else if node.counts[i] > 0 {
+                    let child = self.get_node(txn_id, node.children[i].clone()).await?;
+                    if let Some(key) = self._first(txn_id, child, range, reverse).await? {
+                        return Ok(Some(key));
+                    }
+                }
+            }
+
+            Ok(None)
+        })
+    }
+
     pub async fn update(&self, txn_id: &TxnId, range: BTreeRange, value: &[Value]) -> TCResult<()> {
         let range = validate_range(range, self.schema())?;
         let root_id = self.root.read(txn_id).await?;
-        self._update(txn_id, &root_id, &range, value).await
+        self._update(txn_id, &root_id, &range, value)
+            .await
+            .map(|_| ())
     }
 
+    // returns the number of deleted keys which were restored by the update
     fn _update<'a>(
         &'a self,
         txn_id: &'a TxnId,
         node_id: &'a NodeId,
         range: &'a BTreeRange,
         value: &'a [Value],
-    ) -> TCBoxTryFuture<'a, ()> {
+    ) -> TCBoxTryFuture<'a, u64> {
         Box::pin(async move {
-            let node = self.file.get_block(txn_id, node_id.clone()).await?;
+            let node = self.get_node(txn_id, node_id.clone()).await?;
             let (l, r) = bisect(range, &node.keys, &self.collator);
 
             if node.leaf {
                 if l == r {
-                    return Ok(());
+                    return Ok(0);
                 }
 
                 let mut node = node.upgrade().await?;
+                let mut restored = 0;
                 for i in l..r {
+                    if node.keys[i].deleted {
+                        restored += 1;
+                    }
+
                     node.keys[i] = value.into();
                 }
 
-                Ok(())
+                Ok(restored)
             } else {
                 let children = node.children.to_vec();
 
                 if r > l {
                     let mut node = node.upgrade().await?;
-                    let mut updates = Vec::with_capacity(r - l);
+                    let mut restored = 0;
+                    let mut updates = Vec::with_capacity(r - l + 1);
                     for (i, child_id) in children.iter().enumerate().take(r).skip(l) {
+                        if node.keys[i].deleted {
+                            restored += 1;
+                        }
+
                         node.keys[i] = value.into();
                         updates.push(self._update(txn_id, child_id, range, value));
                     }
 
-                    let last_update = self._update(txn_id, &children[r], range, value);
-                    try_join(try_join_all(updates), last_update).await?;
-                    Ok(())
+                    updates.push(self._update(txn_id, &children[r], range, value));
+                    for (i, child_restored) in (l..=r).zip(try_join_all(updates).await?) {
+                        node.counts[i] += child_restored;
+                        restored += child_restored;
+                    }
+
+                    Ok(restored)
                 } else {
-                    self._update(txn_id, &children[r], range, value).await
+                    let restored = self._update(txn_id, &children[r], range, value).await?;
+                    if restored > 0 {
+                        let mut node = node.upgrade().await?;
+                        node.counts[r] += restored;
+                    }
+
+                    Ok(restored)
                 }
             }
         })
     }
 
+    // returns `true` if the number of live keys under `node` was incremented
     fn _insert<'a>(
         &'a self,
         txn_id: &'a TxnId,
         node: Block<'a, Node>,
         key: Key,
-    ) -> TCBoxTryFuture<'a, ()> {
+    ) -> TCBoxTryFuture<'a, bool> {
         Box::pin(async move {
             let i = self.collator.bisect_left(&node.keys, &key);
             if i < node.keys.len() && self.collator.compare(&node.keys[i], &key) == Ordering::Equal
@@ -398,9 +894,10 @@ impl BTreeFile {
                 if node.keys[i].deleted {
                     let mut node = node.upgrade().await?;
                     node.keys[i].deleted = false;
+                    return Ok(true);
                 }
 
-                return Ok(());
+                return Ok(false);
             }
 
             debug!("insert at index {} into {}", i, node.deref());
@@ -408,15 +905,14 @@ impl BTreeFile {
             if node.leaf {
                 let mut node = node.upgrade().await?;
                 node.keys.insert(i, key.into());
-                Ok(())
+                Ok(true)
             } else {
-                let mut child = self
-                    .file
-                    .get_block(txn_id, node.children[i].clone())
-                    .await?;
+                let mut node = node;
+                let mut child_index = i;
+                let mut child = self.get_node(txn_id, node.children[i].clone()).await?;
 
                 if child.keys.len() == (2 * self.order) - 1 {
-                    let node = self
+                    node = self
                         .split_child(txn_id, node.children[i].clone(), node.upgrade().await?, i)
                         .await?;
 
@@ -426,20 +922,25 @@ impl BTreeFile {
                             if node.keys[i].deleted {
                                 let mut node = node.upgrade().await?;
                                 node.keys[i].deleted = false;
+                                return Ok(true);
                             }
 
-                            return Ok(());
+                            return Ok(false);
                         }
                         Ordering::Greater => {
-                            child = self
-                                .file
-                                .get_block(txn_id, node.children[i + 1].clone())
-                                .await?;
+                            child_index = i + 1;
+                            child = self.get_node(txn_id, node.children[i + 1].clone()).await?;
                         }
                     }
                 }
 
-                self._insert(txn_id, child, key).await
+                let inserted = self._insert(txn_id, child, key).await?;
+                if inserted {
+                    let mut node = node.upgrade().await?;
+                    node.counts[child_index] += 1;
+                }
+
+                Ok(inserted)
             }
         })
     }
@@ -452,12 +953,7 @@ impl BTreeFile {
         i: usize,
     ) -> TCResult<Block<'a, Node>> {
         let child_id = node.children[i].clone(); // needed due to mutable borrow below
-        let mut child = self
-            .file
-            .get_block(txn_id, child_id)
-            .await?
-            .upgrade()
-            .await?;
+        let mut child = self.get_node(txn_id, child_id).await?.upgrade().await?;
 
         debug!(
             "child to split has {} keys and {} children",
@@ -470,15 +966,19 @@ impl BTreeFile {
         node.children.insert(i + 1, new_node_id.clone());
         node.keys.insert(i, child.keys.remove(self.order - 1));
 
-        let mut new_node = Node::new(child.leaf, Some(node_id));
+        let mut new_node = Node::new(child.leaf, Some(node_id), child.compression.to_vec());
         new_node.keys = child.keys.drain((self.order - 1)..).collect();
 
         if child.leaf {
             debug!("child is a leaf node");
         } else {
             new_node.children = child.children.drain(self.order..).collect();
+            new_node.counts = child.counts.drain(self.order..).collect();
         }
 
+        node.counts[i] = child.count();
+        node.counts.insert(i + 1, new_node.count());
+
         self.file
             .clone()
             .create_block(*txn_id, new_node_id, new_node)
@@ -487,46 +987,67 @@ impl BTreeFile {
         node.downgrade(&txn_id).await
     }
 
+    // returns the number of live keys which were deleted
     fn _delete<'a>(
         &'a self,
         txn_id: &'a TxnId,
         node_id: NodeId,
         range: &'a BTreeRange,
-    ) -> TCBoxTryFuture<'a, ()> {
+    ) -> TCBoxTryFuture<'a, u64> {
         Box::pin(async move {
-            let node = self.file.get_block(txn_id, node_id).await?;
+            let node = self.get_node(txn_id, node_id).await?;
             let (l, r) = bisect(range, &node.keys, &self.collator);
 
             debug!("delete from {} [{}..{}]", node.deref(), l, r);
 
             if node.leaf {
                 if l == r {
-                    return Ok(());
+                    return Ok(0);
                 }
 
                 let mut node = node.upgrade().await?;
+                let mut deleted = 0;
                 for i in l..r {
-                    node.keys[i].deleted = true;
+                    if !node.keys[i].deleted {
+                        node.keys[i].deleted = true;
+                        deleted += 1;
+                    }
                 }
                 node.rebalance = true;
 
-                Ok(())
+                Ok(deleted)
             } else if r > l {
                 let mut node = node.upgrade().await?;
-                let mut deletes = Vec::with_capacity(r - l);
+                let mut deleted = 0;
+                let mut deletes = Vec::with_capacity(r - l + 1);
 
                 for i in l..r {
-                    node.keys[i].deleted = true;
+                    if !node.keys[i].deleted {
+                        node.keys[i].deleted = true;
+                        deleted += 1;
+                    }
+
                     deletes.push(self._delete(txn_id, node.children[i].clone(), range));
                 }
                 node.rebalance = true;
 
-                let last_delete = self._delete(txn_id, node.children[r].clone(), range);
-                try_join(try_join_all(deletes), last_delete).await?;
+                deletes.push(self._delete(txn_id, node.children[r].clone(), range));
+                for (i, child_deleted) in (l..=r).zip(try_join_all(deletes).await?) {
+                    node.counts[i] -= child_deleted;
+                    deleted += child_deleted;
+                }
 
-                Ok(())
+                Ok(deleted)
             } else {
-                self._delete(txn_id, node.children[r].clone(), range).await
+                let deleted = self
+                    ._delete(txn_id, node.children[r].clone(), range)
+                    .await?;
+                if deleted > 0 {
+                    let mut node = node.upgrade().await?;
+                    node.counts[r] -= deleted;
+                }
+
+                Ok(deleted)
             }
         })
     }
@@ -536,7 +1057,7 @@ impl BTreeFile {
         use std::collections::VecDeque;
 
         let root_id = self.root.read(txn_id).await?;
-        let root = self.file.get_block(txn_id, root_id.deref().clone()).await?;
+        let root = self.get_node(txn_id, root_id.deref().clone()).await?;
         let order = self.order;
 
         assert!(self.collator.is_sorted(&root.keys));
@@ -547,7 +1068,7 @@ impl BTreeFile {
 
         let mut unvisited: VecDeque<NodeId> = root.children.iter().cloned().collect();
         while let Some(node_id) = unvisited.pop_front() {
-            let node = self.file.get_block(txn_id, node_id).await?;
+            let node = self.get_node(txn_id, node_id).await?;
 
             assert!(!node.keys.is_empty());
             assert!(self.collator.is_sorted(&node.keys));
@@ -555,23 +1076,21 @@ impl BTreeFile {
 
             if node.leaf {
                 assert!(node.children.is_empty());
+                assert!(node.counts.is_empty());
             } else {
                 assert_eq!(node.children.len(), node.keys.len() + 1);
+                assert_eq!(node.counts.len(), node.children.len());
                 assert!(node.children.len() >= div_ceil(order, 2));
 
                 for i in 0..node.keys.len() {
-                    let child_at_i = self
-                        .file
-                        .get_block(txn_id, node.children[i].clone())
-                        .await?;
+                    let child_at_i = self.get_node(txn_id, node.children[i].clone()).await?;
 
-                    let child_after_i = self
-                        .file
-                        .get_block(txn_id, node.children[i + 1].clone())
-                        .await?;
+                    let child_after_i = self.get_node(txn_id, node.children[i + 1].clone()).await?;
 
                     assert!(!child_at_i.keys.is_empty());
                     assert!(!child_after_i.keys.is_empty());
+                    assert_eq!(node.counts[i], child_at_i.count());
+                    assert_eq!(node.counts[i + 1], child_after_i.count());
                     assert_eq!(
                         self.collator
                             .compare(child_at_i.keys.last().unwrap(), &node.keys[i]),
@@ -605,12 +1124,14 @@ impl BTreeInstance for BTreeFile {
     async fn delete(&self, txn_id: &TxnId, range: BTreeRange) -> TCResult<()> {
         let range = validate_range(range, self.schema())?;
         let root_id = self.root.read(txn_id).await?;
-        self._delete(txn_id, (*root_id).clone(), &range).await
+        self._delete(txn_id, (*root_id).clone(), &range)
+            .await
+            .map(|_| ())
     }
 
     async fn insert(&self, txn_id: &TxnId, key: Key) -> TCResult<()> {
         let root_id = self.root.read(txn_id).await?;
-        let root = self.file.get_block(txn_id, root_id.deref().clone()).await?;
+        let root = self.get_node(txn_id, root_id.deref().clone()).await?;
 
         debug!(
             "insert into BTree node with {} keys and {} children (order is {})",
@@ -624,8 +1145,9 @@ impl BTreeInstance for BTreeFile {
             let old_root_id = (*root_id).clone();
 
             (*root_id) = self.file.unique_id(&txn_id).await?;
-            let mut new_root = Node::new(false, None);
+            let mut new_root = Node::new(false, None, compression(&self.schema));
             new_root.children.push(old_root_id.clone());
+            new_root.counts.push(root.count());
 
             self.file
                 .clone()
@@ -633,15 +1155,14 @@ impl BTreeInstance for BTreeFile {
                 .await?;
 
             let new_root = self
-                .file
-                .get_block(txn_id, root_id.deref().clone())
+                .get_node(txn_id, root_id.deref().clone())
                 .await?
                 .upgrade()
                 .await?;
             let new_root = self.split_child(txn_id, old_root_id, new_root, 0).await?;
-            self._insert(txn_id, new_root, key).await
+            self._insert(txn_id, new_root, key).await.map(|_| ())
         } else {
-            self._insert(txn_id, root, key).await
+            self._insert(txn_id, root, key).await.map(|_| ())
         }
     }
 
@@ -673,16 +1194,34 @@ impl BTreeInstance for BTreeFile {
 
     async fn is_empty(&self, txn: &Txn) -> TCResult<bool> {
         let root_id = self.root.read(txn.id()).await?;
-        let root = self
-            .file
-            .get_block(txn.id(), root_id.deref().clone())
-            .await?;
+        let root = self.get_node(txn.id(), root_id.deref().clone()).await?;
         Ok(root.keys.is_empty())
     }
 
     async fn len(&self, txn_id: &TxnId, range: BTreeRange) -> TCResult<u64> {
-        let slice = self.stream(txn_id, range, false).await?;
-        Ok(slice.fold(0u64, |len, _| future::ready(len + 1)).await)
+        let range = validate_range(range, self.schema())?;
+        let root_id = self.root.read(txn_id).await?;
+        let root = self.get_node(txn_id, root_id.deref().clone()).await?;
+
+        if range == BTreeRange::default() {
+            Ok(root.count())
+        } else {
+            self._count(txn_id, root, &range).await
+        }
+    }
+
+    async fn max_key(&self, txn_id: &TxnId, range: BTreeRange) -> TCResult<Option<Key>> {
+        let range = validate_range(range, self.schema())?;
+        let root_id = self.root.read(txn_id).await?;
+        let root = self.get_node(txn_id, root_id.deref().clone()).await?;
+        self._first(txn_id, root, &range, true).await
+    }
+
+    async fn min_key(&self, txn_id: &TxnId, range: BTreeRange) -> TCResult<Option<Key>> {
+        let range = validate_range(range, self.schema())?;
+        let root_id = self.root.read(txn_id).await?;
+        let root = self.get_node(txn_id, root_id.deref().clone()).await?;
+        self._first(txn_id, root, &range, false).await
     }
 
     fn schema(&'_ self) -> &'_ [Column] {
@@ -698,7 +1237,7 @@ impl BTreeInstance for BTreeFile {
         let range = validate_range(range, self.schema())?;
 
         let root_id = self.root.read(txn_id).await?;
-        let root: Block<'a, Node> = self.file.get_block(txn_id, root_id.deref().clone()).await?;
+        let root: Block<'a, Node> = self.get_node(txn_id, root_id.deref().clone()).await?;
 
         if reverse {
             self._slice_reverse(txn_id, root, range)
@@ -739,3 +1278,358 @@ fn bisect<V: Deref<Target = [Value]>>(
         collator.bisect_right_range(keys, range.end()),
     )
 }
+
+fn compression(schema: &[Column]) -> Vec<Option<Compression>> {
+    schema.iter().map(Column::compression).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::block::hostfs;
+    use crate::error::ErrorType;
+    use crate::scalar::{TCString, UIntType};
+
+    use super::*;
+
+    fn schema() -> RowSchema {
+        let name: Id = "key".parse().unwrap();
+        vec![Column::from((name, NumberType::UInt(UIntType::U64)))]
+    }
+
+    async fn file() -> TCResult<Arc<File<Node>>> {
+        let data_dir = std::env::temp_dir().join(Uuid::new_v4().to_string());
+        File::create("btree", hostfs::mount(data_dir)).await
+    }
+
+    async fn restore<I: IntoIterator<Item = u64>>(keys: I) -> TCResult<(BTreeFile, TxnId)>
+    where
+        I::IntoIter: Send + Unpin,
+    {
+        let txn_id = TxnId::zero();
+        let keys = stream::iter(keys.into_iter().map(|n| Ok(vec![Value::from(n)])));
+        let btree = BTreeFile::restore_into(file().await?, txn_id, schema(), keys).await?;
+        Ok((btree, txn_id))
+    }
+
+    // the depth of the tree, and the number of keys in each leaf, in order
+    async fn leaves(btree: &BTreeFile, txn_id: &TxnId) -> TCResult<(usize, Vec<usize>)> {
+        let root_id = btree.root.read(txn_id).await?;
+        let mut level = vec![root_id.deref().clone()];
+        let mut depth = 0;
+
+        loop {
+            depth += 1;
+
+            let mut next = vec![];
+            let mut sizes = vec![];
+            for node_id in level {
+                let node = btree.get_node(txn_id, node_id).await?;
+                if node.leaf {
+                    sizes.push(node.keys.len());
+                } else {
+                    next.extend(node.children.iter().cloned());
+                }
+            }
+
+            if next.is_empty() {
+                return Ok((depth, sizes));
+            }
+
+            level = next;
+        }
+    }
+
+    fn key(n: u64) -> Key {
+        vec![Value::from(n)]
+    }
+
+    fn range(start: Bound, end: Bound) -> BTreeRange {
+        (vec![start], vec![end]).into()
+    }
+
+    fn between(start: u64, end: u64) -> BTreeRange {
+        range(Bound::In(Value::from(start)), Bound::Ex(Value::from(end)))
+    }
+
+    // a tree of the keys `0..len`, inserted out of order so that both leaves and internal nodes
+    // are split along the way
+    async fn insert_shuffled(len: u64) -> TCResult<(BTreeFile, TxnId)> {
+        let txn_id = TxnId::zero();
+        let btree = BTreeFile::create_in(file().await?, txn_id, schema()).await?;
+        for i in 0..len {
+            // 7919 is prime, so this visits every key in `0..len` once
+            btree.insert(&txn_id, key((i * 7919) % len)).await?;
+        }
+
+        Ok((btree, txn_id))
+    }
+
+    fn node(compression: Vec<Option<Compression>>) -> Node {
+        let mut node = Node::new(true, None, compression);
+        for (country, n) in &[("fr", 1u64), ("us", 1), ("us", 2), ("us", 3)] {
+            let country = Value::TCString(TCString::UString(country.to_string()));
+            let key = vec![country, Value::from(*n)];
+            node.keys.push(key.into());
+        }
+
+        node.keys[1].deleted = true;
+        node
+    }
+
+    fn assert_same_keys(left: &Node, right: &Node) {
+        assert_eq!(left.keys.len(), right.keys.len());
+        for (l, r) in left.keys.iter().zip(&right.keys) {
+            assert_eq!(l.value, r.value);
+            assert_eq!(l.deleted, r.deleted);
+        }
+    }
+
+    #[test]
+    fn test_compressed_node() -> TCResult<()> {
+        for compression in &[Compression::Dictionary, Compression::RunLength] {
+            let node = node(vec![Some(*compression), None]);
+            let serialized = Bytes::from(node.clone());
+            assert_eq!(serialized[0], COMPRESSED);
+
+            let decoded = Node::try_from(serialized)?;
+            assert_same_keys(&decoded, &node);
+            assert!(decoded.compression == vec![Some(*compression), None]);
+            assert!(decoded.leaf);
+            assert!(!decoded.stale);
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_plain_node() -> TCResult<()> {
+        // a node with no compressed column, or no compression given at all, is written as-is
+        for compression in vec![vec![None, None], vec![]] {
+            let node = node(compression);
+            let serialized = Bytes::from(node.clone());
+            assert_eq!(serialized[0], PLAIN);
+
+            let decoded = Node::try_from(serialized)?;
+            assert_same_keys(&decoded, &node);
+            assert!(decoded.compression.is_empty());
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_legacy_node() -> TCResult<()> {
+        let leaf = node(vec![]);
+        let serialized = Bytes::from(bincode::serialize(&LegacyNode::from(leaf.clone()))?);
+        let decoded = Node::try_from(serialized)?;
+        assert_same_keys(&decoded, &leaf);
+        assert!(decoded.leaf);
+        assert!(!decoded.stale);
+
+        // a legacy node with children has to be recounted before its counts are known
+        let mut parent = Node::new(false, None, vec![]);
+        parent.keys.push(key(1).into());
+        parent.children = vec!["left".parse()?, "right".parse()?];
+
+        let serialized = Bytes::from(bincode::serialize(&LegacyNode::from(parent.clone()))?);
+        let decoded = Node::try_from(serialized.clone())?;
+        assert_same_keys(&decoded, &parent);
+        assert_eq!(decoded.children, parent.children);
+        assert_eq!(decoded.counts, vec![0, 0]);
+        assert!(decoded.stale);
+
+        // until then, it's written back in the legacy layout
+        assert_eq!(Bytes::from(decoded), serialized);
+
+        parent.counts = vec![3, 4];
+        let serialized = Bytes::from(parent.clone());
+        assert_eq!(serialized[0], PLAIN);
+
+        let decoded = Node::try_from(serialized)?;
+        assert_eq!(decoded.counts, parent.counts);
+        assert!(!decoded.stale);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_counts() -> TCResult<()> {
+        let (btree, txn_id) = insert_shuffled(3000).await?;
+        btree.assert_valid(&txn_id).await?;
+        assert_eq!(btree.len(&txn_id, BTreeRange::default()).await?, 3000);
+
+        // inserting a key which already exists doesn't change the count
+        btree.insert(&txn_id, key(5)).await?;
+        assert_eq!(btree.len(&txn_id, BTreeRange::default()).await?, 3000);
+
+        let cases = [
+            (between(100, 200), 100),
+            (between(0, 3000), 3000),
+            (
+                range(Bound::Ex(0u64.into()), Bound::In(2999u64.into())),
+                2999,
+            ),
+            (range(Bound::In(2990u64.into()), Bound::Unbounded), 10),
+            (BTreeRange::from(key(42)), 1),
+            (between(3000, 4000), 0),
+        ];
+
+        for (range, expected) in cases.iter() {
+            assert_eq!(btree.len(&txn_id, range.clone()).await?, *expected);
+        }
+
+        btree.delete(&txn_id, between(1000, 2000)).await?;
+        btree.assert_valid(&txn_id).await?;
+        assert_eq!(btree.len(&txn_id, BTreeRange::default()).await?, 2000);
+        assert_eq!(btree.len(&txn_id, between(900, 1100)).await?, 100);
+        assert_eq!(btree.len(&txn_id, key(1500).into()).await?, 0);
+
+        // keys which are already deleted aren't counted twice
+        btree.delete(&txn_id, between(1500, 2100)).await?;
+        btree.assert_valid(&txn_id).await?;
+        assert_eq!(btree.len(&txn_id, BTreeRange::default()).await?, 1900);
+
+        // re-inserting or updating a deleted key counts it again
+        btree.insert(&txn_id, key(1500)).await?;
+        btree.update(&txn_id, key(1600).into(), &key(1600)).await?;
+        btree.assert_valid(&txn_id).await?;
+        assert_eq!(btree.len(&txn_id, BTreeRange::default()).await?, 1902);
+        assert_eq!(btree.len(&txn_id, between(1000, 2100)).await?, 2);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_min_max() -> TCResult<()> {
+        let (btree, txn_id) = insert_shuffled(3000).await?;
+
+        assert_eq!(
+            btree.min_key(&txn_id, BTreeRange::default()).await?,
+            Some(key(0))
+        );
+        assert_eq!(
+            btree.max_key(&txn_id, BTreeRange::default()).await?,
+            Some(key(2999))
+        );
+        assert_eq!(
+            btree.min_key(&txn_id, between(100, 200)).await?,
+            Some(key(100))
+        );
+        assert_eq!(
+            btree.max_key(&txn_id, between(100, 200)).await?,
+            Some(key(199))
+        );
+        assert_eq!(btree.min_key(&txn_id, between(3000, 4000)).await?, None);
+
+        // whole subtrees with no live keys are skipped
+        btree.delete(&txn_id, between(0, 1500)).await?;
+        btree.delete(&txn_id, between(2500, 3000)).await?;
+        assert_eq!(
+            btree.min_key(&txn_id, BTreeRange::default()).await?,
+            Some(key(1500))
+        );
+        assert_eq!(
+            btree.max_key(&txn_id, BTreeRange::default()).await?,
+            Some(key(2499))
+        );
+        assert_eq!(
+            btree.min_key(&txn_id, between(100, 1600)).await?,
+            Some(key(1500))
+        );
+        assert_eq!(btree.max_key(&txn_id, between(0, 1000)).await?, None);
+        assert_eq!(
+            btree.max_key(&txn_id, between(0, 2000)).await?,
+            Some(key(1999))
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_recount() -> TCResult<()> {
+        let (btree, txn_id) = restore(0..500).await?;
+        assert_eq!(leaves(&btree, &txn_id).await?.0, 2);
+
+        // make the root look like it was just read from the legacy layout
+        {
+            let root_id = btree.root.read(&txn_id).await?;
+            let root = btree
+                .file
+                .get_block(&txn_id, root_id.deref().clone())
+                .await?;
+            let mut root = root.upgrade().await?;
+            root.counts = vec![0; root.children.len()];
+            root.stale = true;
+        }
+
+        assert_eq!(btree.len(&txn_id, BTreeRange::default()).await?, 500);
+        assert_eq!(btree.len(&txn_id, between(10, 20)).await?, 10);
+        btree.assert_valid(&txn_id).await?;
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_restore() -> TCResult<()> {
+        // a single-column u64 key has an order of 26, so a leaf holds at most 51 keys
+        let order = 26;
+
+        // (number of keys, depth of the restored tree)
+        let cases = vec![
+            (0, 1),
+            (30, 1),
+            // two full leaves, then three keys, which are too few for a leaf of their own
+            (2 * 52 + 3, 2),
+            // more leaves than fit under one node, and a short last leaf
+            (5000, 3),
+        ];
+
+        for (len, expected_depth) in cases {
+            let (btree, txn_id) = restore(0..len).await?;
+            assert_eq!(btree.order, order);
+            btree.assert_valid(&txn_id).await?;
+
+            let keys: Vec<Key> = btree.dump(&txn_id).await?.try_collect().await?;
+            assert!(keys == (0..len).map(|n| vec![Value::from(n)]).collect::<Vec<Key>>());
+            assert_eq!(btree.len(&txn_id, BTreeRange::default()).await?, len);
+
+            let (depth, sizes) = leaves(&btree, &txn_id).await?;
+            assert_eq!(depth, expected_depth);
+            if depth > 1 {
+                assert!(sizes.iter().all(|size| *size >= order - 1));
+            }
+        }
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_round_trip() -> TCResult<()> {
+        let (source, txn_id) = restore((0..3000).map(|n| n * 2)).await?;
+        // insert some keys in descending order, so that the restored leaves are split
+        for n in (0..3000u64).rev().step_by(14) {
+            let key = vec![Value::from((n * 2) + 1)];
+            source.insert(&txn_id, key).await?;
+        }
+
+        let keys = source.dump(&txn_id).await?;
+        let restored = BTreeFile::restore_into(file().await?, txn_id, schema(), keys).await?;
+        restored.assert_valid(&txn_id).await?;
+
+        let restored: Vec<Key> = restored.dump(&txn_id).await?.try_collect().await?;
+        let source: Vec<Key> = source.dump(&txn_id).await?.try_collect().await?;
+        assert_eq!(restored.len(), 3000 + 215);
+        assert!(restored == source);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_restore_unordered() {
+        let cause = restore(vec![1, 3, 2]).await.err().unwrap();
+        assert!(cause.reason() == &ErrorType::BadRequest);
+
+        let cause = restore(vec![1, 1]).await.err().unwrap();
+        assert!(cause.reason() == &ErrorType::BadRequest);
+    }
+}
@@ -21,6 +21,7 @@ use super::schema::Column;
 mod bounds;
 mod class;
 mod collator;
+mod compress;
 mod file;
 mod slice;
 
@@ -104,6 +105,36 @@ impl<'a, T: BTreeInstance> Handler for CountHandler<'a, T> {
     }
 }
 
+struct BoundHandler<'a, T: BTreeInstance> {
+    btree: &'a T,
+    max: bool,
+}
+
+#[async_trait]
+impl<'a, T: BTreeInstance> Handler for BoundHandler<'a, T> {
+    fn subject(&self) -> TCType {
+        Instance::class(self.btree).into()
+    }
+
+    fn scope(&self) -> Option<Scope> {
+        Some(SCOPE_READ.into())
+    }
+
+    async fn handle_get(self: Box<Self>, txn: &Txn, range: Value) -> TCResult<State> {
+        let range = validate_range(range, self.btree.schema())?;
+        let key = if self.max {
+            self.btree.max_key(txn.id(), range).await?
+        } else {
+            self.btree.min_key(txn.id(), range).await?
+        };
+
+        match key {
+            Some(key) => Ok(State::Scalar(Scalar::Value(Value::Tuple(key.into())))),
+            None => Ok(State::Scalar(Scalar::Value(Value::None))),
+        }
+    }
+}
+
 struct DeleteHandler<'a, T: BTreeInstance> {
     btree: &'a T,
 }
@@ -337,6 +368,8 @@ where
         } else if path.len() == 1 {
             match path[0].as_str() {
                 "count" => Some(Box::new(CountHandler { btree })),
+                "max" => Some(Box::new(BoundHandler { btree, max: true })),
+                "min" => Some(Box::new(BoundHandler { btree, max: false })),
                 "reverse" => Some(Box::new(ReverseHandler { btree })),
                 _ => None,
             }
@@ -448,6 +481,20 @@ impl BTreeInstance for BTree {
         }
     }
 
+    async fn max_key(&self, txn_id: &TxnId, range: BTreeRange) -> TCResult<Option<Key>> {
+        match self {
+            Self::Tree(tree) => tree.max_key(txn_id, range).await,
+            Self::View(view) => view.max_key(txn_id, range).await,
+        }
+    }
+
+    async fn min_key(&self, txn_id: &TxnId, range: BTreeRange) -> TCResult<Option<Key>> {
+        match self {
+            Self::Tree(tree) => tree.min_key(txn_id, range).await,
+            Self::View(view) => view.min_key(txn_id, range).await,
+        }
+    }
+
     fn schema(&'_ self) -> &'_ [Column] {
         match self {
             Self::Tree(tree) => tree.schema(),
@@ -134,6 +134,32 @@ impl BTreeInstance for BTreeSlice {
         }
     }
 
+    async fn max_key(&self, txn_id: &TxnId, range: BTreeRange) -> TCResult<Option<Key>> {
+        if range == BTreeRange::default() {
+            self.source.max_key(txn_id, self.range.clone()).await
+        } else if self
+            .range
+            .contains(&range, self.schema(), self.source.collator())
+        {
+            self.source.max_key(txn_id, range).await
+        } else {
+            Err(error::bad_request(ERR_BOUNDS, range))
+        }
+    }
+
+    async fn min_key(&self, txn_id: &TxnId, range: BTreeRange) -> TCResult<Option<Key>> {
+        if range == BTreeRange::default() {
+            self.source.min_key(txn_id, self.range.clone()).await
+        } else if self
+            .range
+            .contains(&range, self.schema(), self.source.collator())
+        {
+            self.source.min_key(txn_id, range).await
+        } else {
+            Err(error::bad_request(ERR_BOUNDS, range))
+        }
+    }
+
     fn schema(&'_ self) -> &'_ [Column] {
         self.source.schema()
     }
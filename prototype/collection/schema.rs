@@ -1,11 +1,15 @@
 use std::collections::{BTreeMap, HashMap, HashSet};
 use std::fmt;
+use std::iter::FromIterator;
 
 use log::debug;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
 
 use crate::error;
 use crate::general::Map;
-use crate::scalar::{Id, NumberType, Scalar, ScalarClass, Value, ValueType};
+use crate::registry::SchemaRef;
+use crate::scalar::{Id, NumberType, Scalar, ScalarClass, TCString, Value, ValueType};
 use crate::{Match, TCResult, TryCastFrom, TryCastInto};
 
 pub type Row = HashMap<Id, Value>;
@@ -30,11 +34,43 @@ impl TryCastFrom<Map<Scalar>> for Row {
     }
 }
 
+/// The compression of a string column within each block of a BTree.
+///
+/// Compression is given as the fourth element of a column definition, like
+/// `("country", "/sbin/value/string", 64, "dictionary")`.
+#[derive(Clone, Copy, Deserialize, Eq, PartialEq, Serialize)]
+pub enum Compression {
+    /// Store each distinct value in a block once, with an index to it in each row.
+    Dictionary,
+    /// Store each run of consecutive equal values in a block once, with the length of the run.
+    RunLength,
+}
+
+impl Compression {
+    fn from_name(name: &str) -> Option<Compression> {
+        match name {
+            "dictionary" => Some(Self::Dictionary),
+            "rle" => Some(Self::RunLength),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Compression {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Dictionary => write!(f, "dictionary"),
+            Self::RunLength => write!(f, "rle"),
+        }
+    }
+}
+
 #[derive(Clone, PartialEq)]
 pub struct Column {
     name: Id,
     dtype: ValueType,
     max_len: Option<usize>,
+    compression: Option<Compression>,
 }
 
 impl Column {
@@ -49,6 +85,18 @@ impl Column {
     pub fn max_len(&'_ self) -> &'_ Option<usize> {
         &self.max_len
     }
+
+    pub fn compression(&self) -> Option<Compression> {
+        self.compression
+    }
+
+    /// Return a copy of this column with the given maximum length.
+    pub fn with_max_len(&self, max_len: usize) -> Column {
+        Column {
+            max_len: Some(max_len),
+            ..self.clone()
+        }
+    }
 }
 
 impl<I: Into<Id>> From<(I, NumberType)> for Column {
@@ -62,6 +110,7 @@ impl<I: Into<Id>> From<(I, NumberType)> for Column {
             name,
             dtype,
             max_len,
+            compression: None,
         }
     }
 }
@@ -75,6 +124,7 @@ impl From<(Id, ValueType)> for Column {
             name,
             dtype,
             max_len,
+            compression: None,
         }
     }
 }
@@ -88,6 +138,7 @@ impl From<(Id, ValueType, usize)> for Column {
             name,
             dtype,
             max_len,
+            compression: None,
         }
     }
 }
@@ -96,7 +147,7 @@ impl TryCastFrom<Value> for Column {
     fn can_cast_from(value: &Value) -> bool {
         debug!("Column::can_cast_from {}?", value);
 
-        value.matches::<(Id, ValueType)>() || value.matches::<(Id, ValueType, u64)>()
+        Self::opt_cast_from(value.clone()).is_some()
     }
 
     fn opt_cast_from(value: Value) -> Option<Column> {
@@ -106,6 +157,7 @@ impl TryCastFrom<Value> for Column {
                 name,
                 dtype,
                 max_len: None,
+                compression: None,
             })
         } else if value.matches::<(Id, ValueType, u64)>() {
             let (name, dtype, max_len) = value.opt_cast_into().unwrap();
@@ -113,7 +165,22 @@ impl TryCastFrom<Value> for Column {
                 name,
                 dtype,
                 max_len: Some(max_len),
+                compression: None,
             })
+        } else if value.matches::<(Id, ValueType, u64, Id)>() {
+            let (name, dtype, max_len, compression): (Id, ValueType, u64, Id) =
+                value.opt_cast_into().unwrap();
+
+            if let ValueType::TCString(_) = dtype {
+                Some(Column {
+                    name,
+                    dtype,
+                    max_len: Some(max_len as usize),
+                    compression: Some(Compression::from_name(compression.as_str())?),
+                })
+            } else {
+                None
+            }
         } else {
             None
         }
@@ -129,9 +196,202 @@ impl<'a> From<&'a Column> for (&'a Id, ValueType) {
 impl fmt::Display for Column {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self.max_len {
-            Some(max_len) => write!(f, "{}: {}({})", self.name, self.dtype, max_len),
-            None => write!(f, "{}: {}", self.name, self.dtype),
+            Some(max_len) => write!(f, "{}: {}({})", self.name, self.dtype, max_len)?,
+            None => write!(f, "{}: {}", self.name, self.dtype)?,
+        }
+
+        if let Some(compression) = self.compression {
+            write!(f, " ({} compression)", compression)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// A declarative constraint on the values of a single column.
+///
+/// Constraints are given in a `TableSchema` as `(column, constraint)` tuples, where the
+/// constraint is one of `("range", min, max)` (inclusive, with `None` for an open end),
+/// `("pattern", regex)` (which must match the entire string), or `("enum", (option, ...))`.
+#[derive(Clone)]
+pub enum Constraint {
+    Range(Value, Value),
+    Pattern(Regex),
+    OneOf(Vec<Value>),
+}
+
+impl Constraint {
+    fn check(&self, value: &Value) -> Result<(), String> {
+        match self {
+            Self::Range(min, max) => {
+                let n = match value {
+                    Value::Number(n) => n,
+                    Value::None => return Ok(()),
+                    other => return Err(format!("expected a number but found {}", other)),
+                };
+
+                let too_small = match min {
+                    Value::Number(min) => n < min,
+                    _ => false,
+                };
+
+                let too_large = match max {
+                    Value::Number(max) => n > max,
+                    _ => false,
+                };
+
+                if too_small || too_large {
+                    Err(format!("{} is not in range {}", n, self))
+                } else {
+                    Ok(())
+                }
+            }
+            Self::Pattern(regex) => match value {
+                Value::TCString(TCString::UString(s)) if regex.is_match(s) => Ok(()),
+                Value::TCString(TCString::Id(id)) if regex.is_match(id.as_str()) => Ok(()),
+                Value::None => Ok(()),
+                other => Err(format!("{} does not match {}", other, self)),
+            },
+            Self::OneOf(options) => {
+                if options.contains(value) {
+                    Ok(())
+                } else {
+                    Err(format!("{} is not one of {}", value, self))
+                }
+            }
+        }
+    }
+}
+
+impl TryCastFrom<Value> for Constraint {
+    fn can_cast_from(value: &Value) -> bool {
+        Self::opt_cast_from(value.clone()).is_some()
+    }
+
+    fn opt_cast_from(value: Value) -> Option<Constraint> {
+        if value.matches::<(Id, Value, Value)>() {
+            let (name, min, max): (Id, Value, Value) = value.opt_cast_into().unwrap();
+            match (name.as_str(), &min, &max) {
+                ("range", Value::Number(lo), Value::Number(hi)) if lo > hi => None,
+                ("range", Value::Number(_), Value::Number(_))
+                | ("range", Value::Number(_), Value::None)
+                | ("range", Value::None, Value::Number(_))
+                | ("range", Value::None, Value::None) => Some(Constraint::Range(min, max)),
+                _ => None,
+            }
+        } else if value.matches::<(Id, Value)>() {
+            let (name, arg): (Id, Value) = value.opt_cast_into().unwrap();
+            match (name.as_str(), arg) {
+                ("pattern", Value::TCString(TCString::UString(pattern))) => {
+                    Regex::new(&format!("^(?:{})$", pattern))
+                        .ok()
+                        .map(Constraint::Pattern)
+                }
+                ("enum", Value::Tuple(options)) => Some(Constraint::OneOf(options.into_inner())),
+                _ => None,
+            }
+        } else {
+            None
+        }
+    }
+}
+
+impl fmt::Display for Constraint {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Range(min, max) => write!(f, "[{}, {}]", min, max),
+            Self::Pattern(regex) => write!(f, "/{}/", regex.as_str()),
+            Self::OneOf(options) => write!(
+                f,
+                "{{{}}}",
+                options
+                    .iter()
+                    .map(|v| v.to_string())
+                    .collect::<Vec<String>>()
+                    .join(", ")
+            ),
+        }
+    }
+}
+
+/// The column constraints of a `TableSchema`.
+#[derive(Clone, Default)]
+pub struct Constraints(BTreeMap<Id, Vec<Constraint>>);
+
+impl Constraints {
+    pub fn columns(&self) -> Vec<Id> {
+        self.0.keys().cloned().collect()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// Check every constrained column present in `row`, returning the reason that each column
+    /// which failed validation is invalid.
+    pub fn validate(&self, row: &Row) -> Result<(), ValidationErrors> {
+        let mut failures = BTreeMap::new();
+        for (name, constraints) in &self.0 {
+            if let Some(value) = row.get(name) {
+                for constraint in constraints {
+                    if let Err(cause) = constraint.check(value) {
+                        failures.insert(name.clone(), cause);
+                        break;
+                    }
+                }
+            }
         }
+
+        if failures.is_empty() {
+            Ok(())
+        } else {
+            Err(ValidationErrors(failures))
+        }
+    }
+}
+
+/// The columns of a row which failed validation, with the reason each one is invalid.
+///
+/// This converts into a `BadRequest` error whose message is a JSON object mapping each column to
+/// its reason, so that a client can report every invalid field at once.
+#[derive(Debug)]
+pub struct ValidationErrors(BTreeMap<Id, String>);
+
+impl ValidationErrors {
+    pub fn fields(&'_ self) -> &'_ BTreeMap<Id, String> {
+        &self.0
+    }
+}
+
+impl From<ValidationErrors> for error::TCError {
+    fn from(errors: ValidationErrors) -> error::TCError {
+        error::bad_request("Row failed validation", errors)
+    }
+}
+
+impl fmt::Display for ValidationErrors {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let fields: BTreeMap<&str, &str> = self
+            .0
+            .iter()
+            .map(|(name, cause)| (name.as_str(), cause.as_str()))
+            .collect();
+
+        match serde_json::to_string(&fields) {
+            Ok(fields) => write!(f, "{}", fields),
+            Err(_) => write!(f, "{:?}", fields),
+        }
+    }
+}
+
+impl FromIterator<(Id, Constraint)> for Constraints {
+    fn from_iter<I: IntoIterator<Item = (Id, Constraint)>>(iter: I) -> Constraints {
+        let mut constraints: BTreeMap<Id, Vec<Constraint>> = BTreeMap::new();
+        for (name, constraint) in iter {
+            constraints.entry(name).or_default().push(constraint);
+        }
+
+        Constraints(constraints)
     }
 }
 
@@ -407,13 +667,57 @@ impl fmt::Display for IndexSchema {
     }
 }
 
+/// A generator for the key of a `TableSchema` with a single key column, given in the schema
+/// as either `"sequence"` (for a sequence of integers stored at `/sbin/sequence`) or `"uuid"`
+/// (for a UUIDv7).
+#[derive(Clone, Copy, Eq, PartialEq)]
+pub enum KeyGenerator {
+    Sequence,
+    Uuid,
+}
+
+impl TryCastFrom<Value> for KeyGenerator {
+    fn can_cast_from(value: &Value) -> bool {
+        Self::opt_cast_from(value.clone()).is_some()
+    }
+
+    fn opt_cast_from(value: Value) -> Option<KeyGenerator> {
+        let name: Id = value.opt_cast_into()?;
+        match name.as_str() {
+            "sequence" => Some(KeyGenerator::Sequence),
+            "uuid" => Some(KeyGenerator::Uuid),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for KeyGenerator {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Sequence => write!(f, "sequence"),
+            Self::Uuid => write!(f, "uuid"),
+        }
+    }
+}
+
 #[derive(Clone)]
 pub struct TableSchema {
     primary: IndexSchema,
     indices: BTreeMap<Id, Vec<Id>>,
+    constraints: Constraints,
+    generator: Option<KeyGenerator>,
+    registered: Option<SchemaRef>,
 }
 
 impl TableSchema {
+    pub fn constraints(&'_ self) -> &'_ Constraints {
+        &self.constraints
+    }
+
+    pub fn generator(&self) -> Option<KeyGenerator> {
+        self.generator
+    }
+
     pub fn indices(&'_ self) -> &'_ BTreeMap<Id, Vec<Id>> {
         &self.indices
     }
@@ -421,6 +725,47 @@ impl TableSchema {
     pub fn primary(&'_ self) -> &'_ IndexSchema {
         &self.primary
     }
+
+    /// The registered schema which this schema was resolved from, if any.
+    pub fn registered(&'_ self) -> Option<&'_ SchemaRef> {
+        self.registered.as_ref()
+    }
+
+    pub fn with_registered(self, registered: SchemaRef) -> TableSchema {
+        TableSchema {
+            registered: Some(registered),
+            ..self
+        }
+    }
+
+    /// Return an error if `row` does not have exactly the columns of this schema, with values of
+    /// the right types which satisfy its constraints.
+    pub fn validate_row(&self, row: &Row) -> TCResult<()> {
+        let columns = self.primary.columns();
+        for column in &columns {
+            let value = row
+                .get(&column.name)
+                .ok_or_else(|| error::bad_request("Missing value for column", &column.name))?;
+
+            column.dtype.try_cast(value.clone())?;
+        }
+
+        if row.len() > columns.len() {
+            let names: HashSet<&Id> = columns.iter().map(|c| &c.name).collect();
+            let extra: Vec<String> = row
+                .keys()
+                .filter(|name| !names.contains(name))
+                .map(|name| name.to_string())
+                .collect();
+
+            return Err(error::bad_request(
+                &format!("Unrecognized columns (`{}`) for schema", extra.join("`, `")),
+                &self.primary,
+            ));
+        }
+
+        self.constraints.validate(row).map_err(error::TCError::from)
+    }
 }
 
 impl From<IndexSchema> for TableSchema {
@@ -428,6 +773,9 @@ impl From<IndexSchema> for TableSchema {
         TableSchema {
             primary: schema,
             indices: BTreeMap::new(),
+            constraints: Constraints::default(),
+            generator: None,
+            registered: None,
         }
     }
 }
@@ -437,27 +785,179 @@ impl<I: Iterator<Item = (Id, Vec<Id>)>> From<(IndexSchema, I)> for TableSchema {
         TableSchema {
             primary: schema.0,
             indices: schema.1.collect(),
+            constraints: Constraints::default(),
+            generator: None,
+            registered: None,
         }
     }
 }
 
 impl TryCastFrom<Value> for TableSchema {
     fn can_cast_from(value: &Value) -> bool {
-        value.matches::<(IndexSchema, Vec<(Id, Vec<Id>)>)>() || value.matches::<IndexSchema>()
+        value.matches::<(
+            IndexSchema,
+            Vec<(Id, Vec<Id>)>,
+            Vec<(Id, Constraint)>,
+            KeyGenerator,
+        )>() || value.matches::<(IndexSchema, Vec<(Id, Vec<Id>)>, Vec<(Id, Constraint)>)>()
+            || value.matches::<(IndexSchema, Vec<(Id, Vec<Id>)>)>()
+            || value.matches::<IndexSchema>()
     }
 
     fn opt_cast_from(value: Value) -> Option<TableSchema> {
-        if value.matches::<(IndexSchema, Vec<(Id, Vec<Id>)>)>() {
+        if value.matches::<(
+            IndexSchema,
+            Vec<(Id, Vec<Id>)>,
+            Vec<(Id, Constraint)>,
+            KeyGenerator,
+        )>() {
+            let (primary, indices, constraints, generator): (
+                IndexSchema,
+                Vec<(Id, Vec<Id>)>,
+                Vec<(Id, Constraint)>,
+                KeyGenerator,
+            ) = value.opt_cast_into().unwrap();
+            let indices = indices.into_iter().collect();
+            let constraints = constraints.into_iter().collect();
+            let generator = Some(generator);
+            Some(TableSchema {
+                primary,
+                indices,
+                constraints,
+                generator,
+                registered: None,
+            })
+        } else if value.matches::<(IndexSchema, Vec<(Id, Vec<Id>)>, Vec<(Id, Constraint)>)>() {
+            let (primary, indices, constraints): (
+                IndexSchema,
+                Vec<(Id, Vec<Id>)>,
+                Vec<(Id, Constraint)>,
+            ) = value.opt_cast_into().unwrap();
+            let indices = indices.into_iter().collect();
+            let constraints = constraints.into_iter().collect();
+            let generator = None;
+            Some(TableSchema {
+                primary,
+                indices,
+                constraints,
+                generator,
+                registered: None,
+            })
+        } else if value.matches::<(IndexSchema, Vec<(Id, Vec<Id>)>)>() {
             let (primary, indices): (IndexSchema, Vec<(Id, Vec<Id>)>) =
                 value.opt_cast_into().unwrap();
             let indices = indices.into_iter().collect();
-            Some(TableSchema { primary, indices })
+            let constraints = Constraints::default();
+            let generator = None;
+            Some(TableSchema {
+                primary,
+                indices,
+                constraints,
+                generator,
+                registered: None,
+            })
         } else if value.matches::<IndexSchema>() {
             let primary = value.opt_cast_into().unwrap();
             let indices = BTreeMap::new();
-            Some(TableSchema { primary, indices })
+            let constraints = Constraints::default();
+            let generator = None;
+            Some(TableSchema {
+                primary,
+                indices,
+                constraints,
+                generator,
+                registered: None,
+            })
         } else {
             None
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn id(name: &str) -> Id {
+        name.parse().unwrap()
+    }
+
+    fn string(s: &str) -> Value {
+        Value::TCString(TCString::UString(s.to_string()))
+    }
+
+    fn range(min: Value, max: Value) -> Option<Constraint> {
+        Value::Tuple(vec![Value::from(id("range")), min, max].into()).opt_cast_into()
+    }
+
+    #[test]
+    fn test_range_bounds() {
+        assert!(range(Value::from(0u64), Value::from(10u64)).is_some());
+        assert!(range(Value::None, Value::from(10u64)).is_some());
+        assert!(range(Value::from(10u64), Value::from(0u64)).is_none());
+        assert!(range(string("a"), Value::from(10u64)).is_none());
+        assert!(range(Value::from(0u64), string("z")).is_none());
+    }
+
+    #[test]
+    fn test_validate_fields() {
+        let pattern = Value::Tuple(vec![Value::from(id("pattern")), string("[a-z]+")].into());
+        let constraints: Constraints = vec![
+            (
+                id("age"),
+                range(Value::from(0u64), Value::from(150u64)).unwrap(),
+            ),
+            (id("name"), pattern.opt_cast_into().unwrap()),
+        ]
+        .into_iter()
+        .collect();
+
+        let mut row = Row::new();
+        row.insert(id("age"), Value::from(200u64));
+        row.insert(id("name"), string("Bob"));
+
+        let errors = constraints.validate(&row).unwrap_err();
+        let fields: Vec<&Id> = errors.fields().keys().collect();
+        assert_eq!(fields, vec![&id("age"), &id("name")]);
+
+        row.insert(id("age"), Value::from(30u64));
+        row.insert(id("name"), string("bob"));
+        assert!(constraints.validate(&row).is_ok());
+    }
+
+    #[test]
+    fn test_validate_row() {
+        let key = vec![Column::from((
+            id("id"),
+            ValueType::Number(NumberType::uint64()),
+        ))];
+        let values = vec![Column::from((
+            id("age"),
+            ValueType::Number(NumberType::uint64()),
+        ))];
+        let constraints: Constraints = vec![(
+            id("age"),
+            range(Value::from(0u64), Value::from(150u64)).unwrap(),
+        )]
+        .into_iter()
+        .collect();
+
+        let schema = TableSchema {
+            constraints,
+            ..TableSchema::from(IndexSchema::from((key, values)))
+        };
+
+        let mut row = Row::new();
+        row.insert(id("id"), Value::from(1u64));
+        assert!(schema.validate_row(&row).is_err());
+
+        row.insert(id("age"), Value::from(200u64));
+        assert!(schema.validate_row(&row).is_err());
+
+        row.insert(id("age"), Value::from(30u64));
+        assert!(schema.validate_row(&row).is_ok());
+
+        row.insert(id("name"), string("bob"));
+        assert!(schema.validate_row(&row).is_err());
+    }
+}
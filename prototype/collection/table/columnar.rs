@@ -0,0 +1,718 @@
+use std::collections::HashMap;
+use std::fmt;
+
+use futures::future;
+use futures::stream::{StreamExt, TryStreamExt};
+
+use crate::class::Instance;
+use crate::collection::tensor::Array;
+use crate::error;
+use crate::general::Map;
+use crate::scalar::{
+    label, Id, Number, NumberClass, NumberInstance, NumberType, Scalar, UInt, Value, ValueType,
+};
+use crate::transaction::TxnId;
+use crate::{CastInto, Match, TCResult, TCTryStream, TryCastFrom, TryCastInto};
+
+use super::TableInstance;
+
+/// The number of rows to materialize in each [`ColumnChunk`].
+pub const CHUNK_SIZE: usize = 1024;
+
+/// How to read the rows of a table in order to evaluate an [`Aggregation`].
+#[derive(Clone, Copy, Eq, PartialEq)]
+pub enum ScanMode {
+    Rows,
+    Columns,
+}
+
+impl fmt::Display for ScanMode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Rows => write!(f, "rows"),
+            Self::Columns => write!(f, "columns"),
+        }
+    }
+}
+
+/// A comparison operator in a [`Predicate`].
+#[derive(Clone, Copy, Eq, PartialEq)]
+pub enum Compare {
+    Eq,
+    Gt,
+    Gte,
+    Lt,
+    Lte,
+    Ne,
+}
+
+impl Compare {
+    fn eval(&self, left: &Number, right: &Number) -> bool {
+        match self {
+            Self::Eq => left == right,
+            Self::Gt => left > right,
+            Self::Gte => left >= right,
+            Self::Lt => left < right,
+            Self::Lte => left <= right,
+            Self::Ne => left != right,
+        }
+    }
+
+    fn eval_array(&self, left: &Array, right: &Array) -> Array {
+        match self {
+            Self::Eq => left.eq(right),
+            Self::Gt => left.gt(right),
+            Self::Gte => left.gte(right),
+            Self::Lt => left.lt(right),
+            Self::Lte => left.lte(right),
+            Self::Ne => left.ne(right),
+        }
+    }
+}
+
+impl TryCastFrom<Value> for Compare {
+    fn can_cast_from(value: &Value) -> bool {
+        Self::opt_cast_from(value.clone()).is_some()
+    }
+
+    fn opt_cast_from(value: Value) -> Option<Compare> {
+        let op: Id = value.opt_cast_into()?;
+        match op.as_str() {
+            "eq" => Some(Self::Eq),
+            "gt" => Some(Self::Gt),
+            "gte" => Some(Self::Gte),
+            "lt" => Some(Self::Lt),
+            "lte" => Some(Self::Lte),
+            "ne" => Some(Self::Ne),
+            _ => None,
+        }
+    }
+}
+
+/// A comparison of the value of a numeric column to a constant.
+#[derive(Clone)]
+pub struct Predicate {
+    column: Id,
+    op: Compare,
+    value: Number,
+}
+
+impl TryCastFrom<Value> for Predicate {
+    fn can_cast_from(value: &Value) -> bool {
+        value.matches::<(Id, Compare, Number)>()
+    }
+
+    fn opt_cast_from(value: Value) -> Option<Predicate> {
+        let (column, op, value) = value.opt_cast_into()?;
+        Some(Predicate { column, op, value })
+    }
+}
+
+/// A filtered count and sum of the rows of a table, like
+/// `{"sum": ["price"], "where": [["quantity", "gt", 0]], "mode": "columns"}`.
+///
+/// In `rows` mode (the default) each row is evaluated one at a time. In `columns` mode rows are
+/// read in chunks of [`CHUNK_SIZE`], each column is materialized as an [`Array`], and predicates
+/// and sums are evaluated on whole chunks at once, which is much faster for large tables.
+pub struct Aggregation {
+    sum: Vec<Id>,
+    filter: Vec<Predicate>,
+    mode: ScanMode,
+}
+
+impl Aggregation {
+    /// Evaluate this `Aggregation` and return a map with the number of matching rows under
+    /// `count` and the sum of each summed column under its own name.
+    pub async fn evaluate<T: TableInstance>(
+        &self,
+        table: &T,
+        txn_id: &TxnId,
+    ) -> TCResult<Map<Scalar>> {
+        let columns = self.columns();
+        let dtypes = numeric_columns(table, &columns)?;
+
+        let (count, sums) = match self.mode {
+            ScanMode::Rows => self.evaluate_rows(table, txn_id, &columns, &dtypes).await?,
+            ScanMode::Columns => {
+                self.evaluate_columns(table, txn_id, &columns, &dtypes)
+                    .await?
+            }
+        };
+
+        let mut result: Map<Scalar> = self
+            .sum
+            .iter()
+            .cloned()
+            .zip(sums.into_iter().map(Scalar::from))
+            .collect();
+        result.insert(label("count").into(), Scalar::from(Number::from(count)));
+        Ok(result)
+    }
+
+    /// Merge the results of evaluating this `Aggregation` over disjoint sets of rows, like the
+    /// shards of a table, into the result of evaluating it over all of them.
+    pub fn merge(&self, partials: Vec<Map<Scalar>>) -> TCResult<Map<Scalar>> {
+        let mut names = self.sum.to_vec();
+        names.push(label("count").into());
+
+        let mut totals: Vec<Option<Number>> = vec![None; names.len()];
+        for mut partial in partials {
+            for (name, total) in names.iter().zip(totals.iter_mut()) {
+                let value = partial
+                    .remove(name)
+                    .ok_or_else(|| error::bad_request("Partial aggregate is missing", name))?;
+
+                let value = Value::try_cast_from(value, |s| {
+                    error::bad_request("Invalid partial aggregate", s)
+                })?;
+
+                let value = Number::try_cast_from(value, |v| {
+                    error::bad_request("Invalid partial aggregate", v)
+                })?;
+
+                *total = Some(match total.take() {
+                    Some(total) => total + value,
+                    None => value,
+                });
+            }
+        }
+
+        names
+            .into_iter()
+            .zip(totals)
+            .map(|(name, total)| {
+                total
+                    .map(|total| (name, Scalar::from(total)))
+                    .ok_or_else(|| error::internal("No partial aggregates to merge"))
+            })
+            .collect()
+    }
+
+    async fn evaluate_rows<T: TableInstance>(
+        &self,
+        table: &T,
+        txn_id: &TxnId,
+        columns: &[Id],
+        dtypes: &HashMap<Id, NumberType>,
+    ) -> TCResult<(u64, Vec<Number>)> {
+        let positions = positions(table, columns)?;
+        let zero: Vec<Number> = self.sum.iter().map(|name| dtypes[name].zero()).collect();
+
+        let rows = table.stream(txn_id).await?;
+        rows.map(|row| row.and_then(|row| project(&positions, columns, row)))
+            .try_fold((0u64, zero), |(count, sums), row| {
+                let matches = self
+                    .filter
+                    .iter()
+                    .all(|predicate| predicate.op.eval(&row[&predicate.column], &predicate.value));
+
+                if matches {
+                    let sums = self
+                        .sum
+                        .iter()
+                        .zip(sums)
+                        .map(|(name, sum)| sum + row[name])
+                        .collect();
+
+                    future::ready(Ok((count + 1, sums)))
+                } else {
+                    future::ready(Ok((count, sums)))
+                }
+            })
+            .await
+    }
+
+    async fn evaluate_columns<T: TableInstance>(
+        &self,
+        table: &T,
+        txn_id: &TxnId,
+        columns: &[Id],
+        dtypes: &HashMap<Id, NumberType>,
+    ) -> TCResult<(u64, Vec<Number>)> {
+        let zero: Vec<Number> = self.sum.iter().map(|name| dtypes[name].zero()).collect();
+        let mut chunks = scan(table, txn_id, columns.to_vec(), dtypes).await?;
+
+        let mut count = 0u64;
+        let mut sums = zero;
+        while let Some(chunk) = chunks.try_next().await? {
+            let mask = chunk.mask(&self.filter)?;
+            let matched: UInt = mask.sum().cast_into();
+            count += u64::from(matched);
+
+            for (name, sum) in self.sum.iter().zip(sums.iter_mut()) {
+                *sum = *sum + chunk.sum(name, &mask)?;
+            }
+        }
+
+        Ok((count, sums))
+    }
+
+    fn columns(&self) -> Vec<Id> {
+        let mut columns = self.sum.to_vec();
+        for predicate in &self.filter {
+            if !columns.contains(&predicate.column) {
+                columns.push(predicate.column.clone());
+            }
+        }
+
+        columns
+    }
+}
+
+impl TryCastFrom<Map<Scalar>> for Aggregation {
+    fn can_cast_from(params: &Map<Scalar>) -> bool {
+        Self::opt_cast_from(params.clone()).is_some()
+    }
+
+    fn opt_cast_from(mut params: Map<Scalar>) -> Option<Aggregation> {
+        let sum = match params.remove(&label("sum").into()) {
+            Some(sum) => Value::opt_cast_from(sum)?.opt_cast_into()?,
+            None => vec![],
+        };
+
+        let filter = match params.remove(&label("where").into()) {
+            Some(filter) => Value::opt_cast_from(filter)?.opt_cast_into()?,
+            None => vec![],
+        };
+
+        let mode = match params.remove(&label("mode").into()) {
+            Some(mode) => {
+                let mode: Id = Value::opt_cast_from(mode)?.opt_cast_into()?;
+                match mode.as_str() {
+                    "rows" => ScanMode::Rows,
+                    "columns" => ScanMode::Columns,
+                    _ => return None,
+                }
+            }
+            None => ScanMode::Rows,
+        };
+
+        if params.is_empty() {
+            Some(Aggregation { sum, filter, mode })
+        } else {
+            None
+        }
+    }
+}
+
+/// The values of a contiguous run of rows of a table, stored column-wise.
+pub struct ColumnChunk {
+    columns: HashMap<Id, Array>,
+    len: usize,
+}
+
+impl ColumnChunk {
+    /// The materialized values of the given column.
+    pub fn column(&self, name: &Id) -> TCResult<&Array> {
+        self.columns
+            .get(name)
+            .ok_or_else(|| error::bad_request("This column chunk has no column", name))
+    }
+
+    /// The number of rows in this chunk.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Evaluate the given predicates on every row in this chunk at once, and return a boolean
+    /// mask of the rows which satisfy all of them.
+    pub fn mask(&self, filter: &[Predicate]) -> TCResult<Array> {
+        let mut mask = Array::constant(Number::from(true), self.len);
+        for predicate in filter {
+            let column = self.column(&predicate.column)?;
+            let value = Array::constant(predicate.value.into_type(column.dtype()), self.len);
+            mask = mask.and(&predicate.op.eval_array(column, &value));
+        }
+
+        Ok(mask)
+    }
+
+    /// Sum the values of the given column in the rows selected by `mask`.
+    pub fn sum(&self, name: &Id, mask: &Array) -> TCResult<Number> {
+        // select rather than multiply by the mask, since a NaN or infinite value in a row
+        // which doesn't match would otherwise still make the sum NaN
+        let column = self.column(name)?;
+        let zero = Array::constant(column.dtype().zero(), self.len);
+        Ok(mask.select(column, &zero).sum())
+    }
+}
+
+/// Read the given numeric columns of `table` in chunks of [`CHUNK_SIZE`] rows.
+pub async fn scan<'a, T: TableInstance>(
+    table: &'a T,
+    txn_id: &'a TxnId,
+    columns: Vec<Id>,
+    dtypes: &HashMap<Id, NumberType>,
+) -> TCResult<TCTryStream<'a, ColumnChunk>> {
+    let positions = positions(table, &columns)?;
+    let dtypes: Vec<NumberType> = columns.iter().map(|name| dtypes[name]).collect();
+
+    let rows = table.stream(txn_id).await?;
+    let chunks = rows.chunks(CHUNK_SIZE).map(move |rows| {
+        let rows = rows.into_iter().collect::<TCResult<Vec<Vec<Value>>>>()?;
+        let len = rows.len();
+
+        let mut values: Vec<Vec<Number>> =
+            columns.iter().map(|_| Vec::with_capacity(len)).collect();
+        for row in rows {
+            for (i, position) in positions.iter().enumerate() {
+                values[i].push(try_into_number(&columns[i], &row[*position])?);
+            }
+        }
+
+        let columns = columns
+            .iter()
+            .cloned()
+            .zip(values.into_iter().zip(&dtypes))
+            .map(|(name, (values, dtype))| Ok((name, Array::cast_from_values(values, *dtype)?)))
+            .collect::<TCResult<HashMap<Id, Array>>>()?;
+
+        Ok(ColumnChunk { columns, len })
+    });
+
+    Ok(Box::pin(chunks))
+}
+
+fn numeric_columns<T: TableInstance>(
+    table: &T,
+    columns: &[Id],
+) -> TCResult<HashMap<Id, NumberType>> {
+    let schema = table.key().iter().chain(table.values());
+
+    let mut dtypes = HashMap::with_capacity(columns.len());
+    for column in schema {
+        if !columns.contains(column.name()) {
+            continue;
+        }
+
+        match column.dtype() {
+            ValueType::Number(dtype) => {
+                dtypes.insert(column.name().clone(), dtype);
+            }
+            other => {
+                return Err(error::bad_request(
+                    format!("Cannot aggregate column {} of type", column.name()),
+                    other,
+                ))
+            }
+        }
+    }
+
+    for name in columns {
+        if !dtypes.contains_key(name) {
+            return Err(error::bad_request(
+                format!("{} has no column", table.class()),
+                name,
+            ));
+        }
+    }
+
+    Ok(dtypes)
+}
+
+fn positions<T: TableInstance>(table: &T, columns: &[Id]) -> TCResult<Vec<usize>> {
+    let schema: Vec<&Id> = table
+        .key()
+        .iter()
+        .chain(table.values())
+        .map(|column| column.name())
+        .collect();
+
+    columns
+        .iter()
+        .map(|name| {
+            schema
+                .iter()
+                .position(|c| *c == name)
+                .ok_or_else(|| error::bad_request(format!("{} has no column", table.class()), name))
+        })
+        .collect()
+}
+
+fn project(positions: &[usize], columns: &[Id], row: Vec<Value>) -> TCResult<HashMap<Id, Number>> {
+    columns
+        .iter()
+        .zip(positions)
+        .map(|(name, i)| Ok((name.clone(), try_into_number(name, &row[*i])?)))
+        .collect()
+}
+
+fn try_into_number(column: &Id, value: &Value) -> TCResult<Number> {
+    match value {
+        Value::Number(n) => Ok(*n),
+        other => Err(error::bad_request(
+            format!("Expected a number in column {} but found", column),
+            other,
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::iter::FromIterator;
+
+    use crate::scalar::{Float, FloatType};
+
+    use super::*;
+
+    fn id(name: &str) -> Id {
+        name.parse().unwrap()
+    }
+
+    fn partial(count: u64, price: u64) -> Map<Scalar> {
+        let mut partial = Map::default();
+        partial.insert(id("count"), Scalar::from(Number::from(count)));
+        partial.insert(id("price"), Scalar::from(Number::from(price)));
+        partial
+    }
+
+    fn aggregation() -> Aggregation {
+        let mut params = Map::default();
+        params.insert(id("sum"), Scalar::Value(Value::from(vec![id("price")])));
+        params
+            .try_cast_into(|_| error::internal("invalid aggregation"))
+            .unwrap()
+    }
+
+    fn predicate(column: &str, op: &str, value: Number) -> Value {
+        Value::from_iter(vec![
+            Value::from(id(column)),
+            Value::from(id(op)),
+            Value::from(value),
+        ])
+    }
+
+    fn float(n: f64) -> Number {
+        Float::F64(n).into()
+    }
+
+    fn chunk(columns: Vec<(&str, Vec<Number>, NumberType)>) -> ColumnChunk {
+        let len = columns[0].1.len();
+        let columns = columns
+            .into_iter()
+            .map(|(name, values, dtype)| {
+                (id(name), Array::cast_from_values(values, dtype).unwrap())
+            })
+            .collect();
+
+        ColumnChunk { columns, len }
+    }
+
+    fn values(array: Array) -> Vec<bool> {
+        array
+            .into_values()
+            .into_iter()
+            .map(|n| n == Number::from(true))
+            .collect()
+    }
+
+    #[test]
+    fn test_compare() {
+        let one = Number::from(1u64);
+        let two = Number::from(2u64);
+
+        let cases = [
+            (Compare::Eq, [false, true, false]),
+            (Compare::Gt, [false, false, true]),
+            (Compare::Gte, [false, true, true]),
+            (Compare::Lt, [true, false, false]),
+            (Compare::Lte, [true, true, false]),
+            (Compare::Ne, [true, false, true]),
+        ];
+
+        let left = [one, two, Number::from(3u64)];
+        let uint = NumberType::uint64();
+        let array = Array::cast_from_values(left.to_vec(), uint).unwrap();
+        let constant = Array::constant(two, left.len());
+
+        for (op, expected) in cases.iter() {
+            for (l, e) in left.iter().zip(expected) {
+                assert_eq!(op.eval(l, &two), *e);
+            }
+
+            assert_eq!(values(op.eval_array(&array, &constant)), expected.to_vec());
+        }
+
+        assert!(Compare::Lt.eval(&one, &float(1.5)));
+    }
+
+    #[test]
+    fn test_parse_predicate() {
+        let ops = [
+            ("eq", Compare::Eq),
+            ("gte", Compare::Gte),
+            ("ne", Compare::Ne),
+        ];
+        for (name, op) in ops.iter() {
+            let parsed: Compare = Value::from(id(name)).opt_cast_into().unwrap();
+            assert!(parsed == *op);
+        }
+
+        assert!(Compare::opt_cast_from(Value::from(id("between"))).is_none());
+        assert!(Compare::opt_cast_from(Value::from(1u64)).is_none());
+
+        let parsed = Predicate::opt_cast_from(predicate("quantity", "gt", 0u64.into())).unwrap();
+        assert_eq!(parsed.column, id("quantity"));
+        assert!(parsed.op == Compare::Gt);
+        assert!(parsed.value == Number::from(0u64));
+
+        assert!(Predicate::opt_cast_from(predicate("quantity", "like", 0u64.into())).is_none());
+
+        let too_short = Value::from((id("quantity"), id("gt")));
+        assert!(Predicate::opt_cast_from(too_short).is_none());
+    }
+
+    #[test]
+    fn test_parse_aggregation() {
+        let aggregation = aggregation();
+        assert_eq!(aggregation.sum, vec![id("price")]);
+        assert!(aggregation.filter.is_empty());
+        assert!(aggregation.mode == ScanMode::Rows);
+
+        let mut params = Map::default();
+        params.insert(id("sum"), Scalar::Value(Value::from(vec![id("price")])));
+        params.insert(
+            id("where"),
+            Scalar::Value(Value::from(vec![
+                predicate("quantity", "gt", 0u64.into()),
+                predicate("price", "lte", 100u64.into()),
+            ])),
+        );
+        params.insert(id("mode"), Scalar::Value(Value::from(id("columns"))));
+
+        let aggregation = Aggregation::opt_cast_from(params.clone()).unwrap();
+        assert_eq!(aggregation.filter.len(), 2);
+        assert!(aggregation.mode == ScanMode::Columns);
+        assert_eq!(aggregation.columns(), vec![id("price"), id("quantity")]);
+
+        let mut invalid_mode = params.clone();
+        invalid_mode.insert(id("mode"), Scalar::Value(Value::from(id("blocks"))));
+        assert!(!Aggregation::can_cast_from(&invalid_mode));
+
+        let mut unknown = params.clone();
+        unknown.insert(
+            id("group_by"),
+            Scalar::Value(Value::from(vec![id("price")])),
+        );
+        assert!(!Aggregation::can_cast_from(&unknown));
+
+        let mut invalid_filter = params;
+        invalid_filter.insert(
+            id("where"),
+            Scalar::Value(Value::from(vec![predicate("price", "like", 1u64.into())])),
+        );
+        assert!(!Aggregation::can_cast_from(&invalid_filter));
+    }
+
+    #[test]
+    fn test_mask() {
+        let uint = NumberType::uint64();
+        let f64_type = NumberType::Float(FloatType::F64);
+
+        let quantity = (0..6u64).map(Number::from).collect();
+        let price = [1.5, 2., 2.5, 3., 3.5, 4.]
+            .iter()
+            .copied()
+            .map(float)
+            .collect();
+        let chunk = chunk(vec![
+            ("quantity", quantity, uint),
+            ("price", price, f64_type),
+        ]);
+        assert_eq!(chunk.len(), 6);
+
+        let all = chunk.mask(&[]).unwrap();
+        assert_eq!(values(all), vec![true; 6]);
+
+        let filter = vec![
+            Predicate {
+                column: id("quantity"),
+                op: Compare::Gt,
+                value: 0u64.into(),
+            },
+            // the constant is cast to the column's type, so 3 matches 3.0
+            Predicate {
+                column: id("price"),
+                op: Compare::Lte,
+                value: 3u64.into(),
+            },
+        ];
+
+        let mask = chunk.mask(&filter).unwrap();
+        assert_eq!(
+            values(mask.clone()),
+            vec![false, true, true, true, false, false]
+        );
+
+        assert!(chunk.sum(&id("price"), &mask).unwrap() == float(7.5));
+        assert!(chunk.sum(&id("quantity"), &mask).unwrap() == Number::from(6u64));
+
+        let missing = Predicate {
+            column: id("discount"),
+            op: Compare::Eq,
+            value: 0u64.into(),
+        };
+        assert!(chunk.mask(&[missing]).is_err());
+        assert!(chunk.sum(&id("discount"), &mask).is_err());
+    }
+
+    #[test]
+    fn test_sum_ignores_unmatched_non_finite() {
+        let f64_type = NumberType::Float(FloatType::F64);
+        let price = [f64::NAN, 2., f64::INFINITY, 3.]
+            .iter()
+            .copied()
+            .map(float)
+            .collect();
+
+        let chunk = chunk(vec![("price", price, f64_type)]);
+        let filter = [Predicate {
+            column: id("price"),
+            op: Compare::Lt,
+            value: 10u64.into(),
+        }];
+
+        let mask = chunk.mask(&filter).unwrap();
+        assert_eq!(values(mask.clone()), vec![false, true, false, true]);
+        assert!(chunk.sum(&id("price"), &mask).unwrap() == float(5.));
+    }
+
+    #[test]
+    fn test_try_into_number() {
+        let column = id("price");
+        assert!(try_into_number(&column, &Value::from(3u64)).unwrap() == Number::from(3u64));
+        assert!(try_into_number(&column, &Value::None).is_err());
+        assert!(try_into_number(&column, &Value::from(id("three"))).is_err());
+    }
+
+    #[test]
+    fn test_merge() {
+        let aggregation = aggregation();
+
+        let merged = aggregation
+            .merge(vec![partial(2, 10), partial(0, 0), partial(3, 7)])
+            .unwrap();
+
+        assert_eq!(merged.len(), 2);
+        assert!(merged[&id("count")] == Scalar::from(Number::from(5u64)));
+        assert!(merged[&id("price")] == Scalar::from(Number::from(17u64)));
+
+        let merged = aggregation.merge(vec![partial(1, 4)]).unwrap();
+        assert!(merged[&id("count")] == Scalar::from(Number::from(1u64)));
+        assert!(merged[&id("price")] == Scalar::from(Number::from(4u64)));
+    }
+
+    #[test]
+    fn test_merge_invalid() {
+        let aggregation = aggregation();
+        assert!(aggregation.merge(vec![]).is_err());
+
+        let mut missing = partial(1, 2);
+        missing.remove(&id("price"));
+        assert!(aggregation.merge(vec![partial(1, 2), missing]).is_err());
+
+        let mut invalid = partial(1, 2);
+        invalid.insert(id("count"), Scalar::from(id("one")));
+        assert!(aggregation.merge(vec![invalid]).is_err());
+    }
+}
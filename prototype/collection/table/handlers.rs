@@ -7,6 +7,7 @@ use futures::TryFutureExt;
 
 use crate::auth::{Scope, SCOPE_READ, SCOPE_WRITE};
 use crate::class::{Instance, State, TCType};
+use crate::collection::schema::Row;
 use crate::collection::CollectionInstance;
 use crate::error;
 use crate::general::Map;
@@ -16,8 +17,43 @@ use crate::scalar::{Id, MethodType, PathSegment, Scalar, Value};
 use crate::transaction::Txn;
 use crate::{Match, TCResult, TCTryStream, TryCastFrom, TryCastInto};
 
+use super::columnar::Aggregation;
 use super::{Bounds, Table, TableInstance};
 
+pub struct AggregateHandler<'a, T: TableInstance> {
+    table: &'a T,
+}
+
+#[async_trait]
+impl<'a, T: TableInstance> Handler for AggregateHandler<'a, T>
+where
+    <T as Instance>::Class: Into<TCType>,
+{
+    fn subject(&self) -> TCType {
+        self.table.class().into()
+    }
+
+    fn scope(&self) -> Option<Scope> {
+        Some(SCOPE_READ.into())
+    }
+
+    async fn handle_post(
+        self: Box<Self>,
+        _request: &Request,
+        txn: &Txn,
+        params: Map<Scalar>,
+    ) -> TCResult<State> {
+        let aggregation: Aggregation =
+            params.try_cast_into(|v| error::bad_request("Invalid aggregation", v))?;
+
+        aggregation
+            .evaluate(self.table, txn.id())
+            .map_ok(Scalar::Map)
+            .map_ok(State::Scalar)
+            .await
+    }
+}
+
 pub struct DeleteHandler<'a, T: TableInstance> {
     table: &'a T,
 }
@@ -101,6 +137,38 @@ where
         let (key, values) = try_into_row(key, value)?;
         self.table.insert(txn.id(), key, values).await
     }
+
+    async fn handle_post(
+        self: Box<Self>,
+        _request: &Request,
+        txn: &Txn,
+        params: Map<Scalar>,
+    ) -> TCResult<State> {
+        let mut row: Row = params.try_cast_into(|v| error::bad_request("Invalid row", v))?;
+
+        let values = self
+            .table
+            .values()
+            .iter()
+            .map(|col| {
+                row.remove(col.name())
+                    .ok_or_else(|| error::bad_request("Missing value for column", col.name()))
+            })
+            .collect::<TCResult<Vec<Value>>>()?;
+
+        if !row.is_empty() {
+            return Err(error::bad_request(
+                "Unrecognized columns",
+                row.keys()
+                    .map(|c| c.to_string())
+                    .collect::<Vec<String>>()
+                    .join(", "),
+            ));
+        }
+
+        let key = self.table.insert_generated(txn, values).await?;
+        Ok(State::from(Value::from_iter(key)))
+    }
 }
 
 pub struct LimitHandler<'a, T: TableInstance> {
@@ -391,6 +459,7 @@ where
             Some(handler)
         } else if path.len() == 1 {
             let handler: Box<dyn Handler> = match path[0].as_str() {
+                "aggregate" => Box::new(AggregateHandler { table }),
                 "group_by" => Box::new(GroupByHandler { table }),
                 "insert" => Box::new(InsertHandler { table }),
                 "limit" => Box::new(LimitHandler { table }),
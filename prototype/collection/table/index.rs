@@ -1,36 +1,78 @@
 use std::collections::{BTreeMap, HashMap, HashSet};
 use std::iter::FromIterator;
+use std::ops::Deref;
+use std::time::{SystemTime, UNIX_EPOCH};
 
 use async_trait::async_trait;
-use futures::future::{self, join_all, try_join_all, TryFutureExt};
+use futures::future::{self, join, join_all, try_join_all, TryFutureExt};
 use futures::stream::{StreamExt, TryStreamExt};
 use log::debug;
+use uuid::Uuid;
 
 use crate::class::Instance;
 use crate::collection::btree::{self, BTreeFile, BTreeInstance};
-use crate::collection::schema::{Column, IndexSchema, Row, TableSchema};
+use crate::collection::schema::{Column, Constraints, IndexSchema, KeyGenerator, Row, TableSchema};
 use crate::collection::Collection;
 use crate::error;
-use crate::scalar::{Id, Scalar, Value};
+use crate::registry::SchemaRef;
+use crate::scalar::{Id, Link, Scalar, TCString, Value};
+use crate::transaction::lock::{Mutable, TxnLock};
 use crate::transaction::{Transact, Txn, TxnId};
-use crate::{TCResult, TCTryStream};
+use crate::{TCResult, TCTryStream, TryCastInto};
 
 use super::bounds::{Bounds, ColumnBound};
+use super::overflow::{self, Overflow};
+use super::shard::Shards;
 use super::view::{IndexSlice, MergeSource, Merged, TableSlice};
 use super::{Table, TableInstance, TableType};
 
+const OVERFLOW: &str = "overflow";
 const PRIMARY_INDEX: &str = "primary";
 
 #[derive(Clone)]
 pub struct Index {
     btree: BTreeFile,
     schema: IndexSchema,
+    overflow: Option<Overflow>,
 }
 
 impl Index {
-    pub async fn create(txn: &Txn, schema: IndexSchema) -> TCResult<Index> {
-        let btree = BTreeFile::create(txn, schema.clone().into()).await?;
-        Ok(Index { btree, schema })
+    async fn copy_from<'a>(
+        txn: &'a Txn,
+        schema: IndexSchema,
+        rows: TCTryStream<'a, Vec<Value>>,
+    ) -> TCResult<Index> {
+        let overflow =
+            Overflow::create(&txn.subcontext_tmp().await?, &schema, &HashSet::new()).await?;
+
+        let btree = BTreeFile::create(
+            &txn.subcontext_tmp().await?,
+            overflow::storage_schema(&overflow, &schema).into(),
+        )
+        .await?;
+
+        let rows = overflow::store(&overflow, txn.id(), rows);
+        btree.try_insert_from(txn.id(), rows).await?;
+
+        Ok(Index {
+            btree,
+            schema,
+            overflow,
+        })
+    }
+
+    // delete the out-of-row values of the stored rows in the given range, if there are any
+    async fn free(&self, txn_id: &TxnId, range: btree::BTreeRange) -> TCResult<()> {
+        let overflow = match &self.overflow {
+            Some(overflow) => overflow,
+            None => return Ok(()),
+        };
+
+        self.btree
+            .stream(txn_id, range, false)
+            .await?
+            .try_for_each(|row| async move { overflow.free(txn_id, &row).await })
+            .await
     }
 
     pub fn btree(&'_ self) -> &'_ BTreeFile {
@@ -43,7 +85,8 @@ impl Index {
         key: Vec<Value>,
     ) -> TCResult<Option<Vec<Value>>> {
         let key = self.schema.validate_key(key)?;
-        let mut rows = self.btree.stream(&txn_id, key.into(), false).await?;
+        let rows = self.btree.stream(&txn_id, key.into(), false).await?;
+        let mut rows = overflow::resolve(&self.overflow, txn_id, rows);
         rows.try_next().await
     }
 
@@ -58,11 +101,16 @@ impl Index {
     pub fn index_slice(self, bounds: Bounds) -> TCResult<IndexSlice> {
         debug!("Index::index_slice");
         let bounds = bounds.validate(&self.schema.columns())?;
-        IndexSlice::new(self.btree, self.schema, bounds)
+        IndexSlice::new(self.btree, self.schema, self.overflow, bounds)
     }
 
     async fn insert(&self, txn_id: &TxnId, row: Row, reject_extra_columns: bool) -> TCResult<()> {
         let key = self.schema().values_from_row(row, reject_extra_columns)?;
+        let key = match &self.overflow {
+            Some(overflow) => overflow.write(txn_id, key).await?,
+            None => key,
+        };
+
         self.btree.insert(txn_id, key).await
     }
 
@@ -94,7 +142,8 @@ impl Index {
         self.validate_bounds(&bounds)?;
 
         let range = bounds.into_btree_range(&self.schema.columns())?;
-        self.btree.stream(txn_id, range, reverse).await
+        let rows = self.btree.stream(txn_id, range, reverse).await?;
+        Ok(overflow::resolve(&self.overflow, txn_id, rows))
     }
 }
 
@@ -121,13 +170,21 @@ impl TableInstance for Index {
     }
 
     async fn delete(&self, txn_id: &TxnId) -> TCResult<()> {
+        self.free(txn_id, btree::BTreeRange::default()).await?;
+
         self.btree
             .delete(txn_id, btree::BTreeRange::default())
             .await
     }
 
     async fn delete_row(&self, txn_id: &TxnId, row: Row) -> TCResult<()> {
-        let key = self.schema.values_from_row(row, false)?;
+        let mut key = self.schema.values_from_row(row, false)?;
+        if self.overflow.is_some() {
+            // the stored row holds pointers in place of its out-of-row values
+            key.truncate(self.schema.key().len());
+            self.free(txn_id, key.clone().into()).await?;
+        }
+
         self.btree.delete(txn_id, key.into()).await
     }
 
@@ -141,7 +198,12 @@ impl TableInstance for Index {
 
     fn order_by(self, order: Vec<Id>, reverse: bool) -> TCResult<Self::OrderBy> {
         if self.schema.starts_with(&order) {
-            Ok(IndexSlice::all(self.btree, self.schema, reverse))
+            Ok(IndexSlice::all(
+                self.btree,
+                self.schema,
+                self.overflow,
+                reverse,
+            ))
         } else {
             Err(error::bad_request(
                 &format!("Index with schema {} does not support order", self.schema),
@@ -151,7 +213,7 @@ impl TableInstance for Index {
     }
 
     fn reversed(self) -> TCResult<Self::Reverse> {
-        Ok(IndexSlice::all(self.btree, self.schema, true).into())
+        Ok(IndexSlice::all(self.btree, self.schema, self.overflow, true).into())
     }
 
     fn slice(self, bounds: Bounds) -> TCResult<IndexSlice> {
@@ -161,9 +223,12 @@ impl TableInstance for Index {
     async fn stream<'a>(&'a self, txn_id: &'a TxnId) -> TCResult<TCTryStream<'a, Vec<Value>>> {
         debug!("Index::stream");
 
-        self.btree
+        let rows = self
+            .btree
             .stream(txn_id, btree::BTreeRange::default(), false)
-            .await
+            .await?;
+
+        Ok(overflow::resolve(&self.overflow, txn_id, rows))
     }
 
     fn validate_bounds(&self, bounds: &Bounds) -> TCResult<()> {
@@ -216,6 +281,14 @@ impl TableInstance for Index {
 
     async fn update(&self, txn: &Txn, row: Row) -> TCResult<()> {
         let key: btree::Key = self.schema().values_from_row(row, false)?;
+        let key = match &self.overflow {
+            Some(overflow) => {
+                self.free(txn.id(), btree::BTreeRange::default()).await?;
+                overflow.write(txn.id(), key).await?
+            }
+            None => key,
+        };
+
         self.btree
             .update(txn.id(), btree::BTreeRange::default(), &key)
             .await
@@ -225,15 +298,27 @@ impl TableInstance for Index {
 #[async_trait]
 impl Transact for Index {
     async fn commit(&self, txn_id: &TxnId) {
-        self.btree.commit(txn_id).await
+        if let Some(overflow) = &self.overflow {
+            join(self.btree.commit(txn_id), overflow.commit(txn_id)).await;
+        } else {
+            self.btree.commit(txn_id).await
+        }
     }
 
     async fn rollback(&self, txn_id: &TxnId) {
-        self.btree.rollback(txn_id).await
+        if let Some(overflow) = &self.overflow {
+            join(self.btree.rollback(txn_id), overflow.rollback(txn_id)).await;
+        } else {
+            self.btree.rollback(txn_id).await
+        }
     }
 
     async fn finalize(&self, txn_id: &TxnId) {
-        self.btree.finalize(txn_id).await
+        if let Some(overflow) = &self.overflow {
+            join(self.btree.finalize(txn_id), overflow.finalize(txn_id)).await;
+        } else {
+            self.btree.finalize(txn_id).await
+        }
     }
 }
 
@@ -256,28 +341,17 @@ impl ReadOnly {
     ) -> TCResult<ReadOnly> {
         let source_schema: IndexSchema = (source.key().to_vec(), source.values().to_vec()).into();
 
-        let (schema, btree) = if let Some(columns) = key_columns {
+        let index = if let Some(columns) = key_columns {
             let column_names: HashSet<&Id> = columns.iter().collect();
             let schema = source_schema.subset(column_names)?;
-            let btree =
-                BTreeFile::create(&txn.subcontext_tmp().await?, schema.clone().into()).await?;
-
             let source = source.select(columns)?;
             let rows = source.stream(txn.id()).await?;
-            btree.try_insert_from(txn.id(), rows).await?;
-            (schema, btree)
+            Index::copy_from(&txn, schema, rows).await?
         } else {
-            let btree =
-                BTreeFile::create(&txn.subcontext_tmp().await?, source_schema.clone().into())
-                    .await?;
-
             let rows = source.stream(txn.id()).await?;
-            btree.try_insert_from(txn.id(), rows).await?;
-            (source_schema, btree)
+            Index::copy_from(&txn, source_schema, rows).await?
         };
 
-        let index = Index { schema, btree };
-
         index
             .index_slice(Bounds::default())
             .map(|index| ReadOnly { index })
@@ -368,16 +442,53 @@ impl From<ReadOnly> for Collection {
 pub struct TableIndex {
     primary: Index,
     auxiliary: BTreeMap<Id, Index>,
+    constraints: Constraints,
+    registered: Option<SchemaRef>,
+    generator: Option<KeyGenerator>,
+    key_sequence: Id,
+    shards: TxnLock<Mutable<Shards>>,
 }
 
 impl TableIndex {
     pub async fn create(txn: &Txn, schema: TableSchema) -> TCResult<TableIndex> {
-        let primary = Index::create(
+        let constraints = schema.constraints().clone();
+        schema.primary().validate_columns(&constraints.columns())?;
+        let registered = schema.registered().cloned();
+
+        let generator = schema.generator();
+        if generator.is_some() && schema.primary().key().len() != 1 {
+            return Err(error::bad_request(
+                "A generated key requires exactly one key column, not",
+                schema.primary().key().len(),
+            ));
+        }
+
+        // a column in the key of an auxiliary index must be stored in-row to be collated
+        let indexed: HashSet<Id> = schema
+            .indices()
+            .iter()
+            .flat_map(|(_, column_names)| column_names.iter().cloned())
+            .collect();
+
+        let overflow = Overflow::create(
+            &txn.subcontext(OVERFLOW.parse()?).await?,
+            schema.primary(),
+            &indexed,
+        )
+        .await?;
+
+        let btree = BTreeFile::create(
             &txn.subcontext(PRIMARY_INDEX.parse()?).await?,
-            schema.primary().clone(),
+            overflow::storage_schema(&overflow, schema.primary()).into(),
         )
         .await?;
 
+        let primary = Index {
+            btree,
+            schema: schema.primary().clone(),
+            overflow,
+        };
+
         let auxiliary: BTreeMap<Id, Index> =
             try_join_all(schema.indices().iter().map(|(name, column_names)| {
                 Self::create_index(txn, schema.primary(), name.clone(), column_names.to_vec())
@@ -387,7 +498,15 @@ impl TableIndex {
             .into_iter()
             .collect();
 
-        Ok(TableIndex { primary, auxiliary })
+        Ok(TableIndex {
+            primary,
+            auxiliary,
+            constraints,
+            registered,
+            generator,
+            key_sequence: format!("table-{}", Uuid::new_v4()).parse()?,
+            shards: TxnLock::new("Table shards", Shards::default().into()),
+        })
     }
 
     async fn create_index(
@@ -437,13 +556,67 @@ impl TableIndex {
         let btree =
             btree::BTreeFile::create(&txn.subcontext_tmp().await?, schema.clone().into()).await?;
 
-        Ok(Index { btree, schema })
+        Ok(Index {
+            btree,
+            schema,
+            overflow: None,
+        })
     }
 
     pub async fn is_empty(&self, txn: &Txn) -> TCResult<bool> {
         self.primary.is_empty(txn).await
     }
 
+    async fn generate_key(&self, txn: &Txn) -> TCResult<Vec<Value>> {
+        let key = match self.generator {
+            Some(KeyGenerator::Sequence) => {
+                // the key sequence is stored at /sbin/sequence, so it outlives this process,
+                // but if it's missing (e.g. the table was restored on a new host) it has to
+                // start after the greatest key already present
+                let sequences = txn.gateway().sequences();
+                let sequence = match sequences.find(&self.key_sequence).await {
+                    Some(sequence) => sequence,
+                    None => {
+                        let start = self.next_key(txn.id()).await?;
+                        sequences
+                            .get_or_create(self.key_sequence.clone(), start)
+                            .await?
+                    }
+                };
+
+                Value::from(sequence.next(txn, 1).await?)
+            }
+            Some(KeyGenerator::Uuid) => Value::TCString(TCString::UString(uuid_v7().to_string())),
+            None => {
+                return Err(error::bad_request(
+                    "This Table has no key generator, so a key is required for",
+                    "insert",
+                ))
+            }
+        };
+
+        Ok(vec![key])
+    }
+
+    /// Return the generated key which follows the greatest key in this table.
+    async fn next_key(&self, txn_id: &TxnId) -> TCResult<u64> {
+        let mut keys = self
+            .primary
+            .btree()
+            .stream(txn_id, btree::BTreeRange::default(), true)
+            .await?;
+
+        let max: u64 = match keys.try_next().await? {
+            Some(mut key) => key.remove(0).try_cast_into(|v| {
+                error::bad_request("A generated key must be an unsigned integer, not", v)
+            })?,
+            None => return Ok(1),
+        };
+
+        max.checked_add(1)
+            .ok_or_else(|| error::bad_request("Key sequence is exhausted after", max))
+    }
+
     pub fn merge_bounds(&self, all_bounds: Vec<Bounds>) -> TCResult<Bounds> {
         let collator = self.primary.btree().collator();
 
@@ -459,6 +632,33 @@ impl TableIndex {
         &self.primary
     }
 
+    /// The ranges of this table's primary key which are stored by peer hosts.
+    pub async fn shards(&self, txn_id: &TxnId) -> TCResult<Shards> {
+        let shards = self.shards.read(txn_id).await?;
+        Ok(shards.deref().clone())
+    }
+
+    /// Return the peer which stores the row with the given primary key, or `None` if it's
+    /// stored by this host.
+    pub async fn owner(&self, txn_id: &TxnId, key: &[Value]) -> TCResult<Option<Link>> {
+        let shards = self.shards.read(txn_id).await?;
+        Ok(shards.owner(self.primary.btree().collator(), key).cloned())
+    }
+
+    /// Record that the range of primary keys beginning at `start` is stored by `peer`, or by
+    /// this host if `peer` is `None`. This does not move any rows, unlike
+    /// [`rebalance`](super::rebalance).
+    pub async fn assign_shard(
+        &self,
+        txn_id: &TxnId,
+        start: Vec<Value>,
+        peer: Option<Link>,
+    ) -> TCResult<()> {
+        let mut shards = self.shards.write(*txn_id).await?;
+        shards.assign(self.primary.btree().collator(), start, peer);
+        Ok(())
+    }
+
     pub fn supporting_index(&self, bounds: &Bounds) -> TCResult<Index> {
         if self.primary.validate_bounds(bounds).is_ok() {
             return Ok(self.primary.clone());
@@ -501,18 +701,56 @@ impl TableIndex {
         }
     }
 
+    /// Insert a batch of new rows, in order. Unlike `insert`, the keys are not looked up first,
+    /// so the caller is responsible for ensuring that none of them already exist.
+    pub async fn insert_new(
+        &self,
+        txn_id: &TxnId,
+        rows: Vec<(Vec<Value>, Vec<Value>)>,
+    ) -> TCResult<()> {
+        for (key, values) in rows {
+            let row = self.primary.schema().row_from_key_values(key, values)?;
+            self.validate(txn_id, &row).await?;
+
+            let mut inserts = Vec::with_capacity(self.auxiliary.len() + 1);
+            inserts.push(self.primary.insert(txn_id, row.clone(), true));
+            for index in self.auxiliary.values() {
+                inserts.push(index.insert(txn_id, row.clone(), false));
+            }
+
+            try_join_all(inserts).await?;
+        }
+
+        Ok(())
+    }
+
+    // validate a new row against the constraints of this table and its registered schema, if any
+    async fn validate(&self, txn_id: &TxnId, row: &Row) -> TCResult<()> {
+        self.constraints.validate(row)?;
+
+        if let Some(registered) = &self.registered {
+            registered.validate(txn_id, row).await?;
+        }
+
+        Ok(())
+    }
+
     pub async fn upsert(
         &self,
         txn_id: &TxnId,
         key: Vec<Value>,
         values: Vec<Value>,
     ) -> TCResult<()> {
-        if let Some(row) = self.get(txn_id, key.to_vec()).await? {
-            let row = self.primary.schema.row_from_values(row.to_vec())?;
-            self.delete_row(txn_id, row.clone()).await?;
-        }
+        let row = self
+            .primary
+            .schema()
+            .row_from_key_values(key.to_vec(), values)?;
+        self.validate(txn_id, &row).await?;
 
-        let row = self.primary.schema().row_from_key_values(key, values)?;
+        if let Some(existing) = self.get(txn_id, key).await? {
+            let existing = self.primary.schema.row_from_values(existing.to_vec())?;
+            self.delete_row(txn_id, existing).await?;
+        }
 
         let mut inserts = Vec::with_capacity(self.auxiliary.len() + 1);
         inserts.push(self.primary.insert(txn_id, row.clone(), true));
@@ -584,6 +822,12 @@ impl TableInstance for TableIndex {
         TableIndex::insert(self, txn_id, key, values).await
     }
 
+    async fn insert_generated(&self, txn: &Txn, values: Vec<Value>) -> TCResult<Vec<Value>> {
+        let key = self.generate_key(txn).await?;
+        TableIndex::insert(self, txn.id(), key.to_vec(), values).await?;
+        Ok(key)
+    }
+
     fn key(&'_ self) -> &'_ [Column] {
         self.primary.key()
     }
@@ -869,6 +1113,7 @@ impl TableInstance for TableIndex {
 
         let schema = self.primary.schema();
         let update = schema.validate_row_partial(update)?;
+        self.constraints.validate(&update)?;
 
         let index = self.clone().index(txn.clone(), None).await?;
         let index = index.stream(txn.id()).await?;
@@ -906,7 +1151,7 @@ impl Transact for TableIndex {
             commits.push(index.commit(txn_id));
         }
 
-        join_all(commits).await;
+        join(join_all(commits), self.shards.commit(txn_id)).await;
     }
 
     async fn rollback(&self, txn_id: &TxnId) {
@@ -916,7 +1161,7 @@ impl Transact for TableIndex {
             rollbacks.push(index.rollback(txn_id));
         }
 
-        join_all(rollbacks).await;
+        join(join_all(rollbacks), self.shards.rollback(txn_id)).await;
     }
 
     async fn finalize(&self, txn_id: &TxnId) {
@@ -926,7 +1171,7 @@ impl Transact for TableIndex {
             cleanups.push(index.finalize(txn_id));
         }
 
-        join_all(cleanups).await;
+        join(join_all(cleanups), self.shards.finalize(txn_id)).await;
     }
 }
 
@@ -935,3 +1180,16 @@ impl From<TableIndex> for Collection {
         Collection::Table(index.into())
     }
 }
+
+fn uuid_v7() -> Uuid {
+    let millis = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("system time")
+        .as_millis() as u64;
+
+    let mut bytes: [u8; 16] = rand::random();
+    bytes[..6].copy_from_slice(&millis.to_be_bytes()[2..]);
+    bytes[6] = (bytes[6] & 0x0f) | 0x70;
+    bytes[8] = (bytes[8] & 0x3f) | 0x80;
+    Uuid::from_bytes(bytes)
+}
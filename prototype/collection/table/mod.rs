@@ -8,6 +8,7 @@ use futures::TryStreamExt;
 use crate::class::*;
 use crate::error;
 use crate::handler::*;
+use crate::registry;
 use crate::scalar::{label, Id, Link, MethodType, PathSegment, Scalar, TCPathBuf, Value};
 use crate::transaction::{Transact, Txn, TxnId};
 use crate::{TCResult, TCTryStream, TryCastInto};
@@ -17,8 +18,12 @@ use super::schema::{Column, Row, TableSchema};
 use super::{Collection, CollectionType};
 
 mod bounds;
+mod columnar;
 mod handlers;
 mod index;
+mod overflow;
+mod scatter;
+mod shard;
 mod view;
 
 const ERR_DELETE: &str = "Deletion is not supported by instance of";
@@ -27,8 +32,12 @@ const ERR_SLICE: &str = "Slicing is not supported by instance of";
 const ERR_UPDATE: &str = "Update is not supported by instance of";
 
 pub use bounds::*;
+pub use columnar::{Aggregation, ColumnChunk, ScanMode};
 pub use handlers::TableImpl;
 pub use index::*;
+pub use overflow::Chunk;
+pub use scatter::{aggregate, slice};
+pub use shard::{rebalance, Shards};
 pub use view::*;
 
 #[derive(Clone, Eq, PartialEq)]
@@ -71,9 +80,15 @@ impl CollectionClass for TableType {
     type Instance = Table;
 
     async fn get(&self, txn: &Txn, schema: Value) -> TCResult<Table> {
-        let schema =
+        let (schema, registered) = registry::resolve(txn, schema).await?;
+        let schema: TableSchema =
             schema.try_cast_into(|v| error::bad_request("Expected TableSchema but found", v))?;
 
+        let schema = match registered {
+            Some(registered) => schema.with_registered(registered),
+            None => schema,
+        };
+
         TableIndex::create(txn, schema)
             .map_ok(TableImpl::from)
             .map_ok(Table::Table)
@@ -150,6 +165,11 @@ pub trait TableInstance: Instance<Class = TableType> + Sized + 'static {
         Err(error::bad_request(ERR_INSERT, self.class()))
     }
 
+    /// Insert a row with a generated key, and return the key.
+    async fn insert_generated(&self, _txn: &Txn, _values: Vec<Value>) -> TCResult<Vec<Value>> {
+        Err(error::bad_request(ERR_INSERT, self.class()))
+    }
+
     fn key(&'_ self) -> &'_ [Column];
 
     fn values(&'_ self) -> &'_ [Column];
@@ -252,7 +272,7 @@ impl Route for Table {
         match self {
             Self::Index(index) => index.route(method, path),
             Self::ROIndex(index) => index.route(method, path),
-            Self::Table(table) => table.route(method, path),
+            Self::Table(table) => shard::route(table, method, path),
             Self::Aggregate(aggregate) => aggregate.route(method, path),
             Self::IndexSlice(index) => index.route(method, path),
             Self::Limit(limit) => limit.route(method, path),
@@ -355,6 +375,16 @@ impl TableInstance for Table {
         }
     }
 
+    async fn insert_generated(&self, txn: &Txn, values: Vec<Value>) -> TCResult<Vec<Value>> {
+        match self {
+            Self::Table(table) => table.insert_generated(txn, values).await,
+            other => Err(error::bad_request(
+                "TableView does not support insert",
+                other,
+            )),
+        }
+    }
+
     fn key(&'_ self) -> &'_ [Column] {
         match self {
             Self::Index(index) => index.key(),
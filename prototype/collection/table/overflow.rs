@@ -0,0 +1,422 @@
+use std::collections::HashSet;
+use std::convert::TryFrom;
+use std::fmt;
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use bytes::{Bytes, BytesMut};
+use futures::stream::TryStreamExt;
+use log::debug;
+use uuid::Uuid;
+
+use crate::block::{BlockData, BlockId, File};
+use crate::collection::schema::{Column, IndexSchema};
+use crate::error;
+use crate::scalar::{Id, StringType, TCString, Value, ValueType};
+use crate::transaction::{Transact, Txn, TxnId};
+use crate::{TCResult, TCTryStream};
+
+const CHUNK_SIZE: usize = 64_000;
+const MARKER: &str = "\u{0}tc-overflow:";
+
+// the serialized size of the largest value which is stored in-row in an eligible column
+const INLINE_SIZE: usize = 4_096;
+
+/// A block of a value stored outside of the row which contains it.
+#[derive(Clone)]
+pub struct Chunk(Bytes);
+
+impl TryFrom<Bytes> for Chunk {
+    type Error = error::TCError;
+
+    fn try_from(data: Bytes) -> TCResult<Chunk> {
+        Ok(Chunk(data))
+    }
+}
+
+impl From<Chunk> for Bytes {
+    fn from(chunk: Chunk) -> Bytes {
+        chunk.0
+    }
+}
+
+impl BlockData for Chunk {
+    fn size(&self) -> usize {
+        self.0.len()
+    }
+}
+
+impl fmt::Display for Chunk {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "table overflow chunk of {} bytes", self.0.len())
+    }
+}
+
+/// Out-of-row storage for large string and bytes values in a table index.
+///
+/// A value column of type String or Bytes whose maximum length is larger than `INLINE_SIZE`,
+/// and which is not part of the key of any index, is eligible for out-of-row storage. Each value
+/// in an eligible column which is larger than `INLINE_SIZE` is split into chunks which are stored
+/// in a separate file, and the row holds a pointer to the chunks instead. A value larger than the
+/// column's maximum length is rejected. Pointers are resolved transparently when the row is read,
+/// and the chunks they point to are deleted along with the row.
+#[derive(Clone)]
+pub struct Overflow {
+    file: Arc<File<Chunk>>,
+    max_lens: Vec<Option<usize>>,
+}
+
+impl Overflow {
+    /// Construct a new `Overflow` file for the given index, or return `None` if none of its
+    /// columns is eligible for out-of-row storage. Columns in `indexed` are not eligible.
+    pub async fn create(
+        txn: &Txn,
+        schema: &IndexSchema,
+        indexed: &HashSet<Id>,
+    ) -> TCResult<Option<Overflow>> {
+        let key_len = schema.key().len();
+        let max_lens: Vec<Option<usize>> = schema
+            .columns()
+            .into_iter()
+            .enumerate()
+            .map(|(i, column)| {
+                let eligible =
+                    i >= key_len && !indexed.contains(column.name()) && is_variable(column.dtype());
+
+                match column.max_len() {
+                    Some(max_len) if eligible && *max_len > INLINE_SIZE => Some(*max_len),
+                    _ => None,
+                }
+            })
+            .collect();
+
+        if max_lens.iter().all(Option::is_none) {
+            return Ok(None);
+        }
+
+        let file = txn.context().await?;
+        Ok(Some(Overflow { file, max_lens }))
+    }
+
+    /// The schema of the rows as stored in-row, where each eligible column holds at most
+    /// `INLINE_SIZE` bytes.
+    pub fn storage_schema(&self, schema: &IndexSchema) -> IndexSchema {
+        let key_len = schema.key().len();
+        let mut columns: Vec<Column> = schema
+            .columns()
+            .into_iter()
+            .zip(&self.max_lens)
+            .map(|(column, max_len)| match max_len {
+                Some(_) => column.with_max_len(INLINE_SIZE),
+                None => column,
+            })
+            .collect();
+
+        let values = columns.split_off(key_len);
+        (columns, values).into()
+    }
+
+    /// Move every value in `row` which is too large to store in-row into this `Overflow` file,
+    /// and return the row with a pointer in its place.
+    pub async fn write(&self, txn_id: &TxnId, mut row: Vec<Value>) -> TCResult<Vec<Value>> {
+        for (value, max_len) in row.iter_mut().zip(&self.max_lens) {
+            let max_len = match max_len {
+                Some(max_len) => *max_len,
+                None => continue,
+            };
+
+            let size = bincode::serialized_size(value)? as usize;
+            if size > max_len {
+                return Err(error::bad_request(
+                    "Column value exceeds the maximum length",
+                    max_len,
+                ));
+            } else if size <= INLINE_SIZE && !is_pointer(value) {
+                continue;
+            }
+
+            let (data, dtype) = match value {
+                Value::TCString(TCString::UString(s)) => (
+                    Bytes::copy_from_slice(s.as_bytes()),
+                    StringType::UString.into(),
+                ),
+                Value::Bytes(bytes) => (bytes.clone(), ValueType::Bytes),
+                _ => continue,
+            };
+
+            *value = self.write_value(txn_id, data, dtype).await?;
+        }
+
+        Ok(row)
+    }
+
+    /// Replace every pointer in `row` with the value it points to.
+    pub async fn read(&self, txn_id: &TxnId, mut row: Vec<Value>) -> TCResult<Vec<Value>> {
+        for (value, max_len) in row.iter_mut().zip(&self.max_lens) {
+            if max_len.is_none() {
+                continue;
+            }
+
+            if let Some((id, num_chunks)) = parse_pointer(value)? {
+                let data = self.read_value(txn_id, &id, num_chunks).await?;
+                *value = match value {
+                    Value::Bytes(_) => Value::Bytes(data),
+                    _ => {
+                        let data = String::from_utf8(data.to_vec()).map_err(|e| {
+                            error::internal(format!("Corrupt overflow value: {}", e))
+                        })?;
+
+                        Value::TCString(TCString::UString(data))
+                    }
+                };
+            }
+        }
+
+        Ok(row)
+    }
+
+    /// Delete the chunks of every out-of-row value in the stored `row`.
+    pub async fn free(&self, txn_id: &TxnId, row: &[Value]) -> TCResult<()> {
+        for (value, max_len) in row.iter().zip(&self.max_lens) {
+            if max_len.is_none() {
+                continue;
+            }
+
+            if let Some((id, num_chunks)) = parse_pointer(value)? {
+                debug!("deleting an out-of-row value in {} chunks", num_chunks);
+
+                for i in 0..num_chunks {
+                    self.file.delete_block(*txn_id, chunk_id(&id, i)?).await?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    async fn write_value(&self, txn_id: &TxnId, data: Bytes, dtype: ValueType) -> TCResult<Value> {
+        let id = Uuid::new_v4();
+        let chunks: Vec<Bytes> = data
+            .chunks(CHUNK_SIZE)
+            .map(Bytes::copy_from_slice)
+            .collect();
+        let num_chunks = chunks.len();
+
+        debug!(
+            "storing a value of {} bytes out-of-row in {} chunks",
+            data.len(),
+            num_chunks
+        );
+
+        for (i, chunk) in chunks.into_iter().enumerate() {
+            self.file
+                .spill_block(*txn_id, chunk_id(&id, i)?, Chunk(chunk))
+                .await?;
+        }
+
+        let pointer = format!("{}{}:{}", MARKER, id, num_chunks);
+        if dtype == ValueType::Bytes {
+            Ok(Value::Bytes(pointer.into()))
+        } else {
+            Ok(Value::TCString(TCString::UString(pointer)))
+        }
+    }
+
+    async fn read_value(&self, txn_id: &TxnId, id: &Uuid, num_chunks: usize) -> TCResult<Bytes> {
+        let mut data = BytesMut::new();
+        for i in 0..num_chunks {
+            let chunk = self.file.get_block(txn_id, chunk_id(id, i)?).await?;
+            data.extend_from_slice(&chunk.0);
+        }
+
+        Ok(data.freeze())
+    }
+}
+
+#[async_trait]
+impl Transact for Overflow {
+    async fn commit(&self, txn_id: &TxnId) {
+        self.file.commit(txn_id).await
+    }
+
+    async fn rollback(&self, txn_id: &TxnId) {
+        self.file.rollback(txn_id).await
+    }
+
+    async fn finalize(&self, txn_id: &TxnId) {
+        self.file.finalize(txn_id).await
+    }
+}
+
+/// Resolve the out-of-row values in a stream of rows, if there are any.
+pub fn resolve<'a>(
+    overflow: &'a Option<Overflow>,
+    txn_id: &'a TxnId,
+    rows: TCTryStream<'a, Vec<Value>>,
+) -> TCTryStream<'a, Vec<Value>> {
+    match overflow {
+        Some(overflow) => Box::pin(rows.and_then(move |row| Box::pin(overflow.read(txn_id, row)))),
+        None => rows,
+    }
+}
+
+/// The schema of the rows as stored in-row, given the `Overflow` file of their index, if any.
+pub fn storage_schema(overflow: &Option<Overflow>, schema: &IndexSchema) -> IndexSchema {
+    match overflow {
+        Some(overflow) => overflow.storage_schema(schema),
+        None => schema.clone(),
+    }
+}
+
+/// Move the large values in a stream of rows out-of-row, if there are any eligible columns.
+pub fn store<'a>(
+    overflow: &'a Option<Overflow>,
+    txn_id: &'a TxnId,
+    rows: TCTryStream<'a, Vec<Value>>,
+) -> TCTryStream<'a, Vec<Value>> {
+    match overflow {
+        Some(overflow) => Box::pin(rows.and_then(move |row| Box::pin(overflow.write(txn_id, row)))),
+        None => rows,
+    }
+}
+
+fn chunk_id(id: &Uuid, i: usize) -> TCResult<BlockId> {
+    format!("{}-{}", id.to_hyphenated(), i).parse()
+}
+
+fn is_variable(dtype: ValueType) -> bool {
+    match dtype {
+        ValueType::Bytes => true,
+        ValueType::TCString(StringType::UString) => true,
+        _ => false,
+    }
+}
+
+fn is_pointer(value: &Value) -> bool {
+    match value {
+        Value::TCString(TCString::UString(s)) => s.starts_with(MARKER),
+        Value::Bytes(bytes) => bytes.starts_with(MARKER.as_bytes()),
+        _ => false,
+    }
+}
+
+fn parse_pointer(value: &Value) -> TCResult<Option<(Uuid, usize)>> {
+    let pointer = match value {
+        Value::TCString(TCString::UString(s)) if s.starts_with(MARKER) => &s[MARKER.len()..],
+        Value::Bytes(bytes) if bytes.starts_with(MARKER.as_bytes()) => {
+            std::str::from_utf8(&bytes[MARKER.len()..])
+                .map_err(|e| error::internal(format!("Corrupt overflow pointer: {}", e)))?
+        }
+        _ => return Ok(None),
+    };
+
+    let mut parts = pointer.splitn(2, ':');
+    let id = parts.next().and_then(|id| Uuid::parse_str(id).ok());
+    let num_chunks = parts.next().and_then(|n| n.parse().ok());
+
+    match (id, num_chunks) {
+        (Some(id), Some(num_chunks)) => Ok(Some((id, num_chunks))),
+        _ => Err(error::internal(format!(
+            "Corrupt overflow pointer: {}",
+            pointer
+        ))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::block::hostfs;
+    use crate::scalar::{NumberType, UIntType};
+
+    use super::*;
+
+    async fn overflow(max_lens: Vec<Option<usize>>) -> TCResult<Overflow> {
+        let data_dir = std::env::temp_dir().join(Uuid::new_v4().to_string());
+        let file = File::create("overflow", hostfs::mount(data_dir)).await?;
+        Ok(Overflow { file, max_lens })
+    }
+
+    fn string(s: String) -> Value {
+        Value::TCString(TCString::UString(s))
+    }
+
+    #[tokio::test]
+    async fn test_write_and_read() -> TCResult<()> {
+        let txn_id = TxnId::zero();
+        let overflow = overflow(vec![None, Some(200_000), Some(200_000)]).await?;
+
+        let large = "a".repeat((2 * CHUNK_SIZE) + 1);
+        let bytes = Value::Bytes(Bytes::from(vec![1u8; INLINE_SIZE * 2]));
+        let row = vec![string(large.clone()), string(large), bytes];
+
+        let stored = overflow.write(&txn_id, row.clone()).await?;
+
+        // only the large values in eligible columns are moved out-of-row
+        assert_eq!(stored[0], row[0]);
+        assert!(is_pointer(&stored[1]));
+        assert!(is_pointer(&stored[2]));
+        assert_eq!(parse_pointer(&stored[1])?.unwrap().1, 3);
+        assert_eq!(parse_pointer(&stored[2])?.unwrap().1, 1);
+
+        assert_eq!(overflow.read(&txn_id, stored.clone()).await?, row);
+
+        overflow.free(&txn_id, &stored).await?;
+        assert!(overflow.read(&txn_id, stored).await.is_err());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_inline() -> TCResult<()> {
+        let txn_id = TxnId::zero();
+        let overflow = overflow(vec![Some(200_000)]).await?;
+
+        let small = vec![string("small".to_string())];
+        assert_eq!(overflow.write(&txn_id, small.clone()).await?, small);
+
+        // a small value which looks like a pointer is stored out-of-row, so it's never misread
+        let lookalike = vec![string(format!("{}not a pointer", MARKER))];
+        let stored = overflow.write(&txn_id, lookalike.clone()).await?;
+        assert_ne!(stored, lookalike);
+        assert_eq!(overflow.read(&txn_id, stored).await?, lookalike);
+
+        let too_large = vec![string("a".repeat(200_001))];
+        let cause = overflow.write(&txn_id, too_large).await.unwrap_err();
+        assert!(cause.reason() == &error::ErrorType::BadRequest);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_pointer() {
+        let id = Uuid::new_v4();
+        let pointer = string(format!("{}{}:{}", MARKER, id, 2));
+        assert_eq!(parse_pointer(&pointer).unwrap(), Some((id, 2)));
+
+        let pointer = Value::Bytes(format!("{}{}:{}", MARKER, id, 2).into());
+        assert_eq!(parse_pointer(&pointer).unwrap(), Some((id, 2)));
+
+        assert_eq!(parse_pointer(&string("value".to_string())).unwrap(), None);
+        assert!(parse_pointer(&string(format!("{}{}", MARKER, id))).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_storage_schema() {
+        let id: Id = "id".parse().unwrap();
+        let body: Id = "body".parse().unwrap();
+        let schema: IndexSchema = (
+            vec![Column::from((id, NumberType::UInt(UIntType::U64)))],
+            vec![Column::from((
+                body,
+                ValueType::TCString(StringType::UString),
+                100_000,
+            ))],
+        )
+            .into();
+
+        let overflow = overflow(vec![None, Some(100_000)]).await.unwrap();
+        let stored = overflow.storage_schema(&schema);
+        assert_eq!(stored.key()[0].max_len(), &None);
+        assert_eq!(stored.values()[0].max_len(), &Some(INLINE_SIZE));
+    }
+}
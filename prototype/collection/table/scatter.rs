@@ -0,0 +1,371 @@
+use std::cmp::Ordering;
+use std::iter::{FromIterator, Peekable};
+use std::vec::IntoIter;
+
+use async_trait::async_trait;
+use futures::future::{try_join, try_join_all};
+use futures::stream::{StreamExt, TryStreamExt};
+use log::debug;
+
+use crate::auth::{Scope, SCOPE_READ};
+use crate::class::{Instance, State, TCType};
+use crate::collection::btree::Collator;
+use crate::error;
+use crate::general::Map;
+use crate::handler::*;
+use crate::request::Request;
+use crate::scalar::{label, Label, Link, Scalar, Value};
+use crate::transaction::Txn;
+use crate::{TCResult, TCTryStream, TryCastFrom, TryCastInto};
+
+use super::columnar::Aggregation;
+use super::shard::{self, SHARDS};
+use super::{Bounds, TableIndex, TableInstance};
+
+const AGGREGATE: Label = label("aggregate");
+const BOUNDS: Label = label("bounds");
+const LIMIT: Label = label("limit");
+const WHERE: Label = label("where");
+
+/// Evaluate `aggregation` over every shard of `table`, and merge the partial results.
+pub async fn aggregate(
+    request: &Request,
+    txn: &Txn,
+    table: &TableIndex,
+    params: Map<Scalar>,
+) -> TCResult<Map<Scalar>> {
+    let aggregation: Aggregation = params
+        .clone()
+        .try_cast_into(|v| error::bad_request("Invalid aggregation", v))?;
+
+    let peers = table.shards(txn.id()).await?.peers();
+    debug!("aggregate a table over {} peers", peers.len());
+
+    let local = aggregation.evaluate(table, txn.id());
+    let remote = try_join_all(peers.iter().map(|peer| {
+        let params = params.clone();
+        async move {
+            let partial = scatter(request, txn, peer, AGGREGATE, params).await?;
+            Map::<Scalar>::try_cast_from(partial, |s| {
+                error::bad_request("Invalid partial aggregate", s)
+            })
+        }
+    }));
+
+    let (local, mut partials) = try_join(local, remote).await?;
+    partials.push(local);
+    aggregation.merge(partials)
+}
+
+/// Return the rows of every shard of `table` within `bounds`, in primary key order, up to
+/// `limit` rows. The limit is pushed down to each shard, so that no shard returns more rows
+/// than the coordinator could need.
+pub async fn slice(
+    request: &Request,
+    txn: &Txn,
+    table: &TableIndex,
+    bounds: Map<Scalar>,
+    limit: Option<u64>,
+) -> TCResult<Vec<Vec<Value>>> {
+    let peers = table.shards(txn.id()).await?.peers();
+    debug!("slice a table over {} peers", peers.len());
+
+    let params = slice_params(bounds.clone(), limit);
+    let bounds = Bounds::try_cast_from(bounds, |v| {
+        error::bad_request("Cannot cast into Table Bounds from", v)
+    })?;
+
+    let local = slice_local(txn, table, bounds, limit);
+    let remote = try_join_all(peers.iter().map(|peer| {
+        let params = params.clone();
+        async move {
+            let rows = scatter(request, txn, peer, WHERE, params).await?;
+            let rows = Value::try_cast_from(rows, |s| {
+                error::bad_request("Invalid rows from a table shard", s)
+            })?;
+
+            rows.try_cast_into(|v| error::bad_request("Invalid rows from a table shard", v))
+        }
+    }));
+
+    let (local, mut sources) = try_join(local, remote).await?;
+    sources.push(local);
+
+    let collator = table.primary().btree().collator();
+    Ok(merge(collator, table.key().len(), sources, limit))
+}
+
+/// Evaluate `aggregation` over only the rows of `table` stored on this host.
+async fn aggregate_local(
+    txn: &Txn,
+    table: &TableIndex,
+    params: Map<Scalar>,
+) -> TCResult<Map<Scalar>> {
+    let aggregation: Aggregation =
+        params.try_cast_into(|v| error::bad_request("Invalid aggregation", v))?;
+
+    aggregation.evaluate(table, txn.id()).await
+}
+
+// encode the parameters of a scattered slice
+fn slice_params(bounds: Map<Scalar>, limit: Option<u64>) -> Map<Scalar> {
+    let mut params = Map::default();
+    params.insert(BOUNDS.into(), Scalar::Map(bounds));
+    if let Some(limit) = limit {
+        params.insert(LIMIT.into(), Scalar::Value(limit.into()));
+    }
+
+    params
+}
+
+// decode the parameters of a scattered slice, as encoded by `slice_params`
+fn parse_slice_params(mut params: Map<Scalar>) -> TCResult<(Bounds, Option<u64>)> {
+    let bounds = match params.remove(&BOUNDS.into()) {
+        Some(bounds) => {
+            let bounds: Map<Scalar> =
+                bounds.try_cast_into(|s| error::bad_request("Invalid Table bounds", s))?;
+
+            Bounds::try_cast_from(bounds, |v| {
+                error::bad_request("Cannot cast into Table Bounds from", v)
+            })?
+        }
+        None => Bounds::default(),
+    };
+
+    let limit = match params.remove(&LIMIT.into()) {
+        Some(limit) => {
+            let limit = Value::try_cast_from(limit, |s| error::bad_request("Invalid limit", s))?;
+            Some(limit.try_cast_into(|v| error::bad_request("Invalid limit", v))?)
+        }
+        None => None,
+    };
+
+    if let Some(name) = params.keys().next() {
+        return Err(error::bad_request("Unrecognized parameter", name));
+    }
+
+    Ok((bounds, limit))
+}
+
+async fn slice_local(
+    txn: &Txn,
+    table: &TableIndex,
+    bounds: Bounds,
+    limit: Option<u64>,
+) -> TCResult<Vec<Vec<Value>>> {
+    if bounds.is_empty() {
+        let rows = table.stream(txn.id()).await?;
+        collect(rows, limit).await
+    } else {
+        let key = table.key().iter().map(|c| c.name()).cloned().collect();
+        let slice = table.clone().slice(bounds)?.order_by(key, false)?;
+        let rows = slice.stream(txn.id()).await?;
+        collect(rows, limit).await
+    }
+}
+
+async fn collect<'a>(
+    rows: TCTryStream<'a, Vec<Value>>,
+    limit: Option<u64>,
+) -> TCResult<Vec<Vec<Value>>> {
+    match limit {
+        Some(limit) => rows.take(limit as usize).try_collect().await,
+        None => rows.try_collect().await,
+    }
+}
+
+// merge the given lists of rows, each sorted by primary key, into a single sorted list
+fn merge(
+    collator: &Collator,
+    key_len: usize,
+    sources: Vec<Vec<Vec<Value>>>,
+    limit: Option<u64>,
+) -> Vec<Vec<Value>> {
+    let limit = limit.map(|limit| limit as usize).unwrap_or(usize::MAX);
+    let mut sources: Vec<Peekable<IntoIter<Vec<Value>>>> = sources
+        .into_iter()
+        .map(|rows| rows.into_iter().peekable())
+        .collect();
+
+    let mut merged = vec![];
+    while merged.len() < limit {
+        let mut next: Option<(usize, &Vec<Value>)> = None;
+        for (i, source) in sources.iter_mut().enumerate() {
+            if let Some(row) = source.peek() {
+                next = match next {
+                    Some((_, least))
+                        if collator.compare(&row[..key_len], &least[..key_len])
+                            != Ordering::Less =>
+                    {
+                        next
+                    }
+                    _ => Some((i, row)),
+                };
+            }
+        }
+
+        match next.map(|(i, _)| i) {
+            Some(i) => merged.push(sources[i].next().unwrap()),
+            None => break,
+        }
+    }
+
+    merged
+}
+
+async fn scatter(
+    request: &Request,
+    txn: &Txn,
+    peer: &Link,
+    op: Label,
+    params: Map<Scalar>,
+) -> TCResult<Scalar> {
+    let link = shard::append(shard::append(peer.clone(), SHARDS), op);
+    debug!("scatter a table op to {}", link);
+
+    let data = params
+        .into_inner()
+        .into_iter()
+        .map(|(name, param)| Scalar::Tuple(vec![Scalar::Value(Value::from(name)), param].into()))
+        .collect();
+
+    let state = txn
+        .gateway()
+        .post(request, txn, link, Scalar::Tuple(data))
+        .await?;
+    Scalar::try_cast_from(state, |s| {
+        error::bad_request("Invalid response from a table shard", s)
+    })
+}
+
+pub struct AggregateHandler<'a> {
+    table: &'a TableIndex,
+}
+
+impl<'a> AggregateHandler<'a> {
+    pub fn new(table: &'a TableIndex) -> Self {
+        Self { table }
+    }
+}
+
+#[async_trait]
+impl<'a> Handler for AggregateHandler<'a> {
+    fn subject(&self) -> TCType {
+        self.table.class().into()
+    }
+
+    fn scope(&self) -> Option<Scope> {
+        Some(SCOPE_READ.into())
+    }
+
+    async fn handle_post(
+        self: Box<Self>,
+        _request: &Request,
+        txn: &Txn,
+        params: Map<Scalar>,
+    ) -> TCResult<State> {
+        // a peer only evaluates its own rows--the coordinator merges the partial results
+        let result = aggregate_local(txn, self.table, params).await?;
+        Ok(State::Scalar(Scalar::Map(result)))
+    }
+}
+
+pub struct SliceHandler<'a> {
+    table: &'a TableIndex,
+}
+
+impl<'a> SliceHandler<'a> {
+    pub fn new(table: &'a TableIndex) -> Self {
+        Self { table }
+    }
+}
+
+#[async_trait]
+impl<'a> Handler for SliceHandler<'a> {
+    fn subject(&self) -> TCType {
+        self.table.class().into()
+    }
+
+    fn scope(&self) -> Option<Scope> {
+        Some(SCOPE_READ.into())
+    }
+
+    async fn handle_post(
+        self: Box<Self>,
+        _request: &Request,
+        txn: &Txn,
+        params: Map<Scalar>,
+    ) -> TCResult<State> {
+        // a peer only slices its own rows--the coordinator merges the results in key order
+        let (bounds, limit) = parse_slice_params(params)?;
+        let rows = slice_local(txn, self.table, bounds, limit).await?;
+        let rows = rows.into_iter().map(Value::from_iter);
+        Ok(State::from(Value::from_iter(rows)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scalar::{Id, NumberType, UIntType, ValueType};
+
+    fn id(name: &str) -> Id {
+        name.parse().unwrap()
+    }
+
+    fn rows(keys: &[u64]) -> Vec<Vec<Value>> {
+        keys.iter()
+            .map(|key| vec![Value::from(*key), Value::from(key * 10)])
+            .collect()
+    }
+
+    #[test]
+    fn test_merge() {
+        let u64_type = ValueType::Number(NumberType::UInt(UIntType::U64));
+        let collator = Collator::new(vec![u64_type]).unwrap();
+
+        let sources = vec![rows(&[1, 4, 7]), rows(&[]), rows(&[2, 3, 8, 9]), rows(&[5])];
+        let merged = merge(&collator, 1, sources.to_vec(), None);
+        assert_eq!(merged, rows(&[1, 2, 3, 4, 5, 7, 8, 9]));
+
+        let merged = merge(&collator, 1, sources.to_vec(), Some(4));
+        assert_eq!(merged, rows(&[1, 2, 3, 4]));
+
+        assert!(merge(&collator, 1, sources, Some(0)).is_empty());
+        assert!(merge(&collator, 1, vec![], None).is_empty());
+    }
+
+    #[test]
+    fn test_slice_params() {
+        let mut bounds = Map::default();
+        bounds.insert(id("key"), Scalar::Value(Value::from(3u64)));
+
+        let (parsed, limit) = parse_slice_params(slice_params(bounds, Some(10))).unwrap();
+        assert_eq!(parsed.len(), 1);
+        assert!(parsed.contains_key(&id("key")));
+        assert_eq!(limit, Some(10));
+
+        let (parsed, limit) = parse_slice_params(slice_params(Map::default(), None)).unwrap();
+        assert!(parsed.is_empty());
+        assert_eq!(limit, None);
+
+        let (parsed, limit) = parse_slice_params(Map::default()).unwrap();
+        assert!(parsed.is_empty());
+        assert_eq!(limit, None);
+    }
+
+    #[test]
+    fn test_slice_params_invalid() {
+        let mut params = slice_params(Map::default(), None);
+        params.insert(id("order"), Scalar::Value(Value::from(id("key"))));
+        assert!(parse_slice_params(params).is_err());
+
+        let mut params = Map::default();
+        params.insert(LIMIT.into(), Scalar::Value(Value::from(id("ten"))));
+        assert!(parse_slice_params(params).is_err());
+
+        let mut params = Map::default();
+        params.insert(BOUNDS.into(), Scalar::Value(Value::from(1u64)));
+        assert!(parse_slice_params(params).is_err());
+    }
+}
@@ -0,0 +1,513 @@
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::iter::FromIterator;
+
+use async_trait::async_trait;
+use futures::future;
+use futures::stream::TryStreamExt;
+use log::debug;
+
+use crate::auth::{Scope, SCOPE_READ, SCOPE_WRITE};
+use crate::class::{Instance, State, TCType};
+use crate::collection::btree::Collator;
+use crate::collection::schema::Column;
+use crate::error;
+use crate::handler::*;
+use crate::request::Request;
+use crate::scalar::{label, Bound, Id, Label, Link, MethodType, PathSegment, Value};
+use crate::transaction::Txn;
+use crate::{TCResult, TryCastFrom, TryCastInto};
+
+use super::scatter;
+use super::{Bounds, ColumnBound, TableImpl, TableIndex, TableInstance};
+
+const INSERT: Label = label("insert");
+pub(super) const SHARDS: Label = label("shards");
+
+/// The ranges of a [`TableIndex`]'s primary key which are stored by peer hosts.
+///
+/// Each range begins at its start key, inclusive, and ends at the start key of the next range,
+/// exclusive. A start key may be a prefix of the primary key. Any key which comes before the
+/// first range is stored by this host.
+#[derive(Clone, Default)]
+pub struct Shards {
+    ranges: Vec<(Vec<Value>, Option<Link>)>,
+}
+
+impl Shards {
+    /// Return `true` if every range is stored by this host.
+    pub fn is_local(&self) -> bool {
+        self.ranges.iter().all(|(_, peer)| peer.is_none())
+    }
+
+    /// Return the peer which stores the given key, or `None` if it's stored by this host.
+    pub fn owner(&self, collator: &Collator, key: &[Value]) -> Option<&Link> {
+        self.ranges
+            .iter()
+            .rev()
+            .find(|(start, _)| collator.compare(start, key) != Ordering::Greater)
+            .and_then(|(_, peer)| peer.as_ref())
+    }
+
+    /// Return the start key of the range after the one which contains `key`, if any.
+    pub fn end(&self, collator: &Collator, key: &[Value]) -> Option<&[Value]> {
+        self.ranges
+            .iter()
+            .find(|(start, _)| collator.compare(start, key) == Ordering::Greater)
+            .map(|(start, _)| &start[..])
+    }
+
+    /// Assign the range which begins at `start` to `peer`, splitting the range which currently
+    /// contains `start` if necessary.
+    pub fn assign(&mut self, collator: &Collator, start: Vec<Value>, peer: Option<Link>) {
+        match self
+            .ranges
+            .binary_search_by(|(probe, _)| collator.compare(probe, &start))
+        {
+            Ok(i) => self.ranges[i].1 = peer,
+            Err(i) => self.ranges.insert(i, (start, peer)),
+        }
+
+        // merge adjacent ranges which are stored by the same host
+        let mut owner = None;
+        self.ranges.retain(|(_, peer)| {
+            if peer == &owner {
+                false
+            } else {
+                owner = peer.clone();
+                true
+            }
+        });
+    }
+
+    /// Return the distinct peers which store any range.
+    pub fn peers(&self) -> Vec<Link> {
+        let mut peers: Vec<Link> = vec![];
+        for (_, peer) in &self.ranges {
+            if let Some(peer) = peer {
+                if !peers.contains(peer) {
+                    peers.push(peer.clone());
+                }
+            }
+        }
+
+        peers
+    }
+
+    /// Iterate over the ranges in order, as pairs of (start key, owner).
+    pub fn iter(&self) -> impl Iterator<Item = &(Vec<Value>, Option<Link>)> {
+        self.ranges.iter()
+    }
+}
+
+/// Move the range of `table`'s primary key which begins at `start` to `peer`, or mark it as
+/// stored by this host if `peer` is `None`. The rows in the range which are stored here are
+/// copied to the table at `peer`, then deleted.
+///
+/// A range stored by another host can only be rebalanced by that host.
+pub async fn rebalance(
+    request: &Request,
+    txn: &Txn,
+    table: &TableIndex,
+    start: Vec<Value>,
+    peer: Option<Link>,
+) -> TCResult<()> {
+    if start.is_empty() || start.len() > table.key().len() {
+        return Err(error::bad_request(
+            "Invalid start key for a shard",
+            Value::from_iter(start),
+        ));
+    }
+
+    let collator = table.primary().btree().collator();
+    let shards = table.shards(txn.id()).await?;
+
+    let current = shards.owner(collator, &start).cloned();
+    if current == peer {
+        return table.assign_shard(txn.id(), start, peer).await;
+    } else if let Some(current) = current {
+        return Err(error::bad_request(
+            "This range is stored by another host, so it can only be moved by",
+            current,
+        ));
+    }
+
+    let peer = peer.unwrap();
+    let end = shards.end(collator, &start).map(|end| end.to_vec());
+
+    let key_len = table.key().len();
+    let bounds = range_bounds(table.key(), &start, end.as_deref());
+    let moved = {
+        let mut moved = vec![];
+        let mut rows = table
+            .stream_slice(txn.id(), bounds, false)
+            .await?
+            .try_skip_while(|row| {
+                let before = collator.compare(&row[..key_len], &start) == Ordering::Less;
+                future::ready(Ok(before))
+            })
+            .try_take_while(|row| {
+                let within = match &end {
+                    Some(end) => collator.compare(&row[..key_len], end) == Ordering::Less,
+                    None => true,
+                };
+
+                future::ready(Ok(within))
+            });
+
+        while let Some(mut row) = rows.try_next().await? {
+            let values = row.split_off(key_len);
+            let key = Value::from_iter(row.to_vec());
+            let value = Value::from_iter(values.to_vec());
+            txn.gateway()
+                .put(request, txn, &peer, key, value.into())
+                .await?;
+
+            row.extend(values);
+            moved.push(row);
+        }
+
+        moved
+    };
+
+    debug!("moved {} table rows to {}", moved.len(), peer);
+    for row in moved {
+        let row = table.primary().schema().row_from_values(row)?;
+        table.delete_row(txn.id(), row).await?;
+    }
+
+    table.assign_shard(txn.id(), start, Some(peer)).await
+}
+
+// the narrowest `Bounds` which contain every key in the range [start, end): the columns on which
+// `start` and `end` agree, followed by a range of the next column
+fn range_bounds(key: &[Column], start: &[Value], end: Option<&[Value]>) -> Bounds {
+    let end = end.unwrap_or(&[]);
+    let prefix = start.iter().zip(end).take_while(|(s, e)| s == e).count();
+
+    let mut bounds: HashMap<Id, ColumnBound> = key
+        .iter()
+        .zip(&start[..prefix])
+        .map(|(column, value)| (column.name().clone(), ColumnBound::Is(value.clone())))
+        .collect();
+
+    if let Some(column) = key.get(prefix) {
+        let lower = match start.get(prefix) {
+            Some(value) => Bound::In(value.clone()),
+            None => Bound::Unbounded,
+        };
+
+        let upper = match end.get(prefix) {
+            Some(value) if end.len() == prefix + 1 => Bound::Ex(value.clone()),
+            Some(value) => Bound::In(value.clone()),
+            None => Bound::Unbounded,
+        };
+
+        if lower != Bound::Unbounded || upper != Bound::Unbounded {
+            bounds.insert(column.name().clone(), (lower, upper).into());
+        }
+    }
+
+    bounds.into()
+}
+
+/// Route a request to a [`TableIndex`], forwarding reads and writes of a single row to the peer
+/// which stores it, if that's not this host.
+pub fn route<'a>(
+    table: &'a TableImpl<TableIndex>,
+    method: MethodType,
+    path: &[PathSegment],
+) -> Option<Box<dyn Handler + 'a>> {
+    let local = table.route(method, path);
+    let table: &'a TableIndex = table;
+
+    if path.len() == 1 {
+        match path[0].as_str() {
+            "rebalance" => return Some(Box::new(RebalanceHandler { table })),
+            "shards" => return Some(Box::new(ShardsHandler { table })),
+            _ => {}
+        }
+    } else if path.len() == 2 && path[0] == SHARDS {
+        return match path[1].as_str() {
+            "aggregate" => Some(Box::new(scatter::AggregateHandler::new(table))),
+            "where" => Some(Box::new(scatter::SliceHandler::new(table))),
+            _ => None,
+        };
+    }
+
+    let local = local?;
+    let suffix = if path.is_empty() {
+        None
+    } else if path.len() == 1 && path[0] == INSERT {
+        Some(INSERT)
+    } else {
+        return Some(local);
+    };
+
+    match method {
+        MethodType::Get if path.is_empty() => Some(Box::new(ShardedHandler {
+            table,
+            local,
+            suffix,
+        })),
+        MethodType::Put => Some(Box::new(ShardedHandler {
+            table,
+            local,
+            suffix,
+        })),
+        _ => Some(local),
+    }
+}
+
+struct ShardedHandler<'a> {
+    table: &'a TableIndex,
+    local: Box<dyn Handler + 'a>,
+    suffix: Option<Label>,
+}
+
+impl<'a> ShardedHandler<'a> {
+    async fn peer(&self, txn: &Txn, key: &[Value]) -> TCResult<Option<Link>> {
+        if key.len() != self.table.key().len() {
+            return Ok(None);
+        }
+
+        let peer = self.table.owner(txn.id(), key).await?;
+        Ok(peer.map(|peer| match &self.suffix {
+            Some(suffix) => append(peer, suffix.clone()),
+            None => peer,
+        }))
+    }
+}
+
+#[async_trait]
+impl<'a> Handler for ShardedHandler<'a> {
+    fn subject(&self) -> TCType {
+        self.local.subject()
+    }
+
+    fn scope(&self) -> Option<Scope> {
+        self.local.scope()
+    }
+
+    async fn get(
+        self: Box<Self>,
+        request: &Request,
+        txn: &Txn,
+        selector: Value,
+    ) -> TCResult<State> {
+        let key: Option<Vec<Value>> = selector.clone().opt_cast_into();
+        if let Some(key) = key {
+            if let Some(peer) = self.peer(txn, &key).await? {
+                debug!("forward GET {} to {}", selector, peer);
+                self.authorize(request)?;
+                return txn.gateway().get(request, txn, &peer, selector).await;
+            }
+        }
+
+        self.local.get(request, txn, selector).await
+    }
+
+    async fn handle_put(
+        self: Box<Self>,
+        request: &Request,
+        txn: &Txn,
+        selector: Value,
+        value: State,
+    ) -> TCResult<()> {
+        let key = match &selector {
+            Value::Tuple(key) => key.to_vec(),
+            other => vec![other.clone()],
+        };
+
+        if let Some(peer) = self.peer(txn, &key).await? {
+            debug!("forward PUT {} to {}", selector, peer);
+            txn.gateway()
+                .put(request, txn, &peer, selector, value)
+                .await
+        } else {
+            self.local.handle_put(request, txn, selector, value).await
+        }
+    }
+}
+
+struct RebalanceHandler<'a> {
+    table: &'a TableIndex,
+}
+
+#[async_trait]
+impl<'a> Handler for RebalanceHandler<'a> {
+    fn subject(&self) -> TCType {
+        self.table.class().into()
+    }
+
+    fn scope(&self) -> Option<Scope> {
+        Some(SCOPE_WRITE.into())
+    }
+
+    async fn handle_put(
+        self: Box<Self>,
+        request: &Request,
+        txn: &Txn,
+        selector: Value,
+        value: State,
+    ) -> TCResult<()> {
+        let start = match selector {
+            Value::Tuple(start) => start.into_inner(),
+            other => vec![other],
+        };
+
+        let peer = Value::try_cast_from(value, |v| error::bad_request("Invalid peer", v))?;
+        let peer: Option<Link> = if peer.is_none() {
+            None
+        } else {
+            Some(peer.try_cast_into(|v| error::bad_request("Invalid peer", v))?)
+        };
+
+        rebalance(request, txn, self.table, start, peer).await
+    }
+}
+
+struct ShardsHandler<'a> {
+    table: &'a TableIndex,
+}
+
+#[async_trait]
+impl<'a> Handler for ShardsHandler<'a> {
+    fn subject(&self) -> TCType {
+        self.table.class().into()
+    }
+
+    fn scope(&self) -> Option<Scope> {
+        Some(SCOPE_READ.into())
+    }
+
+    async fn handle_get(self: Box<Self>, txn: &Txn, selector: Value) -> TCResult<State> {
+        if selector.is_none() {
+            let shards = self.table.shards(txn.id()).await?;
+            let shards = shards.iter().cloned().map(|(start, peer)| {
+                let peer = peer.map(Value::from).unwrap_or(Value::None);
+                Value::Tuple(vec![Value::from_iter(start), peer].into())
+            });
+
+            Ok(State::from(Value::from_iter(shards)))
+        } else {
+            let key: Vec<Value> =
+                selector.try_cast_into(|v| error::bad_request("Invalid key for Table", v))?;
+
+            let peer = self.table.owner(txn.id(), &key).await?;
+            Ok(State::from(peer.map(Value::from).unwrap_or(Value::None)))
+        }
+    }
+}
+
+pub(super) fn append(link: Link, suffix: Label) -> Link {
+    match link.host().clone() {
+        Some(host) => (host, link.into_path().append(suffix)).into(),
+        None => Link::from(link.into_path().append(suffix)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scalar::{NumberType, Range, UIntType, ValueType};
+
+    fn u64_type() -> ValueType {
+        ValueType::Number(NumberType::UInt(UIntType::U64))
+    }
+
+    fn key(key: &[u64]) -> Vec<Value> {
+        key.iter().cloned().map(Value::from).collect()
+    }
+
+    fn peer(host: &str) -> Option<Link> {
+        Some(format!("http://{}/app/table", host).parse().unwrap())
+    }
+
+    #[test]
+    fn test_owner() {
+        let collator = Collator::new(vec![u64_type(), u64_type()]).unwrap();
+        let mut shards = Shards::default();
+        assert!(shards.is_local());
+        assert_eq!(shards.owner(&collator, &key(&[5, 5])), None);
+
+        shards.assign(&collator, key(&[10]), peer("127.0.0.1:8702"));
+        shards.assign(&collator, key(&[20, 5]), None);
+        assert!(!shards.is_local());
+
+        assert_eq!(shards.owner(&collator, &key(&[9, 99])), None);
+        assert_eq!(
+            shards.owner(&collator, &key(&[10, 0])),
+            peer("127.0.0.1:8702").as_ref()
+        );
+        assert_eq!(
+            shards.owner(&collator, &key(&[20, 4])),
+            peer("127.0.0.1:8702").as_ref()
+        );
+        assert_eq!(shards.owner(&collator, &key(&[20, 5])), None);
+        assert_eq!(shards.owner(&collator, &key(&[30, 0])), None);
+
+        assert_eq!(shards.end(&collator, &key(&[0, 0])), Some(&key(&[10])[..]));
+        assert_eq!(
+            shards.end(&collator, &key(&[15, 0])),
+            Some(&key(&[20, 5])[..])
+        );
+        assert_eq!(shards.end(&collator, &key(&[25, 0])), None);
+    }
+
+    #[test]
+    fn test_assign() {
+        let collator = Collator::new(vec![u64_type()]).unwrap();
+        let mut shards = Shards::default();
+
+        shards.assign(&collator, key(&[20]), peer("127.0.0.1:8703"));
+        shards.assign(&collator, key(&[10]), peer("127.0.0.1:8702"));
+        shards.assign(&collator, key(&[30]), None);
+
+        let starts: Vec<Vec<Value>> = shards.iter().map(|(start, _)| start.to_vec()).collect();
+        assert_eq!(starts, vec![key(&[10]), key(&[20]), key(&[30])]);
+        assert_eq!(shards.peers().len(), 2);
+
+        // adjacent ranges stored by the same host are merged
+        shards.assign(&collator, key(&[20]), peer("127.0.0.1:8702"));
+        let starts: Vec<Vec<Value>> = shards.iter().map(|(start, _)| start.to_vec()).collect();
+        assert_eq!(starts, vec![key(&[10]), key(&[30])]);
+        assert_eq!(shards.peers(), vec![peer("127.0.0.1:8702").unwrap()]);
+
+        shards.assign(&collator, key(&[10]), None);
+        assert!(shards.is_local());
+    }
+
+    fn range(bounds: &Bounds, name: &str) -> (Bound, Bound) {
+        match bounds.get(&name.parse::<Id>().unwrap()) {
+            Some(ColumnBound::In(Range { start, end })) => (start.clone(), end.clone()),
+            _ => panic!("expected a range of column {}", name),
+        }
+    }
+
+    #[test]
+    fn test_range_bounds() {
+        let columns: Vec<Column> = ["a", "b", "c"]
+            .iter()
+            .map(|name| (name.parse().unwrap(), u64_type()).into())
+            .collect();
+
+        let bounds = range_bounds(&columns, &key(&[1]), Some(&key(&[3])));
+        assert_eq!(bounds.len(), 1);
+        assert!(range(&bounds, "a") == (Bound::In(1u64.into()), Bound::Ex(3u64.into())));
+
+        let bounds = range_bounds(&columns, &key(&[1, 2]), Some(&key(&[1, 7, 3])));
+        assert_eq!(bounds.len(), 2);
+        assert!(matches!(
+            bounds.get(&"a".parse::<Id>().unwrap()),
+            Some(ColumnBound::Is(_))
+        ));
+        assert!(range(&bounds, "b") == (Bound::In(2u64.into()), Bound::In(7u64.into())));
+
+        let bounds = range_bounds(&columns, &key(&[1]), Some(&key(&[1, 5])));
+        assert!(range(&bounds, "b") == (Bound::Unbounded, Bound::Ex(5u64.into())));
+
+        let bounds = range_bounds(&columns, &key(&[4]), None);
+        assert!(range(&bounds, "a") == (Bound::In(4u64.into()), Bound::Unbounded));
+    }
+}
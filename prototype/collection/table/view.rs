@@ -19,6 +19,7 @@ use crate::{TCResult, TCTryStream};
 
 use super::bounds::Bounds;
 use super::index::TableIndex;
+use super::overflow::{self, Overflow};
 use super::{Table, TableInstance, TableType};
 
 const ERR_AGGREGATE_SLICE: &str = "Table aggregate does not support slicing. \
@@ -109,23 +110,35 @@ pub fn group_by<T: TableInstance>(
 pub struct IndexSlice {
     source: BTreeFile,
     schema: IndexSchema,
+    overflow: Option<Overflow>,
     bounds: Bounds,
     range: BTreeRange,
     reverse: bool,
 }
 
 impl IndexSlice {
-    pub fn all(source: BTreeFile, schema: IndexSchema, reverse: bool) -> IndexSlice {
+    pub fn all(
+        source: BTreeFile,
+        schema: IndexSchema,
+        overflow: Option<Overflow>,
+        reverse: bool,
+    ) -> IndexSlice {
         IndexSlice {
             source,
             schema,
+            overflow,
             bounds: Bounds::default(),
             range: BTreeRange::default(),
             reverse,
         }
     }
 
-    pub fn new(source: BTreeFile, schema: IndexSchema, bounds: Bounds) -> TCResult<IndexSlice> {
+    pub fn new(
+        source: BTreeFile,
+        schema: IndexSchema,
+        overflow: Option<Overflow>,
+        bounds: Bounds,
+    ) -> TCResult<IndexSlice> {
         debug!("IndexSlice::new with bounds {}", bounds);
         let columns = schema.columns();
 
@@ -137,6 +150,7 @@ impl IndexSlice {
         Ok(IndexSlice {
             source,
             schema,
+            overflow,
             bounds,
             range,
             reverse: false,
@@ -186,7 +200,8 @@ impl IndexSlice {
     ) -> TCResult<TCTryStream<'a, Vec<Value>>> {
         let reverse = self.reverse ^ reverse;
         let bounds = bounds.into_btree_range(&self.schema.columns())?;
-        self.source.stream(txn_id, bounds, reverse).await
+        let rows = self.source.stream(txn_id, bounds, reverse).await?;
+        Ok(overflow::resolve(&self.overflow, txn_id, rows))
     }
 }
 
@@ -241,9 +256,12 @@ impl TableInstance for IndexSlice {
     async fn stream<'a>(&'a self, txn_id: &'a TxnId) -> TCResult<TCTryStream<'a, Vec<Value>>> {
         debug!("IndexSlice::stream where {}", self.range);
 
-        self.source
+        let rows = self
+            .source
             .stream(txn_id, self.range.clone(), self.reverse)
-            .await
+            .await?;
+
+        Ok(overflow::resolve(&self.overflow, txn_id, rows))
     }
 
     fn validate_bounds(&self, bounds: &Bounds) -> TCResult<()> {
@@ -274,6 +292,11 @@ impl TableInstance for IndexSlice {
 
     async fn update(&self, txn: &Txn, value: Row) -> TCResult<()> {
         let key = self.schema.values_from_row(value, true)?;
+        let key = match &self.overflow {
+            Some(overflow) => overflow.write(txn.id(), key).await?,
+            None => key,
+        };
+
         self.source.update(txn.id(), self.range.clone(), &key).await
     }
 }
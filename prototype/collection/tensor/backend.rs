@@ -0,0 +1,68 @@
+use std::panic;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use arrayfire as af;
+use log::warn;
+
+use crate::error::{self, ErrorType};
+use crate::TCResult;
+
+static AVAILABLE: AtomicBool = AtomicBool::new(false);
+
+/// Initialize ArrayFire and print information about the compute device it's using.
+///
+/// If ArrayFire fails to initialize (for example because no compute device or driver is
+/// available) the host keeps running, but every tensor request fails with a "tensor backend
+/// unavailable" error. Tables, BTrees, and scalar ops are unaffected.
+pub fn init() -> bool {
+    let available = panic::catch_unwind(af::info).is_ok();
+    if !available {
+        warn!("ArrayFire failed to initialize, tensor ops are disabled on this host");
+    }
+
+    AVAILABLE.store(available, Ordering::Relaxed);
+    available
+}
+
+/// Return an error if the tensor backend is not available on this host.
+pub fn check() -> TCResult<()> {
+    if AVAILABLE.load(Ordering::Relaxed) {
+        Ok(())
+    } else {
+        // not ResourceExhausted, since retrying the request on this host will never help
+        Err(error::TCError::of(
+            ErrorType::NotImplemented,
+            "The tensor backend is unavailable on this host because ArrayFire failed to initialize"
+                .to_string(),
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::collection::btree::Collator;
+    use crate::handler::Route;
+    use crate::scalar::{label, MethodType, Number, NumberType, UIntType, Value, ValueType};
+
+    #[test]
+    fn test_unavailable() {
+        AVAILABLE.store(false, Ordering::Relaxed);
+
+        let cause = check().unwrap_err();
+        assert!(cause.reason() == &ErrorType::NotImplemented);
+
+        // scalar ops and the table collator don't depend on the tensor backend
+        let value = Value::from(1u64);
+        assert!(value
+            .route(MethodType::Get, &[label("eq").into()])
+            .is_some());
+        assert_eq!(Number::from(1u64) + Number::from(2u64), Number::from(3u64));
+
+        let u64_type = ValueType::Number(NumberType::UInt(UIntType::U64));
+        let collator = Collator::new(vec![u64_type]).unwrap();
+        let keys = vec![vec![Value::from(1u64)], vec![Value::from(2u64)]];
+        assert!(collator.is_sorted(&keys));
+        assert!(check().is_err());
+    }
+}
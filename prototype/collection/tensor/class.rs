@@ -65,6 +65,43 @@ impl TensorType {
         }
     }
 
+    /// Construct a new tensor with the named constructor, like `/sbin/collection/tensor/dense/eye`
+    /// or `/sbin/collection/tensor/sparse/from_entries`.
+    pub async fn construct(&self, txn: &Txn, name: &Id, key: Value) -> TCResult<Tensor> {
+        super::backend::check()?;
+
+        match (self, name.as_str()) {
+            (Self::Dense, "eye") => {
+                let (n, dtype) = if key.matches::<(u64, NumberType)>() {
+                    key.opt_cast_into().unwrap()
+                } else {
+                    let n = key.try_cast_into(|v| {
+                        error::bad_request("eye expects (size, NumberType), not", v)
+                    })?;
+
+                    (n, FloatType::F64.into())
+                };
+
+                super::eye(txn, n, dtype).await
+            }
+            (Self::Sparse, "from_entries") => {
+                let (shape, dtype, entries): (Shape, NumberType, Vec<(Coord, Number)>) = key
+                    .try_cast_into(|v| {
+                        error::bad_request(
+                            "from_entries expects (shape, NumberType, ((coord, value)...)), not",
+                            v,
+                        )
+                    })?;
+
+                let entries = stream::iter(entries.into_iter().map(Ok));
+                SparseTensor::from_entries(txn, shape, dtype, entries)
+                    .map_ok(Tensor::from)
+                    .await
+            }
+            _ => Err(error::not_found(format!("{} constructor {}", self, name))),
+        }
+    }
+
     async fn zeros(&self, txn: &Txn, dtype: NumberType, shape: Shape) -> TCResult<Tensor> {
         match self {
             Self::Dense => {
@@ -106,6 +143,8 @@ impl CollectionClass for TensorType {
     type Instance = Tensor;
 
     async fn get(&self, txn: &Txn, schema: Value) -> TCResult<Tensor> {
+        super::backend::check()?;
+
         if schema.matches::<(NumberType, Shape)>() {
             let (dtype, shape) = schema.opt_cast_into().unwrap();
             self.zeros(txn, dtype, shape).await
@@ -590,15 +629,15 @@ impl TensorReduce for Tensor {
 
     fn sum(&self, axis: usize) -> TCResult<Self::Reduce> {
         match self {
-            Self::Dense(dense) => dense.product(axis).map(Self::from),
-            Self::Sparse(sparse) => sparse.product(axis).map(Self::from),
+            Self::Dense(dense) => dense.sum(axis).map(Self::from),
+            Self::Sparse(sparse) => sparse.sum(axis).map(Self::from),
         }
     }
 
     fn sum_all(&self, txn: Txn) -> TCBoxTryFuture<Number> {
         match self {
-            Self::Dense(dense) => dense.product_all(txn),
-            Self::Sparse(sparse) => sparse.product_all(txn),
+            Self::Dense(dense) => dense.sum_all(txn),
+            Self::Sparse(sparse) => sparse.sum_all(txn),
         }
     }
 }
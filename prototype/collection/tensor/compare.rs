@@ -0,0 +1,167 @@
+use futures::stream::{Stream, StreamExt, TryStreamExt};
+use futures::try_join;
+
+use crate::error;
+use crate::scalar::number::Number;
+use crate::transaction::Txn;
+use crate::TCResult;
+
+use super::dense::{DenseAccess, DenseAccessor};
+use super::nan::NanPolicy;
+use super::{broadcast, Array, Tensor, TensorAccess, TensorInstance};
+
+/// The default relative tolerance of [`allclose`].
+pub const DEFAULT_RTOL: f64 = 1e-05;
+
+/// The default absolute tolerance of [`allclose`].
+pub const DEFAULT_ATOL: f64 = 1e-08;
+
+/// Return `true` if `left` and `right` have the same shape and every pair of their elements is
+/// equal, treating `NaN` according to `policy`. Stops reading at the first block which is not
+/// equal.
+pub async fn equals(txn: &Txn, left: Tensor, right: Tensor, policy: NanPolicy) -> TCResult<Number> {
+    if left.shape() != right.shape() {
+        return Ok(false.into());
+    }
+
+    all_blocks(txn, left, right, policy, |l, r| l.eq(r).all())
+        .await
+        .map(Number::from)
+}
+
+/// Return `true` if every element of `left` is within `atol + rtol * |right|` of the
+/// corresponding element of `right`, after broadcasting both to a common shape, like
+/// `numpy.allclose`, treating `NaN` according to `policy`. Stops reading at the first block
+/// which is not close.
+pub async fn allclose(
+    txn: &Txn,
+    left: Tensor,
+    right: Tensor,
+    rtol: f64,
+    atol: f64,
+    policy: NanPolicy,
+) -> TCResult<Number> {
+    if rtol < 0f64 || atol < 0f64 {
+        return Err(error::bad_request(
+            "allclose tolerance must be non-negative, not",
+            format!("rtol={}, atol={}", rtol, atol),
+        ));
+    }
+
+    let (left, right) = broadcast(&left, &right)?;
+    all_blocks(txn, left, right, policy, move |l, r| {
+        l.allclose(r, rtol, atol)
+    })
+    .await
+    .map(Number::from)
+}
+
+async fn all_blocks<F: Fn(&Array, &Array) -> bool>(
+    txn: &Txn,
+    left: Tensor,
+    right: Tensor,
+    policy: NanPolicy,
+    test: F,
+) -> TCResult<bool> {
+    let left = into_blocks(left)?;
+    let right = into_blocks(right)?;
+
+    let (left, right) = try_join!(left.block_stream(txn), right.block_stream(txn))?;
+    let blocks = left.zip(right).map(|(l, r)| Ok((l?, r?)));
+    all_pairs(blocks, policy, test).await
+}
+
+// return `false` as soon as a pair of blocks fails `test`, without reading any further blocks
+async fn all_pairs<S, F>(mut blocks: S, policy: NanPolicy, test: F) -> TCResult<bool>
+where
+    S: Stream<Item = TCResult<(Array, Array)>> + Unpin,
+    F: Fn(&Array, &Array) -> bool,
+{
+    while let Some((l, r)) = blocks.try_next().await? {
+        let (l, r) = policy.pair(l, r)?;
+        if !test(&l, &r) {
+            return Ok(false);
+        }
+    }
+
+    Ok(true)
+}
+
+fn into_blocks(tensor: Tensor) -> TCResult<DenseAccessor> {
+    match tensor.into_dense() {
+        Tensor::Dense(dense) => Ok(dense.into_inner()),
+        Tensor::Sparse(_) => Err(error::internal("Expected a dense tensor")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::stream;
+
+    use crate::error::ErrorType;
+
+    use super::*;
+
+    fn pair(left: Vec<f64>, right: Vec<f64>) -> TCResult<(Array, Array)> {
+        Ok((Array::from(left), Array::from(right)))
+    }
+
+    fn eq(l: &Array, r: &Array) -> bool {
+        l.eq(r).all()
+    }
+
+    #[tokio::test]
+    async fn test_all_pairs() {
+        let blocks = stream::iter(vec![
+            pair(vec![1., 2.], vec![1., 2.]),
+            pair(vec![3.], vec![3.]),
+        ]);
+        assert!(all_pairs(blocks, NanPolicy::Propagate, eq).await.unwrap());
+
+        // the error after the first unequal block is never read
+        let blocks = stream::iter(vec![
+            pair(vec![1., 2.], vec![1., 2.]),
+            pair(vec![3.], vec![4.]),
+            Err(error::internal("read past the first unequal block")),
+        ]);
+        assert!(!all_pairs(blocks, NanPolicy::Propagate, eq).await.unwrap());
+
+        let blocks = stream::iter(vec![
+            pair(vec![1.], vec![1.]),
+            Err(error::internal("failed to read a block")),
+        ]);
+        assert!(all_pairs(blocks, NanPolicy::Propagate, eq).await.is_err());
+
+        let blocks = stream::iter(vec![]);
+        assert!(all_pairs(blocks, NanPolicy::Propagate, eq).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_all_pairs_nan() {
+        let blocks = || stream::iter(vec![pair(vec![1., f64::NAN], vec![1., f64::NAN])]);
+        assert!(!all_pairs(blocks(), NanPolicy::Propagate, eq).await.unwrap());
+        assert!(all_pairs(blocks(), NanPolicy::Ignore, eq).await.unwrap());
+
+        let cause = all_pairs(blocks(), NanPolicy::Error, eq).await.unwrap_err();
+        assert!(cause.reason() == &ErrorType::BadRequest);
+
+        // a NaN on only one side is ignored at that position in both
+        let blocks = stream::iter(vec![pair(vec![f64::NAN, 2.], vec![5., 2.])]);
+        assert!(all_pairs(blocks, NanPolicy::Ignore, eq).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_all_pairs_close() {
+        let close = |l: &Array, r: &Array| l.allclose(r, DEFAULT_RTOL, DEFAULT_ATOL);
+
+        let blocks = stream::iter(vec![pair(vec![1., 1e5], vec![1. + 1e-9, 1e5 + 0.5])]);
+        assert!(all_pairs(blocks, NanPolicy::Propagate, close)
+            .await
+            .unwrap());
+
+        let blocks = stream::iter(vec![pair(vec![1., 1e5], vec![1., 1e5 + 2.])]);
+        assert!(!all_pairs(blocks, NanPolicy::Propagate, close)
+            .await
+            .unwrap());
+    }
+}
@@ -0,0 +1,258 @@
+use std::fmt;
+
+use futures::future;
+use futures::stream::StreamExt;
+use log::debug;
+
+use crate::error;
+use crate::scalar::number::*;
+use crate::transaction::Txn;
+use crate::TCResult;
+
+use super::dense::{BlockListFile, DenseAccess};
+use super::rolling::lanes;
+use super::{Array, DenseTensor, Tensor, TensorAccess, TensorTransform};
+
+/// A cumulative op along one axis of a tensor.
+#[derive(Clone, Copy, Eq, PartialEq)]
+pub enum CumulativeOp {
+    Product,
+    Sum,
+}
+
+impl CumulativeOp {
+    fn identity(&self, dtype: NumberType) -> Number {
+        match self {
+            Self::Product => dtype.one(),
+            Self::Sum => dtype.zero(),
+        }
+    }
+
+    fn scan(&self, block: &Array, offset: usize, lane_len: usize) -> Array {
+        match self {
+            Self::Product => block.cumprod(offset, lane_len),
+            Self::Sum => block.cumsum(offset, lane_len),
+        }
+    }
+
+    fn carry(&self, block: &Array, carry: &Array) -> Array {
+        match self {
+            Self::Product => block.multiply(carry),
+            Self::Sum => block.add(carry),
+        }
+    }
+}
+
+impl fmt::Display for CumulativeOp {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Product => write!(f, "cumprod"),
+            Self::Sum => write!(f, "cumsum"),
+        }
+    }
+}
+
+/// Compute the cumulative sum or product of `tensor` along `axis`.
+///
+/// Each block of the source is scanned on the device, one lane at a time. A lane which spans
+/// more than one block is continued by combining the last value computed for it in the previous
+/// block with the scanned values of the next. The output has the same shape as `tensor` and is
+/// written to a new dense tensor owned by `txn`; a boolean tensor produces a `UInt64` output.
+pub async fn cumulative(
+    txn: &Txn,
+    tensor: Tensor,
+    op: CumulativeOp,
+    axis: usize,
+) -> TCResult<Tensor> {
+    let ndim = tensor.ndim();
+    let (source, inverse) = lanes(tensor, axis)?;
+
+    let shape = source.shape().clone();
+    let lane_len = shape[ndim - 1] as usize;
+    let dtype = match source.dtype() {
+        NumberType::Bool => NumberType::uint64(),
+        dtype => dtype,
+    };
+
+    debug!(
+        "{} along axis {} of a tensor with shape {}",
+        op, axis, shape
+    );
+
+    let source = source.into_inner();
+    let blocks = source.block_stream(txn).await?;
+
+    let scan = Scan::new(op, lane_len, dtype);
+    let blocks = blocks.scan(scan, |scan, block| {
+        future::ready(Some(block.and_then(|block| scan.push(block))))
+    });
+
+    let output = BlockListFile::from_blocks(txn, shape, dtype, Box::pin(blocks)).await?;
+    Tensor::from(DenseTensor::from(output)).transpose(Some(inverse))
+}
+
+/// The state of a cumulative op over a sequence of blocks whose lanes may span block boundaries.
+struct Scan {
+    op: CumulativeOp,
+    lane_len: usize,
+    dtype: NumberType,
+
+    // the number of elements of the current lane in previous blocks, and their last scanned value
+    offset: usize,
+    carry: Option<Number>,
+}
+
+impl Scan {
+    fn new(op: CumulativeOp, lane_len: usize, dtype: NumberType) -> Self {
+        Self {
+            op,
+            lane_len,
+            dtype,
+            offset: 0,
+            carry: None,
+        }
+    }
+
+    /// Scan the next block, continuing the last lane of the previous block if it was partial.
+    fn push(&mut self, block: Array) -> TCResult<Array> {
+        let len = block.len();
+        if len == 0 {
+            return Ok(block.into_type(self.dtype));
+        }
+
+        let mut scanned = self.op.scan(&block, self.offset, self.lane_len);
+
+        if let Some(carry) = self.carry {
+            let continued = len.min(self.lane_len - self.offset);
+            let mut values = vec![carry; continued];
+            values.extend(std::iter::repeat(self.op.identity(self.dtype)).take(len - continued));
+
+            let carry = Array::cast_from_values(values, self.dtype)?;
+            scanned = self.op.carry(&scanned, &carry);
+        }
+
+        let scanned = scanned.into_type(self.dtype);
+
+        self.offset = (self.offset + len) % self.lane_len;
+        self.carry = if self.offset == 0 {
+            None
+        } else {
+            Some(scanned.get_value(len - 1))
+        };
+
+        Ok(scanned)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // scan `values` split into blocks of the given sizes
+    fn scan(op: CumulativeOp, lane_len: usize, values: &[u64], blocks: &[usize]) -> Vec<Number> {
+        assert_eq!(blocks.iter().sum::<usize>(), values.len());
+
+        let mut scan = Scan::new(op, lane_len, NumberType::uint64());
+        let mut scanned = Vec::with_capacity(values.len());
+        let mut start = 0;
+        for len in blocks {
+            let block = Array::from(values[start..start + len].to_vec());
+            let block = scan.push(block).unwrap();
+            assert_eq!(block.len(), *len);
+            scanned.extend(block.into_values());
+            start += len;
+        }
+
+        scanned
+    }
+
+    // the expected result, computed one lane at a time
+    fn expect(op: CumulativeOp, lane_len: usize, values: &[u64]) -> Vec<Number> {
+        values
+            .chunks(lane_len)
+            .flat_map(|lane| {
+                lane.iter()
+                    .scan(None, move |acc: &mut Option<u64>, value| {
+                        let next = match (op, *acc) {
+                            (_, None) => *value,
+                            (CumulativeOp::Product, Some(acc)) => acc * value,
+                            (CumulativeOp::Sum, Some(acc)) => acc + value,
+                        };
+
+                        *acc = Some(next);
+                        Some(Number::from(next))
+                    })
+                    .collect::<Vec<Number>>()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_cumsum() {
+        let values: Vec<u64> = (1..=12).collect();
+        let op = CumulativeOp::Sum;
+
+        let cases: [(usize, &[usize]); 8] = [
+            (4, &[12]),
+            (4, &[4, 4, 4]),
+            (4, &[3, 5, 4]),
+            (4, &[2, 10]),
+            (4, &[1; 12]),
+            (12, &[5, 5, 2]),
+            (6, &[5, 0, 7]),
+            (1, &[7, 5]),
+        ];
+
+        for (lane_len, blocks) in cases.iter() {
+            assert_eq!(
+                scan(op, *lane_len, &values, blocks),
+                expect(op, *lane_len, &values),
+                "lane length {} in blocks {:?}",
+                lane_len,
+                blocks
+            );
+        }
+
+        assert_eq!(scan(op, 4, &values, &[12])[4], Number::from(5u64));
+        assert_eq!(scan(op, 4, &values, &[3, 9])[5], Number::from(11u64));
+    }
+
+    #[test]
+    fn test_cumprod() {
+        let values: Vec<u64> = (1..=6).collect();
+        let op = CumulativeOp::Product;
+
+        let expected: Vec<Number> = [1u64, 2, 6, 4, 20, 120]
+            .iter()
+            .copied()
+            .map(Number::from)
+            .collect();
+
+        assert_eq!(expect(op, 3, &values), expected);
+        assert_eq!(scan(op, 3, &values, &[6]), expected);
+        assert_eq!(scan(op, 3, &values, &[2, 2, 2]), expected);
+        assert_eq!(scan(op, 3, &values, &[1, 4, 1]), expected);
+    }
+
+    #[test]
+    fn test_bool() {
+        let mut scan = Scan::new(CumulativeOp::Sum, 3, NumberType::uint64());
+
+        let first = scan.push(Array::from(vec![true, true])).unwrap();
+        let second = scan
+            .push(Array::from(vec![true, false, true, true]))
+            .unwrap();
+        assert!(first.dtype() == NumberType::uint64());
+        assert!(second.dtype() == NumberType::uint64());
+
+        let expected: Vec<Number> = [1u64, 2, 3, 0, 1, 2]
+            .iter()
+            .copied()
+            .map(Number::from)
+            .collect();
+
+        let mut actual = first.into_values();
+        actual.extend(second.into_values());
+        assert_eq!(actual, expected);
+    }
+}
@@ -12,6 +12,7 @@ use super::super::sparse::SparseAccess;
 use super::super::stream::*;
 use super::super::transform::{self, Rebase};
 use super::super::{Bounds, Coord, Shape, TensorAccess, ERR_NONBIJECTIVE_WRITE};
+use super::memory::DEVICE_MEMORY;
 use super::stream::SparseValueStream;
 use super::{Array, DenseAccess, DenseAccessor, DenseTensor};
 
@@ -93,10 +94,16 @@ impl<L: Clone + DenseAccess, R: Clone + DenseAccess> DenseAccess for BlockListCo
             let (left, right) = try_join!(left, right)?;
 
             let combinator = self.combinator;
+            let dtype = self.dtype;
             let blocks = left
                 .zip(right)
                 .map(|(l, r)| Ok((l?, r?)))
-                .map_ok(move |(l, r)| combinator(&l, &r));
+                .and_then(move |(l, r)| {
+                    let size = l.len() * NumberClass::size(dtype);
+                    let block = DEVICE_MEMORY.check(txn, size).map(|()| combinator(&l, &r));
+
+                    future::ready(block)
+                });
 
             let blocks: TCTryStream<'a, Array> = Box::pin(blocks);
             Ok(blocks)
@@ -481,7 +488,7 @@ pub struct BlockListReduce<T> {
 }
 
 impl<T: DenseAccess> BlockListReduce<T> {
-    fn new(source: T, axis: usize, reductor: Reductor) -> TCResult<BlockListReduce<T>> {
+    pub fn new(source: T, axis: usize, reductor: Reductor) -> TCResult<BlockListReduce<T>> {
         let rebase = transform::Reduce::new(source.shape().clone(), axis)?;
 
         Ok(BlockListReduce {
@@ -848,9 +855,16 @@ impl<T: Clone + DenseAccess> DenseAccess for BlockListUnary<T> {
     fn block_stream<'a>(&'a self, txn: &'a Txn) -> TCBoxTryFuture<'a, TCTryStream<'a, Array>> {
         Box::pin(async move {
             let transform = self.transform;
+            let dtype = self.dtype;
             let blocks = self.source.block_stream(txn).await?;
-            let blocks: TCTryStream<'a, Array> =
-                Box::pin(blocks.map_ok(move |array| transform(&array)));
+            let blocks = blocks.and_then(move |array| {
+                let size = array.len() * NumberClass::size(dtype);
+                let block = DEVICE_MEMORY.check(txn, size).map(|()| transform(&array));
+
+                future::ready(block)
+            });
+
+            let blocks: TCTryStream<'a, Array> = Box::pin(blocks);
             Ok(blocks)
         })
     }
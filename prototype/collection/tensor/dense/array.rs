@@ -59,6 +59,14 @@ impl<T: af::HasAfEnum + Default> ArrayExt<T> {
         }
     }
 
+    fn scan_by_key(&self, keys: &af::Array<u32>, op: af::BinaryOp) -> ArrayExt<T> {
+        ArrayExt(af::scan_by_key(keys, self.af(), 0, op, true).cast())
+    }
+
+    fn select(cond: &ArrayExt<bool>, left: &ArrayExt<T>, right: &ArrayExt<T>) -> ArrayExt<T> {
+        ArrayExt(af::select(left.af(), cond.af(), right.af()))
+    }
+
     fn split(&self, at: usize) -> (ArrayExt<T>, ArrayExt<T>) {
         let left = af::Seq::new(0.0, at as f32, 1.0);
         let right = af::Seq::new(at as f32, self.len() as f32, 1.0);
@@ -308,7 +316,7 @@ impl TryFrom<Bytes> for ArrayExt<bool> {
         for byte in data {
             match byte as u8 {
                 0 => array.push(false),
-                1 => array.push(false),
+                1 => array.push(true),
                 other => return Err(err_corrupt(format!("invalid boolean: {}", other))),
             }
         }
@@ -345,7 +353,7 @@ impl TryFrom<Bytes> for ArrayExt<num::Complex<f64>> {
         }
 
         let mut array = Vec::with_capacity(data.len() / 16);
-        for c in data[..].chunks_exact(8) {
+        for c in data[..].chunks_exact(16) {
             let re = f64::from_be_bytes(c[0..8].try_into().unwrap());
             let im = f64::from_be_bytes(c[8..16].try_into().unwrap());
             array.push(num::Complex::new(re, im));
@@ -502,7 +510,7 @@ impl TryFrom<Bytes> for ArrayExt<u64> {
     fn try_from(data: Bytes) -> TCResult<Self> {
         if data.len() % 8 != 0 {
             return Err(err_corrupt(
-                "invalid byte sequence for 64-bit signed integer",
+                "invalid byte sequence for 64-bit unsigned integer",
             ));
         }
 
@@ -980,11 +988,11 @@ impl Array {
             NumberType::Bool => Bool(vec_cast_into(values).into()),
             NumberType::Complex(c) => match c {
                 ComplexType::C32 => C32(vec_cast_into(values).into()),
-                ComplexType::C64 => C32(vec_cast_into(values).into()),
+                ComplexType::C64 => C64(vec_cast_into(values).into()),
             },
             NumberType::Float(f) => match f {
                 FloatType::F32 => F32(vec_cast_into(values).into()),
-                FloatType::F64 => F32(vec_cast_into(values).into()),
+                FloatType::F64 => F64(vec_cast_into(values).into()),
             },
             NumberType::Int(i) => match i {
                 IntType::I16 => I16(vec_cast_into(values).into()),
@@ -1018,14 +1026,14 @@ impl Array {
                 let values: Vec<Complex> = vec_try_into(values)?;
                 match c {
                     ComplexType::C32 => C32(vec_try_into(values)?.into()),
-                    ComplexType::C64 => C32(vec_try_into(values)?.into()),
+                    ComplexType::C64 => C64(vec_into(values).into()),
                 }
             }
             NumberType::Float(f) => {
                 let values: Vec<Float> = vec_try_into(values)?;
                 match f {
                     FloatType::F32 => F32(vec_try_into(values)?.into()),
-                    FloatType::F64 => F32(vec_try_into(values)?.into()),
+                    FloatType::F64 => F64(vec_into(values).into()),
                 }
             }
             NumberType::Int(i) => {
@@ -1055,18 +1063,53 @@ impl Array {
         Ok(chunk)
     }
 
+    /// Compute the inclusive cumulative product of each lane of `lane_len` elements in this
+    /// `Array`, which begins `offset` elements into its first lane.
+    pub fn cumprod(&self, offset: usize, lane_len: usize) -> Array {
+        self.scan_lanes(af::BinaryOp::MUL, offset, lane_len)
+    }
+
+    /// Compute the inclusive cumulative sum of each lane of `lane_len` elements in this `Array`,
+    /// which begins `offset` elements into its first lane.
+    pub fn cumsum(&self, offset: usize, lane_len: usize) -> Array {
+        self.scan_lanes(af::BinaryOp::ADD, offset, lane_len)
+    }
+
+    fn scan_lanes(&self, op: af::BinaryOp, offset: usize, lane_len: usize) -> Array {
+        let keys: Vec<u32> = (0..self.len())
+            .map(|i| ((offset + i) / lane_len) as u32)
+            .collect();
+        let keys = af::Array::new(&keys, dim4(keys.len()));
+
+        use Array::*;
+        match self {
+            Bool(b) => U64(b.as_type::<u64>().scan_by_key(&keys, op)),
+            C32(c) => C32(c.scan_by_key(&keys, op)),
+            C64(c) => C64(c.scan_by_key(&keys, op)),
+            F32(f) => F32(f.scan_by_key(&keys, op)),
+            F64(f) => F64(f.scan_by_key(&keys, op)),
+            I16(i) => I16(i.scan_by_key(&keys, op)),
+            I32(i) => I32(i.scan_by_key(&keys, op)),
+            I64(i) => I64(i.scan_by_key(&keys, op)),
+            U8(u) => U8(u.scan_by_key(&keys, op)),
+            U16(u) => U16(u.scan_by_key(&keys, op)),
+            U32(u) => U32(u.scan_by_key(&keys, op)),
+            U64(u) => U64(u.scan_by_key(&keys, op)),
+        }
+    }
+
     pub fn dtype(&self) -> NumberType {
         use Array::*;
         match self {
             Bool(_) => NumberType::Bool,
             C32(_) => ComplexType::C32.into(),
-            C64(_) => ComplexType::C32.into(),
+            C64(_) => ComplexType::C64.into(),
             F32(_) => FloatType::F32.into(),
-            F64(_) => FloatType::F32.into(),
+            F64(_) => FloatType::F64.into(),
             I16(_) => IntType::I16.into(),
             I32(_) => IntType::I32.into(),
             I64(_) => IntType::I64.into(),
-            U8(_) => UIntType::U16.into(),
+            U8(_) => UIntType::U8.into(),
             U16(_) => UIntType::U16.into(),
             U32(_) => UIntType::U32.into(),
             U64(_) => UIntType::U64.into(),
@@ -1178,7 +1221,7 @@ impl Array {
     }
 
     pub fn add(&self, other: &Array) -> Array {
-        let dtype = Ord::max(self.dtype(), other.dtype());
+        let dtype = self.dtype().promote(other.dtype()).0;
 
         use ComplexType::*;
         use FloatType::*;
@@ -1211,6 +1254,21 @@ impl Array {
         }
     }
 
+    /// Return `true` if every element of this `Array` is within `atol + rtol * |other|` of the
+    /// corresponding element of `other`. The elements are compared as 64-bit floats, so the
+    /// imaginary part of a complex number is ignored, and `NaN` is never close to anything.
+    pub fn allclose(&self, other: &Array, rtol: f64, atol: f64) -> bool {
+        let left: ArrayExt<f64> = self.af_cast();
+        let right: ArrayExt<f64> = other.af_cast();
+
+        let diff = af::abs(&af::sub(left.af(), right.af(), false));
+        let rtol = af::constant(rtol, dim4(1));
+        let atol = af::constant(atol, dim4(1));
+        let tolerance = af::add(&af::mul(&af::abs(right.af()), &rtol, true), &atol, true);
+
+        af::all_true_all(&af::le(&diff, &tolerance, false)).0 > 0f64
+    }
+
     pub fn and(&self, other: &Array) -> Array {
         let this: ArrayExt<bool> = self.af_cast();
         let that: ArrayExt<bool> = other.af_cast();
@@ -1327,7 +1385,7 @@ impl Array {
     }
 
     pub fn multiply(&self, other: &Array) -> Array {
-        let dtype = Ord::max(self.dtype(), other.dtype());
+        let dtype = self.dtype().promote(other.dtype()).0;
 
         use ComplexType::*;
         use FloatType::*;
@@ -1360,6 +1418,78 @@ impl Array {
         }
     }
 
+    /// Return `true` if any element of this `Array` is `NaN`.
+    pub fn has_nan(&self) -> bool {
+        self.is_nan().any()
+    }
+
+    /// Return a boolean `Array` which is true where this `Array` is infinite.
+    pub fn is_inf(&self) -> Array {
+        use Array::*;
+        match self {
+            C32(c) => Bool(ArrayExt(af::or(
+                &af::isinf(&af::real(c.af())),
+                &af::isinf(&af::imag(c.af())),
+                false,
+            ))),
+            C64(c) => Bool(ArrayExt(af::or(
+                &af::isinf(&af::real(c.af())),
+                &af::isinf(&af::imag(c.af())),
+                false,
+            ))),
+            F32(f) => Bool(ArrayExt(af::isinf(f.af()))),
+            F64(f) => Bool(ArrayExt(af::isinf(f.af()))),
+            other => Array::constant(false.into(), other.len()),
+        }
+    }
+
+    /// Return a boolean `Array` which is true where this `Array` is `NaN`.
+    pub fn is_nan(&self) -> Array {
+        use Array::*;
+        match self {
+            C32(c) => Bool(ArrayExt(af::or(
+                &af::isnan(&af::real(c.af())),
+                &af::isnan(&af::imag(c.af())),
+                false,
+            ))),
+            C64(c) => Bool(ArrayExt(af::or(
+                &af::isnan(&af::real(c.af())),
+                &af::isnan(&af::imag(c.af())),
+                false,
+            ))),
+            F32(f) => Bool(ArrayExt(af::isnan(f.af()))),
+            F64(f) => Bool(ArrayExt(af::isnan(f.af()))),
+            other => Array::constant(false.into(), other.len()),
+        }
+    }
+
+    /// Replace `NaN` with zero, and positive and negative infinity with the largest and smallest
+    /// finite values of this `Array`'s data type. Only real floating-point arrays are changed.
+    pub fn nan_to_num(&self) -> Array {
+        use Array::*;
+        match self {
+            F32(f) => F32(ArrayExt(nan_to_num(
+                f.af(),
+                0f32,
+                std::f32::MAX as f64,
+                std::f32::MIN as f64,
+            ))),
+            F64(f) => F64(ArrayExt(nan_to_num(
+                f.af(),
+                0f64,
+                std::f64::MAX,
+                std::f64::MIN,
+            ))),
+            other => other.clone(),
+        }
+    }
+
+    /// Return the offsets of the nonzero elements of this `Array`.
+    pub fn nonzero(&self) -> af::Array<u64> {
+        let mask: ArrayExt<bool> = self.af_cast();
+        af::locate(mask.af()).cast()
+    }
+
     pub fn not(&self) -> Array {
         let this: ArrayExt<bool> = self.af_cast();
         Array::Bool(this.not())
@@ -1559,6 +1689,42 @@ impl Array {
         }
     }
 
+    /// Select the elements of `left` where this `Array` is true, and of `right` elsewhere.
+    pub fn select(&self, left: &Array, right: &Array) -> Array {
+        let dtype = left.dtype().promote(right.dtype()).0;
+        let cond: ArrayExt<bool> = self.af_cast();
+
+        use ComplexType::*;
+        use FloatType::*;
+        use IntType::*;
+        use NumberType::*;
+        use UIntType::*;
+
+        match dtype {
+            Bool => Self::Bool(ArrayExt::select(&cond, &left.af_cast(), &right.af_cast())),
+            Complex(ct) => match ct {
+                C32 => Self::C32(ArrayExt::select(&cond, &left.af_cast(), &right.af_cast())),
+                C64 => Self::C64(ArrayExt::select(&cond, &left.af_cast(), &right.af_cast())),
+            },
+            Float(ft) => match ft {
+                F32 => Self::F32(ArrayExt::select(&cond, &left.af_cast(), &right.af_cast())),
+                F64 => Self::F64(ArrayExt::select(&cond, &left.af_cast(), &right.af_cast())),
+            },
+            Int(it) => match it {
+                I16 => Self::I16(ArrayExt::select(&cond, &left.af_cast(), &right.af_cast())),
+                I32 => Self::I32(ArrayExt::select(&cond, &left.af_cast(), &right.af_cast())),
+                I64 => Self::I64(ArrayExt::select(&cond, &left.af_cast(), &right.af_cast())),
+            },
+            UInt(ut) => match ut {
+                U8 => Self::U8(ArrayExt::select(&cond, &left.af_cast(), &right.af_cast())),
+                U16 => Self::U16(ArrayExt::select(&cond, &left.af_cast(), &right.af_cast())),
+                U32 => Self::U32(ArrayExt::select(&cond, &left.af_cast(), &right.af_cast())),
+                U64 => Self::U64(ArrayExt::select(&cond, &left.af_cast(), &right.af_cast())),
+            },
+            NumberType::Number => panic!("Array does not support generic type Number"),
+        }
+    }
+
     pub fn split(&self, at: usize) -> TCResult<(Array, Array)> {
         if at < self.len() {
             use Array::*;
@@ -1604,9 +1770,20 @@ impl TryFrom<Bytes> for Array {
     type Error = error::TCError;
 
     fn try_from(mut data: Bytes) -> TCResult<Array> {
-        let array = data.split_off(2);
-        let dtype: NumberType = bincode::deserialize(&data)
-            .map_err(|e| error::bad_request("Unable to deserialize Tensor array data type", e))?;
+        let (dtype, array) = if data.starts_with(&BLOCK_MAGIC[..]) {
+            let header = BlockHeader::read(&data)?;
+            let array = data.split_off(HEADER_LEN);
+            header.validate(&array)?;
+            (header.dtype()?, array)
+        } else {
+            // blocks written before the versioned header was introduced begin with the dtype only
+            let dtype: NumberType = bincode::deserialize(&data).map_err(|e| {
+                error::bad_request("Unable to deserialize Tensor array data type", e)
+            })?;
+
+            let header_len = bincode::serialized_size(&dtype).map_err(err_corrupt)? as usize;
+            (dtype, data.split_off(header_len))
+        };
 
         use Array::*;
         use NumberType::*;
@@ -1644,7 +1821,8 @@ impl TryFrom<Bytes> for Array {
 
 impl From<Array> for Bytes {
     fn from(array: Array) -> Bytes {
-        let dtype = array.dtype();
+        let code = dtype_code(&array);
+        let len = array.len();
 
         use Array::*;
         let serialized: Bytes = match array {
@@ -1662,12 +1840,142 @@ impl From<Array> for Bytes {
             U64(u) => u.into(),
         };
 
-        let dtype = Bytes::from(bincode::serialize(&dtype).unwrap());
-        assert_eq!(dtype.len(), 2);
-        Bytes::from([dtype, serialized].concat())
+        let header = BlockHeader {
+            code,
+            len: len as u64,
+            checksum: adler32(&serialized),
+        };
+
+        Bytes::from([header.write(), serialized].concat())
     }
 }
 
+const BLOCK_MAGIC: [u8; 4] = *b"TCAB";
+const BLOCK_VERSION: u8 = 1;
+const HEADER_LEN: usize = 18;
+
+/// The header of a serialized `Array`:
+/// magic (4 bytes), version (1), dtype (1), element count (8), and Adler-32 checksum (4).
+struct BlockHeader {
+    code: u8,
+    len: u64,
+    checksum: u32,
+}
+
+impl BlockHeader {
+    fn read(data: &[u8]) -> TCResult<BlockHeader> {
+        if data.len() < HEADER_LEN {
+            return Err(err_corrupt("truncated block header"));
+        }
+
+        if data[4] != BLOCK_VERSION {
+            return Err(error::unsupported(format!(
+                "Tensor block format version {} (the latest supported version is {})",
+                data[4], BLOCK_VERSION
+            )));
+        }
+
+        let code = data[5];
+        dtype_from_code(code)?;
+
+        let len = u64::from_be_bytes(data[6..14].try_into().unwrap());
+        let checksum = u32::from_be_bytes(data[14..18].try_into().unwrap());
+
+        Ok(BlockHeader {
+            code,
+            len,
+            checksum,
+        })
+    }
+
+    fn dtype(&self) -> TCResult<NumberType> {
+        dtype_from_code(self.code)
+    }
+
+    fn validate(&self, array: &[u8]) -> TCResult<()> {
+        let dtype = self.dtype()?;
+        let expected = self.len as usize * NumberClass::size(dtype);
+        if array.len() != expected {
+            Err(err_corrupt(format!(
+                "expected {} bytes of {} but found {}",
+                expected,
+                dtype,
+                array.len()
+            )))
+        } else if adler32(array) != self.checksum {
+            Err(err_corrupt("checksum mismatch"))
+        } else {
+            Ok(())
+        }
+    }
+
+    fn write(&self) -> Bytes {
+        let mut header = Vec::with_capacity(HEADER_LEN);
+        header.extend_from_slice(&BLOCK_MAGIC);
+        header.push(BLOCK_VERSION);
+        header.push(self.code);
+        header.extend_from_slice(&self.len.to_be_bytes());
+        header.extend_from_slice(&self.checksum.to_be_bytes());
+        header.into()
+    }
+}
+
+// the dtype of an Array is always a concrete type, so every Array has a code
+fn dtype_code(array: &Array) -> u8 {
+    use Array::*;
+    match array {
+        Bool(_) => 0,
+        C32(_) => 1,
+        C64(_) => 2,
+        F32(_) => 3,
+        F64(_) => 4,
+        I16(_) => 5,
+        I32(_) => 6,
+        I64(_) => 7,
+        U8(_) => 8,
+        U16(_) => 9,
+        U32(_) => 10,
+        U64(_) => 11,
+    }
+}
+
+fn dtype_from_code(code: u8) -> TCResult<NumberType> {
+    let dtype = match code {
+        0 => NumberType::Bool,
+        1 => ComplexType::C32.into(),
+        2 => ComplexType::C64.into(),
+        3 => FloatType::F32.into(),
+        4 => FloatType::F64.into(),
+        5 => IntType::I16.into(),
+        6 => IntType::I32.into(),
+        7 => IntType::I64.into(),
+        8 => UIntType::U8.into(),
+        9 => UIntType::U16.into(),
+        10 => UIntType::U32.into(),
+        11 => UIntType::U64.into(),
+        other => return Err(err_corrupt(format!("invalid dtype code {}", other))),
+    };
+
+    Ok(dtype)
+}
+
+fn adler32(data: &[u8]) -> u32 {
+    const MOD: u32 = 65521;
+
+    let (mut a, mut b) = (1u32, 0u32);
+    for chunk in data.chunks(5552) {
+        for byte in chunk {
+            a += *byte as u32;
+            b += a;
+        }
+
+        a %= MOD;
+        b %= MOD;
+    }
+
+    (b << 16) | a
+}
+
 impl BlockData for Array {
     fn size(&self) -> usize {
         let data: ArrayExt<bool> = self.af_cast();
@@ -1800,6 +2108,17 @@ impl fmt::Display for Array {
     }
 }
 
+fn nan_to_num<T>(array: &af::Array<T>, zero: T, max: f64, min: f64) -> af::Array<T>
+where
+    T: af::HasAfEnum + af::ImplicitPromote<T> + af::ConstGenerator<OutType = T>,
+{
+    let array = af::selectr(array, &af::not(&af::isnan(array)), 0f64);
+    let positive = af::gt(&array, &af::constant(zero, dim4(1)), true);
+    let posinf = af::and(&af::isinf(&array), &positive, false);
+    let array = af::selectr(&array, &af::not(&posinf), max);
+    af::selectr(&array, &af::not(&af::isinf(&array)), min)
+}
+
 fn dim4(size: usize) -> af::Dim4 {
     af::Dim4::new(&[size as u64, 1, 1, 1])
 }
@@ -1833,6 +2152,142 @@ mod tests {
         assert_eq!(arr.get_value(1), Number::from(2))
     }
 
+    fn assert_round_trip(array: Array) {
+        let dtype = array.dtype();
+        let expected = array.clone().into_values();
+
+        let actual = Array::try_from(Bytes::from(array)).unwrap();
+        assert!(actual.dtype() == dtype);
+        assert_eq!(actual.into_values(), expected);
+    }
+
+    #[test]
+    fn test_block_round_trip() {
+        assert_round_trip(Array::from(vec![true, false, true]));
+        assert_round_trip(Array::from(vec![
+            num::Complex::new(1f64, -1f64),
+            num::Complex::new(0.5f64, 2f64),
+        ]));
+        assert_round_trip(Array::from(vec![1.5f32, -2f32]));
+        assert_round_trip(Array::from(vec![-1i16, 2i16]));
+        assert_round_trip(Array::from(vec![u64::MAX, 0u64]));
+    }
+
+    #[test]
+    fn test_block_corrupt() {
+        let mut data = Bytes::from(Array::from(vec![1u32, 2u32, 3u32])).to_vec();
+
+        let last = data.len() - 1;
+        data[last] ^= 1;
+        assert!(Array::try_from(Bytes::from(data.clone())).is_err());
+
+        data[5] = 12;
+        assert!(Array::try_from(Bytes::from(data)).is_err());
+    }
+
+    #[test]
+    fn test_legacy_block() {
+        let dtype = NumberType::from(UIntType::U16);
+        let mut data = bincode::serialize(&dtype).unwrap();
+        data.extend_from_slice(&1u16.to_be_bytes());
+        data.extend_from_slice(&2u16.to_be_bytes());
+
+        let array = Array::try_from(Bytes::from(data)).unwrap();
+        assert!(array.dtype() == dtype);
+
+        let expected: Vec<Number> = vec![UInt::from(1u16).into(), UInt::from(2u16).into()];
+        assert_eq!(array.into_values(), expected);
+    }
+
+    #[test]
+    fn test_scan_lanes() {
+        let arr = Array::from(vec![1u64, 2, 3, 4, 5, 6, 7]);
+
+        let expected: Vec<Number> = [1u64, 3, 6, 4, 9, 15, 7]
+            .iter()
+            .copied()
+            .map(Number::from)
+            .collect();
+        assert_eq!(arr.cumsum(0, 3).into_values(), expected);
+
+        // the first two elements finish a lane which began in a previous block
+        let expected: Vec<Number> = [1u64, 3, 3, 7, 12, 6, 13]
+            .iter()
+            .copied()
+            .map(Number::from)
+            .collect();
+        assert_eq!(arr.cumsum(1, 3).into_values(), expected);
+
+        let expected: Vec<Number> = [1u64, 2, 6, 4, 20, 120, 7]
+            .iter()
+            .copied()
+            .map(Number::from)
+            .collect();
+        assert_eq!(arr.cumprod(0, 3).into_values(), expected);
+
+        let bools = Array::from(vec![true, false, true, true]);
+        let summed = bools.cumsum(0, 4);
+        assert!(summed.dtype() == NumberType::uint64());
+        assert_eq!(
+            summed.into_values(),
+            vec![Number::from(1u64), 1u64.into(), 2u64.into(), 3u64.into()]
+        );
+    }
+
+    #[test]
+    fn test_allclose() {
+        let left = Array::from(vec![1f64, 100., -3.]);
+
+        assert!(left.allclose(&left, 0., 0.));
+        assert!(left.allclose(&Array::from(vec![1.05f64, 100., -3.]), 0., 0.1));
+        assert!(!left.allclose(&Array::from(vec![1.2f64, 100., -3.]), 0., 0.1));
+
+        // the relative tolerance scales with the magnitude of the right operand
+        assert!(left.allclose(&Array::from(vec![1f64, 101., -3.]), 0.01, 0.));
+        assert!(!left.allclose(&Array::from(vec![1.5f64, 100., -3.]), 0.01, 0.));
+
+        // elements are compared as floats regardless of their type
+        assert!(Array::from(vec![1u64, 2]).allclose(&Array::from(vec![1.5f32, 2.]), 0., 0.5));
+
+        let nan = Array::from(vec![f64::NAN, 1.]);
+        assert!(!nan.allclose(&nan, 1., 1.));
+    }
+
+    #[test]
+    fn test_select() {
+        let cond = Array::from(vec![true, false, true, false]);
+        let left = Array::from(vec![1u64, 2, 3, 4]);
+        let right = Array::from(vec![10u64, 20, 30, 40]);
+
+        let selected = cond.select(&left, &right);
+        assert!(selected.dtype() == NumberType::uint64());
+        assert_eq!(
+            selected.into_values(),
+            Array::from(vec![1u64, 20, 3, 40]).into_values()
+        );
+
+        // the output has the promoted type of both operands
+        let right = Array::from(vec![0.5f64, 1.5, f64::NAN, 2.5]);
+        let selected = cond.select(&left, &right);
+        assert!(selected.dtype() == NumberType::Float(FloatType::F64));
+        assert_eq!(
+            selected.into_values(),
+            Array::from(vec![1f64, 1.5, 3., 2.5]).into_values()
+        );
+
+        // a NaN appears in the output only where it's selected
+        assert!(cond.not().select(&left, &right).has_nan());
+
+        // a numeric condition selects where it's nonzero
+        let cond = Array::from(vec![0u64, 7, 0, 1]);
+        let right = Array::from(vec![10u64, 20, 30, 40]);
+        let selected = cond.select(&left, &right);
+        assert_eq!(
+            selected.into_values(),
+            Array::from(vec![10u64, 2, 30, 4]).into_values()
+        );
+    }
+
     #[test]
     fn test_get() {
         let arr = Array::from(vec![1, 2, 3]);
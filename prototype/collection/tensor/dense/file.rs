@@ -24,6 +24,7 @@ use super::super::transform::{self, Rebase};
 use super::super::TensorAccess;
 
 use super::array::Array;
+use super::memory::{Placement, DEVICE_MEMORY};
 use super::{BlockListTranspose, Coord, DenseAccess, DenseAccessor};
 
 pub const PER_BLOCK: usize = 131_072; // = 1 mibibyte / 64 bits
@@ -62,7 +63,7 @@ impl BlockListFile {
         blocks
             .enumerate()
             .map(|(i, r)| r.map(|block| (BlockId::from(i), block)))
-            .map_ok(|(id, block)| file.clone().create_block(txn.id().clone(), id, block))
+            .map_ok(|(id, block)| store_block(txn, file.clone(), id, block))
             .try_buffer_unordered(2)
             .try_fold((), |_, _| future::ready(Ok(())))
             .await?;
@@ -83,12 +84,10 @@ impl BlockListFile {
         while let Some(chunk) = values.next().await {
             let block_id = BlockId::from(i);
             let block = Array::cast_from_values(chunk, dtype)?;
-            let block = file
-                .clone()
-                .create_block(txn.id().clone(), block_id, block)
-                .await?;
+            let len = block.len();
+            store_block(txn, file.clone(), block_id, block).await?;
 
-            debug!("created block {} with {} values", i, block.len());
+            debug!("created block {} with {} values", i, len);
 
             i += 1;
         }
@@ -322,6 +321,21 @@ impl ReadValueAt for BlockListFile {
     }
 }
 
+async fn store_block(
+    txn: &Txn,
+    file: Arc<File<Array>>,
+    block_id: BlockId,
+    block: Array,
+) -> TCResult<()> {
+    match DEVICE_MEMORY.place(txn, &block).await? {
+        Placement::Device => file
+            .create_block(*txn.id(), block_id, block)
+            .await
+            .map(|_| ()),
+        Placement::Host => file.spill_block(*txn.id(), block_id, block).await,
+    }
+}
+
 #[async_trait]
 impl Transact for BlockListFile {
     async fn commit(&self, txn_id: &TxnId) {
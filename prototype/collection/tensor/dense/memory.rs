@@ -0,0 +1,171 @@
+use std::sync::Mutex;
+
+use async_trait::async_trait;
+use log::debug;
+
+use crate::error;
+use crate::scalar::number::*;
+use crate::transaction::{Transact, Txn, TxnId};
+use crate::TCResult;
+
+use super::array::Array;
+
+pub static DEVICE_MEMORY: DeviceMemory = DeviceMemory::new();
+
+/// Where a new block of a dense tensor should be stored.
+pub enum Placement {
+    Device,
+    Host,
+}
+
+/// Accounting for the ArrayFire device memory held by the blocks of each transaction.
+///
+/// If the host has a `device_memory_limit`, it applies to each transaction separately, so that
+/// one large transaction can't starve the others. A block which would take a transaction past the
+/// limit is rejected with a `ResourceExhausted` error instead of failing inside ArrayFire, and a
+/// new block stored after a transaction passes 90% of the limit is spilled to host memory until
+/// it's read again.
+pub struct DeviceMemory {
+    usage: Mutex<Vec<(TxnId, usize)>>,
+}
+
+impl DeviceMemory {
+    const fn new() -> DeviceMemory {
+        DeviceMemory {
+            usage: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// The number of bytes of device memory held by blocks belonging to the given transaction.
+    pub fn usage(&self, txn_id: &TxnId) -> usize {
+        self.usage
+            .lock()
+            .unwrap()
+            .iter()
+            .find(|(id, _)| id == txn_id)
+            .map(|(_, held)| *held)
+            .unwrap_or(0)
+    }
+
+    /// Check that the given transaction may compute a block of `size` bytes on the device.
+    pub fn check(&self, txn: &Txn, size: usize) -> TCResult<()> {
+        let limit = match txn.gateway().config().device_memory_limit()? {
+            Some(limit) => limit,
+            None => return Ok(()),
+        };
+
+        let held = self.usage(txn.id());
+        if fits(held, size, limit) {
+            Ok(())
+        } else {
+            Err(error::resource_exhausted(format!(
+                "Transaction {} needs {} bytes of device memory but already holds {} bytes \
+                of its limit of {} bytes",
+                txn.id(),
+                size,
+                held,
+                limit
+            )))
+        }
+    }
+
+    /// Decide where to store a new block, and charge its transaction if it stays on the device.
+    pub async fn place(&self, txn: &Txn, block: &Array) -> TCResult<Placement> {
+        let size = block_size(block);
+
+        if let Some(limit) = txn.gateway().config().device_memory_limit()? {
+            if should_spill(self.usage(txn.id()), size, limit) {
+                debug!("spilling a block of {} bytes to host memory", size);
+                return Ok(Placement::Host);
+            }
+        }
+
+        if self.charge(txn.id(), size) {
+            txn.enlist(Box::new(Release)).await;
+        }
+
+        Ok(Placement::Device)
+    }
+
+    // charge `size` bytes to the given transaction, and return `true` if it held none before
+    fn charge(&self, txn_id: &TxnId, size: usize) -> bool {
+        let mut usage = self.usage.lock().unwrap();
+        match usage.iter_mut().find(|(id, _)| id == txn_id) {
+            Some((_, held)) => {
+                *held += size;
+                false
+            }
+            None => {
+                usage.push((*txn_id, size));
+                true
+            }
+        }
+    }
+
+    fn release(&self, txn_id: &TxnId) {
+        self.usage.lock().unwrap().retain(|(id, _)| id != txn_id);
+    }
+}
+
+struct Release;
+
+#[async_trait]
+impl Transact for Release {
+    async fn commit(&self, txn_id: &TxnId) {
+        DEVICE_MEMORY.release(txn_id)
+    }
+
+    async fn rollback(&self, txn_id: &TxnId) {
+        DEVICE_MEMORY.release(txn_id)
+    }
+
+    async fn finalize(&self, txn_id: &TxnId) {
+        DEVICE_MEMORY.release(txn_id)
+    }
+}
+
+/// The size of the given block in device memory, in bytes.
+pub fn block_size(block: &Array) -> usize {
+    block.len() * NumberClass::size(block.dtype())
+}
+
+/// Return `true` if a transaction which holds `held` bytes may use another `size` bytes.
+fn fits(held: usize, size: usize, limit: usize) -> bool {
+    held.checked_add(size)
+        .map(|total| total <= limit)
+        .unwrap_or(false)
+}
+
+/// Return `true` if a new block of `size` bytes should be stored in host memory because its
+/// transaction, which holds `held` bytes, is near its limit.
+fn should_spill(held: usize, size: usize, limit: usize) -> bool {
+    !fits(held, size, (limit / 10) * 9)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_limits() {
+        assert!(fits(0, 100, 100));
+        assert!(!fits(1, 100, 100));
+        assert!(!fits(usize::MAX, 1, usize::MAX));
+
+        assert!(!should_spill(0, 80, 100));
+        assert!(should_spill(50, 41, 100));
+    }
+
+    #[test]
+    fn test_usage_per_txn() {
+        let memory = DeviceMemory::new();
+        let txn_id = TxnId::zero();
+
+        assert!(memory.charge(&txn_id, 64));
+        assert!(!memory.charge(&txn_id, 32));
+        assert_eq!(memory.usage(&txn_id), 96);
+
+        memory.release(&txn_id);
+        assert_eq!(memory.usage(&txn_id), 0);
+    }
+}
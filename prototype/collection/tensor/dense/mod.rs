@@ -24,6 +24,7 @@ use super::{
 mod access;
 mod array;
 mod file;
+mod memory;
 mod stream;
 
 pub use access::*;
@@ -566,12 +567,12 @@ impl<T: Clone + DenseAccess, OT: Clone + DenseAccess> TensorMath<DenseTensor<OT>
     type Combine = DenseTensor<BlockListCombine<T, OT>>;
 
     fn add(&self, other: &DenseTensor<OT>) -> TCResult<Self::Combine> {
-        let dtype = Ord::max(self.dtype(), other.dtype());
+        let dtype = self.dtype().promote(other.dtype()).0;
         self.combine(other, Array::add, <Number as NumberInstance>::add, dtype)
     }
 
     fn multiply(&self, other: &DenseTensor<OT>) -> TCResult<Self::Combine> {
-        let dtype = Ord::max(self.dtype(), other.dtype());
+        let dtype = self.dtype().promote(other.dtype()).0;
         self.combine(
             other,
             Array::multiply,
@@ -653,8 +654,9 @@ impl<T: Clone + DenseAccess> TensorDualIO<Tensor> for DenseTensor<T> {
 impl<T: Clone + DenseAccess> TensorReduce for DenseTensor<T> {
     type Reduce = DenseTensor<BlockListReduce<T>>;
 
-    fn product(&self, _axis: usize) -> TCResult<Self::Reduce> {
-        Err(error::not_implemented("DenseTensor::product"))
+    fn product(&self, axis: usize) -> TCResult<Self::Reduce> {
+        let blocks = BlockListReduce::new(self.blocks.clone(), axis, DenseTensor::product_all)?;
+        Ok(DenseTensor { blocks })
     }
 
     fn product_all(&self, txn: Txn) -> TCBoxTryFuture<Number> {
@@ -677,8 +679,9 @@ impl<T: Clone + DenseAccess> TensorReduce for DenseTensor<T> {
         })
     }
 
-    fn sum(&self, _axis: usize) -> TCResult<Self::Reduce> {
-        Err(error::not_implemented("DenseTensor::sum"))
+    fn sum(&self, axis: usize) -> TCResult<Self::Reduce> {
+        let blocks = BlockListReduce::new(self.blocks.clone(), axis, DenseTensor::sum_all)?;
+        Ok(DenseTensor { blocks })
     }
 
     fn sum_all(&self, txn: Txn) -> TCBoxTryFuture<Number> {
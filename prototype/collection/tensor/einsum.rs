@@ -1,10 +1,22 @@
+use std::cmp::{max, min};
 use std::collections::{BTreeMap, HashMap, HashSet};
+use std::iter;
+use std::ops::Range;
+
+use log::debug;
+use num::integer::div_ceil;
 
 use crate::error;
+use crate::scalar::number::*;
+use crate::transaction::Txn;
 use crate::TCResult;
 
 use super::class::TensorInstance;
-use super::{TensorAccess, TensorMath, TensorReduce, TensorTransform};
+use super::dense::{dense_constant, BlockListFile, DenseAccess, DenseTensor};
+use super::sparse::SparseTensor;
+use super::{
+    AxisBounds, Tensor, TensorAccess, TensorDualIO, TensorMath, TensorReduce, TensorTransform,
+};
 
 const VALID_LABELS: [char; 52] = [
     'a', 'A', 'b', 'B', 'c', 'C', 'd', 'D', 'e', 'E', 'f', 'F', 'g', 'G', 'h', 'H', 'i', 'I', 'j',
@@ -22,13 +34,21 @@ fn parse_format(format: &str) -> TCResult<(Vec<Vec<char>>, Vec<char>)> {
         return Err(error::bad_request("Invalid format for einsum", format));
     }
 
+    let f_output: Vec<char> = parts.pop().unwrap().chars().collect();
     let f_inputs: Vec<Vec<char>> = parts
         .pop()
-        .unwrap()
+        .unwrap_or("")
         .split(',')
         .map(|f_input| f_input.chars().collect())
         .collect();
-    let f_output: Vec<char> = parts.pop().unwrap_or("").chars().collect();
+
+    let output_labels: HashSet<char> = f_output.iter().cloned().collect();
+    if output_labels.len() != f_output.len() {
+        return Err(error::bad_request(
+            "Duplicate label in einsum output format",
+            f_output.iter().collect::<String>(),
+        ));
+    }
 
     let valid_labels: HashSet<char> = VALID_LABELS.iter().cloned().collect();
     for f_input in &f_inputs {
@@ -182,23 +202,223 @@ fn contract<T: TensorInstance + TensorReduce<Reduce = T> + TensorTransform<Trans
     }
 }
 
-pub fn einsum<
-    T: TensorInstance
-        + TensorMath<T, Combine = T>
-        + TensorTransform<Expand = T, Transpose = T>
-        + TensorReduce<Reduce = T>,
->(
-    format: &str,
-    tensors: Vec<T>,
-) -> TCResult<T> {
+/// Compute the given Einstein summation one tile at a time.
+///
+/// The output is divided into tiles, and if necessary the contracted axes into chunks, so that
+/// the outer product computed for any one tile holds at most `einsum_working_set` bytes. The
+/// partial sums of each output tile are written to the transaction's workspace as they are
+/// computed, so that a tile is never a lazy chain of more than one pending chunk.
+pub async fn einsum(txn: &Txn, format: &str, tensors: Vec<Tensor>) -> TCResult<Tensor> {
     let (f_inputs, f_output) = parse_format(format)?;
     let dimensions = validate_args(&f_inputs, &tensors)?;
 
-    let op = outer_product(&f_inputs, &dimensions, tensors)?;
-    assert_eq!(
-        op.shape().to_vec(),
-        dimensions.values().cloned().collect::<Vec<u64>>()
+    for label in &f_output {
+        if !dimensions.contains_key(label) {
+            return Err(error::bad_request(
+                "einsum output format has an unknown label",
+                label,
+            ));
+        }
+    }
+
+    let f_contract: Vec<char> = dimensions
+        .keys()
+        .filter(|label| !f_output.contains(label))
+        .cloned()
+        .collect();
+
+    let output_dims: Vec<u64> = f_output.iter().map(|label| dimensions[label]).collect();
+    let contract_dims: Vec<u64> = f_contract.iter().map(|label| dimensions[label]).collect();
+    let contract_size = max(contract_dims.iter().product(), 1);
+
+    // the tensors are only combined once every pair of dtypes is known to promote safely
+    let strict = txn.gateway().config().strict_promotion()?;
+    let dtype = tensors[1..]
+        .iter()
+        .try_fold(tensors[0].dtype(), |dtype, tensor| {
+            dtype.try_promote(tensor.dtype(), strict)
+        })?;
+    let working_set = txn.gateway().config().einsum_working_set()? / NumberClass::size(dtype);
+    let working_set = max(working_set as u64, 1);
+
+    let (output_tile, contract_tile) = plan(working_set, contract_size);
+
+    debug!(
+        "einsum {} in tiles of {} output elements x {} contracted elements",
+        format, output_tile, contract_tile
     );
 
-    contract(op, dimensions, f_output)
+    let output = dense_constant(txn, output_dims.to_vec().into(), dtype.zero()).await?;
+    let output = Tensor::from(output);
+
+    for output_bounds in tiles(&output_dims, output_tile) {
+        let mut tile: Option<Tensor> = None;
+
+        for contract_bounds in tiles(&contract_dims, contract_tile) {
+            let bounds: HashMap<char, Range<u64>> = f_output
+                .iter()
+                .cloned()
+                .zip(output_bounds.iter().cloned())
+                .chain(f_contract.iter().cloned().zip(contract_bounds))
+                .collect();
+
+            let partial = einsum_tile(&f_inputs, &f_output, &tensors, &bounds)?;
+            let sum = match tile {
+                Some(tile) => tile.add(&partial)?,
+                None => partial,
+            };
+
+            tile = Some(materialize(txn, sum).await?);
+        }
+
+        if let Some(tile) = tile {
+            let bounds: Vec<AxisBounds> = output_bounds.into_iter().map(AxisBounds::In).collect();
+            output.write(txn, bounds.into(), tile).await?;
+        }
+    }
+
+    Ok(output)
+}
+
+/// Return the number of output elements and contracted elements to compute at a time, such that
+/// their product is at most `working_set`.
+fn plan(working_set: u64, contract_size: u64) -> (u64, u64) {
+    if contract_size > working_set {
+        (1, working_set)
+    } else {
+        (working_set / contract_size, contract_size)
+    }
+}
+
+// compute `tile` into a new file in the workspace of `txn`
+async fn materialize(txn: &Txn, tile: Tensor) -> TCResult<Tensor> {
+    let workspace = txn.subcontext_tmp().await?;
+    let shape = tile.shape().clone();
+    let dtype = tile.dtype();
+
+    match tile {
+        Tensor::Dense(dense) => {
+            let source = dense.into_inner();
+            let blocks = source.block_stream(txn).await?;
+            let file = BlockListFile::from_blocks(&workspace, shape, dtype, blocks).await?;
+            Ok(DenseTensor::from(file).into())
+        }
+        Tensor::Sparse(sparse) => {
+            let entries = sparse.filled(txn).await?;
+            SparseTensor::from_entries(&workspace, shape, dtype, entries)
+                .await
+                .map(Tensor::from)
+        }
+    }
+}
+
+fn einsum_tile(
+    f_inputs: &[Vec<char>],
+    f_output: &[char],
+    tensors: &[Tensor],
+    bounds: &HashMap<char, Range<u64>>,
+) -> TCResult<Tensor> {
+    let tensors = tensors
+        .iter()
+        .zip(f_inputs)
+        .map(|(tensor, f_input)| {
+            let slice: Vec<AxisBounds> = f_input
+                .iter()
+                .map(|label| AxisBounds::In(bounds[label].clone()))
+                .collect();
+
+            tensor.slice(slice.into())
+        })
+        .collect::<TCResult<Vec<Tensor>>>()?;
+
+    let dimensions: BTreeMap<char, u64> = bounds
+        .iter()
+        .map(|(label, range)| (*label, range.end - range.start))
+        .collect();
+
+    let op = outer_product(f_inputs, &dimensions, tensors)?;
+    contract(op, dimensions, f_output.to_vec())
+}
+
+/// Divide the given shape into tiles of at most `size` elements each, in row-major order.
+fn tiles(dims: &[u64], size: u64) -> Box<dyn Iterator<Item = Vec<Range<u64>>> + Send> {
+    let dims = dims.to_vec();
+
+    // find the first axis such that all the trailing axes fit in a single tile
+    let mut axis = dims.len();
+    let mut trailing = 1;
+    while axis > 0 && trailing * dims[axis - 1] <= size {
+        axis -= 1;
+        trailing *= dims[axis];
+    }
+
+    if axis == 0 {
+        let tile = dims.iter().map(|dim| 0..*dim).collect();
+        return Box::new(iter::once(tile));
+    }
+
+    let split = axis - 1;
+    let chunk = size / trailing;
+    let per_axis = div_ceil(dims[split], chunk);
+    let num_tiles = dims[..split].iter().product::<u64>() * per_axis;
+
+    Box::new((0..num_tiles).map(move |i| {
+        let mut tile = Vec::with_capacity(dims.len());
+
+        let mut leading = i / per_axis;
+        for dim in dims[..split].iter().rev() {
+            let x = leading % dim;
+            tile.push(x..x + 1);
+            leading /= dim;
+        }
+        tile.reverse();
+
+        let start = (i % per_axis) * chunk;
+        tile.push(start..min(start + chunk, dims[split]));
+        tile.extend(dims[axis..].iter().map(|dim| 0..*dim));
+        tile
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn size(tile: &[Range<u64>]) -> u64 {
+        tile.iter().map(|range| range.end - range.start).product()
+    }
+
+    #[test]
+    fn test_plan() {
+        for working_set in &[1, 7, 64, 1000] {
+            for contract_size in &[1, 3, 64, 5000] {
+                let (output_tile, contract_tile) = plan(*working_set, *contract_size);
+                assert!(output_tile >= 1 && contract_tile >= 1);
+                assert!(output_tile * contract_tile <= *working_set);
+                assert!(contract_tile <= *contract_size);
+            }
+        }
+    }
+
+    #[test]
+    fn test_tiles() {
+        let dims = [3, 5, 4];
+        for size in &[1, 2, 4, 7, 20, 60, 100] {
+            let tiles: Vec<Vec<Range<u64>>> = tiles(&dims, *size).collect();
+            assert!(tiles.iter().all(|tile| size(tile) <= *size));
+
+            let mut covered = HashSet::new();
+            for tile in &tiles {
+                for x in tile[0].clone() {
+                    for y in tile[1].clone() {
+                        for z in tile[2].clone() {
+                            assert!(covered.insert((x, y, z)), "tiles overlap");
+                        }
+                    }
+                }
+            }
+
+            assert_eq!(covered.len() as u64, dims.iter().product::<u64>());
+        }
+    }
 }
@@ -1,3 +1,4 @@
+use std::convert::TryFrom;
 use std::iter::FromIterator;
 
 use async_trait::async_trait;
@@ -9,12 +10,24 @@ use crate::error;
 use crate::general::Map;
 use crate::handler::*;
 use crate::request::Request;
-use crate::scalar::{label, MethodType, NumberType, PathSegment, Scalar, Value};
+use crate::scalar::{label, Id, Link, MethodType, NumberType, PathSegment, Scalar, Value};
 use crate::transaction::Txn;
-use crate::{TCResult, TryCastInto};
+use crate::scalar::number::Float;
+use crate::{CastInto, TCResult, TryCastFrom, TryCastInto};
 
+use super::backend;
 use super::bounds::*;
 use super::class::{Tensor, TensorInstance};
+use super::compare::{allclose, equals, DEFAULT_ATOL, DEFAULT_RTOL};
+use super::cumulative::{cumulative, CumulativeOp};
+use super::dense::dense_constant;
+use super::linalg::{diag, diagonal, trace};
+use super::mask::{masked_select, nonzero};
+use super::nan::{
+    isinf, isnan, nan_to_num, product_all, product_axis, sum_all, sum_axis, NanPolicy,
+};
+use super::rolling::{rolling, RollingOp};
+use super::select::select;
 use super::{IntoView, TensorDualIO, TensorUnary};
 
 struct AllHandler<'a, T: TensorInstance> {
@@ -73,6 +86,83 @@ impl<'a, T: TensorInstance> Handler for AnyHandler<'a, T> {
     }
 }
 
+struct CompareHandler<'a, T: TensorInstance> {
+    tensor: &'a T,
+    op: &'static str,
+}
+
+#[async_trait]
+impl<'a, T: TensorInstance> Handler for CompareHandler<'a, T> {
+    fn subject(&self) -> TCType {
+        self.tensor.class().into()
+    }
+
+    fn scope(&self) -> Option<Scope> {
+        Some(SCOPE_READ.into())
+    }
+
+    async fn handle_post(
+        self: Box<Self>,
+        request: &Request,
+        txn: &Txn,
+        mut params: Map<Scalar>,
+    ) -> TCResult<State> {
+        let other = operand(request, txn, self.tensor, &mut params, "other").await?;
+        let policy = nan_policy(&mut params)?;
+
+        let (rtol, atol) = if self.op == "allclose" {
+            (
+                tolerance(&mut params, "rtol", DEFAULT_RTOL)?,
+                tolerance(&mut params, "atol", DEFAULT_ATOL)?,
+            )
+        } else {
+            (DEFAULT_RTOL, DEFAULT_ATOL)
+        };
+
+        if !params.is_empty() {
+            return Err(error::bad_request(
+                "Unrecognized parameters",
+                Scalar::from_iter(params.into_inner()),
+            ));
+        }
+
+        let tensor = self.tensor.clone().into_view();
+        let result = match self.op {
+            "allclose" => allclose(txn, tensor, other, rtol, atol, policy).await,
+            "equals" => equals(txn, tensor, other, policy).await,
+            other => Err(error::not_found(other)),
+        };
+
+        result.map(Value::from).map(State::from)
+    }
+}
+
+struct CumulativeHandler<'a, T: TensorInstance> {
+    tensor: &'a T,
+    op: CumulativeOp,
+}
+
+#[async_trait]
+impl<'a, T: TensorInstance> Handler for CumulativeHandler<'a, T> {
+    fn subject(&self) -> TCType {
+        self.tensor.class().into()
+    }
+
+    fn scope(&self) -> Option<Scope> {
+        Some(SCOPE_READ.into())
+    }
+
+    async fn handle_get(self: Box<Self>, txn: &Txn, selector: Value) -> TCResult<State> {
+        let axis = selector.try_cast_into(|v| error::bad_request("Invalid axis", v))?;
+        let tensor = self.tensor.clone().into_view();
+
+        cumulative(txn, tensor, self.op, axis)
+            .await
+            .map(Collection::from)
+            .map(State::Collection)
+    }
+}
+
 struct GetHandler<'a, T: TensorInstance, R: Fn(&T, &Txn, Value) -> TCResult<State> + Send + Sync> {
     tensor: &'a T,
     read_fn: R,
@@ -95,6 +185,285 @@ impl<'a, T: TensorInstance, R: Fn(&T, &Txn, Value) -> TCResult<State> + Send + S
     }
 }
 
+struct MaskedSelectHandler<'a, T: TensorInstance> {
+    tensor: &'a T,
+}
+
+#[async_trait]
+impl<'a, T: TensorInstance> Handler for MaskedSelectHandler<'a, T> {
+    fn subject(&self) -> TCType {
+        self.tensor.class().into()
+    }
+
+    fn scope(&self) -> Option<Scope> {
+        Some(SCOPE_READ.into())
+    }
+
+    async fn handle_post(
+        self: Box<Self>,
+        request: &Request,
+        txn: &Txn,
+        mut params: Map<Scalar>,
+    ) -> TCResult<State> {
+        let mask = operand(request, txn, self.tensor, &mut params, "mask").await?;
+
+        if !params.is_empty() {
+            return Err(error::bad_request(
+                "Unrecognized parameters",
+                Scalar::from_iter(params.into_inner()),
+            ));
+        }
+
+        let tensor = self.tensor.clone().into_view();
+        masked_select(txn, tensor, mask)
+            .await
+            .map(Collection::from)
+            .map(State::Collection)
+    }
+}
+
+struct MatrixHandler<'a, T: TensorInstance> {
+    tensor: &'a T,
+    op: &'static str,
+}
+
+#[async_trait]
+impl<'a, T: TensorInstance> Handler for MatrixHandler<'a, T> {
+    fn subject(&self) -> TCType {
+        self.tensor.class().into()
+    }
+
+    fn scope(&self) -> Option<Scope> {
+        Some(SCOPE_READ.into())
+    }
+
+    async fn handle_get(self: Box<Self>, txn: &Txn, selector: Value) -> TCResult<State> {
+        if !selector.is_none() {
+            return Err(error::bad_request(
+                format!("Tensor::{} takes no parameters, found", self.op),
+                selector,
+            ));
+        }
+
+        let tensor = self.tensor.clone().into_view();
+        match self.op {
+            "diag" => diag(txn, tensor)
+                .await
+                .map(Collection::from)
+                .map(State::Collection),
+            "diagonal" => diagonal(txn, tensor)
+                .await
+                .map(Collection::from)
+                .map(State::Collection),
+            "trace" => trace(txn, tensor).await.map(Value::from).map(State::from),
+            other => Err(error::not_found(other)),
+        }
+    }
+}
+
+struct NonzeroHandler<'a, T: TensorInstance> {
+    tensor: &'a T,
+}
+
+#[async_trait]
+impl<'a, T: TensorInstance> Handler for NonzeroHandler<'a, T> {
+    fn subject(&self) -> TCType {
+        self.tensor.class().into()
+    }
+
+    fn scope(&self) -> Option<Scope> {
+        Some(SCOPE_READ.into())
+    }
+
+    async fn handle_get(self: Box<Self>, txn: &Txn, selector: Value) -> TCResult<State> {
+        if !selector.is_none() {
+            return Err(error::bad_request(
+                "Tensor::nonzero takes no parameters, found",
+                selector,
+            ));
+        }
+
+        let tensor = self.tensor.clone().into_view();
+        nonzero(txn, tensor)
+            .await
+            .map(Collection::from)
+            .map(State::Collection)
+    }
+}
+
+struct ReduceHandler<'a, T: TensorInstance> {
+    tensor: &'a T,
+    op: &'static str,
+}
+
+impl<'a, T: TensorInstance> ReduceHandler<'a, T> {
+    async fn reduce(&self, txn: &Txn, axis: Option<usize>, policy: NanPolicy) -> TCResult<State> {
+        let tensor = self.tensor.clone().into_view();
+
+        match (self.op, axis) {
+            ("product", None) => product_all(txn, tensor, policy)
+                .await
+                .map(Value::from)
+                .map(State::from),
+            ("product", Some(axis)) => product_axis(txn, tensor, axis, policy)
+                .await
+                .map(Collection::from)
+                .map(State::Collection),
+            ("sum", None) => sum_all(txn, tensor, policy)
+                .await
+                .map(Value::from)
+                .map(State::from),
+            ("sum", Some(axis)) => sum_axis(txn, tensor, axis, policy)
+                .await
+                .map(Collection::from)
+                .map(State::Collection),
+            (other, _) => Err(error::not_found(other)),
+        }
+    }
+}
+
+#[async_trait]
+impl<'a, T: TensorInstance> Handler for ReduceHandler<'a, T> {
+    fn subject(&self) -> TCType {
+        self.tensor.class().into()
+    }
+
+    fn scope(&self) -> Option<Scope> {
+        Some(SCOPE_READ.into())
+    }
+
+    async fn handle_get(self: Box<Self>, txn: &Txn, selector: Value) -> TCResult<State> {
+        let axis = if selector.is_none() {
+            None
+        } else {
+            let axis = selector.try_cast_into(|v| error::bad_request("Invalid axis", v))?;
+            Some(axis)
+        };
+
+        self.reduce(txn, axis, NanPolicy::default()).await
+    }
+
+    async fn handle_post(
+        self: Box<Self>,
+        _request: &Request,
+        txn: &Txn,
+        mut params: Map<Scalar>,
+    ) -> TCResult<State> {
+        let axis = match params.remove(&label("axis").into()) {
+            Some(axis) => {
+                let axis = Value::try_cast_from(axis, |v| error::bad_request("Invalid axis", v))?
+                    .try_cast_into(|v| error::bad_request("Invalid axis", v))?;
+                Some(axis)
+            }
+            None => None,
+        };
+
+        let policy = nan_policy(&mut params)?;
+
+        if !params.is_empty() {
+            return Err(error::bad_request(
+                "Unrecognized parameters",
+                Scalar::from_iter(params.into_inner()),
+            ));
+        }
+
+        self.reduce(txn, axis, policy).await
+    }
+}
+
+struct RollingHandler<'a, T: TensorInstance> {
+    tensor: &'a T,
+}
+
+#[async_trait]
+impl<'a, T: TensorInstance> Handler for RollingHandler<'a, T> {
+    fn subject(&self) -> TCType {
+        self.tensor.class().into()
+    }
+
+    fn scope(&self) -> Option<Scope> {
+        Some(SCOPE_READ.into())
+    }
+
+    async fn handle_post(
+        self: Box<Self>,
+        _request: &Request,
+        txn: &Txn,
+        mut params: Map<Scalar>,
+    ) -> TCResult<State> {
+        let window = params
+            .remove(&label("window").into())
+            .ok_or(error::bad_request("Missing parameter", "window"))?;
+        let window = Value::try_cast_from(window, |v| error::bad_request("Invalid window", v))?
+            .try_cast_into(|v| error::bad_request("Invalid window", v))?;
+
+        let axis = params
+            .remove(&label("axis").into())
+            .ok_or(error::bad_request("Missing parameter", "axis"))?;
+        let axis = Value::try_cast_from(axis, |v| error::bad_request("Invalid axis", v))?
+            .try_cast_into(|v| error::bad_request("Invalid axis", v))?;
+
+        let op: RollingOp = match params.remove(&label("op").into()) {
+            Some(op) => {
+                let op: Id = op.try_cast_into(|v| error::bad_request("Invalid rolling op", v))?;
+                op.as_str().parse()?
+            }
+            None => RollingOp::Sum,
+        };
+
+        if !params.is_empty() {
+            return Err(error::bad_request(
+                "Unrecognized parameters",
+                Scalar::from_iter(params.into_inner()),
+            ));
+        }
+
+        let tensor = self.tensor.clone().into_view();
+        rolling(txn, tensor, window, op, axis)
+            .await
+            .map(Collection::from)
+            .map(State::Collection)
+    }
+}
+
+struct SelectHandler<'a, T: TensorInstance> {
+    tensor: &'a T,
+}
+
+#[async_trait]
+impl<'a, T: TensorInstance> Handler for SelectHandler<'a, T> {
+    fn subject(&self) -> TCType {
+        self.tensor.class().into()
+    }
+
+    fn scope(&self) -> Option<Scope> {
+        Some(SCOPE_READ.into())
+    }
+
+    async fn handle_post(
+        self: Box<Self>,
+        request: &Request,
+        txn: &Txn,
+        mut params: Map<Scalar>,
+    ) -> TCResult<State> {
+        let left = operand(request, txn, self.tensor, &mut params, "left").await?;
+        let right = operand(request, txn, self.tensor, &mut params, "right").await?;
+
+        if !params.is_empty() {
+            return Err(error::bad_request(
+                "Unrecognized parameters",
+                Scalar::from_iter(params.into_inner()),
+            ));
+        }
+
+        let cond = self.tensor.clone().into_view();
+        select(txn, cond, left, right)
+            .await
+            .map(Collection::from)
+            .map(State::Collection)
+    }
+}
+
 struct SliceHandler<'a, T: TensorInstance> {
     tensor: &'a T,
 }
@@ -195,11 +564,107 @@ impl<'a, T: TensorInstance + TensorDualIO<Tensor>> Handler for WriteHandler<'a,
     }
 }
 
+struct UnavailableHandler {
+    subject: TCType,
+}
+
+#[async_trait]
+impl Handler for UnavailableHandler {
+    fn subject(&self) -> TCType {
+        self.subject.clone()
+    }
+
+    async fn handle_get(self: Box<Self>, _txn: &Txn, _selector: Value) -> TCResult<State> {
+        backend::check().map(|()| State::from(Value::None))
+    }
+
+    async fn handle_put(
+        self: Box<Self>,
+        _request: &Request,
+        _txn: &Txn,
+        _selector: Value,
+        _value: State,
+    ) -> TCResult<()> {
+        backend::check()
+    }
+
+    async fn handle_post(
+        self: Box<Self>,
+        _request: &Request,
+        _txn: &Txn,
+        _params: Map<Scalar>,
+    ) -> TCResult<State> {
+        backend::check().map(|()| State::from(Value::None))
+    }
+
+    async fn handle_delete(self: Box<Self>, _txn: &Txn, _selector: Value) -> TCResult<()> {
+        backend::check()
+    }
+}
+
+// Resolve the tensor operand `name` of a POST op, which is either a constant Number, broadcast
+// to the shape of `tensor`, or the link to a Tensor.
+async fn operand<T: TensorInstance>(
+    request: &Request,
+    txn: &Txn,
+    tensor: &T,
+    params: &mut Map<Scalar>,
+    name: &'static str,
+) -> TCResult<Tensor> {
+    let operand = params
+        .remove(&label(name).into())
+        .ok_or(error::bad_request("Missing parameter", name))?;
+
+    match operand {
+        Scalar::Value(Value::Number(number)) => {
+            let constant = dense_constant(txn, tensor.shape().clone(), number).await?;
+            Ok(Tensor::from(constant))
+        }
+        other => {
+            let link: Link = other.try_cast_into(|s| {
+                error::bad_request("Expected a Number or a link to a Tensor, not", s)
+            })?;
+
+            match txn.gateway().get(request, txn, &link, Value::None).await? {
+                State::Collection(collection) => Tensor::try_from(collection),
+                other => Err(error::bad_request("Expected Tensor but found", other)),
+            }
+        }
+    }
+}
+
+fn nan_policy(params: &mut Map<Scalar>) -> TCResult<NanPolicy> {
+    match params.remove(&label("nan_policy").into()) {
+        Some(policy) => {
+            let policy: Id =
+                policy.try_cast_into(|v| error::bad_request("Invalid nan_policy", v))?;
+            policy.as_str().parse()
+        }
+        None => Ok(NanPolicy::default()),
+    }
+}
+
+fn tolerance(params: &mut Map<Scalar>, name: &'static str, default: f64) -> TCResult<f64> {
+    match params.remove(&label(name).into()) {
+        Some(Scalar::Value(Value::Number(tolerance))) => {
+            let tolerance: Float = tolerance.cast_into();
+            Ok(tolerance.into())
+        }
+        Some(other) => Err(error::bad_request(format!("Invalid {}", name), other)),
+        None => Ok(default),
+    }
+}
+
 pub fn route<'a, T: TensorInstance + TensorDualIO<Tensor>>(
     tensor: &'a T,
     method: MethodType,
     path: &'_ [PathSegment],
 ) -> Option<Box<dyn Handler + 'a>> {
+    if backend::check().is_err() {
+        let subject = tensor.class().into();
+        return Some(Box::new(UnavailableHandler { subject }));
+    }
+
     if path.is_empty() {
         let handler: Box<dyn Handler> = match method {
             MethodType::Get => Box::new(SliceHandler { tensor }),
@@ -211,6 +676,10 @@ pub fn route<'a, T: TensorInstance + TensorDualIO<Tensor>>(
     } else if path.len() == 1 {
         let handler: Box<dyn Handler> = match path[0].as_str() {
             "all" => Box::new(AllHandler { tensor }),
+            "allclose" => Box::new(CompareHandler {
+                tensor,
+                op: "allclose",
+            }),
             "any" => Box::new(AnyHandler { tensor }),
             "as_type" => Box::new(GetHandler {
                 tensor,
@@ -238,6 +707,23 @@ pub fn route<'a, T: TensorInstance + TensorDualIO<Tensor>>(
                         .map(State::Collection)
                 },
             }),
+            "cumprod" => Box::new(CumulativeHandler {
+                tensor,
+                op: CumulativeOp::Product,
+            }),
+            "cumsum" => Box::new(CumulativeHandler {
+                tensor,
+                op: CumulativeOp::Sum,
+            }),
+            "diag" => Box::new(MatrixHandler { tensor, op: "diag" }),
+            "diagonal" => Box::new(MatrixHandler {
+                tensor,
+                op: "diagonal",
+            }),
+            "equals" => Box::new(CompareHandler {
+                tensor,
+                op: "equals",
+            }),
             "expand_dims" => Box::new(GetHandler {
                 tensor,
                 read_fn: |tensor, _txn, selector| {
@@ -250,6 +736,53 @@ pub fn route<'a, T: TensorInstance + TensorDualIO<Tensor>>(
                         .map(State::Collection)
                 },
             }),
+            "isinf" => Box::new(GetHandler {
+                tensor,
+                read_fn: |tensor, _txn, selector| {
+                    if selector.is_none() {
+                        isinf(tensor.clone().into_view())
+                            .map(Collection::from)
+                            .map(State::Collection)
+                    } else {
+                        Err(error::bad_request(
+                            "Tensor::isinf takes no parameters, found",
+                            selector,
+                        ))
+                    }
+                },
+            }),
+            "isnan" => Box::new(GetHandler {
+                tensor,
+                read_fn: |tensor, _txn, selector| {
+                    if selector.is_none() {
+                        isnan(tensor.clone().into_view())
+                            .map(Collection::from)
+                            .map(State::Collection)
+                    } else {
+                        Err(error::bad_request(
+                            "Tensor::isnan takes no parameters, found",
+                            selector,
+                        ))
+                    }
+                },
+            }),
+            "masked_select" => Box::new(MaskedSelectHandler { tensor }),
+            "nan_to_num" => Box::new(GetHandler {
+                tensor,
+                read_fn: |tensor, _txn, selector| {
+                    if selector.is_none() {
+                        nan_to_num(tensor.clone().into_view())
+                            .map(Collection::from)
+                            .map(State::Collection)
+                    } else {
+                        Err(error::bad_request(
+                            "Tensor::nan_to_num takes no parameters, found",
+                            selector,
+                        ))
+                    }
+                },
+            }),
+            "nonzero" => Box::new(NonzeroHandler { tensor }),
             "not" => Box::new(GetHandler {
                 tensor,
                 read_fn: |tensor, _txn, selector| {
@@ -267,7 +800,17 @@ pub fn route<'a, T: TensorInstance + TensorDualIO<Tensor>>(
                     }
                 },
             }),
+            "product" => Box::new(ReduceHandler {
+                tensor,
+                op: "product",
+            }),
+            "rolling" => Box::new(RollingHandler { tensor }),
             "slice" => Box::new(SliceHandler { tensor }),
+            "sum" => Box::new(ReduceHandler { tensor, op: "sum" }),
+            "trace" => Box::new(MatrixHandler {
+                tensor,
+                op: "trace",
+            }),
             "transpose" => Box::new(GetHandler {
                 tensor,
                 read_fn: |tensor, _txn, selector| {
@@ -287,6 +830,7 @@ pub fn route<'a, T: TensorInstance + TensorDualIO<Tensor>>(
                         .map(State::Collection)
                 },
             }),
+            "where" => Box::new(SelectHandler { tensor }),
             _ => return None,
         };
 
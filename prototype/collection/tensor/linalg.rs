@@ -0,0 +1,174 @@
+use std::iter;
+
+use futures::future;
+use futures::stream::{self, Stream, StreamExt, TryStreamExt};
+
+use crate::error;
+use crate::scalar::number::*;
+use crate::transaction::Txn;
+use crate::TCResult;
+
+use super::dense::{BlockListFile, DenseAccessor, PER_BLOCK};
+use super::{Array, DenseTensor, Tensor, TensorAccess, TensorInstance};
+
+/// Construct a new dense `n`x`n` identity matrix.
+pub async fn eye(txn: &Txn, n: u64, dtype: NumberType) -> TCResult<Tensor> {
+    let one = dtype.one();
+    let zero = dtype.zero();
+    let values = stream::iter((0..(n * n)).map(
+        move |offset| {
+            if on_diagonal(offset, n, n) {
+                one
+            } else {
+                zero
+            }
+        },
+    ));
+
+    let file = BlockListFile::from_values(txn, vec![n, n].into(), dtype, values).await?;
+    Ok(DenseTensor::from(file).into())
+}
+
+/// Construct a new dense square matrix whose diagonal is the given vector, and which is
+/// zero elsewhere.
+pub async fn diag(txn: &Txn, vector: Tensor) -> TCResult<Tensor> {
+    if vector.ndim() != 1 {
+        return Err(error::bad_request(
+            "diag requires a vector, not a tensor with shape",
+            vector.shape(),
+        ));
+    }
+
+    let n = vector.shape()[0];
+    let dtype = vector.dtype();
+    let zero = dtype.zero();
+
+    let vector = into_dense(vector)?;
+    let values = vector
+        .value_stream(txn)
+        .await?
+        .enumerate()
+        .map(move |(i, value)| {
+            value.map(|value| stream::iter(diag_row(i as u64, n, value, zero).map(TCResult::Ok)))
+        })
+        .try_flatten()
+        .chunks(PER_BLOCK)
+        .map(|values| values.into_iter().collect::<TCResult<Vec<Number>>>())
+        .and_then(move |values| future::ready(Array::cast_from_values(values, dtype)));
+
+    let file = BlockListFile::from_blocks(txn, vec![n, n].into(), dtype, Box::pin(values)).await?;
+    Ok(DenseTensor::from(file).into())
+}
+
+/// Return the main diagonal of the given matrix, as a new dense vector.
+pub async fn diagonal(txn: &Txn, matrix: Tensor) -> TCResult<Tensor> {
+    let (len, dtype) = (diagonal_len(&matrix)?, matrix.dtype());
+    let source = into_dense(matrix)?;
+    let values = diagonal_values(txn, &source).await?;
+    let values = values
+        .chunks(PER_BLOCK)
+        .map(|values| values.into_iter().collect::<TCResult<Vec<Number>>>())
+        .and_then(move |values| future::ready(Array::cast_from_values(values, dtype)));
+
+    let file = BlockListFile::from_blocks(txn, vec![len].into(), dtype, Box::pin(values)).await?;
+    Ok(DenseTensor::from(file).into())
+}
+
+/// Return the sum of the main diagonal of the given matrix.
+pub async fn trace(txn: &Txn, matrix: Tensor) -> TCResult<Number> {
+    diagonal_len(&matrix)?;
+
+    let zero = matrix.dtype().zero();
+    let source = into_dense(matrix)?;
+    diagonal_values(txn, &source)
+        .await?
+        .try_fold(zero, |sum, value| future::ready(Ok(sum + value)))
+        .await
+}
+
+async fn diagonal_values<'a>(
+    txn: &'a Txn,
+    source: &'a DenseTensor<DenseAccessor>,
+) -> TCResult<impl Stream<Item = TCResult<Number>> + Send + Unpin + 'a> {
+    let shape = source.shape();
+    let (rows, columns) = (shape[0], shape[1]);
+    let len = rows.min(columns);
+
+    let values = source
+        .value_stream(txn)
+        .await?
+        .enumerate()
+        .filter(move |(offset, _)| future::ready(on_diagonal(*offset as u64, columns, len)))
+        .map(|(_, value)| value);
+
+    Ok(values)
+}
+
+// the `i`th row of an `n`x`n` matrix whose only nonzero element is `value`, on its diagonal
+fn diag_row(i: u64, n: u64, value: Number, zero: Number) -> impl Iterator<Item = Number> {
+    iter::repeat(zero)
+        .take(i as usize)
+        .chain(iter::once(value))
+        .chain(iter::repeat(zero).take((n - i - 1) as usize))
+}
+
+// whether the element at `offset` of a row-major matrix with `columns` columns is one of the
+// first `len` elements of its main diagonal
+fn on_diagonal(offset: u64, columns: u64, len: u64) -> bool {
+    offset % (columns + 1) == 0 && offset / (columns + 1) < len
+}
+
+fn diagonal_len(matrix: &Tensor) -> TCResult<u64> {
+    if matrix.ndim() == 2 {
+        let shape = matrix.shape();
+        Ok(shape[0].min(shape[1]))
+    } else {
+        Err(error::bad_request(
+            "Expected a matrix, not a tensor with shape",
+            matrix.shape(),
+        ))
+    }
+}
+
+fn into_dense(tensor: Tensor) -> TCResult<DenseTensor<DenseAccessor>> {
+    match tensor.into_dense() {
+        Tensor::Dense(dense) => Ok(dense),
+        Tensor::Sparse(_) => Err(error::internal("Expected a dense tensor")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_on_diagonal() {
+        for (rows, columns) in [(3, 3), (4, 2), (2, 5), (1, 1), (1, 4), (5, 1)].iter() {
+            let len = rows.min(columns);
+            let diagonal: Vec<u64> = (0..rows * columns)
+                .filter(|offset| on_diagonal(*offset, *columns, *len))
+                .collect();
+
+            let expected: Vec<u64> = (0..*len).map(|i| i * columns + i).collect();
+            assert_eq!(diagonal, expected, "{}x{} matrix", rows, columns);
+        }
+    }
+
+    #[test]
+    fn test_diag_row() {
+        let zero = NumberType::uint64().zero();
+        let value = Number::from(7u64);
+
+        let rows: Vec<Vec<Number>> = (0..3)
+            .map(|i| diag_row(i, 3, value, zero).collect())
+            .collect();
+        assert_eq!(rows[0], vec![value, zero, zero]);
+        assert_eq!(rows[1], vec![zero, value, zero]);
+        assert_eq!(rows[2], vec![zero, zero, value]);
+
+        assert_eq!(
+            diag_row(0, 1, value, zero).collect::<Vec<Number>>(),
+            vec![value]
+        );
+    }
+}
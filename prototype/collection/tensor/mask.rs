@@ -0,0 +1,210 @@
+use futures::future;
+use futures::stream::{self, Stream, StreamExt, TryStreamExt};
+use futures::try_join;
+use log::debug;
+
+use crate::error;
+use crate::scalar::number::*;
+use crate::transaction::Txn;
+use crate::TCResult;
+
+use super::dense::{BlockListFile, DenseAccess, DenseAccessor, PER_BLOCK};
+use super::stream::coord_bounds;
+use super::{broadcast, Array, DenseTensor, Tensor, TensorAccess, TensorInstance};
+
+/// Return the coordinates of the nonzero elements of `tensor`, as a `UInt64` tensor with shape
+/// `[num_nonzero, ndim]`, in row-major order.
+///
+/// The source is read twice, one block at a time: once to count the nonzero elements, and once
+/// to compute their coordinates.
+pub async fn nonzero(txn: &Txn, tensor: Tensor) -> TCResult<Tensor> {
+    let ndim = tensor.ndim();
+    let shape = tensor.shape().clone();
+    let strides = coord_bounds(&shape);
+    let source = into_blocks(tensor)?;
+
+    let count = count_nonzero(txn, &source).await?;
+    debug!(
+        "{} nonzero elements in a tensor of {} dimensions",
+        count, ndim
+    );
+
+    let mut block_offset = 0u64;
+    let coords = source.block_stream(txn).await?.map_ok(move |block| {
+        let coords = nonzero_coords(&block, block_offset, &strides, &shape);
+        block_offset += block.len() as u64;
+        Array::from(coords)
+    });
+
+    let dtype = NumberType::uint64();
+    let output_shape = vec![count, ndim as u64].into();
+    let coords = rechunk(coords, dtype);
+    let output = BlockListFile::from_blocks(txn, output_shape, dtype, Box::pin(coords)).await?;
+    Ok(DenseTensor::from(output).into())
+}
+
+/// Return the elements of `tensor` where `mask` is true, as a one-dimensional tensor in
+/// row-major order. The mask is broadcast to the shape of `tensor`, or vice versa.
+pub async fn masked_select(txn: &Txn, tensor: Tensor, mask: Tensor) -> TCResult<Tensor> {
+    let dtype = tensor.dtype();
+    let (tensor, mask) = broadcast(&tensor, &mask)?;
+    let source = into_blocks(tensor)?;
+    let mask = into_blocks(mask)?;
+
+    let count = count_nonzero(txn, &mask).await?;
+    debug!("select {} elements of a tensor by mask", count);
+
+    let (source_blocks, mask_blocks) = try_join!(source.block_stream(txn), mask.block_stream(txn))?;
+
+    let values = source_blocks
+        .zip(mask_blocks)
+        .map(|(block, mask)| Ok((block?, mask?)))
+        .and_then(move |(block, mask)| future::ready(select_nonzero(&block, &mask, dtype)));
+
+    let shape = vec![count].into();
+    let values = rechunk(values, dtype);
+    let output = BlockListFile::from_blocks(txn, shape, dtype, Box::pin(values)).await?;
+    Ok(DenseTensor::from(output).into())
+}
+
+async fn count_nonzero(txn: &Txn, source: &DenseAccessor) -> TCResult<u64> {
+    source
+        .block_stream(txn)
+        .await?
+        .map_ok(|block| block.nonzero().elements() as u64)
+        .try_fold(0, |count, block_count| {
+            future::ready(Ok(count + block_count))
+        })
+        .await
+}
+
+// the flattened coordinates of the nonzero elements of a block which begins at `block_offset`
+fn nonzero_coords(block: &Array, block_offset: u64, strides: &[u64], shape: &[u64]) -> Vec<u64> {
+    let offsets = block.nonzero();
+    let mut host = vec![0u64; offsets.elements()];
+    offsets.host(&mut host);
+
+    let mut coords = Vec::with_capacity(host.len() * shape.len());
+    for offset in host {
+        let offset = block_offset + offset;
+        coords.extend(
+            strides
+                .iter()
+                .zip(shape.iter())
+                .map(|(stride, dim)| (offset / stride) % dim),
+        );
+    }
+
+    coords
+}
+
+fn select_nonzero(block: &Array, mask: &Array, dtype: NumberType) -> TCResult<Array> {
+    let offsets = mask.nonzero();
+    if offsets.elements() == 0 {
+        Array::cast_from_values(vec![], dtype)
+    } else {
+        Ok(block.get(offsets))
+    }
+}
+
+// the selected elements of each source block are a block of arbitrary length,
+// so they have to be re-chunked into blocks of the standard length before they're stored
+fn rechunk<S: Stream<Item = TCResult<Array>>>(
+    blocks: S,
+    dtype: NumberType,
+) -> impl Stream<Item = TCResult<Array>> {
+    blocks
+        .map_ok(|block| stream::iter(block.into_values().into_iter().map(TCResult::Ok)))
+        .try_flatten()
+        .chunks(PER_BLOCK)
+        .map(|values| values.into_iter().collect::<TCResult<Vec<Number>>>())
+        .and_then(move |values| future::ready(Array::cast_from_values(values, dtype)))
+}
+
+fn into_blocks(tensor: Tensor) -> TCResult<DenseAccessor> {
+    match tensor.into_dense() {
+        Tensor::Dense(dense) => Ok(dense.into_inner()),
+        Tensor::Sparse(_) => Err(error::internal("Expected a dense tensor")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn uints(values: &[u64]) -> Vec<Number> {
+        values.iter().copied().map(Number::from).collect()
+    }
+
+    #[test]
+    fn test_nonzero_coords() {
+        let shape = vec![2, 2, 3];
+        let strides = coord_bounds(&shape.to_vec().into());
+        assert_eq!(strides, vec![6, 3, 1]);
+
+        // the elements at offsets 1, 5 and 6 of a tensor with shape [2, 2, 3]
+        let first = Array::from(vec![0u64, 4, 0, 0, 0, 1, 2]);
+        let second = Array::from(vec![0u64, 0, 0, 0, 0]);
+
+        let coords = nonzero_coords(&first, 0, &strides, &shape);
+        assert_eq!(coords, vec![0, 0, 1, 0, 1, 2, 1, 0, 0]);
+
+        assert!(nonzero_coords(&second, 7, &strides, &shape).is_empty());
+
+        // the last element, at offset 11 of the tensor and 4 of its second block
+        let second = Array::from(vec![false, false, false, false, true]);
+        let coords = nonzero_coords(&second, 7, &strides, &shape);
+        assert_eq!(coords, vec![1, 1, 2]);
+    }
+
+    #[test]
+    fn test_select_nonzero() {
+        let block = Array::from(vec![1.5f32, 2.5, 3.5, 4.5]);
+
+        let mask = Array::from(vec![true, false, false, true]);
+        let selected = select_nonzero(&block, &mask, block.dtype()).unwrap();
+        assert_eq!(
+            selected.into_values(),
+            Array::from(vec![1.5f32, 4.5]).into_values()
+        );
+
+        let mask = Array::from(vec![false; 4]);
+        let selected = select_nonzero(&block, &mask, block.dtype()).unwrap();
+        assert_eq!(selected.len(), 0);
+        assert!(selected.dtype() == block.dtype());
+    }
+
+    #[tokio::test]
+    async fn test_rechunk() {
+        let dtype = NumberType::uint64();
+        let blocks: Vec<TCResult<Array>> = vec![
+            Ok(Array::from(vec![1u64, 2, 3])),
+            Array::cast_from_values(vec![], dtype),
+            Ok(Array::from(vec![7u64; PER_BLOCK])),
+            Ok(Array::from(vec![4u64, 5])),
+        ];
+
+        let chunks: Vec<Array> = rechunk(stream::iter(blocks), dtype)
+            .try_collect()
+            .await
+            .unwrap();
+
+        let lens: Vec<usize> = chunks.iter().map(|chunk| chunk.len()).collect();
+        assert_eq!(lens, vec![PER_BLOCK, 5]);
+        assert!(chunks.iter().all(|chunk| chunk.dtype() == dtype));
+
+        let mut expected = uints(&[1, 2, 3]);
+        expected.extend(vec![Number::from(7u64); PER_BLOCK]);
+        expected.extend(uints(&[4, 5]));
+
+        let values: Vec<Number> = chunks.into_iter().flat_map(Array::into_values).collect();
+        assert_eq!(values, expected);
+
+        let empty: Vec<TCResult<Array>> = vec![];
+        let chunks: Vec<Array> = rechunk(stream::iter(empty), dtype)
+            .try_collect()
+            .await
+            .unwrap();
+        assert!(chunks.is_empty());
+    }
+}
@@ -5,11 +5,19 @@ use crate::scalar::value::number::*;
 use crate::transaction::{Txn, TxnId};
 use crate::{TCBoxTryFuture, TCResult};
 
+mod compare;
+mod cumulative;
 mod einsum;
 mod handlers;
+mod linalg;
+mod mask;
+mod nan;
+mod rolling;
+mod select;
 mod stream;
 mod transform;
 
+pub mod backend;
 pub mod bounds;
 pub mod class;
 pub mod dense;
@@ -17,8 +25,15 @@ pub mod sparse;
 
 pub use bounds::*;
 pub use class::{Tensor, TensorInstance, TensorType};
+pub use compare::{allclose, equals, DEFAULT_ATOL, DEFAULT_RTOL};
+pub use cumulative::{cumulative, CumulativeOp};
 pub use dense::{from_sparse, Array, DenseTensor};
 pub use einsum::einsum;
+pub use linalg::{diag, diagonal, eye, trace};
+pub use mask::{masked_select, nonzero};
+pub use nan::{isinf, isnan, nan_to_num, product_all, product_axis, sum_all, sum_axis, NanPolicy};
+pub use rolling::{rolling, RollingOp};
+pub use select::select;
 pub use sparse::{from_dense, SparseTensor};
 
 pub type Coord = Vec<u64>;
@@ -148,8 +163,16 @@ fn broadcast<L: Clone + TensorTransform, R: Clone + TensorTransform>(
     <L as TensorTransform>::Broadcast,
     <R as TensorTransform>::Broadcast,
 )> {
-    let mut left_shape = left.shape().to_vec();
-    let mut right_shape = right.shape().to_vec();
+    let shape = broadcast_shape(left.shape(), right.shape())?;
+    let left = left.broadcast(shape.to_vec().into())?;
+    let right = right.broadcast(shape.into())?;
+    Ok((left, right))
+}
+
+/// The shape to which tensors with the given shapes broadcast, if they are compatible.
+fn broadcast_shape(left: &[u64], right: &[u64]) -> TCResult<Vec<u64>> {
+    let mut left_shape = left.to_vec();
+    let mut right_shape = right.to_vec();
 
     match (left_shape.len(), right_shape.len()) {
         (l, r) if l < r => {
@@ -178,7 +201,47 @@ fn broadcast<L: Clone + TensorTransform, R: Clone + TensorTransform>(
             ));
         }
     }
-    let left = left.broadcast(shape.to_vec().into())?;
-    let right = right.broadcast(shape.into())?;
-    Ok((left, right))
+
+    Ok(shape)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_broadcast_shape() {
+        assert_eq!(broadcast_shape(&[2, 3], &[2, 3]).unwrap(), vec![2, 3]);
+        assert_eq!(broadcast_shape(&[2, 1], &[1, 3]).unwrap(), vec![2, 3]);
+        assert_eq!(broadcast_shape(&[3], &[4, 2, 3]).unwrap(), vec![4, 2, 3]);
+        assert_eq!(broadcast_shape(&[4, 1, 3], &[2, 1]).unwrap(), vec![4, 2, 3]);
+        assert_eq!(broadcast_shape(&[1], &[5]).unwrap(), vec![5]);
+
+        assert!(broadcast_shape(&[2, 3], &[3, 2]).is_err());
+        assert!(broadcast_shape(&[4], &[2, 3]).is_err());
+    }
+
+    #[test]
+    fn test_broadcast_three() {
+        // select broadcasts its condition with the left operand, then the left with the right,
+        // then the condition with the right, so all three end up with the same shape
+        let cases: [(&[u64], &[u64], &[u64], &[u64]); 4] = [
+            (&[3, 1], &[1, 4], &[2, 1, 1], &[2, 3, 4]),
+            (&[1], &[1], &[5], &[5]),
+            (&[5], &[2, 1], &[1], &[2, 5]),
+            (&[2, 3], &[2, 3], &[2, 3], &[2, 3]),
+        ];
+
+        for (cond, left, right, expected) in cases.iter() {
+            let cond_left = broadcast_shape(cond, left).unwrap();
+            let left_right = broadcast_shape(&cond_left, right).unwrap();
+            let cond_right = broadcast_shape(&cond_left, &left_right).unwrap();
+
+            assert_eq!(left_right, expected.to_vec());
+            assert_eq!(cond_right, expected.to_vec());
+        }
+
+        let cond_left = broadcast_shape(&[2], &[2]).unwrap();
+        assert!(broadcast_shape(&cond_left, &[3]).is_err());
+    }
 }
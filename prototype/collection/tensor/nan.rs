@@ -0,0 +1,369 @@
+use std::fmt;
+use std::str::FromStr;
+
+use futures::future;
+use futures::stream::TryStreamExt;
+
+use crate::error;
+use crate::scalar::number::*;
+use crate::transaction::Txn;
+use crate::TCResult;
+
+use super::dense::{dense_constant, BlockListUnary, DenseAccess, DenseAccessor};
+use super::select::select;
+use super::{
+    Array, DenseTensor, Tensor, TensorAccess, TensorInstance, TensorReduce, TensorUnary,
+};
+
+/// How a reduction or comparison should treat `NaN` elements.
+#[derive(Clone, Copy, Eq, PartialEq)]
+pub enum NanPolicy {
+    /// `NaN` elements are reduced and compared like any other value, so a `NaN` in the input
+    /// produces a `NaN` sum or product, and is never equal or close to anything.
+    Propagate,
+    /// `NaN` elements are skipped, as if they were not present.
+    Ignore,
+    /// `NaN` elements are a bad request.
+    Error,
+}
+
+impl NanPolicy {
+    // check a single block according to this policy, replacing its NaN elements with `fill`
+    // if they should be ignored
+    fn block(&self, block: Array, fill: Number) -> TCResult<Array> {
+        match self {
+            Self::Propagate => Ok(block),
+            Self::Ignore => {
+                let fill = Array::constant(fill, block.len());
+                Ok(block.is_nan().select(&fill, &block))
+            }
+            Self::Error => check(&block).map(|()| block),
+        }
+    }
+
+    // check a whole tensor according to this policy, replacing its NaN elements with `fill`
+    // if they should be ignored
+    async fn tensor(&self, txn: &Txn, tensor: Tensor, fill: Number) -> TCResult<Tensor> {
+        match self {
+            Self::Propagate => Ok(tensor),
+            Self::Ignore => {
+                let mask = isnan(tensor.clone())?;
+                let fill = dense_constant(txn, tensor.shape().clone(), fill).await?;
+                select(txn, mask, fill.into(), tensor).await
+            }
+            Self::Error => {
+                if isnan(tensor.clone())?.any(txn).await? {
+                    Err(error::bad_request("Found NaN with nan_policy", NanPolicy::Error))
+                } else {
+                    Ok(tensor)
+                }
+            }
+        }
+    }
+
+    /// Check a pair of blocks to compare according to this policy. If `NaN` is to be ignored,
+    /// every position which is `NaN` in either block is set to zero in both.
+    pub(super) fn pair(&self, left: Array, right: Array) -> TCResult<(Array, Array)> {
+        match self {
+            Self::Propagate => Ok((left, right)),
+            Self::Ignore => {
+                let mask = left.is_nan().or(&right.is_nan());
+                let left_zero = Array::constant(left.dtype().zero(), left.len());
+                let right_zero = Array::constant(right.dtype().zero(), right.len());
+                Ok((
+                    mask.select(&left_zero, &left),
+                    mask.select(&right_zero, &right),
+                ))
+            }
+            Self::Error => {
+                check(&left)?;
+                check(&right)?;
+                Ok((left, right))
+            }
+        }
+    }
+}
+
+impl Default for NanPolicy {
+    fn default() -> Self {
+        Self::Propagate
+    }
+}
+
+impl FromStr for NanPolicy {
+    type Err = error::TCError;
+
+    fn from_str(policy: &str) -> TCResult<NanPolicy> {
+        match policy {
+            "propagate" => Ok(Self::Propagate),
+            "ignore" => Ok(Self::Ignore),
+            "error" => Ok(Self::Error),
+            other => Err(error::bad_request("Unsupported nan_policy", other)),
+        }
+    }
+}
+
+impl fmt::Display for NanPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Propagate => write!(f, "propagate"),
+            Self::Ignore => write!(f, "ignore"),
+            Self::Error => write!(f, "error"),
+        }
+    }
+}
+
+/// Return a boolean tensor which is true where `tensor` is `NaN`.
+pub fn isnan(tensor: Tensor) -> TCResult<Tensor> {
+    unary(tensor, Array::is_nan, is_nan, NumberType::Bool)
+}
+
+/// Return a boolean tensor which is true where `tensor` is positive or negative infinity.
+pub fn isinf(tensor: Tensor) -> TCResult<Tensor> {
+    unary(tensor, Array::is_inf, is_inf, NumberType::Bool)
+}
+
+/// Replace the `NaN` elements of `tensor` with zero, and its infinite elements with the largest
+/// or smallest finite value of its data type, like `numpy.nan_to_num`. Only real floating-point
+/// tensors are changed.
+pub fn nan_to_num(tensor: Tensor) -> TCResult<Tensor> {
+    let dtype = tensor.dtype();
+    unary(tensor, Array::nan_to_num, finite, dtype)
+}
+
+/// Return the sum of all the elements of `tensor`, treating `NaN` according to `policy`.
+pub async fn sum_all(txn: &Txn, tensor: Tensor, policy: NanPolicy) -> TCResult<Number> {
+    let zero = tensor.dtype().zero();
+    let source = into_blocks(tensor)?;
+
+    source
+        .block_stream(txn)
+        .await?
+        .and_then(|block| future::ready(policy.block(block, zero)))
+        .try_fold(zero, |sum, block| future::ready(Ok(sum + block.sum())))
+        .await
+}
+
+/// Return the product of all the elements of `tensor`, treating `NaN` according to `policy`.
+pub async fn product_all(txn: &Txn, tensor: Tensor, policy: NanPolicy) -> TCResult<Number> {
+    let one = tensor.dtype().one();
+    let source = into_blocks(tensor)?;
+
+    source
+        .block_stream(txn)
+        .await?
+        .and_then(|block| future::ready(policy.block(block, one)))
+        .try_fold(one, |product, block| {
+            future::ready(Ok(product * block.product()))
+        })
+        .await
+}
+
+/// Sum `tensor` along `axis`, treating `NaN` according to `policy`.
+pub async fn sum_axis(
+    txn: &Txn,
+    tensor: Tensor,
+    axis: usize,
+    policy: NanPolicy,
+) -> TCResult<Tensor> {
+    let zero = tensor.dtype().zero();
+    policy.tensor(txn, tensor, zero).await?.sum(axis)
+}
+
+/// Multiply `tensor` along `axis`, treating `NaN` according to `policy`.
+pub async fn product_axis(
+    txn: &Txn,
+    tensor: Tensor,
+    axis: usize,
+    policy: NanPolicy,
+) -> TCResult<Tensor> {
+    let one = tensor.dtype().one();
+    policy.tensor(txn, tensor, one).await?.product(axis)
+}
+
+fn check(block: &Array) -> TCResult<()> {
+    if block.has_nan() {
+        Err(error::bad_request(
+            "Found NaN with nan_policy",
+            NanPolicy::Error,
+        ))
+    } else {
+        Ok(())
+    }
+}
+
+fn unary(
+    tensor: Tensor,
+    transform: fn(&Array) -> Array,
+    value_transform: fn(Number) -> Number,
+    dtype: NumberType,
+) -> TCResult<Tensor> {
+    let source = into_blocks(tensor)?;
+    let blocks = BlockListUnary::new(source, transform, value_transform, dtype);
+    Ok(DenseTensor::from(blocks).into())
+}
+
+fn is_nan(value: Number) -> Number {
+    let is_nan = match value {
+        Number::Complex(Complex::C32(c)) => c.re.is_nan() || c.im.is_nan(),
+        Number::Complex(Complex::C64(c)) => c.re.is_nan() || c.im.is_nan(),
+        Number::Float(Float::F32(f)) => f.is_nan(),
+        Number::Float(Float::F64(f)) => f.is_nan(),
+        _ => false,
+    };
+
+    is_nan.into()
+}
+
+fn is_inf(value: Number) -> Number {
+    let is_inf = match value {
+        Number::Complex(Complex::C32(c)) => c.re.is_infinite() || c.im.is_infinite(),
+        Number::Complex(Complex::C64(c)) => c.re.is_infinite() || c.im.is_infinite(),
+        Number::Float(Float::F32(f)) => f.is_infinite(),
+        Number::Float(Float::F64(f)) => f.is_infinite(),
+        _ => false,
+    };
+
+    is_inf.into()
+}
+
+fn finite(value: Number) -> Number {
+    match value {
+        Number::Float(Float::F32(f)) if f.is_nan() => Float::F32(0.).into(),
+        Number::Float(Float::F32(f)) if f.is_infinite() => {
+            Float::F32(if f > 0. { f32::MAX } else { f32::MIN }).into()
+        }
+        Number::Float(Float::F64(f)) if f.is_nan() => Float::F64(0.).into(),
+        Number::Float(Float::F64(f)) if f.is_infinite() => {
+            Float::F64(if f > 0. { f64::MAX } else { f64::MIN }).into()
+        }
+        other => other,
+    }
+}
+
+fn into_blocks(tensor: Tensor) -> TCResult<DenseAccessor> {
+    match tensor.into_dense() {
+        Tensor::Dense(dense) => Ok(dense.into_inner()),
+        Tensor::Sparse(_) => Err(error::internal("Expected a dense tensor")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::error::ErrorType;
+
+    use super::*;
+
+    const VALUES: [f64; 5] = [1.5, f64::NAN, f64::INFINITY, f64::NEG_INFINITY, -2.];
+
+    fn floats(values: &[f64]) -> Vec<Number> {
+        values.iter().map(|f| Float::F64(*f).into()).collect()
+    }
+
+    fn bools(values: Vec<Number>) -> Vec<bool> {
+        values
+            .into_iter()
+            .map(|n| n == Number::from(true))
+            .collect()
+    }
+
+    #[test]
+    fn test_parse_policy() {
+        for policy in [NanPolicy::Propagate, NanPolicy::Ignore, NanPolicy::Error].iter() {
+            assert!(policy.to_string().parse::<NanPolicy>().unwrap() == *policy);
+        }
+
+        assert!(NanPolicy::default() == NanPolicy::Propagate);
+
+        let cause = "omit".parse::<NanPolicy>().unwrap_err();
+        assert!(cause.reason() == &ErrorType::BadRequest);
+    }
+
+    #[test]
+    fn test_block() {
+        let block = Array::from(vec![1f64, f64::NAN, 3.]);
+        let zero = Float::F64(0.).into();
+        let one = Float::F64(1.).into();
+
+        let propagated = NanPolicy::Propagate.block(block.clone(), zero).unwrap();
+        assert!(propagated.has_nan());
+
+        let ignored = NanPolicy::Ignore.block(block.clone(), zero).unwrap();
+        assert_eq!(ignored.sum(), Number::from(Float::F64(4.)));
+
+        let ignored = NanPolicy::Ignore.block(block.clone(), one).unwrap();
+        assert_eq!(ignored.product(), Number::from(Float::F64(3.)));
+
+        let cause = NanPolicy::Error.block(block, zero).unwrap_err();
+        assert!(cause.reason() == &ErrorType::BadRequest);
+
+        let finite = Array::from(vec![1f64, 2.]);
+        assert!(NanPolicy::Error.block(finite, zero).is_ok());
+    }
+
+    #[test]
+    fn test_pair() {
+        let left = Array::from(vec![1f64, f64::NAN, 3.]);
+        let right = Array::from(vec![f64::NAN, 2., 3.]);
+
+        let (l, r) = NanPolicy::Ignore.pair(left.clone(), right.clone()).unwrap();
+        assert_eq!(l.into_values(), floats(&[0., 0., 3.]));
+        assert_eq!(r.into_values(), floats(&[0., 0., 3.]));
+
+        let (l, r) = NanPolicy::Propagate
+            .pair(left.clone(), right.clone())
+            .unwrap();
+        assert!(l.has_nan() && r.has_nan());
+
+        assert!(NanPolicy::Error
+            .pair(left.clone(), Array::from(vec![1f64]))
+            .is_err());
+        assert!(NanPolicy::Error
+            .pair(Array::from(vec![1f64]), right)
+            .is_err());
+    }
+
+    // the elementwise transforms of a block must agree with the transforms of a single value
+    #[test]
+    fn test_unary() {
+        let block = Array::from(VALUES.to_vec());
+        let values = floats(&VALUES);
+
+        let expected: Vec<bool> = VALUES.iter().map(|f| f.is_nan()).collect();
+        assert_eq!(bools(block.is_nan().into_values()), expected);
+        assert_eq!(
+            bools(values.iter().copied().map(is_nan).collect()),
+            expected
+        );
+
+        let expected: Vec<bool> = VALUES.iter().map(|f| f.is_infinite()).collect();
+        assert_eq!(bools(block.is_inf().into_values()), expected);
+        assert_eq!(
+            bools(values.iter().copied().map(is_inf).collect()),
+            expected
+        );
+
+        let expected = floats(&[1.5, 0., f64::MAX, f64::MIN, -2.]);
+        assert_eq!(block.nan_to_num().into_values(), expected);
+        assert_eq!(
+            values.into_iter().map(finite).collect::<Vec<Number>>(),
+            expected
+        );
+    }
+
+    #[test]
+    fn test_unary_types() {
+        let nan = Number::from(Complex::C64(num::Complex::new(1., f64::NAN)));
+        assert_eq!(is_nan(nan), Number::from(true));
+        assert_eq!(is_inf(nan), Number::from(false));
+
+        let inf = Number::from(Complex::C32(num::Complex::new(f32::NEG_INFINITY, 0.)));
+        assert_eq!(is_inf(inf), Number::from(true));
+
+        assert_eq!(is_nan(Number::from(3u64)), Number::from(false));
+        assert_eq!(finite(Number::from(3u64)), Number::from(3u64));
+
+        let inf = Number::from(Float::F32(f32::INFINITY));
+        assert_eq!(finite(inf), Number::from(Float::F32(f32::MAX)));
+    }
+}
@@ -0,0 +1,476 @@
+use std::collections::VecDeque;
+use std::fmt;
+use std::str::FromStr;
+
+use futures::future;
+use futures::stream::{StreamExt, TryStreamExt};
+use log::debug;
+
+use crate::error;
+use crate::scalar::number::*;
+use crate::transaction::Txn;
+use crate::{CastInto, TCResult};
+
+use super::dense::{BlockListFile, DenseAccessor, PER_BLOCK};
+use super::{Array, DenseTensor, Tensor, TensorAccess, TensorInstance, TensorTransform};
+
+/// The reduction to apply to each window of a rolling op.
+#[derive(Clone, Copy, Eq, PartialEq)]
+pub enum RollingOp {
+    Max,
+    Mean,
+    Sum,
+}
+
+impl RollingOp {
+    fn dtype(&self, source: NumberType) -> NumberType {
+        match self {
+            Self::Mean => FloatType::F64.into(),
+            _ => source,
+        }
+    }
+}
+
+impl FromStr for RollingOp {
+    type Err = error::TCError;
+
+    fn from_str(op: &str) -> TCResult<RollingOp> {
+        match op {
+            "max" => Ok(Self::Max),
+            "mean" => Ok(Self::Mean),
+            "sum" => Ok(Self::Sum),
+            other => Err(error::bad_request("Unsupported rolling op", other)),
+        }
+    }
+}
+
+impl fmt::Display for RollingOp {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Max => write!(f, "max"),
+            Self::Mean => write!(f, "mean"),
+            Self::Sum => write!(f, "sum"),
+        }
+    }
+}
+
+// The values of the current window along one lane of the rolling axis. Since the source is read
+// as a stream of blocks, a window may begin in one block and end in the next, so the trailing
+// values of each block are carried over until the window which contains them is complete.
+//
+// The sum of a window is kept as a running total of its finite values, plus a count of each kind
+// of non-finite value, since subtracting an expired NaN or infinity from a running total would
+// leave it NaN for good. Like the sum, the max of a window with a NaN in it is NaN.
+struct Window {
+    op: RollingOp,
+    size: usize,
+    lane_len: u64,
+    position: u64,
+    values: VecDeque<Number>,
+    total: Number,
+    re: NonFinite,
+    im: NonFinite,
+    maxima: VecDeque<(u64, Number)>,
+    zero: Number,
+}
+
+impl Window {
+    fn new(op: RollingOp, size: usize, lane_len: u64, dtype: NumberType) -> Self {
+        // the sum of a boolean window is a count, not a logical "or"
+        let zero = match dtype {
+            NumberType::Bool => NumberType::uint64().zero(),
+            dtype => dtype.zero(),
+        };
+
+        Self {
+            op,
+            size,
+            lane_len,
+            position: 0,
+            values: VecDeque::with_capacity(size),
+            total: zero,
+            re: NonFinite::default(),
+            im: NonFinite::default(),
+            maxima: VecDeque::new(),
+            zero,
+        }
+    }
+
+    fn push(&mut self, value: Number) -> Option<Number> {
+        if self.values.len() == self.size {
+            let expired = self.values.pop_front().unwrap();
+            let expired = self.finite(expired, true);
+            self.total = self.total - expired;
+        }
+
+        self.values.push_back(value);
+        let finite = self.finite(value, false);
+        self.total = self.total + finite;
+
+        if self.op == RollingOp::Max {
+            self.push_max(value);
+        }
+
+        let reduced = if self.values.len() == self.size {
+            Some(self.reduce())
+        } else {
+            None
+        };
+
+        self.position += 1;
+        if self.position == self.lane_len {
+            self.position = 0;
+            self.values.clear();
+            self.total = self.zero;
+            self.re = NonFinite::default();
+            self.im = NonFinite::default();
+            self.maxima.clear();
+        }
+
+        reduced
+    }
+
+    // keep only the values which could still be the max of a window, in decreasing order
+    fn push_max(&mut self, value: Number) {
+        let size = self.size as u64;
+        while let Some((position, _)) = self.maxima.front() {
+            if position + size <= self.position {
+                self.maxima.pop_front();
+            } else {
+                break;
+            }
+        }
+
+        if is_nan(value) {
+            // a NaN is counted, and the window is NaN until it expires
+            return;
+        }
+
+        while let Some((_, last)) = self.maxima.back() {
+            if *last <= value {
+                self.maxima.pop_back();
+            } else {
+                break;
+            }
+        }
+
+        self.maxima.push_back((self.position, value));
+    }
+
+    // count the non-finite parts of `value`, and return its finite parts
+    fn finite(&mut self, value: Number, expired: bool) -> Number {
+        let re = &mut self.re;
+        let im = &mut self.im;
+
+        match value {
+            Number::Complex(Complex::C32(c)) => {
+                let re = re.count(c.re as f64, expired) as f32;
+                let im = im.count(c.im as f64, expired) as f32;
+                Complex::C32(num::Complex::new(re, im)).into()
+            }
+            Number::Complex(Complex::C64(c)) => {
+                let re = re.count(c.re, expired);
+                let im = im.count(c.im, expired);
+                Complex::C64(num::Complex::new(re, im)).into()
+            }
+            Number::Float(Float::F32(f)) => Float::F32(re.count(f as f64, expired) as f32).into(),
+            Number::Float(Float::F64(f)) => Float::F64(re.count(f, expired)).into(),
+            other => other,
+        }
+    }
+
+    fn reduce(&self) -> Number {
+        match self.op {
+            RollingOp::Max => {
+                if self.re.nan > 0 || self.im.nan > 0 {
+                    Float::F64(f64::NAN).into()
+                } else {
+                    self.maxima.front().unwrap().1
+                }
+            }
+            RollingOp::Mean => {
+                let sum: Float = self.sum().cast_into();
+                let sum: f64 = sum.into();
+                Float::from(sum / self.size as f64).into()
+            }
+            RollingOp::Sum => self.sum(),
+        }
+    }
+
+    fn sum(&self) -> Number {
+        match (self.re.sum(), self.im.sum()) {
+            (None, None) => self.total,
+            (re, im) => match self.total {
+                Number::Complex(c) => {
+                    let c: num::Complex<f64> = c.into();
+                    let re = re.unwrap_or(c.re);
+                    let im = im.unwrap_or(c.im);
+                    Complex::C64(num::Complex::new(re, im)).into()
+                }
+                _ => Float::F64(re.unwrap_or(f64::NAN)).into(),
+            },
+        }
+    }
+}
+
+// the number of each kind of non-finite value in a window, along one component of its values
+#[derive(Default)]
+struct NonFinite {
+    nan: usize,
+    pos: usize,
+    neg: usize,
+}
+
+impl NonFinite {
+    // count `value` into (or, if it's `expired`, out of) this window if it's not finite, and
+    // return the part of it which belongs in the running total
+    fn count(&mut self, value: f64, expired: bool) -> f64 {
+        let count = if value.is_nan() {
+            &mut self.nan
+        } else if value == f64::INFINITY {
+            &mut self.pos
+        } else if value == f64::NEG_INFINITY {
+            &mut self.neg
+        } else {
+            return value;
+        };
+
+        if expired {
+            *count -= 1;
+        } else {
+            *count += 1;
+        }
+
+        0.
+    }
+
+    // the sum of the non-finite values in this window, if there are any
+    fn sum(&self) -> Option<f64> {
+        if self.nan > 0 || (self.pos > 0 && self.neg > 0) {
+            Some(f64::NAN)
+        } else if self.pos > 0 {
+            Some(f64::INFINITY)
+        } else if self.neg > 0 {
+            Some(f64::NEG_INFINITY)
+        } else {
+            None
+        }
+    }
+}
+
+fn is_nan(value: Number) -> bool {
+    match value {
+        Number::Complex(Complex::C32(c)) => c.re.is_nan() || c.im.is_nan(),
+        Number::Complex(Complex::C64(c)) => c.re.is_nan() || c.im.is_nan(),
+        Number::Float(Float::F32(f)) => f.is_nan(),
+        Number::Float(Float::F64(f)) => f.is_nan(),
+        _ => false,
+    }
+}
+
+/// Apply `op` to every `window` consecutive elements of `tensor` along `axis`.
+///
+/// The output has the same shape as `tensor`, except that the length of `axis` is reduced by
+/// `window - 1`, like a "valid" convolution. The source is read exactly once, and the output is
+/// written to a new dense tensor owned by `txn`.
+pub async fn rolling(
+    txn: &Txn,
+    tensor: Tensor,
+    window: u64,
+    op: RollingOp,
+    axis: usize,
+) -> TCResult<Tensor> {
+    let ndim = tensor.ndim();
+    let (source, inverse) = lanes(tensor, axis)?;
+
+    let lane_len = source.shape()[ndim - 1];
+    if window == 0 || window > lane_len {
+        return Err(error::bad_request(
+            format!("Rolling window must be between 1 and {}, not", lane_len),
+            window,
+        ));
+    }
+
+    let source_dtype = source.dtype();
+    let dtype = op.dtype(source_dtype);
+    let mut shape = source.shape().to_vec();
+    shape[ndim - 1] = lane_len - window + 1;
+
+    debug!(
+        "rolling {} of {} elements along axis {} of a tensor with shape {}",
+        op,
+        window,
+        axis,
+        source.shape()
+    );
+
+    let mut state = Window::new(op, window as usize, lane_len, source_dtype);
+    let values = source
+        .value_stream(txn)
+        .await?
+        .try_filter_map(move |value| future::ready(Ok(state.push(value))))
+        .chunks(PER_BLOCK)
+        .map(|values| values.into_iter().collect::<TCResult<Vec<Number>>>())
+        .and_then(move |values| future::ready(Array::cast_from_values(values, dtype)));
+
+    let output = BlockListFile::from_blocks(txn, shape.into(), dtype, Box::pin(values)).await?;
+    Tensor::from(DenseTensor::from(output)).transpose(Some(inverse))
+}
+
+/// Transpose `tensor` so that `axis` is its last axis, so that each lane along `axis` is
+/// contiguous in its value stream. Returns the transposed tensor and the permutation which
+/// restores the original order of its axes.
+pub(super) fn lanes(
+    tensor: Tensor,
+    axis: usize,
+) -> TCResult<(DenseTensor<DenseAccessor>, Vec<usize>)> {
+    let ndim = tensor.ndim();
+    if axis >= ndim {
+        return Err(error::bad_request(
+            format!("Tensor with {} dimensions has no axis", ndim),
+            axis,
+        ));
+    }
+
+    let mut permutation: Vec<usize> = (0..ndim).filter(|x| *x != axis).collect();
+    permutation.push(axis);
+
+    let mut inverse = vec![0; ndim];
+    for (i, x) in permutation.iter().enumerate() {
+        inverse[*x] = i;
+    }
+
+    match tensor.into_dense().transpose(Some(permutation))? {
+        Tensor::Dense(dense) => Ok((dense, inverse)),
+        Tensor::Sparse(_) => Err(error::internal("Expected a dense tensor")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn roll(op: RollingOp, size: usize, lane_len: u64, values: &[f64]) -> Vec<f64> {
+        let mut window = Window::new(op, size, lane_len, FloatType::F64.into());
+        values
+            .iter()
+            .filter_map(|value| window.push(Float::F64(*value).into()))
+            .map(|reduced| {
+                let reduced: Float = reduced.cast_into();
+                reduced.into()
+            })
+            .collect()
+    }
+
+    // the expected result, computed one window at a time
+    fn expect(op: RollingOp, size: usize, lane_len: usize, values: &[f64]) -> Vec<f64> {
+        values
+            .chunks(lane_len)
+            .flat_map(|lane| lane.windows(size))
+            .map(|window| match op {
+                RollingOp::Max if window.iter().any(|value| value.is_nan()) => f64::NAN,
+                RollingOp::Max => window.iter().cloned().fold(f64::NEG_INFINITY, f64::max),
+                RollingOp::Mean => window.iter().sum::<f64>() / size as f64,
+                RollingOp::Sum => window.iter().sum(),
+            })
+            .collect()
+    }
+
+    fn assert_same(actual: &[f64], expected: &[f64]) {
+        assert_eq!(actual.len(), expected.len());
+        for (i, (actual, expected)) in actual.iter().zip(expected).enumerate() {
+            let same = (actual.is_nan() && expected.is_nan()) || actual == expected;
+            assert!(same, "at {}: {} != {}", i, actual, expected);
+        }
+    }
+
+    #[test]
+    fn test_window() {
+        let values = [1., 3., 2., 5., 4., 0., 7., 6.];
+        assert_eq!(
+            roll(RollingOp::Sum, 3, 8, &values),
+            vec![6., 10., 11., 9., 11., 13.]
+        );
+        assert_eq!(
+            roll(RollingOp::Max, 3, 8, &values),
+            vec![3., 5., 5., 5., 7., 7.]
+        );
+
+        // each lane is rolled separately
+        assert_eq!(
+            roll(RollingOp::Sum, 2, 4, &values),
+            vec![4., 5., 7., 4., 7., 13.]
+        );
+        assert_eq!(roll(RollingOp::Mean, 4, 4, &values), vec![2.75, 4.25]);
+    }
+
+    #[test]
+    fn test_non_finite() {
+        let values = [
+            1.,
+            f64::NAN,
+            2.,
+            3.,
+            4.,
+            f64::INFINITY,
+            5.,
+            f64::NEG_INFINITY,
+            6.,
+            7.,
+            8.,
+            9.,
+        ];
+
+        // a window is NaN or infinite only until the non-finite value in it expires
+        for op in &[RollingOp::Max, RollingOp::Mean, RollingOp::Sum] {
+            for size in 1..5 {
+                let actual = roll(*op, size, 12, &values);
+                assert_same(&actual, &expect(*op, size, 12, &values));
+            }
+        }
+
+        let sum = roll(RollingOp::Sum, 3, 12, &values);
+        assert!(sum[0].is_nan() && sum[1].is_nan());
+        assert_eq!(sum[2], 9.);
+        assert_eq!(sum[3], f64::INFINITY);
+        assert!(sum[5].is_nan());
+        assert_eq!(sum[7], f64::NEG_INFINITY);
+        assert_eq!(sum[8], 21.);
+
+        let max = roll(RollingOp::Max, 3, 12, &values);
+        assert!(max[0].is_nan() && max[1].is_nan());
+        assert_eq!(max[2], 4.);
+    }
+
+    #[test]
+    fn test_boundaries() {
+        // two lanes which each span a block boundary, so the windows at the end of the first
+        // block are completed by the values at the start of the next
+        let lane_len = PER_BLOCK + 3;
+        let values: Vec<f64> = (0..(2 * lane_len))
+            .map(|i| match i % 1_000 {
+                0 => f64::NAN,
+                500 => f64::INFINITY,
+                _ => (i % 17) as f64,
+            })
+            .collect();
+
+        for op in &[RollingOp::Max, RollingOp::Sum] {
+            let actual = roll(*op, 5, lane_len as u64, &values);
+            assert_same(&actual, &expect(*op, 5, lane_len, &values));
+        }
+    }
+
+    #[test]
+    fn test_bool() {
+        let mut window = Window::new(RollingOp::Sum, 3, 6, NumberType::Bool);
+        let sums: Vec<Number> = [true, true, false, true, false, false]
+            .iter()
+            .filter_map(|value| window.push(Number::from(*value)))
+            .collect();
+
+        // the sum of a boolean window counts its true values
+        let expected: Vec<Number> = vec![2u64.into(), 2u64.into(), 1u64.into(), 1u64.into()];
+        assert!(sums == expected);
+    }
+}
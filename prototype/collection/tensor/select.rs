@@ -0,0 +1,61 @@
+use futures::stream::{StreamExt, TryStreamExt};
+use futures::try_join;
+use log::debug;
+
+use crate::error;
+use crate::transaction::Txn;
+use crate::TCResult;
+
+use super::dense::{BlockListFile, DenseAccess, DenseAccessor};
+use super::{broadcast, DenseTensor, Tensor, TensorAccess, TensorInstance};
+
+/// Select the elements of `left` where `cond` is true, and of `right` elsewhere, like
+/// `numpy.where`.
+///
+/// All three operands are broadcast to a common shape, and the output has the promoted dtype of
+/// `left` and `right`. The output is written to a new dense tensor owned by `txn`, which is
+/// converted to a sparse tensor if both `left` and `right` are sparse.
+pub async fn select(txn: &Txn, cond: Tensor, left: Tensor, right: Tensor) -> TCResult<Tensor> {
+    let strict = txn.gateway().config().strict_promotion()?;
+    let dtype = left.dtype().try_promote(right.dtype(), strict)?;
+    let sparse = matches!((&left, &right), (Tensor::Sparse(_), Tensor::Sparse(_)));
+
+    let (cond, left) = broadcast(&cond, &left)?;
+    let (left, right) = broadcast(&left, &right)?;
+    let (cond, right) = broadcast(&cond, &right)?;
+    let shape = cond.shape().clone();
+
+    debug!("select from two tensors with shape {}", shape);
+
+    let cond = into_blocks(cond)?;
+    let left = into_blocks(left)?;
+    let right = into_blocks(right)?;
+
+    let (cond_blocks, left_blocks, right_blocks) = try_join!(
+        cond.block_stream(txn),
+        left.block_stream(txn),
+        right.block_stream(txn)
+    )?;
+
+    let blocks = cond_blocks
+        .zip(left_blocks)
+        .zip(right_blocks)
+        .map(|((cond, left), right)| Ok((cond?, left?, right?)))
+        .map_ok(move |(cond, left, right)| cond.select(&left, &right).into_type(dtype));
+
+    let output = BlockListFile::from_blocks(txn, shape, dtype, Box::pin(blocks)).await?;
+    let output = Tensor::from(DenseTensor::from(output));
+
+    if sparse {
+        Ok(output.into_sparse())
+    } else {
+        Ok(output)
+    }
+}
+
+fn into_blocks(tensor: Tensor) -> TCResult<DenseAccessor> {
+    match tensor.into_dense() {
+        Tensor::Dense(dense) => Ok(dense.into_inner()),
+        Tensor::Sparse(_) => Err(error::internal("Expected a dense tensor")),
+    }
+}
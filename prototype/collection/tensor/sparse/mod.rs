@@ -352,12 +352,12 @@ impl<T: Clone + SparseAccess, OT: Clone + SparseAccess> TensorMath<SparseTensor<
     type Combine = SparseTensor<SparseCombinator<T, OT>>;
 
     fn add(&self, other: &SparseTensor<OT>) -> TCResult<Self::Combine> {
-        let dtype = Ord::max(self.dtype(), other.dtype());
+        let dtype = self.dtype().promote(other.dtype()).0;
         self.combine(other, <Number as NumberInstance>::add, dtype)
     }
 
     fn multiply(&self, other: &SparseTensor<OT>) -> TCResult<Self::Combine> {
-        let dtype = Ord::max(self.dtype(), other.dtype());
+        let dtype = self.dtype().promote(other.dtype()).0;
         self.combine(other, <Number as NumberInstance>::multiply, dtype)
     }
 }
@@ -439,6 +439,21 @@ impl<T: Clone + SparseAccess> Route for SparseTensor<T> {
     }
 }
 
+impl SparseTensor<SparseTable> {
+    /// Construct a new `SparseTensor` from a stream of `(coord, value)` entries, which are
+    /// written in sorted batches. See [`SparseTable::from_entries`].
+    pub async fn from_entries<S: Stream<Item = TCResult<SparseRow>> + Unpin>(
+        txn: &Txn,
+        shape: Shape,
+        dtype: NumberType,
+        entries: S,
+    ) -> TCResult<Self> {
+        SparseTable::from_entries(txn, shape, dtype, entries)
+            .map_ok(|accessor| SparseTensor { accessor })
+            .await
+    }
+}
+
 #[async_trait]
 impl<T: Clone + SparseAccess> Transact for SparseTensor<T> {
     async fn commit(&self, txn_id: &TxnId) {
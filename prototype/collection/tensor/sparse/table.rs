@@ -20,11 +20,13 @@ use super::super::transform::{self, Rebase};
 use super::super::{Coord, TensorAccess};
 
 use super::access::{SparseAccess, SparseAccessor};
-use super::SparseStream;
+use super::{SparseRow, SparseStream};
 use crate::collection::tensor::sparse::SparseTranspose;
 
 const VALUE: Label = label("value");
 
+const BATCH_SIZE: usize = 1024;
+
 const ERR_CORRUPT: &str = "SparseTensor corrupted! Please file a bug report.";
 
 #[derive(Clone)]
@@ -89,6 +91,45 @@ impl SparseTable {
         Ok(table)
     }
 
+    /// Construct a new `SparseTable` from a stream of `(coord, value)` entries.
+    ///
+    /// The entries are read in batches of `BATCH_SIZE`, each of which is sorted by coordinate
+    /// before it's written. A coordinate greater than every coordinate already written must be
+    /// new, so if the entries are in row-major order, none of them are looked up before they're
+    /// inserted. If the same coordinate is given more than once, its last value is kept.
+    pub async fn from_entries<S: Stream<Item = TCResult<SparseRow>> + Unpin>(
+        txn: &Txn,
+        shape: Shape,
+        dtype: NumberType,
+        entries: S,
+    ) -> TCResult<Self> {
+        let txn_id = *txn.id();
+        let table = Self::create(txn, shape, dtype).await?;
+
+        let mut batches = entries.chunks(BATCH_SIZE);
+        let mut written: Option<Coord> = None;
+        while let Some(batch) = batches.next().await {
+            let batch = batch.into_iter().collect::<TCResult<Vec<SparseRow>>>()?;
+            let (new, existing) = split_batch(&table.shape, dtype, &mut written, batch)?;
+
+            let new = new
+                .into_iter()
+                .map(|(coord, value)| {
+                    let key = coord.into_iter().map(u64_to_value).collect();
+                    (key, vec![Value::Number(value)])
+                })
+                .collect();
+
+            table.table.insert_new(&txn_id, new).await?;
+
+            for (coord, value) in existing {
+                upsert_value(&table.table, txn_id, coord, value).await?;
+            }
+        }
+
+        Ok(table)
+    }
+
     pub fn try_from_table(table: TableIndex, shape: Shape) -> TCResult<SparseTable> {
         let expected_key = Self::key(shape.len());
         let actual_key = table.key();
@@ -387,6 +428,57 @@ async fn upsert_value<T: TableInstance>(
     table.upsert(&txn_id, key, vec![Value::Number(value)]).await
 }
 
+// sort a batch of entries by coordinate and split it into the nonzero entries whose coordinates
+// are greater than every coordinate `written` so far, which can be inserted without a lookup,
+// and the rest, which must be upserted
+fn split_batch(
+    shape: &Shape,
+    dtype: NumberType,
+    written: &mut Option<Coord>,
+    mut batch: Vec<SparseRow>,
+) -> TCResult<(Vec<SparseRow>, Vec<SparseRow>)> {
+    for (coord, _) in &batch {
+        if !shape.contains_coord(coord) {
+            return Err(error::bad_request(
+                "Coordinate out of bounds",
+                Bounds::from(coord.to_vec()),
+            ));
+        }
+    }
+
+    // the sort is stable, so duplicate coordinates stay in the order they were given
+    batch.sort_by(|(l, _), (r, _)| l.cmp(r));
+
+    let mut new: Vec<SparseRow> = Vec::with_capacity(batch.len());
+    let mut existing = vec![];
+    for (coord, value) in batch {
+        let value = value.into_type(dtype);
+        let is_new = match written {
+            Some(written) => &coord > written,
+            None => true,
+        };
+
+        if !is_new {
+            existing.push((coord, value));
+            continue;
+        }
+
+        match new.last_mut() {
+            Some((last, last_value)) if last == &coord => *last_value = value,
+            _ => new.push((coord, value)),
+        }
+    }
+
+    if let Some((last, _)) = new.last() {
+        *written = Some(last.to_vec());
+    }
+
+    let zero = dtype.zero();
+    new.retain(|(_, value)| value != &zero);
+
+    Ok((new, existing))
+}
+
 fn u64_to_value(u: u64) -> Value {
     Value::Number(Number::UInt(UInt::U64(u)))
 }
@@ -411,3 +503,75 @@ fn unwrap_u64(value: &Value) -> TCResult<u64> {
         Err(error::bad_request("Expected u64 but found", value))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::error::ErrorType;
+
+    use super::*;
+
+    fn entries(entries: &[(&[u64], u64)]) -> Vec<SparseRow> {
+        entries
+            .iter()
+            .map(|(coord, value)| (coord.to_vec(), Number::from(*value)))
+            .collect()
+    }
+
+    fn assert_entries(actual: Vec<SparseRow>, expected: &[(&[u64], u64)]) {
+        assert_eq!(actual, entries(expected));
+    }
+
+    #[test]
+    fn test_split_batch() {
+        let shape: Shape = vec![3, 4].into();
+        let dtype = NumberType::uint64();
+        let mut written = None;
+
+        // a batch out of order is sorted, and the last value of a duplicate coordinate is kept
+        let batch = entries(&[(&[1, 2], 5), (&[0, 1], 1), (&[1, 2], 6), (&[0, 3], 0)]);
+        let (new, existing) = split_batch(&shape, dtype, &mut written, batch).unwrap();
+        assert_entries(new, &[(&[0, 1], 1), (&[1, 2], 6)]);
+        assert!(existing.is_empty());
+        assert_eq!(written, Some(vec![1, 2]));
+
+        // a coordinate at or before the last one written may already exist, so it's upserted
+        let batch = entries(&[(&[2, 0], 7), (&[1, 2], 0), (&[0, 0], 3), (&[2, 3], 8)]);
+        let (new, existing) = split_batch(&shape, dtype, &mut written, batch).unwrap();
+        assert_entries(new, &[(&[2, 0], 7), (&[2, 3], 8)]);
+        assert_entries(existing, &[(&[0, 0], 3), (&[1, 2], 0)]);
+        assert_eq!(written, Some(vec![2, 3]));
+
+        let (new, existing) = split_batch(&shape, dtype, &mut written, vec![]).unwrap();
+        assert!(new.is_empty() && existing.is_empty());
+        assert_eq!(written, Some(vec![2, 3]));
+    }
+
+    #[test]
+    fn test_split_batch_zero() {
+        let shape: Shape = vec![4].into();
+        let dtype = NumberType::uint64();
+        let mut written = None;
+
+        // a zero isn't written, but it still advances the coordinates which must be new
+        let batch = entries(&[(&[0], 1), (&[2], 0)]);
+        let (new, _) = split_batch(&shape, dtype, &mut written, batch).unwrap();
+        assert_entries(new, &[(&[0], 1)]);
+        assert_eq!(written, Some(vec![2]));
+
+        // a value is cast to the tensor's data type before it's compared to zero
+        let batch = vec![(vec![3], Number::from(false))];
+        let (new, existing) = split_batch(&shape, dtype, &mut written, batch).unwrap();
+        assert!(new.is_empty() && existing.is_empty());
+    }
+
+    #[test]
+    fn test_split_batch_out_of_bounds() {
+        let shape: Shape = vec![3, 4].into();
+        let mut written = None;
+
+        let batch = entries(&[(&[0, 1], 1), (&[3, 0], 2)]);
+        let cause = split_batch(&shape, NumberType::uint64(), &mut written, batch).unwrap_err();
+        assert!(cause.reason() == &ErrorType::BadRequest);
+        assert!(written.is_none());
+    }
+}
@@ -0,0 +1,487 @@
+use std::collections::BTreeMap;
+use std::convert::TryFrom;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+
+use async_trait::async_trait;
+use log::{debug, warn, LevelFilter};
+use tokio::sync::watch;
+
+use crate::auth::SCOPE_WRITE;
+use crate::block;
+use crate::class::State;
+use crate::error;
+use crate::general::Map;
+use crate::handler::Public;
+use crate::logger::{parse_filters, LOGGER};
+use crate::persist::{sbin_dir, Persistent};
+use crate::request::Request;
+use crate::scalar::*;
+use crate::transaction::Txn;
+use crate::{Match, TCResult, TryCastFrom, TryCastInto};
+
+pub const CACHE_SIZE: &str = "cache_size";
+pub const DECODE_PARALLELISM: &str = "decode_parallelism";
+pub const DEVICE_MEMORY_LIMIT: &str = "device_memory_limit";
+pub const EINSUM_WORKING_SET: &str = "einsum_working_set";
+pub const LOG_LEVEL: &str = "log_level";
+pub const RATE_LIMIT: &str = "rate_limit";
+pub const REQUEST_LIMIT: &str = "request_limit";
+pub const REQUEST_TTL: &str = "request_ttl";
+pub const STRICT_PROMOTION: &str = "strict_promotion";
+
+struct Knob {
+    sender: watch::Sender<Value>,
+    receiver: watch::Receiver<Value>,
+    validate: fn(&Value) -> bool,
+    stored: AtomicBool,
+}
+
+impl Knob {
+    fn new(value: Value, validate: fn(&Value) -> bool) -> Knob {
+        let (sender, receiver) = watch::channel(value);
+        Knob {
+            sender,
+            receiver,
+            validate,
+            stored: AtomicBool::new(false),
+        }
+    }
+
+    fn set(&self, value: Value) -> TCResult<()> {
+        self.sender
+            .send(value)
+            .map_err(|_| error::internal("Unable to update config: channel closed"))
+    }
+}
+
+/// The runtime configuration of this host, available at `/sbin/config`.
+///
+/// Each setting can be watched by the components which depend on it, so that a new value set by
+/// an operator takes effect without a restart. A setting which an operator has set is stored in
+/// `data_dir` and overrides its command-line value when the host restarts. Only a request with a
+/// bearer token granting the write scope may change a setting.
+pub struct HostConfig {
+    knobs: BTreeMap<Id, Knob>,
+    store: Persistent,
+}
+
+impl HostConfig {
+    /// Construct the configuration of this host from its command-line values, overridden by the
+    /// settings stored in `data_dir`.
+    pub fn load(
+        data_dir: &Path,
+        cache_size: usize,
+        log_level: LevelFilter,
+        rate_limit: Option<u64>,
+        request_limit: usize,
+        request_ttl: Duration,
+        decode_parallelism: usize,
+        device_memory_limit: Option<usize>,
+        einsum_working_set: usize,
+        strict_promotion: bool,
+    ) -> TCResult<HostConfig> {
+        let mut knobs = BTreeMap::new();
+
+        knobs.insert(
+            label(CACHE_SIZE).into(),
+            Knob::new(Value::from(cache_size as u64), is_positive),
+        );
+
+        knobs.insert(
+            label(DECODE_PARALLELISM).into(),
+            Knob::new(Value::from(decode_parallelism as u64), is_positive),
+        );
+
+        knobs.insert(
+            label(DEVICE_MEMORY_LIMIT).into(),
+            Knob::new(
+                device_memory_limit
+                    .map(|limit| Value::from(limit as u64))
+                    .unwrap_or(Value::None),
+                |v| v.is_none() || v.matches::<u64>(),
+            ),
+        );
+
+        knobs.insert(
+            label(EINSUM_WORKING_SET).into(),
+            Knob::new(Value::from(einsum_working_set as u64), is_positive),
+        );
+
+        knobs.insert(
+            label(LOG_LEVEL).into(),
+            Knob::new(
+                Value::TCString(TCString::UString(log_level.to_string().to_lowercase())),
+                |v| log_filters_from(v).is_ok(),
+            ),
+        );
+
+        knobs.insert(
+            label(RATE_LIMIT).into(),
+            Knob::new(rate_limit.map(Value::from).unwrap_or(Value::None), |v| {
+                v.is_none() || is_positive(v)
+            }),
+        );
+
+        knobs.insert(
+            label(REQUEST_LIMIT).into(),
+            Knob::new(Value::from(request_limit as u64), |v| v.matches::<u64>()),
+        );
+
+        knobs.insert(
+            label(REQUEST_TTL).into(),
+            Knob::new(Value::from(request_ttl.as_secs()), is_positive),
+        );
+
+        knobs.insert(
+            label(STRICT_PROMOTION).into(),
+            Knob::new(Value::from(strict_promotion), |v| {
+                bool::try_from(v.clone()).is_ok()
+            }),
+        );
+
+        for (name, knob) in &knobs {
+            let value = knob.receiver.borrow().clone();
+            if !(knob.validate)(&value) {
+                return Err(error::bad_request(
+                    format!("Invalid value for {}", name),
+                    value,
+                ));
+            }
+        }
+
+        let store = Persistent::new(sbin_dir(data_dir, "config"), "settings");
+        let config = HostConfig { knobs, store };
+
+        if let Some(Value::Tuple(settings)) = config.store.load()? {
+            for setting in settings.into_inner() {
+                let (name, value): (Id, Value) = setting
+                    .try_cast_into(|v| error::internal(format!("Invalid stored setting {}", v)))?;
+
+                match config.knobs.get(&name) {
+                    Some(knob) if (knob.validate)(&value) => {
+                        debug!("stored config {} = {}", name, value);
+                        knob.set(value)?;
+                        knob.stored.store(true, Ordering::Relaxed);
+                    }
+                    Some(_) => warn!("ignoring invalid stored setting {} = {}", name, value),
+                    None => warn!("ignoring unknown stored setting {}", name),
+                }
+            }
+        }
+
+        Ok(config)
+    }
+
+    /// Return the current value of the given setting.
+    pub fn value(&self, name: &str) -> TCResult<Value> {
+        self.knobs
+            .iter()
+            .find(|(id, _)| id.as_str() == name)
+            .map(|(_, knob)| knob.receiver.borrow().clone())
+            .ok_or_else(|| error::not_found(name))
+    }
+
+    fn setting<T: TryCastFrom<Value>>(&self, name: &str) -> TCResult<T> {
+        self.value(name)?
+            .try_cast_into(|v| error::internal(format!("Invalid setting {}: {}", name, v)))
+    }
+
+    /// Return a `watch::Receiver` which is notified whenever the given setting changes.
+    pub fn watch(&self, name: &Id) -> TCResult<watch::Receiver<Value>> {
+        self.knobs
+            .get(name)
+            .map(|knob| knob.receiver.clone())
+            .ok_or_else(|| error::not_found(name))
+    }
+
+    /// The maximum number of bytes of decoded blocks for a file to keep in memory.
+    pub fn cache_size(&self) -> TCResult<usize> {
+        self.setting::<u64>(CACHE_SIZE).map(|size| size as usize)
+    }
+
+    /// The maximum number of tasks to use to decode the top-level entries of a request body.
+    pub fn decode_parallelism(&self) -> TCResult<usize> {
+        self.setting::<u64>(DECODE_PARALLELISM)
+            .map(|parallelism| parallelism as usize)
+    }
+
+    /// The maximum number of bytes of device memory to use for tensor data, if any.
+    pub fn device_memory_limit(&self) -> TCResult<Option<usize>> {
+        let limit = self.value(DEVICE_MEMORY_LIMIT)?;
+        if limit.is_none() {
+            Ok(None)
+        } else {
+            self.setting::<u64>(DEVICE_MEMORY_LIMIT)
+                .map(|limit| Some(limit as usize))
+        }
+    }
+
+    /// The maximum number of bytes of intermediate data to compute at once in an `einsum`.
+    pub fn einsum_working_set(&self) -> TCResult<usize> {
+        self.setting::<u64>(EINSUM_WORKING_SET)
+            .map(|working_set| working_set as usize)
+    }
+
+    /// The maximum number of requests per second to accept, if any.
+    pub fn rate_limit(&self) -> TCResult<Option<u64>> {
+        let rate_limit = self.value(RATE_LIMIT)?;
+        if rate_limit.is_none() {
+            Ok(None)
+        } else {
+            self.setting(RATE_LIMIT).map(Some)
+        }
+    }
+
+    pub fn request_limit(&self) -> TCResult<usize> {
+        self.setting::<u64>(REQUEST_LIMIT).map(|limit| limit as usize)
+    }
+
+    pub fn request_ttl(&self) -> TCResult<Duration> {
+        self.setting(REQUEST_TTL).map(Duration::from_secs)
+    }
+
+    /// Whether a lossy dtype promotion, like `I64` to `F64`, is an error.
+    pub fn strict_promotion(&self) -> TCResult<bool> {
+        bool::try_from(self.value(STRICT_PROMOTION)?)
+    }
+
+    /// Apply the `cache_size` setting now, and whenever it changes until the host shuts down.
+    pub fn apply_cache_size(&self) -> TCResult<()> {
+        let mut cache_size = self.watch(&label(CACHE_SIZE).into())?;
+        block::set_cache_size(self.cache_size()?);
+
+        tokio::spawn(async move {
+            while cache_size.changed().await.is_ok() {
+                let size: Option<u64> = cache_size.borrow().clone().opt_cast_into();
+                match size {
+                    Some(size) => block::set_cache_size(size as usize),
+                    None => warn!("ignoring invalid cache_size setting"),
+                }
+            }
+        });
+
+        Ok(())
+    }
+
+    /// Apply the `log_level` setting now, and whenever it changes until the host shuts down.
+    ///
+    /// The setting holds a log filter specification, like `warn,tinychain::transaction=debug`.
+    pub fn apply_log_level(&self) -> TCResult<()> {
+        let mut log_level = self.watch(&label(LOG_LEVEL).into())?;
+        LOGGER.set_filters(&log_filters_from(&log_level.borrow())?)?;
+
+        tokio::spawn(async move {
+            while log_level.changed().await.is_ok() {
+                let spec = log_filters_from(&log_level.borrow());
+                match spec.and_then(|spec| LOGGER.set_filters(&spec)) {
+                    Ok(()) => {}
+                    Err(cause) => warn!("ignoring invalid log level: {}", cause),
+                }
+            }
+        });
+
+        Ok(())
+    }
+
+    /// Store every setting which an operator has set.
+    async fn save(&self) -> TCResult<()> {
+        let knobs = &self.knobs;
+
+        self.store
+            .save(|| {
+                let settings: Vec<Value> = knobs
+                    .iter()
+                    .filter(|(_, knob)| knob.stored.load(Ordering::Relaxed))
+                    .map(|(name, knob)| {
+                        let value = knob.receiver.borrow().clone();
+                        Value::Tuple(vec![Value::from(name.clone()), value].into())
+                    })
+                    .collect();
+
+                Value::Tuple(settings.into())
+            })
+            .await
+    }
+}
+
+#[async_trait]
+impl Public for HostConfig {
+    async fn get(
+        &self,
+        _request: &Request,
+        _txn: &Txn,
+        path: &[PathSegment],
+        key: Value,
+    ) -> TCResult<State> {
+        if !key.is_none() {
+            return Err(error::bad_request(
+                "/sbin/config takes no key, but found",
+                key,
+            ));
+        }
+
+        if path.is_empty() {
+            let settings: Map<Scalar> = self
+                .knobs
+                .iter()
+                .map(|(name, knob)| (name.clone(), Scalar::Value(knob.receiver.borrow().clone())))
+                .collect();
+
+            Ok(State::Scalar(Scalar::Map(settings)))
+        } else if path.len() == 1 {
+            let setting = self.watch(&path[0])?;
+            let value = setting.borrow().clone();
+            Ok(State::from(value))
+        } else {
+            Err(error::path_not_found(path))
+        }
+    }
+
+    async fn put(
+        &self,
+        request: &Request,
+        _txn: &Txn,
+        path: &[PathSegment],
+        key: Value,
+        value: State,
+    ) -> TCResult<()> {
+        match request.auth() {
+            Some(token) => token.validate(SCOPE_WRITE.into(), "/sbin/config")?,
+            None => {
+                return Err(error::unauthorized(
+                    "Changing the host configuration requires a bearer token",
+                ))
+            }
+        }
+
+        if path.len() != 1 {
+            return Err(error::method_not_allowed(TCPath::from(path)));
+        } else if !key.is_none() {
+            return Err(error::bad_request(
+                "/sbin/config takes no key, but found",
+                key,
+            ));
+        }
+
+        let knob = self
+            .knobs
+            .get(&path[0])
+            .ok_or_else(|| error::not_found(&path[0]))?;
+
+        let value: Value =
+            value.try_cast_into(|v| error::bad_request("Invalid config setting", v))?;
+
+        if !(knob.validate)(&value) {
+            return Err(error::bad_request(
+                format!("Invalid value for {}", path[0]),
+                value,
+            ));
+        }
+
+        debug!("set config {} = {}", path[0], value);
+        knob.set(value)?;
+        knob.stored.store(true, Ordering::Relaxed);
+        self.save().await
+    }
+
+    async fn post(
+        &self,
+        _request: &Request,
+        _txn: &Txn,
+        path: &[PathSegment],
+        _params: Map<Scalar>,
+    ) -> TCResult<State> {
+        Err(error::method_not_allowed(TCPath::from(path)))
+    }
+
+    async fn delete(
+        &self,
+        _request: &Request,
+        _txn: &Txn,
+        path: &[PathSegment],
+        _key: Value,
+    ) -> TCResult<()> {
+        Err(error::method_not_allowed(TCPath::from(path)))
+    }
+}
+
+fn is_positive(value: &Value) -> bool {
+    let value: Option<u64> = value.clone().opt_cast_into();
+    value.map(|value| value > 0).unwrap_or(false)
+}
+
+fn log_filters_from(value: &Value) -> TCResult<String> {
+    match value {
+        Value::TCString(spec) => {
+            let spec = spec.to_string();
+            parse_filters(&spec)?;
+            Ok(spec)
+        }
+        other => Err(error::bad_request("Invalid log level", other)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn load(data_dir: &Path, request_ttl: u64) -> TCResult<HostConfig> {
+        HostConfig::load(
+            data_dir,
+            1_000_000,
+            LevelFilter::Warn,
+            None,
+            1_000_000,
+            Duration::from_secs(request_ttl),
+            1,
+            None,
+            1_000_000,
+            false,
+        )
+    }
+
+    #[test]
+    fn test_request_ttl() {
+        let data_dir = std::env::temp_dir().join(uuid::Uuid::new_v4().to_string());
+        assert!(load(&data_dir, 0).is_err());
+
+        let config = load(&data_dir, 30).unwrap();
+        assert_eq!(config.request_ttl().unwrap(), Duration::from_secs(30));
+        let request_ttl: Id = label(REQUEST_TTL).into();
+        assert!(!(config.knobs[&request_ttl].validate)(&Value::from(0u64)));
+        assert!(config.value("unknown").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_stored_settings() {
+        let data_dir = std::env::temp_dir().join(uuid::Uuid::new_v4().to_string());
+
+        let config = load(&data_dir, 30).unwrap();
+        let rate_limit: Id = label(RATE_LIMIT).into();
+        let knob = &config.knobs[&rate_limit];
+        knob.set(Value::from(100u64)).unwrap();
+        knob.stored.store(true, Ordering::Relaxed);
+        config.save().await.unwrap();
+
+        let config = load(&data_dir, 30).unwrap();
+        assert_eq!(config.rate_limit().unwrap(), Some(100));
+        assert_eq!(config.request_ttl().unwrap(), Duration::from_secs(30));
+
+        std::fs::remove_dir_all(data_dir).unwrap();
+    }
+
+    #[test]
+    fn test_strict_promotion() {
+        let data_dir = std::env::temp_dir().join(uuid::Uuid::new_v4().to_string());
+        let config = load(&data_dir, 30).unwrap();
+        assert!(!config.strict_promotion().unwrap());
+
+        let strict_promotion: Id = label(STRICT_PROMOTION).into();
+        config.knobs[&strict_promotion]
+            .set(Value::from(true))
+            .unwrap();
+        assert!(config.strict_promotion().unwrap());
+    }
+}
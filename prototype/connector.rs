@@ -0,0 +1,580 @@
+use std::collections::{HashMap, VecDeque};
+use std::convert::TryFrom;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use async_trait::async_trait;
+use log::{debug, warn};
+
+use crate::auth::SCOPE_WRITE;
+use crate::class::State;
+use crate::collection::schema::{IndexSchema, Row};
+use crate::error;
+use crate::gateway::Gateway;
+use crate::general::Map;
+use crate::handler::Public;
+use crate::lock::RwLock;
+use crate::persist::{sbin_dir, Persistent};
+use crate::request::Request;
+use crate::scalar::*;
+use crate::transaction::{Transact, Txn, TxnId};
+use crate::{TCResult, TryCastFrom, TryCastInto};
+
+const HISTORY_LEN: usize = 100;
+const ERR_DEF: &str = "Invalid connector definition";
+
+#[derive(Clone)]
+struct PollResult {
+    txn_id: TxnId,
+    result: Result<u64, String>,
+}
+
+impl From<PollResult> for Value {
+    fn from(poll: PollResult) -> Value {
+        let txn_id = Value::from(TCString::UString(poll.txn_id.to_string()));
+        match poll.result {
+            Ok(rows) => Value::from((txn_id, Value::from(rows))),
+            Err(cause) => Value::from((txn_id, Value::from(TCString::UString(cause)))),
+        }
+    }
+}
+
+/// A pull connector which periodically imports JSON records from an external source.
+///
+/// The source must be a path on this host, so a connector can only reach another host through an
+/// adapter under `/ext` which the operator of this host has configured.
+///
+/// Each record is passed as the parameters of the `transform` POST Op, which must return a Map
+/// of column values matching `schema`. The resulting rows are upserted into `target` in a single
+/// transaction, which is committed only if every row is imported successfully.
+///
+/// The definition and recent history of each connector are stored in `data_dir`, so a connector
+/// resumes polling when its host restarts.
+pub struct Connector {
+    name: Id,
+    def: Map<Scalar>,
+    source: Link,
+    target: Link,
+    schema: IndexSchema,
+    transform: OpDef,
+    interval: Duration,
+    running: AtomicBool,
+    history: RwLock<VecDeque<Value>>,
+    store: Persistent,
+}
+
+impl Connector {
+    fn try_from_def(name: Id, mut def: Map<Scalar>, store: Persistent) -> TCResult<Connector> {
+        let stored = def.clone();
+
+        let source: Link = def
+            .remove(&label("source").into())
+            .ok_or_else(|| error::bad_request(ERR_DEF, "missing 'source'"))?
+            .try_cast_into(|v| error::bad_request("Connector source must be a Link, not", v))?;
+
+        if source.host().is_some() {
+            return Err(error::forbidden(
+                "A connector may only read from a path on this host (use an adapter under /ext \
+                to read from another host), not",
+                source,
+            ));
+        }
+
+        let target = def
+            .remove(&label("target").into())
+            .ok_or_else(|| error::bad_request(ERR_DEF, "missing 'target'"))?
+            .try_cast_into(|v| error::bad_request("Connector target must be a Link, not", v))?;
+
+        let schema: Value = def
+            .remove(&label("schema").into())
+            .ok_or_else(|| error::bad_request(ERR_DEF, "missing 'schema'"))?
+            .try_cast_into(|v| error::bad_request("Invalid connector schema", v))?;
+        let schema = schema.try_cast_into(|v| error::bad_request("Invalid connector schema", v))?;
+
+        let transform = match def.remove(&label("transform").into()) {
+            Some(Scalar::Op(op_def)) => match *op_def {
+                OpDef::Post(op) => OpDef::Post(op),
+                other => {
+                    return Err(error::bad_request(
+                        "Connector transform must be a POST Op, not",
+                        other,
+                    ))
+                }
+            },
+            Some(other) => {
+                return Err(error::bad_request(
+                    "Connector transform must be a POST Op, not",
+                    other,
+                ))
+            }
+            None => return Err(error::bad_request(ERR_DEF, "missing 'transform'")),
+        };
+
+        let interval: Value = def
+            .remove(&label("interval").into())
+            .ok_or_else(|| error::bad_request(ERR_DEF, "missing 'interval'"))?
+            .try_cast_into(|v| error::bad_request("Invalid connector interval", v))?;
+        let interval: u64 =
+            interval.try_cast_into(|v| error::bad_request("Invalid connector interval", v))?;
+
+        if interval == 0 {
+            return Err(error::bad_request(
+                "Connector interval must be at least one second, not",
+                interval,
+            ));
+        }
+
+        if !def.is_empty() {
+            return Err(error::bad_request(
+                "Connector definition got unrecognized parameters",
+                Value::from(def.keys().cloned().collect::<Vec<Id>>()),
+            ));
+        }
+
+        Ok(Connector {
+            name,
+            def: stored,
+            source,
+            target,
+            schema,
+            transform,
+            interval: Duration::from_secs(interval),
+            running: AtomicBool::new(true),
+            history: RwLock::new(VecDeque::new()),
+            store,
+        })
+    }
+
+    fn is_running(&self) -> bool {
+        self.running.load(Ordering::Relaxed)
+    }
+
+    fn stop(&self) {
+        self.running.store(false, Ordering::Relaxed)
+    }
+
+    async fn poll(&self, gateway: &Arc<Gateway>, ttl: Duration) -> PollResult {
+        let request = Request::new(ttl, None, None, None);
+        let txn = match gateway.transaction(&request).await {
+            Ok(txn) => txn,
+            Err(cause) => {
+                return PollResult {
+                    txn_id: TxnId::new(Gateway::time()),
+                    result: Err(cause.to_string()),
+                }
+            }
+        };
+
+        let result = match self.import(gateway, &request, &txn).await {
+            Ok(rows) => {
+                txn.commit().await;
+                Ok(rows)
+            }
+            Err(cause) => {
+                warn!("connector {} failed to import: {}", self.name, cause);
+                txn.rollback().await;
+                Err(cause.to_string())
+            }
+        };
+
+        PollResult {
+            txn_id: *txn.id(),
+            result,
+        }
+    }
+
+    async fn import(&self, gateway: &Gateway, request: &Request, txn: &Txn) -> TCResult<u64> {
+        debug!("connector {} polling {}", self.name, self.source);
+
+        let records = match gateway.fetch(request, txn, &self.source).await? {
+            Scalar::Tuple(records) => records.into_inner(),
+            Scalar::Map(record) => vec![Scalar::Map(record)],
+            other => {
+                return Err(error::bad_request(
+                    "Connector source must return a list of records, not",
+                    other,
+                ))
+            }
+        };
+
+        let mut imported = 0;
+        for record in records.into_iter() {
+            let params = Map::<Scalar>::try_from(record)?;
+            let row = self
+                .transform
+                .handler(None)
+                .post(request, txn, params)
+                .await?;
+
+            let row = Scalar::try_from(row)?;
+            let row = Map::<Scalar>::try_from(row)?;
+            let row = Row::try_cast_from(row, |r| {
+                error::bad_request("Connector transform must return a row, not", r)
+            })?;
+
+            let (key, values) = self.schema.key_values_from_row(row)?;
+            gateway
+                .put(
+                    request,
+                    txn,
+                    &self.target,
+                    Value::Tuple(key.into()),
+                    Value::Tuple(values.into()).into(),
+                )
+                .await?;
+
+            imported += 1;
+        }
+
+        Ok(imported)
+    }
+
+    /// Load the connector stored at `store`, with its history.
+    fn load(name: Id, store: Persistent) -> TCResult<Connector> {
+        let stored = store
+            .load()?
+            .ok_or_else(|| error::internal(format!("Connector {} has no stored state", name)))?;
+
+        let (def, history) = decode(stored)?;
+        let mut connector = Connector::try_from_def(name, def, store)?;
+        connector.history = RwLock::new(history);
+        Ok(connector)
+    }
+
+    async fn save(&self) -> TCResult<()> {
+        let history: Vec<Value> = self.history.read().await.iter().cloned().collect();
+        self.store.save(|| encode(self.def.clone(), history)).await
+    }
+
+    async fn record(&self, poll: PollResult) {
+        {
+            let mut history = self.history.write().await;
+            if history.len() == HISTORY_LEN {
+                history.pop_front();
+            }
+
+            history.push_back(poll.into());
+        }
+
+        // a connector which has been replaced or deleted must not overwrite the stored state
+        if self.is_running() {
+            if let Err(cause) = self.save().await {
+                warn!("unable to save the history of {}: {}", self, cause);
+            }
+        }
+    }
+
+    async fn status(&self) -> Map<Scalar> {
+        let history = self.history.read().await;
+        let history: Vec<Value> = history.iter().cloned().collect();
+
+        let status = vec![
+            (label("source").into(), Value::from(self.source.clone())),
+            (label("target").into(), Value::from(self.target.clone())),
+            (
+                label("interval").into(),
+                Value::from(self.interval.as_secs()),
+            ),
+            (label("running").into(), Value::from(self.is_running())),
+            (label("history").into(), Value::from(history)),
+        ];
+
+        status
+            .into_iter()
+            .map(|(id, value): (Id, Value)| (id, Scalar::Value(value)))
+            .collect()
+    }
+}
+
+impl fmt::Display for Connector {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "connector {} ({} -> {})",
+            self.name, self.source, self.target
+        )
+    }
+}
+
+/// Encode the stored state of a connector.
+fn encode(def: Map<Scalar>, history: Vec<Value>) -> Scalar {
+    Scalar::Tuple(vec![Scalar::Map(def), Scalar::Value(Value::from(history))].into())
+}
+
+/// Decode the stored state of a connector.
+fn decode(stored: Scalar) -> TCResult<(Map<Scalar>, VecDeque<Value>)> {
+    let err = |s: &Scalar| error::internal(format!("Invalid stored connector: {}", s));
+
+    let (def, history): (Scalar, Value) = stored.try_cast_into(err)?;
+    let def = Map::<Scalar>::try_from(def)?;
+    let history: Vec<Value> = history
+        .try_cast_into(|v| error::internal(format!("Invalid stored connector history: {}", v)))?;
+
+    Ok((def, history.into_iter().collect()))
+}
+
+/// Return an error unless the caller of `request` may define a connector.
+fn authorize(request: &Request) -> TCResult<()> {
+    match request.auth() {
+        Some(token) => token.validate(SCOPE_WRITE.into(), "/sbin/connectors"),
+        None => Err(error::unauthorized(
+            "Defining a connector requires a bearer token",
+        )),
+    }
+}
+
+fn spawn(gateway: Arc<Gateway>, connector: Arc<Connector>, ttl: Duration) {
+    tokio::spawn(async move {
+        while connector.is_running() {
+            let poll = connector.poll(&gateway, ttl).await;
+            connector.record(poll).await;
+            tokio::time::sleep(connector.interval).await;
+        }
+
+        debug!("stopped {}", connector);
+    });
+}
+
+/// A change to the set of connectors, applied when the transaction which made it commits.
+struct Staged {
+    gateway: Arc<Gateway>,
+    request_ttl: Duration,
+    connectors: RwLock<HashMap<Id, Arc<Connector>>>,
+    name: Id,
+    connector: Option<Arc<Connector>>,
+}
+
+#[async_trait]
+impl Transact for Staged {
+    async fn commit(&self, txn_id: &TxnId) {
+        let previous = if let Some(connector) = &self.connector {
+            if let Err(cause) = connector.save().await {
+                warn!(
+                    "unable to save {} at commit of {}: {}",
+                    connector, txn_id, cause
+                );
+            }
+
+            let mut connectors = self.connectors.write().await;
+            connectors.insert(self.name.clone(), connector.clone())
+        } else {
+            self.connectors.write().await.remove(&self.name)
+        };
+
+        if let Some(previous) = previous {
+            debug!("stopping {}", previous);
+            previous.stop();
+
+            if self.connector.is_none() {
+                if let Err(cause) = previous.store.delete().await {
+                    warn!(
+                        "unable to delete {} at commit of {}: {}",
+                        previous, txn_id, cause
+                    );
+                }
+            }
+        }
+
+        if let Some(connector) = &self.connector {
+            spawn(self.gateway.clone(), connector.clone(), self.request_ttl);
+        }
+    }
+
+    async fn rollback(&self, _txn_id: &TxnId) {
+        // no-op
+    }
+
+    async fn finalize(&self, _txn_id: &TxnId) {
+        // no-op
+    }
+}
+
+/// The set of import connectors available at `/sbin/connectors`.
+///
+/// A connector which is defined or deleted in a transaction starts or stops polling only when
+/// that transaction commits.
+pub struct Connectors {
+    dir: PathBuf,
+    request_ttl: Duration,
+    connectors: RwLock<HashMap<Id, Arc<Connector>>>,
+}
+
+impl Connectors {
+    /// Load the connectors stored in `data_dir`. They start polling when `resume` is called.
+    pub fn load(data_dir: &Path, request_ttl: Duration) -> TCResult<Connectors> {
+        let dir = sbin_dir(data_dir, "connectors");
+
+        let mut connectors = HashMap::new();
+        for name in Persistent::list(&dir)? {
+            let name: Id = name.parse()?;
+            let store = Persistent::new(dir.clone(), name.as_str());
+            let connector = Connector::load(name.clone(), store)?;
+            connectors.insert(name, Arc::new(connector));
+        }
+
+        Ok(Connectors {
+            dir,
+            request_ttl,
+            connectors: RwLock::new(connectors),
+        })
+    }
+
+    /// Start polling each connector loaded by `load`.
+    pub async fn resume(&self, gateway: &Arc<Gateway>) {
+        for connector in self.connectors.read().await.values() {
+            debug!("resuming {}", connector);
+            spawn(gateway.clone(), connector.clone(), self.request_ttl);
+        }
+    }
+
+    async fn stage(&self, txn: &Txn, name: Id, connector: Option<Connector>) {
+        let staged = Staged {
+            gateway: txn.gateway().clone(),
+            request_ttl: self.request_ttl,
+            connectors: self.connectors.clone(),
+            name,
+            connector: connector.map(Arc::new),
+        };
+
+        txn.enlist(Box::new(staged)).await;
+    }
+}
+
+#[async_trait]
+impl Public for Connectors {
+    async fn get(
+        &self,
+        _request: &Request,
+        _txn: &Txn,
+        path: &[PathSegment],
+        key: Value,
+    ) -> TCResult<State> {
+        if !key.is_none() {
+            return Err(error::bad_request(
+                "/sbin/connectors takes no key, but found",
+                key,
+            ));
+        }
+
+        let connectors = self.connectors.read().await;
+
+        if path.is_empty() {
+            let names: Vec<Id> = connectors.keys().cloned().collect();
+            Ok(State::from(Value::from(names)))
+        } else if path.len() == 1 {
+            let connector = connectors
+                .get(&path[0])
+                .ok_or_else(|| error::not_found(&path[0]))?;
+
+            let status = connector.status().await;
+            Ok(State::Scalar(Scalar::Map(status)))
+        } else {
+            Err(error::path_not_found(path))
+        }
+    }
+
+    async fn put(
+        &self,
+        request: &Request,
+        txn: &Txn,
+        path: &[PathSegment],
+        key: Value,
+        value: State,
+    ) -> TCResult<()> {
+        if path.len() != 1 {
+            return Err(error::method_not_allowed(TCPath::from(path)));
+        } else if !key.is_none() {
+            return Err(error::bad_request(
+                "/sbin/connectors takes no key, but found",
+                key,
+            ));
+        }
+
+        authorize(request)?;
+
+        let def = Scalar::try_from(value)?;
+        let def = Map::<Scalar>::try_from(def)?;
+        let store = Persistent::new(self.dir.clone(), path[0].as_str());
+        let connector = Connector::try_from_def(path[0].clone(), def, store)?;
+        self.stage(txn, path[0].clone(), Some(connector)).await;
+        Ok(())
+    }
+
+    async fn post(
+        &self,
+        _request: &Request,
+        _txn: &Txn,
+        path: &[PathSegment],
+        _params: Map<Scalar>,
+    ) -> TCResult<State> {
+        Err(error::method_not_allowed(TCPath::from(path)))
+    }
+
+    async fn delete(
+        &self,
+        request: &Request,
+        txn: &Txn,
+        path: &[PathSegment],
+        key: Value,
+    ) -> TCResult<()> {
+        if path.len() != 1 {
+            return Err(error::method_not_allowed(TCPath::from(path)));
+        } else if !key.is_none() {
+            return Err(error::bad_request(
+                "/sbin/connectors takes no key, but found",
+                key,
+            ));
+        }
+
+        authorize(request)?;
+
+        if self.connectors.read().await.contains_key(&path[0]) {
+            self.stage(txn, path[0].clone(), None).await;
+            Ok(())
+        } else {
+            Err(error::not_found(&path[0]))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn def(source: &str) -> Map<Scalar> {
+        let source: Link = source.parse().unwrap();
+        vec![(label("source").into(), Scalar::Value(Value::from(source)))]
+            .into_iter()
+            .collect()
+    }
+
+    #[test]
+    fn test_remote_source() {
+        let store = Persistent::new(std::env::temp_dir(), "connector");
+        let name: Id = "remote".parse().unwrap();
+        let result = Connector::try_from_def(name, def("http://10.0.0.1:8702/secret"), store);
+        match result {
+            Err(cause) => assert!(cause.reason() == &error::ErrorType::Forbidden),
+            Ok(_) => panic!("a connector must not read from another host"),
+        }
+    }
+
+    #[test]
+    fn test_authorize() {
+        let request = Request::new(Duration::from_secs(30), None, None, None);
+        assert!(authorize(&request).is_err());
+    }
+
+    #[test]
+    fn test_encode_decode() {
+        let history = vec![Value::from(3u64), Value::from(4u64)];
+        let stored = encode(def("/ext/feed"), history);
+        let (def, history) = decode(stored).unwrap();
+        let source: Id = label("source").into();
+        assert!(def.contains_key(&source));
+        assert_eq!(history.len(), 2);
+    }
+}
@@ -0,0 +1,355 @@
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use async_trait::async_trait;
+use log::{debug, warn};
+
+use crate::class::State;
+use crate::error;
+use crate::general::Map;
+use crate::handler::Public;
+use crate::request::Request;
+use crate::scalar::*;
+use crate::transaction::Txn;
+use crate::TCResult;
+
+const POLL_INTERVAL: Duration = Duration::from_secs(10);
+
+struct Volume {
+    name: &'static str,
+    path: PathBuf,
+    quota: Option<u64>,
+    used: AtomicU64,
+    full: AtomicBool,
+}
+
+impl Volume {
+    fn warn_at(&self, warn_percent: u8) -> Option<u64> {
+        self.quota.map(|quota| quota / 100 * warn_percent as u64)
+    }
+
+    fn update(&self, used: u64, warn_percent: u8) {
+        self.used.store(used, Ordering::Relaxed);
+
+        let full = match self.quota {
+            Some(quota) => used >= quota,
+            None => false,
+        };
+
+        let was_full = self.full.swap(full, Ordering::Relaxed);
+        if full && !was_full {
+            warn!(
+                "{} at {:?} is full ({} of {} bytes), new writes will be rejected",
+                self.name,
+                self.path,
+                used,
+                self.quota.unwrap()
+            );
+        } else if was_full && !full {
+            warn!(
+                "{} at {:?} has space again, accepting writes",
+                self.name, self.path
+            );
+        } else if let Some(warn_at) = self.warn_at(warn_percent) {
+            if used >= warn_at && !full {
+                warn!(
+                    "{} at {:?} is {}% full ({} of {} bytes)",
+                    self.name,
+                    self.path,
+                    used * 100 / self.quota.unwrap().max(1),
+                    used,
+                    self.quota.unwrap()
+                );
+            }
+        }
+    }
+}
+
+/// Monitors the disk usage of the host's `data_dir` and `workspace`, available at `/sbin/disk`.
+///
+/// Usage is measured periodically by walking each directory. Once a directory's usage passes
+/// `warn_percent` of its quota a warning is logged on every measurement, and once it reaches
+/// its quota new writes are rejected with an `OutOfSpace` error until space is freed again.
+/// Reads, and the cleanup of finished transactions, are never rejected.
+#[derive(Clone)]
+pub struct DiskMonitor {
+    volumes: Arc<Vec<Volume>>,
+    warn_percent: u8,
+}
+
+impl DiskMonitor {
+    pub fn new(
+        data_dir: PathBuf,
+        data_dir_quota: Option<usize>,
+        workspace: PathBuf,
+        workspace_quota: Option<usize>,
+        warn_percent: u8,
+    ) -> DiskMonitor {
+        let volume = |name, path, quota: Option<usize>| Volume {
+            name,
+            path,
+            quota: quota.map(|quota| quota as u64),
+            used: AtomicU64::new(0),
+            full: AtomicBool::new(false),
+        };
+
+        DiskMonitor {
+            volumes: Arc::new(vec![
+                volume("data_dir", data_dir, data_dir_quota),
+                volume("workspace", workspace, workspace_quota),
+            ]),
+            warn_percent: warn_percent.min(100),
+        }
+    }
+
+    /// Measure disk usage now, and every few seconds until the host shuts down.
+    pub fn start(&self) {
+        if self.volumes.iter().all(|volume| volume.quota.is_none()) {
+            debug!("no disk quota configured, disk usage will only be measured on request");
+            return;
+        }
+
+        let monitor = self.clone();
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(POLL_INTERVAL);
+            loop {
+                interval.tick().await;
+                monitor.measure().await;
+            }
+        });
+    }
+
+    /// Return an `OutOfSpace` error if any monitored directory has reached its quota.
+    pub fn admit(&self) -> TCResult<()> {
+        for volume in self.volumes.iter() {
+            if volume.full.load(Ordering::Relaxed) {
+                return Err(error::out_of_space(format!(
+                    "{} is full ({} of {} bytes), so this host cannot accept new writes",
+                    volume.name,
+                    volume.used.load(Ordering::Relaxed),
+                    volume.quota.unwrap_or(0)
+                )));
+            }
+        }
+
+        Ok(())
+    }
+
+    async fn measure(&self) {
+        for volume in self.volumes.iter() {
+            let path = volume.path.clone();
+            let used = tokio::task::spawn_blocking(move || dir_size(&path)).await;
+
+            match used {
+                Ok(Ok(used)) => volume.update(used, self.warn_percent),
+                Ok(Err(cause)) => warn!(
+                    "unable to measure disk usage of {:?}: {}",
+                    volume.path, cause
+                ),
+                Err(cause) => warn!(
+                    "unable to measure disk usage of {:?}: {}",
+                    volume.path, cause
+                ),
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl Public for DiskMonitor {
+    async fn get(
+        &self,
+        _request: &Request,
+        _txn: &Txn,
+        path: &[PathSegment],
+        key: Value,
+    ) -> TCResult<State> {
+        if !path.is_empty() {
+            return Err(error::path_not_found(path));
+        } else if !key.is_none() {
+            return Err(error::bad_request(
+                "/sbin/disk takes no key, but found",
+                key,
+            ));
+        }
+
+        self.measure().await;
+
+        let usage: Map<Scalar> = self
+            .volumes
+            .iter()
+            .map(|volume| {
+                let quota = volume.quota.map(Value::from).unwrap_or(Value::None);
+                let usage = Value::Tuple(
+                    vec![
+                        Value::from(volume.used.load(Ordering::Relaxed)),
+                        quota,
+                        Value::from(volume.full.load(Ordering::Relaxed)),
+                    ]
+                    .into(),
+                );
+
+                (label(volume.name).into(), Scalar::Value(usage))
+            })
+            .collect();
+
+        Ok(State::Scalar(Scalar::Map(usage)))
+    }
+
+    async fn put(
+        &self,
+        _request: &Request,
+        _txn: &Txn,
+        path: &[PathSegment],
+        _key: Value,
+        _value: State,
+    ) -> TCResult<()> {
+        Err(error::method_not_allowed(TCPath::from(path)))
+    }
+
+    async fn post(
+        &self,
+        _request: &Request,
+        _txn: &Txn,
+        path: &[PathSegment],
+        _params: Map<Scalar>,
+    ) -> TCResult<State> {
+        Err(error::method_not_allowed(TCPath::from(path)))
+    }
+
+    async fn delete(
+        &self,
+        _request: &Request,
+        _txn: &Txn,
+        path: &[PathSegment],
+        _key: Value,
+    ) -> TCResult<()> {
+        Err(error::method_not_allowed(TCPath::from(path)))
+    }
+}
+
+fn dir_size(path: &Path) -> io::Result<u64> {
+    let mut size = 0;
+    let entries = match std::fs::read_dir(path) {
+        Ok(entries) => entries,
+        Err(cause) if cause.kind() == io::ErrorKind::NotFound => return Ok(0),
+        Err(cause) => return Err(cause),
+    };
+
+    for entry in entries {
+        let entry = entry?;
+        let metadata = entry.metadata()?;
+        if metadata.is_dir() {
+            size += dir_size(&entry.path())?;
+        } else {
+            size += metadata.len();
+        }
+    }
+
+    Ok(size)
+}
+
+#[cfg(test)]
+mod tests {
+    use uuid::Uuid;
+
+    use crate::error::ErrorType;
+
+    use super::*;
+
+    fn monitor(quota: Option<usize>) -> DiskMonitor {
+        let root = std::env::temp_dir().join(Uuid::new_v4().to_string());
+        DiskMonitor::new(root.join("data"), quota, root.join("workspace"), None, 80)
+    }
+
+    #[test]
+    fn test_admit() {
+        let monitor = monitor(Some(1000));
+        assert!(monitor.admit().is_ok());
+
+        let data_dir = &monitor.volumes[0];
+        data_dir.update(999, monitor.warn_percent);
+        assert!(monitor.admit().is_ok());
+
+        data_dir.update(1000, monitor.warn_percent);
+        let cause = monitor.admit().unwrap_err();
+        assert!(cause.reason() == &ErrorType::OutOfSpace);
+        assert!(cause
+            .message()
+            .contains("data_dir is full (1000 of 1000 bytes)"));
+
+        data_dir.update(500, monitor.warn_percent);
+        assert!(monitor.admit().is_ok());
+    }
+
+    #[test]
+    fn test_no_quota() {
+        let monitor = monitor(None);
+        for volume in monitor.volumes.iter() {
+            volume.update(u64::MAX, monitor.warn_percent);
+            assert_eq!(volume.warn_at(monitor.warn_percent), None);
+        }
+
+        assert!(monitor.admit().is_ok());
+    }
+
+    #[test]
+    fn test_warn_at() {
+        let monitor = monitor(Some(1000));
+        assert_eq!(monitor.volumes[0].warn_at(monitor.warn_percent), Some(800));
+
+        let monitor = DiskMonitor::new(
+            PathBuf::from("data"),
+            Some(1000),
+            PathBuf::from("workspace"),
+            None,
+            250,
+        );
+        assert_eq!(monitor.warn_percent, 100);
+        assert_eq!(monitor.volumes[0].warn_at(monitor.warn_percent), Some(1000));
+    }
+
+    #[test]
+    fn test_dir_size() {
+        let root = std::env::temp_dir().join(Uuid::new_v4().to_string());
+        assert_eq!(dir_size(&root).unwrap(), 0);
+
+        std::fs::create_dir_all(root.join("a").join("b")).unwrap();
+        std::fs::write(root.join("one"), [0u8; 10]).unwrap();
+        std::fs::write(root.join("a").join("two"), [0u8; 20]).unwrap();
+        std::fs::write(root.join("a").join("b").join("three"), [0u8; 30]).unwrap();
+        assert_eq!(dir_size(&root).unwrap(), 60);
+
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_measure() {
+        let monitor = monitor(Some(16));
+        let data_dir = monitor.volumes[0].path.clone();
+        std::fs::create_dir_all(&data_dir).unwrap();
+
+        monitor.measure().await;
+        assert_eq!(monitor.volumes[0].used.load(Ordering::Relaxed), 0);
+        assert!(monitor.admit().is_ok());
+
+        std::fs::write(data_dir.join("block"), [0u8; 16]).unwrap();
+        monitor.measure().await;
+        assert_eq!(monitor.volumes[0].used.load(Ordering::Relaxed), 16);
+        assert!(monitor.admit().unwrap_err().reason() == &ErrorType::OutOfSpace);
+
+        std::fs::remove_file(data_dir.join("block")).unwrap();
+        monitor.measure().await;
+        assert!(monitor.admit().is_ok());
+
+        std::fs::remove_dir_all(data_dir.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn test_status_code() {
+        assert!(ErrorType::from(507) == ErrorType::OutOfSpace);
+    }
+}
@@ -28,6 +28,12 @@ pub enum ErrorType {
     // "This is marked for implementation in the future"
     NotImplemented,
 
+    // "This host is too low on disk space to accept new writes"
+    OutOfSpace,
+
+    // "This host doesn't have enough of some resource (like device memory) to handle your request"
+    ResourceExhausted,
+
     // "The request failed to complete in the allotted time"
     Timeout,
 
@@ -74,6 +80,8 @@ impl NativeClass for ErrorType {
                 "transport" => Self::Transport,
                 "internal" => Self::Internal,
                 "not_implemented" => Self::NotImplemented,
+                "out_of_space" => Self::OutOfSpace,
+                "resource_exhausted" => Self::ResourceExhausted,
                 "unknown" => Self::Unknown,
                 other => return Err(not_found(other)),
             };
@@ -105,6 +113,8 @@ impl From<u16> for ErrorType {
             499 => Transport,
             500 => Internal,
             501 => NotImplemented,
+            503 => ResourceExhausted,
+            507 => OutOfSpace,
             _ => Unknown,
         }
     }
@@ -126,6 +136,8 @@ impl From<ErrorType> for Link {
             Transport => label("transport"),
             Internal => label("internal"),
             NotImplemented => label("not_implemented"),
+            OutOfSpace => label("out_of_space"),
+            ResourceExhausted => label("resource_exhausted"),
             Unknown => label("unknown"),
         };
 
@@ -143,6 +155,8 @@ impl fmt::Display for ErrorType {
             ErrorType::MethodNotAllowed => write!(f, "Method not allowed"),
             ErrorType::NotFound => write!(f, "Not found"),
             ErrorType::NotImplemented => write!(f, "Not implemented"),
+            ErrorType::OutOfSpace => write!(f, "Insufficient storage"),
+            ErrorType::ResourceExhausted => write!(f, "Resource exhausted"),
             ErrorType::Timeout => write!(f, "Timeout"),
             ErrorType::TooLarge => write!(f, "Request too large"),
             ErrorType::Transport => write!(f, "Transport protocol error"),
@@ -267,6 +281,14 @@ pub fn unsupported<I: fmt::Display>(hint: I) -> TCError {
     TCError::of(ErrorType::BadRequest, hint.to_string())
 }
 
+pub fn out_of_space<I: fmt::Display>(info: I) -> TCError {
+    TCError::of(ErrorType::OutOfSpace, info.to_string())
+}
+
+pub fn resource_exhausted<I: fmt::Display>(info: I) -> TCError {
+    TCError::of(ErrorType::ResourceExhausted, info.to_string())
+}
+
 pub fn timeout<I: fmt::Display>(info: I) -> TCError {
     TCError::of(ErrorType::Timeout, info.to_string())
 }
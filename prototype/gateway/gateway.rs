@@ -1,5 +1,7 @@
 use std::collections::HashSet;
+use std::iter;
 use std::net::IpAddr;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
 use std::time::Duration;
 
@@ -8,27 +10,42 @@ use futures::stream;
 use log::debug;
 
 use crate::auth::Token;
+use crate::blob::BlobStore;
+use crate::block::hostfs::Migrations;
 use crate::block::Dir;
 use crate::class::State;
+use crate::config::{self, HostConfig};
+use crate::connector::Connectors;
+use crate::disk::DiskMonitor;
 use crate::error;
+use crate::general::Map;
 use crate::handler::Public;
 use crate::kernel;
+use crate::registry::SchemaRegistry;
 use crate::request::Request;
-use crate::scalar::{Id, Link, Scalar, TCPath, Value};
+use crate::routes::{self, Method, Namespace, Native, Routes};
+use crate::scalar::{label, Id, Link, PathSegment, Scalar, TCPath, Value};
+use crate::sequence::Sequences;
 use crate::transaction::{Txn, TxnServer};
-use crate::{TCBoxTryFuture, TCResult, TryCastInto};
+use crate::{TCBoxTryFuture, TCResult, TryCastFrom, TryCastInto};
 
 use super::http;
-use super::{Hosted, NetworkTime, Server};
+use super::{Hosted, NetworkTime, Placement, Server};
 
 const ERR_BAD_POST_DATA: &str = "POST requires a list of (Id, Value) tuples, not";
 
 pub struct Gateway {
     adapters: Vec<Link>,
     hosted: Hosted,
+    placement: Placement,
+    blobs: BlobStore,
     client: http::Client,
-    request_limit: usize,
-    request_ttl: Duration,
+    config: HostConfig,
+    connectors: Connectors,
+    disk: DiskMonitor,
+    migrations: Migrations,
+    routes: Routes,
+    sequences: Sequences,
     txn_server: TxnServer,
 }
 
@@ -40,9 +57,21 @@ impl Gateway {
     pub fn new(
         adapters: Vec<Link>,
         hosted: Hosted,
+        placement: Placement,
         workspace: Arc<Dir>,
+        data_dir: &Path,
+        blob_dir: PathBuf,
+        cache_size: usize,
+        rate_limit: Option<u64>,
+        blob_grace_period: Duration,
+        disk: DiskMonitor,
+        migrations: Migrations,
         request_limit: usize,
         request_ttl: Duration,
+        decode_parallelism: usize,
+        device_memory_limit: Option<usize>,
+        einsum_working_set: usize,
+        strict_promotion: bool,
     ) -> TCResult<Gateway> {
         let mut adapter_uris = HashSet::new();
         for adapter in &adapters {
@@ -60,15 +89,45 @@ impl Gateway {
             }
         }
 
+        let routes = Routes::new(
+            hosted.paths().chain(placement.paths()),
+            adapters.iter().map(|adapter| adapter.path()),
+        )?;
+
         let client = http::Client::new(request_ttl, request_limit);
         let txn_server = TxnServer::new(workspace.clone());
+        let config = HostConfig::load(
+            data_dir,
+            cache_size,
+            log::max_level(),
+            rate_limit,
+            request_limit,
+            request_ttl,
+            decode_parallelism,
+            device_memory_limit,
+            einsum_working_set,
+            strict_promotion,
+        )?;
+        config.apply_cache_size()?;
+        config.apply_log_level()?;
+        disk.start();
+
+        let blobs = BlobStore::new(blob_dir, blob_grace_period);
+        let connectors = Connectors::load(data_dir, request_ttl)?;
+        let sequences = Sequences::load(data_dir)?;
 
         Ok(Gateway {
             adapters,
             hosted,
+            placement,
+            blobs,
             client,
-            request_limit,
-            request_ttl,
+            config,
+            connectors,
+            disk,
+            migrations,
+            routes,
+            sequences,
             txn_server,
         })
     }
@@ -77,22 +136,131 @@ impl Gateway {
         Err(error::not_implemented("Gateway::authenticate"))
     }
 
+    pub fn blobs(&'_ self) -> &'_ BlobStore {
+        &self.blobs
+    }
+
+    pub fn config(&'_ self) -> &'_ HostConfig {
+        &self.config
+    }
+
+    pub fn connectors(&'_ self) -> &'_ Connectors {
+        &self.connectors
+    }
+
+    pub fn disk(&'_ self) -> &'_ DiskMonitor {
+        &self.disk
+    }
+
+    pub fn migrations(&'_ self) -> &'_ Migrations {
+        &self.migrations
+    }
+
+    /// Return the schema registry of the cluster hosted at the start of `path`, and the rest of
+    /// `path`, which is the name of a schema in that registry.
+    pub fn schemas<'a>(
+        &self,
+        path: &'a [PathSegment],
+    ) -> TCResult<(&'a [PathSegment], SchemaRegistry)> {
+        match self.hosted.get(path) {
+            Some((name, cluster)) => Ok((name, cluster.schemas().clone())),
+            None => Err(error::not_found(format!(
+                "cluster hosting schema {}",
+                TCPath::from(path)
+            ))),
+        }
+    }
+
+    /// Return the schema registry of each cluster hosted by this gateway.
+    pub fn registries(&self) -> Vec<SchemaRegistry> {
+        self.hosted
+            .paths()
+            .filter_map(|path| self.hosted.get(path))
+            .map(|(_, cluster)| cluster.schemas().clone())
+            .collect()
+    }
+
+    /// Reload the tensors of each cluster hosted by this host from `data_dir`.
+    pub async fn mount(self: &Arc<Self>) -> TCResult<()> {
+        for path in self.hosted.paths() {
+            if let Some((_, cluster)) = self.hosted.get(path) {
+                cluster.mount(self).await?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Start replicating each cluster which this host directs to its actors.
+    pub fn replicate(self: &Arc<Self>) -> TCResult<()> {
+        let ttl = self.config.request_ttl()?;
+        for path in self.hosted.paths() {
+            if let Some((_, cluster)) = self.hosted.get(path) {
+                cluster.replicate(self, ttl);
+            }
+        }
+
+        Ok(())
+    }
+
+    // report the lag of each actor of each cluster which this host directs
+    async fn metrics(&self, path: &[PathSegment], key: Value) -> TCResult<State> {
+        if !path.is_empty() {
+            return Err(error::path_not_found(path));
+        } else if !key.is_none() {
+            return Err(error::bad_request(
+                "/sbin/metrics takes no key, but found",
+                key,
+            ));
+        }
+
+        let mut replication = vec![];
+        for path in self.hosted.paths() {
+            if let Some((_, cluster)) = self.hosted.get(path) {
+                let lag = cluster.lag().await;
+                if !lag.is_empty() {
+                    let cluster = Link::from(path.clone());
+                    replication.push(Value::Tuple(
+                        vec![Value::from(cluster), Value::Tuple(lag.into())].into(),
+                    ));
+                }
+            }
+        }
+
+        let metrics: Map<Scalar> = iter::once((
+            label("replication").into(),
+            Scalar::Value(Value::Tuple(replication.into())),
+        ))
+        .collect();
+
+        Ok(State::Scalar(Scalar::Map(metrics)))
+    }
+
+    pub fn sequences(&'_ self) -> &'_ Sequences {
+        &self.sequences
+    }
+
     pub async fn transaction(self: &Arc<Self>, request: &Request) -> TCResult<Txn> {
         self.txn_server
             .new_txn(self.clone(), request.txn_id().clone())
             .await
     }
 
+    /// Read the `Scalar` at `source`.
+    pub async fn fetch(&self, request: &Request, txn: &Txn, source: &Link) -> TCResult<Scalar> {
+        debug!("Gateway::fetch {}", source);
+        let state = self.get(request, txn, source, Value::None).await?;
+        Scalar::try_cast_from(state, |s| {
+            error::bad_request("Expected a Scalar but found", s)
+        })
+    }
+
     pub async fn http_listen(
         self: Arc<Self>,
         address: IpAddr,
         port: u16,
     ) -> Result<(), hyper::Error> {
-        let server = Arc::new(super::HttpServer::new(
-            (address, port).into(),
-            self.request_limit,
-            self.request_ttl,
-        ));
+        let server = Arc::new(super::HttpServer::new((address, port).into()));
 
         server.listen(self).await
     }
@@ -107,12 +275,55 @@ impl Gateway {
         debug!("Gateway::get {}", subject);
 
         if subject.host().is_some() {
-            Err(error::not_implemented("Gateway::get over the network"))
+            self.client
+                .get(request, txn, subject, &key)
+                .map_ok(State::Scalar)
+                .await
         } else if subject.path().as_slice().len() > 1 {
             let path = subject.path();
-            if &path[0] == "sbin" {
-                kernel::get(txn, &path[..], key).await
-            } else if &path[0] == "ext" {
+            let namespace = routes::namespace(path);
+            if namespace == Namespace::Native {
+                match routes::resolve(path, Method::Get)? {
+                    Native::Blobs => self.blobs.get(request, txn, &path[2..], key).await,
+                    Native::Config => self.config.get(request, txn, &path[2..], key).await,
+                    Native::Connectors => self.connectors.get(request, txn, &path[2..], key).await,
+                    Native::Disk => self.disk.get(request, txn, &path[2..], key).await,
+                    Native::LogLevel if path.len() == 2 => {
+                        let setting = [label(config::LOG_LEVEL).into()];
+                        self.config.get(request, txn, &setting, key).await
+                    }
+                    Native::LogLevel => Err(error::path_not_found(path)),
+                    Native::Metrics => self.metrics(&path[2..], key).await,
+                    Native::Migrate => self.migrations.get(request, txn, &path[2..], key).await,
+                    Native::Routes => self.routes.get(request, txn, &path[2..], key).await,
+                    Native::Schema if path.len() == 2 => {
+                        let clusters: Vec<Value> = self
+                            .hosted
+                            .paths()
+                            .map(|cluster| {
+                                let mut path = SchemaRegistry::prefix();
+                                path.extend(cluster.iter().cloned());
+                                Value::from(Link::from(path))
+                            })
+                            .collect();
+
+                        Ok(State::from(Value::from(clusters)))
+                    }
+                    Native::Schema => {
+                        let (name, schemas) = self.schemas(&path[2..])?;
+                        schemas.get(request, txn, name, key).await
+                    }
+                    Native::Sequence => self.sequences.get(request, txn, &path[2..], key).await,
+                    Native::Chain | Native::Collection | Native::Error | Native::Value => {
+                        kernel::get(txn, &path[..], key).await
+                    }
+                    #[cfg(feature = "graphql")]
+                    Native::GraphQL => Err(error::method_not_allowed(path)),
+                    #[cfg(feature = "sql")]
+                    Native::Sql => Err(error::method_not_allowed(path)),
+                    Native::Object | Native::Transact => Err(error::method_not_allowed(path)),
+                }
+            } else if namespace == Namespace::Adapter {
                 for adapter in &self.adapters {
                     if path.as_slice().starts_with(adapter.path()) {
                         let host = adapter.host().as_ref().unwrap();
@@ -129,6 +340,13 @@ impl Gateway {
             } else if let Some((suffix, cluster)) = self.hosted.get(path) {
                 debug!("Gateway::get {}{}: {}", cluster, TCPath::from(suffix), key);
                 cluster.get(request, txn, &suffix[..], key).await
+            } else if let Some(owner) = self.placement.locate(path) {
+                let dest: Link = (owner.clone(), path.clone()).into();
+                debug!("Gateway::get forward to {}", dest);
+                self.client
+                    .get(request, txn, &dest, &key)
+                    .map_ok(State::Scalar)
+                    .await
             } else {
                 Err(error::not_found(path))
             }
@@ -148,11 +366,69 @@ impl Gateway {
         debug!("Gateway::put {}: {} <- {}", subject, selector, state);
 
         if subject.host().is_some() {
-            Err(error::not_implemented("Gateway::put over the network"))
+            let value = Scalar::try_cast_from(state, |s| {
+                error::bad_request("Cannot send over the network", s)
+            })?;
+
+            self.client
+                .put(request, txn, subject, &selector, value)
+                .await
         } else {
             let path = subject.path();
-            if &path[0] == "sbin" {
-                return Err(error::method_not_allowed("/sbin is immutable"));
+            if routes::namespace(path) == Namespace::Native {
+                return match routes::resolve(path, Method::Put)? {
+                    Native::Blobs => {
+                        self.disk.admit()?;
+                        self.blobs
+                            .put(request, txn, &path[2..], selector, state)
+                            .await
+                    }
+                    Native::Config => {
+                        // the host settings must stay writable,
+                        // so that an operator can free up space
+                        self.config
+                            .put(request, txn, &path[2..], selector, state)
+                            .await
+                    }
+                    Native::Connectors => {
+                        self.disk.admit()?;
+                        self.connectors
+                            .put(request, txn, &path[2..], selector, state)
+                            .await
+                    }
+                    Native::LogLevel if path.len() == 2 => {
+                        let setting = [label(config::LOG_LEVEL).into()];
+                        self.config
+                            .put(request, txn, &setting, selector, state)
+                            .await
+                    }
+                    Native::LogLevel => Err(error::path_not_found(path)),
+                    Native::Schema => {
+                        self.disk.admit()?;
+                        let (name, schemas) = self.schemas(&path[2..])?;
+                        schemas.put(request, txn, name, selector, state).await
+                    }
+                    Native::Sequence => {
+                        self.disk.admit()?;
+                        self.sequences
+                            .put(request, txn, &path[2..], selector, state)
+                            .await
+                    }
+                    #[cfg(feature = "graphql")]
+                    Native::GraphQL => Err(error::method_not_allowed(path)),
+                    #[cfg(feature = "sql")]
+                    Native::Sql => Err(error::method_not_allowed(path)),
+                    Native::Chain
+                    | Native::Collection
+                    | Native::Disk
+                    | Native::Error
+                    | Native::Metrics
+                    | Native::Migrate
+                    | Native::Object
+                    | Native::Routes
+                    | Native::Transact
+                    | Native::Value => Err(error::method_not_allowed(path)),
+                };
             }
 
             if let Some((suffix, cluster)) = self.hosted.get(path) {
@@ -165,6 +441,15 @@ impl Gateway {
                 );
 
                 cluster.put(request, txn, suffix, selector, state).await
+            } else if let Some(owner) = self.placement.locate(path) {
+                let dest: Link = (owner.clone(), path.clone()).into();
+                debug!("Gateway::put forward to {}", dest);
+
+                let value = Scalar::try_cast_from(state, |s| {
+                    error::bad_request("Cannot send over the network", s)
+                })?;
+
+                self.client.put(request, txn, &dest, &selector, value).await
             } else {
                 Err(error::path_not_found(path))
             }
@@ -181,10 +466,7 @@ impl Gateway {
         Box::pin(async move {
             debug!("Gateway::post {}", subject);
 
-            if subject.host().is_none()
-                && !subject.path().is_empty()
-                && &subject.path()[0] == "sbin"
-            {
+            if subject.host().is_none() && routes::namespace(subject.path()) == Namespace::Native {
                 return kernel::post(request, txn, &subject.into_path()[..], data).await;
             }
 
@@ -197,6 +479,12 @@ impl Gateway {
                     cluster
                         .post(request, txn, suffix, data.into_iter().collect())
                         .await
+                } else if let Some(owner) = self.placement.locate(path) {
+                    let dest: Link = (owner.clone(), path.clone()).into();
+                    debug!("Gateway::post forward to {}", dest);
+                    self.client
+                        .post(request, txn, dest, stream::iter(data.into_iter()))
+                        .await
                 } else {
                     Err(error::path_not_found(path))
                 }
@@ -229,14 +517,42 @@ impl Gateway {
                     key
                 );
                 cluster.delete(request, txn, &suffix[..], key).await
+            } else if let Some(owner) = self.placement.locate(path) {
+                let dest: Link = (owner.clone(), path.clone()).into();
+                debug!("Gateway::delete forward to {}", dest);
+                self.client.delete(request, txn, &dest, &key).await
             } else {
-                match path[0].as_str() {
-                    "sbin" => match path[1].as_str() {
-                        "chain" | "cluster" | "collection" | "object" | "op" | "slice"
-                        | "value" => Err(error::method_not_allowed(&path[1])),
-                        other => Err(error::not_found(other)),
+                match routes::namespace(path) {
+                    Namespace::Native => match routes::resolve(path, Method::Delete)? {
+                        Native::Blobs => self.blobs.delete(request, txn, &path[2..], key).await,
+                        Native::Connectors => {
+                            self.connectors.delete(request, txn, &path[2..], key).await
+                        }
+                        Native::Schema => {
+                            let (name, schemas) = self.schemas(&path[2..])?;
+                            schemas.delete(request, txn, name, key).await
+                        }
+                        Native::Sequence => {
+                            self.sequences.delete(request, txn, &path[2..], key).await
+                        }
+                        #[cfg(feature = "graphql")]
+                        Native::GraphQL => Err(error::method_not_allowed(path)),
+                        #[cfg(feature = "sql")]
+                        Native::Sql => Err(error::method_not_allowed(path)),
+                        Native::Chain
+                        | Native::Collection
+                        | Native::Config
+                        | Native::Disk
+                        | Native::Error
+                        | Native::LogLevel
+                        | Native::Metrics
+                        | Native::Migrate
+                        | Native::Object
+                        | Native::Routes
+                        | Native::Transact
+                        | Native::Value => Err(error::method_not_allowed(path)),
                     },
-                    other => Err(error::not_found(other)),
+                    Namespace::Adapter | Namespace::Cluster => Err(error::not_found(&path[0])),
                 }
             }
         } else if path.len() == 1 {
@@ -48,6 +48,10 @@ impl Hosted {
         }
     }
 
+    pub fn paths(&self) -> impl Iterator<Item = &TCPathBuf> {
+        self.hosted.keys()
+    }
+
     pub fn push(&mut self, path: TCPathBuf, cluster: Cluster) -> Option<Cluster> {
         let mut node = &mut self.root;
         for segment in path.clone() {
@@ -1,13 +1,15 @@
 use std::collections::HashMap;
 use std::convert::Infallible;
 use std::net::SocketAddr;
-use std::sync::Arc;
-use std::time::Duration;
+use std::ops::Range;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 
 use async_trait::async_trait;
 use bytes::Bytes;
 use futures::future::{self, Future, TryFutureExt};
 use futures::stream::{self, Stream, StreamExt, TryStreamExt};
+use hyper::server::conn::AddrStream;
 use hyper::service::{make_service_fn, service_fn};
 use hyper::{Body, Method, StatusCode, Uri};
 use log::debug;
@@ -17,6 +19,7 @@ use tokio::time::timeout;
 use crate::auth::Token;
 use crate::class::State;
 use crate::error;
+use crate::replication::Session;
 use crate::request::Request;
 use crate::scalar::value::link::*;
 use crate::scalar::{Id, Scalar, Value};
@@ -27,7 +30,14 @@ use crate::{TCResult, TCStream};
 use super::Gateway;
 
 const CONTENT_TYPE: &str = "application/json; charset=utf-8";
+const CONTENT_TYPE_BINARY: &str = "application/octet-stream";
 const ERR_DECODE: &str = "(unable to decode error message)";
+const PARALLEL_DECODE_MIN: usize = 64;
+
+// the header which carries the TxnId of a client's last write to each replicated cluster, so that
+// a replica can serve the client's subsequent reads of a cluster only once it has applied the
+// client's last write to it
+const SESSION: &str = "x-tc-session";
 
 pub struct Client {
     client: hyper::Client<hyper::client::HttpConnector, Body>,
@@ -158,33 +168,201 @@ impl Client {
 
                     Err(error::TCError::of(status.into(), msg))
                 }
-                Ok(_) => {
-                    // TODO: deserialize response
-                    Ok(().into())
+                Ok(mut response) => {
+                    deserialize_body(response.body_mut(), self.response_limit)
+                        .map_ok(State::Scalar)
+                        .await
+                }
+            },
+        }
+    }
+
+    pub async fn put(
+        &self,
+        request: &Request,
+        txn: &Txn,
+        link: &Link,
+        key: &Value,
+        value: Scalar,
+    ) -> TCResult<()> {
+        if request.auth().is_some() {
+            return Err(error::not_implemented("Authorization"));
+        }
+
+        let host = link
+            .host()
+            .as_ref()
+            .ok_or_else(|| error::bad_request("No host to resolve", &link))?;
+
+        let key: String = serde_json::to_string(key).map_err(error::TCError::from)?;
+        let key: String = url::form_urlencoded::byte_serialize(key.as_bytes()).collect();
+        let uri = Uri::builder()
+            .scheme(host.protocol().to_string().as_str())
+            .authority(host.authority().as_str())
+            .path_and_query(format!("{}?key={}&txn_id={}", link.path(), key, txn.id()).as_str())
+            .build()
+            .map_err(error::internal)?;
+
+        debug!("PUT to {}", uri);
+
+        let body = serde_json::to_string(&value).map_err(error::TCError::from)?;
+        let req = hyper::Request::builder()
+            .method(Method::PUT)
+            .uri(uri)
+            .header("content-type", "application/json")
+            .body(Body::from(body))
+            .map_err(error::internal)?;
+
+        match timeout(request.ttl(), self.client.request(req)).await {
+            Err(_) => Err(error::bad_request("The request timed out waiting on", link)),
+            Ok(result) => match result {
+                Err(cause) => Err(error::transport(cause)),
+                Ok(response) if !response.status().is_success() => {
+                    Err(response_error(response).await)
+                }
+                Ok(_) => Ok(()),
+            },
+        }
+    }
+
+    pub async fn delete(
+        &self,
+        request: &Request,
+        txn: &Txn,
+        link: &Link,
+        key: &Value,
+    ) -> TCResult<()> {
+        if request.auth().is_some() {
+            return Err(error::not_implemented("Authorization"));
+        }
+
+        let host = link
+            .host()
+            .as_ref()
+            .ok_or_else(|| error::bad_request("No host to resolve", &link))?;
+
+        let key: String = serde_json::to_string(key).map_err(error::TCError::from)?;
+        let key: String = url::form_urlencoded::byte_serialize(key.as_bytes()).collect();
+        let uri = Uri::builder()
+            .scheme(host.protocol().to_string().as_str())
+            .authority(host.authority().as_str())
+            .path_and_query(format!("{}?key={}&txn_id={}", link.path(), key, txn.id()).as_str())
+            .build()
+            .map_err(error::internal)?;
+
+        debug!("DELETE {}", uri);
+
+        let req = hyper::Request::builder()
+            .method(Method::DELETE)
+            .uri(uri)
+            .body(Body::empty())
+            .map_err(error::internal)?;
+
+        match timeout(request.ttl(), self.client.request(req)).await {
+            Err(_) => Err(error::bad_request("The request timed out waiting on", link)),
+            Ok(result) => match result {
+                Err(cause) => Err(error::transport(cause)),
+                Ok(response) if !response.status().is_success() => {
+                    Err(response_error(response).await)
                 }
+                Ok(_) => Ok(()),
             },
         }
     }
 }
 
+/// A token bucket which admits requests at the `rate_limit` configured at `/sbin/config`, in
+/// requests per second, with bursts of up to one second's worth of requests.
+struct RateLimit {
+    bucket: Mutex<(f64, Instant)>,
+}
+
+impl RateLimit {
+    fn new() -> RateLimit {
+        RateLimit {
+            bucket: Mutex::new((f64::INFINITY, Instant::now())),
+        }
+    }
+
+    fn admit(&self, rate_limit: Option<u64>) -> TCResult<()> {
+        let rate_limit = match rate_limit {
+            Some(rate_limit) => rate_limit as f64,
+            None => return Ok(()),
+        };
+
+        let mut bucket = self.bucket.lock().unwrap();
+        let (tokens, refilled) = *bucket;
+        let now = Instant::now();
+        let elapsed = now.duration_since(refilled).as_secs_f64();
+        let tokens = (tokens + elapsed * rate_limit).min(rate_limit);
+
+        if tokens >= 1. {
+            *bucket = (tokens - 1., now);
+            Ok(())
+        } else {
+            *bucket = (tokens, now);
+            Err(error::resource_exhausted(format!(
+                "This host accepts at most {} requests per second",
+                rate_limit
+            )))
+        }
+    }
+}
+
 pub struct Server {
     address: SocketAddr,
-    request_limit: usize,
-    request_ttl: Duration,
+    rate_limit: RateLimit,
 }
 
 impl Server {
-    pub fn new(address: SocketAddr, request_limit: usize, request_ttl: Duration) -> Server {
+    pub fn new(address: SocketAddr) -> Server {
         Server {
             address,
-            request_limit,
-            request_ttl,
+            rate_limit: RateLimit::new(),
+        }
+    }
+
+    async fn serve(
+        self: Arc<Self>,
+        gateway: Arc<Gateway>,
+        peer: SocketAddr,
+        http_request: hyper::Request<Body>,
+    ) -> Result<hyper::Response<Body>, hyper::Error> {
+        let method = http_request.method().clone();
+
+        let admitted = gateway
+            .config()
+            .rate_limit()
+            .and_then(|rate_limit| self.rate_limit.admit(rate_limit));
+
+        if let Err(cause) = admitted {
+            return Ok(transform_error(cause));
+        }
+
+        match http_request.uri().path().parse::<TCPathBuf>() {
+            Ok(path) if is_blob_stream(&method, &path) => {
+                match stream_blob(&gateway, &path[2..], http_request).await {
+                    Ok(response) => Ok(response),
+                    Err(cause) => Ok(transform_error(cause)),
+                }
+            }
+            _ => {
+                let session = match session(&http_request) {
+                    Ok(session) => session,
+                    Err(cause) => return Ok(transform_error(cause)),
+                };
+
+                let state = self.handle(gateway.clone(), peer, session.clone(), http_request);
+                encode_response(method, session, state).await
+            }
         }
     }
 
     async fn handle(
         self: Arc<Self>,
         gateway: Arc<Gateway>,
+        peer: SocketAddr,
+        session: Option<Session>,
         http_request: hyper::Request<Body>,
     ) -> TCResult<(State, Txn)> {
         let token: Option<Token> = if let Some(header) = http_request.headers().get("Authorization")
@@ -211,7 +389,8 @@ impl Server {
 
         let txn_id = get_param(&mut params, "txn_id")?;
 
-        let request = Request::new(self.request_ttl, token, txn_id);
+        let request = Request::new(gateway.config().request_ttl()?, token, txn_id, session)
+            .with_peer(peer.ip());
         let result = timeout(
             request.ttl(),
             self.route(gateway, request, params, http_request),
@@ -244,7 +423,8 @@ impl Server {
                 let id = get_param(&mut params, "key")?
                     .ok_or_else(|| error::bad_request("Missing URI parameter", "'key'"))?;
                 let value: Scalar =
-                    deserialize_body(http_request.body_mut(), self.request_limit).await?;
+                    deserialize_body(http_request.body_mut(), gateway.config().request_limit()?)
+                        .await?;
 
                 gateway
                     .put(&request, &txn, &path.into(), id, value.into())
@@ -254,8 +434,12 @@ impl Server {
 
             &Method::POST => {
                 debug!("POST {}", path);
-                let request_body: Scalar =
-                    deserialize_body(http_request.body_mut(), self.request_limit).await?;
+                let request_body = deserialize_post_body(
+                    http_request.body_mut(),
+                    gateway.config().request_limit()?,
+                    gateway.config().decode_parallelism()?,
+                )
+                .await?;
 
                 gateway
                     .post(&request, &txn, path.into(), request_body)
@@ -280,10 +464,151 @@ impl Server {
     }
 }
 
+async fn response_error(response: hyper::Response<Body>) -> error::TCError {
+    let status = response.status().as_u16();
+    let msg = if let Ok(msg) = hyper::body::to_bytes(response).await {
+        if let Ok(msg) = String::from_utf8(msg.to_vec()) {
+            msg
+        } else {
+            ERR_DECODE.to_string()
+        }
+    } else {
+        ERR_DECODE.to_string()
+    };
+
+    error::TCError::of(status.into(), msg)
+}
+
 async fn deserialize_body<D: DeserializeOwned>(
     body: &mut hyper::Body,
     max_size: usize,
 ) -> TCResult<D> {
+    let data = read_body(body, max_size).await?;
+    serde_json::from_str(&data).map_err(|e| deserialize_error(e, data))
+}
+
+// decode the top-level entries of a large list in parallel, since each is independent
+//
+// the list is split into its entries by scanning it for the commas between them, without
+// decoding anything, so that the entries are only decoded once, on the blocking tasks
+async fn deserialize_post_body(
+    body: &mut hyper::Body,
+    max_size: usize,
+    parallelism: usize,
+) -> TCResult<Scalar> {
+    let data = read_body(body, max_size).await?;
+    let entries = if parallelism < 2 {
+        None
+    } else {
+        split_list(&data).filter(|entries| entries.len() >= PARALLEL_DECODE_MIN)
+    };
+
+    let entries = match entries {
+        Some(entries) => entries,
+        None => return serde_json::from_str(&data).map_err(|e| deserialize_error(e, data)),
+    };
+
+    debug!(
+        "decode {} request body entries on {} tasks",
+        entries.len(),
+        parallelism
+    );
+
+    let data = Arc::new(data);
+    let chunk_size = (entries.len() + parallelism - 1) / parallelism;
+    let mut tasks = Vec::with_capacity(parallelism);
+    for chunk in entries.chunks(chunk_size) {
+        let data = data.clone();
+        let chunk = chunk.to_vec();
+
+        tasks.push(tokio::task::spawn_blocking(move || {
+            chunk
+                .into_iter()
+                .map(|range| {
+                    let entry = &data[range];
+                    serde_json::from_str(entry).map_err(|e| deserialize_error(e, entry.into()))
+                })
+                .collect::<TCResult<Vec<Scalar>>>()
+        }));
+    }
+
+    let mut decoded = vec![];
+    for chunk in future::try_join_all(tasks)
+        .await
+        .map_err(|e| error::internal(format!("Request body decoding failed: {}", e)))?
+    {
+        decoded.extend(chunk?);
+    }
+
+    Ok(Scalar::Tuple(decoded.into()))
+}
+
+// find the byte range of each top-level entry of the JSON list `data`, or return `None` if
+// `data` is not a list, or if its brackets and quotes are unbalanced
+fn split_list(data: &str) -> Option<Vec<Range<usize>>> {
+    let start = data.len() - data.trim_start().len();
+    if !data[start..].starts_with('[') {
+        return None;
+    }
+
+    let mut entries = vec![];
+    let mut entry_start = start + 1;
+    let mut depth = 0usize;
+    let mut in_string = false;
+    let mut escaped = false;
+    let mut end = None;
+
+    for (i, c) in data.char_indices().skip_while(|(i, _)| *i <= start) {
+        if in_string {
+            match c {
+                _ if escaped => escaped = false,
+                '\\' => escaped = true,
+                '"' => in_string = false,
+                _ => {}
+            }
+
+            continue;
+        }
+
+        match c {
+            '"' => in_string = true,
+            '[' | '{' => depth += 1,
+            ']' if depth == 0 => {
+                end = Some(i);
+                entries.push(entry_start..i);
+                break;
+            }
+            ']' | '}' => depth = depth.checked_sub(1)?,
+            ',' if depth == 0 => {
+                entries.push(entry_start..i);
+                entry_start = i + 1;
+            }
+            _ => {}
+        }
+    }
+
+    let end = end?;
+    if !data[end + 1..].trim().is_empty() {
+        return None;
+    }
+
+    let entries: Vec<Range<usize>> = entries
+        .into_iter()
+        .map(|range| {
+            let entry = &data[range.clone()];
+            let offset = entry.len() - entry.trim_start().len();
+            (range.start + offset)..(range.start + entry.trim_end().len())
+        })
+        .collect();
+
+    match entries.as_slice() {
+        [only] if only.is_empty() => Some(vec![]),
+        entries if entries.iter().any(|range| range.is_empty()) => None,
+        entries => Some(entries.to_vec()),
+    }
+}
+
+async fn read_body(body: &mut hyper::Body, max_size: usize) -> TCResult<String> {
     let mut buffer = vec![];
     while let Some(chunk) = body.next().await {
         buffer.extend(chunk?.to_vec());
@@ -293,15 +618,14 @@ async fn deserialize_body<D: DeserializeOwned>(
         }
     }
 
-    let data = String::from_utf8(buffer)
-        .map_err(|e| error::bad_request("Unable to parse request body", e))?;
+    String::from_utf8(buffer).map_err(|e| error::bad_request("Unable to parse request body", e))
+}
 
-    serde_json::from_str(&data).map_err(|e| {
-        error::bad_request(
-            &format!("Deserialization error \"{}\" when parsing", e),
-            data,
-        )
-    })
+fn deserialize_error(cause: serde_json::Error, data: String) -> error::TCError {
+    error::bad_request(
+        &format!("Deserialization error \"{}\" when parsing", cause),
+        data,
+    )
 }
 
 async fn to_stream<'a>(state: State, txn: Txn) -> TCResult<TCStream<'a, TCResult<Bytes>>> {
@@ -341,14 +665,13 @@ impl super::Server for Server {
 
     async fn listen(self: Arc<Self>, gateway: Arc<Gateway>) -> Result<(), Self::Error> {
         hyper::Server::bind(&self.address)
-            .serve(make_service_fn(|_conn| {
+            .serve(make_service_fn(|conn: &AddrStream| {
                 let this = self.clone();
                 let gateway = gateway.clone();
-                async {
+                let peer = conn.remote_addr();
+                async move {
                     Ok::<_, Infallible>(service_fn(move |request| {
-                        let method = request.method().clone();
-                        let state = this.clone().handle(gateway.clone(), request);
-                        encode_response(method, state)
+                        this.clone().serve(gateway.clone(), peer, request)
                     }))
                 }
             }))
@@ -358,6 +681,7 @@ impl super::Server for Server {
 
 async fn encode_response(
     method: Method,
+    session: Option<Session>,
     result: impl Future<Output = TCResult<(State, Txn)>>,
 ) -> Result<hyper::Response<Body>, hyper::Error> {
     let success_code = if method == Method::PUT || method == Method::DELETE {
@@ -369,9 +693,30 @@ async fn encode_response(
     let mut response = match result.await {
         Err(cause) => transform_error(cause),
         Ok((state, txn)) => {
+            // a request of any method which writes to a replicated cluster returns the client's
+            // session token with this write recorded, to send with its subsequent requests
+            let written = txn.written().await;
+            let session = if written.is_empty() {
+                None
+            } else {
+                let mut session = session.unwrap_or_default();
+                for cluster in written {
+                    session.record(cluster, *txn.id());
+                }
+
+                Some(session.to_string())
+            };
+
             let response = to_stream(state, txn).await.unwrap();
             let mut response = hyper::Response::new(Body::wrap_stream(response));
             *response.status_mut() = success_code;
+
+            if let Some(session) = session {
+                response
+                    .headers_mut()
+                    .insert(SESSION, session.parse().unwrap());
+            }
+
             response
         }
     };
@@ -383,6 +728,56 @@ async fn encode_response(
     Ok(response)
 }
 
+fn session(http_request: &hyper::Request<Body>) -> TCResult<Option<Session>> {
+    match http_request.headers().get(SESSION) {
+        Some(header) => {
+            let session = header
+                .to_str()
+                .map_err(|e| error::bad_request("Unable to parse session header", e))?;
+
+            session.parse().map(Some)
+        }
+        None => Ok(None),
+    }
+}
+
+fn is_blob_stream(method: &Method, path: &[PathSegment]) -> bool {
+    if path.len() < 2 || &path[0] != "sbin" || &path[1] != "blobs" {
+        return false;
+    }
+
+    match method {
+        &Method::GET => path.len() == 3,
+        &Method::POST => path.len() == 2,
+        _ => false,
+    }
+}
+
+async fn stream_blob(
+    gateway: &Gateway,
+    path: &[PathSegment],
+    http_request: hyper::Request<Body>,
+) -> TCResult<hyper::Response<Body>> {
+    let (content_type, body) = if path.is_empty() {
+        debug!("POST /sbin/blobs");
+        gateway.disk().admit()?;
+        let hash = gateway.blobs().upload(http_request.into_body()).await?;
+        let hash = serde_json::to_string(&hash).map_err(error::TCError::from)?;
+        (CONTENT_TYPE, Body::from(format!("{}\r\n", hash)))
+    } else {
+        debug!("GET /sbin/blobs/{}", path[0]);
+        let contents = gateway.blobs().download(&path[0]).await?;
+        (CONTENT_TYPE_BINARY, Body::wrap_stream(contents))
+    };
+
+    let mut response = hyper::Response::new(body);
+    response
+        .headers_mut()
+        .insert(hyper::header::CONTENT_TYPE, content_type.parse().unwrap());
+
+    Ok(response)
+}
+
 fn encode_query_string(data: Vec<(&str, &str)>) -> String {
     let mut query_string = url::form_urlencoded::Serializer::new(String::new());
     for (name, value) in data.into_iter() {
@@ -418,6 +813,8 @@ fn transform_error(err: error::TCError) -> hyper::Response<Body> {
         MethodNotAllowed => StatusCode::METHOD_NOT_ALLOWED,
         NotFound => StatusCode::NOT_FOUND,
         NotImplemented => StatusCode::NOT_IMPLEMENTED,
+        OutOfSpace => StatusCode::INSUFFICIENT_STORAGE,
+        ResourceExhausted => StatusCode::SERVICE_UNAVAILABLE,
         Timeout => StatusCode::REQUEST_TIMEOUT,
         TooLarge => StatusCode::PAYLOAD_TOO_LARGE,
         Transport => StatusCode::from_u16(499).unwrap(), // custom status code
@@ -427,3 +824,26 @@ fn transform_error(err: error::TCError) -> hyper::Response<Body> {
 
     response
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_split_list() {
+        let data = r#" [1, "a, [b]", {"c": [2, 3]}, "\\\"]"] "#;
+        let entries: Vec<&str> = split_list(data)
+            .unwrap()
+            .into_iter()
+            .map(|range| &data[range])
+            .collect();
+
+        assert_eq!(entries, vec!["1", r#""a, [b]""#, r#"{"c": [2, 3]}"#, r#""\\\"]""#]);
+
+        assert_eq!(split_list("[ ]"), Some(vec![]));
+        assert_eq!(split_list(r#"{"a": [1, 2]}"#), None);
+        assert_eq!(split_list("[1, 2"), None);
+        assert_eq!(split_list("[1,, 2]"), None);
+        assert_eq!(split_list("[1, 2] 3"), None);
+    }
+}
@@ -6,12 +6,14 @@ use async_trait::async_trait;
 mod gateway;
 mod hosted;
 mod http;
+mod placement;
 mod time;
 
 pub type Gateway = gateway::Gateway;
 pub type Hosted = hosted::Hosted;
 pub type HttpServer = http::Server;
 pub type NetworkTime = time::NetworkTime;
+pub type Placement = placement::Placement;
 
 #[async_trait]
 pub trait Server {
@@ -0,0 +1,200 @@
+use std::collections::BTreeMap;
+
+use log::debug;
+
+use crate::scalar::value::link::{LinkHost, PathSegment, TCPathBuf};
+
+// the FNV-1a parameters for a 64-bit hash
+const FNV_OFFSET: u64 = 0xcbf2_9ce4_8422_2325;
+const FNV_PRIME: u64 = 0x0000_0100_0000_01b3;
+
+/// Assigns hosted paths to the hosts of a cluster by consistent hashing.
+///
+/// Each host occupies `vnodes` points on a ring of 64-bit hashes, and a path is placed on the
+/// host which occupies the first point at or after the hash of the path. The hash is stable,
+/// so every host with the same list of peers computes the same placement, and a new host only
+/// takes over about `1 / n` of the paths.
+pub struct Placement {
+    ring: BTreeMap<u64, LinkHost>,
+    paths: Vec<TCPathBuf>,
+    vnodes: usize,
+}
+
+impl Placement {
+    pub fn new<I: IntoIterator<Item = LinkHost>>(hosts: I, vnodes: usize) -> Placement {
+        let mut placement = Placement {
+            ring: BTreeMap::new(),
+            paths: vec![],
+            vnodes,
+        };
+
+        for host in hosts {
+            placement.insert(host);
+        }
+
+        placement
+    }
+
+    /// Add a host to the ring.
+    pub fn insert(&mut self, host: LinkHost) {
+        let key = host_key(&host);
+        for i in 0..self.vnodes {
+            self.ring
+                .insert(hash(&format!("{}#{}", key, i)), host.clone());
+        }
+    }
+
+    /// Return the host on which `path` is placed, or `None` if there are no hosts.
+    pub fn owner(&self, path: &TCPathBuf) -> Option<&LinkHost> {
+        let point = hash(&path.to_string());
+        self.ring
+            .range(point..)
+            .next()
+            .or_else(|| self.ring.iter().next())
+            .map(|(_, host)| host)
+    }
+
+    /// Record that requests to `path` should be routed to its owner.
+    pub fn place(&mut self, path: TCPathBuf) {
+        debug!("place {} on {:?}", path, self.owner(&path));
+        self.paths.push(path);
+    }
+
+    /// Return the paths which are placed on one of this host's peers.
+    pub fn paths(&self) -> impl Iterator<Item = &TCPathBuf> {
+        self.paths.iter()
+    }
+
+    /// Return the owner of the longest placed path which is a prefix of `path`, if any.
+    pub fn locate(&self, path: &[PathSegment]) -> Option<&LinkHost> {
+        self.paths
+            .iter()
+            .filter(|placed| path.starts_with(placed))
+            .max_by_key(|placed| placed.len())
+            .and_then(|placed| self.owner(placed))
+    }
+}
+
+fn host_key(host: &LinkHost) -> String {
+    format!("{}://{}", host.protocol(), host.authority())
+}
+
+// FNV-1a alone barely changes the high bits of the hash of keys which differ only in their last
+// few bytes, like the ports of two hosts or the names "/app/1" and "/app/2", so it's followed by
+// the MurmurHash3 finalizer to spread such keys evenly around the ring
+fn hash(key: &str) -> u64 {
+    mix(fnv1a(key))
+}
+
+fn fnv1a(key: &str) -> u64 {
+    key.bytes().fold(FNV_OFFSET, |hash, byte| {
+        (hash ^ byte as u64).wrapping_mul(FNV_PRIME)
+    })
+}
+
+fn mix(mut hash: u64) -> u64 {
+    hash ^= hash >> 33;
+    hash = hash.wrapping_mul(0xff51_afd7_ed55_8ccd);
+    hash ^= hash >> 33;
+    hash = hash.wrapping_mul(0xc4ce_b9fe_1a85_ec53);
+    hash ^ (hash >> 33)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn host(port: u16) -> LinkHost {
+        format!("http://127.0.0.1:{}", port).parse().unwrap()
+    }
+
+    fn path(path: &str) -> TCPathBuf {
+        path.parse().unwrap()
+    }
+
+    fn paths() -> Vec<TCPathBuf> {
+        (0..1000).map(|i| path(&format!("/app/{}", i))).collect()
+    }
+
+    #[test]
+    fn test_hash() {
+        // the FNV-1a test vectors
+        assert_eq!(fnv1a(""), 0xcbf2_9ce4_8422_2325);
+        assert_eq!(fnv1a("a"), 0xaf63_dc4c_8601_ec8c);
+        assert_eq!(fnv1a("foobar"), 0x8594_4171_f739_67e8);
+
+        assert_eq!(mix(0), 0);
+        assert_ne!(hash("/app/1") >> 48, hash("/app/2") >> 48);
+    }
+
+    #[test]
+    fn test_owner() {
+        let empty = Placement::new(vec![], 8);
+        assert!(empty.owner(&path("/app")).is_none());
+
+        let single = Placement::new(vec![host(8702)], 8);
+        assert!(paths().iter().all(|p| single.owner(p) == Some(&host(8702))));
+
+        // the placement doesn't depend on the order in which hosts are listed
+        let forward = Placement::new((8702..8705).map(host), 64);
+        let reverse = Placement::new((8702..8705).rev().map(host), 64);
+        for p in paths() {
+            assert_eq!(forward.owner(&p), reverse.owner(&p));
+        }
+
+        // every host owns some paths
+        for port in 8702..8705 {
+            let owned = paths()
+                .iter()
+                .filter(|p| forward.owner(p) == Some(&host(port)))
+                .count();
+
+            assert!(owned > 200, "{} owns only {} paths", host(port), owned);
+        }
+    }
+
+    #[test]
+    fn test_insert() {
+        let mut placement = Placement::new((8702..8705).map(host), 64);
+        let before: Vec<LinkHost> = paths()
+            .iter()
+            .map(|p| placement.owner(p).unwrap().clone())
+            .collect();
+
+        placement.insert(host(8705));
+
+        // a new host only takes over paths, and only about a quarter of them
+        let mut moved = 0;
+        for (p, owner) in paths().iter().zip(before) {
+            let new_owner = placement.owner(p).unwrap();
+            if new_owner != &owner {
+                assert_eq!(new_owner, &host(8705));
+                moved += 1;
+            }
+        }
+
+        assert!(moved > 150 && moved < 350, "{} paths moved", moved);
+
+        // inserting the same host again changes nothing
+        let ring = placement.ring.clone();
+        placement.insert(host(8705));
+        assert_eq!(placement.ring, ring);
+    }
+
+    #[test]
+    fn test_locate() {
+        let mut placement = Placement::new((8702..8705).map(host), 64);
+        placement.place(path("/app/a"));
+        placement.place(path("/app/a/b"));
+
+        let owner = |p: &str| placement.owner(&path(p)).cloned();
+        let locate = |p: &str| placement.locate(&path(p)).cloned();
+
+        assert_eq!(locate("/app/a/b/c"), owner("/app/a/b"));
+        assert_eq!(locate("/app/a/b"), owner("/app/a/b"));
+        assert_eq!(locate("/app/a/x"), owner("/app/a"));
+        assert_eq!(locate("/app/ab"), None);
+        assert_eq!(locate("/app"), None);
+        assert_eq!(locate("/other"), None);
+    }
+}
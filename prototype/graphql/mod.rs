@@ -0,0 +1,1060 @@
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, HashMap};
+
+use futures::TryStreamExt;
+use graphql_parser::query::{
+    self as gql, Definition, FragmentDefinition, OperationDefinition, Selection, SelectionSet,
+    TypeCondition, VariableDefinition,
+};
+use log::debug;
+
+use crate::class::State;
+use crate::collection::btree::Collator;
+use crate::collection::schema::Column;
+use crate::collection::{Bounds, Collection, ColumnBound, Table, TableInstance};
+use crate::error;
+use crate::general::Map;
+use crate::request::Request;
+use crate::scalar::*;
+use crate::transaction::Txn;
+use crate::{TCResult, TryCastInto};
+
+mod schema;
+
+use schema::{Mutation, Schema, TableDef, MUTATION, QUERY};
+
+type Field = gql::Field<'static, String>;
+type Fragments = HashMap<String, FragmentDefinition<'static, String>>;
+
+const ERR_UNSUPPORTED: &str = "Unsupported GraphQL syntax";
+
+/// The number of rows in a page of a Table, if the query doesn't specify `first`.
+const DEFAULT_PAGE_SIZE: u64 = 100;
+
+/// The maximum number of rows in a page of a Table.
+const MAX_PAGE_SIZE: u64 = 10_000;
+
+/// The maximum depth of nested selections in a GraphQL query.
+const MAX_DEPTH: usize = 32;
+
+/// The maximum number of fields which one GraphQL query may resolve, so that a query which
+/// spreads the same fragment many times can't build an arbitrarily large response.
+const MAX_FIELDS: usize = 1_000_000;
+
+/// A value in a GraphQL response, before its selection set is applied.
+pub enum Node<'a> {
+    Null,
+    Leaf(Value),
+    List(Vec<Node<'a>>),
+    Object(Box<dyn Object + 'a>),
+}
+
+/// A GraphQL object, whose fields are resolved only if they're selected.
+pub trait Object {
+    fn typename(&self) -> &str;
+
+    fn field(&'_ self, name: &str) -> TCResult<Node<'_>>;
+}
+
+/// Handle a GraphQL request.
+///
+/// The schema is generated from the Table schemas registered by each cluster hosted here (see
+/// [`Schema`]) and supports introspection, so standard GraphQL clients can discover it. Each
+/// Table query returns a page of rows as a connection, whose `pageInfo { endCursor }` can be
+/// passed as the `after` argument of the next query to read the next page.
+pub async fn post(request: &Request, txn: &Txn, data: Scalar) -> TCResult<State> {
+    let mut params: Map<Scalar> = data.try_cast_into(|v| {
+        error::bad_request("/sbin/graphql expects a Map with a 'query' but found", v)
+    })?;
+
+    let query = match params.remove(&label("query").into()) {
+        Some(Scalar::Value(Value::TCString(TCString::UString(query)))) => query,
+        Some(other) => {
+            return Err(error::bad_request(
+                "GraphQL query must be a string, not",
+                other,
+            ))
+        }
+        None => return Err(error::bad_request("Missing parameter", "query")),
+    };
+
+    let operation_name = match params.remove(&label("operationName").into()) {
+        Some(Scalar::Value(Value::TCString(TCString::UString(name)))) => Some(name),
+        Some(Scalar::Value(Value::None)) | None => None,
+        Some(other) => return Err(error::bad_request("Invalid operationName", other)),
+    };
+
+    let variables = match params.remove(&label("variables").into()) {
+        Some(Scalar::Map(variables)) => variables,
+        Some(Scalar::Value(Value::None)) | None => Map::default(),
+        Some(other) => return Err(error::bad_request("Invalid GraphQL variables", other)),
+    };
+
+    debug!("/sbin/graphql: {}", query);
+
+    let document = gql::parse_query::<String>(&query)
+        .map_err(|e| error::bad_request("Unable to parse GraphQL query", e))?
+        .into_static();
+
+    let mut operations = vec![];
+    let mut fragments = Fragments::new();
+    for definition in document.definitions {
+        match definition {
+            Definition::Operation(op) => operations.push(op),
+            Definition::Fragment(fragment) => {
+                fragments.insert(fragment.name.clone(), fragment);
+            }
+        }
+    }
+
+    let operation = select_operation(operations, operation_name)?;
+
+    let (definitions, fields, mutation) = match operation {
+        OperationDefinition::SelectionSet(selection) => (vec![], selection, false),
+        OperationDefinition::Query(query) => {
+            reject_directives(&query.directives)?;
+            (query.variable_definitions, query.selection_set, false)
+        }
+        OperationDefinition::Mutation(mutation) => {
+            reject_directives(&mutation.directives)?;
+            (mutation.variable_definitions, mutation.selection_set, true)
+        }
+        OperationDefinition::Subscription(_) => {
+            return Err(error::not_implemented("GraphQL subscriptions"))
+        }
+    };
+
+    let variables = bind_variables(definitions, variables)?;
+
+    let mut tables = vec![];
+    for registry in txn.gateway().registries() {
+        for (name, table) in registry.tables(txn.id()).await? {
+            let mut path = registry.cluster().clone();
+            path.extend(name.into_iter());
+            tables.push((path, table));
+        }
+    }
+
+    let schema = Schema::generate(tables);
+
+    let root = if mutation { MUTATION } else { QUERY };
+    let mut selected = vec![];
+    collect_fields(root, &fields, &fragments, &mut vec![], &mut selected)?;
+
+    let mut budget = MAX_FIELDS;
+    let mut data = Map::<Scalar>::default();
+    for field in selected {
+        let name: Id = field.alias.as_ref().unwrap_or(&field.name).parse()?;
+        let result = if mutation {
+            resolve_mutation(request, txn, &schema, field, &variables).await?
+        } else {
+            let node = resolve_query(request, txn, &schema, field, &variables).await?;
+            select(node, &field.selection_set, &fragments, 1, &mut budget)?
+        };
+
+        data.insert(name, result);
+    }
+
+    let response: Map<Scalar> = vec![(label("data").into(), Scalar::Map(data))]
+        .into_iter()
+        .collect();
+
+    Ok(State::Scalar(Scalar::Map(response)))
+}
+
+fn select_operation(
+    operations: Vec<OperationDefinition<'static, String>>,
+    name: Option<String>,
+) -> TCResult<OperationDefinition<'static, String>> {
+    let operation_name = |op: &OperationDefinition<'static, String>| match op {
+        OperationDefinition::SelectionSet(_) => None,
+        OperationDefinition::Query(query) => query.name.clone(),
+        OperationDefinition::Mutation(mutation) => mutation.name.clone(),
+        OperationDefinition::Subscription(subscription) => subscription.name.clone(),
+    };
+
+    match name {
+        Some(name) => operations
+            .into_iter()
+            .find(|op| operation_name(op).as_ref() == Some(&name))
+            .ok_or_else(|| error::not_found(format!("GraphQL operation {}", name))),
+        None if operations.len() == 1 => Ok(operations.into_iter().next().unwrap()),
+        None if operations.is_empty() => {
+            Err(error::bad_request("GraphQL document has no operation", ""))
+        }
+        None => Err(error::bad_request(
+            "A GraphQL document with multiple operations requires an",
+            "operationName",
+        )),
+    }
+}
+
+fn bind_variables(
+    definitions: Vec<VariableDefinition<'static, String>>,
+    mut provided: Map<Scalar>,
+) -> TCResult<HashMap<String, Scalar>> {
+    let mut variables = HashMap::new();
+    for definition in definitions {
+        let name: Id = definition.name.parse()?;
+        let value = match provided.remove(&name) {
+            Some(value) => value,
+            None => match definition.default_value {
+                Some(default) => scalar(default, &HashMap::new())?,
+                None => match definition.var_type {
+                    gql::Type::NonNullType(_) => {
+                        return Err(error::bad_request(
+                            "Missing GraphQL variable",
+                            definition.name,
+                        ))
+                    }
+                    _ => Scalar::from(()),
+                },
+            },
+        };
+
+        variables.insert(definition.name, value);
+    }
+
+    Ok(variables)
+}
+
+async fn resolve_query<'a>(
+    request: &Request,
+    txn: &Txn,
+    schema: &'a Schema,
+    field: &Field,
+    variables: &HashMap<String, Scalar>,
+) -> TCResult<Node<'a>> {
+    let mut args = arguments(&field.arguments, variables)?;
+
+    match field.name.as_str() {
+        "__typename" => Ok(Node::Leaf(TCString::UString(QUERY.to_string()).into())),
+        "__schema" => {
+            reject_extra_args(args)?;
+            Ok(schema.schema_object())
+        }
+        "__type" => {
+            let name = match args.remove("name") {
+                Some(Scalar::Value(Value::TCString(TCString::UString(name)))) => name,
+                Some(other) => return Err(error::bad_request("Invalid type name", other)),
+                None => return Err(error::bad_request("Missing argument", "name")),
+            };
+
+            reject_extra_args(args)?;
+            Ok(schema.type_object(&name))
+        }
+        name => {
+            let table = schema
+                .table(name)
+                .ok_or_else(|| error::bad_request("Unrecognized GraphQL query field", name))?;
+
+            let page = page(request, txn, table, args).await?;
+            Ok(Node::Object(Box::new(page)))
+        }
+    }
+}
+
+async fn resolve_mutation(
+    request: &Request,
+    txn: &Txn,
+    schema: &Schema,
+    field: &Field,
+    variables: &HashMap<String, Scalar>,
+) -> TCResult<Scalar> {
+    if field.name == "__typename" {
+        return Ok(Value::TCString(TCString::UString(MUTATION.to_string())).into());
+    }
+
+    let mut args = arguments(&field.arguments, variables)?;
+    let (mutation, table_def) = schema
+        .mutation(&field.name)
+        .ok_or_else(|| error::bad_request("Unrecognized GraphQL mutation", &field.name))?;
+
+    if !field.selection_set.items.is_empty() {
+        return Err(error::bad_request(
+            "A GraphQL mutation returns a Boolean, which has no fields, but found",
+            &field.name,
+        ));
+    }
+
+    let table = get_table(request, txn, table_def).await?;
+
+    match mutation {
+        Mutation::Put => {
+            let row = input_object(&mut args, "row")?;
+            reject_extra_args(args)?;
+
+            let (key, values) = into_row(table_def, row)?;
+            txn.gateway().disk().admit()?;
+            table.upsert(txn.id(), key, values).await?;
+        }
+        Mutation::Delete => {
+            let key = input_object(&mut args, "key")?;
+            reject_extra_args(args)?;
+
+            let mut key = key.into_inner();
+            let mut bounds = HashMap::new();
+            for column in &table_def.key {
+                let value = key
+                    .remove(column.name())
+                    .ok_or_else(|| error::bad_request("Missing key column", column.name()))?;
+
+                bounds.insert(
+                    column.name().clone(),
+                    ColumnBound::Is(coerce(column, value)?),
+                );
+            }
+
+            reject_extra_columns(key)?;
+            table.slice(Bounds::from(bounds))?.delete(txn.id()).await?;
+        }
+    }
+
+    Ok(Value::from(true).into())
+}
+
+async fn get_table(request: &Request, txn: &Txn, table: &TableDef) -> TCResult<Table> {
+    match txn
+        .gateway()
+        .get(request, txn, &table.link, Value::None)
+        .await?
+    {
+        State::Collection(Collection::Table(table)) => Ok(table),
+        other => Err(error::bad_request("Expected a Table but found", other)),
+    }
+}
+
+/// A page of the rows of a Table.
+struct Page {
+    typename: String,
+    edge: String,
+    row: String,
+    rows: Vec<(String, Row)>,
+    has_next_page: bool,
+}
+
+impl Object for Page {
+    fn typename(&self) -> &str {
+        &self.typename
+    }
+
+    fn field(&'_ self, name: &str) -> TCResult<Node<'_>> {
+        match name {
+            "edges" => Ok(Node::List(
+                self.rows
+                    .iter()
+                    .map(|(cursor, row)| {
+                        Node::Object(Box::new(Edge {
+                            typename: &self.edge,
+                            cursor,
+                            row: RowObject {
+                                typename: &self.row,
+                                row,
+                            },
+                        }))
+                    })
+                    .collect(),
+            )),
+            "nodes" => Ok(Node::List(
+                self.rows
+                    .iter()
+                    .map(|(_, row)| {
+                        Node::Object(Box::new(RowObject {
+                            typename: &self.row,
+                            row,
+                        }))
+                    })
+                    .collect(),
+            )),
+            "pageInfo" => Ok(Node::Object(Box::new(PageInfo { page: self }))),
+            other => Err(error::bad_request(
+                &format!("{} has no field", self.typename),
+                other,
+            )),
+        }
+    }
+}
+
+struct PageInfo<'a> {
+    page: &'a Page,
+}
+
+impl<'a> Object for PageInfo<'a> {
+    fn typename(&self) -> &str {
+        "PageInfo"
+    }
+
+    fn field(&'_ self, name: &str) -> TCResult<Node<'_>> {
+        match name {
+            "hasNextPage" => Ok(Node::Leaf(self.page.has_next_page.into())),
+            "endCursor" => Ok(match self.page.rows.last() {
+                Some((cursor, _)) => Node::Leaf(TCString::UString(cursor.to_string()).into()),
+                None => Node::Null,
+            }),
+            other => Err(error::bad_request("PageInfo has no field", other)),
+        }
+    }
+}
+
+struct Edge<'a> {
+    typename: &'a str,
+    cursor: &'a str,
+    row: RowObject<'a>,
+}
+
+impl<'a> Object for Edge<'a> {
+    fn typename(&self) -> &str {
+        self.typename
+    }
+
+    fn field(&'_ self, name: &str) -> TCResult<Node<'_>> {
+        match name {
+            "cursor" => Ok(Node::Leaf(
+                TCString::UString(self.cursor.to_string()).into(),
+            )),
+            "node" => Ok(Node::Object(Box::new(RowObject {
+                typename: self.row.typename,
+                row: self.row.row,
+            }))),
+            other => Err(error::bad_request(
+                &format!("{} has no field", self.typename),
+                other,
+            )),
+        }
+    }
+}
+
+type Row = HashMap<String, Value>;
+
+struct RowObject<'a> {
+    typename: &'a str,
+    row: &'a Row,
+}
+
+impl<'a> Object for RowObject<'a> {
+    fn typename(&self) -> &str {
+        self.typename
+    }
+
+    fn field(&'_ self, name: &str) -> TCResult<Node<'_>> {
+        match self.row.get(name) {
+            Some(Value::None) => Ok(Node::Null),
+            Some(value) => Ok(Node::Leaf(output(value.clone()))),
+            None => Err(error::bad_request(
+                &format!("{} has no field", self.typename),
+                name,
+            )),
+        }
+    }
+}
+
+/// Read one page of the rows of `table_def`.
+///
+/// A cursor is the primary key of the last row of the previous page. Without an `order_by` or
+/// `reverse` argument, rows are read in the order of their primary key, so a page starts after
+/// that key even if that row has since been deleted. Otherwise the page starts after the row with
+/// that key, which must still exist.
+async fn page(
+    request: &Request,
+    txn: &Txn,
+    table_def: &TableDef,
+    mut args: BTreeMap<String, Scalar>,
+) -> TCResult<Page> {
+    let mut table = get_table(request, txn, table_def).await?;
+
+    match args.remove("where") {
+        Some(Scalar::Value(Value::None)) | None => {}
+        Some(Scalar::Map(filter)) => {
+            let mut filter = filter.into_inner();
+            let mut bounds = HashMap::new();
+            for column in table_def.columns() {
+                match filter.remove(column.name()) {
+                    Some(Scalar::Value(Value::None)) | None => {}
+                    Some(value) => {
+                        let value = coerce(column, value)?;
+                        bounds.insert(column.name().clone(), ColumnBound::Is(value));
+                    }
+                }
+            }
+
+            reject_extra_columns(filter)?;
+            table = table.slice(Bounds::from(bounds))?;
+        }
+        Some(other) => return Err(error::bad_request("Invalid 'where' argument", other)),
+    }
+
+    let reverse = match args.remove("reverse") {
+        Some(Scalar::Value(Value::Number(Number::Bool(reverse)))) => reverse.into(),
+        Some(Scalar::Value(Value::None)) | None => false,
+        Some(other) => return Err(error::bad_request("Invalid 'reverse' argument", other)),
+    };
+
+    let ordered = match args.remove("order_by") {
+        Some(Scalar::Value(Value::None)) | None if reverse => {
+            table = table.reversed()?;
+            true
+        }
+        Some(Scalar::Value(Value::None)) | None => false,
+        Some(order) => {
+            let order = strings(order)?
+                .into_iter()
+                .map(|name| name.parse())
+                .collect::<TCResult<Vec<Id>>>()?;
+
+            table = table.order_by(order, reverse)?;
+            true
+        }
+    };
+
+    let first = match args.remove("first") {
+        Some(Scalar::Value(Value::None)) | None => DEFAULT_PAGE_SIZE,
+        Some(first) => {
+            let first: Value = first.try_cast_into(|v| error::bad_request("Invalid 'first'", v))?;
+            first.try_cast_into(|v| error::bad_request("Invalid 'first'", v))?
+        }
+    };
+
+    if first > MAX_PAGE_SIZE {
+        return Err(error::bad_request(
+            &format!("A page may not have more than {} rows, not", MAX_PAGE_SIZE),
+            first,
+        ));
+    }
+
+    let after = match args.remove("after") {
+        Some(Scalar::Value(Value::TCString(TCString::UString(cursor)))) => {
+            let key = decode_cursor(&table_def.key, &cursor)?;
+            Some((cursor, key))
+        }
+        Some(Scalar::Value(Value::None)) | None => None,
+        Some(other) => return Err(error::bad_request("Invalid 'after' cursor", other)),
+    };
+
+    reject_extra_args(args)?;
+
+    let names: Vec<String> = table
+        .key()
+        .iter()
+        .chain(table.values())
+        .map(|col| col.name().to_string())
+        .collect();
+
+    let collator = Collator::new(table_def.key.iter().map(|col| col.dtype()).collect())?;
+
+    let mut rows = table.stream(txn.id()).await?;
+    let mut page = vec![];
+    let mut started = after.is_none();
+    let mut has_next_page = false;
+    while let Some(row) = rows.try_next().await? {
+        let row: Row = names.iter().cloned().zip(row).collect();
+        let key = table_def
+            .key
+            .iter()
+            .map(|col| row.get(col.name().as_str()).cloned())
+            .collect::<Option<Vec<Value>>>()
+            .ok_or_else(|| error::internal(format!("{} is missing its key", table_def.link)))?;
+
+        if let (false, Some((_, after))) = (started, &after) {
+            let position = collator.compare(&key, after);
+            if ordered {
+                // skip every row up to and including the row at the cursor
+                started = position == Ordering::Equal;
+                continue;
+            } else if position == Ordering::Greater {
+                started = true;
+            } else {
+                continue;
+            }
+        }
+
+        if page.len() as u64 == first {
+            has_next_page = true;
+            break;
+        }
+
+        page.push((encode_cursor(&key)?, row));
+    }
+
+    if let (false, Some((cursor, _))) = (started, after) {
+        if ordered {
+            return Err(error::bad_request(
+                "The row at this GraphQL cursor no longer exists",
+                cursor,
+            ));
+        }
+    }
+
+    Ok(Page {
+        typename: table_def.connection(),
+        edge: table_def.edge(),
+        row: table_def.type_name.clone(),
+        rows: page,
+        has_next_page,
+    })
+}
+
+/// Encode the key of a row as an opaque GraphQL cursor.
+fn encode_cursor(key: &[Value]) -> TCResult<String> {
+    let key = serde_json::to_string(&Value::Tuple(key.to_vec().into()))?;
+    Ok(base64::encode(key))
+}
+
+/// Decode the key of a row from a GraphQL cursor.
+fn decode_cursor(columns: &[Column], cursor: &str) -> TCResult<Vec<Value>> {
+    let invalid = || error::bad_request("Invalid GraphQL cursor", cursor);
+
+    let key = base64::decode(cursor).map_err(|_| invalid())?;
+    let key: Value = serde_json::from_slice(&key).map_err(|_| invalid())?;
+    let key: Vec<Value> = key.try_cast_into(|_| invalid())?;
+
+    if key.len() != columns.len() {
+        return Err(invalid());
+    }
+
+    columns
+        .iter()
+        .zip(key)
+        .map(|(column, value)| coerce(column, Scalar::Value(value)))
+        .collect()
+}
+
+/// Cast a GraphQL input value into the type of `column`.
+///
+/// A string is only parsed as a `Link` or an `Id` if `column` has that type, so a `String` value
+/// which happens to start with "/" stays a `String`.
+fn coerce(column: &Column, value: Scalar) -> TCResult<Value> {
+    match (column.dtype(), value) {
+        (
+            ValueType::TCString(StringType::Link),
+            Scalar::Value(Value::TCString(TCString::UString(link))),
+        ) => link.parse::<Link>().map(Value::from),
+        (
+            ValueType::TCString(StringType::Id),
+            Scalar::Value(Value::TCString(TCString::UString(id))),
+        ) => id.parse::<Id>().map(Value::from),
+        (dtype, value) => dtype.try_cast(value),
+    }
+}
+
+/// Encode a `Value` for a GraphQL response, where a `Link` is a `String`.
+fn output(value: Value) -> Value {
+    match value {
+        Value::TCString(TCString::Link(link)) => TCString::UString(link.to_string()).into(),
+        Value::Tuple(tuple) => Value::Tuple(
+            tuple
+                .into_inner()
+                .into_iter()
+                .map(output)
+                .collect::<Vec<Value>>()
+                .into(),
+        ),
+        other => other,
+    }
+}
+
+fn into_row(table: &TableDef, row: Map<Scalar>) -> TCResult<(Vec<Value>, Vec<Value>)> {
+    let mut row = row.into_inner();
+    let mut take = |column: &Column| {
+        let value = row
+            .remove(column.name())
+            .ok_or_else(|| error::bad_request("Missing value for column", column.name()))?;
+
+        coerce(column, value)
+    };
+
+    let key = table
+        .key
+        .iter()
+        .map(&mut take)
+        .collect::<TCResult<Vec<Value>>>()?;
+    let values = table
+        .values
+        .iter()
+        .map(&mut take)
+        .collect::<TCResult<Vec<Value>>>()?;
+
+    reject_extra_columns(row)?;
+    Ok((key, values))
+}
+
+/// Apply the selection set of a field at the given `depth` to its value, resolving at most
+/// `budget` more fields.
+fn select(
+    node: Node,
+    selection: &SelectionSet<'static, String>,
+    fragments: &Fragments,
+    depth: usize,
+    budget: &mut usize,
+) -> TCResult<Scalar> {
+    if depth > MAX_DEPTH {
+        return Err(error::bad_request(
+            "GraphQL selections may not be nested deeper than",
+            MAX_DEPTH,
+        ));
+    }
+
+    match node {
+        Node::Null => Ok(Scalar::from(())),
+        Node::Leaf(value) if selection.items.is_empty() => Ok(Scalar::Value(value)),
+        Node::Leaf(value) => Err(error::bad_request(
+            "Cannot select the fields of a leaf value",
+            value,
+        )),
+        Node::List(items) => items
+            .into_iter()
+            .map(|item| select(item, selection, fragments, depth, budget))
+            .collect::<TCResult<Vec<Scalar>>>()
+            .map(Scalar::from),
+        Node::Object(object) => {
+            if selection.items.is_empty() {
+                return Err(error::bad_request(
+                    "A GraphQL query must select the fields of",
+                    object.typename(),
+                ));
+            }
+
+            let mut fields = vec![];
+            collect_fields(
+                object.typename(),
+                selection,
+                fragments,
+                &mut vec![],
+                &mut fields,
+            )?;
+
+            let mut selected = Map::<Scalar>::default();
+            for field in fields {
+                // the only arguments of the introspection types are `includeDeprecated` flags,
+                // which make no difference since nothing in the generated schema is deprecated
+                if !field.arguments.is_empty() && !object.typename().starts_with("__") {
+                    return Err(error::bad_request(
+                        "Unexpected arguments for GraphQL field",
+                        &field.name,
+                    ));
+                }
+
+                if *budget == 0 {
+                    return Err(error::bad_request(
+                        "A GraphQL query may not resolve more fields than",
+                        MAX_FIELDS,
+                    ));
+                } else {
+                    *budget -= 1;
+                }
+
+                let name: Id = field.alias.as_ref().unwrap_or(&field.name).parse()?;
+                let value = if field.name == "__typename" {
+                    Scalar::Value(TCString::UString(object.typename().to_string()).into())
+                } else {
+                    let node = object.field(&field.name)?;
+                    select(node, &field.selection_set, fragments, depth + 1, budget)?
+                };
+
+                selected.insert(name, value);
+            }
+
+            Ok(Scalar::Map(selected))
+        }
+    }
+}
+
+/// Collect the fields selected on an object of type `typename`, including those selected by
+/// fragments which apply to it. `spreads` is the names of the fragments being expanded.
+fn collect_fields<'a>(
+    typename: &str,
+    selection: &'a SelectionSet<'static, String>,
+    fragments: &'a Fragments,
+    spreads: &mut Vec<&'a str>,
+    fields: &mut Vec<&'a Field>,
+) -> TCResult<()> {
+    for item in &selection.items {
+        match item {
+            Selection::Field(field) => {
+                reject_directives(&field.directives)?;
+                fields.push(field);
+            }
+            Selection::FragmentSpread(gql::FragmentSpread {
+                fragment_name: spread_name,
+                directives,
+                ..
+            }) => {
+                reject_directives(directives)?;
+
+                let name = spread_name.as_str();
+                if spreads.contains(&name) {
+                    return Err(error::bad_request("GraphQL fragment spreads itself", name));
+                }
+
+                let fragment = fragments
+                    .get(name)
+                    .ok_or_else(|| error::not_found(format!("GraphQL fragment {}", name)))?;
+
+                let TypeCondition::On(condition) = &fragment.type_condition;
+                if condition == typename {
+                    spreads.push(name);
+                    collect_fields(
+                        typename,
+                        &fragment.selection_set,
+                        fragments,
+                        spreads,
+                        fields,
+                    )?;
+                    spreads.pop();
+                }
+            }
+            Selection::InlineFragment(inline) => {
+                reject_directives(&inline.directives)?;
+
+                let applies = match &inline.type_condition {
+                    Some(TypeCondition::On(condition)) => condition == typename,
+                    None => true,
+                };
+
+                if applies {
+                    collect_fields(typename, &inline.selection_set, fragments, spreads, fields)?;
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn arguments(
+    args: &[(String, gql::Value<'static, String>)],
+    variables: &HashMap<String, Scalar>,
+) -> TCResult<BTreeMap<String, Scalar>> {
+    args.iter()
+        .map(|(name, value)| scalar(value.clone(), variables).map(|value| (name.clone(), value)))
+        .collect()
+}
+
+fn input_object(args: &mut BTreeMap<String, Scalar>, name: &str) -> TCResult<Map<Scalar>> {
+    match args.remove(name) {
+        Some(Scalar::Map(object)) => Ok(object),
+        Some(other) => Err(error::bad_request(
+            &format!("Expected an input object for '{}' but found", name),
+            other,
+        )),
+        None => Err(error::bad_request("Missing argument", name)),
+    }
+}
+
+/// The strings of a GraphQL list argument, which may also be given as a single string.
+fn strings(arg: Scalar) -> TCResult<Vec<String>> {
+    let items: Vec<Scalar> = match arg {
+        Scalar::Tuple(items) => items.into_inner(),
+        Scalar::Value(Value::Tuple(items)) => {
+            items.into_inner().into_iter().map(Scalar::Value).collect()
+        }
+        other => vec![other],
+    };
+
+    items
+        .into_iter()
+        .map(|item| match item {
+            Scalar::Value(Value::TCString(TCString::UString(s))) => Ok(s),
+            other => Err(error::bad_request("Expected a String but found", other)),
+        })
+        .collect()
+}
+
+fn reject_directives(directives: &[gql::Directive<'static, String>]) -> TCResult<()> {
+    match directives.first() {
+        Some(directive) => Err(error::not_implemented(format!(
+            "GraphQL directives (@{})",
+            directive.name
+        ))),
+        None => Ok(()),
+    }
+}
+
+fn reject_extra_args(args: BTreeMap<String, Scalar>) -> TCResult<()> {
+    if args.is_empty() {
+        Ok(())
+    } else {
+        Err(error::bad_request(
+            "Unrecognized GraphQL arguments",
+            args.keys().cloned().collect::<Vec<String>>().join(", "),
+        ))
+    }
+}
+
+fn reject_extra_columns(columns: HashMap<Id, Scalar>) -> TCResult<()> {
+    if columns.is_empty() {
+        Ok(())
+    } else {
+        Err(error::bad_request(
+            "Unrecognized columns",
+            columns
+                .keys()
+                .map(|name| name.to_string())
+                .collect::<Vec<String>>()
+                .join(", "),
+        ))
+    }
+}
+
+/// Convert a GraphQL input value into a `Scalar`.
+///
+/// A string is always converted into a `String`, since only the type of the argument or column
+/// it's passed to can say whether it's meant as a `Link` (see `coerce`).
+fn scalar(
+    value: gql::Value<'static, String>,
+    variables: &HashMap<String, Scalar>,
+) -> TCResult<Scalar> {
+    use gql::Value as GQL;
+
+    match value {
+        GQL::Null => Ok(Scalar::from(())),
+        GQL::Boolean(b) => Ok(Value::from(b).into()),
+        GQL::Int(i) => i
+            .as_i64()
+            .map(|i| Value::Number(Int::from(i).into()).into())
+            .ok_or_else(|| error::bad_request(ERR_UNSUPPORTED, "integer out of range")),
+        GQL::Float(f) => Ok(Value::Number(Float::from(f).into()).into()),
+        GQL::String(s) => Ok(Value::TCString(TCString::UString(s)).into()),
+        GQL::Enum(e) => Ok(Value::TCString(TCString::UString(e)).into()),
+        GQL::List(items) => items
+            .into_iter()
+            .map(|item| scalar(item, variables))
+            .collect::<TCResult<Vec<Scalar>>>()
+            .map(Scalar::from),
+        GQL::Object(object) => object
+            .into_iter()
+            .map(|(name, value)| Ok((name.parse()?, scalar(value, variables)?)))
+            .collect::<TCResult<Map<Scalar>>>()
+            .map(Scalar::Map),
+        GQL::Variable(name) => variables
+            .get(&name)
+            .cloned()
+            .ok_or_else(|| error::bad_request("Undefined GraphQL variable", name)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::collection::schema::{IndexSchema, TableSchema};
+
+    fn column(name: &str, dtype: ValueType) -> Column {
+        Column::from((name.parse::<Id>().unwrap(), dtype))
+    }
+
+    fn schema() -> Schema {
+        let key = vec![column("id", ValueType::uint64())];
+        let values = vec![column("home", ValueType::TCString(StringType::Link))];
+        let table: TableSchema = IndexSchema::from((key, values)).into();
+        Schema::generate(vec![("/app/users".parse().unwrap(), table)])
+    }
+
+    fn query(schema: &Schema, query: &str) -> Scalar {
+        let document = gql::parse_query::<String>(query).unwrap().into_static();
+
+        let mut operation = None;
+        let mut fragments = Fragments::new();
+        for definition in document.definitions {
+            match definition {
+                Definition::Operation(op) => operation = Some(op),
+                Definition::Fragment(fragment) => {
+                    fragments.insert(fragment.name.clone(), fragment);
+                }
+            }
+        }
+
+        let selection = match operation.unwrap() {
+            OperationDefinition::Query(query) => query.selection_set,
+            OperationDefinition::SelectionSet(selection) => selection,
+            _ => panic!("expected a query"),
+        };
+
+        let mut fields = vec![];
+        collect_fields(QUERY, &selection, &fragments, &mut vec![], &mut fields).unwrap();
+        assert_eq!(fields.len(), 1);
+        assert_eq!(fields[0].name, "__schema");
+
+        let mut budget = MAX_FIELDS;
+        let selection = &fields[0].selection_set;
+        select(
+            schema.schema_object(),
+            selection,
+            &fragments,
+            1,
+            &mut budget,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn test_introspection() {
+        let schema = schema();
+        let result = query(
+            &schema,
+            "query IntrospectionQuery {
+                __schema {
+                    queryType { name }
+                    mutationType { name }
+                    types { ...FullType }
+                }
+            }
+
+            fragment FullType on __Type {
+                kind
+                name
+                fields(includeDeprecated: true) { name type { ...TypeRef } }
+            }
+
+            fragment TypeRef on __Type {
+                kind
+                name
+                ofType { kind name ofType { kind name } }
+            }",
+        );
+
+        let json = serde_json::to_value(&result).unwrap();
+        assert_eq!(json["queryType"]["name"], "Query");
+        assert_eq!(json["mutationType"]["name"], "Mutation");
+
+        let types = json["types"].as_array().unwrap();
+        let query = types.iter().find(|t| t["name"] == "Query").unwrap();
+        assert_eq!(query["kind"], "OBJECT");
+        assert_eq!(query["fields"][0]["name"], "app_users");
+        assert_eq!(query["fields"][0]["type"]["kind"], "NON_NULL");
+        assert_eq!(
+            query["fields"][0]["type"]["ofType"]["name"],
+            "AppUsersConnection"
+        );
+
+        let row = types.iter().find(|t| t["name"] == "AppUsers").unwrap();
+        assert_eq!(row["fields"][1]["name"], "home");
+        assert_eq!(row["fields"][1]["type"]["name"], "String");
+
+        let scalar = types.iter().find(|t| t["name"] == "Int").unwrap();
+        assert!(scalar["fields"].is_null());
+    }
+
+    #[test]
+    fn test_explicit_links() {
+        let text = "/not/a/link".to_string();
+        let value = scalar(gql::Value::String(text.clone()), &HashMap::new()).unwrap();
+        assert!(value == Value::TCString(TCString::UString(text.clone())));
+
+        let home = column("home", ValueType::TCString(StringType::Link));
+        let link = coerce(&home, value.clone()).unwrap();
+        assert!(link == Value::from(text.parse::<Link>().unwrap()));
+
+        let name = column("name", ValueType::TCString(StringType::UString));
+        assert!(coerce(&name, value).unwrap() == Value::TCString(TCString::UString(text)));
+
+        assert!(output(link) == Value::TCString(TCString::UString("/not/a/link".into())));
+    }
+
+    #[test]
+    fn test_cursor() {
+        let key = vec![column("id", ValueType::uint64())];
+        let cursor = encode_cursor(&[Value::from(42u64)]).unwrap();
+        assert!(decode_cursor(&key, &cursor).unwrap() == vec![Value::from(42u64)]);
+        assert!(decode_cursor(&key, "not a cursor").is_err());
+    }
+}
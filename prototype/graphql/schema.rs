@@ -0,0 +1,756 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+
+use log::warn;
+
+use crate::collection::schema::{Column, TableSchema};
+use crate::error;
+use crate::scalar::*;
+use crate::TCResult;
+
+use super::{Node, Object};
+
+pub const QUERY: &str = "Query";
+pub const MUTATION: &str = "Mutation";
+
+const PAGE_INFO: &str = "PageInfo";
+const VALUE: &str = "Value";
+
+const BOOLEAN: &str = "Boolean";
+const FLOAT: &str = "Float";
+const ID: &str = "ID";
+const INT: &str = "Int";
+const STRING: &str = "String";
+
+#[derive(Clone, Copy, Eq, PartialEq)]
+pub enum Kind {
+    Scalar,
+    Object,
+    InputObject,
+    List,
+    NonNull,
+}
+
+impl fmt::Display for Kind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Scalar => write!(f, "SCALAR"),
+            Self::Object => write!(f, "OBJECT"),
+            Self::InputObject => write!(f, "INPUT_OBJECT"),
+            Self::List => write!(f, "LIST"),
+            Self::NonNull => write!(f, "NON_NULL"),
+        }
+    }
+}
+
+/// A reference to a named type, or a list or non-null wrapper of one.
+#[derive(Clone, Eq, PartialEq)]
+pub enum TypeRef {
+    Named(String),
+    List(Box<TypeRef>),
+    NonNull(Box<TypeRef>),
+}
+
+impl TypeRef {
+    fn named(name: &str) -> TypeRef {
+        TypeRef::Named(name.to_string())
+    }
+
+    fn list(self) -> TypeRef {
+        TypeRef::List(Box::new(self))
+    }
+
+    fn non_null(self) -> TypeRef {
+        TypeRef::NonNull(Box::new(self))
+    }
+}
+
+impl fmt::Display for TypeRef {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Named(name) => write!(f, "{}", name),
+            Self::List(item) => write!(f, "[{}]", item),
+            Self::NonNull(inner) => write!(f, "{}!", inner),
+        }
+    }
+}
+
+pub struct InputValue {
+    name: String,
+    description: Option<String>,
+    dtype: TypeRef,
+}
+
+impl InputValue {
+    fn new(name: &str, dtype: TypeRef) -> InputValue {
+        InputValue {
+            name: name.to_string(),
+            description: None,
+            dtype,
+        }
+    }
+}
+
+pub struct Field {
+    name: String,
+    description: Option<String>,
+    args: Vec<InputValue>,
+    dtype: TypeRef,
+}
+
+impl Field {
+    fn new(name: &str, dtype: TypeRef) -> Field {
+        Field {
+            name: name.to_string(),
+            description: None,
+            args: vec![],
+            dtype,
+        }
+    }
+
+    fn describe(self, description: String) -> Field {
+        Field {
+            description: Some(description),
+            ..self
+        }
+    }
+}
+
+pub struct Type {
+    kind: Kind,
+    name: String,
+    description: Option<String>,
+    fields: Vec<Field>,
+    input_fields: Vec<InputValue>,
+}
+
+impl Type {
+    fn scalar(name: &str, description: Option<&str>) -> Type {
+        Type {
+            kind: Kind::Scalar,
+            name: name.to_string(),
+            description: description.map(String::from),
+            fields: vec![],
+            input_fields: vec![],
+        }
+    }
+
+    fn object(name: &str, fields: Vec<Field>) -> Type {
+        Type {
+            kind: Kind::Object,
+            name: name.to_string(),
+            description: None,
+            fields,
+            input_fields: vec![],
+        }
+    }
+
+    fn input(name: &str, input_fields: Vec<InputValue>) -> Type {
+        Type {
+            kind: Kind::InputObject,
+            name: name.to_string(),
+            description: None,
+            fields: vec![],
+            input_fields,
+        }
+    }
+}
+
+/// A Table exposed by the generated schema.
+pub struct TableDef {
+    pub link: Link,
+    pub type_name: String,
+    pub key: Vec<Column>,
+    pub values: Vec<Column>,
+}
+
+impl TableDef {
+    pub fn columns(&self) -> impl Iterator<Item = &Column> {
+        self.key.iter().chain(&self.values)
+    }
+
+    pub fn connection(&self) -> String {
+        format!("{}Connection", self.type_name)
+    }
+
+    pub fn edge(&self) -> String {
+        format!("{}Edge", self.type_name)
+    }
+}
+
+#[derive(Clone, Copy, Eq, PartialEq)]
+pub enum Mutation {
+    Put,
+    Delete,
+}
+
+/// The GraphQL schema of the Tables registered by the clusters of this host.
+///
+/// Each Table schema `name` registered by the cluster at `/cluster` is served at
+/// `/cluster/name` and exposed as a query field `cluster_name`, which returns a Relay-style
+/// connection of the Table's rows, and the mutations `put_cluster_name` and
+/// `delete_cluster_name`. A Table whose path or columns don't make valid GraphQL names, or
+/// whose names would collide with another Table's, is left out of the schema.
+pub struct Schema {
+    types: BTreeMap<String, Type>,
+    tables: HashMap<String, TableDef>,
+    mutations: HashMap<String, (Mutation, String)>,
+}
+
+impl Schema {
+    /// Generate the schema of the given Tables, each identified by the path it's served at.
+    pub fn generate<I: IntoIterator<Item = (TCPathBuf, TableSchema)>>(tables: I) -> Schema {
+        let mut types = BTreeMap::new();
+        for scalar in builtin_scalars() {
+            types.insert(scalar.name.clone(), scalar);
+        }
+
+        let page_info = Type::object(
+            PAGE_INFO,
+            vec![
+                Field::new("hasNextPage", TypeRef::named(BOOLEAN).non_null()),
+                Field::new("endCursor", TypeRef::named(STRING)),
+            ],
+        );
+        types.insert(page_info.name.clone(), page_info);
+
+        let mut tables: Vec<(TCPathBuf, TableSchema)> = tables.into_iter().collect();
+        tables.sort_by_key(|(path, _)| path.to_string());
+
+        let mut schema = Schema {
+            types,
+            tables: HashMap::new(),
+            mutations: HashMap::new(),
+        };
+
+        let mut query = vec![];
+        let mut mutation = vec![];
+        for (path, table) in tables {
+            match schema.add_table(path.clone(), &table) {
+                Some((table_fields, mutation_fields)) => {
+                    query.extend(table_fields);
+                    mutation.extend(mutation_fields);
+                }
+                None => warn!("Table at {} is not exposed to GraphQL", path),
+            }
+        }
+
+        schema
+            .types
+            .insert(QUERY.to_string(), Type::object(QUERY, query));
+
+        if !mutation.is_empty() {
+            schema
+                .types
+                .insert(MUTATION.to_string(), Type::object(MUTATION, mutation));
+        }
+
+        schema
+    }
+
+    // add the types of the Table at `path` and return its query and mutation fields
+    fn add_table(
+        &mut self,
+        path: TCPathBuf,
+        schema: &TableSchema,
+    ) -> Option<(Vec<Field>, Vec<Field>)> {
+        let field_name = field_name(&path)?;
+        let type_name = type_name(&field_name)?;
+
+        let key = schema.primary().key().to_vec();
+        let values = schema.primary().values().to_vec();
+        if !key
+            .iter()
+            .chain(&values)
+            .all(|col| is_name(col.name().as_str()))
+        {
+            return None;
+        }
+
+        let table = TableDef {
+            link: Link::from(path),
+            type_name,
+            key,
+            values,
+        };
+
+        let put = format!("put_{}", field_name);
+        let delete = format!("delete_{}", field_name);
+        if [&field_name, &put, &delete]
+            .iter()
+            .any(|name| self.tables.contains_key(*name) || self.mutations.contains_key(*name))
+        {
+            return None;
+        }
+
+        let row = table.type_name.clone();
+        let connection = table.connection();
+        let edge = table.edge();
+        let filter = format!("{}Filter", row);
+        let input = format!("{}Input", row);
+        let key_input = format!("{}Key", row);
+
+        let new_types = [&row, &connection, &edge, &filter, &input, &key_input];
+        if new_types
+            .iter()
+            .any(|name| self.types.contains_key(*name) || *name == QUERY || *name == MUTATION)
+        {
+            return None;
+        }
+
+        let row_fields = table
+            .key
+            .iter()
+            .map(|col| column_field(col, true))
+            .chain(table.values.iter().map(|col| column_field(col, false)))
+            .collect();
+
+        let edge_fields = vec![
+            Field::new("cursor", TypeRef::named(STRING).non_null()),
+            Field::new("node", TypeRef::named(&row).non_null()),
+        ];
+
+        let connection_fields = vec![
+            Field::new("edges", TypeRef::named(&edge).non_null().list().non_null()),
+            Field::new("nodes", TypeRef::named(&row).non_null().list().non_null()),
+            Field::new("pageInfo", TypeRef::named(PAGE_INFO).non_null()),
+        ];
+
+        let filter_fields = table
+            .columns()
+            .map(|col| InputValue::new(col.name().as_str(), column_type(col)))
+            .collect();
+
+        let input_fields = table
+            .columns()
+            .map(|col| InputValue::new(col.name().as_str(), column_type(col).non_null()))
+            .collect();
+
+        let key_fields = table
+            .key
+            .iter()
+            .map(|col| InputValue::new(col.name().as_str(), column_type(col).non_null()))
+            .collect();
+
+        for new_type in vec![
+            Type::object(&row, row_fields),
+            Type::object(&edge, edge_fields),
+            Type::object(&connection, connection_fields),
+            Type::input(&filter, filter_fields),
+            Type::input(&input, input_fields),
+            Type::input(&key_input, key_fields),
+        ] {
+            self.types.insert(new_type.name.clone(), new_type);
+        }
+
+        let mut query = Field::new(&field_name, TypeRef::named(&connection).non_null())
+            .describe(format!("The rows of the Table at {}", table.link));
+
+        query.args = vec![
+            InputValue::new("where", TypeRef::named(&filter)),
+            InputValue::new("order_by", TypeRef::named(STRING).non_null().list()),
+            InputValue::new("reverse", TypeRef::named(BOOLEAN)),
+            InputValue::new("first", TypeRef::named(INT)),
+            InputValue::new("after", TypeRef::named(STRING)),
+        ];
+
+        let mut put_field = Field::new(&put, TypeRef::named(BOOLEAN).non_null()).describe(format!(
+            "Insert or update a row of the Table at {}",
+            table.link
+        ));
+        put_field.args = vec![InputValue::new("row", TypeRef::named(&input).non_null())];
+
+        let mut delete_field = Field::new(&delete, TypeRef::named(BOOLEAN).non_null())
+            .describe(format!("Delete a row of the Table at {}", table.link));
+        delete_field.args = vec![InputValue::new(
+            "key",
+            TypeRef::named(&key_input).non_null(),
+        )];
+
+        self.mutations
+            .insert(put, (Mutation::Put, field_name.clone()));
+        self.mutations
+            .insert(delete, (Mutation::Delete, field_name.clone()));
+        self.tables.insert(field_name, table);
+
+        Some((vec![query], vec![put_field, delete_field]))
+    }
+
+    pub fn table(&'_ self, field: &str) -> Option<&'_ TableDef> {
+        self.tables.get(field)
+    }
+
+    /// Return the kind of the mutation `field`, and the Table it mutates.
+    pub fn mutation(&'_ self, field: &str) -> Option<(Mutation, &'_ TableDef)> {
+        let (mutation, table) = self.mutations.get(field)?;
+        self.tables.get(table).map(|table| (*mutation, table))
+    }
+
+    /// The introspection object `__Type` of the type `name`, if there is one.
+    pub fn type_object<'a>(&'a self, name: &str) -> Node<'a> {
+        if self.types.contains_key(name) {
+            Node::Object(Box::new(TypeObject {
+                schema: self,
+                dtype: TypeRef::named(name),
+            }))
+        } else {
+            Node::Null
+        }
+    }
+
+    /// The introspection object `__Schema` of this schema.
+    pub fn schema_object(&'_ self) -> Node<'_> {
+        Node::Object(Box::new(SchemaObject { schema: self }))
+    }
+}
+
+fn builtin_scalars() -> Vec<Type> {
+    vec![
+        Type::scalar(BOOLEAN, None),
+        Type::scalar(FLOAT, None),
+        Type::scalar(ID, None),
+        Type::scalar(INT, None),
+        Type::scalar(STRING, None),
+        Type::scalar(VALUE, Some("A TinyChain Value, encoded as JSON")),
+    ]
+}
+
+fn column_field(column: &Column, required: bool) -> Field {
+    let dtype = if required {
+        column_type(column).non_null()
+    } else {
+        column_type(column)
+    };
+
+    Field::new(column.name().as_str(), dtype).describe(column.dtype().to_string())
+}
+
+/// The GraphQL type of the values of `column`.
+fn column_type(column: &Column) -> TypeRef {
+    let name = match column.dtype() {
+        ValueType::Number(NumberType::Bool) => BOOLEAN,
+        ValueType::Number(NumberType::Float(_)) => FLOAT,
+        ValueType::Number(NumberType::Int(_)) => INT,
+        ValueType::Number(NumberType::UInt(_)) => INT,
+        ValueType::TCString(_) => STRING,
+        _ => VALUE,
+    };
+
+    TypeRef::named(name)
+}
+
+/// Return `true` if `name` is a valid GraphQL name which is not reserved for introspection.
+pub fn is_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    match chars.next() {
+        Some(c) if c.is_ascii_alphabetic() || c == '_' => {}
+        _ => return false,
+    }
+
+    !name.starts_with("__") && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+/// The name of the query field of the Table at `path`, so `/app/users` is `app_users`.
+fn field_name(path: &TCPathBuf) -> Option<String> {
+    let name = path
+        .iter()
+        .map(|segment| segment.as_str())
+        .collect::<Vec<&str>>()
+        .join("_");
+
+    if is_name(&name) {
+        Some(name)
+    } else {
+        None
+    }
+}
+
+/// The name of the object type of the rows of a Table, so `app_users` is `AppUsers`.
+fn type_name(field_name: &str) -> Option<String> {
+    let name = field_name
+        .split('_')
+        .filter(|word| !word.is_empty())
+        .map(|word| {
+            let mut chars = word.chars();
+            match chars.next() {
+                Some(first) => first.to_ascii_uppercase().to_string() + chars.as_str(),
+                None => String::new(),
+            }
+        })
+        .collect::<String>();
+
+    if is_name(&name) {
+        Some(name)
+    } else {
+        None
+    }
+}
+
+fn string(value: &str) -> Node<'static> {
+    Node::Leaf(Value::TCString(TCString::UString(value.to_string())))
+}
+
+fn optional(value: &Option<String>) -> Node<'static> {
+    match value {
+        Some(value) => string(value),
+        None => Node::Null,
+    }
+}
+
+fn unknown_field(typename: &str, name: &str) -> error::TCError {
+    error::bad_request(&format!("{} has no field", typename), name)
+}
+
+struct SchemaObject<'a> {
+    schema: &'a Schema,
+}
+
+impl<'a> Object for SchemaObject<'a> {
+    fn typename(&self) -> &str {
+        "__Schema"
+    }
+
+    fn field(&'_ self, name: &str) -> TCResult<Node<'_>> {
+        match name {
+            "description" => Ok(Node::Null),
+            "types" => Ok(Node::List(
+                self.schema
+                    .types
+                    .keys()
+                    .map(|name| self.schema.type_object(name))
+                    .collect(),
+            )),
+            "queryType" => Ok(self.schema.type_object(QUERY)),
+            "mutationType" => Ok(self.schema.type_object(MUTATION)),
+            "subscriptionType" => Ok(Node::Null),
+            "directives" => Ok(Node::List(vec![])),
+            other => Err(unknown_field(self.typename(), other)),
+        }
+    }
+}
+
+struct TypeObject<'a> {
+    schema: &'a Schema,
+    dtype: TypeRef,
+}
+
+impl<'a> TypeObject<'a> {
+    fn new(schema: &'a Schema, dtype: &TypeRef) -> Node<'a> {
+        Node::Object(Box::new(TypeObject {
+            schema,
+            dtype: dtype.clone(),
+        }))
+    }
+
+    fn definition(&self) -> TCResult<Option<&'a Type>> {
+        match &self.dtype {
+            TypeRef::Named(name) => self
+                .schema
+                .types
+                .get(name)
+                .map(Some)
+                .ok_or_else(|| error::internal(format!("GraphQL type {} is not defined", name))),
+            _ => Ok(None),
+        }
+    }
+}
+
+impl<'a> Object for TypeObject<'a> {
+    fn typename(&self) -> &str {
+        "__Type"
+    }
+
+    fn field(&'_ self, name: &str) -> TCResult<Node<'_>> {
+        let definition = self.definition()?;
+        let kind = match (&self.dtype, definition) {
+            (TypeRef::List(_), _) => Kind::List,
+            (TypeRef::NonNull(_), _) => Kind::NonNull,
+            (TypeRef::Named(_), Some(definition)) => definition.kind,
+            (TypeRef::Named(name), None) => {
+                return Err(error::internal(format!(
+                    "GraphQL type {} is not defined",
+                    name
+                )))
+            }
+        };
+
+        match name {
+            "kind" => Ok(string(&kind.to_string())),
+            "name" => Ok(definition
+                .map(|def| string(&def.name))
+                .unwrap_or(Node::Null)),
+            "description" => Ok(definition
+                .map(|def| optional(&def.description))
+                .unwrap_or(Node::Null)),
+            "fields" => Ok(match definition {
+                Some(def) if def.kind == Kind::Object => Node::List(
+                    def.fields
+                        .iter()
+                        .map(|field| {
+                            Node::Object(Box::new(FieldObject {
+                                schema: self.schema,
+                                field,
+                            }))
+                        })
+                        .collect(),
+                ),
+                _ => Node::Null,
+            }),
+            "inputFields" => Ok(match definition {
+                Some(def) if def.kind == Kind::InputObject => Node::List(
+                    def.input_fields
+                        .iter()
+                        .map(|input| InputValueObject::new(self.schema, input))
+                        .collect(),
+                ),
+                _ => Node::Null,
+            }),
+            "interfaces" => Ok(match kind {
+                Kind::Object => Node::List(vec![]),
+                _ => Node::Null,
+            }),
+            "possibleTypes" | "enumValues" | "specifiedByURL" | "specifiedByUrl" => Ok(Node::Null),
+            "ofType" => Ok(match &self.dtype {
+                TypeRef::List(inner) | TypeRef::NonNull(inner) => {
+                    TypeObject::new(self.schema, inner)
+                }
+                TypeRef::Named(_) => Node::Null,
+            }),
+            other => Err(unknown_field(self.typename(), other)),
+        }
+    }
+}
+
+struct FieldObject<'a> {
+    schema: &'a Schema,
+    field: &'a Field,
+}
+
+impl<'a> Object for FieldObject<'a> {
+    fn typename(&self) -> &str {
+        "__Field"
+    }
+
+    fn field(&'_ self, name: &str) -> TCResult<Node<'_>> {
+        match name {
+            "name" => Ok(string(&self.field.name)),
+            "description" => Ok(optional(&self.field.description)),
+            "args" => Ok(Node::List(
+                self.field
+                    .args
+                    .iter()
+                    .map(|arg| InputValueObject::new(self.schema, arg))
+                    .collect(),
+            )),
+            "type" => Ok(TypeObject::new(self.schema, &self.field.dtype)),
+            "isDeprecated" => Ok(Node::Leaf(false.into())),
+            "deprecationReason" => Ok(Node::Null),
+            other => Err(unknown_field(self.typename(), other)),
+        }
+    }
+}
+
+struct InputValueObject<'a> {
+    schema: &'a Schema,
+    input: &'a InputValue,
+}
+
+impl<'a> InputValueObject<'a> {
+    fn new(schema: &'a Schema, input: &'a InputValue) -> Node<'a> {
+        Node::Object(Box::new(InputValueObject { schema, input }))
+    }
+}
+
+impl<'a> Object for InputValueObject<'a> {
+    fn typename(&self) -> &str {
+        "__InputValue"
+    }
+
+    fn field(&'_ self, name: &str) -> TCResult<Node<'_>> {
+        match name {
+            "name" => Ok(string(&self.input.name)),
+            "description" => Ok(optional(&self.input.description)),
+            "type" => Ok(TypeObject::new(self.schema, &self.input.dtype)),
+            "defaultValue" => Ok(Node::Null),
+            "isDeprecated" => Ok(Node::Leaf(false.into())),
+            "deprecationReason" => Ok(Node::Null),
+            other => Err(unknown_field(self.typename(), other)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::collection::schema::IndexSchema;
+
+    fn column(name: &str, dtype: ValueType) -> Column {
+        Column::from((name.parse::<Id>().unwrap(), dtype))
+    }
+
+    fn users() -> (TCPathBuf, TableSchema) {
+        let key = vec![column("id", ValueType::uint64())];
+        let values = vec![
+            column("name", ValueType::TCString(StringType::UString)),
+            column("home", ValueType::TCString(StringType::Link)),
+        ];
+
+        let path = "/app/users".parse().unwrap();
+        (path, IndexSchema::from((key, values)).into())
+    }
+
+    #[test]
+    fn test_names() {
+        assert!(is_name("app_users"));
+        assert!(is_name("_private"));
+        assert!(!is_name("__schema"));
+        assert!(!is_name("2020_sales"));
+        assert!(!is_name("user-id"));
+
+        assert_eq!(type_name("app_users"), Some("AppUsers".to_string()));
+        assert_eq!(
+            field_name(&"/app/users".parse().unwrap()),
+            Some("app_users".to_string())
+        );
+        assert_eq!(field_name(&"/app/user-list".parse().unwrap()), None);
+    }
+
+    #[test]
+    fn test_generate() {
+        let schema = Schema::generate(vec![users()]);
+
+        let table = schema.table("app_users").unwrap();
+        assert_eq!(table.link, "/app/users".parse::<Link>().unwrap());
+        assert_eq!(table.type_name, "AppUsers");
+
+        assert!(schema.mutation("put_app_users").unwrap().0 == Mutation::Put);
+        assert!(schema.mutation("delete_app_users").unwrap().0 == Mutation::Delete);
+
+        let row = &schema.types["AppUsers"];
+        let fields: Vec<String> = row
+            .fields
+            .iter()
+            .map(|field| format!("{}: {}", field.name, field.dtype))
+            .collect();
+        assert_eq!(fields, vec!["id: Int!", "name: String", "home: String"]);
+
+        let query = &schema.types[QUERY];
+        assert_eq!(query.fields.len(), 1);
+        assert_eq!(query.fields[0].dtype.to_string(), "AppUsersConnection!");
+    }
+
+    #[test]
+    fn test_collision() {
+        let (_, table) = users();
+        let schema = Schema::generate(vec![
+            ("/app/users".parse().unwrap(), table.clone()),
+            ("/app_users".parse().unwrap(), table),
+        ]);
+
+        assert_eq!(schema.types[QUERY].fields.len(), 1);
+        assert_eq!(
+            schema.table("app_users").unwrap().link,
+            "/app/users".parse::<Link>().unwrap()
+        );
+    }
+}
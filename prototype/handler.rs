@@ -52,6 +52,7 @@ pub trait Handler: Send + Sync {
         value: State,
     ) -> TCResult<()> {
         self.authorize(request)?;
+        txn.gateway().disk().admit()?;
         self.handle_put(request, txn, key, value).await
     }
 
@@ -72,6 +73,10 @@ pub trait Handler: Send + Sync {
         params: Map<Scalar>,
     ) -> TCResult<State> {
         self.authorize(request)?;
+        if self.scope() == Some(auth::SCOPE_WRITE.into()) {
+            txn.gateway().disk().admit()?;
+        }
+
         self.handle_post(request, txn, params).await
     }
 
@@ -80,6 +85,7 @@ pub trait Handler: Send + Sync {
     }
 
     async fn delete(self: Box<Self>, request: &Request, txn: &Txn, key: Value) -> TCResult<()> {
+        // a DELETE frees space, so it's admitted even when the disk is full
         self.authorize(request)?;
         self.handle_delete(txn, key).await
     }
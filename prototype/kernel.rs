@@ -7,9 +7,13 @@ use log::debug;
 use crate::chain::{ChainClass, ChainType};
 use crate::class::{NativeClass, State, TCType};
 use crate::collection::class::{CollectionClass, CollectionType};
+use crate::collection::{Collection, TensorType};
 use crate::error::{self, ErrorType};
+use crate::handler::Public;
 use crate::object::ObjectType;
+use crate::registry;
 use crate::request::Request;
+use crate::routes::{self, Method, Native};
 use crate::scalar::*;
 use crate::transaction::Txn;
 use crate::{Match, TCResult, TryCastInto};
@@ -33,8 +37,23 @@ pub async fn get(txn: &Txn, path: &[PathSegment], id: Value) -> TCResult<State>
                 .map_ok(State::Chain)
                 .await
         }
+        "collection" if path.len() == 5 && &path[2] == "tensor" => {
+            let ttype = TensorType::from_path(&path[..4])?;
+            ttype
+                .construct(txn, &path[4], id)
+                .map_ok(Collection::Tensor)
+                .map_ok(State::Collection)
+                .await
+        }
         "collection" => {
             let ctype = CollectionType::from_path(path)?;
+
+            // a Table resolves a registered schema itself, to validate writes against it
+            let id = match ctype {
+                CollectionType::Table(_) => id,
+                _ => registry::resolve(txn, id).await?.0,
+            };
+
             ctype.get(txn, id).map_ok(State::Collection).await
         }
         "error" => {
@@ -59,26 +78,55 @@ pub async fn post(
     debug!("kernel::post {}", TCPath::from(path));
 
     if path.is_empty() {
-        Err(error::method_not_allowed("/"))
-    } else if &path[0] == "sbin" && &path[1] == "transact" {
-        if data.matches::<Vec<(Id, Scalar)>>() {
-            let values: Vec<(Id, Scalar)> = data.opt_cast_into().unwrap();
-            txn.execute(request, HashMap::new(), values).await
-        } else {
-            Ok(State::Scalar(data))
+        return Err(error::method_not_allowed("/"));
+    }
+
+    match routes::resolve(path, Method::Post)? {
+        Native::Transact => {
+            if data.matches::<Vec<(Id, Scalar)>>() {
+                let values: Vec<(Id, Scalar)> = data.opt_cast_into().unwrap();
+                txn.execute(request, HashMap::new(), values).await
+            } else {
+                Ok(State::Scalar(data))
+            }
+        }
+        Native::Object => {
+            let data = data.try_into()?;
+            ObjectType::post(path, data).map(State::Object)
+        }
+        #[cfg(feature = "graphql")]
+        Native::GraphQL if path.len() == 2 => crate::graphql::post(request, txn, data).await,
+        #[cfg(feature = "sql")]
+        Native::Sql if path.len() == 2 => crate::sql::post(request, txn, data).await,
+        Native::Blobs => {
+            let params = data.try_into()?;
+            let blobs = txn.gateway().blobs();
+            blobs.post(request, txn, &path[2..], params).await
+        }
+        Native::Migrate => {
+            let params = data.try_into()?;
+            let migrations = txn.gateway().migrations();
+            migrations.post(request, txn, &path[2..], params).await
         }
-    } else if path.starts_with(ObjectType::prefix().as_slice()) {
-        let data = data.try_into()?;
-        ObjectType::post(path, data).map(State::Object)
-    } else {
-        match path[0].as_str() {
-            "sbin" if path.len() > 1 => match path[1].as_str() {
-                "chain" | "collection" | "error" | "value" => {
-                    Err(error::method_not_allowed(&path[1]))
-                }
-                other => Err(error::not_found(other)),
-            },
-            other => Err(error::not_found(other)),
+        Native::Sequence => {
+            let params = data.try_into()?;
+            let sequences = txn.gateway().sequences();
+            sequences.post(request, txn, &path[2..], params).await
         }
+        #[cfg(feature = "graphql")]
+        Native::GraphQL => Err(error::path_not_found(path)),
+        #[cfg(feature = "sql")]
+        Native::Sql => Err(error::path_not_found(path)),
+        Native::Chain
+        | Native::Collection
+        | Native::Config
+        | Native::Connectors
+        | Native::Disk
+        | Native::Error
+        | Native::LogLevel
+        | Native::Metrics
+        | Native::Routes
+        | Native::Schema
+        | Native::Value => Err(error::method_not_allowed(TCPath::from(path))),
     }
 }
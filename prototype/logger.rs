@@ -1,10 +1,77 @@
-use log::{Metadata, Record};
+use std::cmp::max;
+use std::str::FromStr;
+use std::sync::RwLock;
 
-pub struct Logger;
+use log::{LevelFilter, Metadata, Record};
+
+use crate::error;
+use crate::TCResult;
+
+pub static LOGGER: Logger = Logger::new();
+
+struct Filters {
+    level: LevelFilter,
+    modules: Vec<(String, LevelFilter)>,
+}
+
+/// The host logger, which supports a global log level plus per-module filters.
+///
+/// Filters are written like `warn,tinychain::transaction=debug`, and can be changed at runtime
+/// via the `log_level` setting at `/sbin/config`, which is also available at `/sbin/log_level`.
+/// A filter applies to the given module and all its submodules, and the most specific filter for
+/// a module takes precedence over the global level.
+pub struct Logger {
+    filters: RwLock<Filters>,
+}
+
+impl Logger {
+    pub const fn new() -> Logger {
+        Logger {
+            filters: RwLock::new(Filters {
+                level: LevelFilter::Warn,
+                modules: Vec::new(),
+            }),
+        }
+    }
+
+    /// Set the global log level, keeping any per-module filters.
+    pub fn set_level(&self, level: LevelFilter) {
+        let mut filters = self.filters.write().unwrap();
+        filters.level = level;
+        update_max_level(&filters);
+    }
+
+    /// Replace the current filters with the given filter specification.
+    pub fn set_filters(&self, spec: &str) -> TCResult<()> {
+        let (level, modules) = parse_filters(spec)?;
+
+        let mut filters = self.filters.write().unwrap();
+        if let Some(level) = level {
+            filters.level = level;
+        }
+
+        filters.modules = modules;
+        update_max_level(&filters);
+        Ok(())
+    }
+
+    fn level_for(&self, target: &str) -> LevelFilter {
+        let filters = self.filters.read().unwrap();
+        for (module, filter) in &filters.modules {
+            if target == module
+                || (target.starts_with(module.as_str()) && target[module.len()..].starts_with("::"))
+            {
+                return *filter;
+            }
+        }
+
+        filters.level
+    }
+}
 
 impl log::Log for Logger {
-    fn enabled(&self, _metadata: &Metadata) -> bool {
-        true
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= self.level_for(metadata.target())
     }
 
     fn log(&self, record: &Record) {
@@ -15,3 +82,71 @@ impl log::Log for Logger {
 
     fn flush(&self) {}
 }
+
+/// Parse a filter specification like `warn,tinychain::transaction=debug` into a global level, if
+/// any, and a list of per-module filters, most specific first.
+pub fn parse_filters(spec: &str) -> TCResult<(Option<LevelFilter>, Vec<(String, LevelFilter)>)> {
+    let mut level = None;
+    let mut modules = Vec::new();
+
+    for directive in spec.split(',').map(str::trim).filter(|d| !d.is_empty()) {
+        if let Some(i) = directive.find('=') {
+            let module = directive[..i].trim();
+            if module.is_empty() {
+                return Err(error::bad_request("Invalid log filter", directive));
+            }
+
+            let filter = parse_level(&directive[i + 1..])?;
+            modules.push((module.to_string(), filter));
+        } else if level.is_none() {
+            level = Some(parse_level(directive)?);
+        } else {
+            return Err(error::bad_request(
+                "Log filter specifies more than one global level",
+                spec,
+            ));
+        }
+    }
+
+    // the longest (most specific) module path should match first
+    modules.sort_by(|(l, _), (r, _)| r.len().cmp(&l.len()));
+
+    Ok((level, modules))
+}
+
+fn parse_level(level: &str) -> TCResult<LevelFilter> {
+    LevelFilter::from_str(level.trim()).map_err(|_| error::bad_request("Invalid log level", level))
+}
+
+fn update_max_level(filters: &Filters) {
+    let level = filters
+        .modules
+        .iter()
+        .map(|(_, filter)| *filter)
+        .fold(filters.level, max);
+
+    log::set_max_level(level);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_filters() {
+        let spec = "warn, tinychain=info,tinychain::transaction=debug";
+        let (level, modules) = parse_filters(spec).unwrap();
+
+        assert_eq!(level, Some(LevelFilter::Warn));
+        assert_eq!(
+            modules,
+            vec![
+                ("tinychain::transaction".to_string(), LevelFilter::Debug),
+                ("tinychain".to_string(), LevelFilter::Info),
+            ]
+        );
+
+        assert!(parse_filters("warn,info").is_err());
+        assert!(parse_filters("=debug").is_err());
+    }
+}
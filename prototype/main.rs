@@ -1,31 +1,45 @@
+use std::iter;
 use std::net::IpAddr;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::pin::Pin;
 use std::str::FromStr;
 use std::sync::Arc;
 use std::time::Duration;
 
-use arrayfire as af;
 use futures::{Future, Stream};
 use log::debug;
+use rand::Rng;
 use structopt::StructOpt;
 
 mod auth;
+mod blob;
 mod block;
 mod chain;
 mod class;
 mod cluster;
 mod collection;
+mod config;
+mod connector;
+mod disk;
 mod error;
 mod gateway;
 mod general;
+#[cfg(feature = "graphql")]
+mod graphql;
 mod handler;
 mod kernel;
 mod lock;
 mod logger;
 mod object;
+mod persist;
+mod registry;
+mod replication;
 mod request;
+mod routes;
 mod scalar;
+mod sequence;
+#[cfg(feature = "sql")]
+mod sql;
 mod stream;
 mod transaction;
 
@@ -38,7 +52,6 @@ type TCStream<'a, T> = Pin<Box<dyn Stream<Item = T> + Send + Unpin + 'a>>;
 type TCTryStream<'a, T> = TCStream<'a, TCResult<T>>;
 
 const VERSION: &str = env!("CARGO_PKG_VERSION");
-static LOGGER: logger::Logger = logger::Logger;
 
 fn data_size(flag: &str) -> TCResult<usize> {
     if flag.is_empty() {
@@ -53,11 +66,33 @@ fn data_size(flag: &str) -> TCResult<usize> {
         Ok(size * 1000)
     } else if flag.ends_with('M') {
         Ok(size * 1_000_000)
+    } else if flag.ends_with('G') {
+        Ok(size * 1_000_000_000)
     } else {
         Err(error::bad_request("Unable to parse request_limit", flag))
     }
 }
 
+// the nonzero id of this host, which breaks ties between TxnIds issued by different hosts:
+// either the configured `--node_id`, or else a random id generated the first time this host
+// starts and stored under `data_dir`, so that it doesn't change when the host restarts
+async fn node_id(data_dir: &Path, configured: Option<u16>) -> TCResult<u16> {
+    match configured {
+        Some(0) => Err(error::bad_request("node_id must be nonzero, not", 0)),
+        Some(node_id) => Ok(node_id),
+        None => {
+            let stored = persist::Persistent::new(persist::sbin_dir(data_dir, "node"), "id");
+            if let Some(node_id) = stored.load()? {
+                Ok(node_id)
+            } else {
+                let node_id = rand::thread_rng().gen::<u16>().max(1);
+                stored.save(|| node_id).await?;
+                Ok(node_id)
+            }
+        }
+    }
+}
+
 fn duration(flag: &str) -> TCResult<Duration> {
     u64::from_str(flag)
         .map(Duration::from_secs)
@@ -78,6 +113,9 @@ struct Config {
     #[structopt(long = "http_port", default_value = "8702")]
     pub http_port: u16,
 
+    #[structopt(long = "node_id")]
+    pub node_id: Option<u16>,
+
     #[structopt(long = "ext")]
     pub adapters: Vec<scalar::value::link::Link>,
 
@@ -87,14 +125,68 @@ struct Config {
     #[structopt(long = "peer")]
     pub peers: Vec<scalar::value::link::LinkHost>,
 
+    #[structopt(long = "vnodes", default_value = "64")]
+    pub vnodes: usize,
+
+    #[structopt(long = "replica")]
+    pub replicas: Vec<scalar::value::link::LinkHost>,
+
+    #[structopt(long = "director")]
+    pub director: Option<scalar::value::link::LinkHost>,
+
+    #[structopt(long = "replication_window", default_value = "64M", parse(try_from_str = data_size))]
+    pub replication_window: usize,
+
+    #[structopt(long = "replication_shed_after", default_value = "30", parse(try_from_str = duration))]
+    pub replication_shed_after: Duration,
+
+    #[structopt(long = "session_wait", default_value = "5", parse(try_from_str = duration))]
+    pub session_wait: Duration,
+
+    #[structopt(long = "cache_size", default_value = "1G", parse(try_from_str = data_size))]
+    pub cache_size: usize,
+
+    #[structopt(long = "rate_limit")]
+    pub rate_limit: Option<u64>,
+
     #[structopt(long = "request_limit", default_value = "10M", parse(try_from_str = data_size))]
     pub request_limit: usize,
 
     #[structopt(long = "request_ttl", default_value = "30", parse(try_from_str = duration))]
     pub request_ttl: Duration,
 
+    #[structopt(long = "decode_parallelism")]
+    pub decode_parallelism: Option<usize>,
+
+    #[structopt(long = "blob_grace_period", default_value = "3600", parse(try_from_str = duration))]
+    pub blob_grace_period: Duration,
+
+    #[structopt(long = "data_dir_quota", parse(try_from_str = data_size))]
+    pub data_dir_quota: Option<usize>,
+
+    #[structopt(long = "workspace_quota", parse(try_from_str = data_size))]
+    pub workspace_quota: Option<usize>,
+
+    #[structopt(long = "disk_warn_percent", default_value = "90")]
+    pub disk_warn_percent: u8,
+
+    #[structopt(long = "fsck")]
+    pub fsck: bool,
+
+    #[structopt(long = "repair")]
+    pub repair: bool,
+
+    #[structopt(long = "device_memory_limit", parse(try_from_str = data_size))]
+    pub device_memory_limit: Option<usize>,
+
+    #[structopt(long = "einsum_working_set", default_value = "64M", parse(try_from_str = data_size))]
+    pub einsum_working_set: usize,
+
     #[structopt(long = "log_level", default_value = "warn")]
     pub log_level: log::LevelFilter,
+
+    #[structopt(long = "strict_promotion")]
+    pub strict_promotion: bool,
 }
 
 #[tokio::main]
@@ -106,15 +198,44 @@ async fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
     println!("Working directory: {}", &config.workspace.to_str().unwrap());
     println!();
 
-    af::info();
-    println!();
-
-    log::set_logger(&LOGGER)
-        .map(|()| log::set_max_level(config.log_level))
+    log::set_logger(&logger::LOGGER)
+        .map(|()| logger::LOGGER.set_level(config.log_level))
         .map_err(|e| error::internal(format!("Unable to configure logging: {}", e)))?;
 
+    if collection::tensor::backend::init() {
+        println!();
+    }
+
+    transaction::clock::set_node(node_id(&config.data_dir, config.node_id).await?);
+
     let txn_id = transaction::TxnId::new(gateway::Gateway::time());
-    let fs_cache_persistent = block::hostfs::mount(config.data_dir);
+    let blob_dir = config.data_dir.join("blobs");
+    if config.fsck {
+        fsck(
+            &config.data_dir,
+            blob_dir.clone(),
+            config.blob_grace_period,
+            config.repair,
+        )
+        .await?;
+    } else if config.repair {
+        return Err(error::bad_request("--repair requires", "--fsck").into());
+    }
+
+    let disk = disk::DiskMonitor::new(
+        config.data_dir.clone(),
+        config.data_dir_quota,
+        config.workspace.clone(),
+        config.workspace_quota,
+        config.disk_warn_percent,
+    );
+
+    for version in block::hostfs::migrate(&config.data_dir).await? {
+        println!("Migrated data directory to format version {}", version);
+    }
+
+    let migrations = block::hostfs::Migrations::new(config.data_dir.clone());
+    let fs_cache_persistent = block::hostfs::mount(config.data_dir.clone());
     let data_dir = block::Dir::create(fs_cache_persistent, "data_dir");
     let fs_cache_temporary = block::hostfs::mount(config.workspace);
     let workspace = block::Dir::create(fs_cache_temporary, "workspace");
@@ -123,29 +244,161 @@ async fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
     data_dir.commit(&txn_id).await;
     workspace.commit(&txn_id).await;
 
-    let hosted = configure(config.hosted, data_dir.clone(), workspace.clone()).await?;
+    // a host either directs the replication of its clusters to its replicas, or follows a director
+    let role = match config.director {
+        Some(_) if !config.replicas.is_empty() => {
+            return Err(
+                error::bad_request("--director cannot be combined with", "--replica").into(),
+            )
+        }
+        Some(director) => replication::Role::Actor {
+            director,
+            session_wait: config.session_wait,
+        },
+        None => replication::Role::Director {
+            replicas: config.replicas,
+            window: config.replication_window,
+            shed_after: config.replication_shed_after,
+        },
+    };
+
+    let host: scalar::value::link::LinkHost = (config.address, config.http_port).into();
+    let (hosted, placement) = configure(
+        host,
+        config.peers,
+        config.vnodes,
+        config.hosted,
+        &config.data_dir,
+        data_dir.clone(),
+        workspace.clone(),
+        &role,
+    )
+    .await?;
+
+    // by default, decode a large request body with up to one task per CPU
+    let decode_parallelism = config.decode_parallelism.unwrap_or_else(|| {
+        std::thread::available_parallelism()
+            .map(|cpus| cpus.get())
+            .unwrap_or(1)
+    });
+
     let gateway = gateway::Gateway::new(
         config.adapters,
         hosted,
+        placement,
         workspace.clone(),
+        &config.data_dir,
+        blob_dir,
+        config.cache_size,
+        config.rate_limit,
+        config.blob_grace_period,
+        disk,
+        migrations,
         config.request_limit,
         config.request_ttl,
+        decode_parallelism,
+        config.device_memory_limit,
+        config.einsum_working_set,
+        config.strict_promotion,
     )
     .map_err(Box::new)?;
 
-    Arc::new(gateway)
+    let gateway = Arc::new(gateway);
+    gateway.mount().await?;
+    gateway.connectors().resume(&gateway).await;
+    gateway.replicate()?;
+
+    gateway
         .http_listen(config.address, config.http_port)
         .await
         .map_err(|e| e.into())
 }
 
+// check the files under data_dir for inconsistencies, and report (or repair) them before serving,
+// returning an error if any problem remains, so that the host never serves inconsistent data
+//
+// the files on disk are the blob store and the `/sbin` state, which includes the journal of each
+// cluster's tensors; blocks and chains are held in memory by block::hostfs, so there are no block
+// checksums, orphaned blocks, or chain references on disk to check
+async fn fsck(
+    data_dir: &Path,
+    blob_dir: PathBuf,
+    grace_period: Duration,
+    repair: bool,
+) -> TCResult<()> {
+    let blobs = blob::BlobStore::new(blob_dir.clone(), grace_period);
+
+    let problems = check(data_dir, &blob_dir, &blobs, repair).await?;
+    for problem in &problems {
+        println!("{}{}", problem, if repair { " (repaired)" } else { "" });
+    }
+
+    println!("{} problem(s) found", problems.len());
+    println!();
+
+    if problems.is_empty() {
+        return Ok(());
+    } else if !repair {
+        return Err(error::bad_request(
+            "Refusing to serve an inconsistent data directory (run again with --repair)",
+            format!("{} problem(s) found", problems.len()),
+        ));
+    }
+
+    // some problems, like unrecognized files, are left for an operator, so check again
+    let remaining = check(data_dir, &blob_dir, &blobs, false).await?;
+    for problem in &remaining {
+        println!("{}", problem);
+    }
+
+    println!("{} problem(s) remain", remaining.len());
+    println!();
+
+    if remaining.is_empty() {
+        Ok(())
+    } else {
+        Err(error::bad_request(
+            "Refusing to serve an inconsistent data directory",
+            format!("{} problem(s) could not be repaired", remaining.len()),
+        ))
+    }
+}
+
+async fn check(
+    data_dir: &Path,
+    blob_dir: &Path,
+    blobs: &blob::BlobStore,
+    repair: bool,
+) -> TCResult<Vec<String>> {
+    println!("Checking {}...", blob_dir.to_str().unwrap());
+    let mut problems = blobs.fsck(repair).await?;
+
+    println!("Checking {}...", data_dir.join("sbin").to_str().unwrap());
+    problems.extend(persist::fsck(data_dir, repair)?);
+
+    Ok(problems)
+}
+
+// if any peers are given, each cluster path is hosted by only one of this host and its peers,
+// chosen by consistent hashing, and requests for the others are forwarded to their owners
 async fn configure(
+    host: scalar::value::link::LinkHost,
+    peers: Vec<scalar::value::link::LinkHost>,
+    vnodes: usize,
     clusters: Vec<scalar::value::link::TCPathBuf>,
+    data_path: &Path,
     data_dir: Arc<block::Dir>,
     workspace: Arc<block::Dir>,
-) -> TCResult<gateway::Hosted> {
+    role: &replication::Role,
+) -> TCResult<(gateway::Hosted, gateway::Placement)> {
     const RESERVED: [&str; 2] = ["/sbin", "/transact"];
 
+    let mut placement = if peers.is_empty() {
+        gateway::Placement::new(vec![], vnodes)
+    } else {
+        gateway::Placement::new(peers.into_iter().chain(iter::once(host.clone())), vnodes)
+    };
+
     let mut hosted = gateway::Hosted::new();
     for path in clusters {
         for reserved in &RESERVED {
@@ -159,9 +412,25 @@ async fn configure(
             }
         }
 
-        let cluster = cluster::Cluster::create(path.clone(), data_dir.clone(), workspace.clone())?;
-        hosted.push(path, cluster);
+        match placement.owner(&path) {
+            Some(owner) if owner != &host => {
+                debug!("cluster at {} is hosted by {}", path, owner);
+                placement.place(path);
+            }
+            _ => {
+                let schemas = registry::SchemaRegistry::load(data_path, path.clone())?;
+                let cluster = cluster::Cluster::create(
+                    path.clone(),
+                    data_path,
+                    data_dir.clone(),
+                    workspace.clone(),
+                    schemas,
+                    role,
+                )?;
+                hosted.push(path, cluster);
+            }
+        }
     }
 
-    Ok(hosted)
+    Ok((hosted, placement))
 }
@@ -0,0 +1,364 @@
+use std::collections::HashSet;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use log::debug;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use tokio::io::AsyncWriteExt;
+use uuid::Uuid;
+
+use crate::error;
+use crate::lock::RwLock;
+use crate::transaction::TxnId;
+use crate::TCResult;
+
+/// The name of the directory which holds the journal of a cluster.
+pub const JOURNAL: &str = "journal";
+
+/// The name of the state which holds the snapshot of a cluster's compacted journal.
+pub const SNAPSHOT: &str = "snapshot";
+
+const SBIN_DIR: &str = "sbin";
+const QUARANTINE_DIR: &str = "quarantine";
+const EXT: &str = "json";
+
+/// Return the directory under `data_dir` which holds the state of the `/sbin` resource `name`.
+pub fn sbin_dir(data_dir: &Path, name: &str) -> PathBuf {
+    data_dir.join(SBIN_DIR).join(name)
+}
+
+/// The durable state of a `/sbin` resource which isn't held by a cluster, like a sequence or a
+/// configuration setting, stored as JSON (usually an encoded `Value`) in a file under
+/// `data_dir/sbin`.
+///
+/// The file is replaced by writing a temporary file and renaming it, so a host which stops in the
+/// middle of a write starts again with either the old state or the new state.
+pub struct Persistent {
+    dir: PathBuf,
+    path: PathBuf,
+    lock: RwLock<()>,
+}
+
+impl Persistent {
+    pub fn new(dir: PathBuf, name: &str) -> Persistent {
+        let path = dir.join(name).with_extension(EXT);
+
+        Persistent {
+            dir,
+            path,
+            lock: RwLock::new(()),
+        }
+    }
+
+    /// List the names of the states stored in `dir`.
+    ///
+    /// This blocks the current thread, so it should only be called while the host starts.
+    pub fn list(dir: &Path) -> TCResult<Vec<String>> {
+        let entries = match std::fs::read_dir(dir) {
+            Ok(entries) => entries,
+            Err(cause) if cause.kind() == io::ErrorKind::NotFound => return Ok(vec![]),
+            Err(cause) => return Err(io_error(dir, cause)),
+        };
+
+        let mut names = vec![];
+        for entry in entries {
+            let path = entry.map_err(|e| io_error(dir, e))?.path();
+            if path.extension().and_then(|ext| ext.to_str()) == Some(EXT) {
+                if let Some(name) = path.file_stem().and_then(|name| name.to_str()) {
+                    names.push(name.to_string());
+                }
+            }
+        }
+
+        Ok(names)
+    }
+
+    /// Read the stored state, or `None` if no state has been stored yet.
+    ///
+    /// This blocks the current thread, so it should only be called while the host starts.
+    pub fn load<T: DeserializeOwned>(&self) -> TCResult<Option<T>> {
+        match std::fs::read_to_string(&self.path) {
+            Ok(data) => serde_json::from_str(&data).map(Some).map_err(|e| {
+                error::internal(format!("Invalid state stored at {:?}: {}", self.path, e))
+            }),
+            Err(cause) if cause.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(cause) => Err(io_error(&self.path, cause)),
+        }
+    }
+
+    /// Store the state returned by `snapshot`.
+    ///
+    /// `snapshot` is called while holding this file's write lock, so concurrent calls are written
+    /// in the order their snapshots were taken and the file always holds the latest one.
+    pub async fn save<T: Serialize, F: FnOnce() -> T>(&self, snapshot: F) -> TCResult<()> {
+        let _lock = self.lock.write().await;
+        let data = serde_json::to_string(&snapshot())?;
+
+        tokio::fs::create_dir_all(&self.dir)
+            .await
+            .map_err(|e| io_error(&self.path, e))?;
+
+        let tmp_path = self.dir.join(Uuid::new_v4().to_string());
+        if let Err(cause) = write(&tmp_path, data.as_bytes()).await {
+            tokio::fs::remove_file(&tmp_path).await.ok();
+            return Err(io_error(&self.path, cause));
+        }
+
+        if let Err(cause) = tokio::fs::rename(&tmp_path, &self.path).await {
+            tokio::fs::remove_file(&tmp_path).await.ok();
+            return Err(io_error(&self.path, cause));
+        }
+
+        debug!("saved {:?}", self.path);
+        Ok(())
+    }
+
+    /// Delete the stored state, if any.
+    pub async fn delete(&self) -> TCResult<()> {
+        let _lock = self.lock.write().await;
+        match tokio::fs::remove_file(&self.path).await {
+            Ok(()) => Ok(()),
+            Err(cause) if cause.kind() == io::ErrorKind::NotFound => Ok(()),
+            Err(cause) => Err(io_error(&self.path, cause)),
+        }
+    }
+}
+
+/// Check the `/sbin` state under `data_dir`, including the journal of each cluster, for
+/// inconsistencies, and return a description of each one found.
+///
+/// This finds states which aren't valid JSON, like a journal entry truncated by a crash, and
+/// temporary files left by a write which never finished. A cluster's journal is replayed in
+/// order on top of its snapshot, so every entry after an unreadable entry, or after an unreadable
+/// snapshot, can't be replayed either, and is reported as dropped.
+///
+/// If `repair` is `true`, an unreadable or dropped state is moved under `data_dir/quarantine`,
+/// at the same path it had under `data_dir`, and an incomplete write is deleted. Unrecognized
+/// files are left for an operator to inspect.
+///
+/// This blocks the current thread, so it should only be called while the host starts.
+pub fn fsck(data_dir: &Path, repair: bool) -> TCResult<Vec<String>> {
+    let mut problems = vec![];
+    let mut dirs = vec![data_dir.join(SBIN_DIR)];
+    let mut broken = HashSet::new();
+
+    while let Some(dir) = dirs.pop() {
+        let entries = match std::fs::read_dir(&dir) {
+            Ok(entries) => entries,
+            Err(cause) if cause.kind() == io::ErrorKind::NotFound => continue,
+            Err(cause) => return Err(io_error(&dir, cause)),
+        };
+
+        let mut states = vec![];
+        for entry in entries {
+            let path = entry.map_err(|e| io_error(&dir, e))?.path();
+            if path.is_dir() {
+                dirs.push(path);
+            } else if path.extension().and_then(|ext| ext.to_str()) == Some(EXT) {
+                states.push(path);
+            } else if is_incomplete(&path) {
+                problems.push(format!("incomplete write {:?}", path));
+                if repair {
+                    std::fs::remove_file(&path).map_err(|e| io_error(&path, e))?;
+                }
+            } else {
+                problems.push(format!("unrecognized file {:?}", path));
+            }
+        }
+
+        let is_journal = dir.file_name().and_then(|name| name.to_str()) == Some(JOURNAL);
+        if is_journal {
+            states.sort_by_key(|path| journal_entry(path));
+        }
+
+        // once a journal can't be replayed, none of its later entries can be either
+        let mut truncated = broken.contains(&dir);
+        let mut dropped = vec![];
+        for path in states {
+            if is_journal && journal_entry(&path).is_none() {
+                problems.push(format!("unrecognized journal entry {:?}", path));
+                dropped.push(path);
+            } else if let Err(cause) = read_state(&path) {
+                problems.push(format!("unreadable state {:?}: {}", path, cause));
+
+                if is_journal {
+                    truncated = true;
+                } else if path.file_stem().and_then(|name| name.to_str()) == Some(SNAPSHOT) {
+                    broken.insert(dir.join(JOURNAL));
+                }
+
+                dropped.push(path);
+            } else if truncated {
+                problems.push(format!(
+                    "journal entry {:?} follows an unreadable state, so it's dropped",
+                    path
+                ));
+
+                dropped.push(path);
+            }
+        }
+
+        if repair {
+            for path in dropped {
+                quarantine(data_dir, &path)?;
+            }
+        }
+    }
+
+    Ok(problems)
+}
+
+// the ID of the transaction which wrote the journal entry at `path`, if it's a valid entry
+fn journal_entry(path: &Path) -> Option<TxnId> {
+    path.file_stem()
+        .and_then(|name| name.to_str())
+        .and_then(|name| name.parse().ok())
+}
+
+fn read_state(path: &Path) -> Result<serde_json::Value, String> {
+    let data = std::fs::read(path).map_err(|e| e.to_string())?;
+    serde_json::from_slice(&data).map_err(|e| e.to_string())
+}
+
+// move the file at `path` from under `data_dir` to the same path under `data_dir/quarantine`
+fn quarantine(data_dir: &Path, path: &Path) -> TCResult<()> {
+    let relative = path
+        .strip_prefix(data_dir)
+        .map_err(|_| error::internal(format!("{:?} is not under {:?}", path, data_dir)))?;
+
+    let dest = data_dir.join(QUARANTINE_DIR).join(relative);
+    if let Some(parent) = dest.parent() {
+        std::fs::create_dir_all(parent).map_err(|e| io_error(parent, e))?;
+    }
+
+    std::fs::rename(path, &dest).map_err(|e| io_error(path, e))
+}
+
+// a write in progress is named with a random UUID until it's renamed into place
+fn is_incomplete(path: &Path) -> bool {
+    path.file_name()
+        .and_then(|name| name.to_str())
+        .map(|name| Uuid::parse_str(name).is_ok())
+        .unwrap_or(false)
+}
+
+fn io_error(path: &Path, cause: io::Error) -> error::TCError {
+    error::internal(format!("Unable to access {:?}: {}", path, cause))
+}
+
+async fn write(path: &Path, data: &[u8]) -> io::Result<()> {
+    let mut file = tokio::fs::File::create(path).await?;
+    file.write_all(data).await?;
+    file.sync_all().await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scalar::Value;
+
+    #[tokio::test]
+    async fn test_save_and_load() {
+        let data_dir = std::env::temp_dir().join(Uuid::new_v4().to_string());
+        let dir = sbin_dir(&data_dir, "test");
+        let persistent = Persistent::new(dir.clone(), "state");
+        assert!(persistent.load::<Value>().unwrap().is_none());
+
+        persistent.save(|| Value::from(1u64)).await.unwrap();
+        persistent.save(|| Value::from(2u64)).await.unwrap();
+        assert_eq!(Persistent::list(&dir).unwrap(), vec!["state".to_string()]);
+
+        let reloaded = Persistent::new(dir.clone(), "state");
+        assert_eq!(reloaded.load().unwrap(), Some(Value::from(2u64)));
+
+        reloaded.delete().await.unwrap();
+        assert!(Persistent::list(&dir).unwrap().is_empty());
+
+        std::fs::remove_dir_all(data_dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_fsck() {
+        let data_dir = std::env::temp_dir().join(Uuid::new_v4().to_string());
+        let dir = sbin_dir(&data_dir, "test");
+        let persistent = Persistent::new(dir.clone(), "state");
+        persistent.save(|| Value::from(1u64)).await.unwrap();
+        assert!(fsck(&data_dir, false).unwrap().is_empty());
+
+        std::fs::write(dir.join("truncated").with_extension(EXT), "[1, 2").unwrap();
+        std::fs::write(dir.join(Uuid::new_v4().to_string()), "[1, 2").unwrap();
+        std::fs::write(dir.join("README"), "").unwrap();
+        assert_eq!(fsck(&data_dir, true).unwrap().len(), 3);
+
+        let remaining = fsck(&data_dir, false).unwrap();
+        assert_eq!(remaining.len(), 1);
+        assert!(remaining[0].starts_with("unrecognized file"));
+        assert_eq!(persistent.load().unwrap(), Some(Value::from(1u64)));
+
+        // an unreadable state is kept in quarantine, rather than deleted
+        let quarantined = data_dir.join(QUARANTINE_DIR).join(SBIN_DIR).join("test");
+        let truncated = std::fs::read_to_string(quarantined.join("truncated").with_extension(EXT));
+        assert_eq!(truncated.unwrap(), "[1, 2");
+
+        std::fs::remove_dir_all(data_dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_fsck_journal() {
+        let data_dir = std::env::temp_dir().join(Uuid::new_v4().to_string());
+        let cluster = sbin_dir(&data_dir, "cluster").join("app");
+        let journal = cluster.join(JOURNAL);
+        let entry = |txn_id: &str| journal.join(txn_id).with_extension(EXT);
+
+        Persistent::new(cluster.clone(), SNAPSHOT)
+            .save(|| Value::from(0u64))
+            .await
+            .unwrap();
+
+        for txn_id in &["1-0", "3-0", "20-0"] {
+            Persistent::new(journal.clone(), txn_id)
+                .save(|| Value::from(1u64))
+                .await
+                .unwrap();
+        }
+
+        assert!(fsck(&data_dir, false).unwrap().is_empty());
+
+        // every entry after an unreadable entry is dropped, in the order they're replayed
+        std::fs::write(entry("3-0"), "[1, 2").unwrap();
+
+        let problems = fsck(&data_dir, false).unwrap();
+        assert_eq!(problems.len(), 2);
+        assert!(problems[0].starts_with("unreadable state"));
+        assert!(problems[0].contains("3-0"));
+        assert!(problems[1].starts_with("journal entry"));
+        assert!(problems[1].contains("20-0"));
+        assert!(entry("20-0").exists());
+
+        assert_eq!(fsck(&data_dir, true).unwrap().len(), 2);
+        assert!(fsck(&data_dir, false).unwrap().is_empty());
+        assert_eq!(Persistent::list(&journal).unwrap(), vec!["1-0".to_string()]);
+
+        let quarantined = data_dir
+            .join(QUARANTINE_DIR)
+            .join(journal.strip_prefix(&data_dir).unwrap());
+        let mut dropped = Persistent::list(&quarantined).unwrap();
+        dropped.sort();
+        assert_eq!(dropped, vec!["20-0".to_string(), "3-0".to_string()]);
+
+        // every entry is dropped if the snapshot they're replayed on top of is unreadable
+        std::fs::write(cluster.join(SNAPSHOT).with_extension(EXT), "{").unwrap();
+        std::fs::write(journal.join("latest").with_extension(EXT), "1").unwrap();
+
+        let problems = fsck(&data_dir, true).unwrap();
+        assert_eq!(problems.len(), 3);
+        assert!(problems
+            .iter()
+            .any(|p| p.starts_with("unrecognized journal entry")));
+        assert!(Persistent::list(&journal).unwrap().is_empty());
+        assert!(fsck(&data_dir, false).unwrap().is_empty());
+
+        std::fs::remove_dir_all(data_dir).unwrap();
+    }
+}
@@ -0,0 +1,476 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::path::Path;
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use log::{debug, warn};
+
+use crate::class::{NativeClass, State, TCType};
+use crate::collection::schema::{Row, TableSchema};
+use crate::collection::tensor::Shape;
+use crate::error;
+use crate::general::Map;
+use crate::handler::Public;
+use crate::persist::{sbin_dir, Persistent};
+use crate::request::Request;
+use crate::scalar::*;
+use crate::transaction::lock::{Mutate, TxnLock};
+use crate::transaction::{Transact, Txn, TxnId};
+use crate::{Match, TCResult, TryCastFrom, TryCastInto};
+
+#[derive(Clone, Copy, Eq, PartialEq)]
+enum SchemaKind {
+    Table,
+    Tensor,
+}
+
+impl SchemaKind {
+    fn of(schema: &Value) -> TCResult<SchemaKind> {
+        if schema.matches::<TableSchema>() {
+            Ok(SchemaKind::Table)
+        } else if schema.matches::<(NumberType, Shape)>() {
+            Ok(SchemaKind::Tensor)
+        } else {
+            Err(error::bad_request(
+                "Expected a Table schema or a (dtype, shape) Tensor spec but found",
+                schema,
+            ))
+        }
+    }
+}
+
+impl fmt::Display for SchemaKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Table => write!(f, "Table schema"),
+            Self::Tensor => write!(f, "Tensor spec"),
+        }
+    }
+}
+
+#[derive(Clone)]
+struct Versions {
+    kind: SchemaKind,
+    versions: Vec<Value>,
+}
+
+#[derive(Clone, Default)]
+struct Schemas(HashMap<TCPathBuf, Versions>);
+
+#[async_trait]
+impl Mutate for Schemas {
+    type Pending = Self;
+
+    fn diverge(&self, _txn_id: &TxnId) -> Self::Pending {
+        self.clone()
+    }
+
+    async fn converge(&mut self, new_value: Self::Pending) {
+        *self = new_value
+    }
+}
+
+/// Return the link to the registered schema `name` of the cluster at `cluster`.
+fn link_to(cluster: &TCPathBuf, name: &TCPathBuf) -> Link {
+    let mut path = SchemaRegistry::prefix();
+    path.extend(cluster.iter().cloned());
+    path.extend(name.iter().cloned());
+    Link::from(path)
+}
+
+/// Encode `schemas` as a `Value`, to store it.
+fn encode(schemas: &Schemas) -> Value {
+    let entries = schemas
+        .0
+        .iter()
+        .map(|(name, entry)| {
+            let versions = Value::Tuple(entry.versions.to_vec().into());
+            Value::Tuple(vec![Link::from(name.clone()).into(), versions].into())
+        })
+        .collect::<Vec<Value>>();
+
+    Value::Tuple(entries.into())
+}
+
+/// Decode schemas stored by `encode`.
+fn decode(stored: Value) -> TCResult<Schemas> {
+    let entries: Vec<(Link, Vec<Value>)> =
+        stored.try_cast_into(|v| error::internal(format!("Invalid stored schemas: {}", v)))?;
+
+    let mut schemas = HashMap::new();
+    for (name, versions) in entries {
+        let kind = match versions.first() {
+            Some(schema) => SchemaKind::of(schema)?,
+            None => return Err(error::internal(format!("Schema {} has no versions", name))),
+        };
+
+        schemas.insert(name.into_path(), Versions { kind, versions });
+    }
+
+    Ok(Schemas(schemas))
+}
+
+/// A reference to a version of a registered schema, kept by a collection created from it so
+/// that writes to the collection can be validated against it.
+///
+/// A reference with no version follows the latest version of the schema.
+#[derive(Clone)]
+pub struct SchemaRef {
+    registry: SchemaRegistry,
+    name: TCPathBuf,
+    version: Option<u64>,
+}
+
+impl SchemaRef {
+    /// Return an error if `row` does not conform to the referenced Table schema.
+    pub async fn validate(&self, txn_id: &TxnId, row: &Row) -> TCResult<()> {
+        let schema = self
+            .registry
+            .get_version(txn_id, &self.name, self.version)
+            .await?;
+
+        let schema: TableSchema = schema.try_cast_into(|_| {
+            error::bad_request(
+                "Cannot validate a row against a Tensor spec",
+                link_to(&self.registry.cluster, &self.name),
+            )
+        })?;
+
+        schema.validate_row(row)
+    }
+}
+
+/// Return `true` if the given collection schema is a reference to a registered schema.
+pub fn is_ref(schema: &Value) -> bool {
+    let prefix = SchemaRegistry::prefix();
+
+    if schema.matches::<Link>() {
+        let link: Link = schema.clone().opt_cast_into().unwrap();
+        link.host().is_none() && link.path().starts_with(&prefix)
+    } else if schema.matches::<(Link, u64)>() {
+        let (link, _): (Link, u64) = schema.clone().opt_cast_into().unwrap();
+        link.host().is_none() && link.path().starts_with(&prefix)
+    } else {
+        false
+    }
+}
+
+/// Resolve a reference to a registered schema, or return the given schema if it is not one.
+pub async fn resolve(txn: &Txn, schema: Value) -> TCResult<(Value, Option<SchemaRef>)> {
+    if !is_ref(&schema) {
+        return Ok((schema, None));
+    }
+
+    let (link, version) = if schema.matches::<(Link, u64)>() {
+        let (link, version): (Link, u64) = schema.opt_cast_into().unwrap();
+        (link, Some(version))
+    } else {
+        let link: Link =
+            schema.try_cast_into(|v| error::bad_request("Invalid schema reference", v))?;
+        (link, None)
+    };
+
+    let path = SchemaRegistry::prefix().try_suffix(link.path())?;
+    let (name, registry) = txn.gateway().schemas(path)?;
+
+    let mut name_buf = TCPathBuf::default();
+    name_buf.extend(name.iter().cloned());
+
+    let resolved = registry.get_version(txn.id(), &name_buf, version).await?;
+    let registered = SchemaRef {
+        registry,
+        name: name_buf,
+        version,
+    };
+
+    Ok((resolved, Some(registered)))
+}
+
+/// The registry of named, versioned collection schemas of one cluster, available at
+/// `/sbin/schema/<cluster path>/<name>`.
+///
+/// Registering a schema under an existing name appends a new version, which must be of the same
+/// kind as the previous versions. A new version is only visible to other transactions once the
+/// registering transaction commits, at which point the registry is stored in `data_dir`.
+///
+/// A collection can be created from a registered schema by passing a `Link` to it (to follow the
+/// latest version) or a `(Link, version)` tuple in place of the schema. Each row inserted into a
+/// Table created this way is validated against the schema it references.
+#[derive(Clone)]
+pub struct SchemaRegistry {
+    cluster: TCPathBuf,
+    schemas: TxnLock<Schemas>,
+    store: Arc<Persistent>,
+}
+
+impl SchemaRegistry {
+    /// Load the schemas registered by the cluster at `cluster` from `data_dir`.
+    pub fn load(data_dir: &Path, cluster: TCPathBuf) -> TCResult<SchemaRegistry> {
+        let mut dir = sbin_dir(data_dir, "schema");
+        for segment in cluster.iter() {
+            dir.push(segment.as_str());
+        }
+
+        let store = Persistent::new(dir, "registry");
+        let schemas = match store.load()? {
+            Some(stored) => decode(stored)?,
+            None => Schemas::default(),
+        };
+
+        let schemas = TxnLock::new(format!("Schema registry of {}", cluster), schemas);
+
+        Ok(SchemaRegistry {
+            cluster,
+            schemas,
+            store: Arc::new(store),
+        })
+    }
+
+    pub fn prefix() -> TCPathBuf {
+        TCType::prefix().append(label("schema"))
+    }
+
+    /// The path of the cluster which owns this registry.
+    pub fn cluster(&'_ self) -> &'_ TCPathBuf {
+        &self.cluster
+    }
+
+    /// Return the name and latest version of each Table schema in this registry.
+    pub async fn tables(&self, txn_id: &TxnId) -> TCResult<Vec<(TCPathBuf, TableSchema)>> {
+        let schemas = self.schemas.read(txn_id).await?;
+
+        let mut tables = vec![];
+        for (name, entry) in schemas.0.iter() {
+            if entry.kind != SchemaKind::Table {
+                continue;
+            }
+
+            if let Some(schema) = entry.versions.last() {
+                let schema = schema.clone().try_cast_into(|v| {
+                    error::internal(format!("Invalid Table schema {}: {}", name, v))
+                })?;
+
+                tables.push((name.clone(), schema));
+            }
+        }
+
+        Ok(tables)
+    }
+
+    async fn get_version(
+        &self,
+        txn_id: &TxnId,
+        name: &[PathSegment],
+        version: Option<u64>,
+    ) -> TCResult<Value> {
+        let schemas = self.schemas.read(txn_id).await?;
+        let entry = schemas
+            .0
+            .get(name)
+            .ok_or_else(|| error::not_found(TCPath::from(name)))?;
+
+        let version = match version {
+            Some(version) if version > 0 => version as usize,
+            Some(_) => return Err(error::bad_request("Schema versions start at", 1)),
+            None => entry.versions.len(),
+        };
+
+        entry.versions.get(version - 1).cloned().ok_or_else(|| {
+            error::not_found(format!(
+                "version {} of schema {}",
+                version,
+                TCPath::from(name)
+            ))
+        })
+    }
+
+    async fn register(&self, txn: &Txn, name: TCPathBuf, schema: Value) -> TCResult<u64> {
+        let kind = SchemaKind::of(&schema)?;
+        let mut schemas = self.schemas.write(*txn.id()).await?;
+
+        let version = if let Some(entry) = schemas.0.get_mut(&name) {
+            if entry.kind != kind {
+                return Err(error::bad_request(
+                    format!("Cannot replace {} {} with", entry.kind, name),
+                    kind,
+                ));
+            }
+
+            entry.versions.push(schema);
+            entry.versions.len() as u64
+        } else {
+            debug!("registered new {} {} in {}", kind, name, self.cluster);
+
+            schemas.0.insert(
+                name,
+                Versions {
+                    kind,
+                    versions: vec![schema],
+                },
+            );
+
+            1
+        };
+
+        txn.enlist(Box::new(self.clone())).await;
+        Ok(version)
+    }
+}
+
+#[async_trait]
+impl Public for SchemaRegistry {
+    async fn get(
+        &self,
+        _request: &Request,
+        txn: &Txn,
+        path: &[PathSegment],
+        key: Value,
+    ) -> TCResult<State> {
+        if path.is_empty() {
+            if !key.is_none() {
+                return Err(error::bad_request(
+                    "/sbin/schema takes no key, but found",
+                    key,
+                ));
+            }
+
+            let schemas = self.schemas.read(txn.id()).await?;
+            let names: Vec<Value> = schemas
+                .0
+                .keys()
+                .map(|name| link_to(&self.cluster, name))
+                .map(Value::from)
+                .collect();
+
+            Ok(State::from(Value::from(names)))
+        } else {
+            let version = if key.is_none() {
+                None
+            } else {
+                Some(key.try_cast_into(|v| error::bad_request("Invalid schema version", v))?)
+            };
+
+            self.get_version(txn.id(), path, version)
+                .await
+                .map(State::from)
+        }
+    }
+
+    async fn put(
+        &self,
+        _request: &Request,
+        txn: &Txn,
+        path: &[PathSegment],
+        key: Value,
+        value: State,
+    ) -> TCResult<()> {
+        if path.is_empty() {
+            return Err(error::method_not_allowed(link_to(
+                &self.cluster,
+                &TCPathBuf::default(),
+            )));
+        } else if !key.is_none() {
+            return Err(error::bad_request(
+                "Schema versions are assigned automatically, but found",
+                key,
+            ));
+        }
+
+        let schema = Value::try_cast_from(value, |v| error::bad_request("Invalid schema", v))?;
+        let mut name = TCPathBuf::default();
+        name.extend(path.iter().cloned());
+
+        let version = self.register(txn, name, schema).await?;
+        debug!(
+            "registered schema {} version {} in {}",
+            TCPath::from(path),
+            version,
+            self.cluster
+        );
+        Ok(())
+    }
+
+    async fn post(
+        &self,
+        _request: &Request,
+        _txn: &Txn,
+        path: &[PathSegment],
+        _params: Map<Scalar>,
+    ) -> TCResult<State> {
+        Err(error::method_not_allowed(TCPath::from(path)))
+    }
+
+    async fn delete(
+        &self,
+        _request: &Request,
+        _txn: &Txn,
+        path: &[PathSegment],
+        _key: Value,
+    ) -> TCResult<()> {
+        Err(error::method_not_allowed(TCPath::from(path)))
+    }
+}
+
+#[async_trait]
+impl Transact for SchemaRegistry {
+    async fn commit(&self, txn_id: &TxnId) {
+        self.schemas.commit(txn_id).await;
+
+        if let Err(cause) = self.store.save(|| encode(self.schemas.canonical())).await {
+            warn!(
+                "unable to save the schemas of {} at commit of {}: {}",
+                self.cluster, txn_id, cause
+            );
+        }
+    }
+
+    async fn rollback(&self, txn_id: &TxnId) {
+        self.schemas.rollback(txn_id).await
+    }
+
+    async fn finalize(&self, txn_id: &TxnId) {
+        self.schemas.finalize(txn_id).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_encode_decode() {
+        let name: TCPathBuf = "/users".parse().unwrap();
+        let spec = Value::Tuple(
+            vec![
+                Value::from(Link::from(NumberType::uint64())),
+                Value::Tuple(vec![Value::from(2u64), Value::from(3u64)].into()),
+            ]
+            .into(),
+        );
+
+        let mut schemas = Schemas::default();
+        schemas.0.insert(
+            name.clone(),
+            Versions {
+                kind: SchemaKind::of(&spec).unwrap(),
+                versions: vec![spec.clone(), spec],
+            },
+        );
+
+        let decoded = decode(encode(&schemas)).unwrap();
+        let entry = decoded.0.get(&name).unwrap();
+        assert!(entry.kind == SchemaKind::Tensor);
+        assert_eq!(entry.versions.len(), 2);
+    }
+
+    #[test]
+    fn test_link_to() {
+        let cluster: TCPathBuf = "/app".parse().unwrap();
+        let name: TCPathBuf = "/users".parse().unwrap();
+        assert_eq!(
+            link_to(&cluster, &name).to_string(),
+            "/sbin/schema/app/users"
+        );
+    }
+}
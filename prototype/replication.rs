@@ -0,0 +1,784 @@
+//! Replication of the writes committed to a cluster from its director to each of its actors.
+//!
+//! The director stages each write to a cluster with the transaction which makes it, and queues
+//! the writes of a transaction for every actor when the transaction commits. Each actor receives
+//! the transactions queued for it in order of `TxnId`, as a PUT to `<cluster>/replicate`, and
+//! rejects a PUT which doesn't come from its director or which replays an earlier `TxnId`. A
+//! transaction which commits while an earlier one is still pending is held back until the earlier
+//! one commits or rolls back, so an actor which has applied a `TxnId` has applied every earlier
+//! write to its cluster.
+//!
+//! Flow control is credit-based. Every actor has a window of credits, one per byte of encoded
+//! writes, and a write takes its size in credits from every actor when it's staged. An actor
+//! returns the credits of a transaction by acknowledging it, so at most one window of writes is
+//! ever queued for an actor. A writer waits for credits while an actor is behind, and once an
+//! actor has been out of credits for longer than `shed_after`, new writes are rejected until it
+//! catches up.
+
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::convert::TryFrom;
+use std::fmt;
+use std::net::IpAddr;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use async_trait::async_trait;
+use log::{debug, info, warn};
+use tokio::sync::{watch, Notify, OwnedSemaphorePermit, Semaphore};
+use tokio::time::timeout;
+
+use crate::class::State;
+use crate::error;
+use crate::gateway::Gateway;
+use crate::lock::{RwLock, RwLockWriteGuard};
+use crate::request::Request;
+use crate::scalar::*;
+use crate::transaction::{Transact, Txn, TxnId};
+use crate::{TCResult, TryCastFrom};
+
+/// The path segment, under the path of a cluster, at which an actor accepts replicated writes.
+pub const REPLICATE: Label = label("replicate");
+
+const RETRY_INTERVAL: Duration = Duration::from_secs(1);
+
+/// How the clusters of this host take part in replication, as configured at startup.
+#[derive(Clone)]
+pub enum Role {
+    Director {
+        replicas: Vec<LinkHost>,
+        window: usize,
+        shed_after: Duration,
+    },
+    Actor {
+        director: LinkHost,
+        session_wait: Duration,
+    },
+}
+
+/// One write to a cluster, at a path relative to the path of the cluster.
+#[derive(Clone)]
+pub struct Write {
+    pub path: TCPathBuf,
+    pub key: Value,
+    pub value: Scalar,
+}
+
+impl Write {
+    pub fn new(path: &[PathSegment], key: Value, value: Scalar) -> Write {
+        let mut write_path = TCPathBuf::default();
+        write_path.extend(path.iter().cloned());
+
+        Write {
+            path: write_path,
+            key,
+            value,
+        }
+    }
+
+    pub fn encode(&self) -> Scalar {
+        Scalar::Tuple(
+            vec![
+                Scalar::Value(string(self.path.to_string())),
+                Scalar::Value(self.key.clone()),
+                self.value.clone(),
+            ]
+            .into(),
+        )
+    }
+
+    pub fn decode(encoded: Scalar) -> TCResult<Write> {
+        let write = match encoded {
+            Scalar::Tuple(write) if write.len() == 3 => write.into_inner(),
+            other => return Err(error::bad_request("Invalid replicated write", other)),
+        };
+
+        let mut write = write.into_iter();
+        let path = Value::try_cast_from(write.next().unwrap(), |s| {
+            error::bad_request("Invalid path of replicated write", s)
+        })?;
+        let path = String::try_from(path)?.parse()?;
+        let key = Value::try_cast_from(write.next().unwrap(), |s| {
+            error::bad_request("Invalid key of replicated write", s)
+        })?;
+        let value = write.next().unwrap();
+
+        Ok(Write { path, key, value })
+    }
+}
+
+/// Decode the `TxnId` and writes of a transaction sent to `<cluster>/replicate`.
+pub fn decode(key: Value, value: State) -> TCResult<(TxnId, Vec<Write>)> {
+    let txn_id: TxnId = String::try_from(key)?.parse()?;
+    let writes = match value {
+        State::Scalar(Scalar::Tuple(writes)) => writes
+            .into_inner()
+            .into_iter()
+            .map(Write::decode)
+            .collect::<TCResult<Vec<Write>>>()?,
+        other => {
+            return Err(error::bad_request(
+                "Expected a list of replicated writes but found",
+                other,
+            ))
+        }
+    };
+
+    Ok((txn_id, writes))
+}
+
+fn string(s: String) -> Value {
+    Value::TCString(TCString::UString(s))
+}
+
+// the writes of one committed transaction, queued for one actor along with the credits they hold
+struct Entry {
+    txn_id: TxnId,
+    writes: Vec<Scalar>,
+    size: usize,
+    _credits: Vec<OwnedSemaphorePermit>,
+}
+
+struct Replica {
+    host: LinkHost,
+    window: usize,
+    credits: Arc<Semaphore>,
+    queue: RwLock<VecDeque<Entry>>,
+    queued: Notify,
+    acknowledged: RwLock<TxnId>,
+    stalled_since: RwLock<Option<Instant>>,
+}
+
+impl Replica {
+    fn new(host: LinkHost, window: usize) -> Replica {
+        Replica {
+            host,
+            window,
+            credits: Arc::new(Semaphore::new(window)),
+            queue: RwLock::new(VecDeque::new()),
+            queued: Notify::new(),
+            acknowledged: RwLock::new(TxnId::zero()),
+            stalled_since: RwLock::new(None),
+        }
+    }
+
+    // take `size` credits, waiting for this replica to catch up if necessary
+    async fn take_credits(
+        &self,
+        size: usize,
+        ttl: Duration,
+        shed_after: Duration,
+    ) -> TCResult<OwnedSemaphorePermit> {
+        if size > self.window {
+            return Err(error::bad_request(
+                &format!("Replication window of {} bytes exceeded by", self.window),
+                format!("a write of {} bytes", size),
+            ));
+        }
+
+        let size = u32::try_from(size).map_err(|_| error::too_large(u32::MAX as usize))?;
+        if let Ok(credits) = self.credits.clone().try_acquire_many_owned(size) {
+            return Ok(credits);
+        }
+
+        let stalled_since = *self
+            .stalled_since
+            .write()
+            .await
+            .get_or_insert_with(Instant::now);
+
+        let stalled = stalled_since.elapsed();
+        if stalled >= shed_after {
+            return Err(error::resource_exhausted(format!(
+                "replica {} has been behind for {}s, so writes are rejected until it catches up",
+                self.host,
+                stalled.as_secs()
+            )));
+        }
+
+        debug!("waiting for replica {} to catch up", self.host);
+
+        let wait = std::cmp::min(ttl, shed_after - stalled);
+        match timeout(wait, self.credits.clone().acquire_many_owned(size)).await {
+            Ok(Ok(credits)) => Ok(credits),
+            Ok(Err(cause)) => Err(error::internal(cause)),
+            Err(_) => Err(error::resource_exhausted(format!(
+                "timed out waiting for replica {} to catch up",
+                self.host
+            ))),
+        }
+    }
+
+    async fn enqueue(&self, entry: Entry) {
+        self.queue.write().await.push_back(entry);
+        self.queued.notify_one();
+    }
+
+    // send the oldest transaction queued for this replica, and return its credits once it's
+    // acknowledged
+    async fn send(
+        &self,
+        gateway: &Arc<Gateway>,
+        cluster: &TCPathBuf,
+        ttl: Duration,
+    ) -> TCResult<()> {
+        let (txn_id, writes) = match self.queue.read().await.front() {
+            Some(entry) => (entry.txn_id, entry.writes.to_vec()),
+            None => return Ok(()),
+        };
+
+        debug!("replicate {} to {} at {}", txn_id, cluster, self.host);
+
+        let request = Request::new(ttl, None, None, None);
+        let txn = gateway.transaction(&request).await?;
+        let dest: Link = (self.host.clone(), cluster.clone().append(REPLICATE)).into();
+        let writes = State::Scalar(Scalar::Tuple(writes.into()));
+        gateway
+            .put(&request, &txn, &dest, string(txn_id.to_string()), writes)
+            .await?;
+
+        self.queue.write().await.pop_front();
+        *self.acknowledged.write().await = txn_id;
+
+        let mut stalled_since = self.stalled_since.write().await;
+        if stalled_since.is_some() && self.credits.available_permits() * 2 >= self.window {
+            info!("replica {} of {} has caught up", self.host, cluster);
+            *stalled_since = None;
+        }
+
+        Ok(())
+    }
+
+    async fn run(self: Arc<Self>, gateway: Arc<Gateway>, cluster: TCPathBuf, ttl: Duration) {
+        loop {
+            if self.queue.read().await.is_empty() {
+                self.queued.notified().await;
+                continue;
+            }
+
+            if let Err(cause) = self.send(&gateway, &cluster, ttl).await {
+                warn!(
+                    "unable to replicate {} to {}: {}",
+                    cluster, self.host, cause
+                );
+
+                tokio::time::sleep(RETRY_INTERVAL).await;
+            }
+        }
+    }
+
+    // the lag of this replica: (host, transactions behind, bytes queued, seconds stalled,
+    // last transaction acknowledged)
+    async fn lag(&self) -> Value {
+        let (behind, queued) = {
+            let queue = self.queue.read().await;
+            (
+                queue.len() as u64,
+                queue.iter().map(|entry| entry.size).sum::<usize>(),
+            )
+        };
+
+        let stalled = self
+            .stalled_since
+            .read()
+            .await
+            .map(|since| since.elapsed().as_secs());
+
+        let acknowledged = *self.acknowledged.read().await;
+
+        Value::Tuple(
+            vec![
+                Value::from(Link::from(self.host.clone())),
+                Value::from(behind),
+                Value::from(queued as u64),
+                Value::from(stalled),
+                string(acknowledged.to_string()),
+            ]
+            .into(),
+        )
+    }
+}
+
+// the writes of a transaction which hasn't committed yet, with the credits taken from each actor
+struct Staged {
+    writes: Vec<Scalar>,
+    size: usize,
+    credits: Vec<Vec<OwnedSemaphorePermit>>,
+}
+
+#[derive(Default)]
+struct Pending {
+    staged: HashMap<TxnId, Staged>,
+    committed: BTreeMap<TxnId, Staged>,
+}
+
+impl Pending {
+    // take the committed transactions which no staged transaction precedes, in order
+    fn release(&mut self) -> Vec<(TxnId, Staged)> {
+        let horizon = self.staged.keys().min().cloned();
+
+        let mut released = vec![];
+        loop {
+            let txn_id = match self.committed.keys().next() {
+                Some(txn_id) if horizon.map_or(true, |horizon| *txn_id < horizon) => *txn_id,
+                _ => break,
+            };
+
+            if let Some(staged) = self.committed.remove(&txn_id) {
+                released.push((txn_id, staged));
+            }
+        }
+
+        released
+    }
+}
+
+struct Inner {
+    replicas: Vec<Arc<Replica>>,
+    shed_after: Duration,
+    pending: RwLock<Pending>,
+}
+
+/// The replication of one cluster from its director to its actors.
+#[derive(Clone)]
+pub struct Replicator {
+    inner: Arc<Inner>,
+}
+
+impl Replicator {
+    pub fn new(replicas: Vec<LinkHost>, window: usize, shed_after: Duration) -> Replicator {
+        let replicas = replicas
+            .into_iter()
+            .map(|host| Arc::new(Replica::new(host, window)))
+            .collect();
+
+        Replicator {
+            inner: Arc::new(Inner {
+                replicas,
+                shed_after,
+                pending: RwLock::new(Pending::default()),
+            }),
+        }
+    }
+
+    /// Return `true` if this cluster has no actors to replicate to.
+    pub fn is_empty(&self) -> bool {
+        self.inner.replicas.is_empty()
+    }
+
+    /// Stage the given writes of `txn`, to be replicated when it commits.
+    pub async fn stage(&self, request: &Request, txn: &Txn, writes: Vec<Write>) -> TCResult<()> {
+        if self.is_empty() || writes.is_empty() {
+            return Ok(());
+        }
+
+        let writes: Vec<Scalar> = writes.iter().map(Write::encode).collect();
+        let size = serde_json::to_string(&writes)
+            .map_err(error::TCError::from)?
+            .len();
+
+        let mut credits = Vec::with_capacity(self.inner.replicas.len());
+        for replica in &self.inner.replicas {
+            let taken = replica
+                .take_credits(size, request.ttl(), self.inner.shed_after)
+                .await?;
+
+            credits.push(taken);
+        }
+
+        let mut pending = self.inner.pending.write().await;
+        if let Some(staged) = pending.staged.get_mut(txn.id()) {
+            staged.writes.extend(writes);
+            staged.size += size;
+            for (held, taken) in staged.credits.iter_mut().zip(credits) {
+                held.push(taken);
+            }
+        } else {
+            let credits = credits.into_iter().map(|taken| vec![taken]).collect();
+            pending.staged.insert(
+                *txn.id(),
+                Staged {
+                    writes,
+                    size,
+                    credits,
+                },
+            );
+
+            txn.enlist(Box::new(self.clone())).await;
+        }
+
+        Ok(())
+    }
+
+    /// Start sending the committed writes of the cluster at `cluster` to each of its actors.
+    pub fn start(&self, gateway: &Arc<Gateway>, cluster: &TCPathBuf, ttl: Duration) {
+        for replica in &self.inner.replicas {
+            info!("replicating {} to {}", cluster, replica.host);
+
+            let replica = replica.clone();
+            tokio::spawn(replica.run(gateway.clone(), cluster.clone(), ttl));
+        }
+    }
+
+    /// Return the lag of each actor of this cluster.
+    pub async fn lag(&self) -> Vec<Value> {
+        let mut lag = Vec::with_capacity(self.inner.replicas.len());
+        for replica in &self.inner.replicas {
+            lag.push(replica.lag().await);
+        }
+
+        lag
+    }
+}
+
+impl Replicator {
+    // queue the transactions which are ready to replicate, in order
+    async fn release(&self, pending: &mut Pending) {
+        for (txn_id, staged) in pending.release() {
+            debug!(
+                "queue {} writes of {} for {} replicas",
+                staged.writes.len(),
+                txn_id,
+                self.inner.replicas.len()
+            );
+
+            for (replica, credits) in self.inner.replicas.iter().zip(staged.credits) {
+                let entry = Entry {
+                    txn_id,
+                    writes: staged.writes.to_vec(),
+                    size: staged.size,
+                    _credits: credits,
+                };
+
+                replica.enqueue(entry).await;
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl Transact for Replicator {
+    async fn commit(&self, txn_id: &TxnId) {
+        let mut pending = self.inner.pending.write().await;
+        if let Some(staged) = pending.staged.remove(txn_id) {
+            pending.committed.insert(*txn_id, staged);
+            self.release(&mut pending).await;
+        }
+    }
+
+    async fn rollback(&self, txn_id: &TxnId) {
+        // dropping the staged writes returns their credits
+        let mut pending = self.inner.pending.write().await;
+        if pending.staged.remove(txn_id).is_some() {
+            self.release(&mut pending).await;
+        }
+    }
+
+    async fn finalize(&self, txn_id: &TxnId) {
+        self.rollback(txn_id).await
+    }
+}
+
+/// The state of a cluster replicated from a director.
+#[derive(Clone)]
+pub struct Follower {
+    director: LinkHost,
+    session_wait: Duration,
+    applied: Arc<watch::Sender<TxnId>>,
+    receiver: watch::Receiver<TxnId>,
+    replayed: RwLock<TxnId>,
+}
+
+impl Follower {
+    pub fn new(director: LinkHost, session_wait: Duration) -> Follower {
+        let (applied, receiver) = watch::channel(TxnId::zero());
+
+        Follower {
+            director,
+            session_wait,
+            applied: Arc::new(applied),
+            receiver,
+            replayed: RwLock::new(TxnId::zero()),
+        }
+    }
+
+    /// The host which directs this cluster, to which writes are forwarded.
+    pub fn director(&'_ self) -> &'_ LinkHost {
+        &self.director
+    }
+
+    /// Return `true` if `peer` is an address of the director of this cluster.
+    pub async fn is_director(&self, peer: IpAddr) -> bool {
+        match self.director.address() {
+            LinkAddress::IPv4(address) => peer == IpAddr::V4(*address),
+            LinkAddress::IPv6(address) => peer == IpAddr::V6(*address),
+            LinkAddress::DomainName(name) => {
+                let port = self.director.port().unwrap_or(80);
+                match tokio::net::lookup_host((name.to_string().as_str(), port)).await {
+                    Ok(mut addresses) => addresses.any(|address| address.ip() == peer),
+                    Err(cause) => {
+                        warn!("unable to resolve the director {}: {}", self.director, cause);
+                        false
+                    }
+                }
+            }
+        }
+    }
+
+    /// The latest replicated transaction which this cluster has applied.
+    pub fn applied(&self) -> TxnId {
+        *self.receiver.borrow()
+    }
+
+    /// Wait until this cluster has applied the transaction `session`, for at most the configured
+    /// `session_wait` or `ttl`, whichever is less. Return `false` if it hasn't caught up by then,
+    /// in which case a read in this session should be served by the director instead.
+    pub async fn wait_for(&self, session: &TxnId, ttl: Duration) -> bool {
+        if self.applied() >= *session {
+            return true;
+        }
+
+        debug!("waiting for {} to apply {}", self, session);
+
+        let mut receiver = self.receiver.clone();
+        let caught_up = async move {
+            while *receiver.borrow() < *session {
+                if receiver.changed().await.is_err() {
+                    return false;
+                }
+            }
+
+            true
+        };
+
+        let wait = std::cmp::min(self.session_wait, ttl);
+        timeout(wait, caught_up).await.unwrap_or(false)
+    }
+
+    /// Lock this cluster for replaying a transaction replicated by its director, so that
+    /// transactions are applied one at a time in the order they are received.
+    ///
+    /// The lock holds the last transaction replayed, so that a transaction which the director
+    /// sends again, because it didn't receive the acknowledgement, is only applied once.
+    pub async fn replay(&self) -> RwLockWriteGuard<TxnId> {
+        self.replayed.write().await
+    }
+
+    /// Record that the replicated transaction `txn_id` has been applied.
+    pub fn apply(&self, txn_id: TxnId) {
+        if txn_id > self.applied() {
+            // this can't fail, since this Follower holds a receiver
+            self.applied.send(txn_id).ok();
+        }
+    }
+}
+
+impl fmt::Display for Follower {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "replica of {} (at {})", self.director, self.applied())
+    }
+}
+
+/// A client's session token, which maps each replicated cluster the client has written to the
+/// `TxnId` of its last write there, like `/app/users=1617210000000-1,/app/orders=1617210000123-4`.
+///
+/// An actor only waits to serve a read in a session until it has applied the session's last write
+/// to the cluster being read, so a read of a cluster which the session hasn't written never waits.
+#[derive(Clone, Default)]
+pub struct Session {
+    writes: HashMap<TCPathBuf, TxnId>,
+}
+
+impl Session {
+    /// The last write of this session to the cluster at `cluster`, if any.
+    pub fn get(&self, cluster: &TCPathBuf) -> Option<&TxnId> {
+        self.writes.get(cluster)
+    }
+
+    /// Record a write of this session to the cluster at `cluster`.
+    pub fn record(&mut self, cluster: TCPathBuf, txn_id: TxnId) {
+        let last = self.writes.entry(cluster).or_insert(txn_id);
+        if txn_id > *last {
+            *last = txn_id;
+        }
+    }
+}
+
+impl std::str::FromStr for Session {
+    type Err = error::TCError;
+
+    fn from_str(s: &str) -> TCResult<Session> {
+        let mut session = Session::default();
+        let writes = s
+            .split(',')
+            .map(str::trim)
+            .filter(|write| !write.is_empty());
+        for write in writes {
+            let mut parts = write.splitn(2, '=');
+            let cluster = parts.next().unwrap_or_default();
+            let txn_id = parts
+                .next()
+                .ok_or_else(|| error::bad_request("Invalid session token", write))?;
+
+            session.record(cluster.parse()?, txn_id.parse()?);
+        }
+
+        Ok(session)
+    }
+}
+
+impl fmt::Display for Session {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let writes: Vec<String> = self
+            .writes
+            .iter()
+            .map(|(cluster, txn_id)| format!("{}={}", cluster, txn_id))
+            .collect();
+
+        write!(f, "{}", writes.join(","))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn host() -> LinkHost {
+        "http://127.0.0.1:8702".parse().unwrap()
+    }
+
+    #[test]
+    fn test_encode_decode() {
+        let path: TCPathBuf = "/tensor/weights".parse().unwrap();
+        let write = Write::new(
+            &path,
+            Value::from(vec![1u64, 2u64]),
+            Value::from(3u64).into(),
+        );
+        let txn_id = TxnId::zero();
+
+        let writes = State::Scalar(Scalar::Tuple(vec![write.encode()].into()));
+        let (decoded_id, decoded) = decode(string(txn_id.to_string()), writes).unwrap();
+        assert_eq!(decoded_id, txn_id);
+        assert_eq!(decoded.len(), 1);
+        assert_eq!(decoded[0].path, path);
+        assert_eq!(decoded[0].key, write.key);
+    }
+
+    #[tokio::test]
+    async fn test_credits() {
+        let replica = Replica::new(host(), 100);
+        let ttl = Duration::from_millis(10);
+        let shed_after = Duration::from_secs(60);
+
+        assert!(replica.take_credits(101, ttl, shed_after).await.is_err());
+
+        let taken = replica.take_credits(60, ttl, shed_after).await.unwrap();
+        let cause = replica.take_credits(60, ttl, shed_after).await.unwrap_err();
+        assert!(cause.reason() == &error::ErrorType::ResourceExhausted);
+        assert!(replica.stalled_since.read().await.is_some());
+
+        // acknowledging a transaction returns its credits
+        std::mem::drop(taken);
+        assert!(replica.take_credits(60, ttl, shed_after).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_shed_load() {
+        let replica = Replica::new(host(), 10);
+        let ttl = Duration::from_secs(60);
+        let shed_after = Duration::from_millis(10);
+
+        let _taken = replica.take_credits(10, ttl, shed_after).await.unwrap();
+        assert!(replica.take_credits(1, ttl, shed_after).await.is_err());
+
+        // once a replica has been stalled for longer than shed_after, writes fail immediately
+        tokio::time::sleep(shed_after).await;
+        let started = Instant::now();
+        let cause = replica.take_credits(1, ttl, shed_after).await.unwrap_err();
+        assert!(cause.reason() == &error::ErrorType::ResourceExhausted);
+        assert!(started.elapsed() < ttl);
+    }
+
+    #[test]
+    fn test_release_in_order() {
+        fn staged() -> Staged {
+            Staged {
+                writes: vec![],
+                size: 0,
+                credits: vec![],
+            }
+        }
+
+        let first: TxnId = "1-1".parse().unwrap();
+        let second: TxnId = "2-1".parse().unwrap();
+        let third: TxnId = "3-1".parse().unwrap();
+
+        let mut pending = Pending::default();
+        pending.staged.insert(first, staged());
+        pending.committed.insert(second, staged());
+        pending.committed.insert(third, staged());
+
+        // nothing is released while an earlier transaction may still commit
+        assert!(pending.release().is_empty());
+
+        pending.staged.remove(&first);
+        let released: Vec<TxnId> = pending.release().into_iter().map(|(id, _)| id).collect();
+        assert_eq!(released, vec![second, third]);
+        assert!(pending.committed.is_empty());
+    }
+
+    #[test]
+    fn test_follower() {
+        let follower = Follower::new(host(), Duration::from_secs(1));
+        assert_eq!(follower.applied(), TxnId::zero());
+
+        let txn_id: TxnId = "2-1".parse().unwrap();
+        follower.apply(txn_id);
+        follower.apply(TxnId::zero());
+        assert_eq!(follower.applied(), txn_id);
+    }
+
+    #[tokio::test]
+    async fn test_read_your_writes() {
+        let follower = Follower::new(host(), Duration::from_millis(100));
+        let ttl = Duration::from_secs(30);
+        let write: TxnId = "2-1".parse().unwrap();
+
+        // a session which hasn't been applied yet waits for at most session_wait
+        assert!(!follower.wait_for(&write, ttl).await);
+
+        let replica = follower.clone();
+        let earlier: TxnId = "1-1".parse().unwrap();
+        tokio::spawn(async move {
+            replica.apply(earlier);
+            tokio::time::sleep(Duration::from_millis(10)).await;
+            replica.apply(write);
+        });
+
+        assert!(follower.wait_for(&write, ttl).await);
+        assert!(follower.wait_for(&earlier, ttl).await);
+    }
+
+    #[test]
+    fn test_session() {
+        let users: TCPathBuf = "/app/users".parse().unwrap();
+        let orders: TCPathBuf = "/app/orders".parse().unwrap();
+        let first: TxnId = "1-1".parse().unwrap();
+        let second: TxnId = "2-1".parse().unwrap();
+
+        let mut session = Session::default();
+        session.record(users.clone(), second);
+        session.record(users.clone(), first);
+        session.record(orders.clone(), first);
+        assert_eq!(session.get(&users), Some(&second));
+
+        let parsed: Session = session.to_string().parse().unwrap();
+        assert_eq!(parsed.get(&users), Some(&second));
+        assert_eq!(parsed.get(&orders), Some(&first));
+        assert_eq!(parsed.get(&"/app/items".parse().unwrap()), None);
+
+        assert!("".parse::<Session>().unwrap().writes.is_empty());
+        assert!("/app/users".parse::<Session>().is_err());
+        assert!("/app/users=1".parse::<Session>().is_err());
+    }
+}
@@ -1,6 +1,8 @@
+use std::net::IpAddr;
 use std::time::Duration;
 
 use crate::auth::Token;
+use crate::replication::Session;
 use crate::transaction::TxnId;
 
 #[derive(Clone)]
@@ -8,11 +10,30 @@ pub struct Request {
     auth: Option<Token>,
     ttl: Duration,
     txn_id: Option<TxnId>,
+    session: Option<Session>,
+    peer: Option<IpAddr>,
 }
 
 impl Request {
-    pub fn new(ttl: Duration, auth: Option<Token>, txn_id: Option<TxnId>) -> Self {
-        Request { auth, ttl, txn_id }
+    pub fn new(
+        ttl: Duration,
+        auth: Option<Token>,
+        txn_id: Option<TxnId>,
+        session: Option<Session>,
+    ) -> Self {
+        Request {
+            auth,
+            ttl,
+            txn_id,
+            session,
+            peer: None,
+        }
+    }
+
+    /// Record the address of the host which sent this request.
+    pub fn with_peer(mut self, peer: IpAddr) -> Self {
+        self.peer = Some(peer);
+        self
     }
 
     pub fn auth(&'_ self) -> &'_ Option<Token> {
@@ -26,4 +47,16 @@ impl Request {
     pub fn txn_id(&self) -> &'_ Option<TxnId> {
         &self.txn_id
     }
+
+    /// The last write of the client which sent this request to each cluster, if it sent a
+    /// session token. A replica only serves a read of a cluster once it has applied the client's
+    /// last write to that cluster.
+    pub fn session(&self) -> &'_ Option<Session> {
+        &self.session
+    }
+
+    /// The address of the host which sent this request, if it came over the network.
+    pub fn peer(&self) -> Option<IpAddr> {
+        self.peer
+    }
 }
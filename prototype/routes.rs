@@ -0,0 +1,419 @@
+use std::fmt;
+
+use async_trait::async_trait;
+
+use crate::class::State;
+use crate::error;
+use crate::general::Map;
+use crate::handler::Public;
+use crate::request::Request;
+use crate::scalar::*;
+use crate::transaction::Txn;
+use crate::TCResult;
+
+#[derive(Clone, Copy, Eq, PartialEq)]
+pub enum Method {
+    Get,
+    Put,
+    Post,
+    Delete,
+}
+
+impl fmt::Display for Method {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Get => write!(f, "GET"),
+            Self::Put => write!(f, "PUT"),
+            Self::Post => write!(f, "POST"),
+            Self::Delete => write!(f, "DELETE"),
+        }
+    }
+}
+
+use Method::*;
+
+const ALL: &[Method] = &[Get, Put, Post, Delete];
+
+/// The kind of handler which `Gateway` dispatches a request to, by the first segment of its path.
+#[derive(Clone, Copy, Eq, PartialEq)]
+pub enum Namespace {
+    /// A native endpoint of this host, under `/sbin`.
+    Native,
+    /// An adapter to an external service, under `/ext`, which only supports `GET`.
+    Adapter,
+    /// A cluster hosted by this host or one of its peers, anywhere else.
+    Cluster,
+}
+
+impl fmt::Display for Namespace {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Native => write!(f, "native endpoint"),
+            Self::Adapter => write!(f, "adapter"),
+            Self::Cluster => write!(f, "cluster"),
+        }
+    }
+}
+
+/// Return the [`Namespace`] of a request to `path`.
+pub fn namespace(path: &[PathSegment]) -> Namespace {
+    match path.first().map(|segment| segment.as_str()) {
+        Some("sbin") => Namespace::Native,
+        Some("ext") => Namespace::Adapter,
+        _ => Namespace::Cluster,
+    }
+}
+
+/// A native endpoint of this host, under `/sbin`.
+#[derive(Clone, Copy, Eq, PartialEq)]
+pub enum Native {
+    Blobs,
+    Chain,
+    Collection,
+    Config,
+    Connectors,
+    Disk,
+    Error,
+    #[cfg(feature = "graphql")]
+    GraphQL,
+    LogLevel,
+    Metrics,
+    Migrate,
+    Object,
+    Routes,
+    Schema,
+    Sequence,
+    #[cfg(feature = "sql")]
+    Sql,
+    Transact,
+    Value,
+}
+
+// Every native endpoint of this host, and the methods it supports. `Gateway` and `kernel` only
+// dispatch a request to `/sbin` which `resolve` finds in this table.
+const NATIVE: &[(&[&str], Native, &[Method])] = &[
+    (&["sbin", "blobs"], Native::Blobs, &[Get, Put, Post, Delete]),
+    (&["sbin", "chain"], Native::Chain, &[Get]),
+    (&["sbin", "collection"], Native::Collection, &[Get]),
+    (&["sbin", "config"], Native::Config, &[Get, Put]),
+    (
+        &["sbin", "connectors"],
+        Native::Connectors,
+        &[Get, Put, Delete],
+    ),
+    (&["sbin", "disk"], Native::Disk, &[Get]),
+    (&["sbin", "error"], Native::Error, &[Get]),
+    #[cfg(feature = "graphql")]
+    (&["sbin", "graphql"], Native::GraphQL, &[Post]),
+    (&["sbin", "log_level"], Native::LogLevel, &[Get, Put]),
+    (&["sbin", "metrics"], Native::Metrics, &[Get]),
+    (&["sbin", "migrate"], Native::Migrate, &[Get, Post]),
+    (&["sbin", "object"], Native::Object, &[Post]),
+    (&["sbin", "routes"], Native::Routes, &[Get]),
+    (&["sbin", "schema"], Native::Schema, &[Get, Put, Delete]),
+    (
+        &["sbin", "sequence"],
+        Native::Sequence,
+        &[Get, Put, Post, Delete],
+    ),
+    #[cfg(feature = "sql")]
+    (&["sbin", "sql"], Native::Sql, &[Post]),
+    (&["sbin", "transact"], Native::Transact, &[Post]),
+    (&["sbin", "value"], Native::Value, &[Get]),
+];
+
+/// Resolve the native endpoint which handles a `method` request to `path`, which must begin
+/// with `/sbin`.
+pub fn resolve(path: &[PathSegment], method: Method) -> TCResult<Native> {
+    if path.len() < 2 {
+        return Err(error::method_not_allowed(TCPath::from(path)));
+    }
+
+    let (_, native, methods) = NATIVE
+        .iter()
+        .find(|(segments, _, _)| &path[0] == segments[0] && &path[1] == segments[1])
+        .ok_or_else(|| error::not_found(&path[1]))?;
+
+    if methods.contains(&method) {
+        Ok(*native)
+    } else {
+        Err(error::method_not_allowed(TCPath::from(path)))
+    }
+}
+
+struct Route {
+    path: TCPathBuf,
+    namespace: Namespace,
+    methods: &'static [Method],
+}
+
+/// The registry of every route of this host, available at `/sbin/routes`.
+///
+/// Each native endpoint, adapter, and cluster is checked against every route already registered
+/// when it's registered, so that a route which shadows another, or which `Gateway` would never
+/// dispatch to, is an error at startup instead of a request which is silently routed to the
+/// wrong handler. Clusters may be nested, since a request is routed to the innermost one.
+pub struct Routes {
+    routes: Vec<Route>,
+}
+
+impl Routes {
+    pub fn new<'a, H, A>(clusters: H, adapters: A) -> TCResult<Routes>
+    where
+        H: IntoIterator<Item = &'a TCPathBuf>,
+        A: IntoIterator<Item = &'a TCPathBuf>,
+    {
+        let mut routes = Routes { routes: vec![] };
+
+        for (segments, _, methods) in NATIVE {
+            let path = TCPathBuf::from(path_label(*segments));
+            routes.register(Namespace::Native, path, *methods)?;
+        }
+
+        for path in adapters {
+            routes.register(Namespace::Adapter, path.clone(), &[Get])?;
+        }
+
+        for path in clusters {
+            routes.register(Namespace::Cluster, path.clone(), ALL)?;
+        }
+
+        Ok(routes)
+    }
+
+    fn register(
+        &mut self,
+        namespace: Namespace,
+        path: TCPathBuf,
+        methods: &'static [Method],
+    ) -> TCResult<()> {
+        if methods.is_empty() {
+            return Err(error::internal(format!(
+                "The {} at {} supports no methods",
+                namespace, path
+            )));
+        } else if self::namespace(&path) != namespace {
+            return Err(error::bad_request(
+                format!(
+                    "Cannot route to a {} at a path reserved for a {}:",
+                    namespace,
+                    self::namespace(&path)
+                ),
+                path,
+            ));
+        }
+
+        for route in &self.routes {
+            let nested = namespace == Namespace::Cluster
+                && route.namespace == Namespace::Cluster
+                && route.path != path;
+
+            let overlaps = route.path.starts_with(&path) || path.starts_with(&route.path);
+            if overlaps && !nested {
+                return Err(error::bad_request(
+                    format!(
+                        "The {} at {} overlaps the {} at",
+                        namespace, path, route.namespace
+                    ),
+                    &route.path,
+                ));
+            }
+        }
+
+        self.routes.push(Route {
+            path,
+            namespace,
+            methods,
+        });
+
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl Public for Routes {
+    async fn get(
+        &self,
+        _request: &Request,
+        _txn: &Txn,
+        path: &[PathSegment],
+        key: Value,
+    ) -> TCResult<State> {
+        if !path.is_empty() {
+            return Err(error::path_not_found(path));
+        } else if !key.is_none() {
+            return Err(error::bad_request(
+                "/sbin/routes takes no key, but found",
+                key,
+            ));
+        }
+
+        let routes: Vec<Value> = self
+            .routes
+            .iter()
+            .map(|route| {
+                let methods: Vec<Value> = route
+                    .methods
+                    .iter()
+                    .map(|method| Value::from(TCString::UString(method.to_string())))
+                    .collect();
+
+                Value::Tuple(
+                    vec![
+                        Value::from(Link::from(route.path.clone())),
+                        Value::Tuple(methods.into()),
+                    ]
+                    .into(),
+                )
+            })
+            .collect();
+
+        Ok(State::from(Value::Tuple(routes.into())))
+    }
+
+    async fn put(
+        &self,
+        _request: &Request,
+        _txn: &Txn,
+        path: &[PathSegment],
+        _key: Value,
+        _value: State,
+    ) -> TCResult<()> {
+        Err(error::method_not_allowed(TCPath::from(path)))
+    }
+
+    async fn post(
+        &self,
+        _request: &Request,
+        _txn: &Txn,
+        path: &[PathSegment],
+        _params: Map<Scalar>,
+    ) -> TCResult<State> {
+        Err(error::method_not_allowed(TCPath::from(path)))
+    }
+
+    async fn delete(
+        &self,
+        _request: &Request,
+        _txn: &Txn,
+        path: &[PathSegment],
+        _key: Value,
+    ) -> TCResult<()> {
+        Err(error::method_not_allowed(TCPath::from(path)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::ErrorType;
+
+    const METHODS: [Method; 4] = [Get, Put, Post, Delete];
+
+    fn path(path: &str) -> TCPathBuf {
+        path.parse().unwrap()
+    }
+
+    #[test]
+    fn test_resolve() {
+        for (segments, native, methods) in NATIVE {
+            let path = TCPathBuf::from(path_label(*segments));
+            for method in &METHODS {
+                match resolve(&path, *method) {
+                    Ok(resolved) => {
+                        assert!(methods.contains(method), "{} {}", method, path);
+                        assert!(resolved == *native, "{} {}", method, path);
+                    }
+                    Err(cause) => {
+                        assert!(!methods.contains(method), "{} {}", method, path);
+                        assert!(cause.reason() == &ErrorType::MethodNotAllowed);
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_resolve_dispatched() {
+        // every path which `Gateway` or `kernel` dispatches specially
+        let dispatched = [
+            ("/sbin/blobs/upload", Get, Native::Blobs),
+            ("/sbin/chain/null", Get, Native::Chain),
+            ("/sbin/collection/tensor/dense", Get, Native::Collection),
+            ("/sbin/config", Put, Native::Config),
+            ("/sbin/connectors/feed", Delete, Native::Connectors),
+            ("/sbin/disk", Get, Native::Disk),
+            ("/sbin/error/not_found", Get, Native::Error),
+            ("/sbin/log_level", Put, Native::LogLevel),
+            ("/sbin/metrics", Get, Native::Metrics),
+            ("/sbin/migrate", Post, Native::Migrate),
+            ("/sbin/object/class", Post, Native::Object),
+            ("/sbin/routes", Get, Native::Routes),
+            ("/sbin/schema/app", Delete, Native::Schema),
+            ("/sbin/sequence/ids", Post, Native::Sequence),
+            ("/sbin/transact", Post, Native::Transact),
+            ("/sbin/value/string", Get, Native::Value),
+        ];
+
+        for (path, method, native) in &dispatched {
+            assert!(
+                resolve(&self::path(path), *method).ok() == Some(*native),
+                "{}",
+                path
+            );
+        }
+    }
+
+    #[test]
+    fn test_resolve_unknown() {
+        let cause = resolve(&path("/sbin/cluster"), Get).err().unwrap();
+        assert!(cause.reason() == &ErrorType::NotFound);
+
+        let cause = resolve(&path("/sbin"), Get).err().unwrap();
+        assert!(cause.reason() == &ErrorType::MethodNotAllowed);
+    }
+
+    #[test]
+    fn test_namespace() {
+        assert!(namespace(&path("/sbin/value")) == Namespace::Native);
+        assert!(namespace(&path("/ext/weather")) == Namespace::Adapter);
+        assert!(namespace(&path("/app/users")) == Namespace::Cluster);
+        assert!(namespace(&path("/")) == Namespace::Cluster);
+    }
+
+    #[test]
+    fn test_routes() {
+        let none: [TCPathBuf; 0] = [];
+        let routes = |clusters: &[&str], adapters: &[&str]| {
+            let clusters: Vec<TCPathBuf> = clusters.iter().map(|p| path(p)).collect();
+            let adapters: Vec<TCPathBuf> = adapters.iter().map(|p| path(p)).collect();
+            Routes::new(&clusters, &adapters)
+        };
+
+        let registered = Routes::new(&none, &none).unwrap();
+        assert_eq!(registered.routes.len(), NATIVE.len());
+
+        assert!(routes(&["/app/users"], &["/ext/weather"]).is_ok());
+
+        // clusters may be nested, but not hosted twice
+        assert!(routes(&["/app", "/app/users"], &[]).is_ok());
+        assert!(routes(&["/app", "/app"], &[]).is_err());
+
+        // nothing may overlap a native endpoint, or be hosted where it would never be routed
+        assert!(routes(&["/sbin/app"], &[]).is_err());
+        assert!(routes(&["/sbin"], &[]).is_err());
+        assert!(routes(&["/"], &[]).is_err());
+        assert!(routes(&["/ext/app"], &[]).is_err());
+        assert!(routes(&[], &["/sbin/value"]).is_err());
+        assert!(routes(&[], &["/weather"]).is_err());
+
+        // and adapters may not overlap one another
+        assert!(routes(&[], &["/ext/weather", "/ext/weather/daily"]).is_err());
+        assert!(routes(&[], &["/ext/weather", "/ext/news"]).is_ok());
+
+        let cause = routes(&["/app/users"], &["/ext/app", "/ext/app/users"])
+            .err()
+            .unwrap();
+        assert!(cause.reason() == &ErrorType::BadRequest);
+    }
+}
@@ -599,6 +599,30 @@ impl<T1: TryCastFrom<Value>, T2: TryCastFrom<Value>, T3: TryCastFrom<Value>> Try
     }
 }
 
+impl<
+        T1: TryCastFrom<Value>,
+        T2: TryCastFrom<Value>,
+        T3: TryCastFrom<Value>,
+        T4: TryCastFrom<Value>,
+    > TryCastFrom<Value> for (T1, T2, T3, T4)
+{
+    fn can_cast_from(source: &Value) -> bool {
+        if let Value::Tuple(source) = source {
+            Self::can_cast_from(source)
+        } else {
+            false
+        }
+    }
+
+    fn opt_cast_from(source: Value) -> Option<Self> {
+        if let Value::Tuple(source) = source {
+            Self::opt_cast_from(source)
+        } else {
+            None
+        }
+    }
+}
+
 pub struct ValueVisitor;
 
 impl ValueVisitor {
@@ -801,6 +801,97 @@ impl NumberType {
     pub fn uint64() -> Self {
         NumberType::UInt(UIntType::U64)
     }
+
+    /// Return the type of the result of combining a number of this type with a number of type
+    /// `other`, and whether the promotion can lose precision.
+    ///
+    /// This is the promotion table shared by `Number` and the dense `Array` type: the result is
+    /// the smallest type which can represent every value of both types, if there is one.
+    pub fn promote(self, other: NumberType) -> (NumberType, bool) {
+        use NumberType::*;
+
+        let (high, low) = if self >= other {
+            (self, other)
+        } else {
+            (other, self)
+        };
+
+        match (high, low) {
+            (Number, _) => (Number, false),
+            (high, Bool) => (high, false),
+            (Complex(h), Complex(l)) => (Complex(Ord::max(h, l)), false),
+            (Complex(h), low) => {
+                let (ft, lossy) = low.float_type();
+                let ct = match ft {
+                    FloatType::F32 => ComplexType::C32,
+                    FloatType::F64 => ComplexType::C64,
+                };
+
+                (Complex(Ord::max(h, ct)), lossy)
+            }
+            (Float(h), low) => {
+                let (ft, lossy) = low.float_type();
+                (Float(Ord::max(h, ft)), lossy)
+            }
+            (Int(h), Int(l)) => (Int(Ord::max(h, l)), false),
+            (Int(h), UInt(l)) => {
+                let (it, lossy) = match l {
+                    UIntType::U8 => (IntType::I16, false),
+                    UIntType::U16 => (IntType::I32, false),
+                    UIntType::U32 => (IntType::I64, false),
+                    UIntType::U64 => (IntType::I64, true),
+                };
+
+                (Int(Ord::max(h, it)), lossy)
+            }
+            (UInt(h), UInt(l)) => (UInt(Ord::max(h, l)), false),
+            (high, low) => unreachable!("{} is ordered before {}", high, low),
+        }
+    }
+
+    /// Like `promote`, but return an error for a lossy promotion (like `I64` to `F64`) if
+    /// `strict` is set, e.g. by the `strict_promotion` setting of the host.
+    pub fn try_promote(self, other: NumberType, strict: bool) -> TCResult<NumberType> {
+        let (dtype, lossy) = self.promote(other);
+        if lossy && strict {
+            Err(error::bad_request(
+                "Lossy dtype promotion is not allowed in strict mode",
+                format!("({}, {}) -> {}", self, other, dtype),
+            ))
+        } else {
+            Ok(dtype)
+        }
+    }
+
+    /// Return an error if `strict` is set and casting a number of this type into `dtype` could
+    /// lose precision.
+    pub fn check_cast(self, dtype: NumberType, strict: bool) -> TCResult<()> {
+        if !strict {
+            return Ok(());
+        }
+
+        match self.promote(dtype) {
+            (promoted, false) if promoted == dtype => Ok(()),
+            _ => Err(error::bad_request(
+                "Lossy cast is not allowed in strict mode",
+                format!("{} -> {}", self, dtype),
+            )),
+        }
+    }
+
+    // the smallest float type which can represent every value of a real type
+    fn float_type(self) -> (FloatType, bool) {
+        use NumberType::*;
+
+        match self {
+            Bool => (FloatType::F32, false),
+            Float(ft) => (ft, false),
+            Int(IntType::I16) | UInt(UIntType::U8) | UInt(UIntType::U16) => (FloatType::F32, false),
+            Int(IntType::I32) | UInt(UIntType::U32) => (FloatType::F64, false),
+            Int(IntType::I64) | UInt(UIntType::U64) => (FloatType::F64, true),
+            Complex(_) | Number => unreachable!("float type of {}", self),
+        }
+    }
 }
 
 impl Class for NumberType {
@@ -1000,3 +1091,104 @@ impl fmt::Display for NumberType {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::error::ErrorType;
+
+    use super::*;
+
+    const BOOL: NumberType = NumberType::Bool;
+    const C32: NumberType = NumberType::Complex(ComplexType::C32);
+    const C64: NumberType = NumberType::Complex(ComplexType::C64);
+    const F32: NumberType = NumberType::Float(FloatType::F32);
+    const F64: NumberType = NumberType::Float(FloatType::F64);
+    const I16: NumberType = NumberType::Int(IntType::I16);
+    const I32: NumberType = NumberType::Int(IntType::I32);
+    const I64: NumberType = NumberType::Int(IntType::I64);
+    const U8: NumberType = NumberType::UInt(UIntType::U8);
+    const U16: NumberType = NumberType::UInt(UIntType::U16);
+    const U32: NumberType = NumberType::UInt(UIntType::U32);
+    const U64: NumberType = NumberType::UInt(UIntType::U64);
+
+    const TYPES: [NumberType; 12] = [BOOL, C32, C64, F32, F64, I16, I32, I64, U8, U16, U32, U64];
+
+    #[test]
+    fn test_promote() {
+        // (left, right, promoted, lossy)
+        let table = [
+            (BOOL, U8, U8, false),
+            (BOOL, F32, F32, false),
+            (U8, U16, U16, false),
+            (U8, I16, I16, false),
+            (U16, I16, I32, false),
+            (U32, I16, I64, false),
+            (U64, I16, I64, true),
+            (U64, I64, I64, true),
+            (U64, U32, U64, false),
+            (I16, F32, F32, false),
+            (I32, F32, F64, false),
+            (I64, F32, F64, true),
+            (I64, F64, F64, true),
+            (U64, F64, F64, true),
+            (F32, F64, F64, false),
+            (U8, C32, C32, false),
+            (I32, C32, C64, false),
+            (I64, C32, C64, true),
+            (F64, C32, C64, false),
+            (C32, C64, C64, false),
+        ];
+
+        for (left, right, promoted, lossy) in &table {
+            for (l, r) in &[(left, right), (right, left)] {
+                let (dtype, is_lossy) = l.promote(**r);
+                assert!(dtype == *promoted, "{} x {} -> {}", l, r, dtype);
+                assert_eq!(is_lossy, *lossy, "{} x {}", l, r);
+            }
+        }
+
+        for left in &TYPES {
+            assert!(left.promote(*left) == (*left, false), "{}", left);
+
+            for right in &TYPES {
+                assert!(left.promote(*right) == right.promote(*left));
+            }
+        }
+    }
+
+    #[test]
+    fn test_try_promote() {
+        assert!(I64.try_promote(F32, false).unwrap() == F64);
+        assert!(I32.try_promote(F32, true).unwrap() == F64);
+
+        let cause = I64.try_promote(F32, true).err().unwrap();
+        assert!(cause.reason() == &ErrorType::BadRequest);
+
+        let cause = U64.try_promote(I16, true).err().unwrap();
+        assert!(cause.reason() == &ErrorType::BadRequest);
+    }
+
+    #[test]
+    fn test_check_cast() {
+        for from in &TYPES {
+            for into in &TYPES {
+                assert!(from.check_cast(*into, false).is_ok());
+            }
+        }
+
+        assert!(I16.check_cast(I32, true).is_ok());
+        assert!(U8.check_cast(I16, true).is_ok());
+        assert!(F32.check_cast(F64, true).is_ok());
+        assert!(F64.check_cast(C64, true).is_ok());
+
+        for (from, into) in &[(I32, I16), (U64, I64), (I64, F64), (F64, F32), (C32, F64)] {
+            let cause = from.check_cast(*into, true).err().unwrap();
+            assert!(
+                cause.reason() == &ErrorType::BadRequest,
+                "{} -> {}",
+                from,
+                into
+            );
+        }
+    }
+}
@@ -34,8 +34,12 @@ where
         self.number.class().into()
     }
 
-    async fn handle_get(self: Box<Self>, _txn: &Txn, key: Value) -> TCResult<State> {
-        let that = T::cast_from(Number::try_from(key)?);
+    async fn handle_get(self: Box<Self>, txn: &Txn, key: Value) -> TCResult<State> {
+        let that = Number::try_from(key)?;
+        let this: Number = (*self.number).into();
+        let strict = txn.gateway().config().strict_promotion()?;
+        that.class().check_cast(this.class(), strict)?;
+        let that = T::cast_from(that);
 
         let result: Number = (self.call)(self.number, that).into();
         Ok(Value::Number(result).into())
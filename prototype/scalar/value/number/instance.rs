@@ -1465,29 +1465,31 @@ impl Add for Number {
     type Output = Self;
 
     fn add(self, other: Number) -> Self {
-        let dtype = Ord::max(self.class(), other.class());
+        let dtype = self.class().promote(other.class()).0;
+        let this = self.into_type(dtype);
+        let other = other.into_type(dtype);
 
         use NumberType as NT;
 
         match dtype {
             NT::Bool => {
-                let this: Boolean = self.cast_into();
+                let this: Boolean = this.cast_into();
                 (this + other.cast_into()).into()
             }
             NT::Complex(_) => {
-                let this: Complex = self.cast_into();
+                let this: Complex = this.cast_into();
                 (this + other.cast_into()).into()
             }
             NT::Float(_) => {
-                let this: Float = self.cast_into();
+                let this: Float = this.cast_into();
                 (this + other.cast_into()).into()
             }
             NT::Int(_) => {
-                let this: Int = self.cast_into();
+                let this: Int = this.cast_into();
                 (this + other.cast_into()).into()
             }
             NT::UInt(_) => {
-                let this: UInt = self.cast_into();
+                let this: UInt = this.cast_into();
                 (this + other.cast_into()).into()
             }
             NT::Number => panic!("A number instance must have a specific type, not Number"),
@@ -1499,29 +1501,31 @@ impl Mul for Number {
     type Output = Self;
 
     fn mul(self, other: Number) -> Self {
-        let dtype = Ord::max(self.class(), other.class());
+        let dtype = self.class().promote(other.class()).0;
+        let this = self.into_type(dtype);
+        let other = other.into_type(dtype);
 
         use NumberType as NT;
 
         match dtype {
             NT::Bool => {
-                let this: Boolean = self.cast_into();
+                let this: Boolean = this.cast_into();
                 (this * other.cast_into()).into()
             }
             NT::Complex(_) => {
-                let this: Complex = self.cast_into();
+                let this: Complex = this.cast_into();
                 (this * other.cast_into()).into()
             }
             NT::Float(_) => {
-                let this: Float = self.cast_into();
+                let this: Float = this.cast_into();
                 (this * other.cast_into()).into()
             }
             NT::Int(_) => {
-                let this: Int = self.cast_into();
+                let this: Int = this.cast_into();
                 (this * other.cast_into()).into()
             }
             NT::UInt(_) => {
-                let this: UInt = self.cast_into();
+                let this: UInt = this.cast_into();
                 (this * other.cast_into()).into()
             }
             NT::Number => panic!("A number instance must have a specific type, not Number"),
@@ -1533,29 +1537,31 @@ impl Sub for Number {
     type Output = Self;
 
     fn sub(self, other: Number) -> Self {
-        let dtype = Ord::max(self.class(), other.class());
+        let dtype = self.class().promote(other.class()).0;
+        let this = self.into_type(dtype);
+        let other = other.into_type(dtype);
 
         use NumberType as NT;
 
         match dtype {
             NT::Bool => {
-                let this: Boolean = self.cast_into();
+                let this: Boolean = this.cast_into();
                 (this - other.cast_into()).into()
             }
             NT::Complex(_) => {
-                let this: Complex = self.cast_into();
+                let this: Complex = this.cast_into();
                 (this - other.cast_into()).into()
             }
             NT::Float(_) => {
-                let this: Float = self.cast_into();
+                let this: Float = this.cast_into();
                 (this - other.cast_into()).into()
             }
             NT::Int(_) => {
-                let this: Int = self.cast_into();
+                let this: Int = this.cast_into();
                 (this - other.cast_into()).into()
             }
             NT::UInt(_) => {
-                let this: UInt = self.cast_into();
+                let this: UInt = this.cast_into();
                 (this - other.cast_into()).into()
             }
             NT::Number => panic!("A number instance must have a specific type, not Number"),
@@ -0,0 +1,403 @@
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use async_trait::async_trait;
+use log::{debug, warn};
+
+use crate::class::State;
+use crate::error;
+use crate::general::Map;
+use crate::handler::Public;
+use crate::lock::RwLock;
+use crate::persist::{sbin_dir, Persistent};
+use crate::request::Request;
+use crate::scalar::*;
+use crate::transaction::{Transact, Txn, TxnId};
+use crate::{TCResult, TryCastInto};
+
+/// The maximum number of IDs which one call to `next` may allocate.
+pub const MAX_BATCH: u64 = 100_000;
+
+struct SequenceState {
+    next: u64,
+    pending: Option<(TxnId, u64)>,
+}
+
+/// A monotonic sequence of IDs, available at `/sbin/sequence/<name>`.
+///
+/// By default, IDs are allocated immediately and IDs allocated by a transaction which is rolled
+/// back are skipped. In no-gap mode, IDs are only allocated when the allocating transaction
+/// commits, and only one transaction at a time may hold pending allocations.
+///
+/// Each sequence is stored in `data_dir`. By default an ID is only returned once the stored
+/// sequence is past it, and in no-gap mode the sequence is stored when the allocating transaction
+/// commits, so a host which restarts doesn't allocate the same ID twice.
+#[derive(Clone)]
+pub struct Sequence {
+    no_gap: bool,
+    state: Arc<Mutex<SequenceState>>,
+    store: Arc<Persistent>,
+}
+
+impl Sequence {
+    fn new(start: u64, no_gap: bool, store: Persistent) -> Sequence {
+        let state = SequenceState {
+            next: start,
+            pending: None,
+        };
+
+        Sequence {
+            no_gap,
+            state: Arc::new(Mutex::new(state)),
+            store: Arc::new(store),
+        }
+    }
+
+    fn load(store: Persistent) -> TCResult<Sequence> {
+        let def = store
+            .load()?
+            .ok_or_else(|| error::internal("Sequence has no stored state"))?;
+
+        let (next, no_gap) = parse_def(def)?;
+        Ok(Sequence::new(next, no_gap, store))
+    }
+
+    async fn save(&self) -> TCResult<()> {
+        let state = self.state.clone();
+        let no_gap = self.no_gap;
+
+        self.store
+            .save(|| {
+                let next = state.lock().unwrap().next;
+                Value::Tuple(vec![Value::from(next), Value::from(no_gap)].into())
+            })
+            .await
+    }
+
+    fn current(&self) -> u64 {
+        self.state.lock().unwrap().next
+    }
+
+    /// Allocate the next `n` IDs in this sequence, and return the first.
+    pub async fn next(&self, txn: &Txn, n: u64) -> TCResult<u64> {
+        if n == 0 {
+            return Err(error::bad_request(
+                "Cannot allocate an empty range of IDs",
+                n,
+            ));
+        } else if n > MAX_BATCH {
+            return Err(error::bad_request(
+                format!(
+                    "Cannot allocate more than {} IDs at once, requested",
+                    MAX_BATCH
+                ),
+                n,
+            ));
+        }
+
+        if !self.no_gap {
+            let first = {
+                let mut state = self.state.lock().unwrap();
+                let first = state.next;
+                state.next = advance(first, n)?;
+                first
+            };
+
+            self.save().await?;
+            return Ok(first);
+        }
+
+        let (first, enlist) = {
+            let mut state = self.state.lock().unwrap();
+            let committed = state.next;
+            match &mut state.pending {
+                Some((txn_id, next)) if txn_id == txn.id() => {
+                    let first = *next;
+                    *next = advance(first, n)?;
+                    (first, false)
+                }
+                Some(_) => return Err(error::conflict()),
+                None => {
+                    let first = committed;
+                    state.pending = Some((txn.id().clone(), advance(first, n)?));
+                    (first, true)
+                }
+            }
+        };
+
+        if enlist {
+            txn.enlist(Box::new(self.clone())).await;
+        }
+
+        Ok(first)
+    }
+
+    fn release(&self, txn_id: &TxnId, commit: bool) -> bool {
+        let mut state = self.state.lock().unwrap();
+        let next = match &state.pending {
+            Some((pending, next)) if pending == txn_id => *next,
+            _ => return false,
+        };
+
+        if commit {
+            state.next = next;
+        }
+
+        state.pending = None;
+        commit
+    }
+}
+
+/// Return the next ID of a sequence after allocating `n` IDs starting with `first`.
+fn advance(first: u64, n: u64) -> TCResult<u64> {
+    first
+        .checked_add(n)
+        .ok_or_else(|| error::bad_request("Sequence is exhausted after", first))
+}
+
+fn parse_def(def: Value) -> TCResult<(u64, bool)> {
+    match def {
+        Value::Tuple(def) if def.len() == 2 => {
+            let mut def = def.into_inner();
+            let no_gap = bool::try_from(def.pop().unwrap())?;
+            let start: u64 = def
+                .pop()
+                .unwrap()
+                .try_cast_into(|v| error::bad_request("Invalid sequence start", v))?;
+
+            Ok((start, no_gap))
+        }
+        Value::None => Ok((0, false)),
+        other => {
+            let start: u64 =
+                other.try_cast_into(|v| error::bad_request("Invalid sequence start", v))?;
+
+            Ok((start, false))
+        }
+    }
+}
+
+#[async_trait]
+impl Transact for Sequence {
+    async fn commit(&self, txn_id: &TxnId) {
+        if self.release(txn_id, true) {
+            if let Err(cause) = self.save().await {
+                warn!("unable to save sequence at commit of {}: {}", txn_id, cause);
+            }
+        }
+    }
+
+    async fn rollback(&self, txn_id: &TxnId) {
+        self.release(txn_id, false);
+    }
+
+    async fn finalize(&self, txn_id: &TxnId) {
+        self.release(txn_id, false);
+    }
+}
+
+/// The set of named sequences available at `/sbin/sequence`.
+pub struct Sequences {
+    dir: PathBuf,
+    sequences: RwLock<HashMap<Id, Sequence>>,
+}
+
+impl Sequences {
+    /// Load the sequences stored in `data_dir`.
+    pub fn load(data_dir: &Path) -> TCResult<Sequences> {
+        let dir = sbin_dir(data_dir, "sequence");
+
+        let mut sequences = HashMap::new();
+        for name in Persistent::list(&dir)? {
+            let sequence = Sequence::load(Persistent::new(dir.clone(), &name))?;
+            sequences.insert(name.parse()?, sequence);
+        }
+
+        Ok(Sequences {
+            dir,
+            sequences: RwLock::new(sequences),
+        })
+    }
+
+    pub async fn sequence(&self, name: &Id) -> TCResult<Sequence> {
+        self.find(name).await.ok_or_else(|| error::not_found(name))
+    }
+
+    /// Return the sequence with the given `name`, if there is one.
+    pub async fn find(&self, name: &Id) -> Option<Sequence> {
+        self.sequences.read().await.get(name).cloned()
+    }
+
+    /// Return the sequence with the given `name`, first creating it at `start` if there is none.
+    pub async fn get_or_create(&self, name: Id, start: u64) -> TCResult<Sequence> {
+        let mut sequences = self.sequences.write().await;
+        if let Some(sequence) = sequences.get(&name) {
+            return Ok(sequence.clone());
+        }
+
+        debug!("new sequence {} starting at {}", name, start);
+
+        let store = Persistent::new(self.dir.clone(), name.as_str());
+        let sequence = Sequence::new(start, false, store);
+        sequence.save().await?;
+        sequences.insert(name, sequence.clone());
+
+        Ok(sequence)
+    }
+}
+
+#[async_trait]
+impl Public for Sequences {
+    async fn get(
+        &self,
+        _request: &Request,
+        _txn: &Txn,
+        path: &[PathSegment],
+        key: Value,
+    ) -> TCResult<State> {
+        if !key.is_none() {
+            return Err(error::bad_request(
+                "/sbin/sequence takes no key, but found",
+                key,
+            ));
+        }
+
+        if path.is_empty() {
+            let names: Vec<Id> = self.sequences.read().await.keys().cloned().collect();
+            Ok(State::from(Value::from(names)))
+        } else if path.len() == 1 {
+            let sequence = self.sequence(&path[0]).await?;
+            Ok(State::from(Value::from(sequence.current())))
+        } else {
+            Err(error::path_not_found(path))
+        }
+    }
+
+    async fn put(
+        &self,
+        _request: &Request,
+        _txn: &Txn,
+        path: &[PathSegment],
+        key: Value,
+        value: State,
+    ) -> TCResult<()> {
+        if path.len() != 1 {
+            return Err(error::method_not_allowed(TCPath::from(path)));
+        } else if !key.is_none() {
+            return Err(error::bad_request(
+                "/sbin/sequence takes no key, but found",
+                key,
+            ));
+        }
+
+        let (start, no_gap) = parse_def(Value::try_from(value)?)?;
+
+        debug!("new sequence {} starting at {}", path[0], start);
+
+        let store = Persistent::new(self.dir.clone(), path[0].as_str());
+        let sequence = Sequence::new(start, no_gap, store);
+
+        let mut sequences = self.sequences.write().await;
+        sequence.save().await?;
+        sequences.insert(path[0].clone(), sequence);
+
+        Ok(())
+    }
+
+    async fn post(
+        &self,
+        _request: &Request,
+        txn: &Txn,
+        path: &[PathSegment],
+        mut params: Map<Scalar>,
+    ) -> TCResult<State> {
+        if path.len() != 2 || &path[1] != "next" {
+            return Err(error::method_not_allowed(TCPath::from(path)));
+        }
+
+        let n = match params.remove(&label("n").into()) {
+            Some(n) => {
+                let n: Value = n.try_cast_into(|v| error::bad_request("Invalid count", v))?;
+                n.try_cast_into(|v| error::bad_request("Invalid count", v))?
+            }
+            None => 1,
+        };
+
+        if !params.is_empty() {
+            return Err(error::bad_request(
+                "Sequence::next got unrecognized parameters",
+                Value::from(params.keys().cloned().collect::<Vec<Id>>()),
+            ));
+        }
+
+        let sequence = self.sequence(&path[0]).await?;
+        let first = sequence.next(txn, n).await?;
+        let ids: Vec<Value> = (0..n).map(|i| Value::from(first + i)).collect();
+        Ok(State::from(Value::from(ids)))
+    }
+
+    async fn delete(
+        &self,
+        _request: &Request,
+        _txn: &Txn,
+        path: &[PathSegment],
+        key: Value,
+    ) -> TCResult<()> {
+        if path.len() != 1 {
+            return Err(error::method_not_allowed(TCPath::from(path)));
+        } else if !key.is_none() {
+            return Err(error::bad_request(
+                "/sbin/sequence takes no key, but found",
+                key,
+            ));
+        }
+
+        let mut sequences = self.sequences.write().await;
+        match sequences.remove(&path[0]) {
+            Some(sequence) => sequence.store.delete().await,
+            None => Err(error::not_found(&path[0])),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_advance() {
+        assert_eq!(advance(5, 10).unwrap(), 15);
+        assert!(advance(u64::MAX - 1, 2).is_err());
+    }
+
+    #[test]
+    fn test_parse_def() {
+        assert_eq!(parse_def(Value::None).unwrap(), (0, false));
+        assert_eq!(parse_def(Value::from(7u64)).unwrap(), (7, false));
+
+        let def = Value::Tuple(vec![Value::from(3u64), Value::from(true)].into());
+        assert_eq!(parse_def(def).unwrap(), (3, true));
+    }
+
+    #[tokio::test]
+    async fn test_get_or_create() {
+        let data_dir = std::env::temp_dir().join(uuid::Uuid::new_v4().to_string());
+        let name: Id = "table-key".parse().unwrap();
+
+        let sequences = Sequences::load(&data_dir).unwrap();
+        assert!(sequences.find(&name).await.is_none());
+
+        let sequence = sequences.get_or_create(name.clone(), 5).await.unwrap();
+        assert_eq!(sequence.current(), 5);
+
+        let sequence = sequences.get_or_create(name.clone(), 9).await.unwrap();
+        assert_eq!(sequence.current(), 5);
+
+        let reloaded = Sequences::load(&data_dir).unwrap();
+        assert_eq!(reloaded.sequence(&name).await.unwrap().current(), 5);
+
+        std::fs::remove_dir_all(data_dir).unwrap();
+    }
+}
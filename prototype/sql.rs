@@ -0,0 +1,376 @@
+use std::collections::HashMap;
+
+use log::debug;
+use sqlparser::ast::{self, BinaryOperator, Expr, SelectItem, SetExpr, Statement, TableFactor};
+use sqlparser::dialect::GenericDialect;
+use sqlparser::parser::Parser;
+
+use crate::class::State;
+use crate::collection::schema::{Column, Row};
+use crate::collection::{Bounds, Collection, ColumnBound, Table, TableInstance};
+use crate::error;
+use crate::general::Map;
+use crate::request::Request;
+use crate::scalar::*;
+use crate::transaction::Txn;
+use crate::{TCResult, TryCastInto};
+
+const ERR_UNSUPPORTED: &str = "Unsupported SQL syntax";
+
+pub async fn post(request: &Request, txn: &Txn, data: Scalar) -> TCResult<State> {
+    let mut params: Map<Scalar> = data.try_cast_into(|v| {
+        error::bad_request("/sbin/sql expects a Map with a 'query' but found", v)
+    })?;
+
+    let query = match params.remove(&label("query").into()) {
+        Some(Scalar::Value(Value::TCString(TCString::UString(query)))) => query,
+        Some(other) => return Err(error::bad_request("SQL query must be a string, not", other)),
+        None => return Err(error::bad_request("Missing parameter", "query")),
+    };
+
+    debug!("/sbin/sql: {}", query);
+
+    let mut statements = Parser::parse_sql(&GenericDialect {}, &query)
+        .map_err(|e| error::bad_request("Unable to parse SQL query", e))?;
+
+    if statements.len() != 1 {
+        return Err(error::bad_request(
+            "Expected exactly one SQL statement but found",
+            statements.len(),
+        ));
+    }
+
+    match statements.pop().unwrap() {
+        Statement::Query(query) => select(request, txn, *query).await,
+        Statement::Insert {
+            table_name,
+            columns,
+            source,
+            ..
+        } => insert(request, txn, table_name, columns, *source).await,
+        Statement::Update {
+            table_name,
+            assignments,
+            selection,
+            ..
+        } => update(request, txn, table_name, assignments, selection).await,
+        Statement::Delete {
+            table_name,
+            selection,
+            ..
+        } => delete(request, txn, table_name, selection).await,
+        other => Err(error::bad_request(ERR_UNSUPPORTED, other)),
+    }
+}
+
+async fn select(request: &Request, txn: &Txn, query: ast::Query) -> TCResult<State> {
+    let select = match query.body {
+        SetExpr::Select(select) => *select,
+        other => return Err(error::bad_request(ERR_UNSUPPORTED, other)),
+    };
+
+    if select.distinct || !select.group_by.is_empty() || select.having.is_some() {
+        return Err(error::bad_request(ERR_UNSUPPORTED, select));
+    }
+
+    if select.from.len() != 1 || !select.from[0].joins.is_empty() {
+        return Err(error::bad_request(
+            "SELECT supports exactly one table, with no joins, not",
+            select,
+        ));
+    }
+
+    let table_name = match &select.from[0].relation {
+        TableFactor::Table { name, .. } => name.clone(),
+        other => return Err(error::bad_request(ERR_UNSUPPORTED, other)),
+    };
+
+    let mut table = resolve_table(request, txn, &table_name).await?;
+
+    if let Some(selection) = select.selection {
+        table = table.slice(bounds(selection)?)?;
+    }
+
+    if !query.order_by.is_empty() {
+        let reverse = query.order_by[0].asc == Some(false);
+        let mut order = Vec::with_capacity(query.order_by.len());
+        for column in query.order_by {
+            if (column.asc == Some(false)) != reverse {
+                return Err(error::bad_request(
+                    "ORDER BY columns must all have the same direction",
+                    column,
+                ));
+            }
+
+            order.push(column_name(column.expr)?);
+        }
+
+        table = table.order_by(order, reverse)?;
+    }
+
+    let mut columns = vec![];
+    for item in select.projection {
+        match item {
+            SelectItem::Wildcard => {}
+            SelectItem::UnnamedExpr(expr) => columns.push(column_name(expr)?),
+            other => return Err(error::bad_request(ERR_UNSUPPORTED, other)),
+        }
+    }
+
+    if !columns.is_empty() {
+        table = table.select(columns)?.into_table();
+    }
+
+    if let Some(limit) = query.limit {
+        let limit: u64 =
+            literal(limit)?.try_cast_into(|v| error::bad_request("Invalid LIMIT", v))?;
+
+        table = table.limit(limit).into_table();
+    }
+
+    Ok(State::from(table))
+}
+
+async fn insert(
+    request: &Request,
+    txn: &Txn,
+    table_name: ast::ObjectName,
+    columns: Vec<ast::Ident>,
+    source: ast::Query,
+) -> TCResult<State> {
+    let table = resolve_table(request, txn, &table_name).await?;
+
+    let columns: Vec<Id> = if columns.is_empty() {
+        table
+            .key()
+            .iter()
+            .chain(table.values())
+            .map(|c| c.name())
+            .cloned()
+            .collect()
+    } else {
+        columns
+            .into_iter()
+            .map(|ident| ident.value.parse())
+            .collect::<TCResult<Vec<Id>>>()?
+    };
+
+    let rows = match source.body {
+        SetExpr::Values(values) => values.0,
+        other => return Err(error::bad_request(ERR_UNSUPPORTED, other)),
+    };
+
+    txn.gateway().disk().admit()?;
+    for values in rows.into_iter() {
+        if values.len() != columns.len() {
+            return Err(error::bad_request(
+                format!("INSERT expected {} values but found", columns.len()),
+                values.len(),
+            ));
+        }
+
+        let mut row: Row = columns
+            .iter()
+            .cloned()
+            .zip(values.into_iter().map(literal))
+            .map(|(name, value)| value.map(|value| (name, value)))
+            .collect::<TCResult<HashMap<Id, Value>>>()?;
+
+        let key = take_columns(&mut row, table.key())?;
+        let values = take_columns(&mut row, table.values())?;
+        table.insert(txn.id(), key, values).await?;
+    }
+
+    Ok(().into())
+}
+
+async fn update(
+    request: &Request,
+    txn: &Txn,
+    table_name: ast::ObjectName,
+    assignments: Vec<ast::Assignment>,
+    selection: Option<Expr>,
+) -> TCResult<State> {
+    let mut table = resolve_table(request, txn, &table_name).await?;
+
+    let mut row = Row::new();
+    for assignment in assignments.into_iter() {
+        let name: Id = assignment.id.value.parse()?;
+        row.insert(name, literal(assignment.value)?);
+    }
+
+    if let Some(selection) = selection {
+        table = table.slice(bounds(selection)?)?;
+    }
+
+    txn.gateway().disk().admit()?;
+    table.update(txn, row).await?;
+    Ok(().into())
+}
+
+async fn delete(
+    request: &Request,
+    txn: &Txn,
+    table_name: ast::ObjectName,
+    selection: Option<Expr>,
+) -> TCResult<State> {
+    let mut table = resolve_table(request, txn, &table_name).await?;
+
+    if let Some(selection) = selection {
+        table = table.slice(bounds(selection)?)?;
+    }
+
+    table.delete(txn.id()).await?;
+    Ok(().into())
+}
+
+async fn resolve_table(request: &Request, txn: &Txn, name: &ast::ObjectName) -> TCResult<Table> {
+    let link: Link = table_path(name)?.into();
+    match txn.gateway().get(request, txn, &link, Value::None).await? {
+        State::Collection(Collection::Table(table)) => Ok(table),
+        other => Err(error::bad_request("Expected a Table but found", other)),
+    }
+}
+
+/// Return the path of the table with the given name, so that `app.inventory.items` resolves to
+/// `/app/inventory/items`.
+fn table_path(name: &ast::ObjectName) -> TCResult<TCPathBuf> {
+    let path = name
+        .0
+        .iter()
+        .map(|ident| ident.value.as_str())
+        .collect::<Vec<&str>>()
+        .join("/");
+
+    format!("/{}", path).parse()
+}
+
+fn bounds(selection: Expr) -> TCResult<Bounds> {
+    let mut bounds = Bounds::default();
+    collect_bounds(selection, &mut bounds)?;
+    Ok(bounds)
+}
+
+fn collect_bounds(expr: Expr, bounds: &mut Bounds) -> TCResult<()> {
+    match expr {
+        Expr::Nested(expr) => collect_bounds(*expr, bounds),
+        Expr::BinaryOp {
+            left,
+            op: BinaryOperator::And,
+            right,
+        } => {
+            collect_bounds(*left, bounds)?;
+            collect_bounds(*right, bounds)
+        }
+        Expr::BinaryOp { left, op, right } => {
+            let name = column_name(*left)?;
+            let value = literal(*right)?;
+
+            let bound = match op {
+                BinaryOperator::Eq => ColumnBound::Is(value),
+                BinaryOperator::Gt => (Bound::Ex(value), Bound::Unbounded).into(),
+                BinaryOperator::GtEq => (Bound::In(value), Bound::Unbounded).into(),
+                BinaryOperator::Lt => (Bound::Unbounded, Bound::Ex(value)).into(),
+                BinaryOperator::LtEq => (Bound::Unbounded, Bound::In(value)).into(),
+                other => return Err(error::bad_request(ERR_UNSUPPORTED, other)),
+            };
+
+            let bound = match bounds.remove(&name) {
+                None => bound,
+                Some(existing) => intersect(existing, bound)?,
+            };
+
+            bounds.insert(name, bound);
+            Ok(())
+        }
+        other => Err(error::bad_request(ERR_UNSUPPORTED, other)),
+    }
+}
+
+fn intersect(left: ColumnBound, right: ColumnBound) -> TCResult<ColumnBound> {
+    match (left, right) {
+        (
+            ColumnBound::In(Range {
+                start,
+                end: Bound::Unbounded,
+            }),
+            ColumnBound::In(Range {
+                start: Bound::Unbounded,
+                end,
+            }),
+        )
+        | (
+            ColumnBound::In(Range {
+                start: Bound::Unbounded,
+                end,
+            }),
+            ColumnBound::In(Range {
+                start,
+                end: Bound::Unbounded,
+            }),
+        ) => Ok((start, end).into()),
+        (left, right) => Err(error::bad_request(
+            "Unable to combine WHERE conditions on the same column",
+            format!("{} and {}", left, right),
+        )),
+    }
+}
+
+fn column_name(expr: Expr) -> TCResult<Id> {
+    match expr {
+        Expr::Identifier(ident) => ident.value.parse(),
+        other => Err(error::bad_request(
+            "Expected a column name but found",
+            other,
+        )),
+    }
+}
+
+fn literal(expr: Expr) -> TCResult<Value> {
+    match expr {
+        Expr::Value(ast::Value::Number(n, ..)) => {
+            if let Ok(n) = n.parse::<i64>() {
+                Ok(Value::Number(Int::from(n).into()))
+            } else {
+                n.parse::<f64>()
+                    .map(|n| Value::Number(Float::from(n).into()))
+                    .map_err(|e| error::bad_request("Invalid number", e))
+            }
+        }
+        Expr::Value(ast::Value::SingleQuotedString(s)) => Ok(Value::TCString(TCString::UString(s))),
+        Expr::Value(ast::Value::Boolean(b)) => Ok(Value::from(b)),
+        Expr::Value(ast::Value::Null) => Ok(Value::None),
+        other => Err(error::bad_request(
+            "Expected a literal value but found",
+            other,
+        )),
+    }
+}
+
+fn take_columns(row: &mut Row, columns: &[Column]) -> TCResult<Vec<Value>> {
+    columns
+        .iter()
+        .map(|c| {
+            row.remove(c.name())
+                .ok_or_else(|| error::bad_request("Missing value for column", c.name()))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn object_name(path: &[&str]) -> ast::ObjectName {
+        ast::ObjectName(path.iter().map(|id| ast::Ident::new(*id)).collect())
+    }
+
+    #[test]
+    fn test_table_path() {
+        let path = table_path(&object_name(&["app", "inventory", "items"])).unwrap();
+        assert_eq!(path.to_string(), "/app/inventory/items");
+
+        let path = table_path(&object_name(&["items"])).unwrap();
+        assert_eq!(path.to_string(), "/items");
+    }
+}
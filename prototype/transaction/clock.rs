@@ -0,0 +1,151 @@
+use std::sync::atomic::{AtomicU16, AtomicU64, Ordering};
+use std::time::Duration;
+
+use rand::Rng;
+
+use crate::error;
+use crate::gateway::NetworkTime;
+use crate::TCResult;
+
+/// The furthest ahead of the clock of this host that a timestamp issued by another host may be.
+pub const MAX_SKEW: Duration = Duration::from_secs(60);
+
+// the clock of this host
+static CLOCK: Clock = Clock::new();
+
+// the id of this host, which breaks ties between timestamps issued by different hosts
+static NODE: AtomicU16 = AtomicU16::new(0);
+
+/// Set the id of this host, which is used as the nonce of every new `TxnId`.
+pub fn set_node(node: u16) {
+    NODE.store(node, Ordering::SeqCst)
+}
+
+/// The id of this host, or a random nonce if it has not been set.
+pub fn node() -> u16 {
+    match NODE.load(Ordering::SeqCst) {
+        0 => rand::thread_rng().gen(),
+        node => node,
+    }
+}
+
+/// Issue a new hybrid logical timestamp: the physical time `now`, unless that is not later than
+/// the latest timestamp issued or observed by this host, in which case the latest plus one.
+/// Timestamps issued by this host are therefore strictly increasing, even if its clock is set
+/// back, or is behind the clock of a host whose timestamps it has observed.
+pub fn tick(now: NetworkTime) -> u64 {
+    CLOCK.tick(now)
+}
+
+/// Observe a timestamp issued by another host, so that every timestamp this host issues after
+/// it will be later.
+///
+/// A timestamp more than `MAX_SKEW` ahead of the physical time `now` is rejected, so that a host
+/// with a fast clock (or a forged `TxnId`) can't push the clock of this host far into the future.
+pub fn observe(timestamp: u64, now: NetworkTime) -> TCResult<()> {
+    CLOCK.observe(timestamp, now)
+}
+
+struct Clock {
+    // the latest timestamp issued or observed, in nanoseconds since the Unix epoch
+    latest: AtomicU64,
+}
+
+impl Clock {
+    const fn new() -> Clock {
+        Clock {
+            latest: AtomicU64::new(0),
+        }
+    }
+
+    fn tick(&self, now: NetworkTime) -> u64 {
+        let now = now.as_nanos() as u64;
+        let latest = self
+            .latest
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |latest| {
+                Some(Ord::max(now, latest + 1))
+            })
+            .unwrap();
+
+        Ord::max(now, latest + 1)
+    }
+
+    fn observe(&self, timestamp: u64, now: NetworkTime) -> TCResult<()> {
+        let limit = now.as_nanos() as u64 + MAX_SKEW.as_nanos() as u64;
+        if timestamp > limit {
+            return Err(error::bad_request(
+                "Timestamp is too far ahead of the clock of this host",
+                timestamp,
+            ));
+        }
+
+        self.latest.fetch_max(timestamp, Ordering::SeqCst);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::error::ErrorType;
+
+    use super::*;
+
+    const SECOND: u64 = 1_000_000_000;
+
+    fn time(nanos: u64) -> NetworkTime {
+        NetworkTime::from_nanos(nanos as u128)
+    }
+
+    #[test]
+    fn test_tick() {
+        let clock = Clock::new();
+        let start = 1_600_000_000 * SECOND;
+
+        assert_eq!(clock.tick(time(start)), start);
+        assert_eq!(clock.tick(time(start + 5)), start + 5);
+
+        // a clock which stands still or is set back still issues increasing timestamps
+        assert_eq!(clock.tick(time(start + 5)), start + 6);
+        assert_eq!(clock.tick(time(start - SECOND)), start + 7);
+        assert_eq!(clock.tick(time(start + 100)), start + 100);
+    }
+
+    #[test]
+    fn test_observe() {
+        let clock = Clock::new();
+        let now = 1_600_000_000 * SECOND;
+
+        // a timestamp from a host whose clock is ahead, but within MAX_SKEW, moves this one ahead
+        let ahead = now + (30 * SECOND);
+        clock.observe(ahead, time(now)).unwrap();
+        assert_eq!(clock.tick(time(now)), ahead + 1);
+
+        // a timestamp behind the latest has no effect
+        clock.observe(now - SECOND, time(now)).unwrap();
+        assert_eq!(clock.tick(time(now)), ahead + 2);
+
+        let limit = now + MAX_SKEW.as_nanos() as u64;
+        clock.observe(limit, time(now)).unwrap();
+        assert_eq!(clock.tick(time(now)), limit + 1);
+    }
+
+    #[test]
+    fn test_max_skew() {
+        let clock = Clock::new();
+        let now = 1_600_000_000 * SECOND;
+        let limit = now + MAX_SKEW.as_nanos() as u64;
+
+        let cause = clock.observe(limit + 1, time(now)).unwrap_err();
+        assert!(cause.reason() == &ErrorType::BadRequest);
+
+        let cause = clock.observe(u64::MAX, time(now)).unwrap_err();
+        assert!(cause.reason() == &ErrorType::BadRequest);
+
+        // a rejected timestamp doesn't move the clock
+        assert_eq!(clock.tick(time(now)), now);
+
+        // the same timestamp is accepted once the physical clock catches up
+        clock.observe(limit + 1, time(now + 1)).unwrap();
+        assert_eq!(clock.tick(time(now)), limit + 2);
+    }
+}
@@ -225,6 +225,21 @@ impl<T: Mutate> TxnLock<T> {
         unsafe { self.inner.lock().unwrap().value.get().as_ref().unwrap() }
     }
 
+    /// Return `true` if no transaction holds a version of this lock.
+    pub fn is_idle(&self) -> bool {
+        let lock = self.inner.lock().unwrap();
+        lock.value_at.is_empty() && lock.state.reserved.is_none() && lock.state.readers.is_empty()
+    }
+
+    /// Drop the version of this lock at `txn_id`, unless it's still in use.
+    pub fn forget(&self, txn_id: &TxnId) {
+        let lock = &mut self.inner.lock().unwrap();
+        if lock.state.reserved.as_ref() != Some(txn_id) && !lock.state.readers.contains_key(txn_id)
+        {
+            lock.value_at.remove(txn_id);
+        }
+    }
+
     pub fn try_read(&self, txn_id: &TxnId) -> TCResult<Option<TxnLockReadGuard<T>>> {
         debug!("TxnLock::try_read {} at {}", &self.name, txn_id);
 
@@ -2,6 +2,7 @@ use std::sync::Arc;
 
 use async_trait::async_trait;
 
+pub mod clock;
 pub mod lock;
 mod server;
 mod txn;
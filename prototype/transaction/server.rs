@@ -44,7 +44,13 @@ impl TxnServer {
     }
 
     pub async fn new_txn(&self, gateway: Arc<Gateway>, txn_id: Option<TxnId>) -> TCResult<Txn> {
-        let txn_id = txn_id.unwrap_or_else(|| TxnId::new(Gateway::time()));
+        let txn_id = match txn_id {
+            Some(txn_id) => {
+                txn_id.observe()?;
+                txn_id
+            }
+            None => TxnId::new(Gateway::time()),
+        };
         let dir = self
             .workspace
             .get_or_create_dir(&txn_id, &[txn_id.to_path()])
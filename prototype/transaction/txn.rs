@@ -10,7 +10,6 @@ use futures::future::{self, TryFutureExt};
 use futures::stream::{FuturesUnordered, StreamExt};
 use futures::try_join;
 use log::debug;
-use rand::Rng;
 use serde::de;
 use tokio::sync::mpsc;
 
@@ -23,7 +22,7 @@ use crate::request::Request;
 use crate::scalar::*;
 use crate::{TCBoxTryFuture, TCResult};
 
-use super::Transact;
+use super::{clock, Transact};
 
 pub type Graph = HashMap<Id, State>;
 
@@ -36,13 +35,22 @@ pub struct TxnId {
 }
 
 impl TxnId {
+    /// Construct a new `TxnId` from the hybrid logical clock of this host, given the current
+    /// physical time. Each new `TxnId` is later than any issued before it by this host, or
+    /// received by this host from another.
     pub fn new(time: NetworkTime) -> TxnId {
         TxnId {
-            timestamp: time.as_nanos(),
-            nonce: rand::thread_rng().gen(),
+            timestamp: clock::tick(time) as u128,
+            nonce: clock::node(),
         }
     }
 
+    /// Advance the clock of this host past this `TxnId`, which was issued by another host,
+    /// unless it's too far ahead of the clock of this host.
+    pub fn observe(&self) -> TCResult<()> {
+        clock::observe(self.timestamp as u64, Gateway::time())
+    }
+
     pub fn to_path(&self) -> PathSegment {
         self.to_string().parse().unwrap()
     }
@@ -112,6 +120,7 @@ struct Inner {
     context: Id,
     gateway: Arc<Gateway>,
     mutated: RwLock<Vec<Box<dyn Transact>>>,
+    written: RwLock<HashSet<TCPathBuf>>,
     txn_server: mpsc::UnboundedSender<TxnId>,
 }
 
@@ -137,6 +146,7 @@ impl Txn {
             context,
             gateway,
             mutated: RwLock::new(vec![]),
+            written: RwLock::new(HashSet::new()),
             txn_server,
         });
 
@@ -147,6 +157,10 @@ impl Txn {
         &self.inner.id
     }
 
+    pub fn gateway(&'_ self) -> &'_ Arc<Gateway> {
+        &self.inner.gateway
+    }
+
     pub async fn context<T: BlockData>(&self) -> TCResult<Arc<File<T>>>
     where
         Arc<File<T>>: Into<DirEntry>,
@@ -170,12 +184,29 @@ impl Txn {
             context: subcontext,
             gateway: self.inner.gateway.clone(),
             mutated: self.inner.mutated.clone(),
+            written: self.inner.written.clone(),
             txn_server: self.inner.txn_server.clone(),
         });
 
         Ok(Txn { inner: subcontext })
     }
 
+    /// Return a handle to this transaction which creates new files in `dir`, under the name
+    /// `context`, instead of in its own workspace.
+    pub fn with_dir(&self, dir: Arc<Dir>, context: Id) -> Txn {
+        let inner = Arc::new(Inner {
+            id: self.inner.id,
+            dir,
+            context,
+            gateway: self.inner.gateway.clone(),
+            mutated: self.inner.mutated.clone(),
+            written: self.inner.written.clone(),
+            txn_server: self.inner.txn_server.clone(),
+        });
+
+        Txn { inner }
+    }
+
     pub fn subcontext_tmp(&self) -> TCBoxTryFuture<Txn> {
         Box::pin(async move {
             let id = self.inner.dir.unique_id(self.id()).await?;
@@ -383,8 +414,22 @@ impl Txn {
             other => panic!("{} does not support transactional mutations!", other),
         };
 
+        self.enlist(state).await
+    }
+
+    pub async fn enlist(&self, state: Box<dyn Transact>) {
         self.inner.mutated.write().await.push(state)
     }
+
+    /// Record that this transaction wrote to the replicated cluster at `cluster`.
+    pub async fn wrote(&self, cluster: &TCPathBuf) {
+        self.inner.written.write().await.insert(cluster.clone());
+    }
+
+    /// The replicated clusters which this transaction wrote to.
+    pub async fn written(&self) -> Vec<TCPathBuf> {
+        self.inner.written.read().await.iter().cloned().collect()
+    }
 }
 
 impl Drop for Txn {